@@ -1,45 +1,891 @@
+use crate::models::did::{
+    decode_ed25519_multibase, decode_ed25519_private_multibase, DocumentLimits,
+    ServiceEndpointPolicy,
+};
+use crate::models::license::License;
+use crate::services::auth_token::{TokenAlgorithm, DEFAULT_SIGNING_NAMESPACE};
+use crate::services::http_client::DEFAULT_USER_AGENT;
+use crate::services::ipfs_service::RetentionPolicy;
+use crate::services::pii_scanner::{PiiAction, PiiPolicy};
+use crate::services::quota_service::QuotaLimits;
+use crate::services::step_up_service::StepUpOperation;
+use crate::services::ucan_service::BioAction;
+use crate::utils::IpfsAddOptions;
 use base64::engine::general_purpose::STANDARD as Base64Engine;
 use base64::Engine;
+use ed25519_zebra::{SigningKey, VerificationKey};
+use figment::providers::{Format, Toml};
+use figment::value::{Dict, Value};
+use figment::Figment;
 use pqcrypto_dilithium::dilithium5::{PublicKey, SecretKey};
+use pqcrypto_kyber::kyber1024::{PublicKey as KemPublicKey, SecretKey as KemSecretKey};
+use pqcrypto_traits::kem::{PublicKey as _, SecretKey as _};
 use pqcrypto_traits::sign::{PublicKey as OtherPublicKey, SecretKey as OtherSecretKey};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
 
 /// Configuration settings
 #[derive(Clone, Debug)]
 pub struct Config {
     pub ipfs_node: String,
     pub database_url: String,
+    // Connections the pool keeps open and never exceeds
+    pub db_pool_min: usize,
+    pub db_pool_max: usize,
+    // Connections older than this are closed when returned to the pool; unset keeps them
+    pub db_conn_ttl: Option<Duration>,
+    // Longest the health check and readiness probe wait for a connection
+    pub db_pool_wait_timeout: Duration,
+    // How often the pool health check runs
+    pub db_pool_health_interval: Duration,
     pub bind_address: String,
+    // Largest JSON request body accepted; auth endpoints use a smaller fixed limit
+    pub max_json_body_bytes: usize,
+    // How often the signing key registry is reloaded, picking up rotations by other instances
+    pub key_refresh_interval: Duration,
     // Base64-encoded public key
     dilithium_public_key: String,
     // Base64-encoded secret key
     dilithium_secret_key: String,
+    // Base64-encoded 32-byte key wrapping the secret keys stored in the key registry
+    key_encryption_key: String,
+    // Algorithm new auth tokens are signed with
+    pub auth_token_algorithm: TokenAlgorithm,
+    // Algorithms accepted when verifying auth tokens; defaults to the signing algorithm
+    pub auth_token_allowed_algorithms: Vec<TokenAlgorithm>,
+    // Base64-encoded 32-byte Ed25519 seed, required when an Ed25519 algorithm is allowed
+    auth_ed25519_secret_key: Option<String>,
+    // Namespace of the contexts auth tokens and UCANs are signed under
+    pub token_signing_namespace: String,
     pub max_concurrent_uploads: usize,
+    // Chunk size handed to resumable upload sessions
+    pub upload_chunk_bytes: u64,
+    // Largest file a resumable upload session accepts
+    pub upload_session_max_bytes: u64,
+    // Where chunks of unfinished upload sessions are staged
+    pub upload_session_dir: PathBuf,
+    // Unfinished upload tasks older than this are marked failed on startup
+    pub stale_task_timeout: Duration,
+    // Public gateways used to fetch content the current node doesn't have
+    pub ipfs_gateways: Vec<String>,
+    // Gzip JSON documents (DIDs, paper metadata) before adding them to IPFS
+    pub compress_ipfs_documents: bool,
+    // CID version, hash function and raw leaves of every IPFS add; these decide the CIDs
+    pub ipfs_add_options: IpfsAddOptions,
+    // Upper bounds on adding and fetching a document, so a stalled node can't hang requests
+    pub ipfs_add_timeout: Duration,
+    pub ipfs_get_timeout: Duration,
+    // `User-Agent` and connect timeout of the HTTP client shared by external API calls
+    pub http_user_agent: String,
+    pub http_connect_timeout: Duration,
+    // Endpoint of the `#storage` service in new DID documents; defaults to the first gateway
+    pub did_storage_endpoint: String,
+    // File of `synonym = Preferred Term` lines keywords are mapped through; unset indexes
+    // keywords in their normalized form only
+    pub keyword_vocabulary_file: Option<String>,
+    // Metadata fields of DID documents encrypted before storage, e.g. researchers[].email
+    pub did_encrypted_fields: Vec<String>,
+    // Base64-encoded Kyber1024 key pair registered with the initial key version
+    kyber_public_key: Option<String>,
+    kyber_secret_key: Option<String>,
+    // MFS directory DID documents are mirrored under as `{root}/dids/{did}.json`; unset
+    // disables mirroring
+    pub ipfs_mfs_root: Option<String>,
+    // REST API of an IPFS Cluster that replicates added content; unset pins on the node only
+    pub ipfs_cluster_api: Option<String>,
+    // Cluster peers each CID is pinned on
+    pub ipfs_cluster_replication: usize,
+    // Number of parsed DID documents kept in memory, keyed by CID
+    pub did_cache_size: NonZeroUsize,
+    // Caps on researchers, keywords and custom fields per DID and on the document's size
+    pub did_document_limits: DocumentLimits,
+    // URL schemes service endpoints of stored DID documents may use
+    pub service_endpoint_policy: ServiceEndpointPolicy,
+    // Number of gene/protein/disease name lookups against NCBI, UniProt and MeSH kept in memory
+    pub entity_cache_size: NonZeroUsize,
+    // Shared secret letting internal callers (health checks, batch jobs) bypass rate limits
+    pub internal_service_token: Option<String>,
+    // Browser origins allowed to call the API; empty disables CORS
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    // Accept any origin; for local development only
+    pub cors_allow_any_origin: bool,
+    // Also serve the current API version under the deprecated, unversioned /api prefix
+    pub unversioned_api_alias: bool,
+    // `Sunset` header (an HTTP date) sent on responses from the unversioned prefix
+    pub unversioned_api_sunset: Option<String>,
+    // License for DIDs and datasets that don't name one; when unset DIDs get CC-BY-4.0
+    // and Dataverse datasets CC0-1.0
+    pub default_license: Option<License>,
+    // Cap on concurrent paper-processing and knowledge-graph calls to BioAgents, and how long
+    // a call waits for a free slot before failing
+    pub bioagents_max_concurrent: usize,
+    pub bioagents_queue_timeout: Duration,
+    // Consecutive failures of Dataverse or BioAgents that open its circuit breaker
+    pub circuit_breaker_failures: u32,
+    // How long an open circuit fails calls before letting a trial call through
+    pub circuit_breaker_cooldown: Duration,
+    // Limits on SPARQL queries over knowledge graphs: run time and rows returned
+    pub sparql_timeout: Duration,
+    pub sparql_max_results: usize,
+    // UCAN actions non-admin users may delegate; admins may delegate any action
+    pub ucan_delegable_actions: Vec<BioAction>,
+    // Operations needing a sign-in within `step_up_max_age` or a TOTP code; none by default
+    pub step_up_operations: HashSet<StepUpOperation>,
+    pub step_up_max_age: Duration,
+    // How often delegated UCANs are revoked when an ancestor in their chain was revoked
+    pub ucan_revocation_sweep_interval: Duration,
+    // How often DIDs whose embargo has passed are looked for and reported to webhooks
+    pub embargo_sweep_interval: Duration,
+    // Superseded DID versions and soft-deleted content unpinned by the cleanup task
+    pub retention_policy: RetentionPolicy,
+    // Issuer of the UCANs this service signs; defaults to the `did:key` of the signing key
+    pub service_did: Option<String>,
+    // `privateKeyMultibase` of the Ed25519 key UCANs are signed with; required
+    service_signing_key: Option<String>,
+    // Monthly limits on papers processed and datasets created, per role
+    pub quota_limits: QuotaLimits,
+    // Personal data tolerated in a paper's abstract and entities, and what is done beyond it
+    pub pii_policy: PiiPolicy,
+    // Dataverse installation supports direct-to-storage (S3) uploads
+    pub dataverse_direct_upload: bool,
+    // Most bytes one request may upload to a dataset, summed over all its files
+    pub dataverse_max_upload_bytes: u64,
+    // Outgoing mail for task notifications; unset disables them
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_from: Option<String>,
+    // "starttls" (default), "tls" or "none"
+    pub smtp_tls: String,
+    // ORCID OAuth client for ORCID login; unset disables it
+    pub orcid_client_id: Option<String>,
+    pub orcid_client_secret: Option<String>,
+    // Callback URL registered with ORCID, ending in /api/auth/orcid/callback
+    pub orcid_redirect_uri: Option<String>,
+    // https://orcid.org, or https://sandbox.orcid.org for testing
+    pub orcid_base_url: String,
+    // BioAgents paper-processing API
+    pub bioagents_api_url: String,
+    // `publicKeyMultibase` of the Ed25519 key BioAgents signs responses with; unset skips
+    // signature verification
+    bioagents_public_key: Option<String>,
+    // Dataverse native API and the key datasets are created with
+    pub dataverse_api_url: String,
+    pub dataverse_api_key: String,
 }
-pub fn load_config() -> Result<Config, env::VarError> {
-    dotenv::dotenv().ok();
 
+/// Why the configuration couldn't be loaded
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    // A required setting isn't set
+    #[error("{0} is not set")]
+    Missing(String),
+    // A setting's value doesn't parse or is out of range
+    #[error("invalid {key}: {reason}")]
+    Invalid { key: String, reason: String },
+    #[error("config file: {0}")]
+    File(Box<figment::Error>),
+    #[error("config file sets unknown settings: {}", .0.join(", "))]
+    UnknownKeys(Vec<String>),
+}
+
+impl From<figment::Error> for ConfigError {
+    fn from(e: figment::Error) -> Self {
+        ConfigError::File(Box::new(e))
+    }
+}
+
+/// Loads configuration from the TOML file named by `CONFIG_FILE`, if any, overridden by
+/// environment variables; settings neither one sets take the defaults below
+pub fn load_config() -> Result<Config, ConfigError> {
+    build_config(&Settings::load()?)
+}
+
+fn build_config(settings: &Settings) -> Result<Config, ConfigError> {
     // Default maximum concurrent uploads
     const DEFAULT_MAX_CONCURRENT_UPLOADS: usize = 42;
 
-    let max_concurrent_uploads = env::var("MAX_CONCURRENT_UPLOADS")
-        .unwrap_or_else(|_| DEFAULT_MAX_CONCURRENT_UPLOADS.to_string())
-        .parse::<usize>()
-        .map_err(|_| env::VarError::NotPresent)?;
-
-    Ok(Config {
-        ipfs_node: env::var("IPFS_NODE").unwrap_or_else(|_| "http://127.0.0.1:5001".to_string()),
-        database_url: env::var("DATABASE_URL")?,
-        bind_address: env::var("BIND_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8081".to_string()),
-        dilithium_public_key: env::var("DILITHIUM_PUBLIC_KEY")?,
-        dilithium_secret_key: env::var("DILITHIUM_SECRET_KEY")?,
+    let max_concurrent_uploads = settings
+        .parsed::<usize>("MAX_CONCURRENT_UPLOADS")?
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_UPLOADS);
+
+    // Default age after which an unfinished upload task is considered abandoned
+    const DEFAULT_STALE_TASK_TIMEOUT_SECS: u64 = 3600;
+
+    let stale_task_timeout = settings
+        .parsed::<u64>("STALE_TASK_TIMEOUT_SECS")?
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(DEFAULT_STALE_TASK_TIMEOUT_SECS));
+
+    // Default pool bounds (mysql_async's own) and health check timings
+    const DEFAULT_DB_POOL_MIN: usize = 10;
+    const DEFAULT_DB_POOL_MAX: usize = 100;
+    const DEFAULT_DB_POOL_WAIT_TIMEOUT_SECS: u64 = 5;
+    const DEFAULT_DB_POOL_HEALTH_INTERVAL_SECS: u64 = 60;
+
+    let env_usize = |name: &str, default: usize| -> Result<usize, ConfigError> {
+        Ok(settings.parsed(name)?.unwrap_or(default))
+    };
+    let env_secs = |name: &str| -> Result<Option<Duration>, ConfigError> {
+        Ok(settings.parsed(name)?.map(Duration::from_secs))
+    };
+    // Settings that must not be 0
+    let positive = |name: &str, value: usize| -> Result<usize, ConfigError> {
+        if value == 0 {
+            return Err(invalid(name, "must be greater than 0"));
+        }
+        Ok(value)
+    };
+    let positive_secs = |name: &str, value: Duration| -> Result<Duration, ConfigError> {
+        if value.is_zero() {
+            return Err(invalid(name, "must be greater than 0"));
+        }
+        Ok(value)
+    };
+
+    let db_pool_min = env_usize("DB_POOL_MIN", DEFAULT_DB_POOL_MIN)?;
+    let db_pool_max = env_usize("DB_POOL_MAX", DEFAULT_DB_POOL_MAX)?;
+    if db_pool_max == 0 || db_pool_min > db_pool_max {
+        return Err(invalid(
+            "DB_POOL_MAX",
+            format!(
+                "{} must be greater than 0 and at least DB_POOL_MIN ({})",
+                db_pool_max, db_pool_min
+            ),
+        ));
+    }
+    let db_conn_ttl = env_secs("DB_CONN_TTL_SECS")?;
+    let db_pool_wait_timeout = env_secs("DB_POOL_WAIT_TIMEOUT_SECS")?
+        .unwrap_or(Duration::from_secs(DEFAULT_DB_POOL_WAIT_TIMEOUT_SECS));
+    let db_pool_health_interval = positive_secs(
+        "DB_POOL_HEALTH_INTERVAL_SECS",
+        env_secs("DB_POOL_HEALTH_INTERVAL_SECS")?
+            .unwrap_or(Duration::from_secs(DEFAULT_DB_POOL_HEALTH_INTERVAL_SECS)),
+    )?;
+
+    // Default JSON request body limit, half of actix's own
+    const DEFAULT_MAX_JSON_BODY_BYTES: usize = 1024 * 1024;
+
+    let max_json_body_bytes = positive(
+        "MAX_JSON_BODY_BYTES",
+        env_usize("MAX_JSON_BODY_BYTES", DEFAULT_MAX_JSON_BODY_BYTES)?,
+    )?;
+
+    // Default chunk size of resumable uploads
+    const DEFAULT_UPLOAD_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+    // Default largest file a resumable upload accepts
+    const DEFAULT_UPLOAD_SESSION_MAX_BYTES: usize = 50 * 1024 * 1024 * 1024;
+
+    let upload_chunk_bytes = positive(
+        "UPLOAD_CHUNK_BYTES",
+        env_usize("UPLOAD_CHUNK_BYTES", DEFAULT_UPLOAD_CHUNK_BYTES)?,
+    )? as u64;
+    let upload_session_max_bytes = positive(
+        "UPLOAD_SESSION_MAX_BYTES",
+        env_usize("UPLOAD_SESSION_MAX_BYTES", DEFAULT_UPLOAD_SESSION_MAX_BYTES)?,
+    )? as u64;
+    let upload_session_dir = settings
+        .optional("UPLOAD_SESSION_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join("bio-did-seq-uploads"));
+
+    let ipfs_add_options = IpfsAddOptions::parse(
+        settings.optional("IPFS_CID_VERSION").as_deref(),
+        settings.optional("IPFS_HASH").as_deref(),
+        settings.optional("IPFS_RAW_LEAVES").as_deref(),
+    )
+    .map_err(|e| invalid("IPFS_CID_VERSION, IPFS_HASH or IPFS_RAW_LEAVES", e))?;
+
+    let ipfs_gateways: Vec<String> = settings
+        .list("IPFS_GATEWAYS", "https://ipfs.io,https://dweb.link")
+        .into_iter()
+        .map(|g| g.trim_end_matches('/').to_string())
+        .collect();
+    let did_storage_endpoint = settings
+        .optional("DID_STORAGE_ENDPOINT")
+        .or_else(|| ipfs_gateways.first().cloned())
+        .unwrap_or_else(|| "https://ipfs.io".to_string());
+
+    // Default IPFS document add/get timeout
+    const DEFAULT_IPFS_TIMEOUT_SECS: u64 = 60;
+
+    let ipfs_timeout = |name: &str| -> Result<Duration, ConfigError> {
+        Ok(env_secs(name)?.unwrap_or(Duration::from_secs(DEFAULT_IPFS_TIMEOUT_SECS)))
+    };
+    let ipfs_add_timeout = ipfs_timeout("IPFS_ADD_TIMEOUT_SECS")?;
+    let ipfs_get_timeout = ipfs_timeout("IPFS_GET_TIMEOUT_SECS")?;
+
+    // Default number of cluster peers pinning each CID
+    const DEFAULT_IPFS_CLUSTER_REPLICATION: usize = 2;
+
+    let ipfs_cluster_replication = positive(
+        "IPFS_CLUSTER_REPLICATION",
+        env_usize("IPFS_CLUSTER_REPLICATION", DEFAULT_IPFS_CLUSTER_REPLICATION)?,
+    )?;
+
+    // Default connect timeout of the shared HTTP client
+    const DEFAULT_HTTP_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+    let http_connect_timeout = positive_secs(
+        "HTTP_CONNECT_TIMEOUT_SECS",
+        env_secs("HTTP_CONNECT_TIMEOUT_SECS")?
+            .unwrap_or(Duration::from_secs(DEFAULT_HTTP_CONNECT_TIMEOUT_SECS)),
+    )?;
+
+    // Default number of cached DID documents
+    const DEFAULT_DID_CACHE_SIZE: usize = 10_000;
+
+    let did_cache_size = positive(
+        "DID_CACHE_SIZE",
+        env_usize("DID_CACHE_SIZE", DEFAULT_DID_CACHE_SIZE)?,
+    )?;
+    let did_cache_size = NonZeroUsize::new(did_cache_size).expect("checked to be positive");
+
+    // Default caps on DID documents
+    const DEFAULT_MAX_DID_RESEARCHERS: usize = 100;
+    const DEFAULT_MAX_DID_KEYWORDS: usize = 100;
+    const DEFAULT_MAX_DID_CUSTOM_FIELDS: usize = 100;
+    const DEFAULT_MAX_DID_DOCUMENT_BYTES: usize = 256 * 1024;
+
+    let did_document_limits = DocumentLimits {
+        max_researchers: env_usize("MAX_DID_RESEARCHERS", DEFAULT_MAX_DID_RESEARCHERS)?,
+        max_keywords: env_usize("MAX_DID_KEYWORDS", DEFAULT_MAX_DID_KEYWORDS)?,
+        max_custom_fields: env_usize("MAX_DID_CUSTOM_FIELDS", DEFAULT_MAX_DID_CUSTOM_FIELDS)?,
+        max_document_bytes: positive(
+            "MAX_DID_DOCUMENT_BYTES",
+            env_usize("MAX_DID_DOCUMENT_BYTES", DEFAULT_MAX_DID_DOCUMENT_BYTES)?,
+        )?,
+    };
+
+    let service_endpoint_policy = ServiceEndpointPolicy::new(&settings.list(
+        "SERVICE_ENDPOINT_SCHEMES",
+        ServiceEndpointPolicy::DEFAULT_SCHEMES,
+    ))
+    .ok_or_else(|| {
+        invalid(
+            "SERVICE_ENDPOINT_SCHEMES",
+            "must be a non-empty list of URL schemes",
+        )
+    })?;
+    // Every new document gets the `#storage` service
+    if !service_endpoint_policy.allows(&did_storage_endpoint) {
+        return Err(invalid(
+            "DID_STORAGE_ENDPOINT",
+            format!(
+                "'{}' uses a scheme SERVICE_ENDPOINT_SCHEMES doesn't allow",
+                did_storage_endpoint
+            ),
+        ));
+    }
+
+    // Default number of cached entity name resolutions
+    const DEFAULT_ENTITY_CACHE_SIZE: usize = 10_000;
+
+    let entity_cache_size = positive(
+        "ENTITY_CACHE_SIZE",
+        env_usize("ENTITY_CACHE_SIZE", DEFAULT_ENTITY_CACHE_SIZE)?,
+    )?;
+    let entity_cache_size = NonZeroUsize::new(entity_cache_size).expect("checked to be positive");
+
+    // Default BioAgents concurrency limit and wait for a free slot
+    const DEFAULT_BIOAGENTS_MAX_CONCURRENT: usize = 8;
+    const DEFAULT_BIOAGENTS_QUEUE_TIMEOUT_SECS: u64 = 30;
+
+    let bioagents_max_concurrent =
+        env_usize("BIOAGENTS_MAX_CONCURRENT", DEFAULT_BIOAGENTS_MAX_CONCURRENT)?.max(1);
+    let bioagents_queue_timeout = env_secs("BIOAGENTS_QUEUE_TIMEOUT_SECS")?
+        .unwrap_or(Duration::from_secs(DEFAULT_BIOAGENTS_QUEUE_TIMEOUT_SECS));
+
+    // Default failures opening a circuit breaker and how long it stays open
+    const DEFAULT_CIRCUIT_BREAKER_FAILURES: usize = 5;
+    const DEFAULT_CIRCUIT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+    let circuit_breaker_failures =
+        env_usize("CIRCUIT_BREAKER_FAILURES", DEFAULT_CIRCUIT_BREAKER_FAILURES)?;
+    let circuit_breaker_failures = u32::try_from(circuit_breaker_failures)
+        .ok()
+        .filter(|failures| *failures > 0)
+        .ok_or_else(|| {
+            invalid(
+                "CIRCUIT_BREAKER_FAILURES",
+                format!(
+                    "{} must be between 1 and {}",
+                    circuit_breaker_failures,
+                    u32::MAX
+                ),
+            )
+        })?;
+    let circuit_breaker_cooldown = env_secs("CIRCUIT_BREAKER_COOLDOWN_SECS")?
+        .unwrap_or(Duration::from_secs(DEFAULT_CIRCUIT_BREAKER_COOLDOWN_SECS));
+
+    // Default limits on knowledge graph SPARQL queries
+    const DEFAULT_SPARQL_TIMEOUT_SECS: u64 = 5;
+    const DEFAULT_SPARQL_MAX_RESULTS: usize = 10_000;
+
+    let sparql_timeout = positive_secs(
+        "SPARQL_TIMEOUT_SECS",
+        env_secs("SPARQL_TIMEOUT_SECS")?
+            .unwrap_or(Duration::from_secs(DEFAULT_SPARQL_TIMEOUT_SECS)),
+    )?;
+    let sparql_max_results = positive(
+        "SPARQL_MAX_RESULTS",
+        env_usize("SPARQL_MAX_RESULTS", DEFAULT_SPARQL_MAX_RESULTS)?,
+    )?;
+
+    let default_license = settings
+        .optional("DEFAULT_LICENSE")
+        .map(|license| License::parse(&license))
+        .transpose()
+        .map_err(|e| invalid("DEFAULT_LICENSE", e))?;
+
+    let ucan_delegable_actions = settings
+        .list(
+            "UCAN_DELEGABLE_ACTIONS",
+            "create,read,update,delete,upload,download,process",
+        )
+        .iter()
+        .map(|action| BioAction::parse(action))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid("UCAN_DELEGABLE_ACTIONS", e))?;
+
+    // Default age of a sign-in still counting as recent for step-up
+    const DEFAULT_STEP_UP_MAX_AGE_SECS: u64 = 300;
+
+    let step_up_operations = settings
+        .list("STEP_UP_OPERATIONS", "")
+        .iter()
+        .map(|operation| StepUpOperation::parse(operation))
+        .collect::<Result<HashSet<_>, _>>()
+        .map_err(|e| invalid("STEP_UP_OPERATIONS", e))?;
+    let step_up_max_age = env_secs("STEP_UP_MAX_AGE_SECS")?
+        .unwrap_or(Duration::from_secs(DEFAULT_STEP_UP_MAX_AGE_SECS));
+
+    // Default interval of the UCAN revocation sweep
+    const DEFAULT_UCAN_REVOCATION_SWEEP_SECS: u64 = 300;
+
+    let ucan_revocation_sweep_interval = positive_secs(
+        "UCAN_REVOCATION_SWEEP_SECS",
+        env_secs("UCAN_REVOCATION_SWEEP_SECS")?
+            .unwrap_or(Duration::from_secs(DEFAULT_UCAN_REVOCATION_SWEEP_SECS)),
+    )?;
+
+    // Default interval of the sweep reporting lifted embargoes
+    const DEFAULT_EMBARGO_SWEEP_SECS: u64 = 300;
+
+    let embargo_sweep_interval = positive_secs(
+        "EMBARGO_SWEEP_SECS",
+        env_secs("EMBARGO_SWEEP_SECS")?.unwrap_or(Duration::from_secs(DEFAULT_EMBARGO_SWEEP_SECS)),
+    )?;
+
+    // Retention is off unless configured: every version stays pinned
+    let retention_policy = RetentionPolicy {
+        keep_versions: settings.parsed::<NonZeroUsize>("RETENTION_KEEP_VERSIONS")?,
+    };
+
+    // Default interval of the signing key registry reload
+    const DEFAULT_KEY_REFRESH_SECS: u64 = 60;
+
+    let key_refresh_interval = positive_secs(
+        "KEY_REFRESH_SECS",
+        env_secs("KEY_REFRESH_SECS")?.unwrap_or(Duration::from_secs(DEFAULT_KEY_REFRESH_SECS)),
+    )?;
+
+    let quota_limits =
+        QuotaLimits::parse(&settings.list("QUOTA_LIMITS", "user:papers=100,user:datasets=20"))
+            .map_err(|e| invalid("QUOTA_LIMITS", e))?;
+
+    // Default number of PII findings tolerated per paper
+    const DEFAULT_PII_THRESHOLD: usize = 0;
+
+    let pii_policy = PiiPolicy {
+        threshold: env_usize("PII_THRESHOLD", DEFAULT_PII_THRESHOLD)?,
+        action: settings
+            .optional("PII_ACTION")
+            .map(|action| PiiAction::parse(&action))
+            .transpose()
+            .map_err(|e| invalid("PII_ACTION", e))?
+            .unwrap_or(PiiAction::Redact),
+    };
+
+    // Default limit on the bytes uploaded to a dataset in one request
+    const DEFAULT_DATAVERSE_MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024 * 1024;
+
+    let dataverse_max_upload_bytes = positive(
+        "DATAVERSE_MAX_UPLOAD_BYTES",
+        env_usize(
+            "DATAVERSE_MAX_UPLOAD_BYTES",
+            DEFAULT_DATAVERSE_MAX_UPLOAD_BYTES,
+        )?,
+    )? as u64;
+
+    let auth_token_algorithm = settings
+        .optional("AUTH_TOKEN_ALG")
+        .map(|alg| TokenAlgorithm::parse(&alg))
+        .transpose()
+        .map_err(|e| invalid("AUTH_TOKEN_ALG", e))?
+        .unwrap_or(TokenAlgorithm::Dilithium5);
+
+    let auth_token_allowed_algorithms = settings
+        .list("AUTH_TOKEN_ALLOWED_ALGS", auth_token_algorithm.as_str())
+        .iter()
+        .map(|alg| TokenAlgorithm::parse(alg))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid("AUTH_TOKEN_ALLOWED_ALGS", e))?;
+
+    // Contexts are `{namespace}:{token type}:v1`, so the namespace can't hold a separator
+    let token_signing_namespace = settings
+        .optional("TOKEN_SIGNING_NAMESPACE")
+        .unwrap_or_else(|| DEFAULT_SIGNING_NAMESPACE.to_string());
+    if token_signing_namespace.contains(':') {
+        return Err(invalid(
+            "TOKEN_SIGNING_NAMESPACE",
+            format!("'{}' must not contain ':'", token_signing_namespace),
+        ));
+    }
+
+    // Given as YYYY-MM-DD; the header needs an HTTP date
+    let unversioned_api_sunset = settings
+        .optional("UNVERSIONED_API_SUNSET")
+        .map(|date| {
+            chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|e| {
+                invalid(
+                    "UNVERSIONED_API_SUNSET",
+                    format!("'{}' is not a YYYY-MM-DD date: {}", date, e),
+                )
+            })
+        })
+        .transpose()?
+        .map(|date| date.format("%a, %d %b %Y 00:00:00 GMT").to_string());
+
+    let smtp_port = settings.parsed::<u16>("SMTP_PORT")?;
+
+    let config = Config {
+        ipfs_node: settings
+            .var("IPFS_NODE")
+            .unwrap_or_else(|_| "http://127.0.0.1:5001".to_string()),
+        database_url: settings.var("DATABASE_URL")?,
+        db_pool_min,
+        db_pool_max,
+        db_conn_ttl,
+        db_pool_wait_timeout,
+        db_pool_health_interval,
+        bind_address: settings
+            .var("BIND_ADDRESS")
+            .unwrap_or_else(|_| "0.0.0.0:8081".to_string()),
+        max_json_body_bytes,
+        key_refresh_interval,
+        dilithium_public_key: settings.var("DILITHIUM_PUBLIC_KEY")?,
+        dilithium_secret_key: settings.var("DILITHIUM_SECRET_KEY")?,
+        key_encryption_key: settings.var("KEY_ENCRYPTION_KEY")?,
+        auth_token_algorithm,
+        auth_token_allowed_algorithms,
+        auth_ed25519_secret_key: settings.optional("AUTH_ED25519_SECRET_KEY"),
+        token_signing_namespace,
         max_concurrent_uploads,
-    })
+        upload_chunk_bytes,
+        upload_session_max_bytes,
+        upload_session_dir,
+        stale_task_timeout,
+        ipfs_gateways,
+        did_storage_endpoint,
+        keyword_vocabulary_file: settings.optional("KEYWORD_VOCABULARY_FILE"),
+        did_encrypted_fields: settings.list("DID_ENCRYPTED_FIELDS", ""),
+        kyber_public_key: settings.optional("KYBER_PUBLIC_KEY"),
+        kyber_secret_key: settings.optional("KYBER_SECRET_KEY"),
+        compress_ipfs_documents: settings.flag("IPFS_COMPRESS_DOCUMENTS"),
+        ipfs_add_options,
+        ipfs_add_timeout,
+        ipfs_get_timeout,
+        http_user_agent: settings
+            .optional("HTTP_USER_AGENT")
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string()),
+        http_connect_timeout,
+        ipfs_mfs_root: settings
+            .optional("IPFS_MFS_ROOT")
+            .map(|root| format!("/{}", root.trim_matches('/'))),
+        ipfs_cluster_api: settings.optional("IPFS_CLUSTER_API"),
+        ipfs_cluster_replication,
+        did_cache_size,
+        did_document_limits,
+        service_endpoint_policy,
+        entity_cache_size,
+        internal_service_token: settings.optional("INTERNAL_SERVICE_TOKEN"),
+        cors_allowed_origins: settings.list("CORS_ALLOWED_ORIGINS", ""),
+        cors_allowed_methods: settings.list("CORS_ALLOWED_METHODS", "GET,POST,PUT,PATCH,DELETE"),
+        cors_allowed_headers: settings.list(
+            "CORS_ALLOWED_HEADERS",
+            "Authorization,Content-Type,Idempotency-Key",
+        ),
+        cors_allow_any_origin: settings.flag("CORS_ALLOW_ANY_ORIGIN"),
+        unversioned_api_alias: !settings.flag("DISABLE_UNVERSIONED_API"),
+        unversioned_api_sunset,
+        default_license,
+        bioagents_max_concurrent,
+        bioagents_queue_timeout,
+        circuit_breaker_failures,
+        circuit_breaker_cooldown,
+        sparql_timeout,
+        sparql_max_results,
+        ucan_delegable_actions,
+        step_up_operations,
+        step_up_max_age,
+        ucan_revocation_sweep_interval,
+        embargo_sweep_interval,
+        retention_policy,
+        service_did: settings.optional("SERVICE_DID"),
+        service_signing_key: settings.optional("SERVICE_SIGNING_KEY"),
+        quota_limits,
+        pii_policy,
+        dataverse_direct_upload: settings.flag("DATAVERSE_DIRECT_UPLOAD"),
+        dataverse_max_upload_bytes,
+        smtp_host: settings.optional("SMTP_HOST"),
+        smtp_port,
+        smtp_username: settings.optional("SMTP_USERNAME"),
+        smtp_password: settings.optional("SMTP_PASSWORD"),
+        smtp_from: settings.optional("SMTP_FROM"),
+        smtp_tls: settings
+            .optional("SMTP_TLS")
+            .map(|v| v.to_ascii_lowercase())
+            .unwrap_or_else(|| "starttls".to_string()),
+        orcid_client_id: settings.optional("ORCID_CLIENT_ID"),
+        orcid_client_secret: settings.optional("ORCID_CLIENT_SECRET"),
+        orcid_redirect_uri: settings.optional("ORCID_REDIRECT_URI"),
+        orcid_base_url: settings
+            .optional("ORCID_BASE_URL")
+            .unwrap_or_else(|| "https://orcid.org".to_string()),
+        bioagents_api_url: settings
+            .optional("BIOAGENTS_API_URL")
+            .unwrap_or_else(|| "http://localhost:3000".to_string()),
+        bioagents_public_key: settings.optional("BIOAGENTS_PUBLIC_KEY"),
+        dataverse_api_url: settings
+            .optional("DATAVERSE_API_URL")
+            .unwrap_or_else(|| "https://dataverse.harvard.edu/api".to_string()),
+        dataverse_api_key: settings.optional("DATAVERSE_API_KEY").unwrap_or_default(),
+    };
+
+    settings.reject_unknown_file_keys()?;
+    Ok(config)
+}
+
+/// Environment variable naming the TOML config file
+const CONFIG_FILE_VAR: &str = "CONFIG_FILE";
+
+// Read before the rest of the configuration, by `LogFormat::from_env`
+const EARLY_SETTINGS: &[&str] = &["log_format"];
+
+/// Raw setting values, looked up by their environment variable name.
+///
+/// Environment variables override the config file. The file uses the same names in lower
+/// case, and tables prefix their keys, so `[dataverse] api_url` sets `DATAVERSE_API_URL`.
+struct Settings {
+    env: HashMap<String, String>,
+    file: HashMap<String, String>,
+    // File keys that were looked up; any others are typos or stale settings
+    read: RefCell<HashSet<String>>,
+}
+
+impl Settings {
+    fn new(env: HashMap<String, String>, file: HashMap<String, String>) -> Self {
+        Settings {
+            env,
+            file,
+            read: RefCell::new(HashSet::new()),
+        }
+    }
+
+    fn load() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+        let env: HashMap<String, String> = env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .collect();
+        let file = match env
+            .get(CONFIG_FILE_VAR)
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+        {
+            Some(path) => file_settings(&Figment::from(Toml::file_exact(path)))?,
+            None => HashMap::new(),
+        };
+        Ok(Settings::new(env, file))
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        let key = name.to_ascii_lowercase();
+        let file_value = self.file.get(&key);
+        self.read.borrow_mut().insert(key);
+        self.env.get(name).or(file_value).map(String::as_str)
+    }
+
+    fn var(&self, name: &str) -> Result<String, ConfigError> {
+        self.get(name)
+            .map(str::to_string)
+            .ok_or_else(|| ConfigError::Missing(name.to_string()))
+    }
+
+    /// Setting treating an empty value as unset
+    fn optional(&self, name: &str) -> Option<String> {
+        self.get(name)
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Setting parsed as `T`, or `None` when unset
+    fn parsed<T>(&self, name: &str) -> Result<Option<T>, ConfigError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.optional(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| invalid(name, format!("'{}': {}", value, e)))
+            })
+            .transpose()
+    }
+
+    /// Comma-separated list, with blanks dropped
+    fn list(&self, name: &str, default: &str) -> Vec<String> {
+        self.get(name)
+            .unwrap_or(default)
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    /// Boolean flag; `1`, `true` and `yes` enable it
+    fn flag(&self, name: &str) -> bool {
+        self.get(name)
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    }
+
+    fn reject_unknown_file_keys(&self) -> Result<(), ConfigError> {
+        let read = self.read.borrow();
+        let mut unknown: Vec<String> = self
+            .file
+            .keys()
+            .filter(|key| !read.contains(*key) && !EARLY_SETTINGS.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(ConfigError::UnknownKeys(unknown))
+    }
+}
+
+/// Error for a setting whose value doesn't parse or is out of range
+fn invalid(key: &str, reason: impl fmt::Display) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Flattens the config file into settings; arrays become comma-separated lists
+fn file_settings(figment: &Figment) -> Result<HashMap<String, String>, ConfigError> {
+    fn flatten(
+        settings: &mut HashMap<String, String>,
+        key: String,
+        value: Value,
+    ) -> Result<(), ConfigError> {
+        match value {
+            Value::Dict(_, dict) => {
+                for (name, value) in dict {
+                    let name = name.to_ascii_lowercase();
+                    let key = if key.is_empty() {
+                        name
+                    } else {
+                        format!("{}_{}", key, name)
+                    };
+                    flatten(settings, key, value)?;
+                }
+            }
+            Value::Array(_, items) => {
+                let items = items
+                    .iter()
+                    .map(scalar)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| not_scalar(&key))?;
+                settings.insert(key, items.join(","));
+            }
+            value => {
+                let value = scalar(&value).ok_or_else(|| not_scalar(&key))?;
+                settings.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn not_scalar(key: &str) -> ConfigError {
+        figment::Error::from(format!(
+            "`{}` must be a string, number, boolean or list",
+            key
+        ))
+        .into()
+    }
+
+    fn scalar(value: &Value) -> Option<String> {
+        match value {
+            Value::String(_, s) => Some(s.clone()),
+            Value::Char(_, c) => Some(c.to_string()),
+            Value::Bool(_, b) => Some(b.to_string()),
+            Value::Num(_, n) => n
+                .to_i128()
+                .map(|n| n.to_string())
+                .or_else(|| n.to_u128().map(|n| n.to_string()))
+                .or_else(|| n.to_f64().map(|n| n.to_string())),
+            Value::Empty(..) | Value::Dict(..) | Value::Array(..) => None,
+        }
+    }
+
+    let mut settings = HashMap::new();
+    flatten(
+        &mut settings,
+        String::new(),
+        Value::from(figment.extract::<Dict>()?),
+    )?;
+    Ok(settings)
+}
+
+/// Log output format, from `LOG_FORMAT`: human-readable `text` (default) or `json`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    /// Read ahead of the rest of the configuration so that configuration errors get logged
+    pub fn from_env() -> Self {
+        match Settings::load()
+            .ok()
+            .and_then(|settings| settings.optional("LOG_FORMAT"))
+            .map(|v| v.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
 }
 
 impl Config {
-    /// Loads configuration from environment variables
-    pub fn from_env() -> Result<Self, env::VarError> {
+    /// Loads configuration from the config file and environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
         load_config()
     }
 
@@ -58,4 +904,152 @@ impl Config {
         SecretKey::from_bytes(&secret_key_bytes)
             .map_err(|e| format!("Invalid Dilithium secret key format: {}", e))
     }
+
+    /// Key wrapping the secret keys of the key registry
+    pub fn get_key_encryption_key(&self) -> Result<[u8; 32], String> {
+        Base64Engine
+            .decode(self.key_encryption_key.trim())
+            .map_err(|e| format!("Failed to decode key-encryption key: {}", e))?
+            .try_into()
+            .map_err(|_| "KEY_ENCRYPTION_KEY must be 32 bytes of base64".to_string())
+    }
+
+    /// Kyber1024 key pair of the initial key version, if one is configured
+    pub fn get_kyber_keys(&self) -> Result<Option<(KemPublicKey, KemSecretKey)>, String> {
+        let (public_key, secret_key) = match (&self.kyber_public_key, &self.kyber_secret_key) {
+            (Some(public_key), Some(secret_key)) => (public_key, secret_key),
+            (None, None) => return Ok(None),
+            _ => {
+                return Err("KYBER_PUBLIC_KEY and KYBER_SECRET_KEY must be set together".to_string())
+            }
+        };
+        let public_key_bytes = Base64Engine
+            .decode(public_key)
+            .map_err(|e| format!("Failed to decode Kyber public key: {}", e))?;
+        let secret_key_bytes = Base64Engine
+            .decode(secret_key)
+            .map_err(|e| format!("Failed to decode Kyber secret key: {}", e))?;
+        let public_key = KemPublicKey::from_bytes(&public_key_bytes)
+            .map_err(|e| format!("Invalid Kyber public key format: {}", e))?;
+        let secret_key = KemSecretKey::from_bytes(&secret_key_bytes)
+            .map_err(|e| format!("Invalid Kyber secret key format: {}", e))?;
+        Ok(Some((public_key, secret_key)))
+    }
+
+    /// Ed25519 key for signing auth tokens, if one is configured
+    pub fn get_ed25519_signing_key(&self) -> Result<Option<SigningKey>, String> {
+        self.auth_ed25519_secret_key
+            .as_ref()
+            .map(|encoded| {
+                let seed = Base64Engine
+                    .decode(encoded)
+                    .map_err(|e| format!("Failed to decode Ed25519 secret key: {}", e))?;
+                SigningKey::try_from(seed.as_slice())
+                    .map_err(|e| format!("Invalid Ed25519 secret key: {}", e))
+            })
+            .transpose()
+    }
+
+    /// Ed25519 key the service signs UCANs with
+    pub fn get_service_signing_key(&self) -> Result<SigningKey, String> {
+        let encoded = self.service_signing_key.as_deref().ok_or_else(|| {
+            "SERVICE_SIGNING_KEY must be set to the privateKeyMultibase of the service's \
+             Ed25519 key"
+                .to_string()
+        })?;
+        decode_ed25519_private_multibase(encoded)
+            .ok_or_else(|| "SERVICE_SIGNING_KEY is not an Ed25519 privateKeyMultibase".to_string())
+    }
+
+    /// Ed25519 key BioAgents responses must be signed with, if verification is enabled
+    pub fn get_bioagents_public_key(&self) -> Result<Option<VerificationKey>, String> {
+        self.bioagents_public_key
+            .as_deref()
+            .map(|encoded| {
+                decode_ed25519_multibase(encoded).ok_or_else(|| {
+                    "BIOAGENTS_PUBLIC_KEY is not an Ed25519 publicKeyMultibase".to_string()
+                })
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(env: &[(&str, &str)], toml: &str) -> Settings {
+        let env = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let file = file_settings(&Figment::from(Toml::string(toml))).unwrap();
+        Settings::new(env, file)
+    }
+
+    #[test]
+    fn test_environment_overrides_config_file_over_defaults() {
+        let toml = r#"
+            database_url = "mysql://file/db"
+            dilithium_public_key = "pk"
+            dilithium_secret_key = "sk"
+            key_encryption_key = "kek"
+            db_pool_max = 20
+            ipfs_gateways = ["https://gw.example/", "https://dweb.link"]
+
+            [dataverse]
+            api_url = "https://dataverse.example/api"
+            direct_upload = true
+        "#;
+        let config = build_config(&settings(
+            &[("DATABASE_URL", "mysql://env/db"), ("DB_POOL_MAX", "30")],
+            toml,
+        ))
+        .unwrap();
+
+        assert_eq!(config.database_url, "mysql://env/db");
+        assert_eq!(config.db_pool_max, 30);
+        assert_eq!(config.db_pool_min, 10);
+        assert_eq!(
+            config.ipfs_gateways,
+            ["https://gw.example", "https://dweb.link"]
+        );
+        assert_eq!(config.dataverse_api_url, "https://dataverse.example/api");
+        assert!(config.dataverse_direct_upload);
+        assert_eq!(config.bioagents_api_url, "http://localhost:3000");
+
+        let typo = format!("db_pool_maxx = 5\n{}", toml);
+        assert!(matches!(
+            build_config(&settings(&[], &typo)),
+            Err(ConfigError::UnknownKeys(keys)) if keys == ["db_pool_maxx"]
+        ));
+    }
+
+    #[test]
+    fn test_errors_name_the_setting_at_fault() {
+        let required = [
+            ("DATABASE_URL", "mysql://env/db"),
+            ("DILITHIUM_PUBLIC_KEY", "pk"),
+            ("DILITHIUM_SECRET_KEY", "sk"),
+            ("KEY_ENCRYPTION_KEY", "kek"),
+        ];
+        let error = |extra: &[(&str, &str)]| {
+            let mut env = required.to_vec();
+            env.extend_from_slice(extra);
+            build_config(&settings(&env, "")).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            error(&[("DB_POOL_MAX", "lots")]),
+            "invalid DB_POOL_MAX: 'lots': invalid digit found in string"
+        );
+        assert_eq!(
+            error(&[("EMBARGO_SWEEP_SECS", "0")]),
+            "invalid EMBARGO_SWEEP_SECS: must be greater than 0"
+        );
+        assert!(matches!(
+            build_config(&settings(&required[1..], "")),
+            Err(ConfigError::Missing(key)) if key == "DATABASE_URL"
+        ));
+    }
 }
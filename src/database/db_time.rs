@@ -0,0 +1,78 @@
+//! Timestamps as stored in `DATETIME` columns.
+//!
+//! `DATETIME` has no time zone, so every timestamp is written and read as UTC through
+//! these functions. Connections also run with the session time zone set to UTC (see
+//! `init_db_pool`), so `UTC_TIMESTAMP()`, `CURRENT_TIMESTAMP` defaults and the values
+//! written here agree whatever the time zone of the server or the database host.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use mysql_async::Value;
+
+/// Layout of timestamps in `DATETIME` columns and `DATE_FORMAT(.., '%Y-%m-%d %H:%i:%s')`
+pub const DB_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// `dt` as a `DATETIME` literal in UTC; sub-second precision is dropped
+pub fn to_db(dt: DateTime<Utc>) -> String {
+    dt.format(DB_TIME_FORMAT).to_string()
+}
+
+/// Parse a `DATETIME` value written by `to_db`, or read with `DATE_FORMAT`, as UTC
+pub fn from_db(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, DB_TIME_FORMAT)
+        .ok()
+        .map(|ndt| ndt.and_utc())
+}
+
+/// A `DATETIME` column read without `DATE_FORMAT`, which arrives as a date value from
+/// prepared statements and as text from plain queries, as UTC
+pub fn from_db_value(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Date(year, month, day, hour, minute, second, micros) => {
+            chrono::NaiveDate::from_ymd_opt(i32::from(*year), u32::from(*month), u32::from(*day))?
+                .and_hms_micro_opt(
+                    u32::from(*hour),
+                    u32::from(*minute),
+                    u32::from(*second),
+                    *micros,
+                )
+                .map(|ndt| ndt.and_utc())
+        }
+        Value::Bytes(bytes) => {
+            let text = std::str::from_utf8(bytes).ok()?;
+            // Text results include fractional seconds when the column has them
+            from_db(text.split('.').next().unwrap_or(text))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_round_trip_ignores_local_time_zone() {
+        std::env::set_var("TZ", "America/New_York");
+        let dt = Utc.with_ymd_and_hms(2030, 3, 10, 6, 30, 15).unwrap();
+
+        assert_eq!(to_db(dt), "2030-03-10 06:30:15");
+        assert_eq!(from_db(&to_db(dt)), Some(dt));
+        assert_eq!(from_db("not a timestamp"), None);
+    }
+
+    #[test]
+    fn test_from_db_value_reads_dates_and_text() {
+        let dt = Utc.with_ymd_and_hms(2030, 3, 10, 6, 30, 15).unwrap();
+
+        assert_eq!(
+            from_db_value(&Value::Date(2030, 3, 10, 6, 30, 15, 0)),
+            Some(dt)
+        );
+        assert_eq!(
+            from_db_value(&Value::Bytes(b"2030-03-10 06:30:15.250000".to_vec())),
+            Some(dt)
+        );
+        assert_eq!(from_db_value(&Value::NULL), None);
+    }
+}
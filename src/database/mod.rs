@@ -1,43 +1,25 @@
+pub mod db_time;
+mod pool;
 mod schema;
 mod users;
 
+pub use pool::{init_db_pool, DbHealth};
 pub use schema::init_schema;
-pub use users::{login_user, register_user};
+pub use users::{
+    find_auth_user, find_orcid_identity, login_user, register_user, upsert_orcid_user,
+    ORCID_PLACEHOLDER_EMAIL_DOMAIN,
+};
 
+use self::db_time::{from_db, to_db};
 use crate::models::file_metadata::{TaskInfo, UploadStatus};
 use crate::{errors::ServiceError, IPFSService};
-use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, Utc};
 use dashmap::DashMap;
 use ipfs_api::{IpfsApi, IpfsClient};
-use log::{error, info};
+use log::info;
 use mysql_async::{prelude::*, Pool, Row};
 use std::sync::Arc;
 
-/// Initialize the database connection pool
-pub async fn init_db_pool(database_url: &str) -> Result<Pool, String> {
-    info!("Initializing database connection pool");
-    let pool = Pool::new(database_url);
-
-    // Test the connection
-    let mut conn = pool.get_conn().await.map_err(|e| {
-        error!("Failed to connect to database: {}", e);
-        format!("Failed to connect to database: {}", e)
-    })?;
-
-    let result: Option<i32> = conn.query_first("SELECT 1").await.map_err(|e| {
-        error!("Failed to execute test query: {}", e);
-        format!("Failed to execute test query: {}", e)
-    })?;
-
-    if result != Some(1) {
-        error!("Database connection test returned unexpected result");
-        return Err("Database connection test failed".to_string());
-    }
-
-    info!("Database connection pool initialized successfully");
-    Ok(pool)
-}
-
 /// Inserts file metadata into the database.
 pub async fn insert_file_metadata(
     db_pool: &Pool,
@@ -46,6 +28,7 @@ pub async fn insert_file_metadata(
     size: u64,
     user_id: i32,
     task_id: Option<&str>,
+    content_type: Option<&str>,
 ) -> Result<(), ServiceError> {
     let mut conn = db_pool
         .get_conn()
@@ -57,15 +40,16 @@ pub async fn insert_file_metadata(
         .map_err(|e| ServiceError::Internal(format!("Failed to start transaction: {}", e)))?;
 
     tx.exec_drop(
-        r"INSERT INTO file_metadata (cid, name, size, timestamp, user_id, task_id)
-          VALUES (:cid, :name, :size, :timestamp, :user_id, :task_id)",
+        r"INSERT INTO file_metadata (cid, name, size, timestamp, user_id, task_id, content_type)
+          VALUES (:cid, :name, :size, :timestamp, :user_id, :task_id, :content_type)",
         params! {
             "cid" => cid,
             "name" => name,
             "size" => size,
-            "timestamp" => Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "timestamp" => to_db(Utc::now()),
             "user_id" => user_id,
             "task_id" => task_id,
+            "content_type" => content_type,
         },
     )
     .await
@@ -78,10 +62,14 @@ pub async fn insert_file_metadata(
 }
 
 /// Inserts an initial task into the upload_tasks table.
+///
+/// The row is written in its own transaction before the task is cached or started, so a
+/// task is never visible in memory without its database row.
 pub async fn insert_initial_task(
     db_pool: &Pool,
     task_id: &str,
     user_id: i32,
+    file_name: &str,
     status: &str,
     started_at: DateTime<Utc>,
 ) -> Result<(), ServiceError> {
@@ -89,22 +77,129 @@ pub async fn insert_initial_task(
         .get_conn()
         .await
         .map_err(|e| ServiceError::Internal(format!("Failed to get DB connection: {}", e)))?;
-    conn.exec_drop(
-        r"INSERT INTO upload_tasks (task_id, user_id, status, started_at)
-          VALUES (:task_id, :user_id, :status, :started_at)",
+    let mut tx = conn
+        .start_transaction(mysql_async::TxOpts::default())
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to start transaction: {}", e)))?;
+
+    tx.exec_drop(
+        r"INSERT INTO upload_tasks (task_id, user_id, file_name, status, progress, started_at)
+          VALUES (:task_id, :user_id, :file_name, :status, 0.0, :started_at)",
         params! {
             "task_id" => task_id,
             "user_id" => user_id,
+            "file_name" => file_name,
             "status" => status,
-            "started_at" => started_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            "started_at" => to_db(started_at),
         },
     )
     .await
     .map_err(|e| ServiceError::Internal(format!("Failed to insert initial task: {}", e)))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to commit transaction: {}", e)))?;
     Ok(())
 }
 
+/// Moves a failed task owned by `user_id` back to "pending" so it can be re-run, returning
+/// the file name it was started with.
+///
+/// This is the only way a task leaves a final state; the row is locked while checked so
+/// two concurrent retries can't both restart it.
+pub async fn reset_failed_task(
+    db_pool: &Pool,
+    task_id: &str,
+    user_id: i32,
+    started_at: DateTime<Utc>,
+) -> Result<Option<String>, ServiceError> {
+    let mut conn = db_pool
+        .get_conn()
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to get DB connection: {}", e)))?;
+    let mut tx = conn
+        .start_transaction(mysql_async::TxOpts::default())
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to start transaction: {}", e)))?;
+
+    let row: Option<(i32, String, Option<String>)> = tx
+        .exec_first(
+            r"SELECT user_id, status, file_name FROM upload_tasks
+              WHERE task_id = :task_id FOR UPDATE",
+            params! { "task_id" => task_id },
+        )
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to query upload task: {}", e)))?;
+
+    let file_name = match row {
+        None => return Err(ServiceError::InvalidInput("Task not found".to_string())),
+        Some((owner, _, _)) if owner != user_id => {
+            return Err(ServiceError::Auth(
+                "Not authorized to retry this task".to_string(),
+            ))
+        }
+        Some((_, status, _)) if status != "failed" => {
+            return Err(ServiceError::InvalidInput(format!(
+                "Only failed tasks can be retried; task is {}",
+                status
+            )))
+        }
+        Some((_, _, file_name)) => file_name,
+    };
+
+    tx.exec_drop(
+        r"UPDATE upload_tasks
+          SET status = 'pending', cid = NULL, error = NULL, progress = 0.0,
+              started_at = :started_at, completed_at = NULL
+          WHERE task_id = :task_id",
+        params! {
+            "task_id" => task_id,
+            "started_at" => to_db(started_at),
+        },
+    )
+    .await
+    .map_err(|e| ServiceError::Internal(format!("Failed to reset upload task: {}", e)))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to commit transaction: {}", e)))?;
+    Ok(file_name)
+}
+
+/// Marks tasks that never reached a final state and started more than `older_than` ago as
+/// failed. Run on startup, since uploads don't survive a restart and would otherwise stay
+/// "pending" forever. Returns the number of tasks marked.
+pub async fn fail_stale_tasks(
+    db_pool: &Pool,
+    older_than: std::time::Duration,
+) -> Result<u64, ServiceError> {
+    let older_than = Duration::from_std(older_than)
+        .map_err(|e| ServiceError::Internal(format!("Invalid stale task threshold: {}", e)))?;
+    let cutoff = to_db(Utc::now() - older_than);
+
+    let mut conn = db_pool
+        .get_conn()
+        .await
+        .map_err(|e| ServiceError::Internal(format!("Failed to get DB connection: {}", e)))?;
+    conn.exec_drop(
+        r"UPDATE upload_tasks
+          SET status = 'failed', error = 'Upload was interrupted before completing',
+              completed_at = UTC_TIMESTAMP()
+          WHERE status NOT IN ('completed', 'failed') AND started_at < :cutoff",
+        params! { "cutoff" => cutoff },
+    )
+    .await
+    .map_err(|e| ServiceError::Internal(format!("Failed to fail stale tasks: {}", e)))?;
+
+    Ok(conn.affected_rows())
+}
+
 /// Updates the status of a task in both the in-memory cache and the database.
+///
+/// Updates are applied atomically per task and never rewind it: progress only increases
+/// and a task that reached "completed" or "failed" keeps that state. Uploads report from
+/// several places at once, so late or out-of-order updates are dropped rather than
+/// clobbering newer ones.
 pub async fn update_task_status(
     tasks: Arc<DashMap<String, TaskInfo>>,
     db_pool: &Pool,
@@ -115,24 +210,22 @@ pub async fn update_task_status(
     progress: Option<f64>,
 ) -> Result<(), ServiceError> {
     // Update in-memory cache
-    if let Some(mut task_info) = tasks.get_mut(task_id) {
-        task_info.status.status = status.to_string();
-        task_info.status.cid = cid.map(String::from);
-        task_info.status.error = error.map(String::from);
-        task_info.status.progress = progress;
-    } else {
-        log::warn!("Task {} not found in cache during status update", task_id);
+    if !update_cached_status(&tasks, task_id, status, cid, error, progress) {
+        log::debug!("Ignored stale status update for task {}", task_id);
     }
 
-    // Update database
+    // Update database. The guarded single-statement UPDATE is atomic, so concurrent writers
+    // can't interleave between the check and the write.
     let mut conn = db_pool
         .get_conn()
         .await
         .map_err(|e| ServiceError::Internal(format!("Failed to get DB connection: {}", e)))?;
     conn.exec_drop(
-        r"UPDATE upload_tasks 
-          SET status = :status, cid = :cid, error = :error, progress = :progress, completed_at = NOW()
-          WHERE task_id = :task_id",
+        r"UPDATE upload_tasks
+          SET status = :status, cid = COALESCE(:cid, cid), error = COALESCE(:error, error),
+              progress = GREATEST(COALESCE(progress, 0), COALESCE(:progress, progress, 0)),
+              completed_at = IF(:status IN ('completed', 'failed'), UTC_TIMESTAMP(), completed_at)
+          WHERE task_id = :task_id AND status NOT IN ('completed', 'failed')",
         params! {
             "task_id" => task_id,
             "status" => status,
@@ -147,6 +240,25 @@ pub async fn update_task_status(
     Ok(())
 }
 
+/// Apply a status update to the cached task, holding its map entry locked for the whole
+/// read-modify-write. Returns false when the task is unknown or the update was stale.
+fn update_cached_status(
+    tasks: &DashMap<String, TaskInfo>,
+    task_id: &str,
+    status: &str,
+    cid: Option<&str>,
+    error: Option<&str>,
+    progress: Option<f64>,
+) -> bool {
+    match tasks.get_mut(task_id) {
+        Some(mut task_info) => task_info.status.apply_update(status, cid, error, progress),
+        None => {
+            log::warn!("Task {} not found in cache during status update", task_id);
+            false
+        }
+    }
+}
+
 /// Retrieves the upload status, checking the cache first and falling back to the database.
 pub async fn get_upload_status(
     service: &IPFSService,
@@ -186,12 +298,13 @@ pub async fn get_upload_status(
             }
 
             let started_at: String = row.get(4).unwrap();
-            let started_at = NaiveDateTime::parse_from_str(&started_at, "%Y-%m-%d %H:%M:%S")
-                .map(|ndt| Utc.from_utc_datetime(&ndt))
-                .unwrap_or_else(|e| {
-                    log::warn!("Failed to parse started_at: {}, defaulting to now", e);
-                    Utc::now()
-                });
+            let started_at = from_db(&started_at).unwrap_or_else(|| {
+                log::warn!(
+                    "Failed to parse started_at: {}, defaulting to now",
+                    started_at
+                );
+                Utc::now()
+            });
 
             let status = UploadStatus {
                 task_id: task_id.to_string(),
@@ -202,14 +315,14 @@ pub async fn get_upload_status(
                 started_at,
             };
 
-            // Update cache
-            service.tasks.insert(
-                task_id.to_string(),
-                TaskInfo {
+            // Update cache, keeping any live entry an in-flight upload is writing to
+            service
+                .tasks
+                .entry(task_id.to_string())
+                .or_insert_with(|| TaskInfo {
                     status: status.clone(),
                     tx: None,
-                },
-            );
+                });
 
             Ok(status)
         }
@@ -258,8 +371,7 @@ pub async fn cleanup_expired_tasks(
 
     let initial_count = tasks.len();
     tasks.retain(|task_id, task| {
-        let retain = task.status.started_at > cutoff_time
-            || (task.status.status != "completed" && task.status.status != "failed");
+        let retain = task.status.started_at > cutoff_time || !task.status.is_final();
         if !retain {
             info!(
                 "Removed expired task from cache: {} (started at {})",
@@ -279,3 +391,105 @@ pub async fn cleanup_expired_tasks(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn pending_task(task_id: &str) -> TaskInfo {
+        TaskInfo {
+            status: UploadStatus {
+                task_id: task_id.to_string(),
+                status: "pending".to_string(),
+                cid: None,
+                error: None,
+                progress: Some(0.0),
+                started_at: Utc::now(),
+            },
+            tx: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_progress_updates_are_monotonic() {
+        let tasks = Arc::new(DashMap::new());
+        tasks.insert("task".to_string(), pending_task("task"));
+        let done = Arc::new(AtomicBool::new(false));
+
+        // Watches the task the way a polling client would
+        let observer = {
+            let tasks = tasks.clone();
+            let done = done.clone();
+            tokio::spawn(async move {
+                let mut last = 0.0;
+                let mut finished = false;
+                while !done.load(Ordering::Acquire) {
+                    let status = tasks.get("task").unwrap().status.clone();
+                    let progress = status.progress.unwrap();
+                    assert!(
+                        progress >= last,
+                        "progress went from {} to {}",
+                        last,
+                        progress
+                    );
+                    assert!(
+                        !finished || status.is_final(),
+                        "final status was overwritten"
+                    );
+                    last = progress;
+                    finished = status.is_final();
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let writers: Vec<_> = (0..32)
+            .map(|writer| {
+                let tasks = tasks.clone();
+                tokio::spawn(async move {
+                    for step in 0..200 {
+                        // Out-of-order progress values, like interleaved chunk reports
+                        let progress = ((writer * 37 + step * 11) % 100) as f64;
+                        update_cached_status(&tasks, "task", "pending", None, None, Some(progress));
+                        if writer == 0 && step == 100 {
+                            update_cached_status(
+                                &tasks,
+                                "task",
+                                "completed",
+                                Some("QmCid"),
+                                None,
+                                Some(100.0),
+                            );
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        done.store(true, Ordering::Release);
+        observer.await.unwrap();
+
+        let status = tasks.get("task").unwrap().status.clone();
+        assert_eq!(status.status, "completed");
+        assert_eq!(status.cid.as_deref(), Some("QmCid"));
+        assert_eq!(status.progress, Some(100.0));
+    }
+
+    #[test]
+    fn test_final_status_is_not_overwritten() {
+        let mut status = pending_task("task").status;
+
+        assert!(status.apply_update("failed", None, Some("boom"), None));
+        assert!(!status.apply_update("completed", Some("QmCid"), None, Some(100.0)));
+
+        assert_eq!(status.status, "failed");
+        assert_eq!(status.error.as_deref(), Some("boom"));
+        assert_eq!(status.cid, None);
+        assert_eq!(status.progress, Some(0.0));
+    }
+}
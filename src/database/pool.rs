@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::services::metrics_service::MetricsService;
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Opts, OptsBuilder, Pool, PoolConstraints, PoolOpts};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Initialize the database connection pool with the configured bounds and TTL
+pub async fn init_db_pool(config: &Config) -> Result<Pool, String> {
+    info!(
+        "Initializing database connection pool ({}-{} connections)",
+        config.db_pool_min, config.db_pool_max
+    );
+
+    let opts =
+        Opts::from_url(&config.database_url).map_err(|e| format!("Invalid database URL: {}", e))?;
+    let constraints = PoolConstraints::new(config.db_pool_min, config.db_pool_max)
+        .ok_or_else(|| "DB_POOL_MIN must not exceed DB_POOL_MAX".to_string())?;
+    let pool_opts = PoolOpts::default()
+        .with_constraints(constraints)
+        .with_abs_conn_ttl(config.db_conn_ttl);
+    // Timestamps are stored in UTC (see `db_time`), so every connection uses UTC too
+    let pool = Pool::new(
+        OptsBuilder::from_opts(opts)
+            .init(vec!["SET time_zone = '+00:00'"])
+            .pool_opts(pool_opts),
+    );
+
+    // Test the connection
+    let mut conn = pool.get_conn().await.map_err(|e| {
+        error!("Failed to connect to database: {}", e);
+        format!("Failed to connect to database: {}", e)
+    })?;
+
+    let result: Option<i32> = conn.query_first("SELECT 1").await.map_err(|e| {
+        error!("Failed to execute test query: {}", e);
+        format!("Failed to execute test query: {}", e)
+    })?;
+
+    if result != Some(1) {
+        error!("Database connection test returned unexpected result");
+        return Err("Database connection test failed".to_string());
+    }
+
+    info!("Database connection pool initialized successfully");
+    Ok(pool)
+}
+
+/// Connection counts seen by one pool health check
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    // Connections of this service's database user that are running a statement, as
+    // reported by the server; `None` without access to the process list
+    pub active: Option<u64>,
+    // Open connections of this user that are idle
+    pub idle: Option<u64>,
+    pub max: usize,
+    // Time taken to get a connection from the pool
+    pub acquire_seconds: f64,
+}
+
+/// Probes the shared pool for the periodic health check and the readiness endpoint
+pub struct DbHealth {
+    pool: Pool,
+    wait_timeout: Duration,
+    max_connections: usize,
+    metrics: Arc<MetricsService>,
+}
+
+impl DbHealth {
+    pub fn new(pool: Pool, config: &Config, metrics: Arc<MetricsService>) -> Self {
+        Self {
+            pool,
+            wait_timeout: config.db_pool_wait_timeout,
+            max_connections: config.db_pool_max,
+            metrics,
+        }
+    }
+
+    /// Get a connection within the wait timeout and run `SELECT 1` on it, recording the
+    /// outcome in the pool metrics. A connection failing the query is closed rather than
+    /// returned to the pool.
+    pub async fn check(&self) -> Result<PoolStats, String> {
+        let result = self.probe().await;
+        match &result {
+            Ok(stats) => self.metrics.record_db_pool(
+                stats.active,
+                stats.idle,
+                stats.max,
+                stats.acquire_seconds,
+            ),
+            Err(_) => self.metrics.record_db_health_failure(),
+        }
+        result
+    }
+
+    async fn probe(&self) -> Result<PoolStats, String> {
+        let started = Instant::now();
+        let mut conn = tokio::time::timeout(self.wait_timeout, self.pool.get_conn())
+            .await
+            .map_err(|_| {
+                format!(
+                    "No database connection available within {}s",
+                    self.wait_timeout.as_secs_f64()
+                )
+            })?
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+        let acquire_seconds = started.elapsed().as_secs_f64();
+
+        if let Err(e) = conn.query_first::<i32, _>("SELECT 1").await {
+            let _ = conn.disconnect().await;
+            return Err(format!("Database connection failed health query: {}", e));
+        }
+
+        let counts: Option<(i64, i64)> = conn
+            .query_first(
+                r"SELECT COUNT(*), CAST(COALESCE(SUM(COMMAND = 'Sleep'), 0) AS SIGNED)
+                  FROM information_schema.PROCESSLIST
+                  WHERE USER = SUBSTRING_INDEX(CURRENT_USER(), '@', 1)",
+            )
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to read database connection counts: {}", e);
+                None
+            });
+
+        Ok(PoolStats {
+            active: counts.map(|(open, idle)| (open - idle).max(0) as u64),
+            idle: counts.map(|(_, idle)| idle.max(0) as u64),
+            max: self.max_connections,
+            acquire_seconds,
+        })
+    }
+
+    /// Run `check` every `interval`, logging the connection counts
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.check().await {
+                    Ok(stats) => info!(
+                        "Database pool: {} active, {} idle, max {}, acquired in {:.3}s",
+                        stats.active.map_or("?".to_string(), |n| n.to_string()),
+                        stats.idle.map_or("?".to_string(), |n| n.to_string()),
+                        stats.max,
+                        stats.acquire_seconds
+                    ),
+                    Err(e) => error!("Database pool health check failed: {}", e),
+                }
+            }
+        });
+    }
+}
@@ -1,5 +1,5 @@
 use log::info;
-use mysql_async::{prelude::*, Pool};
+use mysql_async::{prelude::*, Conn, Pool};
 
 /// Initializes the database schema by creating necessary tables if they don't exist
 pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
@@ -11,12 +11,26 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
             username VARCHAR(50) NOT NULL UNIQUE,
             email VARCHAR(100) NOT NULL UNIQUE,
             password_hash VARCHAR(255) NOT NULL,
+            role VARCHAR(20) NOT NULL DEFAULT 'user',
             created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
             INDEX idx_email (email)
         )",
     )
     .await?;
 
+    // Columns added after the initial release
+    add_column_if_missing(
+        &mut conn,
+        "users",
+        "role",
+        "VARCHAR(20) NOT NULL DEFAULT 'user'",
+    )
+    .await?;
+    // ORCID iD of users who signed in with ORCID
+    add_column_if_missing(&mut conn, "users", "orcid", "VARCHAR(19) NULL UNIQUE").await?;
+    // Name on their ORCID record as of their last sign-in
+    add_column_if_missing(&mut conn, "users", "orcid_name", "VARCHAR(255) NULL").await?;
+
     conn.query_drop(
         r"CREATE TABLE IF NOT EXISTS file_metadata (
             id BIGINT PRIMARY KEY AUTO_INCREMENT,
@@ -26,6 +40,7 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
             timestamp DATETIME NOT NULL,
             user_id INT NOT NULL,
             task_id VARCHAR(36),
+            content_type VARCHAR(100),
             FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
             INDEX idx_cid (cid),
             INDEX idx_user_id (user_id),
@@ -34,10 +49,27 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
     )
     .await?;
 
+    add_column_if_missing(&mut conn, "file_metadata", "content_type", "VARCHAR(100)").await?;
+
+    // Exactly one of did / user_id is set per entry
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS file_acl (
+            id BIGINT PRIMARY KEY AUTO_INCREMENT,
+            cid VARCHAR(100) NOT NULL,
+            did VARCHAR(255),
+            user_id INT,
+            created_at DATETIME NOT NULL,
+            FOREIGN KEY (cid) REFERENCES file_metadata(cid) ON DELETE CASCADE,
+            INDEX idx_cid (cid)
+        )",
+    )
+    .await?;
+
     conn.query_drop(
         r"CREATE TABLE IF NOT EXISTS upload_tasks (
             task_id VARCHAR(36) PRIMARY KEY,
             user_id INT NOT NULL,
+            file_name VARCHAR(255),
             status VARCHAR(20) NOT NULL,
             cid VARCHAR(100),
             error TEXT,
@@ -50,6 +82,8 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
     )
     .await?;
 
+    add_column_if_missing(&mut conn, "upload_tasks", "file_name", "VARCHAR(255)").await?;
+
     conn.query_drop(
         r"CREATE TABLE IF NOT EXISTS did_documents (
             id BIGINT PRIMARY KEY AUTO_INCREMENT,
@@ -67,6 +101,80 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
     )
     .await?;
 
+    // Related identifiers listed by each DID's current document, for reverse lookups
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS did_relations (
+            source_did VARCHAR(255) NOT NULL,
+            target VARCHAR(255) NOT NULL,
+            identifier_type VARCHAR(64) NOT NULL,
+            relation_type VARCHAR(64) NOT NULL,
+            PRIMARY KEY (source_did, target, relation_type),
+            FOREIGN KEY (source_did) REFERENCES did_documents(did) ON DELETE CASCADE,
+            INDEX idx_target (target)
+        )",
+    )
+    .await?;
+
+    // Embargo end from the current document's metadata, and when the sweep saw it pass
+    add_column_if_missing(&mut conn, "did_documents", "embargo_until", "DATETIME NULL").await?;
+    add_column_if_missing(
+        &mut conn,
+        "did_documents",
+        "embargo_lifted_at",
+        "DATETIME NULL",
+    )
+    .await?;
+
+    // Title and data type from the current document's metadata, listed by the DID registry
+    add_column_if_missing(&mut conn, "did_documents", "title", "VARCHAR(500) NULL").await?;
+    add_column_if_missing(&mut conn, "did_documents", "data_type", "VARCHAR(100) NULL").await?;
+
+    // Keywords of each DID's current document and its paper, as written and normalized
+    // through the keyword vocabulary, for autocomplete and keyword search
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS did_keywords (
+            did VARCHAR(255) NOT NULL,
+            keyword VARCHAR(255) NOT NULL,
+            normalized VARCHAR(255) NOT NULL,
+            PRIMARY KEY (did, normalized),
+            FOREIGN KEY (did) REFERENCES did_documents(did) ON DELETE CASCADE,
+            INDEX idx_normalized (normalized)
+        )",
+    )
+    .await?;
+
+    // Every document version stored for a DID, newest with the highest ID, and when the
+    // retention policy unpinned it. No foreign key, so versions outlive their DID's row.
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS did_versions (
+            id BIGINT PRIMARY KEY AUTO_INCREMENT,
+            did VARCHAR(255) NOT NULL,
+            cid VARCHAR(100) NOT NULL,
+            created_at DATETIME NOT NULL,
+            unpinned_at DATETIME NULL,
+            UNIQUE KEY uniq_did_cid (did, cid),
+            INDEX idx_cid (cid)
+        )",
+    )
+    .await?;
+    // Current versions of DIDs stored before versions were recorded
+    conn.query_drop(
+        r"INSERT IGNORE INTO did_versions (did, cid, created_at)
+            SELECT did, cid, updated_at FROM did_documents",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS did_attachments (
+            did VARCHAR(255) NOT NULL,
+            cid VARCHAR(100) NOT NULL,
+            PRIMARY KEY (did, cid),
+            FOREIGN KEY (did) REFERENCES did_documents(did) ON DELETE CASCADE,
+            INDEX idx_cid (cid)
+        )",
+    )
+    .await?;
+
     conn.query_drop(
         r"CREATE TABLE IF NOT EXISTS ucan_tokens (
             id VARCHAR(36) PRIMARY KEY,
@@ -85,6 +193,14 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
         )",
     )
     .await?;
+    // Why a token was revoked, e.g. a revoked ancestor in its delegation chain
+    add_column_if_missing(
+        &mut conn,
+        "ucan_tokens",
+        "revocation_reason",
+        "VARCHAR(255)",
+    )
+    .await?;
 
     conn.query_drop(
         r"CREATE TABLE IF NOT EXISTS research_papers (
@@ -112,6 +228,62 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
         )",
     )
     .await?;
+    // Set when personal data was found in the paper; such papers are left out of search
+    // until an admin approves them
+    add_column_if_missing(
+        &mut conn,
+        "research_papers",
+        "needs_review",
+        "BOOLEAN NOT NULL DEFAULT FALSE",
+    )
+    .await?;
+
+    // Resumable uploads, with the chunks received so far staged on disk
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS upload_sessions (
+            id CHAR(36) PRIMARY KEY,
+            user_id INT NOT NULL,
+            file_name VARCHAR(255) NOT NULL,
+            size BIGINT UNSIGNED NOT NULL,
+            chunk_size BIGINT UNSIGNED NOT NULL,
+            sha256 CHAR(64) NOT NULL,
+            status VARCHAR(20) NOT NULL,
+            cid VARCHAR(100),
+            created_at DATETIME NOT NULL,
+            expires_at DATETIME NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user_id (user_id),
+            INDEX idx_expires_at (expires_at)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS upload_session_chunks (
+            session_id CHAR(36) NOT NULL,
+            chunk_index BIGINT UNSIGNED NOT NULL,
+            size BIGINT UNSIGNED NOT NULL,
+            sha256 CHAR(64) NOT NULL,
+            received_at DATETIME NOT NULL,
+            PRIMARY KEY (session_id, chunk_index),
+            FOREIGN KEY (session_id) REFERENCES upload_sessions(id) ON DELETE CASCADE
+        )",
+    )
+    .await?;
+
+    // Raw BioAgents extractions, reused when the same paper is submitted with the same
+    // options; `options_hash` covers everything sent to BioAgents
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS bioagent_extraction_cache (
+            file_cid VARCHAR(100) NOT NULL,
+            options_hash CHAR(64) NOT NULL,
+            metadata LONGTEXT NOT NULL,
+            knowledge_graph_cid VARCHAR(100),
+            created_at DATETIME NOT NULL,
+            PRIMARY KEY (file_cid, options_hash)
+        )",
+    )
+    .await?;
 
     conn.query_drop(
         r"CREATE TABLE IF NOT EXISTS bioagent_tasks (
@@ -132,6 +304,179 @@ pub async fn init_schema(pool: &Pool) -> Result<(), mysql_async::Error> {
     )
     .await?;
 
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS idempotency_keys (
+            idempotency_key VARCHAR(255) NOT NULL,
+            user_id INT NOT NULL,
+            endpoint VARCHAR(100) NOT NULL,
+            request_hash CHAR(64) NOT NULL,
+            status_code SMALLINT NOT NULL,
+            response_body LONGTEXT NOT NULL,
+            resource_id VARCHAR(255),
+            created_at DATETIME NOT NULL,
+            PRIMARY KEY (user_id, endpoint, idempotency_key),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_created_at (created_at)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS background_tasks (
+            task_id VARCHAR(36) PRIMARY KEY,
+            user_id INT NOT NULL,
+            kind VARCHAR(50) NOT NULL,
+            status VARCHAR(20) NOT NULL,
+            progress DOUBLE DEFAULT 0.0,
+            details LONGTEXT,
+            error TEXT,
+            created_at DATETIME NOT NULL,
+            completed_at DATETIME,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user_id (user_id),
+            INDEX idx_kind (kind)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS did_templates (
+            template_id VARCHAR(36) PRIMARY KEY,
+            user_id INT NOT NULL,
+            name VARCHAR(255) NOT NULL,
+            description TEXT,
+            metadata LONGTEXT NOT NULL,
+            created_at DATETIME NOT NULL,
+            updated_at DATETIME NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user_id (user_id)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS audit_log (
+            id BIGINT PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            action VARCHAR(50) NOT NULL,
+            resource_type VARCHAR(50) NOT NULL,
+            resource_id VARCHAR(255) NOT NULL,
+            timestamp DATETIME NOT NULL,
+            detail JSON,
+            INDEX idx_user_id (user_id),
+            INDEX idx_timestamp (timestamp),
+            INDEX idx_resource (resource_type, resource_id)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS webhooks (
+            webhook_id VARCHAR(36) PRIMARY KEY,
+            user_id INT NOT NULL,
+            url VARCHAR(2048) NOT NULL,
+            secret VARCHAR(64) NOT NULL,
+            events TEXT NOT NULL,
+            active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at DATETIME NOT NULL,
+            updated_at DATETIME NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user_id (user_id)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id BIGINT PRIMARY KEY AUTO_INCREMENT,
+            webhook_id VARCHAR(36) NOT NULL,
+            event VARCHAR(50) NOT NULL,
+            did VARCHAR(255) NOT NULL,
+            payload TEXT NOT NULL,
+            status VARCHAR(20) NOT NULL,
+            attempts INT NOT NULL DEFAULT 0,
+            response_status SMALLINT UNSIGNED,
+            last_error TEXT,
+            created_at DATETIME NOT NULL,
+            delivered_at DATETIME,
+            FOREIGN KEY (webhook_id) REFERENCES webhooks(webhook_id) ON DELETE CASCADE,
+            INDEX idx_webhook_id (webhook_id)
+        )",
+    )
+    .await?;
+
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS user_quotas (
+            user_id INT NOT NULL,
+            resource VARCHAR(32) NOT NULL,
+            window_start DATE NOT NULL,
+            used INT UNSIGNED NOT NULL DEFAULT 0,
+            PRIMARY KEY (user_id, resource, window_start),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+    )
+    .await?;
+
+    // Secret keys are base64; retired versions keep only their public keys
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS key_versions (
+            version INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+            status VARCHAR(16) NOT NULL,
+            kem_public_key TEXT,
+            kem_secret_key TEXT,
+            sign_public_key TEXT NOT NULL,
+            sign_secret_key TEXT,
+            created_at DATETIME NOT NULL,
+            rotated_at DATETIME,
+            retired_at DATETIME,
+            created_by INT,
+            INDEX idx_status (status)
+        )",
+    )
+    .await?;
+
+    // TOTP secrets for step-up verification; a secret is used once `confirmed_at` is set,
+    // and `last_used_step` keeps a code from being accepted twice
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS user_totp (
+            user_id INT PRIMARY KEY,
+            secret VARBINARY(64) NOT NULL,
+            confirmed_at DATETIME,
+            last_used_step BIGINT,
+            created_at DATETIME NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+    )
+    .await?;
+
     info!("Database schema initialized");
     Ok(())
 }
+
+/// Adds a column to an existing table unless it is already present, so that databases
+/// created by older versions pick up new columns on startup.
+async fn add_column_if_missing(
+    conn: &mut Conn,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), mysql_async::Error> {
+    let exists: Option<i32> = conn
+        .exec_first(
+            r"SELECT 1 FROM information_schema.columns
+              WHERE table_schema = DATABASE() AND table_name = :table AND column_name = :column",
+            params! { "table" => table, "column" => column },
+        )
+        .await?;
+
+    if exists.is_none() {
+        conn.query_drop(format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .await?;
+        info!("Added column {}.{}", table, column);
+    }
+
+    Ok(())
+}
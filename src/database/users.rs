@@ -1,10 +1,18 @@
 use crate::errors::ServiceError;
+use crate::models::auth::{AuthUser, OrcidIdentity};
 use crate::models::requests::{SigninRequest, SignupRequest};
 use crate::utils::{hash_password, verify_password};
 use log::info;
 use mysql_async::{prelude::*, Pool};
+use rand_core::{OsRng, RngCore};
 use validator::Validate;
 
+/// Domain of the placeholder email given to ORCID users without a usable public email
+pub const ORCID_PLACEHOLDER_EMAIL_DOMAIN: &str = "orcid.invalid";
+
+/// Usernames tried for a new ORCID user: the iD, then the iD with random suffixes
+const ORCID_USERNAME_ATTEMPTS: usize = 4;
+
 /// Creates a new user in the database
 pub async fn create_user(
     db_pool: &Pool,
@@ -76,9 +84,131 @@ pub async fn login_user(db_pool: &Pool, req: &SigninRequest) -> Result<i32, Serv
 
     let (user_id, password_hash) = authenticate_user(db_pool, &req.email).await?;
 
-    if !verify_password(&req.password, &password_hash)? {
+    // Accounts created through ORCID login have no password
+    if password_hash.is_empty() || !verify_password(&req.password, &password_hash)? {
         return Err(ServiceError::Auth("Invalid credentials".to_string()));
     }
 
     Ok(user_id)
 }
+
+/// Finds the user linked to an ORCID iD, creating one on their first login, and records the
+/// name on their ORCID record.
+///
+/// New users are named after their iD, or the iD with a random suffix if that username is
+/// taken, and get `email` if no other account uses it, otherwise a placeholder address under
+/// `ORCID_PLACEHOLDER_EMAIL_DOMAIN`. Existing accounts are only matched by iD, never by
+/// email, so an ORCID record can't take over a password account.
+pub async fn upsert_orcid_user(
+    db_pool: &Pool,
+    orcid: &str,
+    email: Option<&str>,
+    name: Option<&str>,
+) -> Result<i32, ServiceError> {
+    let mut conn = db_pool.get_conn().await?;
+    let find_by_orcid = "SELECT id FROM users WHERE orcid = :orcid";
+
+    let existing: Option<i32> = conn
+        .exec_first(find_by_orcid, params! { "orcid" => orcid })
+        .await?;
+    if let Some(user_id) = existing {
+        conn.exec_drop(
+            "UPDATE users SET orcid_name = :name WHERE id = :id",
+            params! { "name" => name, "id" => user_id },
+        )
+        .await?;
+        return Ok(user_id);
+    }
+
+    let email_taken = match email {
+        Some(email) => conn
+            .exec_first::<i32, _, _>(
+                "SELECT 1 FROM users WHERE email = :email",
+                params! { "email" => email },
+            )
+            .await?
+            .is_some(),
+        None => true,
+    };
+    let email = match email {
+        Some(email) if !email_taken => email.to_string(),
+        _ => format!("{}@{}", orcid, ORCID_PLACEHOLDER_EMAIL_DOMAIN),
+    };
+
+    let mut username = orcid.to_string();
+    for _ in 0..ORCID_USERNAME_ATTEMPTS {
+        let result = conn
+            .exec_drop(
+                "INSERT INTO users (username, email, password_hash, orcid, orcid_name)
+                 VALUES (:username, :email, '', :orcid, :name)",
+                params! {
+                    "username" => &username,
+                    "email" => &email,
+                    "orcid" => orcid,
+                    "name" => name,
+                },
+            )
+            .await;
+
+        match result {
+            Err(mysql_async::Error::Server(err)) if err.code == 1062 => {
+                // A concurrent first login for the same iD won the insert
+                let existing: Option<i32> = conn
+                    .exec_first(find_by_orcid, params! { "orcid" => orcid })
+                    .await?;
+                if let Some(user_id) = existing {
+                    return Ok(user_id);
+                }
+                // Someone else registered the iD as their username
+                username = format!("{}-{:04x}", orcid, OsRng.next_u32() & 0xffff);
+            }
+            result => {
+                result?;
+                let user_id: i32 = conn
+                    .query_first("SELECT LAST_INSERT_ID()")
+                    .await?
+                    .ok_or_else(|| ServiceError::Internal("Failed to get user ID".to_string()))?;
+
+                info!("New user {} registered with ORCID iD {}", username, orcid);
+                return Ok(user_id);
+            }
+        }
+    }
+
+    Err(ServiceError::Internal(format!(
+        "No free username for ORCID iD {}",
+        orcid
+    )))
+}
+
+/// The ORCID iD, account email and ORCID name of a user who signed in with ORCID
+pub async fn find_orcid_identity(
+    db_pool: &Pool,
+    user_id: i64,
+) -> Result<Option<OrcidIdentity>, ServiceError> {
+    let mut conn = db_pool.get_conn().await?;
+    let identity: Option<(String, String, Option<String>)> = conn
+        .exec_first(
+            "SELECT orcid, email, orcid_name FROM users WHERE id = :id AND orcid IS NOT NULL",
+            params! { "id" => user_id },
+        )
+        .await?;
+
+    Ok(identity.map(|(orcid, email, name)| OrcidIdentity { orcid, email, name }))
+}
+
+/// Loads the user behind an authenticated token, including their role
+pub async fn find_auth_user(
+    db_pool: &Pool,
+    user_id: i64,
+) -> Result<Option<AuthUser>, ServiceError> {
+    let mut conn = db_pool.get_conn().await?;
+    let user: Option<(String, String)> = conn
+        .exec_first(
+            "SELECT username, role FROM users WHERE id = :id",
+            params! { "id" => user_id },
+        )
+        .await?;
+
+    Ok(user.map(|(username, role)| AuthUser::new(user_id, username, vec![role])))
+}
@@ -1,11 +1,17 @@
+use crate::middleware::request_id::current_request_id;
 use actix_web::{http::StatusCode, HttpResponse};
 use base64::DecodeError as Base64DecodeError;
 use hyper::http::uri::InvalidUri;
+use schemars::JsonSchema;
 use serde::Serialize;
 use serde_json::Error as SerdeJsonError;
 use std::sync::PoisonError;
 use thiserror::Error;
 
+/// `AuthError` message for an operation that needs recent sign-in or a TOTP code; its
+/// error response carries the `reauthentication_required` code
+pub const REAUTHENTICATION_REQUIRED: &str = "reauthentication required";
+
 /// Application level errors for the Bio-DID-Seq service
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -27,6 +33,9 @@ pub enum AppError {
     #[error("Validation error: {0}")]
     ValidationError(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
     #[error("Service error: {0}")]
     ServiceError(String),
 
@@ -58,6 +67,7 @@ impl actix_web::error::ResponseError for AppError {
             AppError::AuthorizationError(_) => StatusCode::FORBIDDEN,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
             AppError::ServiceError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::SerializationError => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::DeserializationError => StatusCode::INTERNAL_SERVER_ERROR,
@@ -69,10 +79,11 @@ impl actix_web::error::ResponseError for AppError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(ErrorResponse {
-            error: self.status_code().as_str().to_string(),
-            message: self.to_string(),
-        })
+        let mut body = ErrorResponse::new(self.status_code(), self.to_string());
+        if matches!(self, AppError::AuthError(message) if message == REAUTHENTICATION_REQUIRED) {
+            body.code = Some("reauthentication_required".to_string());
+        }
+        HttpResponse::build(self.status_code()).json(body)
     }
 }
 
@@ -114,6 +125,75 @@ impl From<reqwest::Error> for AppError {
     }
 }
 
+impl From<validator::ValidationErrors> for AppError {
+    fn from(err: validator::ValidationErrors) -> Self {
+        AppError::ValidationError(describe_validation_errors(&err))
+    }
+}
+
+/// One `path: message` entry per failed rule, sorted by path, e.g.
+/// `metadata.researchers[0].email: must be a valid email address`
+fn describe_validation_errors(errors: &validator::ValidationErrors) -> String {
+    validation_problems(errors, "").join("; ")
+}
+
+/// The entries of `describe_validation_errors` as a list, with paths under `prefix`
+pub fn validation_problems(errors: &validator::ValidationErrors, prefix: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    collect_validation_errors(errors, prefix, &mut entries);
+    entries.sort();
+    entries
+}
+
+fn collect_validation_errors(
+    errors: &validator::ValidationErrors,
+    prefix: &str,
+    entries: &mut Vec<String>,
+) {
+    use validator::ValidationErrorsKind;
+
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                entries.extend(
+                    field_errors
+                        .iter()
+                        .map(|e| format!("{}: {}", path, validation_message(e))),
+                );
+            }
+            ValidationErrorsKind::Struct(nested) => {
+                collect_validation_errors(nested, &path, entries)
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    collect_validation_errors(nested, &format!("{}[{}]", path, index), entries);
+                }
+            }
+        }
+    }
+}
+
+fn validation_message(error: &validator::ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    match (
+        error.code.as_ref(),
+        error.params.get("min"),
+        error.params.get("max"),
+    ) {
+        ("length", Some(min), Some(max)) => format!("length must be between {} and {}", min, max),
+        ("length", None, Some(max)) => format!("length must be at most {}", max),
+        ("length", Some(min), None) => format!("length must be at least {}", min),
+        (code, _, _) => format!("failed {} check", code),
+    }
+}
+
 /// Possible errors that can occur in the service
 #[derive(Debug, Error)]
 pub enum ServiceError {
@@ -150,10 +230,8 @@ impl actix_web::error::ResponseError for ServiceError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(ErrorResponse {
-            error: self.status_code().as_str().to_string(),
-            message: self.to_string(),
-        })
+        HttpResponse::build(self.status_code())
+            .json(ErrorResponse::new(self.status_code(), self.to_string()))
     }
 }
 
@@ -196,8 +274,26 @@ impl From<Base64DecodeError> for ServiceError {
 }
 
 /// Error response for API endpoints
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ErrorResponse {
     error: String,
     message: String,
+    // Machine-readable reason for errors clients handle specially, e.g.
+    // `reauthentication_required`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    // Quote this when reporting a problem; matches the `X-Request-Id` response header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ErrorResponse {
+    pub fn new(status: StatusCode, message: String) -> Self {
+        Self {
+            error: status.as_str().to_string(),
+            message,
+            code: None,
+            request_id: current_request_id(),
+        }
+    }
 }
@@ -0,0 +1,16 @@
+use crate::config::LogFormat;
+use tracing_subscriber::EnvFilter;
+
+/// Install the global `tracing` subscriber.
+///
+/// Events are filtered by `RUST_LOG` (default `info`). Records from the `log` crate are
+/// forwarded too, so existing `log::info!` calls carry the request span's fields.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+}
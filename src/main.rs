@@ -2,8 +2,7 @@ use actix_web::{middleware as actix_middleware, App, HttpServer};
 use base64::engine::general_purpose::STANDARD as Base64Engine;
 use base64::Engine;
 use clap::{Parser, Subcommand};
-use env_logger::Env;
-use std::env;
+use rand_core::{OsRng, RngCore};
 use std::io;
 use std::sync::Arc;
 use tokio::time::{interval, Duration};
@@ -11,6 +10,7 @@ use tokio::time::{interval, Duration};
 mod config;
 mod database;
 mod errors;
+mod logging;
 mod middleware;
 mod models;
 mod routes;
@@ -18,14 +18,44 @@ mod services;
 mod stream;
 mod utils;
 
-use config::Config;
+use config::{Config, LogFormat};
+use middleware::auth::Authentication;
+use middleware::cors::build_cors;
+use middleware::metrics::Metrics;
 use middleware::rate_limiter::UserRateLimiter;
+use middleware::request_id::RequestId;
+use models::license::License;
+use services::audit_service::AuditService;
+use services::auth_token::SigningContext;
 use services::bioagents_service::BioAgentsService;
+use services::circuit_breaker::CircuitBreaker;
 use services::dataverse_service::DataverseService;
+use services::did_cache::DidDocumentCache;
 use services::did_service::DIDService;
-use services::ipfs_service::IPFSService;
+use services::entity_enrichment::EntityEnrichmentService;
+use services::export_service::ExportService;
+use services::field_encryption::FieldEncryption;
+use services::http_client::HttpClient;
+use services::idempotency_service::IdempotencyService;
+use services::ipfs_service::{IPFSService, RetentionPolicy};
+use services::key_registry::KeyRegistry;
+use services::keyword_service::KeywordService;
+use services::metrics_service::MetricsService;
+use services::notification_service::NotificationService;
+use services::orcid_service::OrcidService;
+use services::pii_scanner::PatternPiiScanner;
+use services::provenance_service::ProvenanceService;
+use services::publication_service::PublicationService;
+use services::quota_service::QuotaService;
 use services::research_paper_service::ResearchPaperService;
+use services::sparql_service::SparqlService;
+use services::stats_service::StatsService;
+use services::step_up_service::StepUpService;
+use services::task_events::TaskEvents;
+use services::task_service::TaskService;
 use services::ucan_service::UcanService;
+use services::upload_session_service::UploadSessionService;
+use services::webhook_service::WebhookService;
 
 // Post-quantum crypto imports
 use pqcrypto_dilithium::dilithium5;
@@ -79,12 +109,26 @@ fn generate_keys(output_dir: &str) -> io::Result<()> {
     let sk_kem_b64 = Base64Engine.encode(sk_kem.as_bytes());
     let pk_sign_b64 = Base64Engine.encode(pk_sign.as_bytes());
     let sk_sign_b64 = Base64Engine.encode(sk_sign.as_bytes());
+    // Generate the key wrapping the secret keys stored in the key registry
+    let mut kek = [0u8; 32];
+    OsRng.fill_bytes(&mut kek);
 
     // Save Base64 encoded keys
     std::fs::write(format!("{}/kyber1024_public.key", output_dir), pk_kem_b64)?;
     std::fs::write(format!("{}/kyber1024_secret.key", output_dir), sk_kem_b64)?;
     std::fs::write(format!("{}/dilithium5_public.key", output_dir), pk_sign_b64)?;
     std::fs::write(format!("{}/dilithium5_secret.key", output_dir), sk_sign_b64)?;
+    std::fs::write(
+        format!("{}/key_encryption.key", output_dir),
+        Base64Engine.encode(kek),
+    )?;
+
+    // Generate the Ed25519 key UCANs are signed with
+    let service_key = DIDService::generate_keypair();
+    std::fs::write(
+        format!("{}/service_ed25519.key", output_dir),
+        &service_key.private_key_multibase,
+    )?;
 
     log::info!(
         "Base64-encoded keys generated successfully in {}:",
@@ -94,57 +138,211 @@ fn generate_keys(output_dir: &str) -> io::Result<()> {
     println!("- kyber1024_secret.key (KEM secret key)");
     println!("- dilithium5_public.key (Signature public key)");
     println!("- dilithium5_secret.key (Signature secret key)");
+    println!("- key_encryption.key (KEY_ENCRYPTION_KEY, wraps the stored secret keys)");
+    println!(
+        "- service_ed25519.key (SERVICE_SIGNING_KEY, for {})",
+        service_key.controller
+    );
 
     Ok(())
 }
 
+/// actix's default access log format, plus the request ID
+const ACCESS_LOG_FORMAT: &str =
+    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}i"#;
+
 async fn start_server() -> io::Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    logging::init(LogFormat::from_env());
     let config = Config::from_env().map_err(|e| {
         log::error!("Failed to load configuration: {}", e);
-        io::Error::new(io::ErrorKind::Other, "Configuration loading failed")
+        io::Error::other("Configuration loading failed")
+    })?;
+    // UCANs are signed with the service key, so it must be present before anything starts
+    let service_signing_key = config.get_service_signing_key().map_err(|e| {
+        log::error!("Invalid service identity: {}", e);
+        io::Error::other("Service signing key missing or invalid")
+    })?;
+
+    // Initialize database connection pool
+    let db_pool = database::init_db_pool(&config).await.map_err(|e| {
+        log::error!("Failed to initialize database pool: {}", e);
+        io::Error::other("Database initialization failed")
+    })?;
+    let db_pool = Arc::new(db_pool);
+
+    // Initialize task completion emails (no-op unless SMTP is configured)
+    let notification_service = NotificationService::new(&config, db_pool.clone()).map_err(|e| {
+        log::error!("Failed to initialize notifications: {}", e);
+        io::Error::other("Notification service initialization failed")
     })?;
+    let notification_service = Arc::new(notification_service);
+
+    // Task changes are fanned out to `/tasks/{id}/events` streams
+    let task_events = Arc::new(TaskEvents::new());
+
+    // One pooled HTTP client for every external API; services set their own timeouts
+    let http_client = HttpClient::new(&config.http_user_agent, config.http_connect_timeout)
+        .map_err(|e| {
+            log::error!("Failed to create HTTP client: {}", e);
+            io::Error::other("HTTP client initialization failed")
+        })?;
 
     // Initialize IPFS service
-    let ipfs_service = IPFSService::new(&config).await.map_err(|e| {
+    let ipfs_service = IPFSService::new(
+        &config,
+        db_pool.as_ref().clone(),
+        &http_client,
+        notification_service.clone(),
+        task_events.clone(),
+    )
+    .await
+    .map_err(|e| {
         log::error!("Failed to initialize IPFS service: {}", e);
-        io::Error::new(io::ErrorKind::Other, "IPFS service initialization failed")
+        io::Error::other("IPFS service initialization failed")
     })?;
     let ipfs_service = Arc::new(ipfs_service);
 
-    // Initialize database connection pool
-    let db_pool = database::init_db_pool(&config.database_url)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to initialize database pool: {}", e);
-            io::Error::new(io::ErrorKind::Other, "Database initialization failed")
-        })?;
-    let db_pool = Arc::new(db_pool);
+    // Initialize Prometheus metrics registry
+    let metrics_service = MetricsService::new().map_err(|e| {
+        log::error!("Failed to initialize metrics: {}", e);
+        io::Error::other("Metrics initialization failed")
+    })?;
+    let metrics_service = Arc::new(metrics_service);
+
+    // Check the database pool periodically and for the readiness probe
+    let db_health = Arc::new(database::DbHealth::new(
+        db_pool.as_ref().clone(),
+        &config,
+        metrics_service.clone(),
+    ));
+    db_health.clone().start(config.db_pool_health_interval);
+
+    // Initialize audit log shared by every mutating service
+    let audit_service = Arc::new(AuditService::new(db_pool.clone()));
+
+    // Initialize encryption of private DID metadata fields, keyed by the key registry
+    let field_encryption = FieldEncryption::from_config(&config).map_err(|e| {
+        log::error!("Failed to initialize DID field encryption: {}", e);
+        io::Error::other("DID field encryption initialization failed")
+    })?;
+    let field_encryption = Arc::new(field_encryption);
+
+    // Sign auth tokens with the current version of the rotatable key registry
+    let key_registry = KeyRegistry::new(
+        db_pool.clone(),
+        ipfs_service.clone(),
+        audit_service.clone(),
+        field_encryption.clone(),
+        &config,
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Failed to initialize signing key registry: {}", e);
+        io::Error::other("Signing key registry initialization failed")
+    })?;
+    let key_registry = Arc::new(key_registry);
+    key_registry
+        .clone()
+        .start_refresh(config.key_refresh_interval);
+
+    // Initialize webhook delivery for DID lifecycle events
+    let webhook_service = Arc::new(WebhookService::new(db_pool.clone(), &http_client).map_err(
+        |e| {
+            log::error!("Failed to create webhook HTTP client: {}", e);
+            io::Error::other("Webhook service initialization failed")
+        },
+    )?);
+
+    // Initialize keyword index and controlled vocabulary
+    let keyword_service = KeywordService::new(&config, db_pool.clone()).map_err(|e| {
+        log::error!("Failed to initialize keyword vocabulary: {}", e);
+        io::Error::other("Keyword vocabulary initialization failed")
+    })?;
+    let keyword_service = Arc::new(keyword_service);
 
     // Initialize DID service
-    let did_service = DIDService::new(db_pool.clone(), ipfs_service.clone());
+    let did_service = DIDService::new(
+        db_pool.clone(),
+        ipfs_service.clone(),
+        audit_service.clone(),
+        webhook_service.clone(),
+        keyword_service.clone(),
+        DidDocumentCache::new(config.did_cache_size, metrics_service.clone()),
+        config.default_license.clone().unwrap_or(License::CcBy4),
+        config.did_storage_endpoint.clone(),
+        config.did_document_limits,
+        config.service_endpoint_policy.clone(),
+        field_encryption,
+    );
     let did_service = Arc::new(did_service);
+    did_service
+        .clone()
+        .start_embargo_sweep(config.embargo_sweep_interval);
+    did_service.clone().start_registry_backfill();
 
     // Initialize BioAgents service
+    let bioagents_public_key = config.get_bioagents_public_key().map_err(|e| {
+        log::error!("Invalid BioAgents configuration: {}", e);
+        io::Error::other("BioAgents public key invalid")
+    })?;
     let bioagents_service = BioAgentsService::new(
-        &env::var("BIOAGENTS_API_URL").unwrap_or_else(|_| "http://localhost:3000".to_string()),
+        &http_client,
+        &config.bioagents_api_url,
+        bioagents_public_key,
+        metrics_service.clone(),
+        CircuitBreaker::new(
+            "BioAgents",
+            config.circuit_breaker_failures,
+            config.circuit_breaker_cooldown,
+        ),
+        config.bioagents_max_concurrent,
+        config.bioagents_queue_timeout,
     );
     let bioagents_service = Arc::new(bioagents_service);
 
     // Initialize Dataverse service
     let dataverse_service = DataverseService::new(
-        &env::var("DATAVERSE_API_URL")
-            .unwrap_or_else(|_| "https://dataverse.harvard.edu/api".to_string()),
-        &env::var("DATAVERSE_API_KEY").unwrap_or_else(|_| "".to_string()),
+        &http_client,
+        &config.dataverse_api_url,
+        &config.dataverse_api_key,
+        metrics_service.clone(),
+        CircuitBreaker::new(
+            "Dataverse",
+            config.circuit_breaker_failures,
+            config.circuit_breaker_cooldown,
+        ),
+        config.dataverse_direct_upload,
+        config.dataverse_max_upload_bytes,
+        config.default_license.clone().unwrap_or(License::Cc0),
     );
     let dataverse_service = Arc::new(dataverse_service);
 
     // Initialize UCAN service
-    let ucan_service = UcanService::new(db_pool.clone()).await.map_err(|e| {
+    let ucan_service = UcanService::new(
+        db_pool.clone(),
+        audit_service.clone(),
+        did_service.clone(),
+        config.ucan_delegable_actions.clone(),
+        config.service_did.clone(),
+        service_signing_key,
+        SigningContext::ucan(&config.token_signing_namespace),
+    )
+    .await
+    .map_err(|e| {
         log::error!("Failed to initialize UCAN service: {}", e);
-        io::Error::new(io::ErrorKind::Other, "UCAN service initialization failed")
+        io::Error::other("UCAN service initialization failed")
     })?;
     let ucan_service = Arc::new(ucan_service);
+    ucan_service
+        .clone()
+        .start_revocation_sweep(config.ucan_revocation_sweep_interval);
+
+    // Initialize ontology lookups for extracted biological entities
+    let entity_enrichment = Arc::new(EntityEnrichmentService::new(
+        &http_client,
+        config.entity_cache_size,
+        metrics_service.clone(),
+    ));
 
     // Initialize Research Paper service
     let research_paper_service = ResearchPaperService::new(
@@ -152,9 +350,83 @@ async fn start_server() -> io::Result<()> {
         ipfs_service.clone(),
         did_service.clone(),
         bioagents_service.clone(),
+        entity_enrichment,
+        keyword_service.clone(),
+        Arc::new(PatternPiiScanner),
+        config.pii_policy,
     );
     let research_paper_service = Arc::new(research_paper_service);
 
+    // Initialize idempotency key store
+    let idempotency_service = Arc::new(IdempotencyService::new(db_pool.clone()));
+
+    // Initialize per-user monthly quotas on expensive operations
+    let quota_service = Arc::new(QuotaService::new(
+        db_pool.clone(),
+        config.quota_limits.clone(),
+    ));
+
+    // Initialize per-user statistics
+    let stats_service = Arc::new(StatsService::new(db_pool.clone()));
+
+    // Initialize step-up verification for irreversible operations
+    let step_up_service = Arc::new(StepUpService::new(
+        db_pool.clone(),
+        config.step_up_operations.clone(),
+        config.step_up_max_age,
+    ));
+
+    // Initialize ORCID login, if configured
+    let orcid_service =
+        OrcidService::from_config(&config, &http_client, metrics_service.clone()).map(Arc::new);
+
+    // Initialize unified task view over upload and BioAgents tasks
+    let task_service = Arc::new(TaskService::new(
+        db_pool.clone(),
+        bioagents_service.clone(),
+        notification_service.clone(),
+        task_events.clone(),
+    ));
+
+    // Follow Dataverse publications until they are released or rejected
+    let publication_service = Arc::new(PublicationService::new(
+        dataverse_service.clone(),
+        did_service.clone(),
+        task_service.clone(),
+    ));
+    publication_service.clone().start_sync();
+
+    // Initialize resumable upload sessions
+    let upload_session_service = Arc::new(UploadSessionService::new(
+        db_pool.clone(),
+        ipfs_service.clone(),
+        config.upload_session_dir.clone(),
+        config.upload_chunk_bytes,
+        config.upload_session_max_bytes,
+    ));
+    upload_session_service.clone().start_sweep();
+
+    // Initialize account export
+    let export_service = Arc::new(ExportService::new(
+        did_service.clone(),
+        research_paper_service.clone(),
+    ));
+
+    // Initialize provenance chains across DIDs, papers, Dataverse and IPFS
+    let provenance_service = Arc::new(ProvenanceService::new(
+        did_service.clone(),
+        research_paper_service.clone(),
+        dataverse_service.clone(),
+        ipfs_service.clone(),
+    ));
+
+    // Initialize SPARQL queries over knowledge graphs
+    let sparql_service = Arc::new(SparqlService::new(
+        ipfs_service.clone(),
+        config.sparql_timeout,
+        config.sparql_max_results,
+    ));
+
     // Create app state
     let app_state = routes::AppState {
         ipfs_service: ipfs_service.clone(),
@@ -163,21 +435,58 @@ async fn start_server() -> io::Result<()> {
         dataverse_service: dataverse_service.clone(),
         ucan_service: ucan_service.clone(),
         research_paper_service: research_paper_service.clone(),
+        idempotency_service: idempotency_service.clone(),
+        task_service: task_service.clone(),
+        upload_session_service: upload_session_service.clone(),
+        metrics_service: metrics_service.clone(),
+        audit_service: audit_service.clone(),
+        export_service: export_service.clone(),
+        webhook_service: webhook_service.clone(),
+        quota_service: quota_service.clone(),
+        key_registry: key_registry.clone(),
+        sparql_service: sparql_service.clone(),
+        keyword_service: keyword_service.clone(),
+        stats_service: stats_service.clone(),
+        provenance_service: provenance_service.clone(),
+        publication_service: publication_service.clone(),
+        step_up_service: step_up_service.clone(),
+        orcid_service: orcid_service.clone(),
+        db_health: db_health.clone(),
     };
 
-    let rate_limiter = UserRateLimiter::new();
+    let rate_limiter = UserRateLimiter::new(config.internal_service_token.clone());
 
-    start_task_cleanup(ipfs_service.clone());
+    // `Cors` isn't `Clone`, so it is validated here and rebuilt for each worker
+    if let Err(e) = build_cors(&config) {
+        log::error!("Invalid CORS configuration: {}", e);
+        return Err(io::Error::other("CORS configuration failed"));
+    }
+    if config.cors_allow_any_origin {
+        log::warn!("CORS is allowing any origin; do not use CORS_ALLOW_ANY_ORIGIN in production");
+    }
+
+    start_task_cleanup(ipfs_service.clone(), config.retention_policy.clone());
 
     let bind_address = config.bind_address.clone();
     log::info!("Starting server at {}", bind_address);
 
     HttpServer::new(move || {
+        let cors = build_cors(&config).expect("CORS configuration validated at startup");
+
         App::new()
             .app_data(actix_web::web::Data::new(app_state.clone()))
-            .wrap(actix_middleware::Logger::default())
+            .app_data(routes::json_config(config.max_json_body_bytes))
+            // Middleware registered last runs first: request ID, metrics, logging, CORS,
+            // authentication, then rate limiting, so the limiter can see who the caller is.
+            // CORS answers preflight requests itself, so they skip authentication and rate
+            // limiting.
             .wrap(rate_limiter.clone())
-            .configure(routes::init_routes)
+            .wrap(Authentication::new())
+            .wrap(cors)
+            .wrap(actix_middleware::Logger::new(ACCESS_LOG_FORMAT))
+            .wrap(Metrics::new())
+            .wrap(RequestId::new())
+            .configure(|cfg| routes::init_routes(cfg, &config))
     })
     // Use number of CPUs, capped at 8
     .workers(num_cpus::get().min(8))
@@ -191,8 +500,10 @@ async fn start_server() -> io::Result<()> {
 }
 
 /// Spawns a background task to periodically clean up old tasks
-fn start_task_cleanup(ipfs_service: Arc<IPFSService>) {
-    tokio::spawn(async move {
+/// Unpinning for the retention policy makes IPFS calls, which aren't `Send`, so this runs on
+/// the server's runtime
+fn start_task_cleanup(ipfs_service: Arc<IPFSService>, retention_policy: RetentionPolicy) {
+    tokio::task::spawn_local(async move {
         // Cleanup every 2 hours
         let mut interval = interval(Duration::from_secs(7200));
         loop {
@@ -203,6 +514,11 @@ fn start_task_cleanup(ipfs_service: Arc<IPFSService>) {
                 Err(e) => log::error!("Task cleanup failed: {}", e),
             }
             ipfs_service.cleanup_rate_limiters().await;
+            if retention_policy.is_enabled() {
+                if let Err(e) = ipfs_service.enforce_retention(&retention_policy).await {
+                    log::error!("Retention enforcement failed: {}", e);
+                }
+            }
         }
     });
 }
@@ -0,0 +1,142 @@
+use crate::database::find_auth_user;
+use crate::errors::{AppError, ServiceError};
+use crate::models::auth::AuthUser;
+use crate::routes::AppState;
+use crate::services::ucan_service::is_ucan_token;
+use actix_web::{
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error as ActixError, FromRequest, HttpMessage, HttpRequest,
+};
+use chrono::DateTime;
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// Resolves the bearer token into an `AuthUser` request extension.
+///
+/// Requests without an `Authorization` header pass through untouched so public routes keep
+/// working; handlers that need a user extract `AuthUser`. A header carrying an
+/// invalid or expired token is rejected with 401. UCANs are left to `RequireCapability` on
+/// the routes that accept them.
+#[derive(Clone)]
+pub struct Authentication;
+
+impl Authentication {
+    pub fn new() -> Self {
+        Authentication
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = AuthenticationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthenticationMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let token = match req.headers().get("Authorization") {
+                Some(header) => header
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .map(str::to_string)
+                    .ok_or_else(|| ServiceError::Auth("Invalid token format".to_string()))?,
+                None => return service.call(req).await,
+            };
+            if is_ucan_token(&token) {
+                return service.call(req).await;
+            }
+
+            let app_state = req
+                .app_data::<web::Data<AppState>>()
+                .cloned()
+                .ok_or_else(|| ServiceError::Internal("Application state missing".to_string()))?;
+
+            let claims = app_state.ipfs_service.verify_token(&token)?;
+            let user_id = claims
+                .sub
+                .parse::<i64>()
+                .map_err(|_| ServiceError::Auth("Invalid token subject".to_string()))?;
+
+            let mut user = find_auth_user(&app_state.ipfs_service.db_pool, user_id)
+                .await?
+                .ok_or_else(|| ServiceError::Auth("User no longer exists".to_string()))?;
+            user.authenticated_at = DateTime::from_timestamp(claims.iat as i64, 0);
+
+            tracing::Span::current().record("user_id", user.id);
+            req.extensions_mut().insert(user);
+            service.call(req).await
+        })
+    }
+}
+
+/// The signed-in user, put in the request extensions by `Authentication` or, for UCAN
+/// bearers, `RequireCapability`. Requests without one are refused with 401.
+impl FromRequest for AuthUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthUser>()
+                .cloned()
+                .ok_or_else(|| AppError::AuthError("Authentication required".to_string())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::TestRequest, ResponseError};
+
+    #[actix_web::test]
+    async fn test_handlers_without_a_user_get_unauthorized() {
+        let req = TestRequest::post().uri("/api/did/did:bio:1/transfer");
+        let err = AuthUser::extract(&req.to_http_request()).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::get().to_http_request();
+        req.extensions_mut().insert(AuthUser::new(
+            7,
+            "alice".to_string(),
+            vec!["user".to_string()],
+        ));
+        assert_eq!(AuthUser::extract(&req).await.unwrap().id, 7);
+    }
+}
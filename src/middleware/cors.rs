@@ -0,0 +1,70 @@
+use crate::config::Config;
+use crate::routes::{DEPRECATION_HEADER, SUNSET_HEADER};
+use actix_cors::Cors;
+use actix_web::http::{
+    header::{self, HeaderName},
+    Method, Uri,
+};
+
+/// How long browsers may cache a preflight response, in seconds
+const PREFLIGHT_MAX_AGE_SECS: usize = 3600;
+
+/// Build the CORS layer from configuration.
+///
+/// With no allowed origins configured no CORS headers are sent, so browsers on other origins
+/// are refused while server-to-server callers are unaffected. `cors_allow_any_origin` echoes
+/// back any origin and is meant for local development only. Preflight requests are answered
+/// here and never reach the handlers.
+pub fn build_cors(config: &Config) -> Result<Cors, String> {
+    let mut cors = Cors::default().max_age(PREFLIGHT_MAX_AGE_SECS);
+
+    if config.cors_allow_any_origin {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &config.cors_allowed_origins {
+            if origin == "*" {
+                return Err(
+                    "CORS_ALLOWED_ORIGINS cannot contain '*', set CORS_ALLOW_ANY_ORIGIN instead"
+                        .to_string(),
+                );
+            }
+            let uri = origin
+                .parse::<Uri>()
+                .map_err(|e| format!("Invalid CORS origin '{}': {}", origin, e))?;
+            if uri.scheme().is_none() || uri.host().is_none() {
+                return Err(format!(
+                    "Invalid CORS origin '{}': expected scheme://host[:port]",
+                    origin
+                ));
+            }
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    let methods = config
+        .cors_allowed_methods
+        .iter()
+        .map(|m| {
+            Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid CORS method '{}'", m))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let headers = config
+        .cors_allowed_headers
+        .iter()
+        .map(|h| {
+            HeaderName::from_bytes(h.as_bytes()).map_err(|_| format!("Invalid CORS header '{}'", h))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Browser clients can only read these if exposed, and need them to notice the
+    // unversioned prefix is deprecated
+    Ok(cors
+        .allowed_methods(methods)
+        .allowed_headers(headers)
+        .expose_headers([
+            HeaderName::from_static(DEPRECATION_HEADER),
+            HeaderName::from_static(SUNSET_HEADER),
+            header::LINK,
+        ]))
+}
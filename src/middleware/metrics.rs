@@ -0,0 +1,91 @@
+use crate::routes::AppState;
+use crate::services::metrics_service::UNMATCHED_ROUTE;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error as ActixError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Records request count, latency and status per route into the `MetricsService`
+#[derive(Clone)]
+pub struct Metrics;
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Metrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let metrics = req
+            .app_data::<web::Data<AppState>>()
+            .map(|state| state.metrics_service.clone());
+        let method = req.method().to_string();
+        let started = Instant::now();
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+
+            if let Some(metrics) = metrics {
+                let elapsed = started.elapsed().as_secs_f64();
+                match &result {
+                    Ok(res) => {
+                        let route = res
+                            .request()
+                            .match_pattern()
+                            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+                        metrics.observe_request(&method, &route, res.status().as_u16(), elapsed);
+                    }
+                    // Errors from inner middleware (e.g. rejected tokens) never reach a route
+                    Err(e) => {
+                        let status = e.as_response_error().status_code().as_u16();
+                        metrics.observe_request(&method, UNMATCHED_ROUTE, status, elapsed);
+                    }
+                }
+            }
+
+            result
+        })
+    }
+}
@@ -1 +1,6 @@
+pub mod auth;
+pub mod cors;
+pub mod metrics;
 pub mod rate_limiter;
+pub mod request_id;
+pub mod ucan;
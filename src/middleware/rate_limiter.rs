@@ -1,8 +1,8 @@
 use crate::errors::ServiceError;
-use crate::routes::AppState;
+use crate::models::auth::AuthUser;
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error as ActixError,
+    Error as ActixError, HttpMessage,
 };
 use dashmap::DashMap;
 use futures_util::future::{ok, LocalBoxFuture, Ready};
@@ -21,6 +21,9 @@ use std::time::{Duration, Instant};
 const REQUESTS_PER_MINUTE: u32 = 100;
 const BURST_SIZE: u32 = 10;
 
+/// Header carrying the shared secret that marks a request as an internal service call
+pub const INTERNAL_TOKEN_HEADER: &str = "X-Internal-Service-Token";
+
 #[derive(Clone)]
 pub struct RateLimiterEntry {
     pub limiter: Arc<GovernorRateLimiter<NotKeyed, InMemoryState, QuantaClock, NoOpMiddleware>>,
@@ -31,15 +34,25 @@ pub struct RateLimiterEntry {
 pub struct UserRateLimiterMiddleware<S> {
     service: S,
     rate_limiters: Arc<DashMap<String, RateLimiterEntry>>,
+    internal_token: Option<Arc<str>>,
 }
 
-/// Rate limiter initializer
+/// Rate limiter initializer.
+///
+/// Must be wrapped *inside* `Authentication` (i.e. registered with `.wrap()` before it) so
+/// the `AuthUser` extension is already set when a request is counted. Admins and requests
+/// carrying the internal service token are not counted; everyone else is limited per user,
+/// or per IP address when unauthenticated.
 #[derive(Clone)]
-pub struct UserRateLimiter;
+pub struct UserRateLimiter {
+    internal_token: Option<Arc<str>>,
+}
 
 impl UserRateLimiter {
-    pub fn new() -> Self {
-        UserRateLimiter
+    pub fn new(internal_token: Option<String>) -> Self {
+        UserRateLimiter {
+            internal_token: internal_token.map(Arc::from),
+        }
     }
 }
 
@@ -48,6 +61,7 @@ impl<S> UserRateLimiterMiddleware<S> {
         let middleware = UserRateLimiterMiddleware {
             service,
             rate_limiters: Arc::new(DashMap::new()),
+            internal_token: None,
         };
 
         // Start the cleanup task
@@ -62,6 +76,38 @@ impl<S> UserRateLimiterMiddleware<S> {
 
         middleware
     }
+
+    /// Exempt requests presenting `token` in the internal service token header
+    pub fn with_internal_token(mut self, token: Option<Arc<str>>) -> Self {
+        self.internal_token = token;
+        self
+    }
+
+    /// Whether the request skips rate-limit accounting entirely
+    fn is_exempt(&self, req: &ServiceRequest) -> bool {
+        if req
+            .extensions()
+            .get::<AuthUser>()
+            .is_some_and(AuthUser::is_admin)
+        {
+            return true;
+        }
+
+        match (
+            &self.internal_token,
+            req.headers().get(INTERNAL_TOKEN_HEADER),
+        ) {
+            (Some(expected), Some(presented)) => {
+                constant_time_eq(expected.as_bytes(), presented.as_bytes())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Compare secrets without short-circuiting on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 impl<S, B> Transform<S, ServiceRequest> for UserRateLimiter
@@ -77,7 +123,10 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(UserRateLimiterMiddleware::new(service))
+        ok(
+            UserRateLimiterMiddleware::new(service)
+                .with_internal_token(self.internal_token.clone()),
+        )
     }
 }
 
@@ -96,19 +145,15 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        // Extract user ID from token if available, otherwise use IP
+        if self.is_exempt(&req) {
+            return Box::pin(self.service.call(req));
+        }
+
+        // Use the authenticated user's ID if available, otherwise use IP
         let user_id = req
-            .headers()
-            .get("Authorization")
-            .and_then(|token| {
-                let token = token.to_str().ok()?.strip_prefix("Bearer ")?;
-                let app_state = req.app_data::<actix_web::web::Data<AppState>>()?;
-                app_state
-                    .ipfs_service
-                    .verify_token(token)
-                    .ok()
-                    .map(|claims| claims.sub)
-            })
+            .extensions()
+            .get::<AuthUser>()
+            .map(|user| user.id.to_string())
             .unwrap_or_else(|| {
                 req.peer_addr()
                     .map(|addr| addr.ip().to_string())
@@ -140,7 +185,7 @@ where
         }
 
         let fut = self.service.call(req);
-        Box::pin(async move { fut.await })
+        Box::pin(fut)
     }
 }
 
@@ -315,6 +360,87 @@ mod tests {
         assert!(resp.is_err());
     }
 
+    // Send twice the burst size and expect none of the requests to be limited
+    async fn assert_exempt_after_burst(
+        limiter: &UserRateLimiterMiddleware<MockService>,
+        make_req: impl Fn() -> ServiceRequest,
+    ) {
+        for _ in 0..BURST_SIZE * 2 {
+            let resp = limiter.call(make_req()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_users_are_exempt() {
+        let limiter = UserRateLimiterMiddleware::new(MockService);
+
+        assert_exempt_after_burst(&limiter, || {
+            let req = create_test_request(None);
+            req.extensions_mut().insert(AuthUser::new(
+                1,
+                "admin".to_string(),
+                vec!["admin".to_string()],
+            ));
+            req
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_non_admin_is_limited() {
+        let limiter = UserRateLimiterMiddleware::new(MockService);
+        let make_req = || {
+            let req = create_test_request(None);
+            req.extensions_mut().insert(AuthUser::new(
+                7,
+                "researcher".to_string(),
+                vec!["user".to_string()],
+            ));
+            req
+        };
+
+        for _ in 0..BURST_SIZE {
+            let resp = limiter.call(make_req()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert!(limiter.call(make_req()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_internal_token_is_exempt() {
+        let limiter = UserRateLimiterMiddleware::new(MockService)
+            .with_internal_token(Some(Arc::from("internal-secret")));
+
+        assert_exempt_after_burst(&limiter, || {
+            TestRequest::get()
+                .uri("/test")
+                .insert_header((INTERNAL_TOKEN_HEADER, "internal-secret"))
+                .peer_addr("0.0.0.0:8081".parse().unwrap())
+                .to_srv_request()
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_wrong_internal_token_is_limited() {
+        let limiter = UserRateLimiterMiddleware::new(MockService)
+            .with_internal_token(Some(Arc::from("internal-secret")));
+        let make_req = || {
+            TestRequest::get()
+                .uri("/test")
+                .insert_header((INTERNAL_TOKEN_HEADER, "guess"))
+                .peer_addr("3.3.3.3:8081".parse().unwrap())
+                .to_srv_request()
+        };
+
+        for _ in 0..BURST_SIZE {
+            let resp = limiter.call(make_req()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert!(limiter.call(make_req()).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_rate_limiter_cleanup() {
         let rate_limiters = Arc::new(DashMap::new());
@@ -0,0 +1,127 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::{HeaderName, HeaderValue},
+    Error as ActixError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header carrying the request ID in both directions
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied request ID that is kept; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request being handled on this task, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Assigns every request an ID and runs it inside a `request` span.
+///
+/// A well-formed incoming `X-Request-Id` is reused so that IDs can be correlated with a
+/// proxy or calling service; otherwise a UUID is generated. The ID is echoed in the
+/// `X-Request-Id` response header and included in JSON error bodies.
+#[derive(Clone)]
+pub struct RequestId;
+
+impl RequestId {
+    pub fn new() -> Self {
+        RequestId
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = RequestIdMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        let request_id = req
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let header_value =
+            HeaderValue::from_str(&request_id).expect("request IDs are visible ASCII");
+        // Also set on the request so the access log and handlers see generated IDs
+        req.headers_mut()
+            .insert(REQUEST_ID_HEADER, header_value.clone());
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+            user_id = tracing::field::Empty,
+        );
+
+        let handled = async move {
+            match service.call(req).await {
+                Ok(mut res) => {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, header_value);
+                    Ok(res)
+                }
+                // Errors from inner middleware (e.g. rejected tokens) are rendered here, while
+                // the request ID is still in scope, so their bodies carry it too
+                Err(e) => {
+                    let mut response = e.error_response();
+                    response
+                        .headers_mut()
+                        .insert(REQUEST_ID_HEADER, header_value);
+                    Err(InternalError::from_response(e, response).into())
+                }
+            }
+        };
+
+        Box::pin(REQUEST_ID.scope(request_id, handled.instrument(span)))
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
@@ -0,0 +1,181 @@
+use crate::database::find_auth_user;
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::routes::AppState;
+use crate::services::ucan_service::{is_ucan_token, BioAction, BioResource, ANY_RESOURCE};
+use actix_web::{
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error as ActixError, HttpMessage,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use log::info;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// Where the ID of the resource a route acts on comes from
+#[derive(Clone, Copy)]
+enum ResourceId {
+    // The route doesn't target an existing resource (e.g. creation); a wildcard
+    // capability is required
+    Any,
+    // Named path segment, e.g. `{did}`
+    Path(&'static str),
+    // Top-level string field of the JSON body
+    BodyField(&'static str),
+}
+
+/// Route middleware enforcing a UCAN capability.
+///
+/// Requests authenticated with a session token pass through; the handler's ownership checks
+/// apply as before. When the `Authorization` header carries a UCAN instead, it is validated
+/// and must grant `action` on the route's resource, otherwise the request fails with 403.
+/// The request then runs as the user who issued the UCAN.
+#[derive(Clone)]
+pub struct RequireCapability {
+    resource: fn(String) -> BioResource,
+    resource_id: ResourceId,
+    action: BioAction,
+}
+
+impl RequireCapability {
+    /// Require `action` on every resource of a type, e.g.
+    /// `RequireCapability::new(BioResource::DID, BioAction::Create)`
+    pub fn new(resource: fn(String) -> BioResource, action: BioAction) -> Self {
+        Self {
+            resource,
+            resource_id: ResourceId::Any,
+            action,
+        }
+    }
+
+    /// Take the resource ID from a path segment
+    pub fn on_path(mut self, segment: &'static str) -> Self {
+        self.resource_id = ResourceId::Path(segment);
+        self
+    }
+
+    /// Take the resource ID from a field of the JSON body
+    pub fn on_body_field(mut self, field: &'static str) -> Self {
+        self.resource_id = ResourceId::BodyField(field);
+        self
+    }
+}
+
+pub struct RequireCapabilityMiddleware<S> {
+    service: Rc<S>,
+    requirement: RequireCapability,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireCapability
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = RequireCapabilityMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireCapabilityMiddleware {
+            service: Rc::new(service),
+            requirement: self.clone(),
+        })
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequireCapabilityMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let requirement = self.requirement.clone();
+
+        Box::pin(async move {
+            if req.extensions().contains::<AuthUser>() {
+                return service.call(req).await;
+            }
+
+            let token = req
+                .headers()
+                .get("Authorization")
+                .and_then(|header| header.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .filter(|token| is_ucan_token(token))
+                .map(str::to_string)
+                .ok_or_else(|| AppError::AuthError("Authentication required".to_string()))?;
+
+            let app_state = req
+                .app_data::<web::Data<AppState>>()
+                .cloned()
+                .ok_or_else(|| AppError::ServiceError("Application state missing".to_string()))?;
+
+            let data = app_state
+                .ucan_service
+                .validate_token(&token)
+                .await?
+                .map_err(AppError::AuthError)?;
+
+            let resource_id = match requirement.resource_id {
+                ResourceId::Any => None,
+                ResourceId::Path(segment) => req.match_info().get(segment).map(str::to_string),
+                ResourceId::BodyField(field) => {
+                    let body = req.extract::<web::Bytes>().await?;
+                    let id = serde_json::from_slice::<serde_json::Value>(&body)
+                        .ok()
+                        .and_then(|value| value.get(field)?.as_str().map(str::to_string));
+                    req.set_payload(Payload::from(body));
+                    id
+                }
+            };
+            let resource =
+                (requirement.resource)(resource_id.unwrap_or_else(|| ANY_RESOURCE.to_string()));
+
+            if !data
+                .typed_capabilities
+                .iter()
+                .any(|capability| capability.grants(&resource, requirement.action))
+            {
+                return Err(AppError::AuthorizationError(format!(
+                    "UCAN does not grant '{}' on '{}'",
+                    requirement.action, resource
+                ))
+                .into());
+            }
+
+            let user_id = app_state
+                .ucan_service
+                .token_user_id(&token)
+                .await?
+                .ok_or_else(|| {
+                    AppError::AuthorizationError(
+                        "Only UCANs issued by this service can act for a user".to_string(),
+                    )
+                })?;
+            let user = find_auth_user(&app_state.ipfs_service.db_pool, user_id)
+                .await?
+                .ok_or_else(|| AppError::AuthError("User no longer exists".to_string()))?;
+
+            info!(
+                "UCAN from {} grants '{}' on '{}' for user {}",
+                data.issuer, requirement.action, resource, user.id
+            );
+            tracing::Span::current().record("user_id", user.id);
+            req.extensions_mut().insert(user);
+            service.call(req).await
+        })
+    }
+}
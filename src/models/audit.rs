@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Default and maximum page sizes for audit log queries
+pub const DEFAULT_AUDIT_PAGE_SIZE: u32 = 50;
+pub const MAX_AUDIT_PAGE_SIZE: u32 = 200;
+
+/// A single recorded mutating operation
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub user_id: i64,
+    // e.g. "did.create", "ucan.revoke", "dataset.publish"
+    pub action: String,
+    // e.g. "did", "ucan_token", "dataset"
+    pub resource_type: String,
+    pub resource_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub detail: Option<serde_json::Value>,
+}
+
+/// Filters and pagination for `GET /api/admin/audit`
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AuditQuery {
+    pub user_id: Option<i64>,
+    // Inclusive lower bound, RFC 3339
+    pub from: Option<DateTime<Utc>>,
+    // Exclusive upper bound, RFC 3339
+    pub to: Option<DateTime<Utc>>,
+    // 1-based page number
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+/// One page of audit entries, newest first
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditEntry>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: u64,
+}
@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Post-Quantum Safe Auth Token Header.
@@ -6,6 +8,10 @@ pub struct TokenHeader {
     pub alg: String,
     pub typ: String,
     pub nonce: String,
+    // Version of the Dilithium5 key the token was signed with; absent on tokens issued
+    // before key rotation, which used the initial key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<u32>,
 }
 
 /// PQS Token Claims
@@ -21,11 +27,22 @@ pub struct Claims {
 }
 
 /// Auth Response containing PQS token
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct AuthResponse {
     pub token: String,
 }
 
+/// ORCID details of a user who signed in with ORCID, used to fill in the iD of their own
+/// entry among a DID's researchers
+#[derive(Debug, Clone)]
+pub struct OrcidIdentity {
+    pub orcid: String,
+    // Account email, a placeholder if ORCID had no usable one
+    pub email: String,
+    // Name on the ORCID record as of the last sign-in
+    pub name: Option<String>,
+}
+
 /// User authentication model
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthUser {
@@ -33,10 +50,12 @@ pub struct AuthUser {
     pub id: i64,
     pub username: String,
     pub roles: Vec<String>,
+    // When the session token was issued; unset for UCAN and internal callers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authenticated_at: Option<DateTime<Utc>>,
 }
 
 impl AuthUser {
-    #[allow(dead_code)]
     pub fn new(user_id: i64, username: String, roles: Vec<String>) -> Self {
         Self {
             user_id,
@@ -44,10 +63,10 @@ impl AuthUser {
             id: user_id,
             username,
             roles,
+            authenticated_at: None,
         }
     }
 
-    #[allow(dead_code)]
     pub fn is_admin(&self) -> bool {
         self.roles.contains(&"admin".to_string())
     }
@@ -71,6 +90,7 @@ pub struct ApiKeyRequest {
 
 /// Login response model
 #[derive(Debug, Serialize)]
+#[allow(dead_code)]
 pub struct LoginResponse {
     pub token: String,
     pub user: AuthUser,
@@ -78,6 +98,7 @@ pub struct LoginResponse {
 
 /// API key response model
 #[derive(Debug, Serialize)]
+#[allow(dead_code)]
 pub struct ApiKeyResponse {
     pub key: String,
     pub expires_at: String,
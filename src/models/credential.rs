@@ -0,0 +1,102 @@
+use crate::errors::AppError;
+use crate::models::did::canonicalize_value;
+use crate::models::requests::validate_not_blank;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use validator::Validate;
+
+/// JSON-LD context of W3C Verifiable Credentials 1.1
+pub const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+
+/// JSON-LD context of the `Ed25519Signature2020` proof suite
+pub const ED25519_2020_CONTEXT: &str = "https://w3id.org/security/suites/ed25519-2020/v1";
+
+/// Proof type of credentials issued by this service
+pub const ED25519_SIGNATURE_2020: &str = "Ed25519Signature2020";
+
+/// W3C Verifiable Credential
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VerifiableCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub issuer: String,
+    #[serde(rename = "issuanceDate")]
+    pub issuance_date: DateTime<Utc>,
+    // Claims about the subject, with the subject's identifier as `id`
+    #[serde(rename = "credentialSubject")]
+    pub credential_subject: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<CredentialProof>,
+}
+
+/// Signature over a credential by one of the issuer's assertion methods
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CredentialProof {
+    #[serde(rename = "type")]
+    pub proof_type: String,
+    pub created: DateTime<Utc>,
+    #[serde(rename = "verificationMethod")]
+    pub verification_method: String,
+    #[serde(rename = "proofPurpose")]
+    pub proof_purpose: String,
+    // base58btc multibase of the Ed25519 signature
+    #[serde(rename = "proofValue", default)]
+    pub proof_value: String,
+}
+
+impl VerifiableCredential {
+    /// Bytes the proof signs: the canonical JSON (see `canonicalize`) of the credential with
+    /// `proof.proofValue` left out, so the proof's own options are covered as well
+    pub fn signing_input(&self) -> Result<Vec<u8>, AppError> {
+        let mut value = serde_json::to_value(self).map_err(|_| AppError::SerializationError)?;
+        if let Some(proof) = value.get_mut("proof").and_then(Value::as_object_mut) {
+            proof.remove("proofValue");
+        }
+        canonicalize_value(&value)
+    }
+}
+
+/// Request to issue a credential from a DID
+#[derive(Debug, Clone, Deserialize, JsonSchema, Validate)]
+pub struct IssueCredentialRequest {
+    // Identifier of what the credential is about, e.g. a dataset DID
+    #[validate(custom(function = "validate_not_blank"), length(max = 2048))]
+    pub subject: String,
+    // Claims about the subject; `id` is taken from `subject`
+    #[serde(default)]
+    pub claims: Map<String, Value>,
+    // `privateKeyMultibase` of one of the DID's assertion methods; used to sign and not stored
+    #[validate(custom(function = "validate_not_blank"))]
+    pub secret_key: String,
+}
+
+/// Outcome of verifying a credential
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct CredentialVerification {
+    pub verified: bool,
+    pub issuer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification_method: Option<String>,
+    // Why verification failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CredentialVerification {
+    pub fn failed(credential: &VerifiableCredential, error: impl Into<String>) -> Self {
+        Self {
+            verified: false,
+            issuer: credential.issuer.clone(),
+            verification_method: credential
+                .proof
+                .as_ref()
+                .map(|proof| proof.verification_method.clone()),
+            error: Some(error.into()),
+        }
+    }
+}
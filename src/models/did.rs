@@ -1,10 +1,34 @@
+use crate::database::db_time::{from_db, to_db};
+use crate::errors::AppError;
+use crate::models::auth::OrcidIdentity;
+use crate::models::license::License;
+use crate::models::requests::{validate_no_blank_entries, validate_not_blank};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use chrono::{DateTime, Utc};
+use ed25519_zebra::{SigningKey, VerificationKey};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
+use validator::Validate;
+
+/// Schema version of the DID documents this service writes; see `migrate_document`
+pub const DID_DOCUMENT_SCHEMA_VERSION: u32 = 1;
+
+/// Deepest nesting of arrays and objects allowed inside a `custom_fields` value
+pub const MAX_CUSTOM_FIELDS_DEPTH: usize = 16;
+
+/// Longest related identifier, and identifier or relation type, kept in `did_relations`;
+/// longer ones stay in the document but aren't indexed for backlinks
+const MAX_RELATION_IDENTIFIER_LEN: usize = 255;
+const MAX_RELATION_TYPE_LEN: usize = 64;
+
+/// Longest attachment CID kept in `did_attachments`
+const MAX_ATTACHMENT_CID_LEN: usize = 100;
 
 /// W3C-compliant DID Document for biological research data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DIDDocument {
     #[serde(rename = "@context")]
     pub context: Vec<String>,
@@ -22,10 +46,125 @@ pub struct DIDDocument {
     pub updated: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<BiometadataExtension>,
+    // Shape the document was stored with; 0 for documents written before versioning
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: u32,
+}
+
+impl DIDDocument {
+    /// Designate the verification methods used for assertions. Each reference is a method
+    /// ID, or a fragment (`keys-1` / `#keys-1`) of this document, and must name one of its
+    /// verification methods. An empty list removes `assertionMethod`.
+    pub fn set_assertion_methods(&mut self, references: &[String]) -> Result<(), AppError> {
+        let mut ids: Vec<String> = Vec::with_capacity(references.len());
+        for reference in references {
+            let reference = reference.trim();
+            let id = if reference.starts_with("did:") {
+                reference.to_string()
+            } else {
+                format!("{}#{}", self.id, reference.trim_start_matches('#'))
+            };
+            if !self
+                .verification_method
+                .iter()
+                .any(|method| method.id == id)
+            {
+                return Err(AppError::ValidationError(format!(
+                    "Assertion method '{}' is not a verification method of {}",
+                    reference, self.id
+                )));
+            }
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+
+        self.assertion_method = (!ids.is_empty()).then_some(ids);
+        Ok(())
+    }
+
+    /// Sort the verification methods and services by ID, and the authentication and
+    /// assertion method references, so documents with the same content serialize to the
+    /// same bytes however their entries were added
+    pub fn normalize_order(&mut self) {
+        self.verification_method.sort_by(|a, b| a.id.cmp(&b.id));
+        self.service.sort_by(|a, b| a.id.cmp(&b.id));
+        self.authentication.sort();
+        if let Some(assertion_method) = self.assertion_method.as_mut() {
+            assertion_method.sort();
+        }
+    }
+
+    /// Related identifiers of the metadata that fit `did_relations`, once per identifier
+    /// and relation type
+    pub fn relations(&self) -> Vec<&RelatedIdentifier> {
+        let mut relations: Vec<&RelatedIdentifier> = Vec::new();
+        let listed = self
+            .metadata
+            .iter()
+            .flat_map(|metadata| metadata.related_identifiers.iter().flatten());
+        for related in listed {
+            let fits = related.identifier.chars().count() <= MAX_RELATION_IDENTIFIER_LEN
+                && related.identifier_type.chars().count() <= MAX_RELATION_TYPE_LEN
+                && related.relation_type.chars().count() <= MAX_RELATION_TYPE_LEN;
+            if fits
+                && !relations.iter().any(|r| {
+                    r.identifier == related.identifier && r.relation_type == related.relation_type
+                })
+            {
+                relations.push(related);
+            }
+        }
+        relations
+    }
+
+    /// End of the embargo in the document's metadata, if any, whether or not it has passed
+    pub fn embargo_until(&self) -> Option<DateTime<Utc>> {
+        self.metadata.as_ref().and_then(|m| m.embargo_until)
+    }
+
+    /// Whether the document is still embargoed at `now`
+    pub fn is_embargoed(&self, now: DateTime<Utc>) -> bool {
+        self.embargo_until().is_some_and(|until| until > now)
+    }
+
+    /// CIDs of files attached with `attach_file`, taken from their service IDs
+    pub fn attached_cids(&self) -> Vec<&str> {
+        let prefix = format!("{}#attachment-", self.id);
+        let mut cids: Vec<&str> = self
+            .service
+            .iter()
+            .filter_map(|service| service.id.strip_prefix(&prefix))
+            .filter(|cid| !cid.is_empty() && cid.len() <= MAX_ATTACHMENT_CID_LEN)
+            .collect();
+        cids.sort_unstable();
+        cids.dedup();
+        cids
+    }
+
+    /// Remove what an embargo keeps from anyone but the owner: attached files and the
+    /// metadata that describes the data itself. The title, researchers' names, keywords,
+    /// license and identifiers stay so the dataset can still be found and cited.
+    pub fn strip_embargoed(&mut self) {
+        let prefix = format!("{}#attachment-", self.id);
+        self.service
+            .retain(|service| !service.id.starts_with(&prefix));
+        if let Some(metadata) = &mut self.metadata {
+            metadata.description = None;
+            metadata.dataverse_link = None;
+            metadata.related_identifiers = None;
+            metadata.dataset_size = None;
+            metadata.funding_info = None;
+            metadata.custom_fields = None;
+            for researcher in &mut metadata.researchers {
+                researcher.email = None;
+            }
+        }
+    }
 }
 
 /// Verification method for authenticating control of the DID
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VerificationMethod {
     pub id: String,
     pub controller: String,
@@ -38,7 +177,7 @@ pub struct VerificationMethod {
 }
 
 /// JSON Web Key for cryptographic operations
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct KeyJwk {
     pub kty: String,
     pub crv: String,
@@ -52,126 +191,1450 @@ pub struct KeyJwk {
 }
 
 /// Service endpoint definition for DID document
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct Service {
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
     pub id: String,
     #[serde(rename = "type")]
+    #[validate(custom(function = "validate_not_blank"), length(max = 100))]
     pub service_type: String,
     #[serde(rename = "serviceEndpoint")]
+    #[validate(url(message = "must be a valid URL"), length(max = 2048))]
     pub service_endpoint: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = 1000))]
     pub description: Option<String>,
 }
 
 /// Biological metadata extension for research data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct BiometadataExtension {
+    #[validate(custom(function = "validate_not_blank"), length(max = 500))]
     pub title: String,
+    #[validate(length(max = 10000))]
     pub description: Option<String>,
+    #[validate(nested)]
     pub researchers: Vec<Researcher>,
+    // At most `DocumentLimits::max_keywords`
+    #[validate(custom(function = "validate_no_blank_entries"))]
     pub keywords: Vec<String>,
+    #[validate(length(max = 100))]
     pub data_type: String,
+    // SPDX ID, normalized on create/update; the configured default when omitted
+    #[serde(default)]
+    #[validate(length(max = 255))]
     pub license: String,
+    #[validate(length(max = 255))]
     pub doi: Option<String>,
+    #[validate(length(max = 255))]
     pub handle: Option<String>,
+    #[validate(url(message = "must be a valid URL"))]
     pub dataverse_link: Option<String>,
     pub related_identifiers: Option<Vec<RelatedIdentifier>>,
     pub dataset_size: Option<u64>,
     pub funding_info: Option<Vec<FundingInfo>>,
     pub creation_date: DateTime<Utc>,
     pub last_modified: DateTime<Utc>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    // New values nested deeper than MAX_CUSTOM_FIELDS_DEPTH are rejected by `DocumentLimits`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub custom_fields: Option<HashMap<String, serde_json::Value>>,
+    // Until then only the owner sees the description and other details, and attached
+    // files can only be read by their uploader
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embargo_until: Option<DateTime<Utc>>,
+}
+
+/// Levels of arrays and objects in `value`; scalars are 0
+fn json_depth(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::Array(items) => 1 + items.iter().map(json_depth).max().unwrap_or(0),
+        serde_json::Value::Object(members) => {
+            1 + members.values().map(json_depth).max().unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+impl BiometadataExtension {
+    /// Validate the license and rewrite it to its canonical SPDX ID, filling in `default`
+    /// when none was given
+    pub fn normalize_license(&mut self, default: &License) -> Result<(), AppError> {
+        self.license = if self.license.trim().is_empty() {
+            default.to_string()
+        } else {
+            License::parse(&self.license)?.to_string()
+        };
+        Ok(())
+    }
+
+    /// Fill in the iD of the signed-in researcher's own entry: the first without an ORCID
+    /// iD whose email matches their account, or failing that whose name matches their
+    /// ORCID record. Nothing changes if an entry already carries the iD.
+    pub fn link_orcid(&mut self, identity: &OrcidIdentity) {
+        if self
+            .researchers
+            .iter()
+            .any(|r| r.orcid.as_deref() == Some(identity.orcid.as_str()))
+        {
+            return;
+        }
+
+        let unlinked = |r: &Researcher| r.orcid.as_deref().is_none_or(|o| o.trim().is_empty());
+        let same_name = |a: &str, b: &str| {
+            a.split_whitespace()
+                .map(str::to_lowercase)
+                .eq(b.split_whitespace().map(str::to_lowercase))
+        };
+        let by_email = self.researchers.iter().position(|r| {
+            unlinked(r)
+                && r.email
+                    .as_deref()
+                    .is_some_and(|email| email.eq_ignore_ascii_case(&identity.email))
+        });
+        let by_name = || {
+            let name = identity.name.as_deref()?;
+            self.researchers
+                .iter()
+                .position(|r| unlinked(r) && same_name(&r.name, name))
+        };
+        if let Some(index) = by_email.or_else(by_name) {
+            self.researchers[index].orcid = Some(identity.orcid.clone());
+        }
+    }
+}
+
+/// Caps on the size of DID documents, checked when they are created or updated so a
+/// single document can't grow to megabytes in IPFS and the metadata index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DocumentLimits {
+    pub max_researchers: usize,
+    pub max_keywords: usize,
+    pub max_custom_fields: usize,
+    // Length of the document serialized as JSON, as stored in IPFS before compression
+    pub max_document_bytes: usize,
+}
+
+impl DocumentLimits {
+    /// Checks the metadata's researchers, keywords and custom fields against their limits,
+    /// and the nesting of custom field values against `MAX_CUSTOM_FIELDS_DEPTH`
+    pub fn check_metadata(&self, metadata: &BiometadataExtension) -> Result<(), AppError> {
+        let counts = [
+            (
+                "researchers",
+                metadata.researchers.len(),
+                self.max_researchers,
+            ),
+            ("keywords", metadata.keywords.len(), self.max_keywords),
+            (
+                "custom_fields",
+                metadata.custom_fields.as_ref().map_or(0, HashMap::len),
+                self.max_custom_fields,
+            ),
+        ];
+        for (field, count, max) in counts {
+            if count > max {
+                return Err(AppError::ValidationError(format!(
+                    "metadata.{} has {} entries, more than the limit of {}",
+                    field, count, max
+                )));
+            }
+        }
+        if metadata
+            .custom_fields
+            .iter()
+            .flat_map(HashMap::values)
+            .any(|value| json_depth(value) > MAX_CUSTOM_FIELDS_DEPTH)
+        {
+            return Err(AppError::ValidationError(format!(
+                "metadata.custom_fields may not be nested more than {} levels deep",
+                MAX_CUSTOM_FIELDS_DEPTH
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the serialized document against the document size limit
+    pub fn check_document_size(&self, did_json: &str) -> Result<(), AppError> {
+        if did_json.len() > self.max_document_bytes {
+            return Err(AppError::ValidationError(format!(
+                "DID document is {} bytes, more than the limit of {} bytes",
+                did_json.len(),
+                self.max_document_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// URL schemes service endpoints may use, so documents can't point consumers at
+/// `javascript:`, `file:` or other URLs that are dangerous to dereference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceEndpointPolicy {
+    // Lowercase, without the trailing `:`
+    schemes: Vec<String>,
+}
+
+impl ServiceEndpointPolicy {
+    /// Default allow-list
+    pub const DEFAULT_SCHEMES: &'static str = "https,ipfs,did";
+
+    /// `None` if the list is empty or an entry isn't a URL scheme
+    pub fn new(schemes: &[String]) -> Option<Self> {
+        let schemes = schemes
+            .iter()
+            .map(|scheme| scheme.trim().trim_end_matches(':').to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let valid = |scheme: &String| {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        };
+        (!schemes.is_empty() && schemes.iter().all(valid)).then_some(Self { schemes })
+    }
+
+    /// Whether `endpoint`'s scheme is allowed
+    pub fn allows(&self, endpoint: &str) -> bool {
+        endpoint.split_once(':').is_some_and(|(scheme, _)| {
+            self.schemes
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+        })
+    }
+
+    /// Checks every service's endpoint against the allow-list
+    pub fn check(&self, services: &[Service]) -> Result<(), AppError> {
+        match services
+            .iter()
+            .find(|service| !self.allows(&service.service_endpoint))
+        {
+            Some(service) => Err(AppError::ValidationError(format!(
+                "Service {} endpoint '{}' must use one of the schemes: {}",
+                service.id,
+                service.service_endpoint,
+                self.schemes.join(", ")
+            ))),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Researcher information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct Researcher {
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
     pub name: String,
+    #[validate(length(max = 100))]
     pub orcid: Option<String>,
+    #[validate(length(max = 100))]
     pub role: String,
+    #[validate(length(max = 255))]
     pub affiliation: Option<String>,
+    #[validate(email(message = "must be a valid email address"))]
     pub email: Option<String>,
 }
 
 /// Related identifier for cross-referencing
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RelatedIdentifier {
     pub identifier: String,
     pub identifier_type: String,
     pub relation_type: String,
 }
 
+/// When a DID's current document changed, as recorded next to its CID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DidModification {
+    pub updated_at: DateTime<Utc>,
+    pub embargo_until: Option<DateTime<Utc>>,
+}
+
+impl DidModification {
+    /// Last change to what readers see at `now`. Lifting an embargo changes the document
+    /// everyone but the owner gets without storing a new version, so the end of a passed
+    /// embargo counts as a change.
+    pub fn last_modified(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.embargo_until {
+            Some(until) if until <= now => self.updated_at.max(until),
+            _ => self.updated_at,
+        }
+    }
+
+    /// Seconds a response may be cached at `now`: `max_age`, or less when an embargo
+    /// ends sooner, so caches don't keep serving the stripped document past it
+    pub fn max_age(&self, now: DateTime<Utc>, max_age: u64) -> u64 {
+        match self.embargo_until {
+            Some(until) if until > now => max_age.min((until - now).num_seconds().max(0) as u64),
+            _ => max_age,
+        }
+    }
+}
+
+/// A DID whose current document lists another DID among its related identifiers
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DidBacklink {
+    // The referencing DID
+    pub did: String,
+    pub identifier_type: String,
+    // How the referencing DID relates to the target, e.g. IsDerivedFrom
+    pub relation_type: String,
+}
+
+/// Media type of a DID resolution result
+pub const DID_RESOLUTION_MEDIA_TYPE: &str =
+    "application/ld+json;profile=\"https://w3id.org/did-resolution\"";
+
+/// Media types a DID document alone can be returned as
+pub const DID_LD_JSON_MEDIA_TYPE: &str = "application/did+ld+json";
+pub const DID_JSON_MEDIA_TYPE: &str = "application/did+json";
+
+/// Result of resolving a DID, as defined by W3C DID Resolution
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidResolutionResult {
+    #[serde(rename = "@context")]
+    pub context: String,
+    // Unset when resolution failed
+    pub did_document: Option<DIDDocument>,
+    pub did_resolution_metadata: DidResolutionMetadata,
+    pub did_document_metadata: DidDocumentMetadata,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidResolutionMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // Resolution error code such as `notFound` or `invalidDid`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocumentMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<DateTime<Utc>>,
+    // CID of the document version that was resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+}
+
+impl DidResolutionResult {
+    const CONTEXT: &'static str = "https://w3id.org/did-resolution/v1";
+
+    /// Successful resolution of `document`, stored at `cid`
+    pub fn resolved(document: DIDDocument, cid: String, content_type: &str) -> Self {
+        DidResolutionResult {
+            context: Self::CONTEXT.to_string(),
+            did_resolution_metadata: DidResolutionMetadata {
+                content_type: Some(content_type.to_string()),
+                error: None,
+            },
+            did_document_metadata: DidDocumentMetadata {
+                created: Some(document.created),
+                updated: Some(document.updated),
+                version_id: Some(cid),
+            },
+            did_document: Some(document),
+        }
+    }
+
+    /// Failed resolution with the given error code
+    pub fn failed(error: &str) -> Self {
+        DidResolutionResult {
+            context: Self::CONTEXT.to_string(),
+            did_document: None,
+            did_resolution_metadata: DidResolutionMetadata {
+                content_type: None,
+                error: Some(error.to_string()),
+            },
+            did_document_metadata: DidDocumentMetadata::default(),
+        }
+    }
+}
+
 /// Funding information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FundingInfo {
     pub funder_name: String,
     pub grant_id: Option<String>,
     pub award_title: Option<String>,
 }
 
+/// Multicodec prefix for Ed25519 public keys in `did:key` and `publicKeyMultibase`
+pub const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// Multicodec prefix for Ed25519 private keys (`ed25519-priv`)
+pub const ED25519_PRIVATE_MULTICODEC: [u8; 2] = [0x80, 0x26];
+
+/// base58btc multibase (`z...`) of a multicodec-prefixed key
+pub fn multibase_key(multicodec: &[u8], key: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(multicodec.len() + key.len());
+    bytes.extend_from_slice(multicodec);
+    bytes.extend_from_slice(key);
+    format!("z{}", bs58::encode(bytes).into_string())
+}
+
+/// Decode a base58btc (`z`-prefixed) Ed25519 public key, with or without the multicodec prefix
+pub fn decode_ed25519_multibase(multibase: &str) -> Option<VerificationKey> {
+    let bytes = bs58::decode(multibase.strip_prefix('z')?).into_vec().ok()?;
+    let raw = match bytes.strip_prefix(&ED25519_MULTICODEC) {
+        Some(raw) if raw.len() == 32 => raw,
+        _ if bytes.len() == 32 => &bytes,
+        _ => return None,
+    };
+    VerificationKey::try_from(raw).ok()
+}
+
+/// Decode a base58btc (`z`-prefixed) Ed25519 private key seed, as returned in
+/// `privateKeyMultibase`, with or without the multicodec prefix
+pub fn decode_ed25519_private_multibase(multibase: &str) -> Option<SigningKey> {
+    let bytes = bs58::decode(multibase.trim().strip_prefix('z')?)
+        .into_vec()
+        .ok()?;
+    let raw = match bytes.strip_prefix(&ED25519_PRIVATE_MULTICODEC) {
+        Some(raw) if raw.len() == 32 => raw,
+        _ if bytes.len() == 32 => &bytes,
+        _ => return None,
+    };
+    SigningKey::try_from(raw).ok()
+}
+
+/// Ed25519 keypair generated by the server for a client.
+///
+/// The private key is only ever returned in the response that created it; the server
+/// keeps no copy.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GeneratedKeypair {
+    // `did:key` identifier of the public key, usable as a DID controller
+    pub controller: String,
+    #[serde(rename = "type")]
+    pub key_type: String,
+    #[serde(rename = "publicKeyMultibase")]
+    pub public_key_multibase: String,
+    // base58btc multibase of the multicodec-prefixed 32-byte private key seed
+    #[serde(rename = "privateKeyMultibase")]
+    pub private_key_multibase: String,
+}
+
+/// Verification method types accepted at DID creation time
+pub const SUPPORTED_KEY_TYPES: &[&str] = &[
+    "Ed25519VerificationKey2020",
+    "JsonWebKey2020",
+    "EcdsaSecp256k1VerificationKey2019",
+    "Multikey",
+    "Dilithium5VerificationKey2024",
+];
+
+/// Verification method supplied by the client when creating a DID
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VerificationMethodInput {
+    #[serde(rename = "type")]
+    pub vm_type: String,
+    #[serde(rename = "publicKeyMultibase")]
+    pub public_key_multibase: Option<String>,
+    #[serde(rename = "publicKeyJwk")]
+    pub public_key_jwk: Option<KeyJwk>,
+}
+
+impl VerificationMethodInput {
+    /// Checks that the key type is supported and carries exactly one key encoding
+    pub fn validate(&self) -> Result<(), AppError> {
+        if !SUPPORTED_KEY_TYPES.contains(&self.vm_type.as_str()) {
+            return Err(AppError::ValidationError(format!(
+                "Unsupported verification method type '{}', expected one of: {}",
+                self.vm_type,
+                SUPPORTED_KEY_TYPES.join(", ")
+            )));
+        }
+
+        match (&self.public_key_multibase, &self.public_key_jwk) {
+            (Some(_), Some(_)) => Err(AppError::ValidationError(format!(
+                "Verification method of type '{}' must not specify both publicKeyMultibase and publicKeyJwk",
+                self.vm_type
+            ))),
+            (None, None) => Err(AppError::ValidationError(format!(
+                "Verification method of type '{}' requires publicKeyMultibase or publicKeyJwk",
+                self.vm_type
+            ))),
+            (Some(_), None) if self.vm_type == "JsonWebKey2020" => Err(
+                AppError::ValidationError("JsonWebKey2020 requires publicKeyJwk".to_string()),
+            ),
+            (None, Some(_)) if self.vm_type == "Ed25519VerificationKey2020" => {
+                Err(AppError::ValidationError(
+                    "Ed25519VerificationKey2020 requires publicKeyMultibase".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// DID creation request
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct DIDCreationRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
     pub controller: String,
+    // Used for the default Ed25519 key when `verification_methods` is empty
+    #[serde(default)]
     pub public_key: String,
+    // Optional list of keys of any supported type
+    #[serde(default)]
+    pub verification_methods: Vec<VerificationMethodInput>,
+    // Keys allowed to sign assertions such as credentials, as `#keys-N` fragments; by
+    // default every key from `verification_methods`
+    #[serde(default)]
+    pub assertion_methods: Option<Vec<String>>,
+    #[validate(nested)]
     pub service_endpoints: Vec<Service>,
+    #[validate(nested)]
     pub metadata: BiometadataExtension,
 }
 
+/// Request to clone a DID into a derived dataset. Keys are the cloning user's own; the
+/// metadata is copied from the source.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct DIDCloneRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
+    pub controller: String,
+    #[serde(default)]
+    pub public_key: String,
+    #[serde(default)]
+    pub verification_methods: Vec<VerificationMethodInput>,
+    #[serde(default)]
+    pub assertion_methods: Option<Vec<String>>,
+    #[serde(default)]
+    #[validate(nested)]
+    pub service_endpoints: Vec<Service>,
+}
+
+/// `relation_type` of the related identifier pointing a cloned DID at its source
+pub const DERIVED_FROM_RELATION: &str = "IsDerivedFrom";
+
+/// Request to hand a DID, with the research papers processed for it, to another user
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct DIDTransferRequest {
+    pub to_user_id: i64,
+    // Replaces the document's controller, storing a new version; kept when omitted
+    #[serde(default)]
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
+    pub controller: Option<String>,
+}
+
+/// Outcome of a DID ownership transfer
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DIDTransfer {
+    pub did: String,
+    pub from_user_id: i64,
+    pub to_user_id: i64,
+    // Research papers of the DID that moved with it
+    pub papers_transferred: u64,
+    // Current document, a new version when the controller changed
+    pub cid: String,
+}
+
+/// A DID as listed by the public registry of every `did:bio` identifier. Title and data
+/// type come from the current document's metadata and are absent without it.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DidRegistryEntry {
+    pub did: String,
+    pub title: Option<String>,
+    pub data_type: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Position after the last DID of a registry page: DIDs are ordered oldest first, with
+/// ties on the creation time broken by DID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DidRegistryCursor {
+    pub created_at: DateTime<Utc>,
+    pub did: String,
+}
+
+impl DidRegistryCursor {
+    pub fn after(entry: &DidRegistryEntry) -> Self {
+        Self {
+            created_at: entry.created_at,
+            did: entry.did.clone(),
+        }
+    }
+
+    /// Opaque form handed to clients as `next_cursor`
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}", to_db(self.created_at), self.did))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (created_at, did) = decoded.split_once('|')?;
+        if did.is_empty() {
+            return None;
+        }
+        Some(Self {
+            created_at: from_db(created_at)?,
+            did: did.to_string(),
+        })
+    }
+}
+
 /// DID update request
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct DIDUpdateRequest {
     pub controller: Option<String>,
     pub add_verification_method: Option<Vec<VerificationMethod>>,
     pub remove_verification_method: Option<Vec<String>>,
+    // Replaces `assertionMethod` with these verification method IDs or `#keys-N` fragments;
+    // an empty list removes it
+    #[serde(default)]
+    pub set_assertion_method: Option<Vec<String>>,
     pub add_service: Option<Vec<Service>>,
     pub remove_service: Option<Vec<String>>,
+    // Replaces the whole metadata extension
     pub update_metadata: Option<BiometadataExtension>,
+    // RFC 7386 JSON Merge Patch applied to the current metadata; exclusive with `update_metadata`
+    pub patch_metadata: Option<serde_json::Value>,
+    // CID the change was based on, e.g. the `versionId` from resolution; the update is
+    // rejected if the DID has moved on since
+    #[serde(default)]
+    pub expected_cid: Option<String>,
+}
+
+impl DIDUpdateRequest {
+    /// Checks that at most one way of changing the metadata is used, and the replacement
+    /// metadata and added services
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.update_metadata.is_some() && self.patch_metadata.is_some() {
+            return Err(AppError::ValidationError(
+                "update_metadata and patch_metadata are mutually exclusive".to_string(),
+            ));
+        }
+        if let Some(metadata) = &self.update_metadata {
+            Validate::validate(metadata)?;
+        }
+        for service in self.add_service.iter().flatten() {
+            Validate::validate(service)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the DID is still at `expected_cid`, if the client gave one, so a change
+    /// based on an older version doesn't silently overwrite a newer one
+    pub fn check_expected_cid(&self, current_cid: &str) -> Result<(), AppError> {
+        match &self.expected_cid {
+            Some(expected) if expected != current_cid => Err(stale_update(current_cid)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Conflict returned when a DID changed between reading and updating it
+pub fn stale_update(current_cid: &str) -> AppError {
+    AppError::Conflict(format!(
+        "DID was updated concurrently and is now at CID {}; re-read it and retry",
+        current_cid
+    ))
+}
+
+/// Outcome of the conditional `UPDATE … WHERE cid = <CID read>` storing an update. It
+/// changes no row when another update was stored after the document was read, and the
+/// update is then rejected with the CID the DID is at now.
+pub fn check_swapped(affected_rows: u64, current_cid: Option<&str>) -> Result<(), AppError> {
+    if affected_rows == 0 {
+        return Err(stale_update(current_cid.unwrap_or("unknown")));
+    }
+    Ok(())
+}
+
+/// Service types `POST /api/did/{did}/attach` can create
+pub const ATTACHMENT_SERVICE_TYPES: &[&str] = &["IPFSStorage", "LinkedDomains"];
+
+/// Request to attach content already on IPFS to a DID as a service
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct AttachFileRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 100))]
+    pub cid: String,
+    // Stored as the service description
+    #[validate(custom(function = "validate_not_blank"), length(max = 1000))]
+    pub label: String,
+    // "IPFSStorage" (default) or "LinkedDomains"
+    #[serde(default)]
+    pub service_type: Option<String>,
+}
+
+impl AttachFileRequest {
+    /// The service to add to `did` for this attachment, pointing at `endpoint`
+    pub fn to_service(&self, did: &str, endpoint: String) -> Result<Service, AppError> {
+        if !self.cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(AppError::ValidationError(format!(
+                "Invalid CID: {}",
+                self.cid
+            )));
+        }
+        let service_type = self.service_type.as_deref().unwrap_or("IPFSStorage");
+        if !ATTACHMENT_SERVICE_TYPES.contains(&service_type) {
+            return Err(AppError::ValidationError(format!(
+                "Unsupported attachment service type '{}', expected one of: {}",
+                service_type,
+                ATTACHMENT_SERVICE_TYPES.join(", ")
+            )));
+        }
+
+        Ok(Service {
+            id: format!("{}#attachment-{}", did, self.cid),
+            service_type: service_type.to_string(),
+            service_endpoint: endpoint,
+            description: Some(self.label.trim().to_string()),
+        })
+    }
+}
+
+/// Apply `patch` to `metadata` as an RFC 7386 JSON Merge Patch.
+///
+/// Object members in the patch are merged recursively, `null` removes a member and any
+/// other value replaces it. `last_modified` is bumped unless the patch sets it. The result
+/// must still be a valid `BiometadataExtension`.
+pub fn patch_metadata(
+    metadata: Option<&BiometadataExtension>,
+    patch: serde_json::Value,
+) -> Result<BiometadataExtension, AppError> {
+    if !patch.is_object() {
+        return Err(AppError::ValidationError(
+            "patch_metadata must be a JSON object".to_string(),
+        ));
+    }
+
+    let mut target = match metadata {
+        Some(metadata) => {
+            serde_json::to_value(metadata).map_err(|_| AppError::SerializationError)?
+        }
+        None => serde_json::Value::Object(serde_json::Map::new()),
+    };
+    let sets_last_modified = patch.get("last_modified").is_some();
+    apply_merge_patch(&mut target, patch);
+
+    let mut patched: BiometadataExtension = serde_json::from_value(target)
+        .map_err(|e| AppError::ValidationError(format!("Patched metadata is invalid: {}", e)))?;
+    if !sets_last_modified {
+        patched.last_modified = Utc::now();
+    }
+    Ok(patched)
+}
+
+fn apply_merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                apply_merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
 }
 
 /// Generate a new DID with the bio-did-seq method
 pub fn generate_did() -> String {
     let uuid = Uuid::new_v4();
-    format!("did:bio:{}", uuid.to_string())
+    format!("did:bio:{}", uuid)
 }
 
 /// Create a default DID document structure
+///
+/// When `verification_methods` is empty a single `Ed25519VerificationKey2020` is
+/// built from `public_key`, otherwise every supplied method is emitted as
+/// `#keys-1..n` and referenced from `authentication` and `assertionMethod`. The
+/// `#storage` service points at `storage_endpoint`.
 pub fn create_default_did_document(
     did: &str,
     controller: &str,
     public_key: &str,
+    verification_methods: &[VerificationMethodInput],
     metadata: BiometadataExtension,
-) -> DIDDocument {
+    storage_endpoint: &str,
+) -> Result<DIDDocument, AppError> {
     let now = Utc::now();
-    let verification_method_id = format!("{}#keys-1", did);
-
-    DIDDocument {
-        context: vec![
-            "https://www.w3.org/ns/did/v1".to_string(),
-            "https://w3id.org/security/suites/ed25519-2020/v1".to_string(),
-            "https://w3id.org/biodata/v1".to_string(),
-        ],
-        id: did.to_string(),
-        also_known_as: None,
-        controller: vec![controller.to_string()],
-        verification_method: vec![VerificationMethod {
-            id: verification_method_id.clone(),
+
+    let mut context = vec![
+        "https://www.w3.org/ns/did/v1".to_string(),
+        "https://w3id.org/security/suites/ed25519-2020/v1".to_string(),
+        "https://w3id.org/biodata/v1".to_string(),
+    ];
+
+    let (verification_method, assertion_method) = if verification_methods.is_empty() {
+        if public_key.is_empty() {
+            return Err(AppError::ValidationError(
+                "Either public_key or verification_methods must be provided".to_string(),
+            ));
+        }
+
+        let method = VerificationMethod {
+            id: format!("{}#keys-1", did),
             controller: did.to_string(),
             vm_type: "Ed25519VerificationKey2020".to_string(),
             public_key_multibase: Some(public_key.to_string()),
             public_key_jwk: None,
-        }],
-        authentication: vec![verification_method_id],
-        assertion_method: None,
+        };
+        (vec![method], None)
+    } else {
+        let mut methods = Vec::with_capacity(verification_methods.len());
+        for (index, input) in verification_methods.iter().enumerate() {
+            input.validate()?;
+            methods.push(VerificationMethod {
+                id: format!("{}#keys-{}", did, index + 1),
+                controller: did.to_string(),
+                vm_type: input.vm_type.clone(),
+                public_key_multibase: input.public_key_multibase.clone(),
+                public_key_jwk: input.public_key_jwk.clone(),
+            });
+        }
+
+        if methods.iter().any(|m| m.public_key_jwk.is_some()) {
+            context.push("https://w3id.org/security/suites/jws-2020/v1".to_string());
+        }
+
+        let ids = methods.iter().map(|m| m.id.clone()).collect::<Vec<_>>();
+        (methods, Some(ids))
+    };
+
+    let authentication = verification_method
+        .iter()
+        .map(|m| m.id.clone())
+        .collect::<Vec<_>>();
+
+    Ok(DIDDocument {
+        context,
+        id: did.to_string(),
+        also_known_as: None,
+        controller: vec![controller.to_string()],
+        verification_method,
+        authentication,
+        assertion_method,
         service: vec![Service {
             id: format!("{}#storage", did),
             service_type: "IPFSStorage".to_string(),
-            service_endpoint: "https://ipfs.bio-did-seq.example/api".to_string(),
+            service_endpoint: storage_endpoint.to_string(),
             description: Some("IPFS storage for biological research data".to_string()),
         }],
         created: now,
         updated: now,
         metadata: Some(metadata),
+        schema_version: DID_DOCUMENT_SCHEMA_VERSION,
+    })
+}
+
+/// Parse a stored DID document, upgrading it from the schema version it was written with
+/// to `DID_DOCUMENT_SCHEMA_VERSION` first, so documents already on IPFS keep loading as the
+/// struct changes. Each migration step takes a document from one version to the next.
+pub fn migrate_document(mut value: serde_json::Value) -> Result<DIDDocument, AppError> {
+    let document = value
+        .as_object_mut()
+        .ok_or(AppError::DeserializationError)?;
+    let version = document
+        .get("schemaVersion")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0);
+
+    if version < 1 {
+        migrate_v0_to_v1(document);
+    }
+    if version < u64::from(DID_DOCUMENT_SCHEMA_VERSION) {
+        document.insert(
+            "schemaVersion".to_string(),
+            DID_DOCUMENT_SCHEMA_VERSION.into(),
+        );
+    }
+
+    serde_json::from_value(value).map_err(|_| AppError::DeserializationError)
+}
+
+/// v0 documents may have a single `controller` string, or none, as DID Core allows
+fn migrate_v0_to_v1(document: &mut serde_json::Map<String, serde_json::Value>) {
+    let controller = match document.remove("controller") {
+        Some(serde_json::Value::Array(controllers)) => controllers,
+        Some(serde_json::Value::String(controller)) => vec![controller.into()],
+        _ => Vec::new(),
+    };
+    document.insert("controller".to_string(), controller.into());
+}
+
+/// Deterministic byte representation of a DID document for hashing and signing.
+///
+/// This is JSON Canonicalization (RFC 8785) style output rather than full URDNA2015:
+/// object keys are sorted by UTF-16 code units, insignificant whitespace is removed and
+/// `null` members are dropped, since JSON-LD treats them the same as absent ones. Two
+/// documents that differ only in key order or optional-field encoding canonicalize to
+/// the same bytes.
+pub fn canonicalize(doc: &DIDDocument) -> Result<Vec<u8>, AppError> {
+    let value = serde_json::to_value(doc).map_err(|_| AppError::SerializationError)?;
+    canonicalize_value(&value)
+}
+
+/// `canonicalize` for any JSON value
+pub fn canonicalize_value(value: &serde_json::Value) -> Result<Vec<u8>, AppError> {
+    let mut out = Vec::new();
+    write_canonical(value, &mut out)?;
+    Ok(out)
+}
+
+fn write_canonical(value: &serde_json::Value, out: &mut Vec<u8>) -> Result<(), AppError> {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map.iter().filter(|(_, v)| !v.is_null()).collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+            out.push(b'{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key).map_err(|_| AppError::SerializationError)?;
+                out.push(b':');
+                write_canonical(item, out)?;
+            }
+            out.push(b'}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out)?;
+            }
+            out.push(b']');
+        }
+        scalar => {
+            serde_json::to_writer(&mut *out, scalar).map_err(|_| AppError::SerializationError)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_document_wraps_v0_controller() {
+        let stored = serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:bio:abc",
+            "controller": "did:key:z6MkExample",
+            "verificationMethod": [],
+            "authentication": [],
+            "service": [],
+            "created": "2025-01-01T00:00:00Z",
+            "updated": "2025-01-01T00:00:00Z",
+        });
+
+        let document = migrate_document(stored).unwrap();
+        assert_eq!(document.controller, vec!["did:key:z6MkExample".to_string()]);
+        assert_eq!(document.schema_version, DID_DOCUMENT_SCHEMA_VERSION);
+
+        let current = serde_json::to_value(&document).unwrap();
+        let reparsed = migrate_document(current).unwrap();
+        assert_eq!(reparsed.controller, document.controller);
+    }
+
+    #[test]
+    fn test_custom_fields_depth_is_limited() {
+        let metadata = |custom: serde_json::Value| {
+            serde_json::json!({
+                "title": "Sequencing run",
+                "description": null,
+                "researchers": [],
+                "keywords": [],
+                "data_type": "genomic",
+                "doi": null,
+                "handle": null,
+                "dataverse_link": null,
+                "related_identifiers": null,
+                "dataset_size": null,
+                "funding_info": null,
+                "creation_date": "2025-01-01T00:00:00Z",
+                "last_modified": "2025-01-01T00:00:00Z",
+                "custom_fields": custom,
+            })
+        };
+        let nested = |depth: usize| {
+            (0..depth).fold(serde_json::json!(1), |inner, _| serde_json::json!([inner]))
+        };
+
+        let limits = DocumentLimits {
+            max_researchers: 1,
+            max_keywords: 1,
+            max_custom_fields: 1,
+            max_document_bytes: 1,
+        };
+
+        let within = metadata(serde_json::json!({ "run": nested(MAX_CUSTOM_FIELDS_DEPTH) }));
+        let within = serde_json::from_value::<BiometadataExtension>(within).unwrap();
+        assert!(limits.check_metadata(&within).is_ok());
+
+        // Stored documents nested deeper still load; only new metadata is rejected
+        let too_deep = metadata(serde_json::json!({ "run": nested(MAX_CUSTOM_FIELDS_DEPTH + 1) }));
+        let too_deep = serde_json::from_value::<BiometadataExtension>(too_deep).unwrap();
+        let err = limits.check_metadata(&too_deep).unwrap_err();
+        assert!(err.to_string().contains("nested more than"));
+    }
+
+    #[test]
+    fn test_normalize_order_makes_serialization_stable() {
+        let method = |id: &str| VerificationMethod {
+            id: format!("did:bio:abc#{}", id),
+            controller: "did:bio:abc".to_string(),
+            vm_type: "Ed25519VerificationKey2020".to_string(),
+            public_key_multibase: Some("z6MkExample".to_string()),
+            public_key_jwk: None,
+        };
+        let service = |id: &str| Service {
+            id: format!("did:bio:abc#{}", id),
+            service_type: "LinkedDomains".to_string(),
+            service_endpoint: "https://example.org".to_string(),
+            description: None,
+        };
+        let document = |keys: [&str; 2], services: [&str; 2]| DIDDocument {
+            context: vec!["https://www.w3.org/ns/did/v1".to_string()],
+            id: "did:bio:abc".to_string(),
+            also_known_as: None,
+            controller: vec!["did:bio:abc".to_string()],
+            verification_method: keys.iter().map(|k| method(k)).collect(),
+            authentication: keys.iter().map(|k| format!("did:bio:abc#{}", k)).collect(),
+            assertion_method: Some(keys.iter().map(|k| format!("did:bio:abc#{}", k)).collect()),
+            service: services.iter().map(|s| service(s)).collect(),
+            created: DateTime::<Utc>::UNIX_EPOCH,
+            updated: DateTime::<Utc>::UNIX_EPOCH,
+            metadata: None,
+            schema_version: DID_DOCUMENT_SCHEMA_VERSION,
+        };
+
+        let mut first = document(["keys-1", "keys-2"], ["ipfs", "dataverse"]);
+        let mut second = document(["keys-2", "keys-1"], ["dataverse", "ipfs"]);
+        assert_ne!(
+            serde_json::to_string(&first).unwrap(),
+            serde_json::to_string(&second).unwrap()
+        );
+
+        first.normalize_order();
+        second.normalize_order();
+        assert_eq!(
+            serde_json::to_string(&first).unwrap(),
+            serde_json::to_string(&second).unwrap()
+        );
+        assert_eq!(second.service[0].id, "did:bio:abc#dataverse");
+    }
+
+    #[test]
+    fn test_relations_skip_duplicates_and_oversized_identifiers() {
+        let related = |identifier: String, relation_type: &str| {
+            serde_json::json!({
+                "identifier": identifier,
+                "identifier_type": "DID",
+                "relation_type": relation_type,
+            })
+        };
+        let document = serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:bio:abc",
+            "controller": ["did:bio:abc"],
+            "verificationMethod": [],
+            "authentication": [],
+            "service": [],
+            "created": "2025-01-01T00:00:00Z",
+            "updated": "2025-01-01T00:00:00Z",
+            "metadata": {
+                "title": "Sequencing run",
+                "description": null,
+                "researchers": [],
+                "keywords": [],
+                "data_type": "genomic",
+                "doi": null,
+                "handle": null,
+                "dataverse_link": null,
+                "related_identifiers": [
+                    related("did:bio:source".to_string(), DERIVED_FROM_RELATION),
+                    related("did:bio:source".to_string(), "References"),
+                    related("did:bio:source".to_string(), DERIVED_FROM_RELATION),
+                    related(format!("did:bio:{}", "x".repeat(300)), "References"),
+                ],
+                "dataset_size": null,
+                "funding_info": null,
+                "creation_date": "2025-01-01T00:00:00Z",
+                "last_modified": "2025-01-01T00:00:00Z",
+            },
+        });
+
+        let document = migrate_document(document).unwrap();
+        let relations: Vec<(&str, &str)> = document
+            .relations()
+            .iter()
+            .map(|r| (r.identifier.as_str(), r.relation_type.as_str()))
+            .collect();
+        assert_eq!(
+            relations,
+            vec![
+                ("did:bio:source", DERIVED_FROM_RELATION),
+                ("did:bio:source", "References"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_embargoed_keeps_citation_metadata() {
+        let attachment = |cid: &str| {
+            serde_json::json!({
+                "id": format!("did:bio:abc#attachment-{}", cid),
+                "type": "IPFSFile",
+                "serviceEndpoint": format!("ipfs://{}", cid),
+            })
+        };
+        let document = serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:bio:abc",
+            "controller": ["did:bio:abc"],
+            "verificationMethod": [],
+            "authentication": [],
+            "service": [
+                attachment("QmB"),
+                attachment("QmA"),
+                attachment("QmB"),
+                {
+                    "id": "did:bio:abc#ipfs",
+                    "type": "IPFSStorage",
+                    "serviceEndpoint": "ipfs://QmDoc",
+                },
+            ],
+            "created": "2025-01-01T00:00:00Z",
+            "updated": "2025-01-01T00:00:00Z",
+            "metadata": {
+                "title": "Sequencing run",
+                "description": "Unpublished results",
+                "researchers": [{
+                    "name": "A. Researcher",
+                    "orcid": null,
+                    "role": "PI",
+                    "email": "a@example.org",
+                    "affiliation": null,
+                }],
+                "keywords": ["genomics"],
+                "data_type": "genomic",
+                "doi": null,
+                "handle": null,
+                "dataverse_link": null,
+                "related_identifiers": null,
+                "dataset_size": 1024,
+                "funding_info": null,
+                "creation_date": "2025-01-01T00:00:00Z",
+                "last_modified": "2025-01-01T00:00:00Z",
+                "embargo_until": "2030-01-01T00:00:00Z",
+            },
+        });
+
+        let mut document = migrate_document(document).unwrap();
+        assert!(document.is_embargoed("2029-12-31T23:59:59Z".parse().unwrap()));
+        assert!(!document.is_embargoed("2030-01-01T00:00:00Z".parse().unwrap()));
+        assert_eq!(document.attached_cids(), vec!["QmA", "QmB"]);
+
+        document.strip_embargoed();
+        assert!(document.attached_cids().is_empty());
+        assert_eq!(document.service.len(), 1);
+        let metadata = document.metadata.as_ref().unwrap();
+        assert_eq!(metadata.title, "Sequencing run");
+        assert_eq!(metadata.keywords, vec!["genomics"]);
+        assert!(metadata.description.is_none());
+        assert!(metadata.dataset_size.is_none());
+        assert!(metadata.researchers[0].email.is_none());
+    }
+
+    #[test]
+    fn test_link_orcid_fills_only_the_callers_own_entry() {
+        let researcher = |name: &str, email: Option<&str>| Researcher {
+            name: name.to_string(),
+            orcid: None,
+            role: "Author".to_string(),
+            affiliation: None,
+            email: email.map(str::to_string),
+        };
+        let mut metadata: BiometadataExtension = serde_json::from_value(serde_json::json!({
+            "title": "Yeast proteome",
+            "researchers": [],
+            "keywords": [],
+            "data_type": "proteomics",
+            "creation_date": "2025-01-01T00:00:00Z",
+            "last_modified": "2025-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        metadata.researchers = vec![
+            researcher("Josiah Carberry", None),
+            researcher("J. Carberry", Some("J.Carberry@Example.org")),
+        ];
+        let identity = OrcidIdentity {
+            orcid: "0000-0002-1825-0097".to_string(),
+            email: "j.carberry@example.org".to_string(),
+            name: Some("josiah  carberry".to_string()),
+        };
+
+        // The email match wins over the name match
+        let mut by_email = metadata.clone();
+        by_email.link_orcid(&identity);
+        assert!(by_email.researchers[0].orcid.is_none());
+        assert_eq!(
+            by_email.researchers[1].orcid.as_deref(),
+            Some("0000-0002-1825-0097")
+        );
+
+        // Papers list authors by name only
+        let mut by_name = metadata.clone();
+        by_name.researchers[1].email = None;
+        by_name.link_orcid(&identity);
+        assert_eq!(
+            by_name.researchers[0].orcid.as_deref(),
+            Some("0000-0002-1825-0097")
+        );
+        assert!(by_name.researchers[1].orcid.is_none());
+
+        // An entry that already carries the iD is left as the caller's
+        let mut linked = metadata.clone();
+        linked.researchers[0].orcid = Some("0000-0002-1825-0097".to_string());
+        linked.link_orcid(&identity);
+        assert!(linked.researchers[1].orcid.is_none());
+
+        let mut stranger = metadata;
+        stranger.link_orcid(&OrcidIdentity {
+            orcid: "0000-0001-5109-3700".to_string(),
+            email: "0000-0001-5109-3700@orcid.invalid".to_string(),
+            name: None,
+        });
+        assert!(stranger.researchers.iter().all(|r| r.orcid.is_none()));
+    }
+
+    #[test]
+    fn test_document_limits_allow_up_to_their_caps() {
+        let limits = DocumentLimits {
+            max_researchers: 2,
+            max_keywords: 3,
+            max_custom_fields: 1,
+            max_document_bytes: 10,
+        };
+        let researcher = Researcher {
+            name: "A. Researcher".to_string(),
+            orcid: None,
+            role: "PI".to_string(),
+            affiliation: None,
+            email: None,
+        };
+        let mut metadata = BiometadataExtension {
+            title: "Sequencing run".to_string(),
+            description: None,
+            researchers: vec![researcher.clone(); 2],
+            keywords: vec!["genomics".to_string(); 3],
+            data_type: "genomic".to_string(),
+            license: String::new(),
+            doi: None,
+            handle: None,
+            dataverse_link: None,
+            related_identifiers: None,
+            dataset_size: None,
+            funding_info: None,
+            creation_date: DateTime::<Utc>::UNIX_EPOCH,
+            last_modified: DateTime::<Utc>::UNIX_EPOCH,
+            custom_fields: Some(HashMap::from([("run".to_string(), serde_json::json!(1))])),
+            embargo_until: None,
+        };
+        assert!(limits.check_metadata(&metadata).is_ok());
+
+        metadata.researchers.push(researcher);
+        let err = limits.check_metadata(&metadata).unwrap_err();
+        assert!(err.to_string().contains("metadata.researchers"));
+        metadata.researchers.pop();
+
+        metadata.keywords.push("proteomics".to_string());
+        let err = limits.check_metadata(&metadata).unwrap_err();
+        assert!(err.to_string().contains("metadata.keywords"));
+        metadata.keywords.pop();
+
+        metadata
+            .custom_fields
+            .as_mut()
+            .unwrap()
+            .insert("lane".to_string(), serde_json::json!(2));
+        let err = limits.check_metadata(&metadata).unwrap_err();
+        assert!(err.to_string().contains("metadata.custom_fields"));
+
+        assert!(limits.check_document_size(&"x".repeat(10)).is_ok());
+        let err = limits.check_document_size(&"x".repeat(11)).unwrap_err();
+        assert!(err.to_string().contains("11 bytes"));
+    }
+
+    #[test]
+    fn test_service_endpoints_are_limited_to_allowed_schemes() {
+        let service = |endpoint: &str| Service {
+            id: "did:bio:abc#data".to_string(),
+            service_type: "LinkedDomains".to_string(),
+            service_endpoint: endpoint.to_string(),
+            description: None,
+        };
+        let defaults = ServiceEndpointPolicy::new(
+            &ServiceEndpointPolicy::DEFAULT_SCHEMES
+                .split(',')
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        assert!(defaults
+            .check(&[service(
+                "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+            )])
+            .is_ok());
+        assert!(defaults.check(&[service("HTTPS://data.example")]).is_ok());
+        assert!(defaults.check(&[service("did:bio:def")]).is_ok());
+        for rejected in [
+            "http://data.example",
+            "javascript:alert(1)",
+            "file:///etc/passwd",
+        ] {
+            let err = defaults.check(&[service(rejected)]).unwrap_err();
+            assert!(matches!(err, AppError::ValidationError(ref m) if m.contains(rejected)));
+        }
+
+        assert!(ServiceEndpointPolicy::new(&[]).is_none());
+        assert!(ServiceEndpointPolicy::new(&["ht tp".to_string()]).is_none());
+    }
+
+    #[test]
+    fn test_update_based_on_a_superseded_cid_is_rejected() {
+        // Two editors read the DID at the same version; the first one's update lands
+        let read_cid = "bafyread";
+        let first = DIDUpdateRequest {
+            remove_service: Some(vec!["did:bio:abc#old".to_string()]),
+            expected_cid: Some(read_cid.to_string()),
+            ..Default::default()
+        };
+        let second = DIDUpdateRequest {
+            controller: Some("did:key:z6MkSecond".to_string()),
+            expected_cid: Some(read_cid.to_string()),
+            ..Default::default()
+        };
+        assert!(first.check_expected_cid(read_cid).is_ok());
+        let after_first = "bafyfirst";
+
+        let err = second.check_expected_cid(after_first).unwrap_err();
+        assert!(matches!(err, AppError::Conflict(ref message) if message.contains(after_first)));
+
+        // Clients that don't send a version aren't held to one up front
+        let unversioned = DIDUpdateRequest::default();
+        assert!(unversioned.check_expected_cid(after_first).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_only_one_of_two_concurrent_updates_is_stored() {
+        use std::sync::{Arc, Mutex};
+        use tokio::sync::Barrier;
+
+        for round in 0..100 {
+            // The `cid` column of the DID's row
+            let row = Arc::new(Mutex::new("bafyread".to_string()));
+            let barrier = Arc::new(Barrier::new(2));
+
+            let writers: Vec<_> = ["bafyfirst", "bafysecond"]
+                .into_iter()
+                .map(|new_cid| {
+                    let row = row.clone();
+                    let barrier = barrier.clone();
+                    tokio::spawn(async move {
+                        let read_cid = row.lock().unwrap().clone();
+                        // Neither writer stores until both have read
+                        barrier.wait().await;
+                        // UPDATE did_documents SET cid = :cid WHERE did = :did AND cid = :read_cid
+                        let (affected_rows, current) = {
+                            let mut cid = row.lock().unwrap();
+                            if *cid == read_cid {
+                                *cid = new_cid.to_string();
+                                (1, None)
+                            } else {
+                                (0, Some(cid.clone()))
+                            }
+                        };
+                        check_swapped(affected_rows, current.as_deref()).map(|()| new_cid)
+                    })
+                })
+                .collect();
+
+            let mut stored = Vec::new();
+            let mut conflicts = Vec::new();
+            for writer in writers {
+                match writer.await.unwrap() {
+                    Ok(new_cid) => stored.push(new_cid),
+                    Err(AppError::Conflict(message)) => conflicts.push(message),
+                    Err(e) => panic!("unexpected error: {}", e),
+                }
+            }
+            assert_eq!(stored.len(), 1, "round {}: {:?}", round, stored);
+            assert_eq!(conflicts.len(), 1, "round {}: {:?}", round, conflicts);
+            // The loser is told where the DID is now, and the winner's change is kept
+            assert!(conflicts[0].contains(stored[0]));
+            assert_eq!(*row.lock().unwrap(), stored[0]);
+        }
+    }
+
+    #[test]
+    fn test_lifted_embargo_counts_as_modification() {
+        let time = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let modification = DidModification {
+            updated_at: time("2030-01-01T00:00:00Z"),
+            embargo_until: Some(time("2030-01-01T00:10:00Z")),
+        };
+
+        let during = time("2030-01-01T00:09:00Z");
+        assert_eq!(modification.last_modified(during), modification.updated_at);
+        assert_eq!(modification.max_age(during, 300), 60);
+
+        let after = time("2030-01-01T00:11:00Z");
+        assert_eq!(
+            modification.last_modified(after),
+            time("2030-01-01T00:10:00Z")
+        );
+        assert_eq!(modification.max_age(after, 300), 300);
+    }
+
+    #[test]
+    fn test_transfer_request_controller_is_optional_but_not_blank() {
+        let request = |body: serde_json::Value| {
+            serde_json::from_value::<DIDTransferRequest>(body)
+                .unwrap()
+                .validate()
+        };
+        assert!(request(serde_json::json!({ "to_user_id": 2 })).is_ok());
+        assert!(
+            request(serde_json::json!({ "to_user_id": 2, "controller": "did:key:z6Mk" })).is_ok()
+        );
+        assert!(request(serde_json::json!({ "to_user_id": 2, "controller": "  " })).is_err());
+    }
+
+    #[test]
+    fn test_registry_cursor_round_trip() {
+        let cursor = DidRegistryCursor {
+            created_at: "2030-01-01T12:30:00Z".parse().unwrap(),
+            did: "did:bio:6f1c2a4e-7d8b-4c3a-9e2f-1a2b3c4d5e6f".to_string(),
+        };
+        assert_eq!(DidRegistryCursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(DidRegistryCursor::decode("not a cursor"), None);
+        assert_eq!(
+            DidRegistryCursor::decode(&URL_SAFE_NO_PAD.encode("2030-01-01 12:30:00|")),
+            None
+        );
     }
 }
@@ -0,0 +1,163 @@
+use crate::errors::AppError;
+use crate::models::did::{BiometadataExtension, Service, VerificationMethodInput};
+use crate::models::license::License;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// `BiometadataExtension` fields a template may pre-fill
+pub const TEMPLATE_METADATA_FIELDS: &[&str] = &[
+    "title",
+    "description",
+    "researchers",
+    "keywords",
+    "data_type",
+    "license",
+    "doi",
+    "handle",
+    "dataverse_link",
+    "related_identifiers",
+    "dataset_size",
+    "funding_info",
+    "custom_fields",
+];
+
+/// Stored partial metadata reused when creating many similar DIDs
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DIDTemplate {
+    pub template_id: String,
+    pub user_id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    // Partial `BiometadataExtension`, e.g. license, funding_info and researchers
+    pub metadata: Map<String, Value>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request to create or replace a DID template
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DIDTemplateRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub metadata: Map<String, Value>,
+}
+
+impl DIDTemplateRequest {
+    /// Checks the name and that the metadata only contains known extension fields
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.name.trim().is_empty() || self.name.len() > 255 {
+            return Err(AppError::ValidationError(
+                "Template name must be between 1 and 255 characters".to_string(),
+            ));
+        }
+
+        validate_metadata_fields(&self.metadata)?;
+
+        if let Some(license) = self.metadata.get("license") {
+            match license.as_str() {
+                Some(license) => {
+                    License::parse(license)?;
+                }
+                None => {
+                    return Err(AppError::ValidationError(
+                        "license must be a string".to_string(),
+                    ))
+                }
+            }
+        }
+
+        if let Some(custom_fields) = self.metadata.get("custom_fields") {
+            if !custom_fields.is_object() {
+                return Err(AppError::ValidationError(
+                    "custom_fields must be an object".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// DID creation request that takes its metadata from a template
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DIDFromTemplateRequest {
+    pub controller: String,
+    #[serde(default)]
+    pub public_key: String,
+    #[serde(default)]
+    pub verification_methods: Vec<VerificationMethodInput>,
+    #[serde(default)]
+    pub assertion_methods: Option<Vec<String>>,
+    #[serde(default)]
+    pub service_endpoints: Vec<Service>,
+    // Per-DID metadata merged over the template; `custom_fields` is deep-merged
+    #[serde(default)]
+    pub overrides: Map<String, Value>,
+}
+
+fn validate_metadata_fields(metadata: &Map<String, Value>) -> Result<(), AppError> {
+    match metadata
+        .keys()
+        .find(|key| !TEMPLATE_METADATA_FIELDS.contains(&key.as_str()))
+    {
+        Some(key) => Err(AppError::ValidationError(format!(
+            "Unknown metadata field '{}', expected one of: {}",
+            key,
+            TEMPLATE_METADATA_FIELDS.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Merge per-request overrides over a template's metadata.
+///
+/// Top-level fields in `overrides` replace the template's, except `custom_fields`, whose
+/// objects are merged recursively so a DID can add or change a single key without
+/// restating the rest. Creation and modification dates are always set to now.
+pub fn merge_template_metadata(
+    template: &Map<String, Value>,
+    overrides: Map<String, Value>,
+) -> Result<BiometadataExtension, AppError> {
+    validate_metadata_fields(&overrides)?;
+
+    let mut merged = template.clone();
+    for (key, value) in overrides {
+        match (merged.get_mut(&key), value) {
+            (Some(existing), value) if key == "custom_fields" => deep_merge(existing, value),
+            (_, value) => {
+                merged.insert(key, value);
+            }
+        }
+    }
+
+    for list_field in ["researchers", "keywords"] {
+        merged
+            .entry(list_field)
+            .or_insert_with(|| Value::Array(Vec::new()));
+    }
+    let now = serde_json::to_value(Utc::now()).map_err(|_| AppError::SerializationError)?;
+    merged.insert("creation_date".to_string(), now.clone());
+    merged.insert("last_modified".to_string(), now);
+
+    serde_json::from_value(Value::Object(merged)).map_err(|e| {
+        AppError::ValidationError(format!("Template and overrides are incomplete: {}", e))
+    })
+}
+
+fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
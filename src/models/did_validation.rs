@@ -0,0 +1,400 @@
+use crate::errors::{validation_problems, AppError};
+use crate::models::did::{
+    decode_ed25519_multibase, migrate_document, BiometadataExtension, Service,
+    VerificationMethodInput,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, FixedOffset};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use validator::{Validate, ValidateUrl};
+
+/// Context every DID document must list first
+pub const DID_CORE_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+
+/// Outcome of validating a DID document without storing it
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DocumentValidation {
+    pub valid: bool,
+    // One `path: problem` entry per failed check, e.g. `service[0].service_endpoint: must
+    // be a valid URL`
+    pub errors: Vec<String>,
+}
+
+impl DocumentValidation {
+    pub fn new(errors: Vec<String>) -> Self {
+        Self {
+            valid: errors.is_empty(),
+            errors,
+        }
+    }
+}
+
+/// Structural problems of a DID document, as `path: problem` entries; empty when the
+/// document can be stored as is. Checks the required members, that `@context` starts with
+/// the DID Core context, that every verification method carries a key that decodes, that
+/// `authentication` and `assertionMethod` name listed methods, that service endpoints are
+/// URLs and that the timestamps parse. Size limits are left to `DocumentLimits`.
+pub fn validate_document(document: &Value) -> Vec<String> {
+    let Some(document) = document.as_object() else {
+        return vec!["document: must be a JSON object".to_string()];
+    };
+    let mut problems = Vec::new();
+
+    check_context(document.get("@context"), &mut problems);
+    match document.get("id").and_then(Value::as_str) {
+        Some(id) if is_did(id) => {}
+        Some(id) => problems.push(format!("id: '{}' is not a DID", id)),
+        None => problems.push("id: is required".to_string()),
+    }
+    check_controller(document.get("controller"), &mut problems);
+
+    let method_ids = check_verification_methods(document.get("verificationMethod"), &mut problems);
+    check_references(
+        "authentication",
+        document.get("authentication"),
+        true,
+        &method_ids,
+        &mut problems,
+    );
+    check_references(
+        "assertionMethod",
+        document.get("assertionMethod"),
+        false,
+        &method_ids,
+        &mut problems,
+    );
+    check_services(document.get("service"), &mut problems);
+    check_timestamps(document, &mut problems);
+    check_metadata(document.get("metadata"), &mut problems);
+
+    // Anything the checks above let through but the document model can't hold
+    if problems.is_empty() && migrate_document(Value::Object(document.clone())).is_err() {
+        problems.push("document: doesn't match the DID document schema".to_string());
+    }
+    problems
+}
+
+/// `did:<method>:<method-specific-id>`, with a lowercase method name
+fn is_did(value: &str) -> bool {
+    let mut parts = value.splitn(3, ':');
+    parts.next() == Some("did")
+        && parts.next().is_some_and(|method| {
+            !method.is_empty()
+                && method
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        })
+        && parts
+            .next()
+            .is_some_and(|id| !id.is_empty() && !id.contains(['#', '?', '/']))
+}
+
+fn check_context(context: Option<&Value>, problems: &mut Vec<String>) {
+    let entries = match context {
+        Some(Value::Array(entries)) if !entries.is_empty() => entries,
+        Some(Value::Array(_)) | None => {
+            problems.push(format!("@context: must list {}", DID_CORE_CONTEXT));
+            return;
+        }
+        Some(_) => {
+            problems.push("@context: must be an array of URLs".to_string());
+            return;
+        }
+    };
+
+    for (index, entry) in entries.iter().enumerate() {
+        match entry.as_str() {
+            Some(url) if index == 0 && url != DID_CORE_CONTEXT => {
+                problems.push(format!("@context[0]: must be {}", DID_CORE_CONTEXT))
+            }
+            Some(url) if url.validate_url() => {}
+            _ => problems.push(format!("@context[{}]: must be a URL", index)),
+        }
+    }
+}
+
+/// DID Core allows a single controller or a list; both are stored as a list
+fn check_controller(controller: Option<&Value>, problems: &mut Vec<String>) {
+    let controllers: Vec<(String, &Value)> = match controller {
+        None => return,
+        Some(Value::Array(controllers)) => controllers
+            .iter()
+            .enumerate()
+            .map(|(index, controller)| (format!("controller[{}]", index), controller))
+            .collect(),
+        Some(controller) => vec![("controller".to_string(), controller)],
+    };
+    for (path, controller) in controllers {
+        if !controller.as_str().is_some_and(is_did) {
+            problems.push(format!("{}: must be a DID", path));
+        }
+    }
+}
+
+/// Checks each verification method, returning the IDs of those with a valid one
+fn check_verification_methods(
+    methods: Option<&Value>,
+    problems: &mut Vec<String>,
+) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    let methods = match methods {
+        Some(Value::Array(methods)) if !methods.is_empty() => methods,
+        Some(Value::Array(_)) | None => {
+            problems.push("verificationMethod: at least one method is required".to_string());
+            return ids;
+        }
+        Some(_) => {
+            problems.push("verificationMethod: must be an array".to_string());
+            return ids;
+        }
+    };
+
+    for (index, method) in methods.iter().enumerate() {
+        let path = format!("verificationMethod[{}]", index);
+        let Some(members) = method.as_object() else {
+            problems.push(format!("{}: must be an object", path));
+            continue;
+        };
+
+        match members.get("id").and_then(Value::as_str) {
+            Some(id) => match id.split_once('#') {
+                Some((did, fragment)) if is_did(did) && !fragment.is_empty() => {
+                    if !ids.insert(id.to_string()) {
+                        problems.push(format!("{}.id: '{}' is listed twice", path, id));
+                    }
+                }
+                _ => problems.push(format!(
+                    "{}.id: '{}' is not a DID URL with a fragment, e.g. did:bio:123#keys-1",
+                    path, id
+                )),
+            },
+            None => problems.push(format!("{}.id: is required", path)),
+        }
+        if !members
+            .get("controller")
+            .and_then(Value::as_str)
+            .is_some_and(is_did)
+        {
+            problems.push(format!("{}.controller: must be a DID", path));
+        }
+
+        match serde_json::from_value::<VerificationMethodInput>(method.clone()) {
+            Ok(input) => match input.validate() {
+                Ok(()) => check_key(&path, &input, problems),
+                Err(AppError::ValidationError(message)) => {
+                    problems.push(format!("{}: {}", path, message))
+                }
+                Err(e) => problems.push(format!("{}: {}", path, e)),
+            },
+            Err(e) => problems.push(format!("{}: {}", path, e)),
+        }
+    }
+    ids
+}
+
+/// Whether the method's key decodes, so signatures could actually be checked against it
+fn check_key(path: &str, method: &VerificationMethodInput, problems: &mut Vec<String>) {
+    if let Some(multibase) = &method.public_key_multibase {
+        let decodes = if method.vm_type == "Ed25519VerificationKey2020" {
+            decode_ed25519_multibase(multibase).is_some()
+        } else {
+            multibase
+                .strip_prefix('z')
+                .and_then(|key| bs58::decode(key).into_vec().ok())
+                .is_some_and(|key| !key.is_empty())
+        };
+        if !decodes {
+            problems.push(format!(
+                "{}.publicKeyMultibase: is not a base58btc-encoded {} key",
+                path, method.vm_type
+            ));
+        }
+    }
+
+    if let Some(jwk) = &method.public_key_jwk {
+        if jwk.kty.trim().is_empty() || jwk.crv.trim().is_empty() {
+            problems.push(format!("{}.publicKeyJwk: kty and crv are required", path));
+        }
+        for (member, value) in [("x", Some(&jwk.x)), ("y", jwk.y.as_ref())] {
+            let Some(value) = value else { continue };
+            if URL_SAFE_NO_PAD
+                .decode(value)
+                .map_or(true, |bytes| bytes.is_empty())
+            {
+                problems.push(format!(
+                    "{}.publicKeyJwk.{}: is not base64url-encoded",
+                    path, member
+                ));
+            }
+        }
+    }
+}
+
+/// `authentication` and `assertionMethod` reference listed methods by ID
+fn check_references(
+    field: &str,
+    references: Option<&Value>,
+    required: bool,
+    method_ids: &HashSet<String>,
+    problems: &mut Vec<String>,
+) {
+    let references = match references {
+        Some(Value::Array(references)) => references,
+        None if !required => return,
+        None => {
+            problems.push(format!("{}: is required", field));
+            return;
+        }
+        Some(_) => {
+            problems.push(format!(
+                "{}: must be an array of verification method IDs",
+                field
+            ));
+            return;
+        }
+    };
+
+    for (index, reference) in references.iter().enumerate() {
+        match reference.as_str() {
+            Some(id) if method_ids.contains(id) => {}
+            Some(id) => problems.push(format!(
+                "{}[{}]: '{}' is not a verification method of this document",
+                field, index, id
+            )),
+            None => problems.push(format!(
+                "{}[{}]: must be a verification method ID",
+                field, index
+            )),
+        }
+    }
+}
+
+fn check_services(services: Option<&Value>, problems: &mut Vec<String>) {
+    let services = match services {
+        Some(Value::Array(services)) => services,
+        None => {
+            problems.push("service: is required".to_string());
+            return;
+        }
+        Some(_) => {
+            problems.push("service: must be an array".to_string());
+            return;
+        }
+    };
+
+    let mut ids = HashSet::new();
+    for (index, service) in services.iter().enumerate() {
+        let path = format!("service[{}]", index);
+        match serde_json::from_value::<Service>(service.clone()) {
+            Ok(service) => {
+                if let Err(errors) = service.validate() {
+                    problems.extend(validation_problems(&errors, &path));
+                }
+                if !ids.insert(service.id.clone()) {
+                    problems.push(format!("{}.id: '{}' is listed twice", path, service.id));
+                }
+            }
+            Err(e) => problems.push(format!("{}: {}", path, e)),
+        }
+    }
+}
+
+fn check_timestamps(document: &Map<String, Value>, problems: &mut Vec<String>) {
+    let mut parse = |field: &str| -> Option<DateTime<FixedOffset>> {
+        match document.get(field).and_then(Value::as_str) {
+            Some(timestamp) => DateTime::parse_from_rfc3339(timestamp)
+                .map_err(|_| {
+                    problems.push(format!(
+                        "{}: '{}' is not an RFC 3339 timestamp",
+                        field, timestamp
+                    ))
+                })
+                .ok(),
+            None => {
+                problems.push(format!("{}: is required as an RFC 3339 timestamp", field));
+                None
+            }
+        }
+    };
+    let created = parse("created");
+    let updated = parse("updated");
+
+    if let (Some(created), Some(updated)) = (created, updated) {
+        if updated < created {
+            problems.push("updated: is earlier than created".to_string());
+        }
+    }
+}
+
+fn check_metadata(metadata: Option<&Value>, problems: &mut Vec<String>) {
+    let Some(metadata) = metadata.filter(|metadata| !metadata.is_null()) else {
+        return;
+    };
+    match serde_json::from_value::<BiometadataExtension>(metadata.clone()) {
+        Ok(metadata) => {
+            if let Err(errors) = metadata.validate() {
+                problems.extend(validation_problems(&errors, "metadata"));
+            }
+        }
+        Err(e) => problems.push(format!("metadata: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document() -> Value {
+        json!({
+            "@context": [DID_CORE_CONTEXT, "https://w3id.org/security/suites/ed25519-2020/v1"],
+            "id": "did:bio:abc",
+            "controller": "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK",
+            "verificationMethod": [{
+                "id": "did:bio:abc#keys-1",
+                "controller": "did:bio:abc",
+                "type": "Ed25519VerificationKey2020",
+                "publicKeyMultibase": "z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK"
+            }],
+            "authentication": ["did:bio:abc#keys-1"],
+            "service": [{
+                "id": "did:bio:abc#storage",
+                "type": "IPFSStorage",
+                "serviceEndpoint": "https://ipfs.io"
+            }],
+            "created": "2025-01-01T00:00:00Z",
+            "updated": "2025-02-01T00:00:00+01:00"
+        })
+    }
+
+    #[test]
+    fn test_validate_document_lists_every_problem() {
+        assert_eq!(validate_document(&document()), Vec::<String>::new());
+
+        let mut broken = document();
+        broken["@context"] = json!(["https://w3id.org/biodata/v1"]);
+        broken["verificationMethod"][0]["publicKeyMultibase"] = json!("z6Mk0OIl");
+        broken["authentication"] = json!(["did:bio:abc#keys-2"]);
+        broken["service"][0]["serviceEndpoint"] = json!("not a url");
+        broken["updated"] = json!("yesterday");
+
+        assert_eq!(
+            validate_document(&broken),
+            vec![
+                format!("@context[0]: must be {}", DID_CORE_CONTEXT),
+                "verificationMethod[0].publicKeyMultibase: is not a base58btc-encoded \
+                 Ed25519VerificationKey2020 key"
+                    .to_string(),
+                "authentication[0]: 'did:bio:abc#keys-2' is not a verification method of this \
+                 document"
+                    .to_string(),
+                "service[0].service_endpoint: must be a valid URL".to_string(),
+                "updated: 'yesterday' is not an RFC 3339 timestamp".to_string(),
+            ]
+        );
+    }
+}
@@ -1,12 +1,13 @@
 use crate::errors::ServiceError;
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use tokio::sync::oneshot;
 
 /// Metadata for files stored in IPFS
 #[serde_as]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FileMetadata {
     pub cid: String,
     pub name: String,
@@ -14,10 +15,12 @@ pub struct FileMetadata {
     #[serde_as(as = "DisplayFromStr")]
     pub timestamp: DateTime<Utc>,
     pub user_id: i32,
+    // Detected from the file's leading bytes; unset for files uploaded before detection
+    pub content_type: Option<String>,
 }
 
 /// Upload status response
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct UploadStatus {
     pub task_id: String,
     // "pending", "completed", "failed"
@@ -29,6 +32,42 @@ pub struct UploadStatus {
     pub started_at: DateTime<Utc>,
 }
 
+impl UploadStatus {
+    /// Whether the upload has finished; final statuses are never overwritten
+    pub fn is_final(&self) -> bool {
+        matches!(self.status.as_str(), "completed" | "failed")
+    }
+
+    /// Apply a status update unless the upload already finished.
+    ///
+    /// Progress only moves forward, so updates racing each other can't rewind it, and a
+    /// missing `cid`/`error` keeps the current value. Returns whether the update applied.
+    pub fn apply_update(
+        &mut self,
+        status: &str,
+        cid: Option<&str>,
+        error: Option<&str>,
+        progress: Option<f64>,
+    ) -> bool {
+        if self.is_final() {
+            return false;
+        }
+
+        self.status = status.to_string();
+        if let Some(cid) = cid {
+            self.cid = Some(cid.to_string());
+        }
+        if let Some(error) = error {
+            self.error = Some(error.to_string());
+        }
+        self.progress = match (self.progress, progress) {
+            (Some(current), Some(new)) => Some(current.max(new)),
+            (current, new) => new.or(current),
+        };
+        true
+    }
+}
+
 /// Task tracking information stored in memory and database
 pub struct TaskInfo {
     pub status: UploadStatus,
@@ -36,7 +75,7 @@ pub struct TaskInfo {
 }
 
 /// Research paper metadata extracted from papers and linked to DIDs
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ResearchPaperMetadata {
     pub title: String,
     pub authors: Vec<String>,
@@ -51,13 +90,34 @@ pub struct ResearchPaperMetadata {
     pub knowledge_graph_cid: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    // Personal data was found and the paper is hidden from search until approved
+    #[serde(default)]
+    pub needs_review: bool,
 }
 
 /// Reference to a biological entity identified in a research paper
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BiologicalEntityReference {
     pub entity_type: String,
     pub name: String,
     pub identifier: Option<String>,
     pub source: Option<String>,
 }
+
+/// Principals, besides the uploader, allowed to read a file's content.
+///
+/// A file without entries has no ACL and stays readable by anyone who is signed in.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct FileAcl {
+    // Users may read the file if they own one of these DIDs
+    #[serde(default)]
+    pub dids: Vec<String>,
+    #[serde(default)]
+    pub user_ids: Vec<i64>,
+}
+
+impl FileAcl {
+    pub fn is_empty(&self) -> bool {
+        self.dids.is_empty() && self.user_ids.is_empty()
+    }
+}
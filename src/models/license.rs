@@ -0,0 +1,163 @@
+use crate::errors::AppError;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Data and content licenses, identified by their SPDX IDs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum License {
+    Cc0,
+    CcBy4,
+    CcBySa4,
+    CcByNc4,
+    CcByNcSa4,
+    CcByNd4,
+    CcByNcNd4,
+    Odbl1,
+    Pddl1,
+    Mit,
+    Apache2,
+    Gpl3,
+    Bsd3Clause,
+    // `LicenseRef-` identifier, URL or free-text name of a license not listed above
+    Other(String),
+}
+
+/// Every known license, in the order they are suggested
+const KNOWN_LICENSES: &[License] = &[
+    License::Cc0,
+    License::CcBy4,
+    License::CcBySa4,
+    License::CcByNc4,
+    License::CcByNcSa4,
+    License::CcByNd4,
+    License::CcByNcNd4,
+    License::Odbl1,
+    License::Pddl1,
+    License::Mit,
+    License::Apache2,
+    License::Gpl3,
+    License::Bsd3Clause,
+];
+
+impl License {
+    /// Parse a license, accepting known SPDX IDs in any case.
+    ///
+    /// Unknown values that look like an SPDX ID (a single token with a digit or hyphen,
+    /// such as `CC-BY-4`) are rejected as likely typos. Custom licenses can use a
+    /// `LicenseRef-` ID, a URL or a descriptive name.
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(AppError::ValidationError(
+                "License must not be empty".to_string(),
+            ));
+        }
+
+        // Dataverse's historical name for CC0
+        if value.eq_ignore_ascii_case("CC0") {
+            return Ok(License::Cc0);
+        }
+        if let Some(known) = KNOWN_LICENSES
+            .iter()
+            .find(|license| license.spdx_id().eq_ignore_ascii_case(value))
+        {
+            return Ok(known.clone());
+        }
+
+        if looks_like_spdx_id(value) && !value.starts_with("LicenseRef-") {
+            return Err(AppError::ValidationError(format!(
+                "Unknown license identifier '{}', expected an SPDX ID such as {}; use a \
+                 LicenseRef- ID, URL or name for custom licenses",
+                value,
+                KNOWN_LICENSES
+                    .iter()
+                    .map(License::spdx_id)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+
+        Ok(License::Other(value.to_string()))
+    }
+
+    /// SPDX ID, or the value as given for `Other`
+    pub fn spdx_id(&self) -> &str {
+        match self {
+            License::Cc0 => "CC0-1.0",
+            License::CcBy4 => "CC-BY-4.0",
+            License::CcBySa4 => "CC-BY-SA-4.0",
+            License::CcByNc4 => "CC-BY-NC-4.0",
+            License::CcByNcSa4 => "CC-BY-NC-SA-4.0",
+            License::CcByNd4 => "CC-BY-ND-4.0",
+            License::CcByNcNd4 => "CC-BY-NC-ND-4.0",
+            License::Odbl1 => "ODbL-1.0",
+            License::Pddl1 => "PDDL-1.0",
+            License::Mit => "MIT",
+            License::Apache2 => "Apache-2.0",
+            License::Gpl3 => "GPL-3.0-only",
+            License::Bsd3Clause => "BSD-3-Clause",
+            License::Other(value) => value,
+        }
+    }
+
+    /// Dataverse license block (`{"name", "uri"}`). Known licenses use the names Dataverse
+    /// installations ship with; custom ones must be configured on the installation.
+    pub fn dataverse_license(&self) -> Value {
+        let (name, uri) = match self {
+            License::Cc0 => (
+                "CC0 1.0",
+                "http://creativecommons.org/publicdomain/zero/1.0",
+            ),
+            License::CcBy4 => ("CC BY 4.0", "http://creativecommons.org/licenses/by/4.0"),
+            License::CcBySa4 => (
+                "CC BY-SA 4.0",
+                "http://creativecommons.org/licenses/by-sa/4.0",
+            ),
+            License::CcByNc4 => (
+                "CC BY-NC 4.0",
+                "http://creativecommons.org/licenses/by-nc/4.0",
+            ),
+            License::CcByNcSa4 => (
+                "CC BY-NC-SA 4.0",
+                "http://creativecommons.org/licenses/by-nc-sa/4.0",
+            ),
+            License::CcByNd4 => (
+                "CC BY-ND 4.0",
+                "http://creativecommons.org/licenses/by-nd/4.0",
+            ),
+            License::CcByNcNd4 => (
+                "CC BY-NC-ND 4.0",
+                "http://creativecommons.org/licenses/by-nc-nd/4.0",
+            ),
+            License::Odbl1 => ("ODbL 1.0", "http://opendatacommons.org/licenses/odbl/1-0"),
+            License::Pddl1 => ("PDDL 1.0", "http://opendatacommons.org/licenses/pddl/1-0"),
+            License::Mit => ("MIT", "https://opensource.org/licenses/MIT"),
+            License::Apache2 => ("Apache-2.0", "https://www.apache.org/licenses/LICENSE-2.0"),
+            License::Gpl3 => ("GPL-3.0", "https://www.gnu.org/licenses/gpl-3.0.html"),
+            License::Bsd3Clause => (
+                "BSD-3-Clause",
+                "https://opensource.org/licenses/BSD-3-Clause",
+            ),
+            License::Other(value)
+                if value.starts_with("http://") || value.starts_with("https://") =>
+            {
+                return json!({ "name": value, "uri": value });
+            }
+            License::Other(value) => return json!({ "name": value }),
+        };
+        json!({ "name": name, "uri": uri })
+    }
+}
+
+impl fmt::Display for License {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.spdx_id())
+    }
+}
+
+fn looks_like_spdx_id(value: &str) -> bool {
+    value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '+'))
+        && value.chars().any(|c| c.is_ascii_digit() || c == '-')
+}
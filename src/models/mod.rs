@@ -1,4 +1,11 @@
+pub mod audit;
 pub mod auth;
+pub mod credential;
 pub mod did;
+pub mod did_template;
+pub mod did_validation;
 pub mod file_metadata;
+pub mod license;
 pub mod requests;
+pub mod task;
+pub mod webhook;
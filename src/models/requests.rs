@@ -1,8 +1,9 @@
+use schemars::JsonSchema;
 use serde::Deserialize;
 use validator::{Validate, ValidationError};
 
 /// Request structure for user signup
-#[derive(Debug, Validate, Deserialize)]
+#[derive(Debug, Validate, Deserialize, JsonSchema)]
 pub struct SignupRequest {
     #[validate(length(min = 3, max = 50))]
     pub username: String,
@@ -14,7 +15,7 @@ pub struct SignupRequest {
 }
 
 /// Request structure for user signin
-#[derive(Debug, Validate, Deserialize)]
+#[derive(Debug, Validate, Deserialize, JsonSchema)]
 pub struct SigninRequest {
     #[validate(email)]
     pub email: String,
@@ -35,9 +36,38 @@ pub fn validate_password(password: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+/// Rejects empty and whitespace-only strings
+pub fn validate_not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be blank".into()));
+    }
+    Ok(())
+}
+
+/// Rejects lists containing empty or whitespace-only entries
+pub fn validate_no_blank_entries(values: &[String]) -> Result<(), ValidationError> {
+    if values.iter().any(|v| v.trim().is_empty()) {
+        return Err(ValidationError::new("blank_entry")
+            .with_message("must not contain blank entries".into()));
+    }
+    Ok(())
+}
+
 /// Request structure for deleting files
-#[derive(Validate, Deserialize)]
+#[derive(Validate, Deserialize, JsonSchema)]
 pub struct DeleteRequest {
     #[validate(length(min = 1))]
     pub cid: String,
 }
+
+/// Request structure for opening a resumable upload session
+#[derive(Debug, Validate, Deserialize, JsonSchema)]
+pub struct CreateUploadSessionRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub file_name: String,
+    // Bytes
+    pub size: u64,
+    // Hex SHA-256 of the whole file
+    #[validate(length(equal = 64))]
+    pub sha256: String,
+}
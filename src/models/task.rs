@@ -0,0 +1,107 @@
+use crate::database::db_time::{from_db, to_db};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Kind of background work a task tracks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskType {
+    // IPFS upload tracked in `upload_tasks`
+    Upload,
+    // BioAgents paper processing tracked in `bioagent_tasks`
+    Bioagent,
+    // Server-side jobs such as admin maintenance, tracked in `background_tasks`
+    Background,
+}
+
+impl TaskType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskType::Upload => "upload",
+            TaskType::Bioagent => "bioagent",
+            TaskType::Background => "background",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "upload" => Some(TaskType::Upload),
+            "bioagent" => Some(TaskType::Bioagent),
+            "background" => Some(TaskType::Background),
+            _ => None,
+        }
+    }
+}
+
+/// Unified view over upload, BioAgents and background tasks
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Task {
+    pub task_id: String,
+    pub task_type: TaskType,
+    // Job name for background tasks, e.g. "ipfs_reimport"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    pub user_id: i64,
+    // "pending", "processing", "completed", "failed"
+    pub status: String,
+    // Percentage complete (0.0 to 100.0) for every task type
+    pub progress: Option<f64>,
+    // Input CID (paper for BioAgents tasks)
+    pub cid: Option<String>,
+    // Output CID (uploaded file or BioAgents result)
+    pub result_cid: Option<String>,
+    pub error: Option<String>,
+    // Job-specific progress report for background tasks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// States a task can be in
+pub const TASK_STATUSES: &[&str] = &["pending", "processing", "completed", "failed"];
+
+impl Task {
+    /// Whether the task has reached a final state
+    pub fn is_finished(&self) -> bool {
+        self.status == "completed" || self.status == "failed"
+    }
+}
+
+/// Position after the last task of a listing page: tasks are ordered newest first, with
+/// ties on the creation time broken by task ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskCursor {
+    pub created_at: DateTime<Utc>,
+    pub task_id: String,
+}
+
+impl TaskCursor {
+    pub fn after(task: &Task) -> Self {
+        Self {
+            created_at: task.created_at,
+            task_id: task.task_id.clone(),
+        }
+    }
+
+    /// Opaque form handed to clients as `next_cursor`
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}", to_db(self.created_at), self.task_id))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (created_at, task_id) = decoded.split_once('|')?;
+        if task_id.is_empty() {
+            return None;
+        }
+        Some(Self {
+            created_at: from_db(created_at)?,
+            task_id: task_id.to_string(),
+        })
+    }
+}
@@ -0,0 +1,129 @@
+use crate::errors::AppError;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of webhooks a user may register
+pub const MAX_WEBHOOKS_PER_USER: usize = 20;
+
+/// DID lifecycle events delivered to webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum WebhookEvent {
+    DidCreated,
+    DidUpdated,
+    // The embargo in a DID's metadata has passed
+    DidEmbargoLifted,
+}
+
+impl WebhookEvent {
+    pub const ALL: &'static [WebhookEvent] = &[
+        WebhookEvent::DidCreated,
+        WebhookEvent::DidUpdated,
+        WebhookEvent::DidEmbargoLifted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::DidCreated => "did.created",
+            WebhookEvent::DidUpdated => "did.updated",
+            WebhookEvent::DidEmbargoLifted => "did.embargo_lifted",
+        }
+    }
+}
+
+/// A registered webhook subscriber. The signing secret is only returned on creation.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Webhook {
+    pub webhook_id: String,
+    pub user_id: i64,
+    pub url: String,
+    // Subscribed event names, e.g. "did.created"
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Newly created webhook, with the secret used to sign its deliveries
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct WebhookCreated {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    // Hex HMAC-SHA256 key; verify `X-Signature` against it
+    pub secret: String,
+}
+
+/// Request to create or replace a webhook
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookRequest {
+    pub url: String,
+    // Defaults to every event
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl WebhookRequest {
+    /// Checks the URL is absolute HTTP(S) and normalizes the event list, filling in every
+    /// event when none are given. Whether the host is public is checked by the service, as
+    /// that needs a DNS lookup.
+    pub fn validate(&mut self) -> Result<(), AppError> {
+        let url = reqwest::Url::parse(self.url.trim())
+            .map_err(|e| AppError::ValidationError(format!("Invalid webhook URL: {}", e)))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(AppError::ValidationError(
+                "Webhook URL must be an absolute http or https URL".to_string(),
+            ));
+        }
+        self.url = url.to_string();
+
+        if self.events.is_empty() {
+            self.events = WebhookEvent::ALL
+                .iter()
+                .map(|e| e.as_str().to_string())
+                .collect();
+        }
+        if let Some(unknown) = self
+            .events
+            .iter()
+            .find(|e| !WebhookEvent::ALL.iter().any(|known| known.as_str() == *e))
+        {
+            return Err(AppError::ValidationError(format!(
+                "Unknown webhook event '{}', expected one of: {}",
+                unknown,
+                WebhookEvent::ALL
+                    .iter()
+                    .map(WebhookEvent::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        self.events.sort();
+        self.events.dedup();
+
+        Ok(())
+    }
+}
+
+/// Outcome of delivering one event to one webhook
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: String,
+    pub event: String,
+    pub did: String,
+    // "pending" while retrying, then "delivered" or "failed"
+    pub status: String,
+    pub attempts: u32,
+    // HTTP status of the last attempt, if the subscriber answered
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,315 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{error, info};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::errors::AppError;
+use crate::models::audit::AuditQuery;
+use crate::models::auth::AuthUser;
+use crate::routes::totp::step_up_code;
+use crate::routes::AppState;
+use crate::services::ipfs_service::ReimportProgress;
+use crate::services::step_up_service::StepUpOperation;
+
+/// Progress is written to the task table at most once per this many CIDs
+const REIMPORT_PROGRESS_INTERVAL: usize = 10;
+
+/// Reject callers without the admin role
+pub fn require_admin(user: &AuthUser) -> Result<(), AppError> {
+    if user.is_admin() {
+        Ok(())
+    } else {
+        Err(AppError::AuthorizationError(
+            "Admin role required".to_string(),
+        ))
+    }
+}
+
+/// Re-pin all DID and paper content on the current IPFS node after a migration
+///
+/// Runs in the background; poll `GET /api/tasks/{task_id}` for progress.
+pub async fn reimport(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    info!("Admin {} started an IPFS re-import", user.id);
+
+    let task_id = app_state
+        .task_service
+        .create_background_task(user.id, "ipfs_reimport")
+        .await?;
+
+    let ipfs_service = app_state.ipfs_service.clone();
+    let task_service = app_state.task_service.clone();
+    let job_task_id = task_id.clone();
+
+    tokio::task::spawn_local(async move {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<ReimportProgress>();
+
+        let progress_task_service = task_service.clone();
+        let progress_task_id = job_task_id.clone();
+        let progress = async move {
+            while let Some(update) = rx.recv().await {
+                if update.processed % REIMPORT_PROGRESS_INTERVAL != 0
+                    && update.processed != update.total
+                {
+                    continue;
+                }
+                let percent = update.processed as f64 * 100.0 / update.total.max(1) as f64;
+                let details = serde_json::json!({
+                    "processed": update.processed,
+                    "total": update.total,
+                    "succeeded": update.succeeded,
+                    "failed": update.failed,
+                    "last_cid": update.cid,
+                });
+                if let Err(e) = progress_task_service
+                    .update_background_task(
+                        &progress_task_id,
+                        "processing",
+                        percent,
+                        Some(&details),
+                        None,
+                    )
+                    .await
+                {
+                    error!("Failed to record re-import progress: {}", e);
+                }
+            }
+        };
+
+        let (result, ()) = tokio::join!(ipfs_service.reimport_all(tx), progress);
+
+        let outcome = match result {
+            Ok(report) => match serde_json::to_value(&report) {
+                Ok(details) => {
+                    task_service
+                        .update_background_task(
+                            &job_task_id,
+                            "completed",
+                            100.0,
+                            Some(&details),
+                            None,
+                        )
+                        .await
+                }
+                Err(e) => {
+                    task_service
+                        .update_background_task(
+                            &job_task_id,
+                            "failed",
+                            100.0,
+                            None,
+                            Some(&e.to_string()),
+                        )
+                        .await
+                }
+            },
+            Err(e) => {
+                task_service
+                    .update_background_task(&job_task_id, "failed", 0.0, None, Some(&e.to_string()))
+                    .await
+            }
+        };
+        if let Err(e) = outcome {
+            error!(
+                "Failed to record re-import result for {}: {}",
+                job_task_id, e
+            );
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "task_id": task_id,
+        "status": "pending"
+    })))
+}
+
+/// Options for `POST /api/admin/reconcile`
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ReconcileQuery {
+    // Also list pinned CIDs no row references; lists every pin on the node
+    #[serde(default)]
+    pub include_orphans: bool,
+}
+
+/// Report DID and paper CIDs whose content can no longer be retrieved, and optionally
+/// pinned content no row references
+///
+/// Runs in the background; the report is in the task details of `GET /api/tasks/{task_id}`.
+pub async fn reconcile(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    query: web::Query<ReconcileQuery>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    info!(
+        "Admin {} started an IPFS reconciliation (orphans: {})",
+        user.id, query.include_orphans
+    );
+
+    let task_id = app_state
+        .task_service
+        .create_background_task(user.id, "ipfs_reconcile")
+        .await?;
+
+    let ipfs_service = app_state.ipfs_service.clone();
+    let task_service = app_state.task_service.clone();
+    let job_task_id = task_id.clone();
+    let include_orphans = query.include_orphans;
+
+    tokio::task::spawn_local(async move {
+        if let Err(e) = task_service
+            .update_background_task(&job_task_id, "processing", 0.0, None, None)
+            .await
+        {
+            error!(
+                "Failed to mark reconciliation {} started: {}",
+                job_task_id, e
+            );
+        }
+
+        let outcome = match ipfs_service
+            .reconcile(include_orphans)
+            .await
+            .and_then(|report| {
+                serde_json::to_value(&report).map_err(|_| AppError::SerializationError)
+            }) {
+            Ok(details) => {
+                task_service
+                    .update_background_task(&job_task_id, "completed", 100.0, Some(&details), None)
+                    .await
+            }
+            Err(e) => {
+                task_service
+                    .update_background_task(&job_task_id, "failed", 0.0, None, Some(&e.to_string()))
+                    .await
+            }
+        };
+        if let Err(e) = outcome {
+            error!(
+                "Failed to record reconciliation result for {}: {}",
+                job_task_id, e
+            );
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "task_id": task_id,
+        "status": "pending"
+    })))
+}
+
+/// Page through the audit log, filtered by user and time range
+pub async fn audit_log(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    query: web::Query<AuditQuery>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            return Err(AppError::ValidationError(
+                "'from' must be earlier than 'to'".to_string(),
+            ));
+        }
+    }
+
+    let page = app_state.audit_service.list(&query).await?;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// List the versions of the service's post-quantum keys, without secret keys
+pub async fn list_keys(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    let versions = app_state.key_registry.list().await?;
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+/// Generate new Kyber1024/Dilithium5 key pairs and sign new auth tokens with them
+///
+/// Tokens signed with the previous version stay valid until it is retired, and DID fields
+/// are sealed to the new Kyber1024 key.
+pub async fn rotate_keys(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    let version = app_state.key_registry.rotate(user.id).await?;
+    Ok(HttpResponse::Created().json(version))
+}
+
+/// Stop accepting tokens signed with a previous key version and erase its signing secret key
+pub async fn retire_key(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    path: web::Path<u32>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    app_state
+        .step_up_service
+        .require(&user, StepUpOperation::RetireKey, step_up_code(&req))
+        .await?;
+    let version = app_state
+        .key_registry
+        .retire(path.into_inner(), user.id)
+        .await?;
+    Ok(HttpResponse::Ok().json(version))
+}
+
+/// List research papers held back from search because personal data was found in them
+pub async fn papers_needing_review(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    let papers = app_state
+        .research_paper_service
+        .list_papers_needing_review()
+        .await?;
+    Ok(HttpResponse::Ok().json(papers))
+}
+
+/// Approve a flagged research paper after review, making it searchable
+pub async fn approve_paper(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    require_admin(&user)?;
+    let did = path.into_inner();
+    let paper = app_state.research_paper_service.approve_paper(&did).await?;
+    app_state
+        .audit_service
+        .record_audit(
+            user.id,
+            "paper.approve",
+            "paper",
+            &did,
+            serde_json::json!({ "cid": paper.cid }),
+        )
+        .await;
+    Ok(HttpResponse::Ok().json(paper))
+}
+
+/// Initialize admin routes
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .route("/reimport", web::post().to(reimport))
+            .route("/reconcile", web::post().to(reconcile))
+            .route("/audit", web::get().to(audit_log))
+            .route("/keys", web::get().to(list_keys))
+            .route("/keys/rotate", web::post().to(rotate_keys))
+            .route("/keys/{version}/retire", web::post().to(retire_key))
+            .route("/papers/review", web::get().to(papers_needing_review))
+            .route("/papers/{did}/approve", web::post().to(approve_paper)),
+    );
+}
@@ -1,36 +1,49 @@
 use crate::errors::AppError;
 use crate::models::auth::AuthUser;
 use crate::models::{auth::AuthResponse, requests::*};
-use crate::routes::AppState;
-use actix_web::{web, HttpResponse, Responder};
+use crate::routes::{json_config, AppState};
+use crate::services::orcid_service::{OrcidService, ORCID_STATE_COOKIE};
+use crate::services::ucan_service::DEFAULT_LISTED_TOKENS;
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
 use log::info;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use validator::Validate;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema, Validate)]
 pub struct UcanIssueRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
     pub audience: String,
+    #[validate(
+        length(min = 1, max = 50, message = "must list between 1 and 50 capabilities"),
+        nested
+    )]
     pub capabilities: Vec<UcanCapability>,
     pub expiration: Option<i64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Validate)]
 pub struct UcanCapability {
+    #[validate(custom(function = "validate_not_blank"), length(max = 2048))]
     pub with: String,
+    #[validate(custom(function = "validate_not_blank"), length(max = 255))]
     pub can: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct UcanResponse {
     pub token: String,
     pub expires_at: i64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct UcanValidateRequest {
     pub token: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct UcanValidationResponse {
     pub valid: bool,
     pub issuer: Option<String>,
@@ -40,17 +53,55 @@ pub struct UcanValidationResponse {
     pub reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct UcanRevokeRequest {
     pub token: String,
 }
 
+/// Filters and page of `GET /api/ucan`
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UcanListQuery {
+    // Also list revoked tokens
+    #[serde(default)]
+    pub include_revoked: bool,
+    // Also list expired tokens
+    #[serde(default)]
+    pub include_expired: bool,
+    // Tokens per page, 50 by default and at most 200
+    pub limit: Option<u32>,
+    // Tokens skipped
+    #[serde(default)]
+    pub offset: u64,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct OrcidCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    // Set by ORCID when the researcher denied access
+    pub error: Option<String>,
+}
+
+/// Body limit of signup and signin, far below the global one since credentials are small
+const AUTH_JSON_LIMIT: usize = 4 * 1024;
+
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.route("/signup", web::post().to(signup))
-        .route("/signin", web::post().to(signin))
-        .route("/ucan/issue", web::post().to(issue_ucan))
-        .route("/ucan/validate", web::post().to(validate_ucan))
-        .route("/ucan/revoke", web::post().to(revoke_ucan));
+    cfg.service(
+        web::resource("/signup")
+            .app_data(json_config(AUTH_JSON_LIMIT))
+            .route(web::post().to(signup)),
+    )
+    .service(
+        web::resource("/signin")
+            .app_data(json_config(AUTH_JSON_LIMIT))
+            .route(web::post().to(signin)),
+    )
+    .route("/auth/orcid/login", web::get().to(orcid_login))
+    .route("/auth/orcid/callback", web::get().to(orcid_callback))
+    .route("/ucan", web::get().to(list_ucans))
+    .route("/ucan/issue", web::post().to(issue_ucan))
+    .route("/ucan/validate", web::post().to(validate_ucan))
+    .route("/ucan/revoke", web::post().to(revoke_ucan));
 }
 
 /// Handles user signup requests
@@ -73,17 +124,88 @@ async fn signin(
     Ok(HttpResponse::Ok().json(AuthResponse { token }))
 }
 
+fn orcid_service(app_state: &AppState) -> Result<&Arc<OrcidService>, AppError> {
+    app_state
+        .orcid_service
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("ORCID login is not configured".to_string()))
+}
+
+/// Redirect the browser to ORCID to sign in
+/// GET /api/auth/orcid/login
+async fn orcid_login(app_state: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let orcid = orcid_service(&app_state)?;
+
+    // Echoed back by ORCID and checked against the cookie, so a callback can't be forged
+    let state = uuid::Uuid::new_v4().simple().to_string();
+    let location = orcid.authorize_url(&state)?;
+    let cookie = Cookie::build(ORCID_STATE_COOKIE, state)
+        .path(orcid.callback_path())
+        .http_only(true)
+        .secure(orcid.redirect_is_https())
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(10))
+        .finish();
+
+    Ok(HttpResponse::Found()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, location))
+        .finish())
+}
+
+/// Complete an ORCID sign-in, creating the account on first login
+/// GET /api/auth/orcid/callback
+async fn orcid_callback(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<OrcidCallbackQuery>,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let orcid = orcid_service(&app_state)?;
+
+    if let Some(error) = &query.error {
+        return Err(
+            AppError::AuthError(format!("ORCID sign-in was not completed: {}", error)).into(),
+        );
+    }
+    let expected_state = http_req.cookie(ORCID_STATE_COOKIE);
+    match (&query.state, &expected_state) {
+        (Some(state), Some(cookie)) if state == cookie.value() => {}
+        _ => {
+            return Err(AppError::AuthError(
+                "ORCID sign-in state is missing or does not match".to_string(),
+            )
+            .into())
+        }
+    }
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| AppError::ValidationError("Missing ORCID authorization code".to_string()))?;
+
+    let profile = orcid.exchange_code(code).await?;
+    let token = app_state.ipfs_service.signin_orcid(&profile).await?;
+
+    let mut clear = Cookie::build(ORCID_STATE_COOKIE, "")
+        .path(orcid.callback_path())
+        .finish();
+    clear.make_removal();
+    Ok(HttpResponse::Ok()
+        .cookie(clear)
+        .json(AuthResponse { token }))
+}
+
 /// Issue a new UCAN token
 /// POST /api/ucan/issue
 async fn issue_ucan(
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     req: web::Json<UcanIssueRequest>,
 ) -> Result<impl Responder, AppError> {
     info!(
         "User {} is issuing a UCAN token for {}",
         user.id, req.audience
     );
+    req.validate()?;
 
     let capabilities = req
         .capabilities
@@ -93,12 +215,38 @@ async fn issue_ucan(
 
     let (token, expires_at) = app_state
         .ucan_service
-        .issue_token(user.id, &req.audience, &capabilities, req.expiration)
+        .issue_token(
+            user.id,
+            user.is_admin(),
+            &req.audience,
+            &capabilities,
+            req.expiration,
+        )
         .await?;
 
     Ok(HttpResponse::Created().json(UcanResponse { token, expires_at }))
 }
 
+/// List the UCANs the caller issued, without the tokens themselves
+/// GET /api/ucan
+async fn list_ucans(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    query: web::Query<UcanListQuery>,
+) -> Result<impl Responder, AppError> {
+    let page = app_state
+        .ucan_service
+        .list_tokens(
+            user.id,
+            query.include_revoked,
+            query.include_expired,
+            query.limit.unwrap_or(DEFAULT_LISTED_TOKENS),
+            query.offset,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
 /// Validate a UCAN token
 /// POST /api/ucan/validate
 async fn validate_ucan(
@@ -143,7 +291,7 @@ async fn validate_ucan(
 /// POST /api/ucan/revoke
 async fn revoke_ucan(
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     req: web::Json<UcanRevokeRequest>,
 ) -> Result<impl Responder, AppError> {
     info!("User {} is revoking a UCAN token", user.id);
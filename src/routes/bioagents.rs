@@ -1,73 +1,218 @@
-use actix_web::{web, HttpResponse, Responder};
-use log::info;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{error, info, warn};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
 use crate::errors::AppError;
 use crate::models::auth::AuthUser;
+use crate::models::requests::{validate_no_blank_entries, validate_not_blank};
 use crate::routes::AppState;
-use crate::services::bioagents_service::ProcessPaperRequest;
+use crate::services::bioagents_service::{BiologicalEntity, ProcessPaperRequest};
+use crate::services::idempotency_service::IdempotencyKey;
+use crate::services::quota_service::QuotaResource;
+use crate::services::sparql_service::SPARQL_RESULTS_MEDIA_TYPE;
 
 /// Request to process a paper
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema, Validate)]
 pub struct ProcessPaperApiRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 100))]
     pub file_cid: String,
+    #[validate(custom(function = "validate_not_blank"), length(max = 500))]
     pub title: String,
+    #[validate(
+        length(min = 1, max = 200, message = "must list between 1 and 200 authors"),
+        custom(function = "validate_no_blank_entries")
+    )]
     pub authors: Vec<String>,
+    #[validate(length(max = 255))]
     pub doi: Option<String>,
 }
 
 /// Request to check task status
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct TaskStatusRequest {
     pub task_id: String,
 }
 
+/// Request to check the status of several tasks at once
+#[derive(Deserialize, Validate, JsonSchema)]
+pub struct TaskStatusBatchRequest {
+    #[validate(
+        length(min = 1, max = 100, message = "must list between 1 and 100 tasks"),
+        custom(function = "validate_no_blank_entries")
+    )]
+    pub task_ids: Vec<String>,
+}
+
 /// Request to search for biological entities
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct EntitySearchRequest {
     pub query: String,
 }
 
+/// Optional filters for entity search, passed as query parameters
+#[derive(Deserialize, JsonSchema)]
+pub struct EntitySearchFilters {
+    // Comma-separated, e.g. `gene,protein`
+    pub entity_types: Option<String>,
+    pub min_confidence: Option<f32>,
+    // Page size; when set the response is an `EntitySearchPage`
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// Upper bound on `limit` for entity search
+const MAX_ENTITY_PAGE_SIZE: u32 = 500;
+
+/// One page of entity search results
+#[derive(Serialize, JsonSchema)]
+pub struct EntitySearchPage {
+    pub entities: Vec<BiologicalEntity>,
+    // Pass as `offset` to fetch the next page; absent on the last page
+    pub next_page_token: Option<String>,
+}
+
+/// Entity search response: a plain array unless pagination was requested
+#[derive(Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum EntitySearchResponse {
+    All(Vec<BiologicalEntity>),
+    Page(EntitySearchPage),
+}
+
 /// Request to extract metadata
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct ExtractMetadataRequest {
     pub task_id: String,
 }
 
 /// Request to generate a knowledge graph
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct GenerateKnowledgeGraphRequest {
     pub cid: String,
 }
 
+/// Request to merge the knowledge graphs of several papers
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct MergeKnowledgeGraphsRequest {
+    // CIDs of the papers, as submitted for processing
+    #[validate(
+        length(min = 2, max = 20, message = "must list between 2 and 20 papers"),
+        custom(function = "validate_no_blank_entries")
+    )]
+    pub paper_cids: Vec<String>,
+}
+
+/// Accepted knowledge graph merge
+#[derive(Serialize, JsonSchema)]
+pub struct MergeKnowledgeGraphsResponse {
+    pub task_id: String,
+    pub status: String,
+    // Knowledge graphs being merged
+    pub knowledge_graph_cids: Vec<String>,
+    // Papers that were skipped and why
+    pub warnings: Vec<String>,
+}
+
 /// Process a paper through BioAgents
+///
+/// Retries carrying the same `Idempotency-Key` header return the original task.
 pub async fn process_paper(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     request: web::Json<ProcessPaperApiRequest>,
 ) -> Result<impl Responder, AppError> {
     info!("Processing paper: {} for user: {}", request.title, user.id);
+    request.validate()?;
 
-    let service_request = ProcessPaperRequest {
-        file_cid: request.file_cid.clone(),
-        title: request.title.clone(),
-        authors: request.authors.clone(),
-        doi: request.doi.clone(),
-        extract_metadata: true,
-        generate_knowledge_graph: true,
-    };
+    let idempotency_key = IdempotencyKey::from_request(&http_req, "bioagents_process", &*request)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(response) = app_state.idempotency_service.reserve(user.id, key).await? {
+            return Ok(response);
+        }
+    }
 
-    let response = app_state
-        .bioagents_service
-        .process_paper(service_request)
-        .await?;
+    let submitted = async {
+        app_state
+            .research_paper_service
+            .validate_paper_file(&request.file_cid)
+            .await?;
+
+        let service_request = ProcessPaperRequest {
+            file_cid: request.file_cid.clone(),
+            title: request.title.clone(),
+            authors: request.authors.clone(),
+            doi: request.doi.clone(),
+            extract_metadata: true,
+            generate_knowledge_graph: true,
+        };
+
+        app_state
+            .quota_service
+            .check_and_increment(&user, QuotaResource::Papers)
+            .await?;
+        let response = match app_state
+            .bioagents_service
+            .process_paper(service_request)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                app_state
+                    .quota_service
+                    .refund(user.id, QuotaResource::Papers)
+                    .await;
+                return Err(e);
+            }
+        };
+
+        app_state
+            .task_service
+            .record_bioagent_task(
+                &response.task_id,
+                user.id,
+                &request.file_cid,
+                &response.status,
+            )
+            .await?;
+        Ok(response)
+    }
+    .await;
+    let response = match submitted {
+        Ok(response) => response,
+        Err(e) => {
+            if let Some(key) = &idempotency_key {
+                app_state.idempotency_service.release(user.id, key).await;
+            }
+            return Err(e);
+        }
+    };
+    app_state
+        .task_service
+        .watch_bioagent_task(response.task_id.clone());
+
+    if let Some(key) = &idempotency_key {
+        app_state
+            .idempotency_service
+            .record(
+                user.id,
+                key,
+                StatusCode::ACCEPTED,
+                &response,
+                Some(&response.task_id),
+            )
+            .await;
+    }
 
     Ok(HttpResponse::Accepted().json(response))
 }
 
 /// Check the status of a paper processing task
 pub async fn check_task_status(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
     request: web::Json<TaskStatusRequest>,
 ) -> Result<impl Responder, AppError> {
@@ -84,9 +229,46 @@ pub async fn check_task_status(
     Ok(HttpResponse::Ok().json(status))
 }
 
+/// Check the status of several paper processing tasks; unknown or failed lookups, and tasks
+/// of other users, are reported per task in `errors`
+pub async fn check_task_statuses(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    request: web::Json<TaskStatusBatchRequest>,
+) -> Result<impl Responder, AppError> {
+    request.validate()?;
+    info!(
+        "Checking status of {} tasks for user: {}",
+        request.task_ids.len(),
+        user.id
+    );
+
+    let owned = app_state
+        .task_service
+        .owned_bioagent_tasks(&request.task_ids, user.id)
+        .await?;
+    let (task_ids, others): (Vec<String>, Vec<String>) = request
+        .task_ids
+        .iter()
+        .cloned()
+        .partition(|task_id| owned.contains(task_id));
+
+    let mut batch = app_state
+        .bioagents_service
+        .check_task_statuses(&task_ids)
+        .await;
+    // Other users' tasks look the same as unknown ones
+    for task_id in others {
+        let error = AppError::NotFound(format!("Task not found: {}", task_id));
+        batch.errors.insert(task_id, error.to_string());
+    }
+
+    Ok(HttpResponse::Ok().json(batch))
+}
+
 /// Get extracted metadata for a completed task
 pub async fn get_extracted_metadata(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
     request: web::Json<ExtractMetadataRequest>,
 ) -> Result<impl Responder, AppError> {
@@ -104,9 +286,14 @@ pub async fn get_extracted_metadata(
 }
 
 /// Search for related biological entities
+///
+/// Results can be narrowed with `?entity_types=gene,protein&min_confidence=0.8`. Without
+/// `limit`/`offset` all matches are returned as a plain array; with them the response is an
+/// `EntitySearchPage`.
 pub async fn search_entities(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
+    filters: web::Query<EntitySearchFilters>,
     request: web::Json<EntitySearchRequest>,
 ) -> Result<impl Responder, AppError> {
     info!(
@@ -114,17 +301,57 @@ pub async fn search_entities(
         request.query, user.id
     );
 
-    let entities = app_state
+    if let Some(min_confidence) = filters.min_confidence {
+        if !(0.0..=1.0).contains(&min_confidence) {
+            return Err(AppError::ValidationError(
+                "min_confidence must be between 0.0 and 1.0".to_string(),
+            ));
+        }
+    }
+
+    let entity_types: Vec<String> = filters
+        .entity_types
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect();
+
+    if filters.limit == Some(0) || filters.limit > Some(MAX_ENTITY_PAGE_SIZE) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_ENTITY_PAGE_SIZE
+        )));
+    }
+
+    let (entities, next_offset) = app_state
         .bioagents_service
-        .search_related_entities(&request.query)
+        .search_related_entities(
+            &request.query,
+            &entity_types,
+            filters.min_confidence,
+            filters.limit,
+            filters.offset,
+        )
         .await?;
 
-    Ok(HttpResponse::Ok().json(entities))
+    let response = if filters.limit.is_none() && filters.offset.is_none() {
+        EntitySearchResponse::All(entities)
+    } else {
+        EntitySearchResponse::Page(EntitySearchPage {
+            entities,
+            next_page_token: next_offset.map(|offset| offset.to_string()),
+        })
+    };
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
 /// Generate a knowledge graph for a paper
 pub async fn generate_knowledge_graph(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
     request: web::Json<GenerateKnowledgeGraphRequest>,
 ) -> Result<impl Responder, AppError> {
@@ -143,25 +370,198 @@ pub async fn generate_knowledge_graph(
     })))
 }
 
-#[derive(Debug, Deserialize)]
+/// Merge the knowledge graphs of several papers into one graph stored on IPFS
+///
+/// Papers without a knowledge graph are skipped and listed in `warnings`. The merge runs
+/// in the background; poll `GET /api/tasks/{task_id}`, whose details hold the merged
+/// graph's `knowledge_graph_cid` once it completes.
+pub async fn merge_knowledge_graphs(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    request: web::Json<MergeKnowledgeGraphsRequest>,
+) -> Result<impl Responder, AppError> {
+    request.validate()?;
+    info!(
+        "User {} merging knowledge graphs of {} papers",
+        user.id,
+        request.paper_cids.len()
+    );
+
+    let graphs = app_state
+        .research_paper_service
+        .knowledge_graph_cids(&request.paper_cids)
+        .await?;
+
+    let mut knowledge_graph_cids: Vec<String> = Vec::new();
+    let mut warnings = Vec::new();
+    for paper_cid in &request.paper_cids {
+        match graphs.get(paper_cid) {
+            Some(Some(graph_cid)) if !knowledge_graph_cids.contains(graph_cid) => {
+                knowledge_graph_cids.push(graph_cid.clone())
+            }
+            Some(Some(_)) => {}
+            Some(None) => warnings.push(format!(
+                "Paper {} has no knowledge graph and was skipped",
+                paper_cid
+            )),
+            None => warnings.push(format!("Paper {} not found and was skipped", paper_cid)),
+        }
+    }
+    for warning in &warnings {
+        warn!("{}", warning);
+    }
+    if knowledge_graph_cids.len() < 2 {
+        return Err(AppError::ValidationError(format!(
+            "At least 2 knowledge graphs are needed to merge, found {}",
+            knowledge_graph_cids.len()
+        )));
+    }
+
+    let task_id = app_state
+        .task_service
+        .create_background_task(user.id, "knowledge_graph_merge")
+        .await?;
+
+    let bioagents_service = app_state.bioagents_service.clone();
+    let ipfs_service = app_state.ipfs_service.clone();
+    let task_service = app_state.task_service.clone();
+    let job_task_id = task_id.clone();
+    let job_graph_cids = knowledge_graph_cids.clone();
+    let job_warnings = warnings.clone();
+
+    tokio::task::spawn_local(async move {
+        let progress = |percent: f64, step: &str| {
+            let task_service = task_service.clone();
+            let task_id = job_task_id.clone();
+            let details = serde_json::json!({ "step": step });
+            async move {
+                if let Err(e) = task_service
+                    .update_background_task(&task_id, "processing", percent, Some(&details), None)
+                    .await
+                {
+                    error!("Failed to record knowledge graph merge progress: {}", e);
+                }
+            }
+        };
+
+        progress(10.0, "merging").await;
+        let result = async {
+            let merged = bioagents_service
+                .merge_knowledge_graphs(&job_graph_cids)
+                .await?;
+            progress(80.0, "storing").await;
+            ipfs_service.add_content(&merged).await
+        }
+        .await;
+
+        let outcome = match result {
+            Ok(merged_cid) => {
+                info!(
+                    "Merged {} knowledge graphs into {}",
+                    job_graph_cids.len(),
+                    merged_cid
+                );
+                let details = serde_json::json!({
+                    "knowledge_graph_cid": merged_cid,
+                    "merged": job_graph_cids,
+                    "warnings": job_warnings,
+                });
+                task_service
+                    .update_background_task(&job_task_id, "completed", 100.0, Some(&details), None)
+                    .await
+            }
+            Err(e) => {
+                task_service
+                    .update_background_task(&job_task_id, "failed", 0.0, None, Some(&e.to_string()))
+                    .await
+            }
+        };
+        if let Err(e) = outcome {
+            error!(
+                "Failed to record knowledge graph merge result for {}: {}",
+                job_task_id, e
+            );
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(MergeKnowledgeGraphsResponse {
+        task_id,
+        status: "pending".to_string(),
+        knowledge_graph_cids,
+        warnings,
+    }))
+}
+
+/// Read-only SPARQL query over a knowledge graph
+#[derive(Debug, Deserialize, Validate, JsonSchema)]
+pub struct SparqlQueryRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 10000))]
+    pub query: String,
+}
+
+/// Run a SPARQL query against a knowledge graph stored on IPFS.
+///
+/// Only `SELECT` and `ASK` over triple patterns are supported; updates are refused.
+/// Graphs uploaded through this service keep their access rules; graphs BioAgents stored
+/// are open to every signed-in user.
+pub async fn query_knowledge_graph(
+    path: web::Path<String>,
+    request: web::Json<SparqlQueryRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+) -> Result<impl Responder, AppError> {
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+    let cid = path.into_inner();
+    info!("User {} querying knowledge graph {}", user.id, cid);
+
+    let uploaded = app_state
+        .ipfs_service
+        .get_file_metadata(&cid)
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .is_some();
+    if uploaded {
+        app_state.ipfs_service.check_access(&cid, &user).await?;
+    }
+
+    let results = app_state.sparql_service.query(&cid, &request.query).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(SPARQL_RESULTS_MEDIA_TYPE)
+        .json(results))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct AgentQueryRequest {
     pub query: String,
 }
 
-#[derive(Debug, Serialize)]
+/// Optional paging for agent queries, passed as query parameters
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AgentQueryParams {
+    // Number of sources to retrieve
+    pub top_k: Option<u32>,
+    // `next_page_token` from a previous response
+    pub page_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct AgentQueryResponse {
     pub answer: String,
     pub sources: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct KnowledgeAddRequest {
     pub title: String,
     pub content: String,
     pub keywords: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct KnowledgeAddResponse {
     pub id: String,
     pub status: String,
@@ -170,21 +570,35 @@ pub struct KnowledgeAddResponse {
 /// Query bioagents with a natural language question
 pub async fn query_agents(
     req: web::Json<AgentQueryRequest>,
+    params: web::Query<AgentQueryParams>,
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
 ) -> Result<impl Responder, AppError> {
     info!("User {} is querying bioagents with: {}", user.id, req.query);
 
-    let (answer, sources) = app_state.bioagents_service.query_agents(&req.query).await?;
+    if params.top_k == Some(0) {
+        return Err(AppError::ValidationError(
+            "top_k must be at least 1".to_string(),
+        ));
+    }
 
-    Ok(HttpResponse::Ok().json(AgentQueryResponse { answer, sources }))
+    let result = app_state
+        .bioagents_service
+        .query_agents(&req.query, params.top_k, params.page_token.as_deref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(AgentQueryResponse {
+        answer: result.answer,
+        sources: result.sources,
+        next_page_token: result.next_page_token,
+    }))
 }
 
 /// Add knowledge to the bioagent system
 pub async fn add_knowledge(
     req: web::Json<KnowledgeAddRequest>,
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
 ) -> Result<impl Responder, AppError> {
     info!("User {} is adding knowledge: {}", user.id, req.title);
 
@@ -225,9 +639,18 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
         web::scope("/bioagents")
             .route("/process", web::post().to(process_paper))
             .route("/status", web::post().to(check_task_status))
+            .route("/status/batch", web::post().to(check_task_statuses))
             .route("/metadata", web::post().to(get_extracted_metadata))
             .route("/search", web::post().to(search_entities))
             .route("/knowledge-graph", web::post().to(generate_knowledge_graph))
+            .route(
+                "/knowledge-graph/merge",
+                web::post().to(merge_knowledge_graphs),
+            )
+            .route(
+                "/knowledge-graph/{cid}/sparql",
+                web::post().to(query_knowledge_graph),
+            )
             .route("/query", web::post().to(query_agents))
             .route("/knowledge", web::post().to(add_knowledge))
             .route("/health", web::get().to(health_check)),
@@ -1,62 +1,154 @@
 use crate::errors::AppError;
+use crate::middleware::ucan::RequireCapability;
 use crate::models::auth::AuthUser;
+use crate::models::license::License;
+use crate::models::requests::{validate_no_blank_entries, validate_not_blank};
 use crate::routes::AppState;
+use crate::services::dataverse_service::{
+    BatchFileUpload, DatasetVersion, DEFAULT_FILES_PAGE_SIZE,
+};
+use crate::services::idempotency_service::IdempotencyKey;
+use crate::services::publication_service::PublicationState;
+use crate::services::quota_service::QuotaResource;
+use crate::services::ucan_service::{BioAction, BioResource};
 use actix_multipart::Multipart;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use futures_util::TryStreamExt;
-use log::{error, info};
+use log::{error, info, warn};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::path::PathBuf;
 use tempfile::NamedTempFile;
+use validator::Validate;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct DatasetCreateRequest {
+    #[validate(custom(function = "validate_not_blank"), length(max = 500))]
     pub title: String,
+    #[validate(custom(function = "validate_not_blank"), length(max = 20000))]
     pub description: String,
+    #[validate(
+        length(min = 1, max = 200, message = "must list between 1 and 200 authors"),
+        custom(function = "validate_no_blank_entries")
+    )]
     pub authors: Vec<String>,
+    #[validate(custom(function = "validate_no_blank_entries"), length(max = 100))]
     pub keywords: Vec<String>,
+    // SPDX ID; defaults to DEFAULT_LICENSE, or CC0-1.0 when that is unset
+    pub license: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DatasetCreateResponse {
     pub id: String,
     pub persistent_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct MetadataUpdateRequest {
     pub persistent_id: String,
     pub title: Option<String>,
     pub description: Option<String>,
     pub authors: Option<Vec<String>>,
     pub keywords: Option<Vec<String>>,
+    // SPDX ID; the dataset keeps its current license when omitted
+    pub license: Option<String>,
 }
 
 /// Create a new dataset in Dataverse
+///
+/// Retries carrying the same `Idempotency-Key` header return the original dataset.
 pub async fn create_dataset(
     req: web::Json<DatasetCreateRequest>,
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
+    http_req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
     info!("Creating new dataset: {} for user {}", req.title, user.id);
+    req.validate()?;
+    let license = req.license.as_deref().map(License::parse).transpose()?;
 
-    let dataset = app_state
-        .dataverse_service
-        .create_dataset(&req.title, &req.description, &req.authors, &req.keywords)
-        .await?;
+    let idempotency_key = IdempotencyKey::from_request(&http_req, "create_dataset", &*req)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(response) = app_state.idempotency_service.reserve(user.id, key).await? {
+            return Ok(response);
+        }
+    }
 
-    Ok(HttpResponse::Created().json(DatasetCreateResponse {
+    let created = async {
+        app_state
+            .quota_service
+            .check_and_increment(&user, QuotaResource::Datasets)
+            .await?;
+        let created = app_state
+            .dataverse_service
+            .create_dataset(
+                &req.title,
+                &req.description,
+                &req.authors,
+                &req.keywords,
+                license.as_ref(),
+            )
+            .await;
+        if created.is_err() {
+            app_state
+                .quota_service
+                .refund(user.id, QuotaResource::Datasets)
+                .await;
+        }
+        created
+    }
+    .await;
+    let dataset = match created {
+        Ok(dataset) => dataset,
+        Err(e) => {
+            if let Some(key) = &idempotency_key {
+                app_state.idempotency_service.release(user.id, key).await;
+            }
+            return Err(e);
+        }
+    };
+
+    let response = DatasetCreateResponse {
         id: dataset.id,
         persistent_id: dataset.persistent_id,
-    }))
+    };
+
+    if let Some(key) = &idempotency_key {
+        app_state
+            .idempotency_service
+            .record(
+                user.id,
+                key,
+                StatusCode::CREATED,
+                &response,
+                Some(&response.persistent_id),
+            )
+            .await;
+    }
+
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// File added to a dataset; `bytes_received` lets the client confirm nothing was lost
+#[derive(Serialize, JsonSchema)]
+pub struct DatasetFileUploadResponse {
+    pub file_id: String,
+    pub bytes_received: u64,
+    pub message: String,
 }
 
 /// Upload a file to a dataset
+///
+/// Fails if the multipart stream breaks off, or if the file part's `Content-Length`
+/// header or a `size` field declares a different size than what arrived.
 pub async fn upload_file(
     path: web::Path<String>,
     mut payload: Multipart,
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
 ) -> Result<impl Responder, AppError> {
     let persistent_id = path.into_inner();
     info!(
@@ -66,15 +158,24 @@ pub async fn upload_file(
 
     let mut description = String::new();
     let mut temp_file = None;
+    let mut bytes_received: u64 = 0;
+    // From the file part's `Content-Length` header or a `size` field, if either is sent
+    let mut declared_size: Option<u64> = None;
 
-    // Process multipart form
-    while let Ok(Some(mut field)) = payload.try_next().await {
+    // Process multipart form; a stream that breaks off fails the upload instead of
+    // sending Dataverse whatever arrived before it did
+    while let Some(mut field) = payload.try_next().await.map_err(upload_interrupted)? {
         let content_disposition = field.content_disposition();
         let name = content_disposition
             .and_then(|cd| cd.get_name())
-            .unwrap_or("");
+            .unwrap_or("")
+            .to_string();
 
         if name == "file" {
+            if let Some(length) = field.headers().get(header::CONTENT_LENGTH) {
+                declared_size = Some(parse_declared_size(length.to_str().unwrap_or(""))?);
+            }
+
             // Create temp file
             let mut tmp = NamedTempFile::new().map_err(|e| {
                 error!("Failed to create temp file: {}", e);
@@ -82,18 +183,28 @@ pub async fn upload_file(
             })?;
 
             // Write file content
-            while let Ok(Some(chunk)) = field.try_next().await {
+            while let Some(chunk) = field.try_next().await.map_err(upload_interrupted)? {
                 tmp.write_all(&chunk).map_err(|e| {
                     error!("Failed to write to temp file: {}", e);
                     AppError::FileError(format!("Failed to write file: {}", e))
                 })?;
+                bytes_received += chunk.len() as u64;
+                app_state
+                    .dataverse_service
+                    .check_upload_size(bytes_received)?;
             }
 
             temp_file = Some(tmp);
-        } else if name == "description" {
-            // Read description
-            while let Ok(Some(chunk)) = field.try_next().await {
-                description = String::from_utf8_lossy(&chunk).to_string();
+        } else if name == "description" || name == "size" {
+            let mut value = Vec::new();
+            while let Some(chunk) = field.try_next().await.map_err(upload_interrupted)? {
+                value.extend_from_slice(&chunk);
+            }
+            let value = String::from_utf8_lossy(&value).to_string();
+            if name == "size" {
+                declared_size = Some(parse_declared_size(&value)?);
+            } else {
+                description = value;
             }
         }
     }
@@ -103,36 +214,166 @@ pub async fn upload_file(
         Some(f) => f,
         None => return Err(AppError::ValidationError("No file provided".to_string())),
     };
+    if let Some(declared) = declared_size.filter(|&declared| declared != bytes_received) {
+        warn!(
+            "Upload to {} declared {} bytes but {} arrived",
+            persistent_id, declared, bytes_received
+        );
+        return Err(AppError::FileError(format!(
+            "Upload incomplete: {} bytes declared, {} received",
+            declared, bytes_received
+        )));
+    }
 
     // Upload the file to Dataverse
     let file_id = app_state
         .dataverse_service
-        .upload_file(&persistent_id, tmp.path(), &description)
+        .upload_dataset_file(&persistent_id, tmp.path(), &description)
         .await?;
 
-    #[derive(Serialize)]
-    struct FileResponse {
-        file_id: String,
-        message: String,
-    }
-
-    Ok(HttpResponse::Ok().json(FileResponse {
+    Ok(HttpResponse::Ok().json(DatasetFileUploadResponse {
         file_id,
+        bytes_received,
         message: "File uploaded successfully".to_string(),
     }))
 }
 
+/// Files added to a dataset by a batch upload
+#[derive(Serialize, JsonSchema)]
+pub struct DatasetFilesUploadResponse {
+    pub uploaded: usize,
+    pub failed: usize,
+    // One per `file` part, in the order they were sent
+    pub files: Vec<BatchFileUpload>,
+}
+
+/// Upload several files to a dataset
+///
+/// Each `file` part may be followed by a `description` part for it, and is stored under
+/// its part's file name. Files Dataverse rejects are reported in `files`; the request only
+/// fails if the stream breaks off or the files together exceed the upload limit.
+pub async fn upload_files(
+    path: web::Path<String>,
+    mut payload: Multipart,
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+) -> Result<impl Responder, AppError> {
+    let persistent_id = path.into_inner();
+    info!(
+        "Uploading files to dataset: {} for user {}",
+        persistent_id, user.id
+    );
+
+    let dir = tempfile::tempdir().map_err(|e| {
+        error!("Failed to create temp dir: {}", e);
+        AppError::FileError(format!("Failed to create temp dir: {}", e))
+    })?;
+    let mut files: Vec<(PathBuf, String)> = Vec::new();
+    // Across all files, so the limit applies to the batch
+    let mut bytes_received: u64 = 0;
+
+    while let Some(mut field) = payload.try_next().await.map_err(upload_interrupted)? {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition
+            .and_then(|cd| cd.get_name())
+            .unwrap_or("")
+            .to_string();
+        let file_name = content_disposition
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string);
+
+        if name == "file" {
+            // Each file gets its own directory, so files sent under the same name don't
+            // overwrite each other
+            let file_dir = dir.path().join(files.len().to_string());
+            let file_path = file_dir.join(upload_file_name(file_name.as_deref(), files.len()));
+            let mut file = std::fs::create_dir(&file_dir)
+                .and_then(|_| std::fs::File::create(&file_path))
+                .map_err(|e| {
+                    error!("Failed to create temp file: {}", e);
+                    AppError::FileError(format!("Failed to create temp file: {}", e))
+                })?;
+
+            while let Some(chunk) = field.try_next().await.map_err(upload_interrupted)? {
+                bytes_received += chunk.len() as u64;
+                app_state
+                    .dataverse_service
+                    .check_upload_size(bytes_received)?;
+                file.write_all(&chunk).map_err(|e| {
+                    error!("Failed to write to temp file: {}", e);
+                    AppError::FileError(format!("Failed to write file: {}", e))
+                })?;
+            }
+
+            files.push((file_path, String::new()));
+        } else if name == "description" {
+            let mut value = Vec::new();
+            while let Some(chunk) = field.try_next().await.map_err(upload_interrupted)? {
+                value.extend_from_slice(&chunk);
+            }
+            let Some((_, description)) = files.last_mut() else {
+                return Err(AppError::ValidationError(
+                    "A description must follow the file it describes".to_string(),
+                ));
+            };
+            *description = String::from_utf8_lossy(&value).to_string();
+        }
+    }
+
+    if files.is_empty() {
+        return Err(AppError::ValidationError("No file provided".to_string()));
+    }
+
+    let results = app_state
+        .dataverse_service
+        .upload_files(&persistent_id, files)
+        .await?;
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+
+    Ok(HttpResponse::Ok().json(DatasetFilesUploadResponse {
+        uploaded: results.len() - failed,
+        failed,
+        files: results,
+    }))
+}
+
+/// Name to store an uploaded file under: the client's file name without any directories,
+/// or a numbered name when it sent none
+fn upload_file_name(file_name: Option<&str>, index: usize) -> String {
+    file_name
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("file-{}", index + 1))
+}
+
+fn upload_interrupted(e: actix_multipart::MultipartError) -> AppError {
+    error!("Multipart upload interrupted: {}", e);
+    AppError::FileError(format!("Upload interrupted: {}", e))
+}
+
+fn parse_declared_size(value: &str) -> Result<u64, AppError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| AppError::ValidationError(format!("Invalid declared size: {}", value)))
+}
+
 /// Update dataset metadata
 pub async fn update_metadata(
     req: web::Json<MetadataUpdateRequest>,
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
 ) -> Result<impl Responder, AppError> {
     info!(
         "Updating metadata for dataset: {} for user {}",
         req.persistent_id, user.id
     );
 
+    let license = req.license.as_deref().map(License::parse).transpose()?;
     app_state
         .dataverse_service
         .update_metadata(
@@ -141,6 +382,7 @@ pub async fn update_metadata(
             req.description.as_deref(),
             req.authors.as_ref().map(|v| &v[..]),
             req.keywords.as_ref().map(|v| &v[..]),
+            license.as_ref(),
         )
         .await?;
 
@@ -157,21 +399,30 @@ pub async fn update_metadata(
 }
 
 /// Request to publish a dataset in Dataverse
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct PublishDatasetRequest {
     pub persistent_id: String,
+    // Submit the dataset to a curator instead, for accounts that may not publish it
+    #[serde(default)]
+    pub submit_for_review: bool,
 }
 
-/// Response for Dataverse operations
-#[derive(Serialize)]
-pub struct DataverseResponse {
+/// Accepted publication, followed by a `dataverse_publish` task
+#[derive(Serialize, JsonSchema)]
+pub struct PublishDatasetResponse {
     pub persistent_id: String,
-    pub message: String,
+    pub task_id: String,
+    // `publishing` or `in_review`
+    pub state: PublicationState,
 }
 
-/// Publish a dataset in Dataverse
+/// Publish a dataset in Dataverse, or submit it for review
+///
+/// Dataverse may take a while to finalize the release and can assign a new persistent ID,
+/// and a curator may reject a reviewed dataset. The returned task follows the publication
+/// until then, relinking DIDs if the persistent ID changed.
 pub async fn publish_dataset(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
     request: web::Json<PublishDatasetRequest>,
 ) -> Result<impl Responder, AppError> {
@@ -180,17 +431,35 @@ pub async fn publish_dataset(
         request.persistent_id, user.id
     );
 
-    // Publish the dataset
-    app_state
-        .dataverse_service
-        .publish_dataset(&request.persistent_id)
+    let (task_id, publication) = app_state
+        .publication_service
+        .start(&request.persistent_id, request.submit_for_review, user.id)
         .await?;
 
-    info!("Dataset published in Dataverse: {}", request.persistent_id);
+    info!(
+        "Dataset {} publication started, followed by task {}",
+        request.persistent_id, task_id
+    );
+
+    app_state
+        .audit_service
+        .record_audit(
+            user.id,
+            if request.submit_for_review {
+                "dataset.submit_for_review"
+            } else {
+                "dataset.publish"
+            },
+            "dataset",
+            &request.persistent_id,
+            serde_json::json!({ "task_id": task_id }),
+        )
+        .await;
 
-    Ok(HttpResponse::Ok().json(DataverseResponse {
+    Ok(HttpResponse::Accepted().json(PublishDatasetResponse {
         persistent_id: request.persistent_id.clone(),
-        message: "Dataset published successfully".to_string(),
+        task_id,
+        state: publication.state,
     }))
 }
 
@@ -212,17 +481,116 @@ pub async fn get_dataset_metadata(
     Ok(HttpResponse::Ok().json(metadata))
 }
 
+/// Which version of a dataset to list and which page of its files
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DatasetFilesQuery {
+    // "latest" (default), "draft" or "published"
+    pub version: Option<String>,
+    // 1-based page number
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+/// List the files in a dataset
+///
+/// `?version=draft` or `?version=published` picks the version; by default the draft is
+/// listed when there is one.
+pub async fn list_dataset_files(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<DatasetFilesQuery>,
+) -> Result<impl Responder, AppError> {
+    let persistent_id = path.into_inner();
+    info!(
+        "Listing files of dataset: {} for user {}",
+        persistent_id, user.id
+    );
+
+    let version = query
+        .version
+        .as_deref()
+        .map(DatasetVersion::parse)
+        .transpose()?
+        .unwrap_or(DatasetVersion::Latest);
+
+    let files = app_state
+        .dataverse_service
+        .list_files(
+            &persistent_id,
+            version,
+            query.page.unwrap_or(1),
+            query.per_page.unwrap_or(DEFAULT_FILES_PAGE_SIZE),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(files))
+}
+
 /// Initialize Dataverse routes
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/dataverse")
-            .route("/dataset", web::post().to(create_dataset))
-            .route("/dataset/file/{persistent_id}", web::post().to(upload_file))
-            .route("/dataset/metadata", web::put().to(update_metadata))
-            .route("/dataset/publish", web::post().to(publish_dataset))
+            .route(
+                "/dataset",
+                web::post().to(create_dataset).wrap(RequireCapability::new(
+                    BioResource::Dataset,
+                    BioAction::Create,
+                )),
+            )
+            .route(
+                "/dataset/file/{persistent_id}",
+                web::post().to(upload_file).wrap(
+                    RequireCapability::new(BioResource::Dataset, BioAction::Upload)
+                        .on_path("persistent_id"),
+                ),
+            )
+            .route(
+                "/dataset/files/{persistent_id}",
+                web::post().to(upload_files).wrap(
+                    RequireCapability::new(BioResource::Dataset, BioAction::Upload)
+                        .on_path("persistent_id"),
+                ),
+            )
+            .route(
+                "/dataset/metadata",
+                web::put().to(update_metadata).wrap(
+                    RequireCapability::new(BioResource::Dataset, BioAction::Update)
+                        .on_body_field("persistent_id"),
+                ),
+            )
+            .route(
+                "/dataset/publish",
+                web::post().to(publish_dataset).wrap(
+                    RequireCapability::new(BioResource::Dataset, BioAction::Publish)
+                        .on_body_field("persistent_id"),
+                ),
+            )
             .route(
                 "/dataset/{persistent_id}",
                 web::get().to(get_dataset_metadata),
+            )
+            .route(
+                "/dataset/{persistent_id}/files",
+                web::get().to(list_dataset_files),
             ),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uploaded_files_keep_only_their_base_name() {
+        assert_eq!(upload_file_name(Some("spectra.csv"), 0), "spectra.csv");
+        assert_eq!(upload_file_name(Some("../../etc/passwd"), 0), "passwd");
+        assert_eq!(
+            upload_file_name(Some("C:\\data\\run 1.fastq"), 0),
+            "run 1.fastq"
+        );
+        assert_eq!(upload_file_name(Some(".."), 2), "file-3");
+        assert_eq!(upload_file_name(Some("data/"), 0), "file-1");
+        assert_eq!(upload_file_name(None, 4), "file-5");
+    }
+}
@@ -1,52 +1,304 @@
-use actix_web::{web, HttpResponse, Responder};
-use log::info;
-use serde::Deserialize;
+use actix_web::http::header::{self, Header, HttpDate, CACHE_CONTROL};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use validator::Validate;
 
-use crate::errors::AppError;
+use crate::errors::{AppError, ErrorResponse};
+use crate::middleware::ucan::RequireCapability;
 use crate::models::auth::AuthUser;
-use crate::models::did::{DIDCreationRequest, DIDUpdateRequest};
+use crate::models::credential::{IssueCredentialRequest, VerifiableCredential};
+use crate::models::did::{
+    migrate_document, AttachFileRequest, DIDCloneRequest, DIDCreationRequest, DIDTransferRequest,
+    DIDUpdateRequest, DidModification, DidRegistryCursor, DidRegistryEntry, DID_JSON_MEDIA_TYPE,
+    DID_LD_JSON_MEDIA_TYPE,
+};
+use crate::models::did_template::{DIDFromTemplateRequest, DIDTemplateRequest};
+use crate::models::did_validation;
+use crate::routes::totp::step_up_code;
 use crate::routes::AppState;
+use crate::services::dataverse_service::DataverseService;
+use crate::services::did_service::{DIDService, MAX_REGISTRY_PAGE_SIZE};
+use crate::services::idempotency_service::IdempotencyKey;
+use crate::services::step_up_service::StepUpOperation;
+use crate::services::ucan_service::{BioAction, BioResource};
+
+/// DIDs per registry page unless `limit` says otherwise
+const DEFAULT_REGISTRY_PAGE_SIZE: u32 = 100;
+
+/// Seconds DID resolution responses may be cached; resolvers then revalidate with
+/// `If-Modified-Since`
+const RESOLUTION_MAX_AGE_SECS: u64 = 60;
+
+/// Resolution responses differ for the owner of an embargoed DID
+const VARY_AUTHORIZATION: (header::HeaderName, &str) = (header::VARY, "Authorization");
+
+/// Document responses are negotiated on `Accept`
+const VARY_ACCEPT: (header::HeaderName, &str) = (header::VARY, "Accept");
+
+/// `Last-Modified` and `Cache-Control` of a DID resolution response
+pub(crate) struct ResolutionCaching {
+    last_modified: DateTime<Utc>,
+    cache_control: String,
+}
+
+impl ResolutionCaching {
+    /// `private` responses depend on who asked, e.g. an owner seeing past an embargo,
+    /// and mustn't be stored by shared caches
+    pub(crate) fn new(modification: &DidModification, private: bool) -> Self {
+        let now = Utc::now();
+        let max_age = modification.max_age(now, RESOLUTION_MAX_AGE_SECS);
+        Self {
+            last_modified: modification.last_modified(now),
+            cache_control: format!(
+                "{}, max-age={}",
+                if private { "private" } else { "public" },
+                max_age
+            ),
+        }
+    }
+
+    /// Whether the request's `If-Modified-Since` shows the client already has the current
+    /// document; HTTP dates have whole seconds, so sub-second changes don't count
+    pub(crate) fn is_fresh(&self, req: &HttpRequest) -> bool {
+        match header::IfModifiedSince::parse(req) {
+            Ok(header::IfModifiedSince(since)) => {
+                self.last_modified.timestamp()
+                    <= DateTime::<Utc>::from(SystemTime::from(since)).timestamp()
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn apply<'a>(
+        &self,
+        builder: &'a mut HttpResponseBuilder,
+    ) -> &'a mut HttpResponseBuilder {
+        builder
+            .insert_header(header::LastModified(HttpDate::from(SystemTime::from(
+                self.last_modified,
+            ))))
+            .insert_header((CACHE_CONTROL, self.cache_control.as_str()))
+    }
+}
+
+/// Representation of a DID document, as negotiated with the request's `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DocumentMediaType {
+    // `application/did+ld+json`: the document with its JSON-LD `@context`
+    JsonLd,
+    // `application/did+json`: plain JSON, without `@context`
+    Json,
+}
+
+impl DocumentMediaType {
+    /// Most preferred representation the request accepts. A missing or unparsable `Accept`
+    /// header and generic JSON types get JSON-LD; `None` means neither is acceptable.
+    pub(crate) fn negotiate(req: &HttpRequest) -> Option<Self> {
+        let Ok(accept) = header::Accept::parse(req) else {
+            return Some(Self::JsonLd);
+        };
+        if accept.is_empty() {
+            return Some(Self::JsonLd);
+        }
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match mime.essence_str() {
+                DID_JSON_MEDIA_TYPE => Some(Self::Json),
+                DID_LD_JSON_MEDIA_TYPE
+                | "application/ld+json"
+                | "application/json"
+                | "application/*"
+                | "*/*" => Some(Self::JsonLd),
+                _ => None,
+            })
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::JsonLd => DID_LD_JSON_MEDIA_TYPE,
+            Self::Json => DID_JSON_MEDIA_TYPE,
+        }
+    }
+
+    /// `document` in this representation: DID Core's plain JSON has no `@context`, which
+    /// only means something to JSON-LD processors
+    pub(crate) fn render(self, mut document: serde_json::Value) -> serde_json::Value {
+        if self == Self::Json {
+            if let Some(document) = document.as_object_mut() {
+                document.remove("@context");
+            }
+        }
+        document
+    }
+
+    fn not_acceptable() -> HttpResponse {
+        HttpResponse::NotAcceptable().json(ErrorResponse::new(
+            StatusCode::NOT_ACCEPTABLE,
+            format!(
+                "DID documents are available as {} or {}",
+                DID_LD_JSON_MEDIA_TYPE, DID_JSON_MEDIA_TYPE
+            ),
+        ))
+    }
+}
 
 /// Request to link a DID to a Dataverse dataset
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct LinkToDataverseRequest {
     pub dataverse_doi: String,
 }
 
+/// Query parameters for linking a DID to Dataverse
+#[derive(Deserialize, JsonSchema)]
+pub struct LinkToDataverseQuery {
+    // Set to false to skip checking that the dataset exists, e.g. when Dataverse is offline
+    pub verify: Option<bool>,
+}
+
+/// Generate an Ed25519 keypair for a client that can't create its own.
+///
+/// The private key is in this response only; it is not stored and never logged, so the
+/// response is marked uncacheable.
+pub async fn generate_keypair(user: AuthUser) -> impl Responder {
+    info!("Generating DID keypair for user {}", user.id);
+
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(DIDService::generate_keypair())
+}
+
 /// Create a new DID
+///
+/// Retries carrying the same `Idempotency-Key` header return the original document.
 pub async fn create_did(
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
+    http_req: HttpRequest,
     req: web::Json<DIDCreationRequest>,
 ) -> Result<impl Responder, AppError> {
     info!("Creating new DID for user {}", user.id);
+    req.validate()?;
 
-    let did_doc = app_state
+    let idempotency_key = IdempotencyKey::from_request(&http_req, "create_did", &*req)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(response) = app_state.idempotency_service.reserve(user.id, key).await? {
+            return Ok(response);
+        }
+    }
+
+    let did_doc = match app_state
         .did_service
         .create_did(req.into_inner(), user.id)
-        .await?;
+        .await
+    {
+        Ok(did_doc) => did_doc,
+        Err(e) => {
+            if let Some(key) = &idempotency_key {
+                app_state.idempotency_service.release(user.id, key).await;
+            }
+            return Err(e);
+        }
+    };
+
+    if let Some(key) = &idempotency_key {
+        app_state
+            .idempotency_service
+            .record(
+                user.id,
+                key,
+                StatusCode::CREATED,
+                &did_doc,
+                Some(&did_doc.id),
+            )
+            .await;
+    }
 
     Ok(HttpResponse::Created().json(did_doc))
 }
 
+/// Import a complete DID document from a backup or another instance under its existing
+/// `did:bio` ID
+///
+/// Documents written with an older schema version are upgraded first. The DID must not
+/// already exist here.
+pub async fn import_did(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    req: web::Json<serde_json::Value>,
+) -> Result<impl Responder, AppError> {
+    let document = req.into_inner();
+    let problems = did_validation::validate_document(&document);
+    if !problems.is_empty() {
+        return Err(AppError::ValidationError(problems.join("; ")));
+    }
+    let document = migrate_document(document)?;
+    info!("Importing DID {} for user {}", document.id, user.id);
+
+    let did_doc = app_state.did_service.import_did(document, user.id).await?;
+
+    Ok(HttpResponse::Created().json(did_doc))
+}
+
+/// Query parameters for retrieving a DID document
+#[derive(Deserialize, JsonSchema)]
+pub struct GetDidQuery {
+    // CID of an earlier version to return instead of the current document
+    pub cid: Option<String>,
+}
+
 /// Get a DID document by its identifier
+///
+/// With `?cid=` the document stored at that CID is returned, provided it is a version of
+/// this DID. While the DID is embargoed, callers other than its owner get it stripped.
+/// `Accept` selects `application/did+ld+json` (the default) or `application/did+json`.
 pub async fn get_did(
     app_state: web::Data<AppState>,
+    req: HttpRequest,
+    user: Option<AuthUser>,
     path: web::Path<String>,
+    query: web::Query<GetDidQuery>,
 ) -> Result<impl Responder, AppError> {
     let did_id = path.into_inner();
+    let Some(media_type) = DocumentMediaType::negotiate(&req) else {
+        return Ok(DocumentMediaType::not_acceptable());
+    };
 
-    info!("Retrieving DID document: {}", did_id);
-
-    let did_document = app_state.did_service.get_did(&did_id).await?;
+    let did_document = match &query.cid {
+        Some(cid) => {
+            info!("Retrieving DID document {} at CID {}", did_id, cid);
+            app_state.did_service.get_did_at_cid(&did_id, cid).await?
+        }
+        None => {
+            info!("Retrieving DID document: {}", did_id);
+            app_state.did_service.get_did(&did_id).await?
+        }
+    };
+    let requester = user.map(|user| user.id);
+    let did_document = app_state
+        .did_service
+        .apply_embargo(did_document, requester)
+        .await?;
+    let did_document = app_state
+        .did_service
+        .present(&did_document, &did_id, "/metadata", requester)
+        .await?;
 
-    Ok(HttpResponse::Ok().json(did_document))
+    Ok(HttpResponse::Ok()
+        .content_type(media_type.as_str())
+        .append_header(VARY_ACCEPT)
+        .json(media_type.render(did_document)))
 }
 
 /// Update a DID Document
 pub async fn update_did(
     app_state: web::Data<AppState>,
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     path: web::Path<String>,
     req: web::Json<DIDUpdateRequest>,
 ) -> Result<impl Responder, AppError> {
@@ -61,11 +313,33 @@ pub async fn update_did(
     Ok(HttpResponse::Ok().json(did_doc))
 }
 
+/// Attach a file or other content already on IPFS to a DID as a service
+pub async fn attach_file(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<AttachFileRequest>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    req.validate()?;
+    info!("User {} attaching {} to DID: {}", user.id, req.cid, did);
+
+    let did_doc = app_state
+        .did_service
+        .attach_file(&did, req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(did_doc))
+}
+
 /// Link a DID to a Dataverse dataset
+///
+/// The DOI must resolve to an existing dataset unless `?verify=false` is passed.
 pub async fn link_to_dataverse(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
     path: web::Path<String>,
+    query: web::Query<LinkToDataverseQuery>,
     request: web::Json<LinkToDataverseRequest>,
 ) -> Result<impl Responder, AppError> {
     let did_id = path.into_inner();
@@ -75,39 +349,505 @@ pub async fn link_to_dataverse(
         did_id, request.dataverse_doi
     );
 
+    let dataset_title = if query.verify.unwrap_or(true) {
+        let metadata = app_state
+            .dataverse_service
+            .get_dataset_metadata(&request.dataverse_doi)
+            .await?;
+        DataverseService::dataset_title(&metadata)
+    } else {
+        info!(
+            "Skipping Dataverse existence check for DOI: {}",
+            request.dataverse_doi
+        );
+        None
+    };
+
     app_state
         .did_service
-        .link_to_dataverse(&did_id, &request.dataverse_doi, user.id)
+        .link_to_dataverse(
+            &did_id,
+            &request.dataverse_doi,
+            dataset_title.as_deref(),
+            user.id,
+        )
         .await?;
 
+    // Dataverse embargoes files, so the dataset's files inherit the DID's embargo where the
+    // installation supports it; the link stands either way
+    let embargo_until = app_state
+        .did_service
+        .get_did(&did_id)
+        .await?
+        .embargo_until()
+        .filter(|until| *until > Utc::now());
+    let embargo_applied = match embargo_until {
+        Some(until) => match app_state
+            .dataverse_service
+            .set_embargo(&request.dataverse_doi, until)
+            .await
+        {
+            Ok(_) => Some(true),
+            Err(e) => {
+                warn!(
+                    "Could not embargo files of {} in Dataverse: {}",
+                    request.dataverse_doi, e
+                );
+                Some(false)
+            }
+        },
+        None => None,
+    };
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "DID successfully linked to Dataverse dataset",
         "did": did_id,
-        "dataverse_doi": request.dataverse_doi
+        "dataverse_doi": request.dataverse_doi,
+        "dataset_title": dataset_title,
+        "embargo_applied": embargo_applied
     })))
 }
 
 /// Resolve a DID to its DID Document
+///
+/// Responses carry `Last-Modified` and `Cache-Control`; a request whose `If-Modified-Since`
+/// is no earlier than the last change gets `304 Not Modified` without the document.
+/// `Accept` selects the representation as for `get_did`.
 pub async fn resolve_did(
     app_state: web::Data<AppState>,
+    req: HttpRequest,
+    user: Option<AuthUser>,
     path: web::Path<String>,
 ) -> Result<impl Responder, AppError> {
     let did = path.into_inner();
     info!("Resolving DID: {}", did);
+    let Some(media_type) = DocumentMediaType::negotiate(&req) else {
+        return Ok(DocumentMediaType::not_acceptable());
+    };
+
+    // Checked before IPFS is touched, so polling resolvers cost one database lookup
+    let modification = app_state.did_service.get_modification(&did).await?;
+    let caching = ResolutionCaching::new(&modification, user.is_some());
+    if caching.is_fresh(&req) {
+        return Ok(caching
+            .apply(&mut HttpResponse::NotModified())
+            .append_header(VARY_AUTHORIZATION)
+            .append_header(VARY_ACCEPT)
+            .finish());
+    }
 
     let did_doc = app_state.did_service.resolve_did(&did).await?;
+    let requester = user.map(|user| user.id);
+    let did_doc = app_state
+        .did_service
+        .apply_embargo(did_doc, requester)
+        .await?;
+    let did_doc = app_state
+        .did_service
+        .present(&did_doc, &did, "/metadata", requester)
+        .await?;
 
-    Ok(HttpResponse::Ok().json(did_doc))
+    Ok(caching
+        .apply(&mut HttpResponse::Ok())
+        .append_header(VARY_AUTHORIZATION)
+        .append_header(VARY_ACCEPT)
+        .content_type(media_type.as_str())
+        .json(media_type.render(did_doc)))
+}
+
+/// Issue a Verifiable Credential signed by one of the DID's assertion methods
+///
+/// The response is marked uncacheable since the request carried the signing key.
+pub async fn issue_credential(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<IssueCredentialRequest>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    req.validate()?;
+    info!("User {} issuing a credential from {}", user.id, did);
+
+    let req = req.into_inner();
+    let credential = app_state
+        .did_service
+        .issue_credential(&did, &req.subject, req.claims, &req.secret_key)
+        .await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(credential))
+}
+
+/// Check a DID document created elsewhere before importing it
+///
+/// Reports every structural problem found, e.g. a missing DID Core context, a key that
+/// doesn't decode or a service endpoint that isn't a URL. Nothing is stored.
+pub async fn validate_did_document(
+    app_state: web::Data<AppState>,
+    req: web::Json<serde_json::Value>,
+) -> Result<impl Responder, AppError> {
+    let validation = app_state.did_service.validate_document(&req);
+    info!(
+        "Validated DID document {}: {} problems",
+        req.get("id")
+            .and_then(|id| id.as_str())
+            .unwrap_or("without id"),
+        validation.errors.len()
+    );
+
+    Ok(HttpResponse::Ok().json(validation))
+}
+
+/// Verify a credential's proof against its issuer's DID document
+pub async fn verify_credential(
+    app_state: web::Data<AppState>,
+    req: web::Json<VerifiableCredential>,
+) -> Result<impl Responder, AppError> {
+    info!("Verifying credential {} from {}", req.id, req.issuer);
+
+    let verification = app_state.did_service.verify_credential(&req).await?;
+
+    Ok(HttpResponse::Ok().json(verification))
+}
+
+/// Get only the biological metadata of a DID's current document
+pub async fn get_did_metadata(
+    app_state: web::Data<AppState>,
+    user: Option<AuthUser>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    info!("Retrieving metadata of DID: {}", did);
+
+    let requester = user.map(|user| user.id);
+    let metadata = app_state.did_service.get_metadata(&did, requester).await?;
+    let metadata = app_state
+        .did_service
+        .present(&metadata, &did, "", requester)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(metadata))
+}
+
+/// Get the SHA-256 hash of a DID document's canonical form
+pub async fn get_document_hash(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    info!("Hashing DID document: {}", did);
+
+    let hash = app_state.did_service.document_hash(&did).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "did": did,
+        "algorithm": "sha-256",
+        "hash": hash
+    })))
+}
+
+/// The DID's provenance chain: its document, research paper, Dataverse dataset and
+/// knowledge graph, each with whether it can still be found
+pub async fn get_did_provenance(
+    app_state: web::Data<AppState>,
+    user: Option<AuthUser>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    info!("Checking provenance of DID: {}", did);
+
+    let requester = user.map(|user| user.id);
+    let provenance = app_state
+        .provenance_service
+        .get_provenance(&did, requester)
+        .await?;
+    let provenance = app_state
+        .did_service
+        .present(&provenance, &did, "/document/document/metadata", requester)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(provenance))
+}
+
+/// List the DIDs whose current documents reference a DID in their related identifiers
+pub async fn get_did_backlinks(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    info!("Listing backlinks of DID: {}", did);
+
+    let backlinks = app_state.did_service.find_referencing(&did).await?;
+
+    Ok(HttpResponse::Ok().json(backlinks))
+}
+
+/// Filters and paging of the DID registry, passed as query parameters
+#[derive(Deserialize, JsonSchema)]
+pub struct DidRegistryQuery {
+    pub data_type: Option<String>,
+    // RFC 3339; DIDs created at or after this time
+    pub created_after: Option<DateTime<Utc>>,
+    // RFC 3339; DIDs created before this time
+    pub created_before: Option<DateTime<Utc>>,
+    // `next_cursor` from a previous page
+    pub cursor: Option<String>,
+    // Page size, 100 by default
+    pub limit: Option<u32>,
+}
+
+/// One page of the DID registry, oldest first
+#[derive(Serialize, JsonSchema)]
+pub struct DidRegistryPage {
+    pub dids: Vec<DidRegistryEntry>,
+    // Pass as `cursor` to fetch the next page; absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// List every `did:bio` DID with its title, data type and creation time, for registries,
+/// aggregators and crawlers
+pub async fn list_registry(
+    app_state: web::Data<AppState>,
+    query: web::Query<DidRegistryQuery>,
+) -> Result<impl Responder, AppError> {
+    if query.limit == Some(0) || query.limit > Some(MAX_REGISTRY_PAGE_SIZE) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_REGISTRY_PAGE_SIZE
+        )));
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            DidRegistryCursor::decode(cursor)
+                .ok_or_else(|| AppError::ValidationError("Invalid cursor".to_string()))
+        })
+        .transpose()?;
+
+    let (dids, next_cursor) = app_state
+        .did_service
+        .list_registry(
+            query.data_type.as_deref(),
+            query.created_after,
+            query.created_before,
+            cursor.as_ref(),
+            query.limit.unwrap_or(DEFAULT_REGISTRY_PAGE_SIZE),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(DidRegistryPage {
+        dids,
+        next_cursor: next_cursor.map(|cursor| cursor.encode()),
+    }))
+}
+
+/// Create a reusable DID metadata template
+pub async fn create_template(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    req: web::Json<DIDTemplateRequest>,
+) -> Result<impl Responder, AppError> {
+    info!("User {} creating DID template", user.id);
+
+    let template = app_state
+        .did_service
+        .create_template(req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Created().json(template))
+}
+
+/// List the current user's DID templates
+pub async fn list_templates(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+) -> Result<impl Responder, AppError> {
+    let templates = app_state.did_service.list_templates(user.id).await?;
+
+    Ok(HttpResponse::Ok().json(templates))
+}
+
+/// Get a DID template
+pub async fn get_template(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let template = app_state
+        .did_service
+        .get_template(&path.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(template))
+}
+
+/// Replace a DID template
+pub async fn update_template(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<DIDTemplateRequest>,
+) -> Result<impl Responder, AppError> {
+    let template_id = path.into_inner();
+    info!("User {} updating DID template {}", user.id, template_id);
+
+    let template = app_state
+        .did_service
+        .update_template(&template_id, req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(template))
+}
+
+/// Delete a DID template
+pub async fn delete_template(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let template_id = path.into_inner();
+    info!("User {} deleting DID template {}", user.id, template_id);
+
+    app_state
+        .did_service
+        .delete_template(&template_id, user.id)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Create a new DID from a template, merging per-DID metadata overrides
+pub async fn create_did_from_template(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<DIDFromTemplateRequest>,
+) -> Result<impl Responder, AppError> {
+    let did_doc = app_state
+        .did_service
+        .create_from_template(&path.into_inner(), req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Created().json(did_doc))
+}
+
+/// Create a new DID for a dataset derived from an existing one, owned by the caller
+pub async fn clone_did(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<DIDCloneRequest>,
+) -> Result<impl Responder, AppError> {
+    let source_did = path.into_inner();
+    info!("User {} cloning DID {}", user.id, source_did);
+    req.validate()?;
+
+    let did_doc = app_state
+        .did_service
+        .clone_did(&source_did, req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Created().json(did_doc))
+}
+
+/// Transfer a DID and its research papers to another user; the owner or an admin only
+pub async fn transfer_did(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+    req: web::Json<DIDTransferRequest>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    req.validate()?;
+    app_state
+        .step_up_service
+        .require(
+            &user,
+            StepUpOperation::TransferOwnership,
+            step_up_code(&http_req),
+        )
+        .await?;
+    info!(
+        "User {} transferring DID {} to user {}",
+        user.id, did, req.to_user_id
+    );
+
+    let transfer = app_state
+        .did_service
+        .transfer_ownership(&did, req.into_inner(), user.id, user.is_admin())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(transfer))
 }
 
 /// Initialize DID routes
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/did")
-            .route("", web::post().to(create_did))
+            .route(
+                "",
+                web::post()
+                    .to(create_did)
+                    .wrap(RequireCapability::new(BioResource::DID, BioAction::Create)),
+            )
+            .route(
+                "/import",
+                web::post()
+                    .to(import_did)
+                    .wrap(RequireCapability::new(BioResource::DID, BioAction::Create)),
+            )
+            .route("/keygen", web::post().to(generate_keypair))
+            .route("/templates", web::post().to(create_template))
+            .route("/templates", web::get().to(list_templates))
+            .route("/templates/{template_id}", web::get().to(get_template))
+            .route("/templates/{template_id}", web::put().to(update_template))
+            .route(
+                "/templates/{template_id}",
+                web::delete().to(delete_template),
+            )
+            .route(
+                "/templates/{template_id}/did",
+                web::post()
+                    .to(create_did_from_template)
+                    .wrap(RequireCapability::new(BioResource::DID, BioAction::Create)),
+            )
+            .route("/credentials/verify", web::post().to(verify_credential))
+            .route("/validate", web::post().to(validate_did_document))
+            .route("/registry", web::get().to(list_registry))
             .route("/{did}", web::get().to(get_did))
-            .route("/{did}", web::put().to(update_did))
-            .route("/{did}/dataverse", web::post().to(link_to_dataverse))
+            .route(
+                "/{did}",
+                web::put().to(update_did).wrap(
+                    RequireCapability::new(BioResource::DID, BioAction::Update).on_path("did"),
+                ),
+            )
+            .route(
+                "/{did}/dataverse",
+                web::post().to(link_to_dataverse).wrap(
+                    RequireCapability::new(BioResource::DID, BioAction::Update).on_path("did"),
+                ),
+            )
+            .route(
+                "/{did}/attach",
+                web::post().to(attach_file).wrap(
+                    RequireCapability::new(BioResource::DID, BioAction::Update).on_path("did"),
+                ),
+            )
+            .route(
+                "/{did}/clone",
+                web::post()
+                    .to(clone_did)
+                    .wrap(RequireCapability::new(BioResource::DID, BioAction::Create)),
+            )
+            .route("/{did}/transfer", web::post().to(transfer_did))
+            .route("/{did}/credentials", web::post().to(issue_credential))
+            .route("/{did}/metadata", web::get().to(get_did_metadata))
+            .route("/{did}/hash", web::get().to(get_document_hash))
+            .route("/{did}/backlinks", web::get().to(get_did_backlinks))
+            .route("/{did}/provenance", web::get().to(get_did_provenance))
             .route("/resolve/{did}", web::get().to(resolve_did)),
     );
 }
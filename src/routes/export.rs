@@ -0,0 +1,50 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use log::{error, info};
+use tokio_util::io::ReaderStream;
+
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::routes::AppState;
+
+/// Bytes buffered between the archive writer and the response
+const EXPORT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Download all of the caller's DID documents and paper metadata as a ZIP archive.
+///
+/// The archive is streamed while it is built; `manifest.json` lists every DID with its IPFS
+/// CIDs so the content can be fetched independently of this service.
+pub async fn export_archive(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let user_id = user.id;
+    info!("Exporting records for user {}", user_id);
+
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER_BYTES);
+    let export_service = app_state.export_service.clone();
+    tokio::task::spawn_local(async move {
+        // Dropping the writer on failure truncates the archive, which clients detect
+        if let Err(e) = export_service.write_user_archive(user_id, writer).await {
+            error!("Export for user {} failed: {}", user_id, e);
+        }
+    });
+
+    let filename = format!(
+        "bio-did-seq-export-{}.zip",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .streaming(ReaderStream::new(reader)))
+}
+
+/// Initialize export routes
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/export", web::get().to(export_archive));
+}
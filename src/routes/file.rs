@@ -1,24 +1,44 @@
 use crate::{
-    database, errors::ServiceError, models::requests::*, services::ipfs_service::IPFSService,
+    database,
+    errors::{AppError, ServiceError},
+    models::{auth::AuthUser, file_metadata::FileAcl, requests::*},
+    services::ipfs_service::IPFSService,
 };
 use actix_multipart::Multipart;
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, ContentDisposition, Header, Range};
 use actix_web::{web, HttpRequest, HttpResponse};
 use futures_util::StreamExt;
 use mime_guess::from_path;
 use sanitize_filename::sanitize;
+use schemars::JsonSchema;
+use serde::Deserialize;
 use std::collections::HashMap;
 use validator::Validate;
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.route("/upload", web::post().to(upload))
+        .route("/file/uploads", web::post().to(create_upload_session))
+        .route("/file/uploads/{id}", web::get().to(upload_session_progress))
+        .route("/file/uploads/{id}", web::patch().to(upload_chunk))
+        .route(
+            "/file/uploads/{id}/complete",
+            web::post().to(complete_upload_session),
+        )
         .route("/download/{cid}", web::get().to(download))
         .route("/delete", web::post().to(delete))
         .route("/pins", web::get().to(list_pins))
+        .route("/pins/{cid}", web::get().to(pin_status))
         .route("/metadata/{cid}", web::get().to(get_metadata))
+        .route("/file/{cid}/content", web::get().to(get_content))
+        .route("/file/{cid}/download", web::get().to(stream_download))
+        .route("/file/{cid}/acl", web::get().to(get_acl))
+        .route("/file/{cid}/acl", web::put().to(set_acl))
         .route(
             "/upload/status/{task_id}",
             web::get().to(get_upload_status_handler),
-        );
+        )
+        .route("/upload/{task_id}/retry", web::post().to(retry_upload));
 }
 
 /// Handles file upload requests via multipart form data
@@ -30,27 +50,10 @@ async fn upload(
     query: web::Query<HashMap<String, String>>,
 ) -> Result<HttpResponse, actix_web::error::Error> {
     let user_id = verify_token(http_req, &state.ipfs_service).await?;
-    let is_async = query.get("async").map_or(false, |v| v == "true");
+    let is_async = query.get("async").is_some_and(|v| v == "true");
 
-    let mut file_bytes = Vec::new();
-    let mut file_name = "unnamed_file".to_string();
-
-    // Process the multipart stream
-    while let Some(field) = payload.next().await {
-        let mut field = field?;
-        if let Some(content_disposition) = field.content_disposition() {
-            if let Some(name) = content_disposition.get_filename() {
-                file_name = sanitize(name);
-            }
-        }
-        while let Some(chunk) = field.next().await {
-            file_bytes.extend(chunk?);
-        }
-    }
-
-    if file_bytes.is_empty() {
-        return Err(ServiceError::InvalidInput("Empty file uploaded".to_string()).into());
-    }
+    let (file_bytes, file_name) = read_upload(&mut payload).await?;
+    let file_name = file_name.unwrap_or_else(|| "unnamed_file".to_string());
 
     let file_stream = futures::stream::iter(vec![Ok(file_bytes)]);
 
@@ -73,6 +76,141 @@ async fn upload(
     }
 }
 
+/// Re-runs a failed asynchronous upload with the same task ID; the file is sent again
+/// POST /api/upload/{task_id}/retry
+async fn retry_upload(
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    mut payload: Multipart,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let task_id = path.into_inner();
+    let user_id = verify_token(http_req, &state.ipfs_service).await?;
+
+    let (file_bytes, file_name) = read_upload(&mut payload).await?;
+    let file_stream = futures::stream::iter(vec![Ok(file_bytes)]);
+
+    let status = state
+        .ipfs_service
+        .retry_upload(&task_id, file_stream, file_name, user_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Collects an uploaded file and its sanitized name from a multipart form
+async fn read_upload(
+    payload: &mut Multipart,
+) -> Result<(Vec<u8>, Option<String>), actix_web::error::Error> {
+    let mut file_bytes = Vec::new();
+    let mut file_name = None;
+
+    // Process the multipart stream
+    while let Some(field) = payload.next().await {
+        let mut field = field?;
+        if let Some(content_disposition) = field.content_disposition() {
+            if let Some(name) = content_disposition.get_filename() {
+                file_name = Some(sanitize(name));
+            }
+        }
+        while let Some(chunk) = field.next().await {
+            file_bytes.extend(chunk?);
+        }
+    }
+
+    if file_bytes.is_empty() {
+        return Err(ServiceError::InvalidInput("Empty file uploaded".to_string()).into());
+    }
+
+    Ok((file_bytes, file_name))
+}
+
+/// Where a chunk of a resumable upload goes
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UploadChunkQuery {
+    // Zero-based chunk number
+    pub index: u64,
+    // Byte offset of the chunk, checked against `index` when given
+    pub offset: Option<u64>,
+    // Hex SHA-256 of the chunk, checked when given
+    pub sha256: Option<String>,
+}
+
+/// Opens a resumable upload session for a file sent in chunks
+/// POST /api/file/uploads
+async fn create_upload_session(
+    state: web::Data<super::AppState>,
+    req: web::Json<CreateUploadSessionRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let req = req.into_inner();
+    req.validate()
+        .map_err(|e| ServiceError::Validation(e.to_string()))?;
+    let user_id = verify_token(http_req, &state.ipfs_service).await?;
+
+    let session = state
+        .upload_session_service
+        .create(user_id, &sanitize(&req.file_name), req.size, &req.sha256)
+        .await?;
+    Ok(HttpResponse::Created().json(session))
+}
+
+/// Reports the chunks of a resumable upload received so far and those still missing
+/// GET /api/file/uploads/{id}
+async fn upload_session_progress(
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let user_id = verify_token(http_req, &state.ipfs_service).await?;
+    let progress = state
+        .upload_session_service
+        .progress(&path.into_inner(), user_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(progress))
+}
+
+/// Stores one chunk of a resumable upload, sent as the raw request body
+/// PATCH /api/file/uploads/{id}?index=N
+async fn upload_chunk(
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    query: web::Query<UploadChunkQuery>,
+    payload: web::Payload,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let user_id = verify_token(http_req, &state.ipfs_service).await?;
+    let query = query.into_inner();
+    let chunk = state
+        .upload_session_service
+        .put_chunk(
+            &path.into_inner(),
+            user_id,
+            query.index,
+            query.offset,
+            query.sha256.as_deref(),
+            payload,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(chunk))
+}
+
+/// Assembles a resumable upload once every chunk is in and adds the file to IPFS
+/// POST /api/file/uploads/{id}/complete
+async fn complete_upload_session(
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let user_id = verify_token(http_req, &state.ipfs_service).await?;
+    let metadata = state
+        .upload_session_service
+        .complete(&path.into_inner(), user_id)
+        .await?;
+    Ok(HttpResponse::Ok()
+        .append_header(("X-CID", metadata.cid.clone()))
+        .json(metadata))
+}
+
 /// Handles requests to get the status of an upload task
 /// GET /api/upload_file/status/{task_id}
 async fn get_upload_status_handler(
@@ -115,11 +253,15 @@ async fn download(
 
     let file_bytes = state.ipfs_service.fetch_file_bytes(&cid, user_id).await?;
 
-    // Determine MIME type based on file extension, default to octet-stream
-    let mime_type = from_path(&metadata.name).first_or_octet_stream();
+    // Prefer the type detected at upload, then the file extension, default to octet-stream
+    let mime_type = metadata.content_type.clone().unwrap_or_else(|| {
+        from_path(&metadata.name)
+            .first_or_octet_stream()
+            .to_string()
+    });
 
     Ok(HttpResponse::Ok()
-        .content_type(mime_type.to_string())
+        .content_type(mime_type)
         .append_header((
             "Content-Disposition",
             format!("inline; filename=\"{}\"", metadata.name),
@@ -127,6 +269,137 @@ async fn download(
         .body(file_bytes))
 }
 
+/// Serves file content to the uploader and to principals on the file's ACL
+/// GET /api/file/{cid}/content
+async fn get_content(
+    user: AuthUser,
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let cid = path.into_inner();
+    state.ipfs_service.check_access(&cid, &user).await?;
+
+    let content = state.ipfs_service.get_file_content(&cid).await?;
+    let content_type = state
+        .ipfs_service
+        .detect_content_type(&cid)
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    Ok(HttpResponse::Ok().content_type(content_type).body(content))
+}
+
+/// Streams file content to the uploader and to principals on the file's ACL as an
+/// attachment, without buffering it. A single-range `Range` header is answered with
+/// `206 Partial Content` so interrupted downloads can resume; other ranges get the whole file.
+/// GET /api/file/{cid}/download
+async fn stream_download(
+    user: AuthUser,
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let cid = path.into_inner();
+    state.ipfs_service.check_access(&cid, &user).await?;
+
+    let metadata = state
+        .ipfs_service
+        .get_file_metadata(&cid)
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| AppError::NotFound(format!("File {} not found", cid)))?;
+    let size = metadata.size;
+    // Prefer the type detected at upload, then the file extension
+    let content_type = metadata.content_type.clone().unwrap_or_else(|| {
+        from_path(&metadata.name)
+            .first_or_octet_stream()
+            .to_string()
+    });
+
+    let range = match Range::parse(&http_req) {
+        Ok(Range::Bytes(specs)) if specs.len() == 1 => Some(specs[0].to_satisfiable_range(size)),
+        _ => None,
+    };
+
+    let mut response = match range {
+        None => HttpResponse::Ok(),
+        Some(None) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish());
+        }
+        Some(Some((first, last))) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, size),
+            ));
+            response
+        }
+    };
+
+    let (body_range, length) = match range.flatten() {
+        Some((first, last)) => (Some((first, last - first + 1)), last - first + 1),
+        None => (None, size),
+    };
+    let body = SizedStream::new(
+        length,
+        state.ipfs_service.stream_file_content(&cid, body_range),
+    );
+
+    Ok(response
+        .content_type(content_type)
+        .insert_header(ContentDisposition::attachment(metadata.name))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .body(body))
+}
+
+/// Most ACL entries of each kind a file can carry
+const MAX_ACL_ENTRIES: usize = 100;
+
+/// Returns a file's ACL to its uploader
+/// GET /api/file/{cid}/acl
+async fn get_acl(
+    user: AuthUser,
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let acl = state
+        .ipfs_service
+        .get_file_acl(&path.into_inner(), user.id)
+        .await?;
+    Ok(HttpResponse::Ok().json(acl))
+}
+
+/// Replaces a file's ACL; an empty list makes the file public
+/// PUT /api/file/{cid}/acl
+async fn set_acl(
+    user: AuthUser,
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    acl: web::Json<FileAcl>,
+) -> Result<HttpResponse, AppError> {
+    let mut acl = acl.into_inner();
+    if acl.dids.len() > MAX_ACL_ENTRIES || acl.user_ids.len() > MAX_ACL_ENTRIES {
+        return Err(AppError::ValidationError(format!(
+            "An ACL can list at most {} DIDs and {} users",
+            MAX_ACL_ENTRIES, MAX_ACL_ENTRIES
+        )));
+    }
+    if let Some(did) = acl.dids.iter().find(|did| !did.starts_with("did:")) {
+        return Err(AppError::ValidationError(format!("Invalid DID: {}", did)));
+    }
+    acl.dids.sort();
+    acl.dids.dedup();
+    acl.user_ids.sort_unstable();
+    acl.user_ids.dedup();
+
+    let cid = path.into_inner();
+    state.ipfs_service.set_file_acl(&cid, user.id, &acl).await?;
+    Ok(HttpResponse::Ok().json(acl))
+}
+
 /// Handles file deletion requests
 /// POST /api/delete
 async fn delete(
@@ -176,6 +449,18 @@ async fn list_pins(
     Ok(HttpResponse::Ok().json(pins))
 }
 
+/// Reports whether content is available and, in cluster mode, how many peers pin it
+/// GET /api/pins/{cid}
+async fn pin_status(
+    state: web::Data<super::AppState>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    verify_token(http_req, &state.ipfs_service).await?;
+    let status = state.ipfs_service.pin_status(&path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
 /// Retrieves metadata for a specific file
 /// GET /api/metadata/{cid}
 async fn get_metadata(
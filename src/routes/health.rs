@@ -0,0 +1,32 @@
+use crate::routes::AppState;
+use actix_web::{web, HttpResponse};
+use log::warn;
+
+/// Readiness probe: ready once a database connection can be acquired and queried within
+/// `DB_POOL_WAIT_TIMEOUT_SECS`, otherwise 503. The circuit breakers of Dataverse and
+/// BioAgents are reported but don't affect readiness, since other endpoints still work.
+async fn ready(state: web::Data<AppState>) -> HttpResponse {
+    let circuits = serde_json::json!({
+        "dataverse": state.dataverse_service.circuit_status(),
+        "bioagents": state.bioagents_service.circuit_status(),
+    });
+    match state.db_health.check().await {
+        Ok(stats) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "ready",
+            "database": stats,
+            "circuits": circuits,
+        })),
+        Err(e) => {
+            warn!("Readiness check failed: {}", e);
+            HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                "status": "unavailable",
+                "error": e,
+                "circuits": circuits,
+            }))
+        }
+    }
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/ready", web::get().to(ready));
+}
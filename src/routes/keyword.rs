@@ -0,0 +1,32 @@
+use actix_web::{web, HttpResponse, Responder};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::errors::AppError;
+use crate::routes::AppState;
+use crate::services::keyword_service::DEFAULT_SUGGESTIONS;
+
+/// Keyword autocomplete query
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct KeywordSuggestQuery {
+    // What has been typed so far
+    pub q: String,
+    // Suggestions returned, 10 by default and at most 50
+    pub limit: Option<u32>,
+}
+
+/// Keywords used by DIDs and papers that complete the query, most used first
+pub async fn suggest_keywords(
+    app_state: web::Data<AppState>,
+    query: web::Query<KeywordSuggestQuery>,
+) -> Result<impl Responder, AppError> {
+    let suggestions = app_state
+        .keyword_service
+        .suggest(&query.q, query.limit.unwrap_or(DEFAULT_SUGGESTIONS))
+        .await?;
+    Ok(HttpResponse::Ok().json(suggestions))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/keywords/suggest", web::get().to(suggest_keywords));
+}
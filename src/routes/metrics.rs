@@ -0,0 +1,16 @@
+use crate::errors::AppError;
+use crate::routes::AppState;
+use actix_web::{web, HttpResponse};
+
+/// Prometheus scrape endpoint
+async fn metrics(state: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let body = state.metrics_service.render()?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+}
@@ -1,18 +1,51 @@
+use crate::config::Config;
+use crate::database::DbHealth;
+use crate::errors::AppError;
+use crate::services::audit_service::AuditService;
 use crate::services::bioagents_service::BioAgentsService;
 use crate::services::dataverse_service::DataverseService;
 use crate::services::did_service::DIDService;
+use crate::services::export_service::ExportService;
+use crate::services::idempotency_service::IdempotencyService;
 use crate::services::ipfs_service::IPFSService;
+use crate::services::key_registry::KeyRegistry;
+use crate::services::keyword_service::KeywordService;
+use crate::services::metrics_service::MetricsService;
+use crate::services::orcid_service::OrcidService;
+use crate::services::provenance_service::ProvenanceService;
+use crate::services::publication_service::PublicationService;
+use crate::services::quota_service::QuotaService;
 use crate::services::research_paper_service::ResearchPaperService;
+use crate::services::sparql_service::SparqlService;
+use crate::services::stats_service::StatsService;
+use crate::services::step_up_service::StepUpService;
+use crate::services::task_service::TaskService;
 use crate::services::ucan_service::UcanService;
+use crate::services::upload_session_service::UploadSessionService;
+use crate::services::webhook_service::WebhookService;
+use actix_web::http::header;
+use actix_web::middleware::DefaultHeaders;
 use actix_web::web;
 use std::sync::Arc;
 
+pub mod admin;
 pub mod auth;
 pub mod bioagents;
 pub mod dataverse;
 pub mod did;
+pub mod export;
 pub mod file;
+pub mod health;
+pub mod keyword;
+pub mod metrics;
+pub mod openapi;
+pub mod quota;
 pub mod research_paper;
+pub mod resolver;
+pub mod stats;
+pub mod task;
+pub mod totp;
+pub mod webhook;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -22,16 +55,195 @@ pub struct AppState {
     pub dataverse_service: Arc<DataverseService>,
     pub ucan_service: Arc<UcanService>,
     pub research_paper_service: Arc<ResearchPaperService>,
+    pub idempotency_service: Arc<IdempotencyService>,
+    pub task_service: Arc<TaskService>,
+    pub upload_session_service: Arc<UploadSessionService>,
+    pub metrics_service: Arc<MetricsService>,
+    pub audit_service: Arc<AuditService>,
+    pub export_service: Arc<ExportService>,
+    pub webhook_service: Arc<WebhookService>,
+    pub quota_service: Arc<QuotaService>,
+    pub key_registry: Arc<KeyRegistry>,
+    pub sparql_service: Arc<SparqlService>,
+    pub keyword_service: Arc<KeywordService>,
+    pub stats_service: Arc<StatsService>,
+    pub provenance_service: Arc<ProvenanceService>,
+    pub publication_service: Arc<PublicationService>,
+    pub step_up_service: Arc<StepUpService>,
+    // `None` when ORCID login isn't configured
+    pub orcid_service: Option<Arc<OrcidService>>,
+    pub db_health: Arc<DbHealth>,
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/api")
-            .configure(auth::init_routes)
-            .configure(file::init_routes)
-            .configure(did::init_routes)
-            .configure(bioagents::init_routes)
-            .configure(dataverse::init_routes)
-            .configure(research_paper::init_routes),
-    );
+/// JSON extractor settings accepting bodies of up to `limit` bytes. Oversized or malformed
+/// bodies, including metadata rejected while deserializing, fail with `ValidationError`.
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, _req| {
+            AppError::ValidationError(format!("Invalid JSON body: {}", err)).into()
+        })
+}
+
+/// Versions of the API, each served under `/api/{version}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// Version the unversioned `/api` prefix is an alias of
+    pub const CURRENT: ApiVersion = ApiVersion::V1;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+        }
+    }
+}
+
+/// Header marking responses from the unversioned `/api` prefix as deprecated
+pub const DEPRECATION_HEADER: &str = "deprecation";
+/// Header carrying the date the unversioned prefix stops being served, when configured
+pub const SUNSET_HEADER: &str = "sunset";
+
+/// Routes of one API version. Versions share handlers until a breaking change gives one
+/// its own, which is then registered here for the versions that need it.
+fn configure_version(cfg: &mut web::ServiceConfig, version: ApiVersion) {
+    match version {
+        ApiVersion::V1 => {
+            cfg.configure(auth::init_routes)
+                .configure(admin::init_routes)
+                .configure(file::init_routes)
+                .configure(did::init_routes)
+                .configure(bioagents::init_routes)
+                .configure(dataverse::init_routes)
+                .configure(research_paper::init_routes)
+                .configure(keyword::init_routes)
+                .configure(task::init_routes)
+                .configure(export::init_routes)
+                .configure(webhook::init_routes)
+                .configure(quota::init_routes)
+                .configure(stats::init_routes)
+                .configure(totp::init_routes)
+                .configure(openapi::init_routes);
+        }
+    }
+}
+
+/// Scope at `path` serving the routes of `version`
+fn versioned_scope(path: &str, version: ApiVersion) -> actix_web::Scope {
+    web::scope(path).configure(move |cfg| configure_version(cfg, version))
+}
+
+/// Headers marking a response as coming from the deprecated unversioned prefix, pointing
+/// at the version that succeeds it
+fn deprecation_headers(sunset: Option<&str>) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add((DEPRECATION_HEADER, "true"))
+        .add((
+            header::LINK,
+            format!(
+                "</api/{}>; rel=\"successor-version\"",
+                ApiVersion::CURRENT.as_str()
+            ),
+        ));
+    match sunset {
+        Some(sunset) => headers.add((SUNSET_HEADER, sunset)),
+        None => headers,
+    }
+}
+
+/// Register `/api/v1` and, unless disabled, the unversioned `/api` alias of the current
+/// version, whose responses carry `Deprecation` (and `Sunset`, when configured) headers
+pub fn init_routes(cfg: &mut web::ServiceConfig, config: &Config) {
+    // Versioned scopes go first, as `/api` would otherwise claim `/api/v1/...` paths
+    cfg.service(versioned_scope(
+        &format!("/api/{}", ApiVersion::V1.as_str()),
+        ApiVersion::V1,
+    ));
+    if config.unversioned_api_alias {
+        cfg.service(
+            versioned_scope("/api", ApiVersion::CURRENT).wrap(deprecation_headers(
+                config.unversioned_api_sunset.as_deref(),
+            )),
+        );
+    }
+    cfg.configure(metrics::init_routes)
+        .configure(health::init_routes)
+        .configure(resolver::init_routes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App, HttpResponse};
+
+    async fn echo(body: web::Json<serde_json::Value>) -> HttpResponse {
+        HttpResponse::Ok().json(body.into_inner())
+    }
+
+    #[actix_web::test]
+    async fn test_unversioned_alias_is_marked_deprecated() {
+        let sunset = "Fri, 01 Jan 2027 00:00:00 GMT";
+        let app = test::init_service(
+            App::new()
+                .service(versioned_scope("/api/v1", ApiVersion::V1))
+                .service(
+                    versioned_scope("/api", ApiVersion::CURRENT)
+                        .wrap(deprecation_headers(Some(sunset))),
+                ),
+        )
+        .await;
+
+        let versioned = test::TestRequest::get()
+            .uri("/api/v1/openapi.json")
+            .to_request();
+        let resp = test::call_service(&app, versioned).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(DEPRECATION_HEADER).is_none());
+
+        let alias = test::TestRequest::get()
+            .uri("/api/openapi.json")
+            .to_request();
+        let resp = test::call_service(&app, alias).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(DEPRECATION_HEADER).unwrap(), "true");
+        assert_eq!(resp.headers().get(SUNSET_HEADER).unwrap(), sunset);
+        assert_eq!(
+            resp.headers().get(header::LINK).unwrap(),
+            "</api/v1>; rel=\"successor-version\""
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_json_config_rejects_oversized_body() {
+        let app = test::init_service(
+            App::new()
+                .app_data(json_config(64))
+                .route("/echo", web::post().to(echo)),
+        )
+        .await;
+
+        let small = test::TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({ "title": "ok" }))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, small).await.status(),
+            StatusCode::OK
+        );
+
+        let oversized = test::TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({ "title": "x".repeat(128) }))
+            .to_request();
+        let resp = test::call_service(&app, oversized).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["message"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("Invalid JSON body"));
+    }
 }
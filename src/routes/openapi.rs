@@ -0,0 +1,1053 @@
+use actix_web::{web, HttpResponse, Responder};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+use crate::errors::ErrorResponse;
+use crate::models::audit::{AuditLogPage, AuditQuery};
+use crate::models::auth::AuthResponse;
+use crate::models::credential::{
+    CredentialVerification, IssueCredentialRequest, VerifiableCredential,
+};
+use crate::models::did::{
+    AttachFileRequest, BiometadataExtension, DIDCloneRequest, DIDCreationRequest, DIDDocument,
+    DIDTransfer, DIDTransferRequest, DIDUpdateRequest, DidBacklink, GeneratedKeypair,
+};
+use crate::models::did_template::{DIDFromTemplateRequest, DIDTemplate, DIDTemplateRequest};
+use crate::models::did_validation::DocumentValidation;
+use crate::models::file_metadata::{FileAcl, FileMetadata, ResearchPaperMetadata, UploadStatus};
+use crate::models::requests::{
+    CreateUploadSessionRequest, DeleteRequest, SigninRequest, SignupRequest,
+};
+use crate::models::task::Task;
+use crate::models::webhook::{Webhook, WebhookCreated, WebhookDelivery, WebhookRequest};
+use crate::routes::admin::ReconcileQuery;
+use crate::routes::auth::{
+    OrcidCallbackQuery, UcanIssueRequest, UcanListQuery, UcanResponse, UcanRevokeRequest,
+    UcanValidateRequest, UcanValidationResponse,
+};
+use crate::routes::bioagents::{
+    AgentQueryParams, AgentQueryRequest, AgentQueryResponse, EntitySearchFilters,
+    EntitySearchRequest, EntitySearchResponse, ExtractMetadataRequest,
+    GenerateKnowledgeGraphRequest, KnowledgeAddRequest, KnowledgeAddResponse,
+    MergeKnowledgeGraphsRequest, MergeKnowledgeGraphsResponse, ProcessPaperApiRequest,
+    SparqlQueryRequest, TaskStatusBatchRequest, TaskStatusRequest,
+};
+use crate::routes::dataverse::{
+    DatasetCreateRequest, DatasetCreateResponse, DatasetFileUploadResponse, DatasetFilesQuery,
+    DatasetFilesUploadResponse, MetadataUpdateRequest, PublishDatasetRequest,
+    PublishDatasetResponse,
+};
+use crate::routes::did::{
+    DidRegistryPage, DidRegistryQuery, GetDidQuery, LinkToDataverseQuery, LinkToDataverseRequest,
+};
+use crate::routes::file::UploadChunkQuery;
+use crate::routes::keyword::KeywordSuggestQuery;
+use crate::routes::research_paper::{
+    ProcessPaperRequest, QuickExtractResponse, RelatedPapersQuery, ReprocessPapersRequest,
+    SearchPapersRequest,
+};
+use crate::routes::task::{ListTasksQuery, ListTasksResponse};
+use crate::routes::totp::TotpVerifyRequest;
+use crate::services::bioagents_service::{
+    ExtractedMetadata, HealthStatus, ProcessPaperResponse, TaskStatus, TaskStatusBatch,
+};
+use crate::services::dataverse_service::DataverseFilesPage;
+use crate::services::ipfs_service::PinStatus;
+use crate::services::key_registry::KeyVersion;
+use crate::services::keyword_service::KeywordSuggestion;
+use crate::services::provenance_service::Provenance;
+use crate::services::quota_service::QuotaUsage;
+use crate::services::research_paper_service::{PaperPreview, RelatedPaper};
+use crate::services::stats_service::UserStats;
+use crate::services::step_up_service::{TotpEnrollment, TotpStatus, TOTP_CODE_HEADER};
+use crate::services::ucan_service::UcanTokenPage;
+use crate::services::upload_session_service::{
+    ReceivedChunk, UploadSession, UploadSessionProgress,
+};
+
+/// Generated spec, built once on first request
+static SPEC: OnceLock<Value> = OnceLock::new();
+
+/// Accumulates OpenAPI path items and the component schemas they reference
+struct SpecBuilder {
+    gen: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+/// A single operation being added to the spec
+struct Operation<'a> {
+    builder: &'a mut SpecBuilder,
+    method: &'static str,
+    path: &'static str,
+    operation: Map<String, Value>,
+}
+
+impl SpecBuilder {
+    fn new() -> Self {
+        SpecBuilder {
+            gen: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+        }
+    }
+
+    /// Start describing `method path`; path parameters are taken from `{name}` segments
+    fn op(&mut self, method: &'static str, path: &'static str, summary: &str) -> Operation<'_> {
+        let mut operation = Map::new();
+        operation.insert("summary".to_string(), json!(summary));
+        operation.insert(
+            "tags".to_string(),
+            json!([path.trim_start_matches('/').split('/').next().unwrap_or("")]),
+        );
+
+        let params: Vec<Value> = path
+            .split('/')
+            .filter(|s| s.starts_with('{') && s.ends_with('}'))
+            .map(|s| {
+                json!({
+                    "name": &s[1..s.len() - 1],
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                })
+            })
+            .collect();
+        if !params.is_empty() {
+            operation.insert("parameters".to_string(), Value::Array(params));
+        }
+
+        Operation {
+            builder: self,
+            method,
+            path,
+            operation,
+        }
+    }
+
+    fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.gen.subschema_for::<T>()).unwrap_or(Value::Null)
+    }
+
+    fn finish(mut self) -> Value {
+        let schemas = serde_json::to_value(self.gen.take_definitions()).unwrap_or_default();
+
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "bio-did-seq API",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "Decentralized Identifiers for biological research data",
+            },
+            "servers": [{ "url": "/api/v1" }],
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "bearerAuth": { "type": "http", "scheme": "bearer" }
+                }
+            }
+        })
+    }
+}
+
+impl Operation<'_> {
+    /// JSON request body
+    fn body<T: JsonSchema>(mut self) -> Self {
+        let schema = self.builder.schema::<T>();
+        self.operation.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema } }
+            }),
+        );
+        self
+    }
+
+    /// Multipart form body with a `file` field
+    fn multipart(mut self) -> Self {
+        self.operation.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": {
+                    "multipart/form-data": {
+                        "schema": {
+                            "type": "object",
+                            "properties": {
+                                "file": { "type": "string", "format": "binary" },
+                                "description": { "type": "string" }
+                            },
+                            "required": ["file"]
+                        }
+                    }
+                }
+            }),
+        );
+        self
+    }
+
+    /// Raw bytes as the request body
+    fn binary_body(mut self) -> Self {
+        self.operation.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": {
+                    "application/octet-stream": {
+                        "schema": { "type": "string", "format": "binary" }
+                    }
+                }
+            }),
+        );
+        self
+    }
+
+    /// Query string parameters, flattened from the properties of `T`
+    fn query<T: JsonSchema>(mut self) -> Self {
+        let schema = T::json_schema(&mut self.builder.gen).into_object();
+        let required = schema
+            .object
+            .as_ref()
+            .map(|o| o.required.clone())
+            .unwrap_or_default();
+        let params: Vec<Value> = schema
+            .object
+            .map(|o| o.properties)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, prop)| {
+                json!({
+                    "name": name,
+                    "in": "query",
+                    "required": required.contains(&name),
+                    "schema": prop
+                })
+            })
+            .collect();
+
+        let entry = self
+            .operation
+            .entry("parameters")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(existing) = entry {
+            existing.extend(params);
+        }
+        self
+    }
+
+    /// Operation may need step-up: a recent sign-in or a TOTP code in `X-TOTP-Code`
+    fn step_up(mut self) -> Self {
+        let entry = self
+            .operation
+            .entry("parameters")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(existing) = entry {
+            existing.push(json!({
+                "name": TOTP_CODE_HEADER,
+                "in": "header",
+                "required": false,
+                "schema": { "type": "string" }
+            }));
+        }
+        self
+    }
+
+    /// Operation expects a bearer token in the `Authorization` header
+    fn auth(mut self) -> Self {
+        self.operation
+            .insert("security".to_string(), json!([{ "bearerAuth": [] }]));
+        self
+    }
+
+    /// Successful JSON response of type `T`
+    fn returns<T: JsonSchema>(self, status: u16) -> Self {
+        let schema = self.builder.schema::<T>();
+        self.response(
+            status,
+            json!({
+                "description": "Success",
+                "content": { "application/json": { "schema": schema } }
+            }),
+        )
+    }
+
+    /// Successful response with an untyped JSON object body
+    fn returns_object(self, status: u16) -> Self {
+        self.response(
+            status,
+            json!({
+                "description": "Success",
+                "content": { "application/json": { "schema": { "type": "object" } } }
+            }),
+        )
+    }
+
+    /// Successful response without a body
+    fn returns_empty(self, status: u16) -> Self {
+        self.response(status, json!({ "description": "Success" }))
+    }
+
+    /// Successful response with raw file content of the given media type
+    fn returns_binary(self, content_type: &str) -> Self {
+        self.response(
+            200,
+            json!({
+                "description": "File content",
+                "content": {
+                    content_type: {
+                        "schema": { "type": "string", "format": "binary" }
+                    }
+                }
+            }),
+        )
+    }
+
+    fn response(mut self, status: u16, response: Value) -> Self {
+        let entry = self
+            .operation
+            .entry("responses")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(responses) = entry {
+            responses.insert(status.to_string(), response);
+        }
+        self
+    }
+
+    /// Add the shared error response and register the operation
+    fn add(self) {
+        let error = self.builder.schema::<ErrorResponse>();
+        let mut this = self.response(
+            400,
+            json!({
+                "description": "Error",
+                "content": { "application/json": { "schema": error } }
+            }),
+        );
+
+        let item = this
+            .builder
+            .paths
+            .entry(this.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(item) = item {
+            item.insert(
+                this.method.to_string(),
+                Value::Object(std::mem::take(&mut this.operation)),
+            );
+        }
+    }
+}
+
+/// Build the OpenAPI document for every route mounted under `/api`
+fn build_spec() -> Value {
+    let mut spec = SpecBuilder::new();
+
+    // Admin
+    spec.op(
+        "post",
+        "/admin/reimport",
+        "Re-pin all content on the current IPFS node",
+    )
+    .auth()
+    .returns_object(202)
+    .add();
+    spec.op(
+        "post",
+        "/admin/reconcile",
+        "Report unreachable and orphaned IPFS content",
+    )
+    .auth()
+    .query::<ReconcileQuery>()
+    .returns_object(202)
+    .add();
+    spec.op("get", "/admin/audit", "Page through the audit log")
+        .auth()
+        .query::<AuditQuery>()
+        .returns::<AuditLogPage>(200)
+        .add();
+    spec.op(
+        "get",
+        "/admin/keys",
+        "List the versions of the service keys",
+    )
+    .auth()
+    .returns::<Vec<KeyVersion>>(200)
+    .add();
+    spec.op(
+        "post",
+        "/admin/keys/rotate",
+        "Rotate the post-quantum service keys",
+    )
+    .auth()
+    .returns::<KeyVersion>(201)
+    .add();
+    spec.op(
+        "post",
+        "/admin/keys/{version}/retire",
+        "Retire a previous key version",
+    )
+    .auth()
+    .step_up()
+    .returns::<KeyVersion>(200)
+    .add();
+    spec.op(
+        "get",
+        "/admin/papers/review",
+        "Research papers flagged for personal data",
+    )
+    .auth()
+    .returns::<Vec<ResearchPaperMetadata>>(200)
+    .add();
+    spec.op(
+        "post",
+        "/admin/papers/{did}/approve",
+        "Approve a flagged research paper",
+    )
+    .auth()
+    .returns::<ResearchPaperMetadata>(200)
+    .add();
+
+    // Auth
+    spec.op("post", "/signup", "Create a user account")
+        .body::<SignupRequest>()
+        .returns::<AuthResponse>(200)
+        .add();
+    spec.op("post", "/signin", "Sign in and receive a PQC token")
+        .body::<SigninRequest>()
+        .returns::<AuthResponse>(200)
+        .add();
+    spec.op("get", "/auth/orcid/login", "Redirect to ORCID to sign in")
+        .returns_empty(302)
+        .add();
+    spec.op("get", "/auth/orcid/callback", "Complete an ORCID sign-in")
+        .query::<OrcidCallbackQuery>()
+        .returns::<AuthResponse>(200)
+        .add();
+    spec.op("get", "/ucan", "List the UCANs the caller issued")
+        .auth()
+        .query::<UcanListQuery>()
+        .returns::<UcanTokenPage>(200)
+        .add();
+    spec.op("post", "/ucan/issue", "Issue a UCAN token")
+        .auth()
+        .body::<UcanIssueRequest>()
+        .returns::<UcanResponse>(201)
+        .add();
+    spec.op("post", "/ucan/validate", "Validate a UCAN token")
+        .body::<UcanValidateRequest>()
+        .returns::<UcanValidationResponse>(200)
+        .add();
+    spec.op("post", "/ucan/revoke", "Revoke a UCAN token")
+        .auth()
+        .body::<UcanRevokeRequest>()
+        .returns_object(200)
+        .add();
+
+    // Files
+    spec.op("post", "/upload", "Upload a file to IPFS")
+        .auth()
+        .multipart()
+        .returns::<FileMetadata>(200)
+        .add();
+    spec.op(
+        "get",
+        "/upload/status/{task_id}",
+        "Get the status of an upload",
+    )
+    .auth()
+    .returns::<UploadStatus>(200)
+    .add();
+    spec.op(
+        "post",
+        "/upload/{task_id}/retry",
+        "Retry a failed asynchronous upload, resending the file",
+    )
+    .auth()
+    .multipart()
+    .returns::<UploadStatus>(200)
+    .add();
+    spec.op(
+        "post",
+        "/file/uploads",
+        "Open a resumable upload session for a file sent in chunks",
+    )
+    .auth()
+    .body::<CreateUploadSessionRequest>()
+    .returns::<UploadSession>(201)
+    .add();
+    spec.op(
+        "get",
+        "/file/uploads/{id}",
+        "Get the received and missing chunks of an upload session",
+    )
+    .auth()
+    .returns::<UploadSessionProgress>(200)
+    .add();
+    spec.op(
+        "patch",
+        "/file/uploads/{id}",
+        "Send one chunk of an upload session",
+    )
+    .auth()
+    .query::<UploadChunkQuery>()
+    .binary_body()
+    .returns::<ReceivedChunk>(200)
+    .add();
+    spec.op(
+        "post",
+        "/file/uploads/{id}/complete",
+        "Assemble an upload session's chunks and add the file to IPFS",
+    )
+    .auth()
+    .returns::<FileMetadata>(200)
+    .add();
+    spec.op("get", "/download/{cid}", "Download file content")
+        .auth()
+        .returns_binary("application/octet-stream")
+        .add();
+    spec.op("post", "/delete", "Delete a file")
+        .auth()
+        .body::<DeleteRequest>()
+        .returns_object(200)
+        .add();
+    spec.op("get", "/pins", "List pinned CIDs for the caller")
+        .auth()
+        .returns::<Vec<String>>(200)
+        .add();
+    spec.op(
+        "get",
+        "/pins/{cid}",
+        "Get a CID's availability and cluster replication",
+    )
+    .auth()
+    .returns::<PinStatus>(200)
+    .add();
+    spec.op("get", "/metadata/{cid}", "Get file metadata")
+        .auth()
+        .returns::<FileMetadata>(200)
+        .add();
+    spec.op(
+        "get",
+        "/file/{cid}/content",
+        "Read file content, subject to the file's ACL",
+    )
+    .auth()
+    .returns_binary("application/octet-stream")
+    .add();
+    spec.op(
+        "get",
+        "/file/{cid}/download",
+        "Stream file content as a download, with Range support, subject to the file's ACL",
+    )
+    .auth()
+    .returns_binary("application/octet-stream")
+    .add();
+    spec.op("get", "/file/{cid}/acl", "Get a file's ACL (uploader only)")
+        .auth()
+        .returns::<FileAcl>(200)
+        .add();
+    spec.op(
+        "put",
+        "/file/{cid}/acl",
+        "Replace a file's ACL; an empty ACL makes it public",
+    )
+    .auth()
+    .body::<FileAcl>()
+    .returns::<FileAcl>(200)
+    .add();
+
+    // DIDs
+    spec.op("post", "/did", "Create a DID")
+        .auth()
+        .body::<DIDCreationRequest>()
+        .returns::<DIDDocument>(201)
+        .add();
+    spec.op(
+        "post",
+        "/did/import",
+        "Import a DID document under its existing did:bio ID",
+    )
+    .auth()
+    .body::<DIDDocument>()
+    .returns::<DIDDocument>(201)
+    .add();
+    spec.op(
+        "post",
+        "/did/keygen",
+        "Generate an Ed25519 keypair and did:key controller; the private key is not kept",
+    )
+    .auth()
+    .returns::<GeneratedKeypair>(200)
+    .add();
+    spec.op(
+        "get",
+        "/did/{did}",
+        "Get a DID document, or the version stored at `cid`",
+    )
+    .query::<GetDidQuery>()
+    .returns::<DIDDocument>(200)
+    .add();
+    spec.op("put", "/did/{did}", "Update a DID document")
+        .auth()
+        .body::<DIDUpdateRequest>()
+        .returns::<DIDDocument>(200)
+        .add();
+    spec.op(
+        "post",
+        "/did/{did}/dataverse",
+        "Link a DID to a Dataverse dataset",
+    )
+    .auth()
+    .query::<LinkToDataverseQuery>()
+    .body::<LinkToDataverseRequest>()
+    .returns_object(200)
+    .add();
+    spec.op(
+        "post",
+        "/did/{did}/attach",
+        "Attach content already on IPFS to a DID as a service",
+    )
+    .auth()
+    .body::<AttachFileRequest>()
+    .returns::<DIDDocument>(200)
+    .add();
+    spec.op(
+        "post",
+        "/did/{did}/credentials",
+        "Issue a Verifiable Credential signed by an assertion method of the DID",
+    )
+    .auth()
+    .body::<IssueCredentialRequest>()
+    .returns::<VerifiableCredential>(201)
+    .add();
+    spec.op(
+        "post",
+        "/did/credentials/verify",
+        "Verify a credential's proof against its issuer's DID document",
+    )
+    .body::<VerifiableCredential>()
+    .returns::<CredentialVerification>(200)
+    .add();
+    spec.op(
+        "post",
+        "/did/validate",
+        "Check a DID document created elsewhere without storing it",
+    )
+    .body::<DIDDocument>()
+    .returns::<DocumentValidation>(200)
+    .add();
+    spec.op(
+        "get",
+        "/did/{did}/metadata",
+        "Get the biological metadata of a DID's current document",
+    )
+    .returns::<BiometadataExtension>(200)
+    .add();
+    spec.op(
+        "post",
+        "/did/{did}/clone",
+        "Create a derived DID with a copy of a DID's metadata",
+    )
+    .auth()
+    .body::<DIDCloneRequest>()
+    .returns::<DIDDocument>(201)
+    .add();
+    spec.op(
+        "post",
+        "/did/{did}/transfer",
+        "Transfer a DID and its research papers to another user",
+    )
+    .auth()
+    .step_up()
+    .body::<DIDTransferRequest>()
+    .returns::<DIDTransfer>(200)
+    .add();
+    spec.op(
+        "get",
+        "/did/{did}/hash",
+        "SHA-256 of the canonical DID document",
+    )
+    .returns_object(200)
+    .add();
+    spec.op(
+        "get",
+        "/did/{did}/backlinks",
+        "DIDs whose documents reference this DID",
+    )
+    .returns::<Vec<DidBacklink>>(200)
+    .add();
+    spec.op(
+        "get",
+        "/did/{did}/provenance",
+        "Provenance chain of a DID with the health of each link",
+    )
+    .returns::<Provenance>(200)
+    .add();
+    spec.op(
+        "get",
+        "/did/registry",
+        "List every did:bio DID, oldest first",
+    )
+    .query::<DidRegistryQuery>()
+    .returns::<DidRegistryPage>(200)
+    .add();
+    spec.op("get", "/did/resolve/{did}", "Resolve a DID")
+        .returns::<DIDDocument>(200)
+        .add();
+    spec.op("post", "/did/templates", "Create a DID metadata template")
+        .auth()
+        .body::<DIDTemplateRequest>()
+        .returns::<DIDTemplate>(201)
+        .add();
+    spec.op("get", "/did/templates", "List the caller's DID templates")
+        .auth()
+        .returns::<Vec<DIDTemplate>>(200)
+        .add();
+    spec.op("get", "/did/templates/{template_id}", "Get a DID template")
+        .auth()
+        .returns::<DIDTemplate>(200)
+        .add();
+    spec.op(
+        "put",
+        "/did/templates/{template_id}",
+        "Replace a DID template",
+    )
+    .auth()
+    .body::<DIDTemplateRequest>()
+    .returns::<DIDTemplate>(200)
+    .add();
+    spec.op(
+        "delete",
+        "/did/templates/{template_id}",
+        "Delete a DID template",
+    )
+    .auth()
+    .returns_empty(204)
+    .add();
+    spec.op(
+        "post",
+        "/did/templates/{template_id}/did",
+        "Create a DID from a template with metadata overrides",
+    )
+    .auth()
+    .body::<DIDFromTemplateRequest>()
+    .returns::<DIDDocument>(201)
+    .add();
+
+    // BioAgents
+    spec.op(
+        "post",
+        "/bioagents/process",
+        "Process a paper through BioAgents",
+    )
+    .auth()
+    .body::<ProcessPaperApiRequest>()
+    .returns::<ProcessPaperResponse>(202)
+    .add();
+    spec.op("post", "/bioagents/status", "Check a BioAgents task")
+        .auth()
+        .body::<TaskStatusRequest>()
+        .returns::<TaskStatus>(200)
+        .add();
+    spec.op(
+        "post",
+        "/bioagents/status/batch",
+        "Check up to 100 BioAgents tasks",
+    )
+    .auth()
+    .body::<TaskStatusBatchRequest>()
+    .returns::<TaskStatusBatch>(200)
+    .add();
+    spec.op("post", "/bioagents/metadata", "Get extracted metadata")
+        .auth()
+        .body::<ExtractMetadataRequest>()
+        .returns::<ExtractedMetadata>(200)
+        .add();
+    spec.op("post", "/bioagents/search", "Search biological entities")
+        .auth()
+        .query::<EntitySearchFilters>()
+        .body::<EntitySearchRequest>()
+        .returns::<EntitySearchResponse>(200)
+        .add();
+    spec.op(
+        "post",
+        "/bioagents/knowledge-graph",
+        "Generate a knowledge graph",
+    )
+    .auth()
+    .body::<GenerateKnowledgeGraphRequest>()
+    .returns_object(200)
+    .add();
+    spec.op(
+        "post",
+        "/bioagents/knowledge-graph/merge",
+        "Merge the knowledge graphs of several papers in the background",
+    )
+    .auth()
+    .body::<MergeKnowledgeGraphsRequest>()
+    .returns::<MergeKnowledgeGraphsResponse>(202)
+    .add();
+    spec.op(
+        "post",
+        "/bioagents/knowledge-graph/{cid}/sparql",
+        "Run a read-only SPARQL query against a knowledge graph",
+    )
+    .auth()
+    .body::<SparqlQueryRequest>()
+    .returns_object(200)
+    .add();
+    spec.op("post", "/bioagents/query", "Ask BioAgents a question")
+        .auth()
+        .query::<AgentQueryParams>()
+        .body::<AgentQueryRequest>()
+        .returns::<AgentQueryResponse>(200)
+        .add();
+    spec.op("post", "/bioagents/knowledge", "Add knowledge to BioAgents")
+        .auth()
+        .body::<KnowledgeAddRequest>()
+        .returns::<KnowledgeAddResponse>(200)
+        .add();
+    spec.op("get", "/bioagents/health", "BioAgents health")
+        .returns::<HealthStatus>(200)
+        .add();
+
+    // Dataverse
+    spec.op("post", "/dataverse/dataset", "Create a Dataverse dataset")
+        .auth()
+        .body::<DatasetCreateRequest>()
+        .returns::<DatasetCreateResponse>(201)
+        .add();
+    spec.op(
+        "post",
+        "/dataverse/dataset/file/{persistent_id}",
+        "Upload a file to a dataset",
+    )
+    .auth()
+    .multipart()
+    .returns::<DatasetFileUploadResponse>(200)
+    .add();
+    spec.op(
+        "post",
+        "/dataverse/dataset/files/{persistent_id}",
+        "Upload several files to a dataset",
+    )
+    .auth()
+    .multipart()
+    .returns::<DatasetFilesUploadResponse>(200)
+    .add();
+    spec.op(
+        "put",
+        "/dataverse/dataset/metadata",
+        "Update dataset metadata",
+    )
+    .auth()
+    .body::<MetadataUpdateRequest>()
+    .returns_object(200)
+    .add();
+    spec.op(
+        "post",
+        "/dataverse/dataset/publish",
+        "Publish a dataset or submit it for review",
+    )
+    .auth()
+    .body::<PublishDatasetRequest>()
+    .returns::<PublishDatasetResponse>(202)
+    .add();
+    spec.op(
+        "get",
+        "/dataverse/dataset/{persistent_id}",
+        "Get dataset metadata",
+    )
+    .returns_object(200)
+    .add();
+    spec.op(
+        "get",
+        "/dataverse/dataset/{persistent_id}/files",
+        "List the files in a dataset version",
+    )
+    .auth()
+    .query::<DatasetFilesQuery>()
+    .returns::<DataverseFilesPage>(200)
+    .add();
+
+    // Research papers
+    spec.op(
+        "post",
+        "/research-paper",
+        "Process a paper and mint a DID, or preview the extracted metadata with `dry_run`",
+    )
+    .auth()
+    .body::<ProcessPaperRequest>()
+    .returns_object(202)
+    .returns::<PaperPreview>(200)
+    .add();
+    spec.op(
+        "post",
+        "/research-paper/reprocess",
+        "Re-extract the caller's papers with BioAgents in the background",
+    )
+    .auth()
+    .body::<ReprocessPapersRequest>()
+    .returns_object(202)
+    .add();
+    spec.op(
+        "post",
+        "/research-paper/quick-extract",
+        "Extract title, authors and DOI from an uploaded PDF without BioAgents",
+    )
+    .auth()
+    .multipart()
+    .returns::<QuickExtractResponse>(200)
+    .add();
+    spec.op(
+        "get",
+        "/research-paper/did/{did}",
+        "Get paper metadata by DID",
+    )
+    .returns::<ResearchPaperMetadata>(200)
+    .add();
+    spec.op(
+        "get",
+        "/research-paper/did/{did}/related",
+        "Suggest papers sharing biological entities or keywords with a paper",
+    )
+    .query::<RelatedPapersQuery>()
+    .returns::<Vec<RelatedPaper>>(200)
+    .add();
+    spec.op(
+        "get",
+        "/research-paper/cid/{cid}",
+        "Get paper metadata by CID",
+    )
+    .returns::<ResearchPaperMetadata>(200)
+    .add();
+    spec.op("get", "/research-paper/search", "Search research papers")
+        .query::<SearchPapersRequest>()
+        .returns::<Vec<ResearchPaperMetadata>>(200)
+        .add();
+
+    // Keywords
+    spec.op(
+        "get",
+        "/keywords/suggest",
+        "Suggest keywords used by DIDs and papers",
+    )
+    .query::<KeywordSuggestQuery>()
+    .returns::<Vec<KeywordSuggestion>>(200)
+    .add();
+
+    // Tasks
+    spec.op("get", "/tasks", "List upload and BioAgents tasks")
+        .auth()
+        .query::<ListTasksQuery>()
+        .returns::<ListTasksResponse>(200)
+        .add();
+    spec.op("get", "/tasks/{task_id}", "Get the status of any task")
+        .auth()
+        .returns::<Task>(200)
+        .add();
+    spec.op(
+        "get",
+        "/tasks/{task_id}/events",
+        "Stream a task's status as server-sent events",
+    )
+    .auth()
+    .returns::<Task>(200)
+    .add();
+
+    spec.op(
+        "get",
+        "/export",
+        "Download the caller's DIDs and paper metadata as a ZIP archive",
+    )
+    .auth()
+    .returns_binary("application/zip")
+    .add();
+    spec.op(
+        "get",
+        "/me/quota",
+        "Get the caller's paper and dataset usage this month",
+    )
+    .auth()
+    .returns::<QuotaUsage>(200)
+    .add();
+    spec.op(
+        "get",
+        "/me/stats",
+        "Get totals of the caller's DIDs, papers, datasets, storage and tasks",
+    )
+    .auth()
+    .returns::<UserStats>(200)
+    .add();
+    spec.op(
+        "post",
+        "/me/totp/enroll",
+        "Generate a TOTP secret for step-up verification",
+    )
+    .auth()
+    .returns::<TotpEnrollment>(200)
+    .add();
+    spec.op(
+        "post",
+        "/me/totp/verify",
+        "Check a TOTP code, confirming enrollment on first use",
+    )
+    .auth()
+    .body::<TotpVerifyRequest>()
+    .returns::<TotpStatus>(200)
+    .add();
+
+    // Webhooks
+    spec.op(
+        "post",
+        "/webhooks",
+        "Register a webhook for DID lifecycle events",
+    )
+    .auth()
+    .body::<WebhookRequest>()
+    .returns::<WebhookCreated>(201)
+    .add();
+    spec.op("get", "/webhooks", "List the caller's webhooks")
+        .auth()
+        .returns::<Vec<Webhook>>(200)
+        .add();
+    spec.op("get", "/webhooks/{webhook_id}", "Get a webhook")
+        .auth()
+        .returns::<Webhook>(200)
+        .add();
+    spec.op("put", "/webhooks/{webhook_id}", "Replace a webhook")
+        .auth()
+        .body::<WebhookRequest>()
+        .returns::<Webhook>(200)
+        .add();
+    spec.op("delete", "/webhooks/{webhook_id}", "Delete a webhook")
+        .auth()
+        .returns_empty(204)
+        .add();
+    spec.op(
+        "get",
+        "/webhooks/{webhook_id}/deliveries",
+        "List recent deliveries to a webhook",
+    )
+    .auth()
+    .returns::<Vec<WebhookDelivery>>(200)
+    .add();
+
+    spec.op("get", "/openapi.json", "This document")
+        .returns_object(200)
+        .add();
+
+    spec.finish()
+}
+
+/// Serve the generated OpenAPI spec
+/// GET /api/v1/openapi.json
+pub async fn openapi_spec() -> impl Responder {
+    HttpResponse::Ok().json(SPEC.get_or_init(build_spec))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/openapi.json", web::get().to(openapi_spec));
+}
@@ -0,0 +1,18 @@
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::routes::AppState;
+
+/// The caller's paper and dataset usage this month, with their limits
+pub async fn get_quota(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let usage = app_state.quota_service.usage(&user).await?;
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/me/quota", web::get().to(get_quota));
+}
@@ -1,30 +1,75 @@
-use actix_web::{web, HttpResponse, Responder};
-use log::info;
-use serde::Deserialize;
+use actix_multipart::Multipart;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures_util::TryStreamExt;
+use log::{error, info};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::errors::AppError;
 use crate::models::auth::AuthUser;
 use crate::routes::AppState;
+use crate::services::bioagents_service::ExtractedMetadata;
+use crate::services::idempotency_service::IdempotencyKey;
+use crate::services::pdf_metadata::{extract_basic_metadata, ExtractionUnavailable};
+use crate::services::quota_service::QuotaResource;
+use crate::services::research_paper_service::{
+    PaperField, ReprocessProgress, DEFAULT_RELATED_PAPERS, MAX_REPROCESS_PAPERS,
+};
+
+/// Largest PDF accepted by quick extraction, which parses it in memory
+const MAX_QUICK_EXTRACT_BYTES: usize = 50 * 1024 * 1024;
 
 /// Request to process a research paper and create metadata
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct ProcessPaperRequest {
     pub file_cid: String,
     pub title: String,
     pub authors: Vec<String>,
     pub doi: Option<String>,
+    // Only run extraction and return the preview; nothing is stored and no DID is minted
+    #[serde(default)]
+    pub dry_run: bool,
+    // Don't generate keywords from the title and abstract when BioAgents returns none
+    #[serde(default)]
+    pub disable_keyword_fallback: bool,
+    // Run BioAgents again even if this paper was already extracted with the same options
+    #[serde(default)]
+    pub refresh_cache: bool,
+}
+
+/// Request to run BioAgents again on papers processed earlier
+#[derive(Deserialize, JsonSchema)]
+pub struct ReprocessPapersRequest {
+    // DIDs of the caller's papers
+    pub dids: Vec<String>,
+    // Fields to keep as stored, e.g. ones the user corrected
+    #[serde(default)]
+    pub preserve_fields: Vec<PaperField>,
+}
+
+/// Query parameters for related papers
+#[derive(Deserialize, JsonSchema)]
+pub struct RelatedPapersQuery {
+    // Papers returned, 10 by default and at most 50
+    pub limit: Option<u32>,
 }
 
 /// Request to search for research papers
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct SearchPapersRequest {
     pub query: String,
 }
 
 /// Process a research paper and create metadata
+///
+/// Retries carrying the same `Idempotency-Key` header return the originally minted DID.
+/// With `dry_run` set the extracted metadata is returned instead and nothing is stored.
 pub async fn process_paper(
-    user: web::ReqData<AuthUser>,
+    user: AuthUser,
     app_state: web::Data<AppState>,
+    http_req: HttpRequest,
     request: web::Json<ProcessPaperRequest>,
 ) -> Result<impl Responder, AppError> {
     info!(
@@ -32,23 +77,278 @@ pub async fn process_paper(
         user.id, request.title
     );
 
-    let did = app_state
-        .research_paper_service
-        .process_paper_and_create_metadata(
-            &request.file_cid,
-            &request.title,
-            &request.authors,
-            request.doi.as_deref(),
-            user.id,
-        )
+    if request.dry_run {
+        let preview = app_state
+            .research_paper_service
+            .preview_paper_metadata(
+                &request.file_cid,
+                &request.title,
+                &request.authors,
+                request.doi.as_deref(),
+                user.id,
+                !request.disable_keyword_fallback,
+                request.refresh_cache,
+            )
+            .await?;
+        return Ok(HttpResponse::Ok().json(preview));
+    }
+
+    let idempotency_key =
+        IdempotencyKey::from_request(&http_req, "research_paper_process", &*request)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(response) = app_state.idempotency_service.reserve(user.id, key).await? {
+            return Ok(response);
+        }
+    }
+
+    let processed = async {
+        app_state
+            .quota_service
+            .check_and_increment(&user, QuotaResource::Papers)
+            .await?;
+        let processed = app_state
+            .research_paper_service
+            .process_paper_and_create_metadata(
+                &request.file_cid,
+                &request.title,
+                &request.authors,
+                request.doi.as_deref(),
+                user.id,
+                !request.disable_keyword_fallback,
+                request.refresh_cache,
+            )
+            .await;
+        if processed.is_err() {
+            app_state
+                .quota_service
+                .refund(user.id, QuotaResource::Papers)
+                .await;
+        }
+        processed
+    }
+    .await;
+    let processed = match processed {
+        Ok(processed) => processed,
+        Err(e) => {
+            if let Some(key) = &idempotency_key {
+                app_state.idempotency_service.release(user.id, key).await;
+            }
+            return Err(e);
+        }
+    };
+
+    let response = serde_json::json!({
+        "message": "Research paper processed successfully",
+        "did": processed.did
+    });
+
+    // Recorded without the keypair so the private key never reaches the database;
+    // replays return only the DID
+    if let Some(key) = &idempotency_key {
+        app_state
+            .idempotency_service
+            .record(
+                user.id,
+                key,
+                StatusCode::ACCEPTED,
+                &response,
+                Some(&processed.did),
+            )
+            .await;
+    }
+
+    let mut response = response;
+    response["keypair"] = serde_json::to_value(&processed.keypair)?;
+    Ok(HttpResponse::Accepted()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(response))
+}
+
+/// Re-extract the caller's papers with BioAgents, e.g. after its models improved
+///
+/// Runs in the background; poll `GET /api/tasks/{task_id}` for progress and, once done,
+/// the result of each paper.
+pub async fn reprocess_papers(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    request: web::Json<ReprocessPapersRequest>,
+) -> Result<impl Responder, AppError> {
+    let request = request.into_inner();
+    let mut dids: Vec<String> = Vec::with_capacity(request.dids.len());
+    for did in request.dids {
+        if !dids.contains(&did) {
+            dids.push(did);
+        }
+    }
+    if dids.is_empty() || dids.len() > MAX_REPROCESS_PAPERS {
+        return Err(AppError::ValidationError(format!(
+            "Between 1 and {} DIDs can be reprocessed at once",
+            MAX_REPROCESS_PAPERS
+        )));
+    }
+    info!(
+        "User {} started reprocessing {} research papers",
+        user.id,
+        dids.len()
+    );
+
+    let task_id = app_state
+        .task_service
+        .create_background_task(user.id, "paper_reprocess")
         .await?;
 
+    let research_paper_service = app_state.research_paper_service.clone();
+    let task_service = app_state.task_service.clone();
+    let job_task_id = task_id.clone();
+    let user_id = user.id;
+    let total = dids.len();
+
+    tokio::task::spawn_local(async move {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<ReprocessProgress>();
+
+        let progress_task_service = task_service.clone();
+        let progress_task_id = job_task_id.clone();
+        let progress = async move {
+            while let Some(update) = rx.recv().await {
+                let percent = update.processed as f64 * 100.0 / update.total.max(1) as f64;
+                let details = serde_json::json!({
+                    "processed": update.processed,
+                    "total": update.total,
+                    "updated": update.updated,
+                    "failed": update.failed,
+                    "last_did": update.did,
+                });
+                if let Err(e) = progress_task_service
+                    .update_background_task(
+                        &progress_task_id,
+                        "processing",
+                        percent,
+                        Some(&details),
+                        None,
+                    )
+                    .await
+                {
+                    error!("Failed to record reprocessing progress: {}", e);
+                }
+            }
+        };
+
+        let (report, ()) = tokio::join!(
+            research_paper_service.reprocess(&dids, user_id, &request.preserve_fields, tx),
+            progress
+        );
+
+        let outcome = match serde_json::to_value(&report) {
+            Ok(details) => {
+                task_service
+                    .update_background_task(&job_task_id, "completed", 100.0, Some(&details), None)
+                    .await
+            }
+            Err(e) => {
+                task_service
+                    .update_background_task(
+                        &job_task_id,
+                        "failed",
+                        100.0,
+                        None,
+                        Some(&e.to_string()),
+                    )
+                    .await
+            }
+        };
+        if let Err(e) = outcome {
+            error!(
+                "Failed to record reprocessing result for {}: {}",
+                job_task_id, e
+            );
+        }
+    });
+
     Ok(HttpResponse::Accepted().json(serde_json::json!({
-        "message": "Research paper processed successfully",
-        "did": did
+        "task_id": task_id,
+        "status": "pending",
+        "total": total,
     })))
 }
 
+/// Result of a quick local extraction
+#[derive(Serialize, JsonSchema)]
+pub struct QuickExtractResponse {
+    // False when the PDF is encrypted or has no extractable text
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ExtractedMetadata>,
+    // Why extraction is unavailable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Extract title, authors and DOI from an uploaded PDF without BioAgents
+///
+/// Meant as an instant preview before submitting the paper for full processing. Encrypted
+/// and image-only PDFs return `available: false` with the reason.
+pub async fn quick_extract(
+    user: AuthUser,
+    mut payload: Multipart,
+) -> Result<impl Responder, AppError> {
+    let mut pdf = Vec::new();
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|e| AppError::ValidationError(format!("Invalid multipart body: {}", e)))?
+    {
+        if field.content_disposition().and_then(|cd| cd.get_name()) != Some("file") {
+            continue;
+        }
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|e| AppError::ValidationError(format!("Failed to read upload: {}", e)))?
+        {
+            if pdf.len() + chunk.len() > MAX_QUICK_EXTRACT_BYTES {
+                return Err(AppError::ValidationError(format!(
+                    "PDF exceeds {} MiB",
+                    MAX_QUICK_EXTRACT_BYTES / (1024 * 1024)
+                )));
+            }
+            pdf.extend_from_slice(&chunk);
+        }
+    }
+    if pdf.is_empty() {
+        return Err(AppError::ValidationError(
+            "A PDF must be uploaded as the `file` field".to_string(),
+        ));
+    }
+    info!(
+        "Quick metadata extraction for user {} ({} bytes)",
+        user.id,
+        pdf.len()
+    );
+
+    let response = match web::block(move || extract_basic_metadata(&pdf))
+        .await
+        .map_err(|e| AppError::ServiceError(format!("Extraction failed: {}", e)))?
+    {
+        Ok(metadata) => QuickExtractResponse {
+            available: true,
+            metadata: Some(metadata),
+            reason: None,
+        },
+        Err(ExtractionUnavailable::NotPdf) => {
+            return Err(AppError::ValidationError(
+                ExtractionUnavailable::NotPdf.reason().to_string(),
+            ))
+        }
+        Err(unavailable) => QuickExtractResponse {
+            available: false,
+            metadata: None,
+            reason: Some(format!("Extraction unavailable: {}", unavailable.reason())),
+        },
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
 /// Get research paper metadata by DID
 pub async fn get_paper_metadata_by_did(
     app_state: web::Data<AppState>,
@@ -65,6 +365,23 @@ pub async fn get_paper_metadata_by_did(
     Ok(HttpResponse::Ok().json(metadata))
 }
 
+/// Suggest other papers sharing biological entities or keywords with the paper behind a DID
+pub async fn get_related_papers(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<RelatedPapersQuery>,
+) -> Result<impl Responder, AppError> {
+    let did = path.into_inner();
+    info!("Finding research papers related to DID: {}", did);
+
+    let related = app_state
+        .research_paper_service
+        .find_related(&did, query.limit.unwrap_or(DEFAULT_RELATED_PAPERS))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(related))
+}
+
 /// Get research paper metadata by CID
 pub async fn get_paper_metadata_by_cid(
     app_state: web::Data<AppState>,
@@ -101,7 +418,10 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/research-paper")
             .route("", web::post().to(process_paper))
+            .route("/quick-extract", web::post().to(quick_extract))
+            .route("/reprocess", web::post().to(reprocess_papers))
             .route("/did/{did}", web::get().to(get_paper_metadata_by_did))
+            .route("/did/{did}/related", web::get().to(get_related_papers))
             .route("/cid/{cid}", web::get().to(get_paper_metadata_by_cid))
             .route("/search", web::get().to(search_papers)),
     );
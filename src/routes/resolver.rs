@@ -0,0 +1,285 @@
+use actix_web::http::header::{self, Header};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use log::{info, warn};
+
+use crate::errors::AppError;
+use crate::models::did::{
+    DidResolutionResult, DID_JSON_MEDIA_TYPE, DID_LD_JSON_MEDIA_TYPE, DID_RESOLUTION_MEDIA_TYPE,
+};
+use crate::routes::did::{DocumentMediaType, ResolutionCaching};
+use crate::routes::AppState;
+
+/// DID method this service resolves
+const DID_METHOD: &str = "bio";
+
+/// What a resolution request asked for in its `Accept` header
+#[derive(Debug, PartialEq, Eq)]
+enum Representation {
+    ResolutionResult,
+    // The DID document alone, in the given media type
+    Document(DocumentMediaType),
+}
+
+impl Representation {
+    /// Most preferred representation the request accepts; a missing or unparsable `Accept`
+    /// header gets the resolution result, and `None` means nothing acceptable is offered
+    fn negotiate(req: &HttpRequest) -> Option<Self> {
+        let Ok(accept) = header::Accept::parse(req) else {
+            return Some(Representation::ResolutionResult);
+        };
+        if accept.is_empty() {
+            return Some(Representation::ResolutionResult);
+        }
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match mime.essence_str() {
+                DID_LD_JSON_MEDIA_TYPE => Some(Representation::Document(DocumentMediaType::JsonLd)),
+                DID_JSON_MEDIA_TYPE => Some(Representation::Document(DocumentMediaType::Json)),
+                "application/ld+json" => match mime.get_param("profile") {
+                    Some(profile) if profile != "https://w3id.org/did-resolution" => None,
+                    _ => Some(Representation::ResolutionResult),
+                },
+                "application/json" | "application/*" | "*/*" => {
+                    Some(Representation::ResolutionResult)
+                }
+                _ => None,
+            })
+    }
+}
+
+/// Resolution error code and status for a DID this service can't resolve by its syntax
+fn check_did_syntax(did: &str) -> Result<(), (StatusCode, &'static str)> {
+    let mut parts = did.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("did"), Some(DID_METHOD), Some(id))
+            if !id.is_empty()
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':')) =>
+        {
+            Ok(())
+        }
+        (Some("did"), Some(method), Some(_))
+            if method != DID_METHOD
+                && !method.is_empty()
+                && method
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()) =>
+        {
+            Err((StatusCode::NOT_IMPLEMENTED, "methodNotSupported"))
+        }
+        _ => Err((StatusCode::BAD_REQUEST, "invalidDid")),
+    }
+}
+
+fn resolution_failure(status: StatusCode, error: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(DID_RESOLUTION_MEDIA_TYPE)
+        .json(DidResolutionResult::failed(error))
+}
+
+/// Resolve a DID the way the DIF Universal Resolver expects of a driver.
+///
+/// Returns the DID resolution result, or just the document when `Accept` prefers
+/// `application/did+ld+json` or `application/did+json`. Failures are resolution results
+/// carrying the error code, with the HTTP status the DID Resolution spec gives it.
+/// Caching and `If-Modified-Since` work as for `resolve_did`.
+pub async fn resolve_identifier(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let did = path.into_inner();
+    info!("Resolving DID for the universal resolver: {}", did);
+
+    let Some(representation) = Representation::negotiate(&req) else {
+        return resolution_failure(StatusCode::NOT_ACCEPTABLE, "representationNotSupported");
+    };
+    if let Err((status, error)) = check_did_syntax(&did) {
+        return resolution_failure(status, error);
+    }
+
+    let caching = match app_state.did_service.get_modification(&did).await {
+        // Requests are anonymous, so the response is the same for everyone
+        Ok(modification) => ResolutionCaching::new(&modification, false),
+        Err(AppError::NotFound(_)) => return resolution_failure(StatusCode::NOT_FOUND, "notFound"),
+        Err(e) => {
+            warn!("Failed to resolve {}: {}", did, e);
+            return resolution_failure(StatusCode::INTERNAL_SERVER_ERROR, "internalError");
+        }
+    };
+    if caching.is_fresh(&req) {
+        return caching
+            .apply(&mut HttpResponse::NotModified())
+            .append_header((header::VARY, "Accept"))
+            .finish();
+    }
+
+    match app_state.did_service.get_did_with_cid(&did).await {
+        Ok((mut document, cid)) => {
+            // Requests are anonymous, so embargoed documents are always stripped and
+            // encrypted fields always hidden
+            if document.is_embargoed(Utc::now()) {
+                document.strip_embargoed();
+            }
+            let (content_type, body) = match representation {
+                Representation::ResolutionResult => (
+                    DID_RESOLUTION_MEDIA_TYPE,
+                    app_state
+                        .did_service
+                        .present(
+                            &DidResolutionResult::resolved(document, cid, DID_LD_JSON_MEDIA_TYPE),
+                            &did,
+                            "/didDocument/metadata",
+                            None,
+                        )
+                        .await,
+                ),
+                Representation::Document(media_type) => (
+                    media_type.as_str(),
+                    app_state
+                        .did_service
+                        .present(&document, &did, "/metadata", None)
+                        .await
+                        .map(|document| media_type.render(document)),
+                ),
+            };
+            match body {
+                Ok(body) => caching
+                    .apply(&mut HttpResponse::Ok())
+                    .append_header((header::VARY, "Accept"))
+                    .content_type(content_type)
+                    .json(body),
+                Err(e) => {
+                    warn!("Failed to resolve {}: {}", did, e);
+                    resolution_failure(StatusCode::INTERNAL_SERVER_ERROR, "internalError")
+                }
+            }
+        }
+        Err(AppError::NotFound(_)) => resolution_failure(StatusCode::NOT_FOUND, "notFound"),
+        Err(e) => {
+            warn!("Failed to resolve {}: {}", did, e);
+            resolution_failure(StatusCode::INTERNAL_SERVER_ERROR, "internalError")
+        }
+    }
+}
+
+/// Initialize the Universal Resolver driver route, served outside the `/api` prefix
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/1.0/identifiers/{did}", web::get().to(resolve_identifier));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::did::DidModification;
+    use actix_web::test::TestRequest;
+
+    fn negotiate(accept: Option<&str>) -> Option<Representation> {
+        let req = match accept {
+            Some(accept) => TestRequest::default().insert_header((header::ACCEPT, accept)),
+            None => TestRequest::default(),
+        };
+        Representation::negotiate(&req.to_http_request())
+    }
+
+    #[test]
+    fn test_accept_selects_representation() {
+        assert_eq!(negotiate(None), Some(Representation::ResolutionResult));
+        assert_eq!(
+            negotiate(Some(DID_RESOLUTION_MEDIA_TYPE)),
+            Some(Representation::ResolutionResult)
+        );
+        assert_eq!(
+            negotiate(Some("application/json;q=0.5, application/did+ld+json")),
+            Some(Representation::Document(DocumentMediaType::JsonLd))
+        );
+        assert_eq!(
+            negotiate(Some("application/did+json")),
+            Some(Representation::Document(DocumentMediaType::Json))
+        );
+        assert_eq!(
+            negotiate(Some("*/*")),
+            Some(Representation::ResolutionResult)
+        );
+        assert_eq!(negotiate(Some("text/html")), None);
+    }
+
+    #[test]
+    fn test_document_media_type_defaults_to_json_ld() {
+        let negotiate = |accept: Option<&str>| {
+            let req = match accept {
+                Some(accept) => TestRequest::default().insert_header((header::ACCEPT, accept)),
+                None => TestRequest::default(),
+            };
+            DocumentMediaType::negotiate(&req.to_http_request())
+        };
+        assert_eq!(negotiate(None), Some(DocumentMediaType::JsonLd));
+        assert_eq!(
+            negotiate(Some("application/json")),
+            Some(DocumentMediaType::JsonLd)
+        );
+        assert_eq!(
+            negotiate(Some("application/did+ld+json;q=0.5, application/did+json")),
+            Some(DocumentMediaType::Json)
+        );
+        assert_eq!(negotiate(Some("text/html")), None);
+
+        let document = serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:bio:1",
+        });
+        assert_eq!(DocumentMediaType::JsonLd.render(document.clone()), document);
+        assert_eq!(
+            DocumentMediaType::Json.render(document),
+            serde_json::json!({ "id": "did:bio:1" })
+        );
+    }
+
+    #[test]
+    fn test_did_syntax_errors() {
+        assert!(check_did_syntax("did:bio:6f1c2a4e-7d8b-4c3a-9e2f-1a2b3c4d5e6f").is_ok());
+        assert_eq!(
+            check_did_syntax("did:web:example.com"),
+            Err((StatusCode::NOT_IMPLEMENTED, "methodNotSupported"))
+        );
+        assert_eq!(
+            check_did_syntax("did:bio:"),
+            Err((StatusCode::BAD_REQUEST, "invalidDid"))
+        );
+        assert_eq!(
+            check_did_syntax("not-a-did"),
+            Err((StatusCode::BAD_REQUEST, "invalidDid"))
+        );
+    }
+
+    #[test]
+    fn test_if_modified_since_matches_whole_seconds() {
+        let updated_at: chrono::DateTime<Utc> = "2030-01-01T00:00:00.500Z".parse().unwrap();
+        let caching = ResolutionCaching::new(
+            &DidModification {
+                updated_at,
+                embargo_until: None,
+            },
+            false,
+        );
+        let fresh = |since: Option<&str>| {
+            let req = match since {
+                Some(since) => {
+                    TestRequest::default().insert_header((header::IF_MODIFIED_SINCE, since))
+                }
+                None => TestRequest::default(),
+            };
+            caching.is_fresh(&req.to_http_request())
+        };
+
+        assert!(fresh(Some("Tue, 01 Jan 2030 00:00:00 GMT")));
+        assert!(fresh(Some("Tue, 01 Jan 2030 00:05:00 GMT")));
+        assert!(!fresh(Some("Mon, 31 Dec 2029 23:59:59 GMT")));
+        assert!(!fresh(Some("not a date")));
+        assert!(!fresh(None));
+    }
+}
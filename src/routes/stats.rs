@@ -0,0 +1,18 @@
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::routes::AppState;
+
+/// Totals of the caller's DIDs, papers, datasets, storage and tasks
+pub async fn get_stats(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let stats = app_state.stats_service.get_user_stats(user.id).await?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/me/stats", web::get().to(get_stats));
+}
@@ -0,0 +1,269 @@
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures::stream::{self, Stream};
+use log::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval_at, Instant, Interval};
+
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::models::task::{Task, TaskCursor, TaskType, TASK_STATUSES};
+use crate::routes::AppState;
+use crate::services::task_service::MAX_LISTED_TASKS;
+
+/// Interval of heartbeat comments on task event streams. Each heartbeat also re-reads the
+/// task, picking up changes made by other instances and by BioAgents.
+const TASK_EVENTS_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Query parameters for listing tasks
+#[derive(Deserialize, JsonSchema)]
+pub struct ListTasksQuery {
+    #[serde(rename = "type", alias = "task_type")]
+    pub task_type: Option<TaskType>,
+    // "pending", "processing", "completed" or "failed"
+    pub status: Option<String>,
+    // `next_cursor` from a previous page
+    pub cursor: Option<String>,
+    // Page size; when set (or with a cursor) the response is a `TaskPage`
+    pub limit: Option<u32>,
+}
+
+/// One page of tasks, newest first
+#[derive(Serialize, JsonSchema)]
+pub struct TaskPage {
+    pub tasks: Vec<Task>,
+    // Pass as `cursor` to fetch the next page; absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Task listing response: a plain array of the most recent tasks unless pagination was
+/// requested
+#[derive(Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ListTasksResponse {
+    All(Vec<Task>),
+    Page(TaskPage),
+}
+
+/// List the caller's upload, BioAgents and background tasks
+///
+/// Without `limit`/`cursor` the most recent tasks are returned as a plain array; with them
+/// the response is a `TaskPage` whose `next_cursor` continues where the page ended.
+pub async fn list_tasks(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    query: web::Query<ListTasksQuery>,
+) -> Result<impl Responder, AppError> {
+    info!("Listing tasks for user {}", user.id);
+
+    if query.limit == Some(0) || query.limit > Some(MAX_LISTED_TASKS) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_LISTED_TASKS
+        )));
+    }
+    if let Some(status) = query.status.as_deref() {
+        if !TASK_STATUSES.contains(&status) {
+            return Err(AppError::ValidationError(format!(
+                "Unknown task status '{}', expected one of: {}",
+                status,
+                TASK_STATUSES.join(", ")
+            )));
+        }
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            TaskCursor::decode(cursor)
+                .ok_or_else(|| AppError::ValidationError("Invalid cursor".to_string()))
+        })
+        .transpose()?;
+
+    let (tasks, next_cursor) = app_state
+        .task_service
+        .list_tasks(
+            user.id,
+            query.task_type,
+            query.status.as_deref(),
+            cursor.as_ref(),
+            query.limit.unwrap_or(MAX_LISTED_TASKS),
+        )
+        .await?;
+
+    let response = if query.limit.is_none() && query.cursor.is_none() {
+        ListTasksResponse::All(tasks)
+    } else {
+        ListTasksResponse::Page(TaskPage {
+            tasks,
+            next_cursor: next_cursor.map(|cursor| cursor.encode()),
+        })
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Get the status of a single task of either type
+pub async fn get_task(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let task_id = path.into_inner();
+    info!("Getting task {} for user {}", task_id, user.id);
+
+    let task = app_state.task_service.get_status(&task_id, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+/// Stream a task's status as server-sent events until it completes or fails
+///
+/// Every change is sent as a `task` event carrying the task, whose `id` is its status; the
+/// first event is the current state and the stream ends after the final one. Requests that
+/// don't accept `text/event-stream` get the current task as JSON, like `GET /tasks/{id}`.
+/// A reconnect whose `Last-Event-ID` is already the task's final status gets `204`, which
+/// tells `EventSource` to stop reconnecting.
+pub async fn task_events(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let task_id = path.into_inner();
+    let header_value = |name: &str| {
+        http_req
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let wants_events = header_value(header::ACCEPT.as_str())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+
+    // Subscribe before the first read so no change in between is missed
+    let receiver = app_state.task_service.subscribe(&task_id);
+    let task = app_state.task_service.get_status(&task_id, user.id).await?;
+
+    if !wants_events {
+        return Ok(HttpResponse::Ok().json(task));
+    }
+    if task.is_finished() && header_value("Last-Event-ID") == Some(task.status.as_str()) {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    info!("Streaming events of task {} to user {}", task_id, user.id);
+    let events = task_event_stream(TaskEventStream {
+        app_state: app_state.into_inner(),
+        user_id: user.id,
+        task_id,
+        receiver: Some(receiver),
+        heartbeat: interval_at(
+            Instant::now() + TASK_EVENTS_HEARTBEAT,
+            TASK_EVENTS_HEARTBEAT,
+        ),
+        pending: Some(task),
+        last_sent: None,
+        finished: false,
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Keep nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+/// State of one task event stream
+struct TaskEventStream {
+    app_state: Arc<AppState>,
+    user_id: i64,
+    task_id: String,
+    // `None` once the channel closed; heartbeats keep the stream updated
+    receiver: Option<broadcast::Receiver<()>>,
+    heartbeat: Interval,
+    // Task to send next
+    pending: Option<Task>,
+    // JSON of the last task sent, so unchanged reloads aren't repeated
+    last_sent: Option<String>,
+    finished: bool,
+}
+
+fn task_event_stream(state: TaskEventStream) -> impl Stream<Item = Result<Bytes, AppError>> {
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+
+            if let Some(task) = state.pending.take() {
+                let data = match serde_json::to_string(&task) {
+                    Ok(data) => data,
+                    Err(_) => return Some((Err(AppError::SerializationError), state)),
+                };
+                if state.last_sent.as_deref() == Some(data.as_str()) {
+                    continue;
+                }
+                state.finished = task.is_finished();
+                let event = format!("event: task\nid: {}\ndata: {}\n\n", task.status, data);
+                state.last_sent = Some(data);
+                return Some((Ok(Bytes::from(event)), state));
+            }
+
+            let signal = async {
+                match state.receiver.as_mut() {
+                    Some(receiver) => receiver.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let heartbeat = tokio::select! {
+                signal = signal => {
+                    if let Err(RecvError::Closed) = signal {
+                        state.receiver = None;
+                    }
+                    false
+                }
+                _ = state.heartbeat.tick() => true,
+            };
+
+            match state
+                .app_state
+                .task_service
+                .get_status(&state.task_id, state.user_id)
+                .await
+            {
+                Ok(task) => state.pending = Some(task),
+                Err(e) => {
+                    state.finished = true;
+                    let data = serde_json::json!({ "error": e.to_string() });
+                    let event = format!("event: error\ndata: {}\n\n", data);
+                    return Some((Ok(Bytes::from(event)), state));
+                }
+            }
+            if heartbeat
+                && state
+                    .pending
+                    .as_ref()
+                    .is_some_and(|task| serde_json::to_string(task).ok() == state.last_sent)
+            {
+                state.pending = None;
+                return Some((Ok(Bytes::from_static(b": heartbeat\n\n")), state));
+            }
+        }
+    })
+}
+
+/// Initialize task routes
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/tasks")
+            .route("", web::get().to(list_tasks))
+            .route("/{task_id}", web::get().to(get_task))
+            .route("/{task_id}/events", web::get().to(task_events)),
+    );
+}
@@ -0,0 +1,46 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::routes::AppState;
+use crate::services::step_up_service::TOTP_CODE_HEADER;
+
+/// TOTP code sent for an operation needing step-up, if any
+pub fn step_up_code(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(TOTP_CODE_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Code from the caller's authenticator app
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TotpVerifyRequest {
+    pub code: String,
+}
+
+/// Generate a TOTP secret for the caller; it is used for step-up once confirmed with
+/// `/me/totp/verify`
+pub async fn enroll(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let enrollment = app_state.step_up_service.enroll(&user).await?;
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+/// Check a TOTP code, confirming the caller's enrollment on first use
+pub async fn verify(
+    user: AuthUser,
+    app_state: web::Data<AppState>,
+    req: web::Json<TotpVerifyRequest>,
+) -> Result<impl Responder, AppError> {
+    let status = app_state.step_up_service.verify(&user, &req.code).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/me/totp/enroll", web::post().to(enroll))
+        .route("/me/totp/verify", web::post().to(verify));
+}
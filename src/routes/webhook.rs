@@ -0,0 +1,111 @@
+use actix_web::{web, HttpResponse, Responder};
+use log::info;
+
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use crate::models::webhook::WebhookRequest;
+use crate::routes::AppState;
+
+/// Register a webhook for DID lifecycle events
+///
+/// The response holds the signing secret; it is not returned again.
+pub async fn create_webhook(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    req: web::Json<WebhookRequest>,
+) -> Result<impl Responder, AppError> {
+    info!("User {} registering webhook", user.id);
+
+    let webhook = app_state
+        .webhook_service
+        .create_webhook(req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Created().json(webhook))
+}
+
+/// List the current user's webhooks
+pub async fn list_webhooks(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+) -> Result<impl Responder, AppError> {
+    let webhooks = app_state.webhook_service.list_webhooks(user.id).await?;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+/// Get a webhook
+pub async fn get_webhook(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let webhook = app_state
+        .webhook_service
+        .get_webhook(&path.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+/// Replace a webhook's URL, events and active flag
+pub async fn update_webhook(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<WebhookRequest>,
+) -> Result<impl Responder, AppError> {
+    let webhook_id = path.into_inner();
+    info!("User {} updating webhook {}", user.id, webhook_id);
+
+    let webhook = app_state
+        .webhook_service
+        .update_webhook(&webhook_id, req.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+/// Delete a webhook
+pub async fn delete_webhook(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let webhook_id = path.into_inner();
+    info!("User {} deleting webhook {}", user.id, webhook_id);
+
+    app_state
+        .webhook_service
+        .delete_webhook(&webhook_id, user.id)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List recent deliveries to a webhook and their status
+pub async fn list_deliveries(
+    app_state: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let deliveries = app_state
+        .webhook_service
+        .list_deliveries(&path.into_inner(), user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// Initialize webhook routes
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/webhooks")
+            .route("", web::post().to(create_webhook))
+            .route("", web::get().to(list_webhooks))
+            .route("/{webhook_id}", web::get().to(get_webhook))
+            .route("/{webhook_id}", web::put().to(update_webhook))
+            .route("/{webhook_id}", web::delete().to(delete_webhook))
+            .route("/{webhook_id}/deliveries", web::get().to(list_deliveries)),
+    );
+}
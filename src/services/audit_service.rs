@@ -0,0 +1,175 @@
+use crate::database::db_time::{from_db, to_db};
+use crate::errors::AppError;
+use crate::models::audit::{
+    AuditEntry, AuditLogPage, AuditQuery, DEFAULT_AUDIT_PAGE_SIZE, MAX_AUDIT_PAGE_SIZE,
+};
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use mysql_async::{prelude::*, Pool, Row};
+use std::sync::Arc;
+
+/// Records who changed what and when for compliance review
+pub struct AuditService {
+    db_pool: Arc<Pool>,
+}
+
+impl AuditService {
+    pub fn new(db_pool: Arc<Pool>) -> Self {
+        Self { db_pool }
+    }
+
+    /// Append an entry to the audit log.
+    ///
+    /// Failures are logged and swallowed so that auditing never fails the operation
+    /// being audited.
+    pub async fn record_audit(
+        &self,
+        user_id: i64,
+        action: &str,
+        resource_type: &str,
+        resource_id: &str,
+        detail: serde_json::Value,
+    ) {
+        if let Err(e) = self
+            .insert(user_id, action, resource_type, resource_id, &detail)
+            .await
+        {
+            warn!(
+                "Failed to record audit entry {} on {} {} by user {}: {}",
+                action, resource_type, resource_id, user_id, e
+            );
+        }
+    }
+
+    async fn insert(
+        &self,
+        user_id: i64,
+        action: &str,
+        resource_type: &str,
+        resource_id: &str,
+        detail: &serde_json::Value,
+    ) -> Result<(), AppError> {
+        let detail = serde_json::to_string(detail).map_err(|_| AppError::SerializationError)?;
+        let mut conn = self.db_pool.get_conn().await?;
+
+        r"INSERT INTO audit_log (user_id, action, resource_type, resource_id, timestamp, detail)
+          VALUES (:user_id, :action, :resource_type, :resource_id, :timestamp, :detail)"
+            .with(params! {
+                "user_id" => user_id,
+                "action" => action,
+                "resource_type" => resource_type,
+                "resource_id" => resource_id,
+                "timestamp" => to_db(Utc::now()),
+                "detail" => detail,
+            })
+            .run(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Query the audit log, newest entries first
+    pub async fn list(&self, query: &AuditQuery) -> Result<AuditLogPage, AppError> {
+        let page = query.page.unwrap_or(1).max(1);
+        let per_page = query
+            .per_page
+            .unwrap_or(DEFAULT_AUDIT_PAGE_SIZE)
+            .clamp(1, MAX_AUDIT_PAGE_SIZE);
+        let offset = u64::from(page - 1) * u64::from(per_page);
+
+        let filter = r"WHERE (:user_id IS NULL OR user_id = :user_id)
+              AND (:from_ts IS NULL OR timestamp >= :from_ts)
+              AND (:to_ts IS NULL OR timestamp < :to_ts)";
+        let format_ts = |t: &Option<DateTime<Utc>>| t.map(to_db);
+        let params = params! {
+            "user_id" => query.user_id,
+            "from_ts" => format_ts(&query.from),
+            "to_ts" => format_ts(&query.to),
+        };
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let total: Option<u64> = format!("SELECT COUNT(*) FROM audit_log {}", filter)
+            .with(params.clone())
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when counting audit entries: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let rows: Vec<Row> = format!(
+            r"SELECT id, user_id, action, resource_type, resource_id,
+                     DATE_FORMAT(timestamp, '%Y-%m-%d %H:%i:%s'), detail
+              FROM audit_log {} ORDER BY timestamp DESC, id DESC LIMIT {} OFFSET {}",
+            filter, per_page, offset
+        )
+        .with(params)
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing audit entries: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        Ok(AuditLogPage {
+            entries: rows
+                .into_iter()
+                .map(entry_from_row)
+                .collect::<Result<_, _>>()?,
+            page,
+            per_page,
+            total: total.unwrap_or(0),
+        })
+    }
+
+    /// Whether an audit entry for the resource recorded `cid`, i.e. the CID is a known
+    /// earlier version of it. Resources changed before auditing began have no entries.
+    pub async fn has_recorded_cid(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        cid: &str,
+    ) -> Result<bool, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let found: Option<i32> = r"SELECT 1 FROM audit_log
+              WHERE resource_type = :resource_type AND resource_id = :resource_id
+                AND JSON_UNQUOTE(JSON_EXTRACT(detail, '$.cid')) = :cid
+              LIMIT 1"
+            .with(params! {
+                "resource_type" => resource_type,
+                "resource_id" => resource_id,
+                "cid" => cid,
+            })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when searching audit entries: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        Ok(found.is_some())
+    }
+}
+
+fn entry_from_row(row: Row) -> Result<AuditEntry, AppError> {
+    let timestamp: String = row.get(5).unwrap_or_default();
+    let detail: Option<String> = row.get(6).flatten();
+
+    Ok(AuditEntry {
+        id: row.get(0).unwrap_or_default(),
+        user_id: row.get(1).unwrap_or_default(),
+        action: row.get(2).unwrap_or_default(),
+        resource_type: row.get(3).unwrap_or_default(),
+        resource_id: row.get(4).unwrap_or_default(),
+        timestamp: from_db(&timestamp).ok_or(AppError::DeserializationError)?,
+        detail: detail.and_then(|d| serde_json::from_str(&d).ok()),
+    })
+}
@@ -0,0 +1,588 @@
+use crate::errors::ServiceError;
+use crate::models::auth::{Claims, TokenHeader};
+use base64::engine::general_purpose::STANDARD as Base64Engine;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use ed25519_zebra::{Signature, SigningKey, VerificationKey};
+use pqcrypto_dilithium::dilithium5::{self, PublicKey, SecretKey};
+use pqcrypto_traits::sign::DetachedSignature as DetachedSignatureTrait;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{PoisonError, RwLock};
+use uuid::Uuid;
+
+/// Token type recorded in `TokenHeader.typ`
+const TOKEN_TYPE: &str = "PQC";
+const ED25519_SIGNATURE_LEN: usize = 64;
+const DILITHIUM_DIGEST_LEN: usize = 32;
+
+/// Version of the Dilithium5 key pair configured in `DILITHIUM_*_KEY`. Tokens without a
+/// `kid` predate key rotation and were signed with it.
+pub const INITIAL_KEY_VERSION: u32 = 1;
+
+/// Namespace of the signing contexts unless `TOKEN_SIGNING_NAMESPACE` is set
+pub const DEFAULT_SIGNING_NAMESPACE: &str = "bio-did-seq";
+
+/// Domain-separation context prefixed to the bytes a kind of token is signed over, e.g.
+/// `bio-did-seq:auth:v1`. Signatures only verify under the context they were made with, so
+/// an auth token can't be replayed as a UCAN, or a UCAN as an auth token, even when both
+/// are signed with the same key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningContext(String);
+
+impl SigningContext {
+    /// Context of auth tokens
+    pub fn auth(namespace: &str) -> Self {
+        Self(format!("{}:auth:v1", namespace))
+    }
+
+    /// Context of UCANs signed by this service
+    pub fn ucan(namespace: &str) -> Self {
+        Self(format!("{}:ucan:v1", namespace))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The bytes actually signed for `message`: the context, a NUL byte, then `message`
+    pub fn signing_input(&self, message: &[u8]) -> Vec<u8> {
+        let mut input = Vec::with_capacity(self.0.len() + 1 + message.len());
+        input.extend_from_slice(self.0.as_bytes());
+        input.push(0);
+        input.extend_from_slice(message);
+        input
+    }
+}
+
+/// Algorithms auth tokens can be signed with, named as they appear in `TokenHeader.alg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAlgorithm {
+    Ed25519,
+    Dilithium5,
+    // Both signatures; a token is only valid when both verify
+    Hybrid,
+}
+
+impl TokenAlgorithm {
+    pub const ALL: &'static [TokenAlgorithm] = &[
+        TokenAlgorithm::Ed25519,
+        TokenAlgorithm::Dilithium5,
+        TokenAlgorithm::Hybrid,
+    ];
+
+    /// Parse an algorithm name, case-insensitively. Anything else, including `none`,
+    /// is rejected.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        TokenAlgorithm::ALL
+            .iter()
+            .copied()
+            .find(|alg| alg.as_str().eq_ignore_ascii_case(value))
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported token algorithm '{}', expected one of: {}",
+                    value,
+                    TokenAlgorithm::ALL
+                        .iter()
+                        .map(TokenAlgorithm::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenAlgorithm::Ed25519 => "Ed25519",
+            TokenAlgorithm::Dilithium5 => "Dilithium5",
+            TokenAlgorithm::Hybrid => "Ed25519+Dilithium5",
+        }
+    }
+
+    fn uses_ed25519(&self) -> bool {
+        matches!(self, TokenAlgorithm::Ed25519 | TokenAlgorithm::Hybrid)
+    }
+
+    fn uses_dilithium(&self) -> bool {
+        matches!(self, TokenAlgorithm::Dilithium5 | TokenAlgorithm::Hybrid)
+    }
+}
+
+impl fmt::Display for TokenAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Dilithium5 key pairs by version. The current one signs new tokens; all of them verify.
+pub struct DilithiumKeyring {
+    current: u32,
+    keys: HashMap<u32, (PublicKey, SecretKey)>,
+}
+
+impl DilithiumKeyring {
+    /// Fails unless `keys` contains the `current` version
+    pub fn new(
+        current: u32,
+        keys: HashMap<u32, (PublicKey, SecretKey)>,
+    ) -> Result<Self, ServiceError> {
+        if !keys.contains_key(&current) {
+            return Err(ServiceError::Internal(format!(
+                "Current signing key version {} is missing from the keyring",
+                current
+            )));
+        }
+        Ok(Self { current, keys })
+    }
+
+    /// Keyring of just the configured key pair, as `INITIAL_KEY_VERSION`
+    pub fn single(keys: (PublicKey, SecretKey)) -> Self {
+        Self {
+            current: INITIAL_KEY_VERSION,
+            keys: HashMap::from([(INITIAL_KEY_VERSION, keys)]),
+        }
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.current
+    }
+}
+
+/// Signs and verifies auth tokens (`header.payload.signature`, each part base64).
+///
+/// New tokens are signed with the configured algorithm. Verification dispatches on the
+/// token's `alg` and only accepts algorithms in the allow-list, so a token can't pick a
+/// weaker algorithm (or `none`) than the service trusts.
+///
+/// The signature part is the raw Ed25519 signature, the SHA-256 digest of the (deterministic)
+/// Dilithium5 signature, or both concatenated in that order for the hybrid. Tokens using
+/// Dilithium5 name the key version they were signed with in `TokenHeader.kid`, so they stay
+/// valid after a rotation until that version is retired. All signatures are made over the
+/// message prefixed with the auth `SigningContext`.
+pub struct TokenSigner {
+    algorithm: TokenAlgorithm,
+    context: SigningContext,
+    allowed: Vec<TokenAlgorithm>,
+    // Replaced as a whole when keys are rotated or retired
+    dilithium: RwLock<Option<DilithiumKeyring>>,
+    ed25519: Option<(SigningKey, VerificationKey)>,
+}
+
+impl TokenSigner {
+    /// Fails if the signing algorithm isn't allowed, or a key needed by an allowed
+    /// algorithm is missing
+    pub fn new(
+        algorithm: TokenAlgorithm,
+        allowed: Vec<TokenAlgorithm>,
+        context: SigningContext,
+        dilithium: Option<DilithiumKeyring>,
+        ed25519: Option<SigningKey>,
+    ) -> Result<Self, ServiceError> {
+        if !allowed.contains(&algorithm) {
+            return Err(ServiceError::Internal(format!(
+                "Token signing algorithm {} is not in the allowed algorithms",
+                algorithm
+            )));
+        }
+        if allowed.iter().any(TokenAlgorithm::uses_dilithium) && dilithium.is_none() {
+            return Err(ServiceError::Internal(
+                "A Dilithium5 key pair is required for the allowed token algorithms".to_string(),
+            ));
+        }
+        if allowed.iter().any(TokenAlgorithm::uses_ed25519) && ed25519.is_none() {
+            return Err(ServiceError::Internal(
+                "An Ed25519 signing key is required for the allowed token algorithms".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            algorithm,
+            context,
+            allowed,
+            dilithium: RwLock::new(dilithium),
+            ed25519: ed25519.map(|key| {
+                let verification_key = VerificationKey::from(&key);
+                (key, verification_key)
+            }),
+        })
+    }
+
+    pub fn algorithm(&self) -> TokenAlgorithm {
+        self.algorithm
+    }
+
+    pub fn context(&self) -> &SigningContext {
+        &self.context
+    }
+
+    /// Sign new tokens with the keyring's current key and verify against its versions only
+    pub fn install_dilithium_keys(&self, keyring: DilithiumKeyring) {
+        *self
+            .dilithium
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(keyring);
+    }
+
+    /// Issues a token for `user_id` valid for `duration`
+    pub fn sign(&self, user_id: i32, duration: Duration) -> Result<String, ServiceError> {
+        let kid = if self.algorithm.uses_dilithium() {
+            Some(self.current_key_version()?)
+        } else {
+            None
+        };
+        let header = TokenHeader {
+            alg: self.algorithm.as_str().to_string(),
+            typ: TOKEN_TYPE.to_string(),
+            nonce: Uuid::new_v4().to_string(),
+            kid,
+        };
+
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp: (Utc::now() + duration).timestamp() as usize,
+            signature: Vec::new(),
+            iat: Utc::now().timestamp() as usize,
+            nonce: Uuid::new_v4().to_string(),
+        };
+
+        let header_json = serde_json::to_string(&header)
+            .map_err(|e| ServiceError::Internal(format!("Failed to serialize header: {}", e)))?;
+        let payload_json = serde_json::to_string(&claims)
+            .map_err(|e| ServiceError::Internal(format!("Failed to serialize claims: {}", e)))?;
+        let header_encoded = Base64Engine.encode(header_json);
+        let payload_encoded = Base64Engine.encode(payload_json);
+
+        let message = self
+            .context
+            .signing_input(format!("{}.{}", header_encoded, payload_encoded).as_bytes());
+        let signature = self.signature(self.algorithm, &message, kid)?;
+
+        Ok(format!(
+            "{}.{}.{}",
+            header_encoded,
+            payload_encoded,
+            Base64Engine.encode(signature)
+        ))
+    }
+
+    /// Verifies a token's algorithm, signature and expiry and returns its claims
+    pub fn verify(&self, token: &str) -> Result<Claims, ServiceError> {
+        let parts: Vec<&str> = token.split('.').collect();
+        if parts.len() != 3 {
+            return Err(ServiceError::Auth("Invalid token format".to_string()));
+        }
+
+        let header_encoded = parts[0];
+        let payload_encoded = parts[1];
+        let signature_encoded = parts[2];
+
+        let header_json = Base64Engine
+            .decode(header_encoded)
+            .map_err(|e| ServiceError::Auth(format!("Failed to decode header: {}", e)))?;
+        let header: TokenHeader = serde_json::from_slice(&header_json)
+            .map_err(|e| ServiceError::Auth(format!("Failed to parse header: {}", e)))?;
+        if header.typ != TOKEN_TYPE {
+            return Err(ServiceError::Auth("Unsupported token type".to_string()));
+        }
+        // Exact match: the header names an allowed algorithm or the token is rejected
+        // before its signature is looked at
+        let algorithm = self
+            .allowed
+            .iter()
+            .copied()
+            .find(|alg| alg.as_str() == header.alg)
+            .ok_or_else(|| {
+                ServiceError::Auth(format!("Token algorithm '{}' is not allowed", header.alg))
+            })?;
+
+        let provided = Base64Engine
+            .decode(signature_encoded)
+            .map_err(|e| ServiceError::Auth(format!("Failed to decode signature: {}", e)))?;
+        let message = self
+            .context
+            .signing_input(format!("{}.{}", header_encoded, payload_encoded).as_bytes());
+        let kid = header.kid.unwrap_or(INITIAL_KEY_VERSION);
+        self.check_signature(algorithm, &message, &provided, kid)?;
+
+        let payload_json = Base64Engine
+            .decode(payload_encoded)
+            .map_err(|e| ServiceError::Auth(format!("Failed to decode payload: {}", e)))?;
+        let mut claims: Claims = serde_json::from_slice(&payload_json)
+            .map_err(|e| ServiceError::Auth(format!("Failed to parse claims: {}", e)))?;
+
+        if claims.exp < Utc::now().timestamp() as usize {
+            return Err(ServiceError::Auth("Token expired".to_string()));
+        }
+
+        claims.signature = Vec::new();
+        Ok(claims)
+    }
+
+    fn signature(
+        &self,
+        algorithm: TokenAlgorithm,
+        message: &[u8],
+        kid: Option<u32>,
+    ) -> Result<Vec<u8>, ServiceError> {
+        let mut signature = Vec::new();
+        if algorithm.uses_ed25519() {
+            signature.extend_from_slice(&self.ed25519_signature(message)?);
+        }
+        if let (true, Some(kid)) = (algorithm.uses_dilithium(), kid) {
+            signature.extend_from_slice(&self.dilithium_digest(message, kid)?);
+        }
+        Ok(signature)
+    }
+
+    fn check_signature(
+        &self,
+        algorithm: TokenAlgorithm,
+        message: &[u8],
+        provided: &[u8],
+        kid: u32,
+    ) -> Result<(), ServiceError> {
+        let expected_len = match algorithm {
+            TokenAlgorithm::Ed25519 => ED25519_SIGNATURE_LEN,
+            TokenAlgorithm::Dilithium5 => DILITHIUM_DIGEST_LEN,
+            TokenAlgorithm::Hybrid => ED25519_SIGNATURE_LEN + DILITHIUM_DIGEST_LEN,
+        };
+        if provided.len() != expected_len {
+            return Err(ServiceError::Auth("Invalid signature length".to_string()));
+        }
+
+        let (ed25519_part, dilithium_part) = if algorithm.uses_ed25519() {
+            provided.split_at(ED25519_SIGNATURE_LEN)
+        } else {
+            provided.split_at(0)
+        };
+
+        if algorithm.uses_ed25519() {
+            let (_, verification_key) = self.ed25519_keys()?;
+            let mut signature = [0u8; ED25519_SIGNATURE_LEN];
+            signature.copy_from_slice(ed25519_part);
+            verification_key
+                .verify(&Signature::from(signature), message)
+                .map_err(|_| ServiceError::Auth("Signature verification failed".to_string()))?;
+        }
+        if algorithm.uses_dilithium() {
+            // Dilithium5 signing is deterministic, so re-signing reproduces the digest
+            if dilithium_part != self.dilithium_digest(message, kid)?.as_slice() {
+                return Err(ServiceError::Auth("Invalid signature hash".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn ed25519_signature(
+        &self,
+        message: &[u8],
+    ) -> Result<[u8; ED25519_SIGNATURE_LEN], ServiceError> {
+        let (signing_key, _) = self.ed25519_keys()?;
+        Ok(signing_key.sign(message).into())
+    }
+
+    fn current_key_version(&self) -> Result<u32, ServiceError> {
+        self.dilithium
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map(DilithiumKeyring::current_version)
+            .ok_or_else(|| ServiceError::Internal("Dilithium5 key pair not configured".to_string()))
+    }
+
+    /// Digest of the signature of `message` by key version `kid`
+    fn dilithium_digest(&self, message: &[u8], kid: u32) -> Result<Vec<u8>, ServiceError> {
+        let keyring = self
+            .dilithium
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let keyring = keyring.as_ref().ok_or_else(|| {
+            ServiceError::Internal("Dilithium5 key pair not configured".to_string())
+        })?;
+        let (public_key, secret_key) = keyring.keys.get(&kid).ok_or_else(|| {
+            ServiceError::Auth(format!("Signing key version {} is unknown or retired", kid))
+        })?;
+        let signature = dilithium5::detached_sign(message, secret_key);
+        if dilithium5::verify_detached_signature(&signature, message, public_key).is_err() {
+            return Err(ServiceError::Internal(
+                "Dilithium5 key pair does not match".to_string(),
+            ));
+        }
+        Ok(Sha256::digest(signature.as_bytes()).to_vec())
+    }
+
+    fn ed25519_keys(&self) -> Result<&(SigningKey, VerificationKey), ServiceError> {
+        self.ed25519
+            .as_ref()
+            .ok_or_else(|| ServiceError::Internal("Ed25519 signing key not configured".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ed25519_signer() -> TokenSigner {
+        TokenSigner::new(
+            TokenAlgorithm::Ed25519,
+            vec![TokenAlgorithm::Ed25519],
+            SigningContext::auth(DEFAULT_SIGNING_NAMESPACE),
+            None,
+            Some(SigningKey::new(rand_core::OsRng)),
+        )
+        .unwrap()
+    }
+
+    /// Re-encodes `token` with its header's `alg` replaced
+    fn with_alg(token: &str, alg: &str) -> String {
+        let parts: Vec<&str> = token.split('.').collect();
+        let mut header: serde_json::Value =
+            serde_json::from_slice(&Base64Engine.decode(parts[0]).unwrap()).unwrap();
+        header["alg"] = serde_json::Value::String(alg.to_string());
+        format!(
+            "{}.{}.{}",
+            Base64Engine.encode(header.to_string()),
+            parts[1],
+            parts[2]
+        )
+    }
+
+    #[test]
+    fn test_token_round_trip_records_alg() {
+        let signer = ed25519_signer();
+        let token = signer.sign(42, Duration::hours(1)).unwrap();
+
+        let header: TokenHeader = serde_json::from_slice(
+            &Base64Engine
+                .decode(token.split('.').next().unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(header.alg, "Ed25519");
+        assert_eq!(signer.verify(&token).unwrap().sub, "42");
+    }
+
+    #[test]
+    fn test_alg_none_is_rejected() {
+        let signer = ed25519_signer();
+        let token = signer.sign(42, Duration::hours(1)).unwrap();
+        let payload = token.split('.').nth(1).unwrap();
+
+        for forged in [
+            with_alg(&token, "none"),
+            format!("{}.", with_alg(&token, "none").rsplit_once('.').unwrap().0),
+            format!(
+                "{}.{}.",
+                Base64Engine.encode(r#"{"alg":"none","typ":"PQC","nonce":"n"}"#),
+                payload
+            ),
+        ] {
+            match signer.verify(&forged) {
+                Err(ServiceError::Auth(message)) => assert!(message.contains("not allowed")),
+                other => panic!("alg: none accepted: {:?}", other.map(|c| c.sub)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_alg_outside_allow_list_is_rejected() {
+        let signer = ed25519_signer();
+        let token = signer.sign(42, Duration::hours(1)).unwrap();
+
+        assert!(matches!(
+            signer.verify(&with_alg(&token, "Dilithium5")),
+            Err(ServiceError::Auth(_))
+        ));
+    }
+
+    #[test]
+    fn test_signatures_are_bound_to_their_context() {
+        let signer = |context| {
+            TokenSigner::new(
+                TokenAlgorithm::Ed25519,
+                vec![TokenAlgorithm::Ed25519],
+                context,
+                None,
+                Some(SigningKey::from([7u8; 32])),
+            )
+            .unwrap()
+        };
+        let auth = signer(SigningContext::auth(DEFAULT_SIGNING_NAMESPACE));
+        let ucan = signer(SigningContext::ucan(DEFAULT_SIGNING_NAMESPACE));
+
+        // Same key, different token type
+        let token = ucan.sign(42, Duration::hours(1)).unwrap();
+        assert_eq!(ucan.verify(&token).unwrap().sub, "42");
+        assert!(matches!(auth.verify(&token), Err(ServiceError::Auth(_))));
+
+        // Nor does a signature over the bare message verify
+        let (message, _) = token.rsplit_once('.').unwrap();
+        let bare: [u8; ED25519_SIGNATURE_LEN] =
+            SigningKey::from([7u8; 32]).sign(message.as_bytes()).into();
+        let forged = format!("{}.{}", message, Base64Engine.encode(bare));
+        assert!(matches!(auth.verify(&forged), Err(ServiceError::Auth(_))));
+    }
+
+    #[test]
+    fn test_hybrid_token_needs_both_signatures() {
+        let signer = TokenSigner::new(
+            TokenAlgorithm::Hybrid,
+            vec![TokenAlgorithm::Hybrid],
+            SigningContext::auth(DEFAULT_SIGNING_NAMESPACE),
+            Some(DilithiumKeyring::single(dilithium5::keypair())),
+            Some(SigningKey::new(rand_core::OsRng)),
+        )
+        .unwrap();
+        let token = signer.sign(7, Duration::hours(1)).unwrap();
+        assert_eq!(signer.verify(&token).unwrap().sub, "7");
+
+        // Flip a byte of the Dilithium5 digest while the Ed25519 signature stays valid
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let mut signature = Base64Engine.decode(signature).unwrap();
+        *signature.last_mut().unwrap() ^= 1;
+        let tampered = format!("{}.{}", message, Base64Engine.encode(signature));
+        assert!(matches!(
+            signer.verify(&tampered),
+            Err(ServiceError::Auth(_))
+        ));
+    }
+
+    #[test]
+    fn test_tokens_survive_rotation_until_retired() {
+        let initial = dilithium5::keypair();
+        let signer = TokenSigner::new(
+            TokenAlgorithm::Dilithium5,
+            vec![TokenAlgorithm::Dilithium5],
+            SigningContext::auth(DEFAULT_SIGNING_NAMESPACE),
+            Some(DilithiumKeyring::single(initial)),
+            None,
+        )
+        .unwrap();
+        let before = signer.sign(1, Duration::hours(1)).unwrap();
+
+        let rotated = dilithium5::keypair();
+        signer.install_dilithium_keys(
+            DilithiumKeyring::new(2, HashMap::from([(1, initial), (2, rotated)])).unwrap(),
+        );
+        let after = signer.sign(2, Duration::hours(1)).unwrap();
+        let header: TokenHeader = serde_json::from_slice(
+            &Base64Engine
+                .decode(after.split('.').next().unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(header.kid, Some(2));
+        assert_eq!(signer.verify(&before).unwrap().sub, "1");
+        assert_eq!(signer.verify(&after).unwrap().sub, "2");
+
+        // Retiring version 1
+        signer.install_dilithium_keys(
+            DilithiumKeyring::new(2, HashMap::from([(2, rotated)])).unwrap(),
+        );
+        assert!(matches!(signer.verify(&before), Err(ServiceError::Auth(_))));
+        assert_eq!(signer.verify(&after).unwrap().sub, "2");
+        assert!(DilithiumKeyring::new(3, HashMap::from([(2, rotated)])).is_err());
+    }
+}
@@ -1,21 +1,62 @@
 use crate::errors::AppError;
-use log::{error, info};
-use reqwest::Client;
+use crate::services::circuit_breaker::{CircuitBreaker, CircuitStatus};
+use crate::services::http_client::HttpClient;
+use crate::services::metrics_service::MetricsService;
+use base64::engine::general_purpose::STANDARD as Base64Engine;
+use base64::Engine;
+use ed25519_zebra::{Signature, VerificationKey};
+use futures_util::stream::{self, StreamExt};
+use log::{error, info, warn};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Merging many graphs takes longer than the client's default timeout allows
+const KNOWLEDGE_GRAPH_MERGE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Status lookups of a batch sent to BioAgents at once
+const STATUS_BATCH_CONCURRENCY: usize = 8;
+
+/// Base64 Ed25519 signature over the raw response body
+pub const SIGNATURE_HEADER: &str = "X-BioAgents-Signature";
+
+/// Answer to a natural language query, with a token for further sources if available
+#[derive(Debug, Clone)]
+pub struct AgentAnswer {
+    pub answer: String,
+    pub sources: Vec<String>,
+    pub next_page_token: Option<String>,
+}
 
 /// Health status of the BioAgents system
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HealthStatus {
     pub agents_online: i32,
     pub service_status: String,
     pub last_updated: String,
+    // Paper-processing and knowledge-graph calls this service has in flight, and the cap
+    pub in_flight_requests: usize,
+    pub max_concurrent_requests: usize,
 }
 
 /// BioAgents service for interacting with BioAgents API
 pub struct BioAgentsService {
-    client: Client,
+    client: HttpClient,
     api_url: String,
+    // Task statuses and extracted metadata must be signed with this key when set
+    response_key: Option<VerificationKey>,
+    metrics: Arc<MetricsService>,
+    // Fails calls fast while BioAgents is down
+    circuit: CircuitBreaker,
+    // Limits concurrent heavy calls so bursts of submissions can't overwhelm BioAgents
+    slots: Semaphore,
+    max_concurrent: usize,
+    queue_timeout: Duration,
 }
 
 /// Request body for processing a paper through BioAgents
@@ -29,15 +70,23 @@ pub struct ProcessPaperRequest {
     pub generate_knowledge_graph: bool,
 }
 
+impl ProcessPaperRequest {
+    /// Hex SHA-256 of the request, identifying what BioAgents was asked to do
+    pub fn options_hash(&self) -> String {
+        let body = serde_json::to_vec(self).expect("ProcessPaperRequest serializes");
+        format!("{:x}", Sha256::digest(&body))
+    }
+}
+
 /// Response from BioAgents paper processing
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct ProcessPaperResponse {
     pub task_id: String,
     pub status: String,
 }
 
 /// Metadata extracted by BioAgents
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct ExtractedMetadata {
     pub title: String,
     pub authors: Vec<String>,
@@ -47,10 +96,16 @@ pub struct ExtractedMetadata {
     pub journal: Option<String>,
     pub doi: Option<String>,
     pub biological_entities: Vec<BiologicalEntity>,
+    // Keywords were extracted locally because BioAgents returned none
+    #[serde(default)]
+    pub keywords_auto_generated: bool,
+    // More personal data was found than `PII_THRESHOLD` allows and `PII_ACTION` is `flag`
+    #[serde(default)]
+    pub needs_review: bool,
 }
 
 /// Biological entity identified in the paper
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct BiologicalEntity {
     // e.g., "gene", "protein", "disease", etc.
     pub entity_type: String,
@@ -60,10 +115,13 @@ pub struct BiologicalEntity {
     // e.g., "UniProt", "NCBI", etc.
     pub source: Option<String>,
     pub mentions: Vec<EntityMention>,
+    // 0.0 to 1.0, only when reported by BioAgents
+    #[serde(default)]
+    pub confidence: Option<f32>,
 }
 
 /// Mention of a biological entity in the paper
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct EntityMention {
     pub text: String,
     pub start_pos: Option<i32>,
@@ -73,7 +131,7 @@ pub struct EntityMention {
 }
 
 /// Status of a BioAgents task
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct TaskStatus {
     pub task_id: String,
     // "pending", "processing", "completed", "failed"
@@ -84,38 +142,117 @@ pub struct TaskStatus {
     pub error: Option<String>,
 }
 
+/// Statuses of a batch of tasks. Tasks whose status couldn't be read, e.g. because
+/// BioAgents doesn't know them, are listed in `errors` instead of failing the batch.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct TaskStatusBatch {
+    pub statuses: HashMap<String, TaskStatus>,
+    // Task ID to the reason its status is missing
+    pub errors: HashMap<String, String>,
+}
+
 impl BioAgentsService {
     /// Create a new BioAgents service
-    pub fn new(api_url: &str) -> Self {
-        // Create HTTP client with appropriate timeouts
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .connect_timeout(Duration::from_secs(5))
-            .build()
-            .expect("Failed to create HTTP client");
-
+    pub fn new(
+        client: &HttpClient,
+        api_url: &str,
+        response_key: Option<VerificationKey>,
+        metrics: Arc<MetricsService>,
+        circuit: CircuitBreaker,
+        max_concurrent: usize,
+        queue_timeout: Duration,
+    ) -> Self {
         Self {
-            client,
+            client: client.with_timeout(Duration::from_secs(30)),
             api_url: api_url.to_string(),
+            response_key,
+            metrics,
+            circuit,
+            slots: Semaphore::new(max_concurrent),
+            max_concurrent,
+            queue_timeout,
+        }
+    }
+
+    /// State of the circuit breaker in front of BioAgents
+    pub fn circuit_status(&self) -> CircuitStatus {
+        self.circuit.status()
+    }
+
+    /// Number of heavy BioAgents calls currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.slots.available_permits()
+    }
+
+    /// Wait up to the queue timeout for a free BioAgents slot, held until the permit drops
+    async fn acquire_slot(&self) -> Result<SemaphorePermit<'_>, AppError> {
+        match tokio::time::timeout(self.queue_timeout, self.slots.acquire()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) | Err(_) => {
+                warn!(
+                    "BioAgents busy: {} calls in flight, no slot freed within {:?}",
+                    self.in_flight(),
+                    self.queue_timeout
+                );
+                Err(AppError::ServiceError("BioAgents busy".to_string()))
+            }
+        }
+    }
+
+    /// Build an `ExternalServiceError` and count it against the BioAgents upstream
+    pub fn upstream_error(&self, message: String) -> AppError {
+        self.metrics.record_external_error("bioagents");
+        AppError::ExternalServiceError(message)
+    }
+
+    /// Parse a successful response, first checking its signature if a response key is set.
+    ///
+    /// A missing or wrong signature is rejected like any other upstream failure, so a
+    /// spoofed BioAgents can't get entities or metadata into DID documents.
+    async fn verified_json<T: DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+        what: &str,
+    ) -> Result<T, AppError> {
+        let signature = response
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await.map_err(|e| {
+            error!("Failed to read {} response: {}", what, e);
+            self.upstream_error("BioAgents service unavailable".to_string())
+        })?;
+
+        if let Some(key) = &self.response_key {
+            verify_signature(key, signature.as_deref(), &body).map_err(|reason| {
+                error!("Rejected BioAgents {} response: {}", what, reason);
+                self.upstream_error(format!("BioAgents response {}", reason))
+            })?;
         }
+
+        serde_json::from_slice(&body).map_err(|e| {
+            error!("Failed to parse {} response: {}", what, e);
+            AppError::DeserializationError
+        })
     }
 
     /// Process a paper through BioAgents for metadata extraction and knowledge graph generation
+    #[tracing::instrument(skip_all, fields(file_cid = %request.file_cid))]
     pub async fn process_paper(
         &self,
         request: ProcessPaperRequest,
     ) -> Result<ProcessPaperResponse, AppError> {
+        let _slot = self.acquire_slot().await?;
         let url = format!("{}/api/process-paper", self.api_url);
 
         let response = self
-            .client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
+            .circuit
+            .send(self.client.post(&url).json(&request))
+            .await?
             .map_err(|e| {
                 error!("Failed to send request to BioAgents: {}", e);
-                AppError::ExternalServiceError("BioAgents service unavailable".to_string())
+                self.upstream_error("BioAgents service unavailable".to_string())
             })?;
 
         if !response.status().is_success() {
@@ -125,10 +262,7 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
         let process_response: ProcessPaperResponse = response.json().await.map_err(|e| {
@@ -145,14 +279,22 @@ impl BioAgentsService {
     }
 
     /// Check the status of a paper processing task
+    #[tracing::instrument(skip(self))]
     pub async fn check_task_status(&self, task_id: &str) -> Result<TaskStatus, AppError> {
         let url = format!("{}/api/task-status/{}", self.api_url, task_id);
 
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("Failed to check task status: {}", e);
-            AppError::ExternalServiceError("BioAgents service unavailable".to_string())
-        })?;
+        let response = self
+            .circuit
+            .send(self.client.get(&url))
+            .await?
+            .map_err(|e| {
+                error!("Failed to check task status: {}", e);
+                self.upstream_error("BioAgents service unavailable".to_string())
+            })?;
 
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(AppError::NotFound(format!("Unknown task: {}", task_id)));
+        }
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response
@@ -160,18 +302,37 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
-        let task_status: TaskStatus = response.json().await.map_err(|e| {
-            error!("Failed to parse task status response: {}", e);
-            AppError::DeserializationError
-        })?;
+        self.verified_json(response, "task status").await
+    }
 
-        Ok(task_status)
+    /// Check the status of several tasks, looking them up concurrently. Repeated IDs are
+    /// looked up once. Callers bound the batch size.
+    pub async fn check_task_statuses(&self, task_ids: &[String]) -> TaskStatusBatch {
+        let unique: HashSet<&str> = task_ids.iter().map(String::as_str).collect();
+        let results: Vec<(String, Result<TaskStatus, AppError>)> =
+            stream::iter(unique)
+                .map(|task_id| async move {
+                    (task_id.to_string(), self.check_task_status(task_id).await)
+                })
+                .buffer_unordered(STATUS_BATCH_CONCURRENCY)
+                .collect()
+                .await;
+
+        let mut batch = TaskStatusBatch::default();
+        for (task_id, result) in results {
+            match result {
+                Ok(status) => {
+                    batch.statuses.insert(task_id, status);
+                }
+                Err(e) => {
+                    batch.errors.insert(task_id, e.to_string());
+                }
+            }
+        }
+        batch
     }
 
     /// Get extracted metadata for a completed task
@@ -181,10 +342,14 @@ impl BioAgentsService {
     ) -> Result<ExtractedMetadata, AppError> {
         let url = format!("{}/api/metadata/{}", self.api_url, task_id);
 
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("Failed to get extracted metadata: {}", e);
-            AppError::ExternalServiceError("BioAgents service unavailable".to_string())
-        })?;
+        let response = self
+            .circuit
+            .send(self.client.get(&url))
+            .await?
+            .map_err(|e| {
+                error!("Failed to get extracted metadata: {}", e);
+                self.upstream_error("BioAgents service unavailable".to_string())
+            })?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -193,36 +358,51 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
-        let metadata: ExtractedMetadata = response.json().await.map_err(|e| {
-            error!("Failed to parse metadata response: {}", e);
-            AppError::DeserializationError
-        })?;
-
-        Ok(metadata)
+        self.verified_json(response, "metadata").await
     }
 
     /// Search for related biological entities
+    ///
+    /// `entity_types` and `min_confidence` are forwarded upstream and also applied to the
+    /// results, in case BioAgents ignores them. Entities without a confidence score are
+    /// never dropped by `min_confidence`.
+    ///
+    /// With a `limit`, one extra entity is requested to detect whether another page exists;
+    /// its offset is returned alongside the results.
     pub async fn search_related_entities(
         &self,
         query: &str,
-    ) -> Result<Vec<BiologicalEntity>, AppError> {
+        entity_types: &[String],
+        min_confidence: Option<f32>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<(Vec<BiologicalEntity>, Option<u32>), AppError> {
         let url = format!("{}/api/search", self.api_url);
 
+        let mut params = vec![("q", query.to_string())];
+        if !entity_types.is_empty() {
+            params.push(("entity_types", entity_types.join(",")));
+        }
+        if let Some(min_confidence) = min_confidence {
+            params.push(("min_confidence", min_confidence.to_string()));
+        }
+        if let Some(limit) = limit {
+            params.push(("limit", (limit + 1).to_string()));
+        }
+        if let Some(offset) = offset {
+            params.push(("offset", offset.to_string()));
+        }
+
         let response = self
-            .client
-            .get(&url)
-            .query(&[("q", query)])
-            .send()
-            .await
+            .circuit
+            .send(self.client.get(&url).query(&params))
+            .await?
             .map_err(|e| {
                 error!("Failed to search related entities: {}", e);
-                AppError::ExternalServiceError("BioAgents service unavailable".to_string())
+                self.upstream_error("BioAgents service unavailable".to_string())
             })?;
 
         if !response.status().is_success() {
@@ -232,33 +412,54 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
-        let entities: Vec<BiologicalEntity> = response.json().await.map_err(|e| {
+        let mut entities: Vec<BiologicalEntity> = response.json().await.map_err(|e| {
             error!("Failed to parse search response: {}", e);
             AppError::DeserializationError
         })?;
 
-        Ok(entities)
+        entities.retain(|entity| {
+            let type_matches = entity_types.is_empty()
+                || entity_types
+                    .iter()
+                    .any(|t| t.eq_ignore_ascii_case(&entity.entity_type));
+            let confident_enough = match (min_confidence, entity.confidence) {
+                (Some(min), Some(confidence)) => confidence >= min,
+                _ => true,
+            };
+            type_matches && confident_enough
+        });
+
+        let next_offset = match limit {
+            Some(limit) if entities.len() > limit as usize => {
+                entities.truncate(limit as usize);
+                Some(offset.unwrap_or(0) + limit)
+            }
+            _ => None,
+        };
+
+        Ok((entities, next_offset))
     }
 
     /// Generate a knowledge graph from a research paper
+    #[tracing::instrument(skip(self))]
     pub async fn generate_knowledge_graph(&self, cid: &str) -> Result<String, AppError> {
+        let _slot = self.acquire_slot().await?;
         let url = format!("{}/api/knowledge-graph", self.api_url);
 
         let response = self
-            .client
-            .post(&url)
-            .json(&serde_json::json!({ "cid": cid }))
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .json(&serde_json::json!({ "cid": cid })),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to generate knowledge graph: {}", e);
-                AppError::ExternalServiceError("BioAgents service unavailable".to_string())
+                self.upstream_error("BioAgents service unavailable".to_string())
             })?;
 
         if !response.status().is_success() {
@@ -268,10 +469,7 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
         // The response contains a knowledge graph in RDF format
@@ -283,25 +481,83 @@ impl BioAgentsService {
         Ok(knowledge_graph)
     }
 
+    /// Merge knowledge graphs stored on IPFS into one graph, in RDF format.
+    ///
+    /// BioAgents takes the union of the graphs and collapses entities that appear in
+    /// several of them into a single node. Callers store the result.
+    #[tracing::instrument(skip(self))]
+    pub async fn merge_knowledge_graphs(&self, cids: &[String]) -> Result<String, AppError> {
+        let _slot = self.acquire_slot().await?;
+        let url = format!("{}/api/knowledge-graph/merge", self.api_url);
+
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .timeout(KNOWLEDGE_GRAPH_MERGE_TIMEOUT)
+                    .json(&serde_json::json!({
+                        "cids": cids,
+                        "deduplicate_entities": true,
+                    })),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to merge knowledge graphs: {}", e);
+                self.upstream_error("BioAgents service unavailable".to_string())
+            })?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            error!("BioAgents API error ({}): {}", status, error_text);
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
+        }
+
+        response.text().await.map_err(|e| {
+            error!("Failed to read merged knowledge graph: {}", e);
+            AppError::DeserializationError
+        })
+    }
+
     /// Query the BioAgents with a natural language question
-    pub async fn query_agents(&self, query: &str) -> Result<(String, Vec<String>), AppError> {
+    ///
+    /// `top_k` asks for more (or fewer) supporting sources and `page_token` continues a
+    /// previous answer's source list; both are omitted upstream when not set.
+    pub async fn query_agents(
+        &self,
+        query: &str,
+        top_k: Option<u32>,
+        page_token: Option<&str>,
+    ) -> Result<AgentAnswer, AppError> {
         info!("Querying BioAgents: {}", query);
 
         // Create the request body
-        let body = serde_json::json!({
+        let mut body = serde_json::json!({
             "query": query,
         });
+        if let Some(top_k) = top_k {
+            body["top_k"] = top_k.into();
+        }
+        if let Some(page_token) = page_token {
+            body["page_token"] = page_token.into();
+        }
 
         // Send the request to BioAgents
         let response = self
-            .client
-            .post(&format!("{}/query", self.api_url))
-            .json(&body)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .post(format!("{}/query", self.api_url))
+                    .json(&body),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to query BioAgents: {}", e);
-                AppError::ExternalServiceError("BioAgents service unavailable".to_string())
+                self.upstream_error("BioAgents service unavailable".to_string())
             })?;
 
         // Check if the request was successful
@@ -312,10 +568,7 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
         // Parse the response
@@ -337,14 +590,23 @@ impl BioAgentsService {
                     .filter_map(|v| v.as_str().map(|s| s.to_string()))
                     .collect()
             })
-            .unwrap_or_else(Vec::new);
+            .unwrap_or_default();
+
+        let next_page_token = response_data["next_page_token"]
+            .as_str()
+            .filter(|token| !token.is_empty())
+            .map(str::to_string);
 
         info!(
             "BioAgents query successful, answer length: {}",
             answer.len()
         );
 
-        Ok((answer, sources))
+        Ok(AgentAnswer {
+            answer,
+            sources,
+            next_page_token,
+        })
     }
 
     /// Add knowledge to the BioAgents system
@@ -365,14 +627,16 @@ impl BioAgentsService {
 
         // Send the request to BioAgents
         let response = self
-            .client
-            .post(&format!("{}/knowledge", self.api_url))
-            .json(&body)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .post(format!("{}/knowledge", self.api_url))
+                    .json(&body),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to add knowledge to BioAgents: {}", e);
-                AppError::ExternalServiceError("BioAgents service unavailable".to_string())
+                self.upstream_error("BioAgents service unavailable".to_string())
             })?;
 
         // Check if the request was successful
@@ -383,10 +647,7 @@ impl BioAgentsService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("BioAgents API error ({}): {}", status, error_text);
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents API error: {}",
-                error_text
-            )));
+            return Err(self.upstream_error(format!("BioAgents API error: {}", error_text)));
         }
 
         // Parse the response
@@ -412,13 +673,12 @@ impl BioAgentsService {
 
         // Send a health check request to BioAgents
         let response = self
-            .client
-            .get(&format!("{}/health", self.api_url))
-            .send()
-            .await
+            .circuit
+            .send(self.client.get(format!("{}/health", self.api_url)))
+            .await?
             .map_err(|e| {
                 error!("Failed to check BioAgents health: {}", e);
-                AppError::ExternalServiceError("BioAgents service unavailable".to_string())
+                self.upstream_error("BioAgents service unavailable".to_string())
             })?;
 
         // Check if the request was successful
@@ -428,6 +688,8 @@ impl BioAgentsService {
                 agents_online: 0,
                 service_status: "offline".to_string(),
                 last_updated: chrono::Utc::now().to_rfc3339(),
+                in_flight_requests: self.in_flight(),
+                max_concurrent_requests: self.max_concurrent,
             });
         }
 
@@ -460,6 +722,50 @@ impl BioAgentsService {
             agents_online,
             service_status,
             last_updated,
+            in_flight_requests: self.in_flight(),
+            max_concurrent_requests: self.max_concurrent,
         })
     }
 }
+
+/// Check `signature`, the value of `SIGNATURE_HEADER`, against `body`; the error says why
+/// the response can't be trusted
+fn verify_signature(
+    key: &VerificationKey,
+    signature: Option<&str>,
+    body: &[u8],
+) -> Result<(), &'static str> {
+    let signature = signature.ok_or("is not signed")?;
+    let bytes: [u8; 64] = Base64Engine
+        .decode(signature.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("signature is malformed")?;
+    key.verify(&Signature::from(bytes), body)
+        .map_err(|_| "signature does not match")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_zebra::SigningKey;
+
+    #[test]
+    fn test_response_signature_must_match_body() {
+        let signing_key = SigningKey::from([7u8; 32]);
+        let key = VerificationKey::from(&signing_key);
+        let body = br#"{"task_id":"t1","status":"completed","progress":1.0}"#;
+        let signature = Base64Engine.encode(<[u8; 64]>::from(signing_key.sign(body)));
+
+        assert!(verify_signature(&key, Some(&signature), body).is_ok());
+        assert_eq!(
+            verify_signature(&key, Some(&signature), br#"{"task_id":"t2"}"#),
+            Err("signature does not match")
+        );
+        assert_eq!(verify_signature(&key, None, body), Err("is not signed"));
+        assert_eq!(
+            verify_signature(&key, Some("not base64!"), body),
+            Err("signature is malformed")
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::errors::AppError;
+use log::{info, warn};
+use reqwest::{RequestBuilder, Response};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Whether calls to an external service are let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    // Calls fail fast until the cooldown ends
+    Open,
+    // The cooldown ended; the next call is a trial deciding whether the circuit closes
+    HalfOpen,
+}
+
+/// Circuit state as reported by the readiness probe
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    // Seconds until an open circuit lets a trial call through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    // Set while the circuit is open or half-open
+    opened_at: Option<Instant>,
+    // Start of the half-open trial call in flight
+    trial_started_at: Option<Instant>,
+}
+
+/// Fails calls to an external service fast once it has failed `failure_threshold` times
+/// in a row, so requests don't pile up waiting for a dead upstream's timeout.
+///
+/// After `cooldown` the circuit is half-open: one trial call goes through, and closes the
+/// circuit if it succeeds or opens it for another cooldown if it fails. A trial that neither
+/// finishes nor fails within a cooldown, e.g. because its request was dropped, is replaced.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    // Name of the service in errors and logs
+    service: &'static str,
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuit: Mutex<Circuit>,
+}
+
+impl CircuitBreaker {
+    pub fn new(service: &'static str, failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            service,
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            circuit: Mutex::new(Circuit::default()),
+        }
+    }
+
+    /// Send `request` unless the circuit is open. Transport errors, including timeouts, and
+    /// 5xx responses count as failures; the response or transport error is returned as is.
+    pub async fn send(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Result<Response, reqwest::Error>, AppError> {
+        self.acquire(Instant::now())?;
+        let result = request.send().await;
+        let succeeded = matches!(&result, Ok(response) if !response.status().is_server_error());
+        self.record(succeeded, Instant::now());
+        Ok(result)
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        self.status_at(Instant::now())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Let a call through, or refuse it while the circuit is open or a trial is in flight
+    fn acquire(&self, now: Instant) -> Result<(), AppError> {
+        let mut circuit = self.lock();
+        let Some(opened_at) = circuit.opened_at else {
+            return Ok(());
+        };
+        let trial_running = circuit
+            .trial_started_at
+            .is_some_and(|started| now.duration_since(started) < self.cooldown);
+        if now.duration_since(opened_at) < self.cooldown || trial_running {
+            return Err(AppError::ExternalServiceError(format!(
+                "{} unavailable: circuit open",
+                self.service
+            )));
+        }
+        circuit.trial_started_at = Some(now);
+        Ok(())
+    }
+
+    fn record(&self, succeeded: bool, now: Instant) {
+        let mut circuit = self.lock();
+        if succeeded {
+            if circuit.opened_at.is_some() {
+                info!("{} recovered, closing its circuit", self.service);
+            }
+            *circuit = Circuit::default();
+            return;
+        }
+
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        let trial_failed = circuit.opened_at.is_some();
+        if trial_failed || circuit.consecutive_failures >= self.failure_threshold {
+            if !trial_failed {
+                warn!(
+                    "{} failed {} times in a row, failing calls fast for {:?}",
+                    self.service, circuit.consecutive_failures, self.cooldown
+                );
+            }
+            circuit.opened_at = Some(now);
+            circuit.trial_started_at = None;
+        }
+    }
+
+    fn status_at(&self, now: Instant) -> CircuitStatus {
+        let circuit = self.lock();
+        let remaining = circuit
+            .opened_at
+            .map(|opened_at| self.cooldown.saturating_sub(now.duration_since(opened_at)));
+        CircuitStatus {
+            state: match remaining {
+                None => CircuitState::Closed,
+                Some(remaining) if !remaining.is_zero() => CircuitState::Open,
+                Some(_) => CircuitState::HalfOpen,
+            },
+            consecutive_failures: circuit.consecutive_failures,
+            retry_in_secs: remaining
+                .filter(|remaining| !remaining.is_zero())
+                .map(|remaining| remaining.as_secs().max(1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_opens_after_consecutive_failures_and_closes_after_a_trial() {
+        let cooldown = Duration::from_secs(30);
+        let breaker = CircuitBreaker::new("Dataverse", 3, cooldown);
+        let start = Instant::now();
+
+        // A success in between resets the count
+        breaker.record(false, start);
+        breaker.record(false, start);
+        breaker.record(true, start);
+        breaker.record(false, start);
+        breaker.record(false, start);
+        assert_eq!(breaker.status_at(start).state, CircuitState::Closed);
+        assert!(breaker.acquire(start).is_ok());
+
+        breaker.record(false, start);
+        let status = breaker.status_at(start);
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.retry_in_secs, Some(30));
+        assert!(matches!(
+            breaker.acquire(start + Duration::from_secs(29)),
+            Err(AppError::ExternalServiceError(message)) if message.contains("circuit open")
+        ));
+
+        // Half-open: one trial at a time, and a failed trial opens the circuit again
+        let half_open = start + cooldown;
+        assert_eq!(breaker.status_at(half_open).state, CircuitState::HalfOpen);
+        assert!(breaker.acquire(half_open).is_ok());
+        assert!(breaker.acquire(half_open).is_err());
+        breaker.record(false, half_open);
+        assert_eq!(breaker.status_at(half_open).state, CircuitState::Open);
+        assert!(breaker.acquire(half_open + Duration::from_secs(1)).is_err());
+
+        // A trial that never reports back is replaced after a cooldown
+        let retry = half_open + cooldown;
+        assert!(breaker.acquire(retry).is_ok());
+        assert!(breaker.acquire(retry + cooldown).is_ok());
+        breaker.record(true, retry + cooldown);
+        let status = breaker.status_at(retry + cooldown);
+        assert_eq!(status.state, CircuitState::Closed);
+        assert_eq!(status.consecutive_failures, 0);
+        assert!(breaker.acquire(retry + cooldown).is_ok());
+    }
+}
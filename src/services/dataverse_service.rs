@@ -1,15 +1,97 @@
 use crate::errors::AppError;
-use log::{error, info};
+use crate::models::license::License;
+use crate::services::circuit_breaker::{CircuitBreaker, CircuitStatus};
+use crate::services::http_client::HttpClient;
+use crate::services::metrics_service::MetricsService;
+use crate::utils::{detect_mime, to_hex, MIME_SNIFF_BYTES};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
+use log::{error, info, warn};
+use md5::Md5;
 use reqwest::multipart;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::path::Path;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Assumed minimum storage upload throughput, used to size direct-upload timeouts
+const DIRECT_UPLOAD_MIN_BYTES_PER_SEC: u64 = 1024 * 1024;
+
+/// Files of a batch uploaded to a dataset at once
+const BATCH_UPLOAD_CONCURRENCY: usize = 4;
+
+/// Checksum algorithms a Dataverse installation can store files with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    /// Parse a Dataverse checksum type such as `MD5` or `SHA-256`
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_uppercase().replace('-', "").as_str() {
+            "MD5" => Some(Self::Md5),
+            "SHA1" => Some(Self::Sha1),
+            "SHA256" => Some(Self::Sha256),
+            "SHA512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// Incremental hasher for one `ChecksumAlgorithm`
+enum FileHasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl FileHasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Self::Md5(Md5::new()),
+            ChecksumAlgorithm::Sha1 => Self::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Self::Sha512(Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(hasher) => hasher.update(data),
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Lowercase hex digest
+    fn finalize(self) -> String {
+        match self {
+            Self::Md5(hasher) => to_hex(&hasher.finalize()),
+            Self::Sha1(hasher) => to_hex(&hasher.finalize()),
+            Self::Sha256(hasher) => to_hex(&hasher.finalize()),
+            Self::Sha512(hasher) => to_hex(&hasher.finalize()),
+        }
+    }
+}
 
 /// Dataset metadata structure
 #[derive(Debug, Serialize, Deserialize)]
+#[allow(dead_code)]
 pub struct DatasetMetadata {
     pub title: String,
     pub description: String,
@@ -26,55 +108,209 @@ pub struct DatasetResponse {
     pub description: String,
 }
 
+/// Default and maximum page sizes for dataset file listings
+pub const DEFAULT_FILES_PAGE_SIZE: u32 = 100;
+pub const MAX_FILES_PAGE_SIZE: u32 = 1000;
+
+/// Dataset version to read files from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetVersion {
+    // Draft if one exists and is visible to the API key, otherwise the latest published
+    Latest,
+    Draft,
+    Published,
+}
+
+impl DatasetVersion {
+    /// Parse `latest`, `draft` or `published`, in any case
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "latest" => Ok(DatasetVersion::Latest),
+            "draft" => Ok(DatasetVersion::Draft),
+            "published" => Ok(DatasetVersion::Published),
+            _ => Err(AppError::ValidationError(format!(
+                "Unknown dataset version '{}', expected latest, draft or published",
+                value
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DatasetVersion::Latest => "latest",
+            DatasetVersion::Draft => "draft",
+            DatasetVersion::Published => "published",
+        }
+    }
+
+    /// Version identifier in Dataverse API paths
+    fn api_id(&self) -> &'static str {
+        match self {
+            DatasetVersion::Latest => ":latest",
+            DatasetVersion::Draft => ":draft",
+            DatasetVersion::Published => ":latest-published",
+        }
+    }
+}
+
+/// A file in a dataset version
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DataverseFile {
+    pub id: i64,
+    pub label: String,
+    // e.g. "MD5" or "SHA-256"
+    pub checksum_type: Option<String>,
+    pub checksum: Option<String>,
+    // Bytes
+    pub size: Option<u64>,
+}
+
+impl DataverseFile {
+    /// Read an entry of a version's `files` array
+    fn from_version_file(file: &Value) -> Option<Self> {
+        let data_file = &file["dataFile"];
+        Some(Self {
+            id: data_file["id"].as_i64()?,
+            label: file["label"]
+                .as_str()
+                .or_else(|| data_file["filename"].as_str())
+                .unwrap_or_default()
+                .to_string(),
+            checksum_type: data_file["checksum"]["type"].as_str().map(str::to_string),
+            checksum: data_file["checksum"]["value"]
+                .as_str()
+                .or_else(|| data_file["md5"].as_str())
+                .map(str::to_string),
+            size: data_file["filesize"].as_u64(),
+        })
+    }
+}
+
+/// One page of a dataset version's files, in Dataverse's order
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DataverseFilesPage {
+    pub files: Vec<DataverseFile>,
+    // "latest", "draft" or "published"
+    pub version: String,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: u64,
+}
+
+/// Outcome of one file of a batch upload
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct BatchFileUpload {
+    pub file_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    // Why this file wasn't uploaded; the rest of the batch still was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 /// Service for interacting with the Dataverse API
 pub struct DataverseService {
-    client: reqwest::Client,
+    client: HttpClient,
     api_key: String,
     api_url: String,
+    metrics: Arc<MetricsService>,
+    // Fails API calls fast while Dataverse is down; storage uploads bypass it
+    circuit: CircuitBreaker,
+    // Installation has direct-to-storage (S3) uploads enabled
+    direct_upload: bool,
+    // Most bytes one request may upload, summed over its files
+    max_upload_bytes: u64,
+    // License for datasets created without one
+    default_license: License,
+}
+
+/// Where `uploadurls` says to send a direct upload
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectUploadTarget {
+    storage_identifier: String,
+    // Single-part upload URL
+    url: Option<String>,
+    // Multipart upload: part number to URL, plus paths to complete or abort
+    urls: Option<BTreeMap<u32, String>>,
+    part_size: Option<u64>,
+    complete: Option<String>,
+    abort: Option<String>,
 }
 
 impl DataverseService {
     /// Create a new DataverseService instance
-    pub fn new(api_url: &str, api_key: &str) -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(120))
-            .connect_timeout(Duration::from_secs(30))
-            .build()
-            .expect("Failed to create HTTP client");
-
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        client: &HttpClient,
+        api_url: &str,
+        api_key: &str,
+        metrics: Arc<MetricsService>,
+        circuit: CircuitBreaker,
+        direct_upload: bool,
+        max_upload_bytes: u64,
+        default_license: License,
+    ) -> Self {
         Self {
-            client,
+            client: client.with_timeout(Duration::from_secs(120)),
             api_key: api_key.to_string(),
             api_url: api_url.to_string(),
+            metrics,
+            circuit,
+            direct_upload,
+            max_upload_bytes,
+            default_license,
         }
     }
 
+    /// State of the circuit breaker in front of the Dataverse API
+    pub fn circuit_status(&self) -> CircuitStatus {
+        self.circuit.status()
+    }
+
+    /// Build an `ExternalServiceError` for a failed request and count it against Dataverse
+    fn upstream_error(&self, message: String) -> AppError {
+        self.metrics.record_external_error("dataverse");
+        AppError::ExternalServiceError(message)
+    }
+
+    /// Build a `DataverseApiError` for an error response and count it against Dataverse
+    fn api_error(&self, message: String) -> AppError {
+        self.metrics.record_external_error("dataverse");
+        AppError::DataverseApiError(message)
+    }
+
     /// Create a new dataset in Dataverse
+    #[tracing::instrument(skip_all, fields(title = title))]
     pub async fn create_dataset(
         &self,
         title: &str,
         description: &str,
         authors: &[String],
         keywords: &[String],
+        license: Option<&License>,
     ) -> Result<DatasetResponse, AppError> {
         info!("Creating dataset in Dataverse: {}", title);
 
         // Prepare dataset metadata in Dataverse format
-        let metadata = self.build_dataset_metadata(title, description, authors, keywords);
+        let license = license.unwrap_or(&self.default_license).dataverse_license();
+        let metadata = self.build_dataset_metadata(title, description, authors, keywords, license);
 
         // Create the request
         let url = format!("{}/api/datasets", self.api_url);
 
         let response = self
-            .client
-            .post(&url)
-            .header("X-Dataverse-key", &self.api_key)
-            .json(&metadata)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .header("X-Dataverse-key", &self.api_key)
+                    .json(&metadata),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to create dataset in Dataverse: {}", e);
-                AppError::ExternalServiceError(format!("Dataverse request failed: {}", e))
+                self.upstream_error(format!("Dataverse request failed: {}", e))
             })?;
 
         // Check if the request was successful
@@ -85,10 +321,7 @@ impl DataverseService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Dataverse API error ({}): {}", status, error_text);
-            return Err(AppError::DataverseApiError(format!(
-                "API error ({}): {}",
-                status, error_text
-            )));
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
         }
 
         // Parse the response
@@ -123,6 +356,7 @@ impl DataverseService {
     }
 
     /// Update dataset metadata
+    #[tracing::instrument(skip_all, fields(dataset = persistent_id))]
     pub async fn update_metadata(
         &self,
         persistent_id: &str,
@@ -130,6 +364,7 @@ impl DataverseService {
         description: Option<&str>,
         authors: Option<&[String]>,
         keywords: Option<&[String]>,
+        license: Option<&License>,
     ) -> Result<(), AppError> {
         info!("Updating metadata for dataset: {}", persistent_id);
 
@@ -151,12 +386,22 @@ impl DataverseService {
             .and_then(|desc| desc["dsDescriptionValue"]["value"].as_str())
             .unwrap_or("");
 
+        // Keep the dataset's license unless a new one is given
+        let license = match license {
+            Some(license) => license.dataverse_license(),
+            None => match &current["datasetVersion"]["license"] {
+                Value::Null => self.default_license.dataverse_license(),
+                current_license => current_license.clone(),
+            },
+        };
+
         // Build updated metadata
         let metadata = self.build_dataset_metadata(
             title.unwrap_or(current_title),
             description.unwrap_or(current_description),
             authors.unwrap_or(&[]),
             keywords.unwrap_or(&[]),
+            license,
         );
 
         // Send the request
@@ -166,15 +411,17 @@ impl DataverseService {
         );
 
         let response = self
-            .client
-            .put(&url)
-            .header("X-Dataverse-key", &self.api_key)
-            .json(&metadata)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .put(&url)
+                    .header("X-Dataverse-key", &self.api_key)
+                    .json(&metadata),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to update dataset metadata: {}", e);
-                AppError::ExternalServiceError(format!("Dataverse request failed: {}", e))
+                self.upstream_error(format!("Dataverse request failed: {}", e))
             })?;
 
         // Check if the request was successful
@@ -185,10 +432,7 @@ impl DataverseService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Dataverse API error ({}): {}", status, error_text);
-            return Err(AppError::DataverseApiError(format!(
-                "API error ({}): {}",
-                status, error_text
-            )));
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
         }
 
         info!("Dataset metadata updated successfully: {}", persistent_id);
@@ -228,9 +472,19 @@ impl DataverseService {
             .and_then(|n| n.to_str())
             .unwrap_or("file.dat");
 
+        let mime = detect_mime(&buffer[..buffer.len().min(MIME_SNIFF_BYTES)])
+            .unwrap_or("application/octet-stream");
+
+        // Dataverse checksums files added through the API itself, with MD5 unless the
+        // installation is configured otherwise
+        let size = buffer.len() as u64;
+        let mut hasher = FileHasher::new(ChecksumAlgorithm::Md5);
+        hasher.update(&buffer);
+        let md5 = hasher.finalize();
+
         let file_part = multipart::Part::bytes(buffer)
             .file_name(file_name.to_string())
-            .mime_str("application/octet-stream")
+            .mime_str(mime)
             .map_err(|e| {
                 error!("Failed to set MIME type: {}", e);
                 AppError::RequestError(format!("Failed to set MIME type: {}", e))
@@ -245,12 +499,14 @@ impl DataverseService {
         let url = format!("{}/api/datasets/{}/add", self.api_url, dataset_id);
 
         let response = self
-            .client
-            .post(&url)
-            .header("X-Dataverse-key", &self.api_key)
-            .multipart(form)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .header("X-Dataverse-key", &self.api_key)
+                    .multipart(form),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to upload file to Dataverse: {}", e);
                 AppError::RequestError(format!("Failed to upload file: {}", e))
@@ -264,10 +520,7 @@ impl DataverseService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Dataverse API error ({}): {}", status, error_text);
-            return Err(AppError::DataverseApiError(format!(
-                "API error ({}): {}",
-                status, error_text
-            )));
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
         }
 
         // Parse the response
@@ -276,14 +529,15 @@ impl DataverseService {
             AppError::DeserializationError
         })?;
 
-        // Extract the file ID from the response
-        let file_id = response_json["data"]["files"][0]["dataFile"]["id"]
-            .as_i64()
-            .map(|id| id.to_string())
+        // Extract the stored file from the response and check it arrived intact
+        let stored = DataverseFile::from_version_file(&response_json["data"]["files"][0])
             .ok_or_else(|| {
                 error!("Failed to extract file ID from Dataverse response");
                 AppError::DeserializationError
             })?;
+        self.verify_stored_file(&stored, file_path, size, (ChecksumAlgorithm::Md5, &md5))
+            .await?;
+        let file_id = stored.id.to_string();
 
         info!(
             "File uploaded successfully to dataset {}, file ID: {}",
@@ -293,7 +547,446 @@ impl DataverseService {
         Ok(file_id)
     }
 
+    /// Upload a file to a dataset, directly to storage when the installation supports it
+    #[tracing::instrument(skip_all, fields(dataset = dataset_id, direct = self.direct_upload))]
+    pub async fn upload_dataset_file(
+        &self,
+        dataset_id: &str,
+        file_path: &Path,
+        description: &str,
+    ) -> Result<String, AppError> {
+        if self.direct_upload {
+            self.upload_file_direct(dataset_id, file_path, description)
+                .await
+        } else {
+            self.upload_file(dataset_id, file_path, description).await
+        }
+    }
+
+    /// Upload several files, each with its description, `BATCH_UPLOAD_CONCURRENCY` at a
+    /// time. Results are in the order given, and a file that fails is reported in its result
+    /// without stopping the others. Nothing is uploaded if the files add up to more than the
+    /// upload limit.
+    #[tracing::instrument(skip_all, fields(dataset = dataset_id, files = files.len()))]
+    pub async fn upload_files(
+        &self,
+        dataset_id: &str,
+        files: Vec<(PathBuf, String)>,
+    ) -> Result<Vec<BatchFileUpload>, AppError> {
+        let mut total: u64 = 0;
+        for (path, _) in &files {
+            let metadata = tokio::fs::metadata(path).await.map_err(|e| {
+                error!("Failed to read size of {}: {}", path.display(), e);
+                AppError::FileError(format!("Failed to read file: {}", e))
+            })?;
+            total += metadata.len();
+        }
+        self.check_upload_size(total)?;
+
+        info!(
+            "Uploading {} files ({} bytes) to Dataverse dataset {}",
+            files.len(),
+            total,
+            dataset_id
+        );
+
+        let results: Vec<BatchFileUpload> = stream::iter(files)
+            .map(|(path, description)| async move {
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                match self
+                    .upload_dataset_file(dataset_id, &path, &description)
+                    .await
+                {
+                    Ok(file_id) => BatchFileUpload {
+                        file_name,
+                        file_id: Some(file_id),
+                        error: None,
+                    },
+                    Err(e) => {
+                        warn!(
+                            "Failed to upload {} to dataset {}: {}",
+                            file_name, dataset_id, e
+                        );
+                        BatchFileUpload {
+                            file_name,
+                            file_id: None,
+                            error: Some(e.to_string()),
+                        }
+                    }
+                }
+            })
+            .buffered(BATCH_UPLOAD_CONCURRENCY)
+            .collect()
+            .await;
+
+        let failed = results
+            .iter()
+            .filter(|result| result.error.is_some())
+            .count();
+        info!(
+            "Uploaded {} of {} files to dataset {}",
+            results.len() - failed,
+            results.len(),
+            dataset_id
+        );
+
+        Ok(results)
+    }
+
+    /// Fail if uploading `bytes` in one request would exceed the upload limit
+    pub fn check_upload_size(&self, bytes: u64) -> Result<(), AppError> {
+        if bytes > self.max_upload_bytes {
+            return Err(AppError::ValidationError(format!(
+                "Upload exceeds the limit of {} bytes",
+                self.max_upload_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Upload a file using Dataverse's direct-to-storage flow.
+    ///
+    /// The bytes go straight to the installation's S3 store through signed URLs instead of
+    /// through the Dataverse app server: request upload URLs, PUT the file (in parts when
+    /// Dataverse asks for a multipart upload), then register it with its SHA-256 checksum.
+    /// The file is streamed from disk, so memory use doesn't depend on its size.
+    pub async fn upload_file_direct(
+        &self,
+        dataset_id: &str,
+        file_path: &Path,
+        description: &str,
+    ) -> Result<String, AppError> {
+        info!(
+            "Uploading file directly to storage for dataset {}: {}",
+            dataset_id,
+            file_path.display()
+        );
+
+        let size = tokio::fs::metadata(file_path)
+            .await
+            .map_err(|e| AppError::FileError(format!("Failed to read file: {}", e)))?
+            .len();
+        let (checksum, head) = file_checksum(file_path).await?;
+        let mime = detect_mime(&head).unwrap_or("application/octet-stream");
+        let file_name = file_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file.dat");
+
+        let target = self.request_upload_urls(dataset_id, size).await?;
+        match (&target.url, &target.urls) {
+            (Some(url), _) => {
+                self.put_file_range(url, file_path, 0, size, true).await?;
+            }
+            (None, Some(urls)) => {
+                self.put_multipart(&target, urls, file_path, size).await?;
+            }
+            (None, None) => {
+                error!("Dataverse upload URL response had neither url nor urls");
+                return Err(AppError::DeserializationError);
+            }
+        }
+
+        let json_data = serde_json::json!({
+            "description": description,
+            "storageIdentifier": target.storage_identifier,
+            "fileName": file_name,
+            "mimeType": mime,
+            "checksum": { "@type": "SHA-256", "@value": checksum },
+        });
+        let form = multipart::Form::new().text("jsonData", json_data.to_string());
+        let url = format!(
+            "{}/api/datasets/:persistentId/add?persistentId={}",
+            self.api_url, dataset_id
+        );
+
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .header("X-Dataverse-key", &self.api_key)
+                    .multipart(form),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to register direct upload: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+        let response_json = self.success_json(response).await?;
+
+        let stored = DataverseFile::from_version_file(&response_json["data"]["files"][0])
+            .ok_or_else(|| {
+                error!("Failed to extract file ID from Dataverse response");
+                AppError::DeserializationError
+            })?;
+        self.verify_stored_file(
+            &stored,
+            file_path,
+            size,
+            (ChecksumAlgorithm::Sha256, &checksum),
+        )
+        .await?;
+        let file_id = stored.id.to_string();
+
+        info!(
+            "File uploaded directly to dataset {}, file ID: {}",
+            dataset_id, file_id
+        );
+
+        Ok(file_id)
+    }
+
+    /// Check that the file Dataverse stored has the uploaded file's size and checksum, so a
+    /// truncated or corrupted upload fails instead of leaving a damaged file in the dataset.
+    ///
+    /// `local` is the checksum computed before the upload. When Dataverse reports a different
+    /// algorithm, the file is hashed again with that one; checksums with an algorithm this
+    /// service doesn't know are only logged.
+    async fn verify_stored_file(
+        &self,
+        stored: &DataverseFile,
+        file_path: &Path,
+        size: u64,
+        local: (ChecksumAlgorithm, &str),
+    ) -> Result<(), AppError> {
+        if let Some(stored_size) = stored.size {
+            if stored_size != size {
+                error!(
+                    "Dataverse file {} is {} bytes, uploaded {}",
+                    stored.id, stored_size, size
+                );
+                return Err(self.upstream_error(format!(
+                    "Dataverse stored file {} with {} bytes, uploaded {}",
+                    stored.id, stored_size, size
+                )));
+            }
+        }
+
+        let Some(expected) = &stored.checksum else {
+            warn!(
+                "Dataverse reported no checksum for file {}, skipping verification",
+                stored.id
+            );
+            return Ok(());
+        };
+        // Older installations only report an `md5`
+        let checksum_type = stored.checksum_type.as_deref().unwrap_or("MD5");
+        let Some(algorithm) = ChecksumAlgorithm::parse(checksum_type) else {
+            warn!(
+                "Can't verify {} checksum of Dataverse file {}",
+                checksum_type, stored.id
+            );
+            return Ok(());
+        };
+
+        let actual = if algorithm == local.0 {
+            local.1.to_string()
+        } else {
+            file_digest(file_path, algorithm).await?
+        };
+        if !actual.eq_ignore_ascii_case(expected) {
+            error!(
+                "Dataverse file {} has {} {}, uploaded {}",
+                stored.id, checksum_type, expected, actual
+            );
+            return Err(self.upstream_error(format!(
+                "{} checksum of Dataverse file {} doesn't match the uploaded file",
+                checksum_type, stored.id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Ask Dataverse where to upload a file of `size` bytes
+    async fn request_upload_urls(
+        &self,
+        dataset_id: &str,
+        size: u64,
+    ) -> Result<DirectUploadTarget, AppError> {
+        let url = format!(
+            "{}/api/datasets/:persistentId/uploadurls?persistentId={}&size={}",
+            self.api_url, dataset_id, size
+        );
+
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .get(&url)
+                    .header("X-Dataverse-key", &self.api_key),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to request upload URLs: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+        let response_json = self.success_json(response).await?;
+
+        serde_json::from_value(response_json["data"].clone()).map_err(|e| {
+            error!("Unexpected Dataverse upload URL response: {}", e);
+            AppError::DeserializationError
+        })
+    }
+
+    /// Upload each part to its signed URL, then complete the upload, aborting it on failure
+    async fn put_multipart(
+        &self,
+        target: &DirectUploadTarget,
+        urls: &BTreeMap<u32, String>,
+        file_path: &Path,
+        size: u64,
+    ) -> Result<(), AppError> {
+        let part_size = target.part_size.filter(|&p| p > 0).ok_or_else(|| {
+            error!("Dataverse multipart upload response is missing partSize");
+            AppError::DeserializationError
+        })?;
+
+        let mut etags = BTreeMap::new();
+        let mut result = Ok(());
+        for (part, url) in urls {
+            let offset = u64::from(part - 1) * part_size;
+            let length = part_size.min(size.saturating_sub(offset));
+            match self
+                .put_file_range(url, file_path, offset, length, false)
+                .await
+            {
+                Ok(etag) => {
+                    etags.insert(part.to_string(), etag);
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        if result.is_ok() {
+            result = match &target.complete {
+                Some(complete) => self.finish_multipart(complete, &etags).await,
+                None => {
+                    error!("Dataverse multipart upload response is missing complete URL");
+                    Err(AppError::DeserializationError)
+                }
+            };
+        }
+
+        if result.is_err() {
+            if let Some(abort) = &target.abort {
+                let url = format!("{}{}", self.api_url, abort);
+                if let Err(e) = self
+                    .client
+                    .delete(&url)
+                    .header("X-Dataverse-key", &self.api_key)
+                    .send()
+                    .await
+                {
+                    error!("Failed to abort multipart upload: {}", e);
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn finish_multipart(
+        &self,
+        complete: &str,
+        etags: &BTreeMap<String, String>,
+    ) -> Result<(), AppError> {
+        let url = format!("{}{}", self.api_url, complete);
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .put(&url)
+                    .header("X-Dataverse-key", &self.api_key)
+                    .json(etags),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to complete multipart upload: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+        self.success_json(response).await?;
+        Ok(())
+    }
+
+    /// PUT `length` bytes of the file starting at `offset` to a signed storage URL,
+    /// returning the storage ETag
+    async fn put_file_range(
+        &self,
+        url: &str,
+        file_path: &Path,
+        offset: u64,
+        length: u64,
+        tag_temporary: bool,
+    ) -> Result<String, AppError> {
+        let mut file = File::open(file_path)
+            .await
+            .map_err(|e| AppError::FileError(format!("Failed to open file: {}", e)))?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| AppError::FileError(format!("Failed to read file: {}", e)))?;
+        let body = reqwest::Body::wrap_stream(ReaderStream::new(file.take(length)));
+
+        let mut request = self
+            .client
+            .put(url)
+            .header(reqwest::header::CONTENT_LENGTH, length)
+            .timeout(Duration::from_secs(
+                120 + length / DIRECT_UPLOAD_MIN_BYTES_PER_SEC,
+            ))
+            .body(body);
+        if tag_temporary {
+            // Dataverse expects single-part uploads to be tagged until they are registered
+            request = request.header("x-amz-tagging", "dv-state=temp");
+        }
+
+        let response = request.send().await.map_err(|e| {
+            error!("Failed to upload file to storage: {}", e);
+            self.upstream_error(format!("Storage upload failed: {}", e))
+        })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            error!("Storage upload error ({}): {}", status, error_text);
+            return Err(self.upstream_error(format!("Storage upload failed ({})", status)));
+        }
+
+        Ok(response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .unwrap_or_default()
+            .trim_matches('"')
+            .to_string())
+    }
+
+    /// JSON body of a successful Dataverse response, or a `DataverseApiError`
+    async fn success_json(&self, response: reqwest::Response) -> Result<Value, AppError> {
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            error!("Dataverse API error ({}): {}", status, error_text);
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
+        }
+
+        response.json().await.map_err(|e| {
+            error!("Failed to parse Dataverse response: {}", e);
+            AppError::DeserializationError
+        })
+    }
+
     /// Publish a dataset in Dataverse
+    #[tracing::instrument(skip(self))]
     pub async fn publish_dataset(&self, persistent_id: &str) -> Result<(), AppError> {
         info!("Publishing dataset: {}", persistent_id);
 
@@ -303,14 +996,16 @@ impl DataverseService {
         );
 
         let response = self
-            .client
-            .post(&url)
-            .header("X-Dataverse-key", &self.api_key)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .header("X-Dataverse-key", &self.api_key),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to publish dataset: {}", e);
-                AppError::ExternalServiceError(format!("Dataverse request failed: {}", e))
+                self.upstream_error(format!("Dataverse request failed: {}", e))
             })?;
 
         // Check if the request was successful
@@ -321,10 +1016,7 @@ impl DataverseService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Dataverse API error ({}): {}", status, error_text);
-            return Err(AppError::DataverseApiError(format!(
-                "API error ({}): {}",
-                status, error_text
-            )));
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
         }
 
         info!("Dataset published successfully: {}", persistent_id);
@@ -332,7 +1024,92 @@ impl DataverseService {
         Ok(())
     }
 
+    /// Submit a dataset for review, for accounts that may not publish it themselves. A
+    /// curator then publishes it or returns it to the author.
+    #[tracing::instrument(skip(self))]
+    pub async fn submit_for_review(&self, persistent_id: &str) -> Result<(), AppError> {
+        info!("Submitting dataset for review: {}", persistent_id);
+
+        let url = format!(
+            "{}/api/datasets/:persistentId/submitForReview?persistentId={}",
+            self.api_url, persistent_id
+        );
+
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .header("X-Dataverse-key", &self.api_key),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to submit dataset for review: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            error!("Dataverse API error ({}): {}", status, error_text);
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
+        }
+
+        info!("Dataset submitted for review: {}", persistent_id);
+
+        Ok(())
+    }
+
+    /// Types of the locks held on a dataset, e.g. `finalizePublication` while it is being
+    /// published or `InReview` while it awaits a curator
+    #[tracing::instrument(skip(self))]
+    pub async fn get_locks(&self, persistent_id: &str) -> Result<Vec<String>, AppError> {
+        let url = format!(
+            "{}/api/datasets/:persistentId/locks?persistentId={}",
+            self.api_url, persistent_id
+        );
+
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .get(&url)
+                    .header("X-Dataverse-key", &self.api_key),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to get dataset locks: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            error!("Dataverse API error ({}): {}", status, error_text);
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
+        }
+
+        let locks: Value = response.json().await.map_err(|e| {
+            error!("Failed to parse Dataverse response: {}", e);
+            AppError::DeserializationError
+        })?;
+
+        Ok(locks["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|lock| lock["lockType"].as_str().map(str::to_string))
+            .collect())
+    }
+
     /// Get dataset metadata
+    #[tracing::instrument(skip(self))]
     pub async fn get_dataset_metadata(&self, persistent_id: &str) -> Result<Value, AppError> {
         info!("Getting metadata for dataset: {}", persistent_id);
 
@@ -342,16 +1119,25 @@ impl DataverseService {
         );
 
         let response = self
-            .client
-            .get(&url)
-            .header("X-Dataverse-key", &self.api_key)
-            .send()
-            .await
+            .circuit
+            .send(
+                self.client
+                    .get(&url)
+                    .header("X-Dataverse-key", &self.api_key),
+            )
+            .await?
             .map_err(|e| {
                 error!("Failed to get dataset metadata: {}", e);
-                AppError::ExternalServiceError(format!("Dataverse request failed: {}", e))
+                self.upstream_error(format!("Dataverse request failed: {}", e))
             })?;
 
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(AppError::NotFound(format!(
+                "Dataverse dataset not found: {}",
+                persistent_id
+            )));
+        }
+
         // Check if the request was successful
         if !response.status().is_success() {
             let status = response.status();
@@ -360,10 +1146,7 @@ impl DataverseService {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Dataverse API error ({}): {}", status, error_text);
-            return Err(AppError::DataverseApiError(format!(
-                "API error ({}): {}",
-                status, error_text
-            )));
+            return Err(self.api_error(format!("API error ({}): {}", status, error_text)));
         }
 
         // Parse the response
@@ -375,6 +1158,163 @@ impl DataverseService {
         Ok(metadata["data"].clone())
     }
 
+    /// List the files in a version of a dataset.
+    ///
+    /// Dataverse returns a version's whole `files` array (older installations don't support
+    /// paging it), so the requested page is cut from that.
+    #[tracing::instrument(skip(self))]
+    pub async fn list_files(
+        &self,
+        persistent_id: &str,
+        version: DatasetVersion,
+        page: u32,
+        per_page: u32,
+    ) -> Result<DataverseFilesPage, AppError> {
+        info!(
+            "Listing {} files of dataset: {}",
+            version.as_str(),
+            persistent_id
+        );
+
+        let page = page.max(1);
+        let per_page = per_page.clamp(1, MAX_FILES_PAGE_SIZE);
+        let url = format!(
+            "{}/api/datasets/:persistentId/versions/{}/files",
+            self.api_url,
+            version.api_id()
+        );
+
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .get(&url)
+                    .query(&[("persistentId", persistent_id)])
+                    .header("X-Dataverse-key", &self.api_key),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to list dataset files: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(AppError::NotFound(format!(
+                "Dataset {} has no {} version",
+                persistent_id,
+                version.as_str()
+            )));
+        }
+        let body = self.success_json(response).await?;
+
+        let files: Vec<DataverseFile> = body["data"]
+            .as_array()
+            .map(|files| {
+                files
+                    .iter()
+                    .filter_map(DataverseFile::from_version_file)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let total = files.len() as u64;
+        let files = files
+            .into_iter()
+            .skip((page as usize - 1).saturating_mul(per_page as usize))
+            .take(per_page as usize)
+            .collect();
+
+        Ok(DataverseFilesPage {
+            files,
+            version: version.as_str().to_string(),
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    /// Embargo every file in the latest version of a dataset until `until` (a day, as
+    /// Dataverse embargoes have no time). Installations with embargoes disabled answer with
+    /// an API error. Returns how many files were embargoed.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_embargo(
+        &self,
+        persistent_id: &str,
+        until: DateTime<Utc>,
+    ) -> Result<usize, AppError> {
+        let mut file_ids = Vec::new();
+        let mut page = 1;
+        loop {
+            let files = self
+                .list_files(
+                    persistent_id,
+                    DatasetVersion::Latest,
+                    page,
+                    MAX_FILES_PAGE_SIZE,
+                )
+                .await?;
+            file_ids.extend(files.files.iter().map(|file| file.id));
+            if u64::from(page) * u64::from(MAX_FILES_PAGE_SIZE) >= files.total {
+                break;
+            }
+            page += 1;
+        }
+        if file_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let url = format!(
+            "{}/api/datasets/:persistentId/files/actions/:set-embargo",
+            self.api_url
+        );
+        let response = self
+            .circuit
+            .send(
+                self.client
+                    .post(&url)
+                    .query(&[("persistentId", persistent_id)])
+                    .header("X-Dataverse-key", &self.api_key)
+                    .json(&serde_json::json!({
+                        "dateAvailable": until.format("%Y-%m-%d").to_string(),
+                        "reason": "Embargoed until the date set on its DID",
+                        "fileIds": file_ids,
+                    })),
+            )
+            .await?
+            .map_err(|e| {
+                error!("Failed to set dataset embargo: {}", e);
+                self.upstream_error(format!("Dataverse request failed: {}", e))
+            })?;
+        self.success_json(response).await?;
+
+        info!(
+            "Embargoed {} files of {} until {}",
+            file_ids.len(),
+            persistent_id,
+            until.format("%Y-%m-%d")
+        );
+        Ok(file_ids.len())
+    }
+
+    /// Title from the citation block of metadata returned by `get_dataset_metadata`
+    pub fn dataset_title(metadata: &Value) -> Option<String> {
+        metadata["latestVersion"]["metadataBlocks"]["citation"]["fields"]
+            .as_array()?
+            .iter()
+            .find(|field| field["typeName"] == "title")
+            .and_then(|field| field["value"].as_str())
+            .map(str::to_string)
+    }
+
+    /// Persistent ID, e.g. `doi:10.5072/FK2/ABCDEF`, of metadata returned by
+    /// `get_dataset_metadata`; Dataverse may assign a new one when first publishing
+    pub fn persistent_id(metadata: &Value) -> Option<String> {
+        let protocol = metadata["protocol"].as_str()?;
+        let authority = metadata["authority"].as_str()?;
+        let identifier = metadata["identifier"].as_str()?;
+        Some(format!("{}:{}/{}", protocol, authority, identifier))
+    }
+
     /// Build dataset metadata in Dataverse format
     fn build_dataset_metadata(
         &self,
@@ -382,6 +1322,7 @@ impl DataverseService {
         description: &str,
         authors: &[String],
         keywords: &[String],
+        license: Value,
     ) -> Value {
         // Create author entries
         let author_values = authors
@@ -412,7 +1353,7 @@ impl DataverseService {
         // Build the complete metadata object
         serde_json::json!({
             "datasetVersion": {
-                "license": { "name": "CC0", "uri": "http://creativecommons.org/publicdomain/zero/1.0" },
+                "license": license,
                 "metadataBlocks": {
                     "citation": {
                         "fields": [
@@ -448,3 +1389,52 @@ impl DataverseService {
         })
     }
 }
+
+/// Hex SHA-256 of a file and its first bytes (for type detection), read in chunks
+async fn file_checksum(file_path: &Path) -> Result<(String, Vec<u8>), AppError> {
+    let mut file = File::open(file_path)
+        .await
+        .map_err(|e| AppError::FileError(format!("Failed to open file: {}", e)))?;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(MIME_SNIFF_BYTES);
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| AppError::FileError(format!("Failed to read file: {}", e)))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        if head.len() < MIME_SNIFF_BYTES {
+            let wanted = (MIME_SNIFF_BYTES - head.len()).min(read);
+            head.extend_from_slice(&buffer[..wanted]);
+        }
+    }
+
+    Ok((to_hex(&hasher.finalize()), head))
+}
+
+/// Hex digest of a file with `algorithm`, read in chunks
+async fn file_digest(file_path: &Path, algorithm: ChecksumAlgorithm) -> Result<String, AppError> {
+    let mut file = File::open(file_path)
+        .await
+        .map_err(|e| AppError::FileError(format!("Failed to open file: {}", e)))?;
+    let mut hasher = FileHasher::new(algorithm);
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| AppError::FileError(format!("Failed to read file: {}", e)))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize())
+}
@@ -0,0 +1,138 @@
+use crate::errors::AppError;
+use crate::models::did::DIDDocument;
+use crate::services::metrics_service::MetricsService;
+use lru::LruCache;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// `cache` label of the metrics recorded by `DidDocumentCache`
+const CACHE_NAME: &str = "did_document";
+
+/// LRU cache of parsed DID documents, keyed by the CID they are stored at.
+///
+/// Content at a CID never changes, so entries can't go stale: an update stores the new
+/// document under a new CID, and the entry for the old one ages out (or keeps serving
+/// `?cid=` lookups of that version).
+pub struct DidDocumentCache {
+    entries: Mutex<LruCache<String, DIDDocument>>,
+    metrics: Arc<MetricsService>,
+}
+
+impl DidDocumentCache {
+    pub fn new(capacity: NonZeroUsize, metrics: Arc<MetricsService>) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            metrics,
+        }
+    }
+
+    /// The document stored at `cid`, calling `fetch` to load it on a miss. Failed fetches
+    /// aren't cached.
+    pub async fn get_or_fetch<F, Fut>(&self, cid: &str, fetch: F) -> Result<DIDDocument, AppError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<DIDDocument, AppError>>,
+    {
+        let cached = self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(cid)
+            .cloned();
+        self.metrics
+            .record_cache_lookup(CACHE_NAME, cached.is_some());
+        if let Some(document) = cached {
+            return Ok(document);
+        }
+
+        let document = fetch().await?;
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(cid.to_string(), document.clone());
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn document(did: &str, controller: &str) -> DIDDocument {
+        serde_json::from_value(serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": did,
+            "alsoKnownAs": null,
+            "controller": [controller],
+            "verificationMethod": [],
+            "authentication": [],
+            "assertionMethod": null,
+            "service": [],
+            "created": "2025-01-01T00:00:00Z",
+            "updated": "2025-01-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    async fn get(
+        cache: &DidDocumentCache,
+        cid: &str,
+        stored: &DIDDocument,
+        fetches: &AtomicUsize,
+    ) -> DIDDocument {
+        cache
+            .get_or_fetch(cid, || async {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Ok(stored.clone())
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_update_misses_cache_under_new_cid() {
+        let cache = DidDocumentCache::new(
+            NonZeroUsize::new(10).unwrap(),
+            Arc::new(MetricsService::new().unwrap()),
+        );
+        let fetches = AtomicUsize::new(0);
+        let original = document("did:bio:abc", "did:key:z6Mkoriginal");
+        let updated = document("did:bio:abc", "did:key:z6Mkupdated");
+
+        get(&cache, "bafyoriginal", &original, &fetches).await;
+        let cached = get(&cache, "bafyoriginal", &original, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(cached.controller, original.controller);
+
+        // The update was stored under a new CID, so its first resolve goes to IPFS
+        let resolved = get(&cache, "bafyupdated", &updated, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        assert_eq!(resolved.controller, updated.controller);
+
+        // The earlier version is still served from the cache
+        get(&cache, "bafyoriginal", &original, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_fetch_is_not_cached() {
+        let cache = DidDocumentCache::new(
+            NonZeroUsize::new(10).unwrap(),
+            Arc::new(MetricsService::new().unwrap()),
+        );
+        let fetches = AtomicUsize::new(0);
+
+        let result = cache
+            .get_or_fetch("bafymissing", || async {
+                Err(AppError::NotFound("not on this node".to_string()))
+            })
+            .await;
+        assert!(result.is_err());
+
+        let stored = document("did:bio:abc", "did:key:z6Mkoriginal");
+        get(&cache, "bafymissing", &stored, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,91 +1,550 @@
+use crate::database::db_time::{from_db, to_db};
+use crate::database::find_orcid_identity;
 use crate::errors::AppError;
+use crate::models::credential::{
+    CredentialProof, CredentialVerification, VerifiableCredential, CREDENTIALS_CONTEXT,
+    ED25519_2020_CONTEXT, ED25519_SIGNATURE_2020,
+};
 use crate::models::did::{
-    create_default_did_document, generate_did, DIDCreationRequest, DIDDocument, DIDUpdateRequest,
+    canonicalize, check_swapped, create_default_did_document, decode_ed25519_multibase,
+    decode_ed25519_private_multibase, generate_did, migrate_document, multibase_key,
+    patch_metadata, AttachFileRequest, BiometadataExtension, DIDCloneRequest, DIDCreationRequest,
+    DIDDocument, DIDTransfer, DIDTransferRequest, DIDUpdateRequest, DidBacklink, DidModification,
+    DidRegistryCursor, DidRegistryEntry, DocumentLimits, GeneratedKeypair, RelatedIdentifier,
+    ServiceEndpointPolicy, VerificationMethod, DERIVED_FROM_RELATION, DID_DOCUMENT_SCHEMA_VERSION,
+    ED25519_MULTICODEC, ED25519_PRIVATE_MULTICODEC,
+};
+use crate::models::did_template::{
+    merge_template_metadata, DIDFromTemplateRequest, DIDTemplate, DIDTemplateRequest,
 };
+use crate::models::did_validation::{self, DocumentValidation};
+use crate::models::license::License;
+use crate::models::webhook::WebhookEvent;
+use crate::services::audit_service::AuditService;
+use crate::services::did_cache::DidDocumentCache;
+use crate::services::field_encryption::FieldEncryption;
 use crate::services::ipfs_service::IPFSService;
-use chrono::Utc;
-use log::{error, info};
-use mysql_async::{prelude::*, Pool};
+use crate::services::keyword_service::KeywordService;
+use crate::services::webhook_service::WebhookService;
+use chrono::{DateTime, Utc};
+use ed25519_zebra::{Signature, SigningKey, VerificationKey};
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool, Row};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Largest page of the DID registry
+pub const MAX_REGISTRY_PAGE_SIZE: u32 = 1000;
 
 /// Service for handling DID document operations
 pub struct DIDService {
     db_pool: Arc<Pool>,
     ipfs_service: Arc<IPFSService>,
+    audit_service: Arc<AuditService>,
+    webhook_service: Arc<WebhookService>,
+    keyword_service: Arc<KeywordService>,
+    // Parsed documents by CID, so resolving only costs a DB lookup of the current CID
+    document_cache: DidDocumentCache,
+    default_license: License,
+    // Endpoint of the `#storage` service in new DID documents
+    storage_endpoint: String,
+    limits: DocumentLimits,
+    endpoint_policy: ServiceEndpointPolicy,
+    // Seals configured metadata fields before storage and opens sealed fields on read
+    field_encryption: Arc<FieldEncryption>,
 }
 
 impl DIDService {
-    pub fn new(db_pool: Arc<Pool>, ipfs_service: Arc<IPFSService>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db_pool: Arc<Pool>,
+        ipfs_service: Arc<IPFSService>,
+        audit_service: Arc<AuditService>,
+        webhook_service: Arc<WebhookService>,
+        keyword_service: Arc<KeywordService>,
+        document_cache: DidDocumentCache,
+        default_license: License,
+        storage_endpoint: String,
+        limits: DocumentLimits,
+        endpoint_policy: ServiceEndpointPolicy,
+        field_encryption: Arc<FieldEncryption>,
+    ) -> Self {
         Self {
             db_pool,
             ipfs_service,
+            audit_service,
+            webhook_service,
+            keyword_service,
+            document_cache,
+            default_license,
+            storage_endpoint,
+            limits,
+            endpoint_policy,
+            field_encryption,
+        }
+    }
+
+    /// Generate a fresh Ed25519 keypair and its `did:key` controller.
+    ///
+    /// The private key is returned to the caller and dropped; it is never logged or stored.
+    pub fn generate_keypair() -> GeneratedKeypair {
+        let signing_key = SigningKey::new(rand_core::OsRng);
+        let verification_key = VerificationKey::from(&signing_key);
+
+        let public_key_multibase = multibase_key(&ED25519_MULTICODEC, verification_key.as_ref());
+        let private_key_multibase =
+            multibase_key(&ED25519_PRIVATE_MULTICODEC, signing_key.as_ref());
+
+        GeneratedKeypair {
+            controller: format!("did:key:{}", public_key_multibase),
+            key_type: "Ed25519VerificationKey2020".to_string(),
+            public_key_multibase,
+            private_key_multibase,
         }
     }
 
+    /// License given to DIDs whose metadata doesn't name one
+    pub fn default_license(&self) -> &License {
+        &self.default_license
+    }
+
+    /// Check a DID document created elsewhere against the rules `create_did` applies to
+    /// new documents, and the document limits. Nothing is stored.
+    pub fn validate_document(&self, document: &serde_json::Value) -> DocumentValidation {
+        let mut errors = did_validation::validate_document(document);
+        if errors.is_empty() {
+            let limits = migrate_document(document.clone()).and_then(|doc| {
+                doc.metadata
+                    .as_ref()
+                    .map_or(Ok(()), |metadata| self.limits.check_metadata(metadata))?;
+                self.endpoint_policy.check(&doc.service)?;
+                self.limits.check_document_size(&document.to_string())
+            });
+            if let Err(e) = limits {
+                errors.push(match e {
+                    AppError::ValidationError(message) => message,
+                    e => e.to_string(),
+                });
+            }
+        }
+        DocumentValidation::new(errors)
+    }
+
     /// Create a new DID document and store it in IPFS
+    #[tracing::instrument(skip_all, fields(user_id = user_id, did = tracing::field::Empty))]
     pub async fn create_did(
         &self,
-        request: DIDCreationRequest,
+        mut request: DIDCreationRequest,
         user_id: i64,
     ) -> Result<DIDDocument, AppError> {
+        request.metadata.normalize_license(&self.default_license)?;
+        self.limits.check_metadata(&request.metadata)?;
+        self.link_own_orcid(&mut request.metadata, user_id).await?;
+
         let did = generate_did();
+        tracing::Span::current().record("did", did.as_str());
 
         // Create the DID document
-        let did_document = create_default_did_document(
+        let mut did_document = create_default_did_document(
             &did,
             &request.controller,
             &request.public_key,
+            &request.verification_methods,
             request.metadata,
-        );
+            &self.storage_endpoint,
+        )?;
+        if let Some(assertion_methods) = &request.assertion_methods {
+            did_document.set_assertion_methods(assertion_methods)?;
+        }
 
-        // Serialize the DID document to JSON
-        let did_json = serde_json::to_string(&did_document).map_err(|e| {
-            error!("Failed to serialize DID document: {}", e);
-            AppError::SerializationError
+        let cid = self.store_new_document(&mut did_document, user_id).await?;
+        info!("Created new DID: {} with CID: {}", did, cid);
+        self.ipfs_service.mirror_did_to_mfs(&did, &cid).await;
+
+        self.audit_service
+            .record_audit(user_id, "did.create", "did", &did, json!({ "cid": cid }))
+            .await;
+        self.webhook_service
+            .dispatch(WebhookEvent::DidCreated, &did, &cid, user_id);
+
+        Ok(did_document)
+    }
+
+    /// Store a complete DID document from a backup or another instance under its existing
+    /// `did:bio` ID, owned by `user_id`. The document must pass the same validation as
+    /// `create_did`'s and keeps its `created` and `updated` timestamps. Fails with a
+    /// conflict if the DID already exists here, even if deleted.
+    #[tracing::instrument(skip_all, fields(user_id = user_id, did = %document.id))]
+    pub async fn import_did(
+        &self,
+        mut document: DIDDocument,
+        user_id: i64,
+    ) -> Result<DIDDocument, AppError> {
+        let is_bio_did = document
+            .id
+            .strip_prefix("did:bio:")
+            .is_some_and(|id| Uuid::parse_str(id).is_ok());
+        if !is_bio_did {
+            return Err(AppError::ValidationError(format!(
+                "Only did:bio:<uuid> DIDs can be imported, not '{}'",
+                document.id
+            )));
+        }
+        if let Some(metadata) = &mut document.metadata {
+            metadata.normalize_license(&self.default_license)?;
+            self.limits.check_metadata(metadata)?;
+        }
+        document.schema_version = DID_DOCUMENT_SCHEMA_VERSION;
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
         })?;
+        let existing: Option<u8> = "SELECT 1 FROM did_documents WHERE did = :did"
+            .with(params! { "did" => &document.id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when checking DID {}: {}", document.id, e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        drop(conn);
+        if existing.is_some() {
+            return Err(did_exists(&document.id));
+        }
+
+        let cid = self.store_new_document(&mut document, user_id).await?;
+        info!("Imported DID: {} with CID: {}", document.id, cid);
+        self.ipfs_service
+            .mirror_did_to_mfs(&document.id, &cid)
+            .await;
+
+        self.audit_service
+            .record_audit(
+                user_id,
+                "did.import",
+                "did",
+                &document.id,
+                json!({ "cid": cid }),
+            )
+            .await;
+        self.webhook_service
+            .dispatch(WebhookEvent::DidCreated, &document.id, &cid, user_id);
+
+        Ok(document)
+    }
+
+    /// Validate a new DID's document, add it to IPFS and record it as owned by `user_id`,
+    /// returning its CID
+    async fn store_new_document(
+        &self,
+        document: &mut DIDDocument,
+        user_id: i64,
+    ) -> Result<String, AppError> {
+        let did = document.id.clone();
+
+        // Serialize the DID document to JSON
+        let did_json = document_json(document, self.field_encryption.as_ref())?;
+        self.limits.check_document_size(&did_json)?;
+        let problems = did_validation::validate_document(
+            &serde_json::to_value(&*document).map_err(|_| AppError::SerializationError)?,
+        );
+        if !problems.is_empty() {
+            return Err(AppError::ValidationError(problems.join("; ")));
+        }
+        self.endpoint_policy.check(&document.service)?;
 
         // Store the DID document in IPFS
-        let cid = self
+        let (cid, deduplicated) = self
             .ipfs_service
-            .add_content(&did_json)
+            .add_content_dedup(&did_json)
             .await
             .map_err(|e| {
                 error!("Failed to store DID document in IPFS: {:?}", e);
                 e
             })?;
+        if deduplicated {
+            warn!(
+                "DID document for {} matches existing content at CID {}",
+                did, cid
+            );
+        }
 
         // Store the DID reference in the database
-        let now = Utc::now().naive_utc();
-        let created_at = now.format("%Y-%m-%d %H:%M:%S").to_string();
-        let updated_at = created_at.clone();
-
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
             AppError::DatabaseError(e.to_string())
         })?;
+        let mut tx = conn
+            .start_transaction(mysql_async::TxOpts::default())
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
         "INSERT INTO did_documents (did, cid, user_id, created_at, updated_at) VALUES (:did, :cid, :user_id, :created_at, :updated_at)"
             .with(params! {
                 "did" => &did,
                 "cid" => &cid,
                 "user_id" => user_id,
-                "created_at" => created_at,
-                "updated_at" => updated_at,
+                "created_at" => to_db(document.created),
+                "updated_at" => to_db(document.updated),
             })
-            .run(&mut conn)
+            .run(&mut tx)
+            .await
+            .map_err(|e| match e {
+                // An import of the same DID that got in first
+                mysql_async::Error::Server(ref err) if err.code == 1062 => did_exists(&did),
+                e => {
+                    error!("Database error when storing DID reference: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                }
+            })?;
+        index_document(&mut tx, document, &cid, &self.keyword_service).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        Ok(cid)
+    }
+
+    /// Create a DID owned by `user_id` for a dataset derived from `source_did`, copying the
+    /// source's metadata and recording the source as an `IsDerivedFrom` related identifier.
+    ///
+    /// The Dataverse link, DOI and handle identify the source dataset and aren't copied, and
+    /// the creation and modification dates start afresh. Encrypted fields are only copied
+    /// when the caller owns the source.
+    pub async fn clone_did(
+        &self,
+        source_did: &str,
+        request: DIDCloneRequest,
+        user_id: i64,
+    ) -> Result<DIDDocument, AppError> {
+        let mut metadata = self.get_metadata(source_did, Some(user_id)).await?;
+        if self.field_encryption.has_fields() && !self.is_owner(source_did, user_id).await? {
+            metadata = self.field_encryption.strip_metadata(&metadata)?;
+        }
+
+        let now = Utc::now();
+        metadata.dataverse_link = None;
+        metadata.doi = None;
+        metadata.handle = None;
+        metadata.creation_date = now;
+        metadata.last_modified = now;
+        metadata
+            .related_identifiers
+            .get_or_insert_with(Vec::new)
+            .push(RelatedIdentifier {
+                identifier: source_did.to_string(),
+                identifier_type: "DID".to_string(),
+                relation_type: DERIVED_FROM_RELATION.to_string(),
+            });
+
+        let clone = self
+            .create_did(
+                DIDCreationRequest {
+                    controller: request.controller,
+                    public_key: request.public_key,
+                    verification_methods: request.verification_methods,
+                    assertion_methods: request.assertion_methods,
+                    service_endpoints: request.service_endpoints,
+                    metadata,
+                },
+                user_id,
+            )
+            .await?;
+
+        info!(
+            "User {} cloned DID {} into {}",
+            user_id, source_did, clone.id
+        );
+        self.audit_service
+            .record_audit(
+                user_id,
+                "did.clone",
+                "did",
+                &clone.id,
+                json!({ "source": source_did }),
+            )
+            .await;
+
+        Ok(clone)
+    }
+
+    /// Make `request.to_user_id` the owner of a DID and of the research papers processed
+    /// for it, optionally replacing the document's controller.
+    ///
+    /// `user_id` must own the DID unless `as_admin` is set. Ownership, the papers and, with
+    /// a new controller, the new document version change in one transaction. A new
+    /// controller only changes who holds the keys in the document; its verification
+    /// methods are kept, so the new owner should rotate them with an update.
+    pub async fn transfer_ownership(
+        &self,
+        did_id: &str,
+        request: DIDTransferRequest,
+        user_id: i64,
+        as_admin: bool,
+    ) -> Result<DIDTransfer, AppError> {
+        let to_user_id = request.to_user_id;
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        let mut tx = conn
+            .start_transaction(mysql_async::TxOpts::default())
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        // Locked so concurrent transfers or updates can't interleave with this one
+        let current: Option<(i64, String)> =
+            "SELECT user_id, cid FROM did_documents WHERE did = :did FOR UPDATE"
+                .with(params! { "did" => did_id })
+                .first(&mut tx)
+                .await
+                .map_err(|e| {
+                    error!("Database error when reading DID owner: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+        let (from_user_id, mut cid) =
+            current.ok_or_else(|| AppError::NotFound("DID not found".to_string()))?;
+
+        if from_user_id != user_id && !as_admin {
+            return Err(AppError::AuthorizationError(
+                "Not authorized to transfer this DID".to_string(),
+            ));
+        }
+        if from_user_id == to_user_id {
+            return Err(AppError::ValidationError(format!(
+                "User {} already owns {}",
+                to_user_id, did_id
+            )));
+        }
+
+        let target: Option<i64> = "SELECT id FROM users WHERE id = :id"
+            .with(params! { "id" => to_user_id })
+            .first(&mut tx)
             .await
             .map_err(|e| {
-                error!("Database error when storing DID reference: {}", e);
+                error!("Database error when looking up transfer target: {}", e);
                 AppError::DatabaseError(e.to_string())
             })?;
+        if target.is_none() {
+            return Err(AppError::ValidationError(format!(
+                "User {} does not exist",
+                to_user_id
+            )));
+        }
 
-        info!("Created new DID: {} with CID: {}", did, cid);
+        let now = Utc::now();
+        let updated_at = to_db(now);
+        let mut new_version = None;
+        if let Some(controller) = request.controller {
+            let mut did_document = self.get_did(did_id).await?;
+            did_document.controller = vec![controller];
+            did_document.updated = now;
 
-        Ok(did_document)
+            let did_json = document_json(&mut did_document, self.field_encryption.as_ref())?;
+            self.limits.check_document_size(&did_json)?;
+            cid = self
+                .ipfs_service
+                .add_content_dedup(&did_json)
+                .await
+                .map_err(|e| {
+                    error!("Failed to store transferred DID document in IPFS: {:?}", e);
+                    e
+                })?
+                .0;
+
+            "UPDATE did_documents SET cid = :cid, updated_at = :updated_at WHERE did = :did"
+                .with(params! {
+                    "cid" => &cid,
+                    "updated_at" => &updated_at,
+                    "did" => did_id,
+                })
+                .run(&mut tx)
+                .await
+                .map_err(|e| {
+                    error!("Database error when updating DID reference: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+            index_document(&mut tx, &did_document, &cid, &self.keyword_service).await?;
+            new_version = Some(did_document);
+        }
+
+        "UPDATE did_documents SET user_id = :to_user_id WHERE did = :did"
+            .with(params! {
+                "to_user_id" => to_user_id,
+                "did" => did_id,
+            })
+            .ignore(&mut tx)
+            .await
+            .map_err(|e| {
+                error!("Database error when transferring DID: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        "UPDATE research_papers SET user_id = :to_user_id, updated_at = :updated_at WHERE did = :did"
+            .with(params! {
+                "to_user_id" => to_user_id,
+                "updated_at" => &updated_at,
+                "did" => did_id,
+            })
+            .ignore(&mut tx)
+            .await
+            .map_err(|e| {
+                error!("Database error when transferring research papers: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        let papers_transferred = tx.affected_rows();
+
+        tx.commit()
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        info!(
+            "User {} transferred DID {} from user {} to user {}",
+            user_id, did_id, from_user_id, to_user_id
+        );
+        self.audit_service
+            .record_audit(
+                user_id,
+                "did.transfer",
+                "did",
+                did_id,
+                json!({
+                    "cid": cid,
+                    "from_user_id": from_user_id,
+                    "to_user_id": to_user_id,
+                    "papers": papers_transferred,
+                    "controller_changed": new_version.is_some(),
+                }),
+            )
+            .await;
+        if new_version.is_some() {
+            self.ipfs_service.mirror_did_to_mfs(did_id, &cid).await;
+            self.webhook_service
+                .dispatch(WebhookEvent::DidUpdated, did_id, &cid, to_user_id);
+        }
+
+        Ok(DIDTransfer {
+            did: did_id.to_string(),
+            from_user_id,
+            to_user_id,
+            papers_transferred,
+            cid,
+        })
     }
 
     /// Retrieve a DID document by its DID identifier
     pub async fn get_did(&self, did_id: &str) -> Result<DIDDocument, AppError> {
+        self.get_did_with_cid(did_id)
+            .await
+            .map(|(document, _)| document)
+    }
+
+    /// Retrieve a DID's current document along with the CID it is stored at
+    pub async fn get_did_with_cid(&self, did_id: &str) -> Result<(DIDDocument, String), AppError> {
         // Query the database to get the CID for the DID
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
@@ -103,28 +562,129 @@ impl DIDService {
 
         let cid = cid.ok_or_else(|| AppError::NotFound("DID not found".to_string()))?;
 
-        // Retrieve the DID document from IPFS
-        let did_json = self.ipfs_service.get_content(&cid).await.map_err(|e| {
-            error!("Failed to retrieve DID document from IPFS: {:?}", e);
-            e
-        })?;
+        let document = self
+            .document_cache
+            .get_or_fetch(&cid, || async {
+                // Retrieve the DID document from IPFS
+                let did_json = self.ipfs_service.get_content(&cid).await.map_err(|e| {
+                    error!("Failed to retrieve DID document from IPFS: {:?}", e);
+                    e
+                })?;
+
+                // Parse the DID document, decrypting sealed fields and upgrading older
+                // schema versions
+                serde_json::from_str(&did_json)
+                    .map_err(|e| {
+                        error!("Failed to parse DID document: {}", e);
+                        AppError::DeserializationError
+                    })
+                    .and_then(|value| self.open_fields(value))
+                    .and_then(|value| {
+                        migrate_document(value).inspect_err(|_| {
+                            error!("DID document at {} doesn't match its schema version", cid)
+                        })
+                    })
+            })
+            .await?;
+        Ok((document, cid))
+    }
+
+    /// Retrieve the version of a DID document stored at `cid`.
+    ///
+    /// The CID is accepted if it is the DID's current or an audited earlier version; for
+    /// CIDs predating the audit log the document's own `id` is the only check. Either way
+    /// a document whose `id` is a different DID is rejected, so one DID's CID can't be
+    /// passed off as another's.
+    pub async fn get_did_at_cid(&self, did_id: &str, cid: &str) -> Result<DIDDocument, AppError> {
+        if cid.is_empty() || !cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(AppError::ValidationError(format!("Invalid CID: {}", cid)));
+        }
 
-        // Parse the DID document
-        let did_document: DIDDocument = serde_json::from_str(&did_json).map_err(|e| {
-            error!("Failed to parse DID document: {}", e);
-            AppError::DeserializationError
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
         })?;
 
+        let current_cid: Option<String> = "SELECT cid FROM did_documents WHERE did = :did"
+            .with(params! { "did" => did_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when retrieving DID reference: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        let current_cid =
+            current_cid.ok_or_else(|| AppError::NotFound("DID not found".to_string()))?;
+
+        if current_cid != cid
+            && !self
+                .audit_service
+                .has_recorded_cid("did", did_id, cid)
+                .await?
+        {
+            info!(
+                "CID {} is not a recorded version of {}, checking the document id",
+                cid, did_id
+            );
+        }
+
+        let did_document = self
+            .document_cache
+            .get_or_fetch(cid, || async {
+                let did_json = self.ipfs_service.get_content(cid).await.map_err(|e| {
+                    error!("Failed to retrieve DID document {} from IPFS: {:?}", cid, e);
+                    e
+                })?;
+                serde_json::from_str(&did_json)
+                    .map_err(|_| AppError::DeserializationError)
+                    .and_then(|value| self.open_fields(value))
+                    .and_then(migrate_document)
+                    .map_err(|_| {
+                        AppError::ValidationError(format!(
+                            "CID {} does not hold a DID document",
+                            cid
+                        ))
+                    })
+            })
+            .await?;
+
+        if did_document.id != did_id {
+            return Err(AppError::ValidationError(format!(
+                "CID {} holds the document for {}, not {}",
+                cid, did_document.id, did_id
+            )));
+        }
+
         Ok(did_document)
     }
 
+    /// DIDs owned by a user with the CID of their current document, oldest first
+    pub async fn list_user_dids(&self, user_id: i64) -> Result<Vec<(String, String)>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        "SELECT did, cid FROM did_documents WHERE user_id = :user_id ORDER BY created_at, did"
+            .with(params! { "user_id" => user_id })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing DIDs: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })
+    }
+
     /// Update an existing DID document
+    #[tracing::instrument(skip_all, fields(user_id = user_id, did = did_id))]
     pub async fn update_did(
         &self,
         did_id: &str,
         request: DIDUpdateRequest,
         user_id: i64,
     ) -> Result<DIDDocument, AppError> {
+        request.validate()?;
+
         // Check if the user is authorized to update this DID
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
@@ -150,8 +710,32 @@ impl DIDService {
             ));
         }
 
+        let changed_fields = [
+            ("controller", request.controller.is_some()),
+            (
+                "add_verification_method",
+                request.add_verification_method.is_some(),
+            ),
+            (
+                "remove_verification_method",
+                request.remove_verification_method.is_some(),
+            ),
+            (
+                "set_assertion_method",
+                request.set_assertion_method.is_some(),
+            ),
+            ("add_service", request.add_service.is_some()),
+            ("remove_service", request.remove_service.is_some()),
+            ("update_metadata", request.update_metadata.is_some()),
+            ("patch_metadata", request.patch_metadata.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect::<Vec<_>>();
+
         // Get the current DID document
-        let mut did_document = self.get_did(did_id).await?;
+        let (mut did_document, read_cid) = self.get_did_with_cid(did_id).await?;
+        request.check_expected_cid(&read_cid)?;
 
         // Update the controller if specified
         if let Some(controller) = request.controller {
@@ -168,10 +752,23 @@ impl DIDService {
             did_document
                 .verification_method
                 .retain(|method| !method_ids.contains(&method.id));
+            if let Some(assertion_method) = did_document.assertion_method.as_mut() {
+                assertion_method.retain(|id| !method_ids.contains(id));
+                if assertion_method.is_empty() {
+                    did_document.assertion_method = None;
+                }
+            }
         }
 
-        // Add new services if specified
+        // Designate the keys used for assertions
+        if let Some(references) = &request.set_assertion_method {
+            did_document.set_assertion_methods(references)?;
+        }
+
+        // Add new services if specified; only they are held to the endpoint schemes, so
+        // documents stored before the allow-list can still be updated
         if let Some(services) = request.add_service {
+            self.endpoint_policy.check(&services)?;
             did_document.service.extend(services);
         }
 
@@ -187,52 +784,224 @@ impl DIDService {
             did_document.metadata = Some(metadata);
         }
 
+        // Or merge a partial change into the existing metadata
+        if let Some(patch) = request.patch_metadata {
+            did_document.metadata = Some(patch_metadata(did_document.metadata.as_ref(), patch)?);
+        }
+
+        // Only changed metadata is checked, so documents stored before license validation
+        // or the metadata limits can still have their keys or services updated
+        if changed_fields
+            .iter()
+            .any(|field| matches!(*field, "update_metadata" | "patch_metadata"))
+        {
+            if let Some(metadata) = did_document.metadata.as_mut() {
+                metadata.normalize_license(&self.default_license)?;
+                self.limits.check_metadata(metadata)?;
+            }
+        }
+
         // Update the timestamp
         did_document.updated = Utc::now();
 
         // Serialize the updated DID document to JSON
-        let did_json = serde_json::to_string(&did_document).map_err(|e| {
-            error!("Failed to serialize updated DID document: {}", e);
-            AppError::SerializationError
-        })?;
+        let did_json = document_json(&mut did_document, self.field_encryption.as_ref())?;
+        self.limits.check_document_size(&did_json)?;
 
         // Store the updated DID document in IPFS
-        let cid = self
+        let (cid, deduplicated) = self
             .ipfs_service
-            .add_content(&did_json)
+            .add_content_dedup(&did_json)
             .await
             .map_err(|e| {
                 error!("Failed to store updated DID document in IPFS: {:?}", e);
                 e
             })?;
+        if deduplicated {
+            info!(
+                "Updated DID document for {} already stored at CID {}",
+                did_id, cid
+            );
+        }
 
         // Update the DID reference in the database
-        let now = Utc::now().naive_utc();
-        let updated_at = now.format("%Y-%m-%d %H:%M:%S").to_string();
+        let now = Utc::now();
+        let updated_at = to_db(now);
 
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
             AppError::DatabaseError(e.to_string())
         })?;
+        let mut tx = conn
+            .start_transaction(mysql_async::TxOpts::default())
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
-        "UPDATE did_documents SET cid = :cid, updated_at = :updated_at WHERE did = :did"
+        // Only if no other update was stored since the document was read, whether or not
+        // the client sent `expected_cid`; otherwise one of the changes would be lost
+        let swapped = r"UPDATE did_documents SET cid = :cid, updated_at = :updated_at
+            WHERE did = :did AND cid = :read_cid"
             .with(params! {
                 "cid" => &cid,
                 "updated_at" => updated_at,
                 "did" => did_id,
+                "read_cid" => &read_cid,
             })
-            .run(&mut conn)
+            .run(&mut tx)
             .await
             .map_err(|e| {
                 error!("Database error when updating DID reference: {}", e);
                 AppError::DatabaseError(e.to_string())
-            })?;
+            })?
+            .affected_rows();
+        let current: Option<String> = if swapped == 0 {
+            warn!(
+                "Update of {} based on CID {} lost the race to a concurrent update",
+                did_id, read_cid
+            );
+            "SELECT cid FROM did_documents WHERE did = :did"
+                .with(params! { "did" => did_id })
+                .first(&mut tx)
+                .await
+                .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        } else {
+            None
+        };
+        check_swapped(swapped, current.as_deref())?;
+        index_document(&mut tx, &did_document, &cid, &self.keyword_service).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
         info!("Updated DID: {} with new CID: {}", did_id, cid);
+        self.ipfs_service.mirror_did_to_mfs(did_id, &cid).await;
+
+        self.audit_service
+            .record_audit(
+                user_id,
+                "did.update",
+                "did",
+                did_id,
+                json!({ "cid": cid, "changes": changed_fields }),
+            )
+            .await;
+        self.webhook_service
+            .dispatch(WebhookEvent::DidUpdated, did_id, &cid, user_id);
 
         Ok(did_document)
     }
 
+    /// Attach content already on IPFS to a DID as a service pointing at its gateway URL.
+    ///
+    /// The CID must be retrievable. The change goes through `update_did`, so only the
+    /// owner can attach and a new document version is stored.
+    pub async fn attach_file(
+        &self,
+        did_id: &str,
+        request: AttachFileRequest,
+        user_id: i64,
+    ) -> Result<DIDDocument, AppError> {
+        let service = request.to_service(did_id, self.ipfs_service.gateway_url(&request.cid))?;
+
+        let (current, current_cid) = self.get_did_with_cid(did_id).await?;
+        if current.service.iter().any(|s| s.id == service.id) {
+            return Err(AppError::Conflict(format!(
+                "{} is already attached to {}",
+                request.cid, did_id
+            )));
+        }
+
+        self.ipfs_service
+            .stat(&request.cid)
+            .await
+            .map_err(|e| match e {
+                AppError::ExternalServiceError(_) | AppError::IPFSError(_) => {
+                    AppError::ValidationError(format!(
+                        "CID {} could not be found on IPFS",
+                        request.cid
+                    ))
+                }
+                e => e,
+            })?;
+
+        info!("Attaching {} to {}", request.cid, did_id);
+        self.update_did(
+            did_id,
+            DIDUpdateRequest {
+                add_service: Some(vec![service]),
+                // The duplicate check above holds only for this version
+                expected_cid: Some(current_cid),
+                ..Default::default()
+            },
+            user_id,
+        )
+        .await
+    }
+
+    /// Biological metadata of the current DID document as `requester` may see it (see
+    /// `apply_embargo`), `NotFound` if it has none
+    pub async fn get_metadata(
+        &self,
+        did_id: &str,
+        requester: Option<i64>,
+    ) -> Result<BiometadataExtension, AppError> {
+        let document = self.get_did(did_id).await?;
+        self.apply_embargo(document, requester)
+            .await?
+            .metadata
+            .ok_or_else(|| AppError::NotFound(format!("DID {} has no metadata", did_id)))
+    }
+
+    /// SHA-256 (hex) of the canonical form of the current DID document
+    pub async fn document_hash(&self, did_id: &str) -> Result<String, AppError> {
+        let did_document = self.get_did(did_id).await?;
+        let canonical = canonicalize(&did_document)?;
+        Ok(format!("{:x}", Sha256::digest(&canonical)))
+    }
+
+    /// DIDs whose current documents list `did_id` among their related identifiers, ordered
+    /// by DID. `NotFound` if `did_id` doesn't exist.
+    pub async fn find_referencing(&self, did_id: &str) -> Result<Vec<DidBacklink>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let exists: Option<i32> = "SELECT 1 FROM did_documents WHERE did = :did"
+            .with(params! { "did" => did_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when looking up DID: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        if exists.is_none() {
+            return Err(AppError::NotFound("DID not found".to_string()));
+        }
+
+        let rows: Vec<(String, String, String)> =
+            "SELECT source_did, identifier_type, relation_type
+             FROM did_relations WHERE target = :target
+             ORDER BY source_did, relation_type"
+                .with(params! { "target" => did_id })
+                .fetch(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when reading DID backlinks: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(did, identifier_type, relation_type)| DidBacklink {
+                did,
+                identifier_type,
+                relation_type,
+            })
+            .collect())
+    }
+
     /// Resolve a DID document and validate it
     pub async fn resolve_did(&self, did_id: &str) -> Result<DIDDocument, AppError> {
         // For now, we simply retrieve the DID document
@@ -240,20 +1009,82 @@ impl DIDService {
         self.get_did(did_id).await
     }
 
-    /// Create a link between a DID and a Dataverse dataset
-    pub async fn link_to_dataverse(
+    /// Strip what `document`'s embargo protects (see `DIDDocument::strip_embargoed`) unless
+    /// the embargo has passed or `requester` owns the DID
+    pub async fn apply_embargo(
+        &self,
+        mut document: DIDDocument,
+        requester: Option<i64>,
+    ) -> Result<DIDDocument, AppError> {
+        if !document.is_embargoed(Utc::now()) {
+            return Ok(document);
+        }
+        if let Some(user_id) = requester {
+            if self.is_owner(&document.id, user_id).await? {
+                return Ok(document);
+            }
+        }
+        document.strip_embargoed();
+        Ok(document)
+    }
+
+    /// `value`, a DID document or a response holding one, as `requester` may see it: with
+    /// the encrypted fields of the metadata at `metadata_pointer` hidden unless they own
+    /// the DID
+    pub async fn present<T: serde::Serialize>(
         &self,
+        value: &T,
         did_id: &str,
-        dataverse_doi: &str,
+        metadata_pointer: &str,
+        requester: Option<i64>,
+    ) -> Result<serde_json::Value, AppError> {
+        let mut value = serde_json::to_value(value).map_err(|_| AppError::SerializationError)?;
+        let encryption = &self.field_encryption;
+        if !encryption.has_fields() {
+            return Ok(value);
+        }
+        if let Some(user_id) = requester {
+            if self.is_owner(did_id, user_id).await? {
+                return Ok(value);
+            }
+        }
+        if let Some(metadata) = value.pointer_mut(metadata_pointer) {
+            encryption.redact_metadata(metadata);
+        }
+        Ok(value)
+    }
+
+    /// Decrypt the sealed fields of a stored document, which fail to parse until opened
+    fn open_fields(&self, mut value: serde_json::Value) -> Result<serde_json::Value, AppError> {
+        self.field_encryption.open_document(&mut value)?;
+        Ok(value)
+    }
+
+    /// Fill in the ORCID iD of the user's own researcher entry if they signed in with ORCID
+    pub async fn link_own_orcid(
+        &self,
+        metadata: &mut BiometadataExtension,
         user_id: i64,
     ) -> Result<(), AppError> {
-        // Check if the user is authorized to update this DID
+        let identity = find_orcid_identity(&self.db_pool, user_id)
+            .await
+            .map_err(|e| {
+                error!("Database error when loading ORCID iD: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        if let Some(identity) = identity {
+            metadata.link_orcid(&identity);
+        }
+        Ok(())
+    }
+
+    async fn is_owner(&self, did_id: &str, user_id: i64) -> Result<bool, AppError> {
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
             AppError::DatabaseError(e.to_string())
         })?;
 
-        let authorized: Option<i32> =
+        let owned: Option<i32> =
             "SELECT 1 FROM did_documents WHERE did = :did AND user_id = :user_id"
                 .with(params! {
                     "did" => did_id,
@@ -262,11 +1093,472 @@ impl DIDService {
                 .first(&mut conn)
                 .await
                 .map_err(|e| {
-                    error!("Database error when checking DID authorization: {}", e);
+                    error!("Database error when checking DID ownership: {}", e);
                     AppError::DatabaseError(e.to_string())
                 })?;
+        Ok(owned.is_some())
+    }
 
-        if authorized.is_none() {
+    /// Persistent ID of the Dataverse dataset the DID is linked to, if any. `NotFound` if the
+    /// DID doesn't exist.
+    pub async fn get_dataverse_doi(&self, did_id: &str) -> Result<Option<String>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let doi: Option<Option<String>> =
+            "SELECT dataverse_doi FROM did_documents WHERE did = :did"
+                .with(params! { "did" => did_id })
+                .first(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when retrieving Dataverse link: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+
+        doi.ok_or_else(|| AppError::NotFound("DID not found".to_string()))
+    }
+
+    /// When a DID's document last changed, read from the database alone so conditional
+    /// resolution can answer without fetching the document from IPFS
+    pub async fn get_modification(&self, did_id: &str) -> Result<DidModification, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let row: Option<(String, Option<String>)> = r"SELECT
+                DATE_FORMAT(updated_at, '%Y-%m-%d %H:%i:%s'),
+                DATE_FORMAT(embargo_until, '%Y-%m-%d %H:%i:%s')
+            FROM did_documents WHERE did = :did"
+            .with(params! { "did" => did_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reading DID modification time: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        let (updated_at, embargo_until) =
+            row.ok_or_else(|| AppError::NotFound("DID not found".to_string()))?;
+
+        Ok(DidModification {
+            updated_at: from_db(&updated_at).ok_or(AppError::DeserializationError)?,
+            embargo_until: embargo_until
+                .map(|until| from_db(&until).ok_or(AppError::DeserializationError))
+                .transpose()?,
+        })
+    }
+
+    /// One page of the registry of every DID, oldest first.
+    ///
+    /// Returns up to `limit` DIDs (at most `MAX_REGISTRY_PAGE_SIZE`) created after `cursor`,
+    /// optionally only those of `data_type` created in `[created_after, created_before)`,
+    /// and the cursor of the next page if more DIDs remain.
+    pub async fn list_registry(
+        &self,
+        data_type: Option<&str>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        cursor: Option<&DidRegistryCursor>,
+        limit: u32,
+    ) -> Result<(Vec<DidRegistryEntry>, Option<DidRegistryCursor>), AppError> {
+        let limit = limit.clamp(1, MAX_REGISTRY_PAGE_SIZE);
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        // Keyset pagination on (created_at, did); one extra row tells whether another page
+        // exists
+        let rows: Vec<(String, Option<String>, Option<String>, String)> = format!(
+            r"SELECT did, title, data_type, DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s')
+                FROM did_documents
+                WHERE did LIKE 'did:bio:%'
+                    AND (:data_type IS NULL OR data_type = :data_type)
+                    AND (:created_after IS NULL OR created_at >= :created_after)
+                    AND (:created_before IS NULL OR created_at < :created_before)
+                    AND (:cursor_created_at IS NULL OR created_at > :cursor_created_at
+                         OR (created_at = :cursor_created_at AND did > :cursor_did))
+                ORDER BY created_at, did LIMIT {}",
+            limit + 1
+        )
+        .with(params! {
+            "data_type" => data_type,
+            "created_after" => created_after.map(to_db),
+            "created_before" => created_before.map(to_db),
+            "cursor_created_at" => cursor.map(|c| to_db(c.created_at)),
+            "cursor_did" => cursor.map(|c| c.did.as_str()),
+        })
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing the DID registry: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let mut entries = rows
+            .into_iter()
+            .map(|(did, title, data_type, created_at)| {
+                Ok(DidRegistryEntry {
+                    did,
+                    title,
+                    data_type,
+                    created_at: from_db(&created_at).ok_or(AppError::DeserializationError)?,
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        let next_cursor = if entries.len() > limit as usize {
+            entries.truncate(limit as usize);
+            entries.last().map(DidRegistryCursor::after)
+        } else {
+            None
+        };
+
+        Ok((entries, next_cursor))
+    }
+
+    /// Fill in the registry's title and data type of DIDs whose current document was
+    /// stored before they were indexed. Returns how many DIDs were filled in.
+    pub async fn backfill_registry(&self) -> Result<usize, AppError> {
+        let db_error = |e: mysql_async::Error| {
+            error!("Database error when backfilling the DID registry: {}", e);
+            AppError::DatabaseError(e.to_string())
+        };
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let mut filled = 0;
+        let mut after = String::new();
+        loop {
+            let batch: Vec<(String, String)> = r"SELECT did, cid FROM did_documents
+                WHERE title IS NULL AND did > :after ORDER BY did LIMIT 100"
+                .with(params! { "after" => &after })
+                .fetch(&mut conn)
+                .await
+                .map_err(db_error)?;
+            let Some((last, _)) = batch.last() else {
+                return Ok(filled);
+            };
+            after = last.clone();
+
+            for (did, cid) in batch {
+                let metadata = match self.get_did_at_cid(&did, &cid).await {
+                    Ok(document) => document.metadata,
+                    Err(e) => {
+                        warn!("Skipping {} in the registry backfill: {}", did, e);
+                        continue;
+                    }
+                };
+                let Some(metadata) = metadata else {
+                    continue;
+                };
+                // Only if the document wasn't replaced meanwhile; its update indexed it
+                r"UPDATE did_documents SET title = :title, data_type = :data_type
+                    WHERE did = :did AND cid = :cid AND title IS NULL"
+                    .with(params! {
+                        "title" => &metadata.title,
+                        "data_type" => &metadata.data_type,
+                        "did" => &did,
+                        "cid" => &cid,
+                    })
+                    .ignore(&mut conn)
+                    .await
+                    .map_err(db_error)?;
+                filled += 1;
+            }
+        }
+    }
+
+    /// Run `backfill_registry` once, in the background. IPFS reads aren't `Send`, so this
+    /// must be called on the server's runtime.
+    pub fn start_registry_backfill(self: Arc<Self>) {
+        tokio::task::spawn_local(async move {
+            match self.backfill_registry().await {
+                Ok(0) => {}
+                Ok(filled) => info!("Indexed {} DIDs for the registry", filled),
+                Err(e) => error!("DID registry backfill failed: {}", e),
+            }
+        });
+    }
+
+    /// Record embargoes that have passed since the last sweep and tell the owners' webhooks.
+    /// Each is reported once, even with several instances sweeping. Returns how many
+    /// embargoes were lifted.
+    pub async fn lift_expired_embargoes(&self) -> Result<usize, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        let now = to_db(Utc::now());
+
+        let expired: Vec<(String, String, i64, String)> = r"SELECT did, cid, user_id,
+                DATE_FORMAT(embargo_until, '%Y-%m-%d %H:%i:%s')
+            FROM did_documents
+            WHERE embargo_until <= :now AND embargo_lifted_at IS NULL"
+            .with(params! { "now" => &now })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reading expired embargoes: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let mut lifted = 0;
+        for (did, cid, user_id, embargo_until) in expired {
+            // Skipped if another instance got here first or the owner changed the date
+            r"UPDATE did_documents SET embargo_lifted_at = :now
+                WHERE did = :did AND embargo_until = :embargo_until AND embargo_lifted_at IS NULL"
+                .with(params! {
+                    "now" => &now,
+                    "did" => &did,
+                    "embargo_until" => &embargo_until,
+                })
+                .ignore(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when lifting embargo of {}: {}", did, e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+            if conn.affected_rows() == 0 {
+                continue;
+            }
+
+            info!("Embargo on {} ended at {} UTC", did, embargo_until);
+            self.audit_service
+                .record_audit(
+                    user_id,
+                    "did.lift_embargo",
+                    "did",
+                    &did,
+                    json!({ "cid": cid, "embargo_until": embargo_until }),
+                )
+                .await;
+            self.webhook_service
+                .dispatch(WebhookEvent::DidEmbargoLifted, &did, &cid, user_id);
+            lifted += 1;
+        }
+        Ok(lifted)
+    }
+
+    /// Run `lift_expired_embargoes` every `interval`
+    pub fn start_embargo_sweep(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.lift_expired_embargoes().await {
+                    Ok(0) => {}
+                    Ok(lifted) => info!("Lifted {} expired embargoes", lifted),
+                    Err(e) => error!("Embargo sweep failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Issue a W3C Verifiable Credential about `subject` from `issuer_did`.
+    ///
+    /// `secret_key` is the `privateKeyMultibase` of an Ed25519 key listed in the issuer's
+    /// `assertionMethod`; holding it is what authorizes issuing. The key is only used to sign
+    /// the `Ed25519Signature2020` proof and is never logged or stored.
+    pub async fn issue_credential(
+        &self,
+        issuer_did: &str,
+        subject: &str,
+        claims: serde_json::Map<String, serde_json::Value>,
+        secret_key: &str,
+    ) -> Result<VerifiableCredential, AppError> {
+        let signing_key = decode_ed25519_private_multibase(secret_key).ok_or_else(|| {
+            AppError::ValidationError(
+                "secret_key must be a base58btc multibase Ed25519 private key".to_string(),
+            )
+        })?;
+        let public_key = VerificationKey::from(&signing_key);
+
+        let issuer = self.get_did(issuer_did).await?;
+        let assertion_method = assertion_methods(&issuer)
+            .find(|method| {
+                ed25519_key(method).is_some_and(|key| key.as_ref() == public_key.as_ref())
+            })
+            .ok_or_else(|| {
+                AppError::AuthorizationError(format!(
+                    "secret_key does not belong to an Ed25519 assertion method of {}",
+                    issuer_did
+                ))
+            })?;
+
+        let mut credential_subject = claims;
+        credential_subject.insert("id".to_string(), json!(subject));
+
+        let now = Utc::now();
+        let mut credential = VerifiableCredential {
+            context: vec![
+                CREDENTIALS_CONTEXT.to_string(),
+                ED25519_2020_CONTEXT.to_string(),
+            ],
+            id: format!("urn:uuid:{}", Uuid::new_v4()),
+            types: vec!["VerifiableCredential".to_string()],
+            issuer: issuer_did.to_string(),
+            issuance_date: now,
+            credential_subject,
+            proof: Some(CredentialProof {
+                proof_type: ED25519_SIGNATURE_2020.to_string(),
+                created: now,
+                verification_method: assertion_method.id.clone(),
+                proof_purpose: "assertionMethod".to_string(),
+                proof_value: String::new(),
+            }),
+        };
+
+        let signature: [u8; 64] = signing_key.sign(&credential.signing_input()?).into();
+        if let Some(proof) = credential.proof.as_mut() {
+            proof.proof_value = format!("z{}", bs58::encode(signature).into_string());
+        }
+
+        info!(
+            "Issued credential {} from {} about {}",
+            credential.id, issuer_did, subject
+        );
+        Ok(credential)
+    }
+
+    /// Check a credential's proof against the issuer's current DID document.
+    ///
+    /// The proof must be an `Ed25519Signature2020` made for `assertionMethod` by a key the
+    /// issuer still lists as an assertion method. Failed checks are reported in the result
+    /// rather than as errors.
+    pub async fn verify_credential(
+        &self,
+        credential: &VerifiableCredential,
+    ) -> Result<CredentialVerification, AppError> {
+        let Some(proof) = &credential.proof else {
+            return Ok(CredentialVerification::failed(
+                credential,
+                "Credential has no proof",
+            ));
+        };
+        if proof.proof_type != ED25519_SIGNATURE_2020 {
+            return Ok(CredentialVerification::failed(
+                credential,
+                format!("Unsupported proof type '{}'", proof.proof_type),
+            ));
+        }
+        if proof.proof_purpose != "assertionMethod" {
+            return Ok(CredentialVerification::failed(
+                credential,
+                format!("Unsupported proof purpose '{}'", proof.proof_purpose),
+            ));
+        }
+
+        let issuer = match self.get_did(&credential.issuer).await {
+            Ok(issuer) => issuer,
+            Err(AppError::NotFound(_)) => {
+                return Ok(CredentialVerification::failed(
+                    credential,
+                    format!("Issuer {} not found", credential.issuer),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+
+        let Some(public_key) = assertion_methods(&issuer)
+            .find(|method| method.id == proof.verification_method)
+            .and_then(ed25519_key)
+        else {
+            return Ok(CredentialVerification::failed(
+                credential,
+                format!(
+                    "{} is not an Ed25519 assertion method of {}",
+                    proof.verification_method, credential.issuer
+                ),
+            ));
+        };
+
+        let signature = proof
+            .proof_value
+            .strip_prefix('z')
+            .and_then(|encoded| bs58::decode(encoded).into_vec().ok())
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok());
+        let Some(signature) = signature else {
+            return Ok(CredentialVerification::failed(
+                credential,
+                "proofValue is not a base58btc multibase Ed25519 signature",
+            ));
+        };
+
+        if public_key
+            .verify(&Signature::from(signature), &credential.signing_input()?)
+            .is_err()
+        {
+            return Ok(CredentialVerification::failed(
+                credential,
+                "Signature verification failed",
+            ));
+        }
+
+        Ok(CredentialVerification {
+            verified: true,
+            issuer: credential.issuer.clone(),
+            verification_method: Some(proof.verification_method.clone()),
+            error: None,
+        })
+    }
+
+    /// Live DIDs linked to the Dataverse dataset `dataverse_doi`, with their owners
+    pub async fn find_by_dataverse_doi(
+        &self,
+        dataverse_doi: &str,
+    ) -> Result<Vec<(String, i64)>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        "SELECT did, user_id FROM did_documents WHERE dataverse_doi = :dataverse_doi"
+            .with(params! { "dataverse_doi" => dataverse_doi })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Database error when finding DIDs of {}: {}",
+                    dataverse_doi, e
+                );
+                AppError::DatabaseError(e.to_string())
+            })
+    }
+
+    /// Create a link between a DID and a Dataverse dataset. When known, the dataset's
+    /// title is kept in the `dataverse_title` custom field.
+    #[tracing::instrument(skip_all, fields(user_id = user_id, did = did_id, dataverse_doi = dataverse_doi))]
+    pub async fn link_to_dataverse(
+        &self,
+        did_id: &str,
+        dataverse_doi: &str,
+        dataset_title: Option<&str>,
+        user_id: i64,
+    ) -> Result<(), AppError> {
+        // Check if the user is authorized to update this DID
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let authorized: Option<i32> =
+            "SELECT 1 FROM did_documents WHERE did = :did AND user_id = :user_id"
+                .with(params! {
+                    "did" => did_id,
+                    "user_id" => user_id,
+                })
+                .first(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when checking DID authorization: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+
+        if authorized.is_none() {
             return Err(AppError::AuthorizationError(
                 "Not authorized to link this DID".to_string(),
             ));
@@ -282,26 +1574,35 @@ impl DIDService {
                 "https://dataverse.harvard.edu/dataset.xhtml?persistentId={}",
                 dataverse_doi
             ));
+            if let Some(title) = dataset_title {
+                metadata
+                    .custom_fields
+                    .get_or_insert_with(Default::default)
+                    .insert("dataverse_title".to_string(), title.into());
+            }
         }
 
         // Update the DID document in IPFS
-        let did_json = serde_json::to_string(&did_document).map_err(|e| {
-            error!("Failed to serialize updated DID document: {}", e);
-            AppError::SerializationError
-        })?;
+        let did_json = document_json(&mut did_document, self.field_encryption.as_ref())?;
 
-        let cid = self
+        let (cid, deduplicated) = self
             .ipfs_service
-            .add_content(&did_json)
+            .add_content_dedup(&did_json)
             .await
             .map_err(|e| {
                 error!("Failed to store updated DID document in IPFS: {:?}", e);
                 e
             })?;
+        if deduplicated {
+            info!(
+                "Updated DID document for {} already stored at CID {}",
+                did_id, cid
+            );
+        }
 
         // Update the DID reference in the database
-        let now = Utc::now().naive_utc();
-        let updated_at = now.format("%Y-%m-%d %H:%M:%S").to_string();
+        let now = Utc::now();
+        let updated_at = to_db(now);
 
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
@@ -321,9 +1622,386 @@ impl DIDService {
                 error!("Database error when updating DID reference: {}", e);
                 AppError::DatabaseError(e.to_string())
             })?;
+        record_version(&mut conn, did_id, &cid).await.map_err(|e| {
+            error!("Database error when recording DID version: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
 
         info!("Linked DID: {} to Dataverse DOI: {}", did_id, dataverse_doi);
+        self.ipfs_service.mirror_did_to_mfs(did_id, &cid).await;
+
+        self.audit_service
+            .record_audit(
+                user_id,
+                "did.link_dataverse",
+                "did",
+                did_id,
+                json!({ "cid": cid, "dataverse_doi": dataverse_doi }),
+            )
+            .await;
+        self.webhook_service
+            .dispatch(WebhookEvent::DidUpdated, did_id, &cid, user_id);
 
         Ok(())
     }
+
+    /// Store a new DID template owned by the user
+    pub async fn create_template(
+        &self,
+        request: DIDTemplateRequest,
+        user_id: i64,
+    ) -> Result<DIDTemplate, AppError> {
+        request.validate()?;
+
+        let template_id = Uuid::new_v4().to_string();
+        let now = to_db(Utc::now());
+        let metadata = serde_json::to_string(&request.metadata).map_err(|e| {
+            error!("Failed to serialize template metadata: {}", e);
+            AppError::SerializationError
+        })?;
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        r"INSERT INTO did_templates (template_id, user_id, name, description, metadata, created_at, updated_at)
+          VALUES (:template_id, :user_id, :name, :description, :metadata, :created_at, :updated_at)"
+            .with(params! {
+                "template_id" => &template_id,
+                "user_id" => user_id,
+                "name" => &request.name,
+                "description" => &request.description,
+                "metadata" => metadata,
+                "created_at" => &now,
+                "updated_at" => &now,
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when storing DID template: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Created DID template {} for user {}", template_id, user_id);
+
+        self.get_template(&template_id, user_id).await
+    }
+
+    /// List the user's DID templates, most recently updated first
+    pub async fn list_templates(&self, user_id: i64) -> Result<Vec<DIDTemplate>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let rows: Vec<Row> = format!(
+            "{} WHERE user_id = :user_id ORDER BY updated_at DESC",
+            TEMPLATE_COLUMNS
+        )
+        .with(params! { "user_id" => user_id })
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing DID templates: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        rows.into_iter().map(template_from_row).collect()
+    }
+
+    /// Get a DID template owned by the user
+    pub async fn get_template(
+        &self,
+        template_id: &str,
+        user_id: i64,
+    ) -> Result<DIDTemplate, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let row: Option<Row> = format!("{} WHERE template_id = :template_id", TEMPLATE_COLUMNS)
+            .with(params! { "template_id" => template_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when retrieving DID template: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let template = row
+            .map(template_from_row)
+            .transpose()?
+            .ok_or_else(|| AppError::NotFound(format!("Template not found: {}", template_id)))?;
+
+        if template.user_id != user_id {
+            return Err(AppError::AuthorizationError(
+                "Not authorized to access this template".to_string(),
+            ));
+        }
+
+        Ok(template)
+    }
+
+    /// Replace a DID template's name, description and metadata
+    pub async fn update_template(
+        &self,
+        template_id: &str,
+        request: DIDTemplateRequest,
+        user_id: i64,
+    ) -> Result<DIDTemplate, AppError> {
+        request.validate()?;
+        self.get_template(template_id, user_id).await?;
+
+        let metadata = serde_json::to_string(&request.metadata).map_err(|e| {
+            error!("Failed to serialize template metadata: {}", e);
+            AppError::SerializationError
+        })?;
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        r"UPDATE did_templates
+          SET name = :name, description = :description, metadata = :metadata, updated_at = :updated_at
+          WHERE template_id = :template_id"
+            .with(params! {
+                "template_id" => template_id,
+                "name" => &request.name,
+                "description" => &request.description,
+                "metadata" => metadata,
+                "updated_at" => to_db(Utc::now()),
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when updating DID template: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Updated DID template {}", template_id);
+
+        self.get_template(template_id, user_id).await
+    }
+
+    /// Delete a DID template. DIDs already created from it are unaffected.
+    pub async fn delete_template(&self, template_id: &str, user_id: i64) -> Result<(), AppError> {
+        self.get_template(template_id, user_id).await?;
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        "DELETE FROM did_templates WHERE template_id = :template_id"
+            .with(params! { "template_id" => template_id })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when deleting DID template: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Deleted DID template {}", template_id);
+        Ok(())
+    }
+
+    /// Create a DID whose metadata is the template's, merged with the request's overrides
+    pub async fn create_from_template(
+        &self,
+        template_id: &str,
+        overrides: DIDFromTemplateRequest,
+        user_id: i64,
+    ) -> Result<DIDDocument, AppError> {
+        let template = self.get_template(template_id, user_id).await?;
+        let metadata = merge_template_metadata(&template.metadata, overrides.overrides)?;
+
+        info!(
+            "Creating DID for user {} from template {}",
+            user_id, template_id
+        );
+
+        self.create_did(
+            DIDCreationRequest {
+                controller: overrides.controller,
+                public_key: overrides.public_key,
+                verification_methods: overrides.verification_methods,
+                assertion_methods: overrides.assertion_methods,
+                service_endpoints: overrides.service_endpoints,
+                metadata,
+            },
+            user_id,
+        )
+        .await
+    }
+}
+
+fn did_exists(did: &str) -> AppError {
+    AppError::Conflict(format!("DID {} already exists", did))
+}
+
+/// JSON stored on IPFS for `doc`, with its collections in a stable order so documents with
+/// the same content get the same CID, and the fields `encryption` selects sealed
+fn document_json(doc: &mut DIDDocument, encryption: &FieldEncryption) -> Result<String, AppError> {
+    doc.normalize_order();
+    let serialize_error = |e: serde_json::Error| {
+        error!("Failed to serialize DID document {}: {}", doc.id, e);
+        AppError::SerializationError
+    };
+    if !encryption.has_fields() {
+        return serde_json::to_string(doc).map_err(serialize_error);
+    }
+    let mut value = serde_json::to_value(&*doc).map_err(serialize_error)?;
+    encryption.seal_document(&mut value)?;
+    serde_json::to_string(&value).map_err(serialize_error)
+}
+
+/// Bring the database's view of `doc` in line with its new version stored at `cid`: the
+/// version history, its embargo end, attached files, keywords and related identifiers.
+/// Runs in the transaction storing the new CID.
+async fn index_document(
+    tx: &mut mysql_async::Transaction<'_>,
+    doc: &DIDDocument,
+    cid: &str,
+    keyword_service: &KeywordService,
+) -> Result<(), AppError> {
+    let index_error = |e: mysql_async::Error| {
+        error!("Database error when indexing {}: {}", doc.id, e);
+        AppError::DatabaseError(e.to_string())
+    };
+
+    record_version(&mut *tx, &doc.id, cid)
+        .await
+        .map_err(index_error)?;
+
+    // A changed embargo end is swept again when it passes
+    r"UPDATE did_documents
+        SET embargo_lifted_at = IF(embargo_until <=> :embargo_until, embargo_lifted_at, NULL),
+            embargo_until = :embargo_until,
+            title = :title,
+            data_type = :data_type
+        WHERE did = :did"
+        .with(params! {
+            "embargo_until" => doc
+                .embargo_until()
+                .map(to_db),
+            "title" => doc.metadata.as_ref().map(|m| &m.title),
+            "data_type" => doc.metadata.as_ref().map(|m| &m.data_type),
+            "did" => &doc.id,
+        })
+        .ignore(&mut *tx)
+        .await
+        .map_err(index_error)?;
+
+    "DELETE FROM did_attachments WHERE did = :did"
+        .with(params! { "did" => &doc.id })
+        .ignore(&mut *tx)
+        .await
+        .map_err(index_error)?;
+    "INSERT INTO did_attachments (did, cid) VALUES (:did, :cid)"
+        .with(
+            doc.attached_cids()
+                .into_iter()
+                .map(|cid| params! { "did" => &doc.id, "cid" => cid }),
+        )
+        .batch(&mut *tx)
+        .await
+        .map_err(index_error)?;
+
+    let keywords = doc
+        .metadata
+        .as_ref()
+        .map(|metadata| &metadata.keywords[..])
+        .unwrap_or_default();
+    keyword_service
+        .replace(&mut *tx, &doc.id, keywords)
+        .await
+        .map_err(index_error)?;
+
+    replace_relations(tx, doc).await.map_err(index_error)
+}
+
+/// Add `cid` to the versions of `did`, unless an earlier update already stored the same
+/// document
+async fn record_version(
+    conn: &mut impl Queryable,
+    did: &str,
+    cid: &str,
+) -> Result<(), mysql_async::Error> {
+    conn.exec_drop(
+        r"INSERT IGNORE INTO did_versions (did, cid, created_at)
+            VALUES (:did, :cid, :created_at)",
+        params! { "did" => did, "cid" => cid, "created_at" => to_db(Utc::now()) },
+    )
+    .await
+}
+
+/// Replace the related identifiers indexed for `doc`
+async fn replace_relations(
+    tx: &mut mysql_async::Transaction<'_>,
+    doc: &DIDDocument,
+) -> Result<(), mysql_async::Error> {
+    "DELETE FROM did_relations WHERE source_did = :source_did"
+        .with(params! { "source_did" => &doc.id })
+        .ignore(&mut *tx)
+        .await?;
+
+    "INSERT INTO did_relations (source_did, target, identifier_type, relation_type)
+        VALUES (:source_did, :target, :identifier_type, :relation_type)"
+        .with(doc.relations().into_iter().map(|related| {
+            params! {
+                "source_did" => &doc.id,
+                "target" => &related.identifier,
+                "identifier_type" => &related.identifier_type,
+                "relation_type" => &related.relation_type,
+            }
+        }))
+        .batch(&mut *tx)
+        .await
+}
+
+/// Verification methods `doc` lists in `assertionMethod`
+fn assertion_methods(doc: &DIDDocument) -> impl Iterator<Item = &VerificationMethod> {
+    doc.verification_method.iter().filter(|method| {
+        doc.assertion_method
+            .iter()
+            .flatten()
+            .any(|id| *id == method.id)
+    })
+}
+
+/// Ed25519 public key of a `publicKeyMultibase` method
+fn ed25519_key(method: &VerificationMethod) -> Option<VerificationKey> {
+    match method.vm_type.as_str() {
+        "Ed25519VerificationKey2020" | "Multikey" => method
+            .public_key_multibase
+            .as_deref()
+            .and_then(decode_ed25519_multibase),
+        _ => None,
+    }
+}
+
+/// Columns selected for a `DIDTemplate`, in the order `template_from_row` reads them
+const TEMPLATE_COLUMNS: &str = r"
+    SELECT template_id, user_id, name, description, metadata,
+           DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s'),
+           DATE_FORMAT(updated_at, '%Y-%m-%d %H:%i:%s')
+    FROM did_templates";
+
+fn template_from_row(row: Row) -> Result<DIDTemplate, AppError> {
+    let metadata: String = row.get(4).unwrap_or_default();
+    let created_at: String = row.get(5).unwrap_or_default();
+    let updated_at: String = row.get(6).unwrap_or_default();
+
+    Ok(DIDTemplate {
+        template_id: row.get(0).unwrap_or_default(),
+        user_id: row.get(1).unwrap_or_default(),
+        name: row.get(2).unwrap_or_default(),
+        description: row.get(3).flatten(),
+        metadata: serde_json::from_str(&metadata).map_err(|_| AppError::DeserializationError)?,
+        created_at: from_db(&created_at).ok_or(AppError::DeserializationError)?,
+        updated_at: from_db(&updated_at).ok_or(AppError::DeserializationError)?,
+    })
 }
@@ -0,0 +1,273 @@
+use crate::errors::AppError;
+use crate::services::bioagents_service::BiologicalEntity;
+use crate::services::http_client::HttpClient;
+use crate::services::metrics_service::MetricsService;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use lru::LruCache;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// `cache` label of the metrics recorded by `EntityEnrichmentService`
+const CACHE_NAME: &str = "entity_resolution";
+
+/// Ontology lookups run at once while enriching one paper's entities
+const ENRICHMENT_CONCURRENCY: usize = 4;
+
+const NCBI_ESEARCH_URL: &str = "https://eutils.ncbi.nlm.nih.gov/entrez/eutils/esearch.fcgi";
+const UNIPROT_SEARCH_URL: &str = "https://rest.uniprot.org/uniprotkb/search";
+const MESH_LOOKUP_URL: &str = "https://id.nlm.nih.gov/mesh/lookup/descriptor";
+
+/// Identifier of an entity in an external ontology
+#[derive(Debug, Clone)]
+struct ResolvedIdentifier {
+    identifier: String,
+    source: &'static str,
+}
+
+/// Ontology an entity type is resolved against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Ontology {
+    NcbiGene,
+    UniProt,
+    Mesh,
+}
+
+impl Ontology {
+    fn for_entity_type(entity_type: &str) -> Option<Self> {
+        match entity_type.to_ascii_lowercase().as_str() {
+            "gene" => Some(Self::NcbiGene),
+            "protein" => Some(Self::UniProt),
+            "disease" => Some(Self::Mesh),
+            _ => None,
+        }
+    }
+
+    /// Canonical `source` of entities resolved against this ontology
+    fn source(self) -> &'static str {
+        match self {
+            Self::NcbiGene => "NCBI Gene",
+            Self::UniProt => "UniProt",
+            Self::Mesh => "MeSH",
+        }
+    }
+
+    /// Name of the upstream in `external_service_errors_total`
+    fn service(self) -> &'static str {
+        match self {
+            Self::NcbiGene => "ncbi",
+            Self::UniProt => "uniprot",
+            Self::Mesh => "mesh",
+        }
+    }
+}
+
+/// Fills in missing identifiers of genes, proteins and diseases extracted by BioAgents by
+/// looking their names up in NCBI Gene, UniProt and MeSH.
+///
+/// Resolutions, including names with no match, are cached by entity type and name. Failed
+/// lookups aren't cached, so the entity is retried with the next paper that mentions it.
+pub struct EntityEnrichmentService {
+    client: HttpClient,
+    cache: Mutex<LruCache<(Ontology, String), Option<ResolvedIdentifier>>>,
+    metrics: Arc<MetricsService>,
+}
+
+impl EntityEnrichmentService {
+    pub fn new(
+        client: &HttpClient,
+        cache_size: NonZeroUsize,
+        metrics: Arc<MetricsService>,
+    ) -> Self {
+        Self {
+            client: client.with_timeout(Duration::from_secs(10)),
+            cache: Mutex::new(LruCache::new(cache_size)),
+            metrics,
+        }
+    }
+
+    /// `entities` with missing identifiers filled in where a lookup found one.
+    ///
+    /// Entities that already have an identifier, have a type without an ontology, or
+    /// couldn't be resolved (no match or a failed lookup) are returned unchanged, in the
+    /// original order.
+    pub async fn enrich_entities(&self, entities: &[BiologicalEntity]) -> Vec<BiologicalEntity> {
+        let keys: HashSet<(Ontology, String)> = entities
+            .iter()
+            .filter(|entity| entity.identifier.is_none())
+            .filter_map(|entity| {
+                let ontology = Ontology::for_entity_type(&entity.entity_type)?;
+                let name = normalize_name(&entity.name);
+                (!name.is_empty()).then_some((ontology, name))
+            })
+            .collect();
+
+        let resolved: HashMap<(Ontology, String), Option<ResolvedIdentifier>> = stream::iter(keys)
+            .map(|(ontology, name)| async move {
+                let resolution = self.resolve(ontology, &name).await;
+                ((ontology, name), resolution)
+            })
+            .buffer_unordered(ENRICHMENT_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut enriched = entities.to_vec();
+        let mut filled = 0;
+        for entity in enriched
+            .iter_mut()
+            .filter(|entity| entity.identifier.is_none())
+        {
+            let Some(ontology) = Ontology::for_entity_type(&entity.entity_type) else {
+                continue;
+            };
+            let key = (ontology, normalize_name(&entity.name));
+            if let Some(Some(found)) = resolved.get(&key) {
+                entity.identifier = Some(found.identifier.clone());
+                entity.source = Some(found.source.to_string());
+                filled += 1;
+            }
+        }
+
+        if filled > 0 {
+            info!(
+                "Resolved identifiers of {} of {} biological entities",
+                filled,
+                entities.len()
+            );
+        }
+        enriched
+    }
+
+    /// Identifier of `name` in `ontology`, from the cache or a lookup. `None` when there is
+    /// no match or the lookup failed.
+    async fn resolve(&self, ontology: Ontology, name: &str) -> Option<ResolvedIdentifier> {
+        let key = (ontology, name.to_string());
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .cloned();
+        self.metrics
+            .record_cache_lookup(CACHE_NAME, cached.is_some());
+        if let Some(resolution) = cached {
+            return resolution;
+        }
+
+        let lookup = match ontology {
+            Ontology::NcbiGene => self.lookup_ncbi_gene(name).await,
+            Ontology::UniProt => self.lookup_uniprot(name).await,
+            Ontology::Mesh => self.lookup_mesh(name).await,
+        };
+        match lookup {
+            Ok(identifier) => {
+                let resolution = identifier.map(|identifier| ResolvedIdentifier {
+                    identifier,
+                    source: ontology.source(),
+                });
+                self.cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .put(key, resolution.clone());
+                resolution
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to resolve '{}' against {}: {}",
+                    name,
+                    ontology.source(),
+                    e
+                );
+                self.metrics.record_external_error(ontology.service());
+                None
+            }
+        }
+    }
+
+    /// NCBI Gene ID of the first gene with `name` as its symbol or name
+    async fn lookup_ncbi_gene(&self, name: &str) -> Result<Option<String>, AppError> {
+        let term = format!("{}[Gene Name]", name);
+        let body = self
+            .get_json(
+                NCBI_ESEARCH_URL,
+                &[("db", "gene"), ("term", &term), ("retmode", "json")],
+            )
+            .await?;
+        Ok(body
+            .pointer("/esearchresult/idlist/0")
+            .and_then(Value::as_str)
+            .map(str::to_string))
+    }
+
+    /// UniProtKB accession of the best-ranked reviewed entry matching `name`
+    async fn lookup_uniprot(&self, name: &str) -> Result<Option<String>, AppError> {
+        let query = format!(
+            "(protein_name:\"{0}\" OR gene_exact:\"{0}\") AND reviewed:true",
+            name.replace('"', "")
+        );
+        let body = self
+            .get_json(
+                UNIPROT_SEARCH_URL,
+                &[
+                    ("query", &query),
+                    ("fields", "accession"),
+                    ("format", "json"),
+                    ("size", "1"),
+                ],
+            )
+            .await?;
+        Ok(body
+            .pointer("/results/0/primaryAccession")
+            .and_then(Value::as_str)
+            .map(str::to_string))
+    }
+
+    /// MeSH descriptor ID (e.g. `D003920`) whose label is exactly `name`
+    async fn lookup_mesh(&self, name: &str) -> Result<Option<String>, AppError> {
+        let body = self
+            .get_json(
+                MESH_LOOKUP_URL,
+                &[("label", name), ("match", "exact"), ("limit", "1")],
+            )
+            .await?;
+        Ok(body
+            .pointer("/0/resource")
+            .and_then(Value::as_str)
+            .and_then(|resource| resource.rsplit('/').next())
+            .map(str::to_string))
+    }
+
+    async fn get_json(&self, url: &str, query: &[(&str, &str)]) -> Result<Value, AppError> {
+        let response = self
+            .client
+            .get(url)
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| AppError::ExternalServiceError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(AppError::ExternalServiceError(format!(
+                "{} returned {}",
+                url,
+                response.status()
+            )));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|_| AppError::DeserializationError)
+    }
+}
+
+/// Cache key form of an entity name: trimmed, lowercase, single-spaced
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
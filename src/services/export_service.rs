@@ -0,0 +1,178 @@
+use crate::errors::AppError;
+use crate::services::did_service::DIDService;
+use crate::services::research_paper_service::ResearchPaperService;
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+
+/// Index of an export archive, written last as `manifest.json`
+#[derive(Debug, Serialize)]
+pub struct ExportManifest {
+    pub user_id: i64,
+    pub exported_at: DateTime<Utc>,
+    pub dids: Vec<ExportedDid>,
+    // DIDs that could not be exported, with the reason
+    pub errors: Vec<ExportError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedDid {
+    pub did: String,
+    // CID of the DID document on IPFS
+    pub cid: String,
+    // Path of the document inside the archive
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paper: Option<ExportedPaper>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedPaper {
+    // CID of the paper file on IPFS
+    pub cid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub knowledge_graph_cid: Option<String>,
+    pub file: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportError {
+    pub did: String,
+    pub error: String,
+}
+
+/// Packages a user's DID documents and paper metadata into a ZIP archive
+pub struct ExportService {
+    did_service: Arc<DIDService>,
+    research_paper_service: Arc<ResearchPaperService>,
+}
+
+impl ExportService {
+    pub fn new(
+        did_service: Arc<DIDService>,
+        research_paper_service: Arc<ResearchPaperService>,
+    ) -> Self {
+        Self {
+            did_service,
+            research_paper_service,
+        }
+    }
+
+    /// Write the user's archive to `writer`.
+    ///
+    /// Documents are fetched and written one at a time, so memory use doesn't grow with the
+    /// number of DIDs. A DID that fails to resolve is listed under `errors` in the manifest
+    /// instead of aborting the export; a failing writer (e.g. the client went away) does.
+    pub async fn write_user_archive<W>(
+        &self,
+        user_id: i64,
+        writer: W,
+    ) -> Result<ExportManifest, AppError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut zip = ZipFileWriter::with_tokio(writer);
+        let mut manifest = ExportManifest {
+            user_id,
+            exported_at: Utc::now(),
+            dids: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        for (did, cid) in self.did_service.list_user_dids(user_id).await? {
+            let document = match self.did_service.resolve_did(&did).await {
+                Ok(document) => document,
+                Err(e) => {
+                    warn!("Skipping DID {} in export: {}", did, e);
+                    manifest.errors.push(ExportError {
+                        did,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            let name = archive_name(&did);
+            let file = format!("dids/{}.json", name);
+            write_json_entry(&mut zip, &file, &document).await?;
+
+            let paper = match self
+                .research_paper_service
+                .get_paper_metadata_by_did(&did)
+                .await
+            {
+                Ok(paper) => {
+                    let paper_file = format!("papers/{}.json", name);
+                    write_json_entry(&mut zip, &paper_file, &paper).await?;
+                    Some(ExportedPaper {
+                        cid: paper.cid,
+                        knowledge_graph_cid: paper.knowledge_graph_cid,
+                        file: paper_file,
+                    })
+                }
+                Err(AppError::NotFound(_)) => None,
+                Err(e) => {
+                    warn!("Skipping paper metadata for {} in export: {}", did, e);
+                    manifest.errors.push(ExportError {
+                        did: did.clone(),
+                        error: format!("paper metadata: {}", e),
+                    });
+                    None
+                }
+            };
+
+            manifest.dids.push(ExportedDid {
+                did,
+                cid,
+                file,
+                paper,
+            });
+        }
+
+        write_json_entry(&mut zip, "manifest.json", &manifest).await?;
+        zip.close().await.map_err(archive_error)?;
+
+        info!(
+            "Exported {} DIDs for user {} ({} errors)",
+            manifest.dids.len(),
+            user_id,
+            manifest.errors.len()
+        );
+        Ok(manifest)
+    }
+}
+
+async fn write_json_entry<W, T>(
+    zip: &mut ZipFileWriter<W>,
+    path: &str,
+    value: &T,
+) -> Result<(), AppError>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let bytes = serde_json::to_vec_pretty(value).map_err(|_| AppError::SerializationError)?;
+    let entry = ZipEntryBuilder::new(path.into(), Compression::Deflate);
+    zip.write_entry_whole(entry, &bytes)
+        .await
+        .map_err(archive_error)
+}
+
+/// File-name-safe form of a DID, e.g. `did:bio:abc` becomes `did_bio_abc`
+fn archive_name(did: &str) -> String {
+    did.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn archive_error(e: async_zip::error::ZipError) -> AppError {
+    error!("Failed to write export archive: {}", e);
+    AppError::ServiceError(format!("Failed to write export archive: {}", e))
+}
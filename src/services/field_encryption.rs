@@ -0,0 +1,467 @@
+use crate::config::Config;
+use crate::errors::AppError;
+use crate::models::did::BiometadataExtension;
+use base64::engine::general_purpose::STANDARD as Base64Engine;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use log::{error, info};
+use pqcrypto_kyber::kyber1024::{self, PublicKey, SecretKey};
+use pqcrypto_traits::kem::{Ciphertext as _, SharedSecret as _};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// `alg` of sealed fields: a Kyber1024 shared secret, through HKDF-SHA256, keys
+/// ChaCha20-Poly1305
+const SEAL_ALGORITHM: &str = "Kyber1024+HKDF-SHA256+ChaCha20-Poly1305";
+
+/// HKDF info deriving the field key from the shared secret
+const FIELD_KEY_INFO: &[u8] = b"bio-did-seq:field-encryption:v2";
+
+/// One step of a field path: an object key, optionally followed by `[]` for every element
+/// of the array found there
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PathSegment {
+    key: String,
+    each: bool,
+}
+
+/// A field of the document metadata to encrypt, e.g. `researchers[].email`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldPath(Vec<PathSegment>);
+
+impl FieldPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let segments = path
+            .trim()
+            .split('.')
+            .map(|segment| {
+                let (key, each) = match segment.strip_suffix("[]") {
+                    Some(key) => (key, true),
+                    None => (segment, false),
+                };
+                if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format!(
+                        "Invalid field path '{}', expected keys such as researchers[].email",
+                        path
+                    ));
+                }
+                Ok(PathSegment {
+                    key: key.to_string(),
+                    each,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(segments))
+    }
+
+    /// Call `f` on every non-null value at this path under `root`
+    fn for_each(&self, root: &mut Value, f: &mut impl FnMut(&mut Value)) {
+        visit(root, &self.0, f);
+    }
+}
+
+fn visit(value: &mut Value, segments: &[PathSegment], f: &mut impl FnMut(&mut Value)) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if !value.is_null() {
+            f(value);
+        }
+        return;
+    };
+    let Some(child) = value.get_mut(&segment.key) else {
+        return;
+    };
+    if segment.each {
+        if let Some(elements) = child.as_array_mut() {
+            for element in elements {
+                visit(element, rest, f);
+            }
+        }
+    } else {
+        visit(child, rest, f);
+    }
+}
+
+/// Whether `value` is a field sealed by `FieldEncryption::seal_document`
+fn is_sealed(value: &Value) -> bool {
+    value.get("encrypted") == Some(&Value::Bool(true)) && value.get("kem").is_some()
+}
+
+/// Kyber1024 key pairs of the key registry's versions. Fields are sealed to the current
+/// version's key; every version with a secret key opens what was sealed to it.
+#[derive(Default)]
+pub struct KemKeyring {
+    current: Option<u32>,
+    keys: HashMap<u32, (PublicKey, SecretKey)>,
+}
+
+impl KemKeyring {
+    /// `current` is `None`, or ignored, when that version has no Kyber1024 key pair
+    pub fn new(current: u32, keys: HashMap<u32, (PublicKey, SecretKey)>) -> Self {
+        Self {
+            current: Some(current).filter(|version| keys.contains_key(version)),
+            keys,
+        }
+    }
+
+    pub fn current_version(&self) -> Option<u32> {
+        self.current
+    }
+}
+
+/// Encrypts selected fields of DID document metadata before the document is stored on
+/// IPFS, so contact details and the like stay private while the title and keywords remain
+/// public for discovery.
+///
+/// Each field is sealed on its own: a fresh Kyber1024 encapsulation against the current
+/// key registry version gives a shared secret, HKDF-SHA256 derives a ChaCha20-Poly1305 key
+/// from it, and the field's JSON is encrypted with the DID as associated data, so a sealed
+/// field can't be moved to another document. The sealed field names its key version, which
+/// picks the secret key opening it.
+pub struct FieldEncryption {
+    paths: Vec<FieldPath>,
+    // Installed by the key registry on every reload
+    keyring: RwLock<Arc<KemKeyring>>,
+}
+
+impl FieldEncryption {
+    pub fn new(paths: Vec<FieldPath>) -> Self {
+        Self {
+            paths,
+            keyring: RwLock::new(Arc::new(KemKeyring::default())),
+        }
+    }
+
+    /// Encryption of `DID_ENCRYPTED_FIELDS`, keyed once the key registry installs its keys
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let paths = config
+            .did_encrypted_fields
+            .iter()
+            .map(|path| FieldPath::parse(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !paths.is_empty() {
+            info!(
+                "Encrypting {} DID metadata fields: {}",
+                paths.len(),
+                config.did_encrypted_fields.join(", ")
+            );
+        }
+        Ok(Self::new(paths))
+    }
+
+    /// Whether any field is encrypted, and so hidden from readers other than the owner
+    pub fn has_fields(&self) -> bool {
+        !self.paths.is_empty()
+    }
+
+    pub fn install_keys(&self, keyring: KemKeyring) {
+        *self.keyring.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(keyring);
+    }
+
+    /// Key version new fields are sealed to, if the current version has a Kyber1024 key
+    pub fn current_kem_version(&self) -> Option<u32> {
+        self.keyring().current_version()
+    }
+
+    fn keyring(&self) -> Arc<KemKeyring> {
+        self.keyring
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Seal the configured fields of `document`'s metadata in place
+    pub fn seal_document(&self, document: &mut Value) -> Result<(), AppError> {
+        let did = document["id"].as_str().unwrap_or_default().to_string();
+        let Some(metadata) = document.get_mut("metadata") else {
+            return Ok(());
+        };
+        let keyring = self.keyring();
+        let mut result = Ok(());
+        for path in &self.paths {
+            path.for_each(metadata, &mut |field| {
+                if result.is_err() || is_sealed(field) {
+                    return;
+                }
+                match seal(&keyring, &did, field) {
+                    Ok(sealed) => *field = sealed,
+                    Err(e) => result = Err(e),
+                }
+            });
+        }
+        result
+    }
+
+    /// Decrypt every sealed field of `document`'s metadata in place, whether or not its path
+    /// is still configured
+    pub fn open_document(&self, document: &mut Value) -> Result<(), AppError> {
+        let did = document["id"].as_str().unwrap_or_default().to_string();
+        match document.get_mut("metadata") {
+            Some(metadata) => open_all(&self.keyring(), &did, metadata),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the configured fields of `metadata` with `{"encrypted": true}`, for readers
+    /// who may not see them
+    pub fn redact_metadata(&self, metadata: &mut Value) {
+        for path in &self.paths {
+            path.for_each(metadata, &mut |field| *field = json!({ "encrypted": true }));
+        }
+    }
+
+    /// Copy of `metadata` without the encrypted fields, for documents derived from it by
+    /// users who may not read them
+    pub fn strip_metadata(
+        &self,
+        metadata: &BiometadataExtension,
+    ) -> Result<BiometadataExtension, AppError> {
+        let mut value = serde_json::to_value(metadata).map_err(|_| AppError::SerializationError)?;
+        for path in &self.paths {
+            path.for_each(&mut value, &mut |field| *field = Value::Null);
+        }
+        serde_json::from_value(value).map_err(|e| {
+            AppError::ValidationError(format!(
+                "Metadata can't be copied without its encrypted fields: {}",
+                e
+            ))
+        })
+    }
+}
+
+fn open_all(keyring: &KemKeyring, did: &str, value: &mut Value) -> Result<(), AppError> {
+    if is_sealed(value) {
+        *value = open(keyring, did, value)?;
+        return Ok(());
+    }
+    match value {
+        Value::Object(map) => map
+            .values_mut()
+            .try_for_each(|value| open_all(keyring, did, value)),
+        Value::Array(values) => values
+            .iter_mut()
+            .try_for_each(|value| open_all(keyring, did, value)),
+        _ => Ok(()),
+    }
+}
+
+/// ChaCha20-Poly1305 keyed by a Kyber1024 shared secret
+fn field_cipher(shared_secret: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(None, shared_secret)
+        .expand(FIELD_KEY_INFO, &mut key)
+        .expect("HKDF-SHA256 derives 32 bytes");
+    ChaCha20Poly1305::new(&key)
+}
+
+fn seal(keyring: &KemKeyring, did: &str, field: &Value) -> Result<Value, AppError> {
+    let (version, (public_key, _)) = keyring
+        .current
+        .and_then(|version| Some((version, keyring.keys.get(&version)?)))
+        .ok_or_else(|| {
+            error!("No Kyber1024 key to encrypt DID fields with; rotate the keys");
+            AppError::ServiceError("DID field encryption has no current key".to_string())
+        })?;
+    let plaintext = serde_json::to_vec(field).map_err(|_| AppError::SerializationError)?;
+    let (shared_secret, kem_ciphertext) = kyber1024::encapsulate(public_key);
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = field_cipher(shared_secret.as_bytes())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &plaintext,
+                aad: did.as_bytes(),
+            },
+        )
+        .map_err(|_| AppError::ServiceError("Failed to encrypt DID field".to_string()))?;
+
+    let mut sealed = Map::new();
+    sealed.insert("encrypted".to_string(), Value::Bool(true));
+    sealed.insert("alg".to_string(), json!(SEAL_ALGORITHM));
+    sealed.insert("key_version".to_string(), json!(version));
+    sealed.insert(
+        "kem".to_string(),
+        json!(Base64Engine.encode(kem_ciphertext.as_bytes())),
+    );
+    sealed.insert("nonce".to_string(), json!(Base64Engine.encode(nonce)));
+    sealed.insert(
+        "ciphertext".to_string(),
+        json!(Base64Engine.encode(&ciphertext)),
+    );
+    Ok(Value::Object(sealed))
+}
+
+fn open(keyring: &KemKeyring, did: &str, sealed: &Value) -> Result<Value, AppError> {
+    let invalid = || {
+        error!(
+            "Encrypted field of {} is malformed or was tampered with",
+            did
+        );
+        AppError::DeserializationError
+    };
+    if sealed["alg"].as_str() != Some(SEAL_ALGORITHM) {
+        return Err(invalid());
+    }
+    let version = sealed["key_version"]
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+        .ok_or_else(invalid)?;
+    let (_, secret_key) = keyring.keys.get(&version).ok_or_else(|| {
+        error!(
+            "Encrypted field of {} needs key version {}, which has no secret key",
+            did, version
+        );
+        AppError::ServiceError(format!("Key version {} isn't available", version))
+    })?;
+    let part = |name: &str| {
+        sealed[name]
+            .as_str()
+            .and_then(|encoded| Base64Engine.decode(encoded).ok())
+            .ok_or_else(invalid)
+    };
+    let kem_ciphertext = kyber1024::Ciphertext::from_bytes(&part("kem")?).map_err(|_| invalid())?;
+    let nonce = part("nonce")?;
+    if nonce.len() != 12 {
+        return Err(invalid());
+    }
+    let ciphertext = part("ciphertext")?;
+
+    // Kyber decapsulation never fails outright; a wrong key or ciphertext shows up here
+    let shared_secret = kyber1024::decapsulate(&kem_ciphertext, secret_key);
+    let plaintext = field_cipher(shared_secret.as_bytes())
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: did.as_bytes(),
+            },
+        )
+        .map_err(|_| invalid())?;
+    serde_json::from_slice(&plaintext).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encryption(paths: &[&str]) -> FieldEncryption {
+        let encryption = FieldEncryption::new(
+            paths
+                .iter()
+                .map(|path| FieldPath::parse(path).unwrap())
+                .collect(),
+        );
+        encryption.install_keys(KemKeyring::new(
+            1,
+            HashMap::from([(1, kyber1024::keypair())]),
+        ));
+        encryption
+    }
+
+    #[test]
+    fn test_selected_fields_are_sealed_opened_and_redacted() {
+        let encryption = encryption(&["researchers[].email", "funding_info"]);
+        let document = json!({
+            "id": "did:bio:1",
+            "metadata": {
+                "title": "Yeast proteome",
+                "keywords": ["proteomics"],
+                "researchers": [
+                    { "name": "A", "email": "a@example.org" },
+                    { "name": "B", "email": null },
+                ],
+                "funding_info": [{ "funder": "NIH", "grant_id": "R01" }],
+            }
+        });
+
+        let mut sealed = document.clone();
+        encryption.seal_document(&mut sealed).unwrap();
+        let metadata = &sealed["metadata"];
+        assert_eq!(metadata["title"], "Yeast proteome");
+        assert_eq!(metadata["researchers"][0]["name"], "A");
+        assert!(is_sealed(&metadata["researchers"][0]["email"]));
+        assert!(metadata["researchers"][1]["email"].is_null());
+        assert!(is_sealed(&metadata["funding_info"]));
+        assert!(!sealed.to_string().contains("a@example.org"));
+
+        // Sealing again leaves sealed fields alone
+        let mut resealed = sealed.clone();
+        encryption.seal_document(&mut resealed).unwrap();
+        assert_eq!(resealed, sealed);
+
+        let mut opened = sealed.clone();
+        encryption.open_document(&mut opened).unwrap();
+        assert_eq!(opened, document);
+
+        // After a rotation new fields are sealed to the new version, and the old one
+        // still opens what it sealed
+        let previous = encryption.keyring().keys.get(&1).cloned().unwrap();
+        encryption.install_keys(KemKeyring::new(
+            2,
+            HashMap::from([(1, previous), (2, kyber1024::keypair())]),
+        ));
+        let mut rotated = document.clone();
+        encryption.seal_document(&mut rotated).unwrap();
+        assert_eq!(rotated["metadata"]["funding_info"]["key_version"], 2);
+        let mut opened = sealed.clone();
+        encryption.open_document(&mut opened).unwrap();
+        assert_eq!(opened, document);
+        encryption.install_keys(KemKeyring::new(2, HashMap::new()));
+        assert!(encryption.open_document(&mut sealed.clone()).is_err());
+        assert!(encryption.seal_document(&mut document.clone()).is_err());
+        encryption.install_keys(KemKeyring::new(2, HashMap::from([(1, previous)])));
+
+        // Sealed fields are bound to their DID and authenticated
+        let mut moved = sealed.clone();
+        moved["id"] = json!("did:bio:2");
+        assert!(encryption.open_document(&mut moved).is_err());
+        let mut tampered = sealed.clone();
+        tampered["metadata"]["funding_info"]["ciphertext"] = json!(Base64Engine.encode(b"{}"));
+        assert!(encryption.open_document(&mut tampered).is_err());
+
+        let mut public = document["metadata"].clone();
+        encryption.redact_metadata(&mut public);
+        assert_eq!(
+            public["researchers"][0]["email"],
+            json!({ "encrypted": true })
+        );
+        assert!(public["researchers"][1]["email"].is_null());
+        assert_eq!(public["funding_info"], json!({ "encrypted": true }));
+        assert_eq!(public["keywords"], json!(["proteomics"]));
+
+        assert!(FieldPath::parse("researchers[].").is_err());
+        assert!(FieldPath::parse("researchers[0]").is_err());
+    }
+
+    #[test]
+    fn test_stripped_metadata_has_no_encrypted_fields() {
+        let sealed = encryption(&["researchers[].email", "funding_info"]);
+        let metadata: BiometadataExtension = serde_json::from_value(json!({
+            "title": "Yeast proteome",
+            "researchers": [{ "name": "A", "role": "PI", "email": "a@example.org" }],
+            "keywords": ["proteomics"],
+            "data_type": "proteomics",
+            "funding_info": [{ "funder_name": "NIH", "grant_id": "R01-secret" }],
+            "creation_date": "2026-01-01T00:00:00Z",
+            "last_modified": "2026-01-01T00:00:00Z",
+        }))
+        .unwrap();
+
+        let stripped = sealed.strip_metadata(&metadata).unwrap();
+        let copied = serde_json::to_string(&stripped).unwrap();
+        assert!(!copied.contains("a@example.org"));
+        assert!(!copied.contains("R01-secret"));
+        assert_eq!(stripped.researchers[0].name, "A");
+        assert!(stripped.funding_info.is_none());
+
+        let required = encryption(&["title"]);
+        assert!(matches!(
+            required.strip_metadata(&metadata),
+            Err(AppError::ValidationError(_))
+        ));
+    }
+}
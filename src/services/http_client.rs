@@ -0,0 +1,221 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{IntoUrl, Method, RequestBuilder, Url};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `User-Agent` sent to external APIs unless `HTTP_USER_AGENT` overrides it
+pub const DEFAULT_USER_AGENT: &str = concat!("bio-did-seq/", env!("CARGO_PKG_VERSION"));
+
+/// Request timeout until a service sets its own with `with_timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// HTTP client shared by the services calling external APIs (BioAgents, Dataverse,
+/// NCBI/UniProt/MeSH, ORCID and IPFS gateways). Webhook subscribers get a `public_only`
+/// client built from it.
+///
+/// It is built once, so connections are pooled across services and TLS and proxy
+/// settings (`HTTPS_PROXY` and friends) live in one place. Clones share the pool;
+/// `with_timeout` gives a service its own request timeout on top of it.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    // Applied to every request; a request can still set its own
+    timeout: Duration,
+    user_agent: String,
+    connect_timeout: Duration,
+}
+
+impl HttpClient {
+    pub fn new(user_agent: &str, connect_timeout: Duration) -> Result<Self, reqwest::Error> {
+        let client = Self::builder(user_agent, connect_timeout).build()?;
+        Ok(Self {
+            client,
+            timeout: DEFAULT_TIMEOUT,
+            user_agent: user_agent.to_string(),
+            connect_timeout,
+        })
+    }
+
+    fn builder(user_agent: &str, connect_timeout: Duration) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(connect_timeout)
+            .pool_idle_timeout(Duration::from_secs(90))
+    }
+
+    /// The same pooled client with a different default request timeout
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            client: self.client.clone(),
+            timeout,
+            user_agent: self.user_agent.clone(),
+            connect_timeout: self.connect_timeout,
+        }
+    }
+
+    /// A separate client, with the same settings, for URLs chosen by users. It doesn't
+    /// follow redirects and won't connect to host names resolving to addresses that
+    /// aren't public, so such URLs can't reach the service's own network. URLs with an
+    /// IP address as host bypass DNS; check them with `check_public_url` first.
+    pub fn public_only(&self) -> Result<Self, reqwest::Error> {
+        let client = Self::builder(&self.user_agent, self.connect_timeout)
+            .redirect(Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()?;
+        Ok(Self {
+            client,
+            timeout: self.timeout,
+            user_agent: self.user_agent.clone(),
+            connect_timeout: self.connect_timeout,
+        })
+    }
+
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url).timeout(self.timeout)
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::PUT, url)
+    }
+
+    pub fn delete<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+}
+
+/// Whether `ip` is reachable on the internet rather than loopback, private, link-local,
+/// unspecified, broadcast or multicast
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(IpAddr::V4(mapped)),
+            None => {
+                let segment = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || segment & 0xfe00 == 0xfc00
+                    || segment & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolve the host of an http(s) URL and check every address it resolves to is public
+pub async fn check_public_url(url: &str) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+    let host = url.host_str().ok_or("URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    // IPv6 hosts keep their brackets in URLs
+    let addresses: Vec<IpAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+            .map(|address| address.ip())
+            .collect(),
+    };
+
+    match addresses.into_iter().find(|ip| !is_public_address(*ip)) {
+        Some(ip) => Err(format!(
+            "{} resolves to {}, which is not a public address",
+            host, ip
+        )),
+        None => Ok(()),
+    }
+}
+
+/// System resolver that fails for names resolving to any address that isn't public,
+/// checked as the connection is made so a name can't be re-pointed after validation
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(address) = addresses.iter().find(|a| !is_public_address(a.ip())) {
+                return Err(format!(
+                    "{} resolves to {}, which is not a public address",
+                    name.as_str(),
+                    address.ip()
+                )
+                .into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_get_the_client_timeout_unless_they_set_one() {
+        let base = HttpClient::new(DEFAULT_USER_AGENT, Duration::from_secs(1)).unwrap();
+        let dataverse = base.with_timeout(Duration::from_secs(120));
+
+        let timeout = |request: RequestBuilder| request.build().unwrap().timeout().copied();
+        assert_eq!(
+            timeout(base.get("http://localhost/")),
+            Some(DEFAULT_TIMEOUT)
+        );
+        assert_eq!(
+            timeout(dataverse.post("http://localhost/")),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            timeout(
+                dataverse
+                    .delete("http://localhost/")
+                    .timeout(Duration::from_secs(5))
+            ),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_urls_of_non_public_hosts_are_rejected() {
+        for url in [
+            "http://127.0.0.1:5001/api/v0/id",
+            "http://localhost:5001/api/v0/id",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.8/",
+            "https://172.16.4.1/",
+            "http://192.168.1.1:8080/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:192.168.1.1]/",
+        ] {
+            assert!(check_public_url(url).await.is_err(), "{} was accepted", url);
+        }
+
+        assert!(check_public_url("https://93.184.216.34/hook").await.is_ok());
+        assert!(check_public_url("https://[2606:4700::1111]/hook")
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,269 @@
+use crate::database::db_time::to_db;
+use crate::errors::AppError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use log::{error, info};
+use mysql_async::{prelude::*, Pool};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Header clients set to make a create request safe to retry
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+/// Header set on responses that were replayed from a stored result
+pub const REPLAYED_HEADER: &str = "Idempotency-Replayed";
+
+/// Status stored for a key whose request is still being processed
+const PENDING_STATUS: u16 = 0;
+
+/// Age after which a pending reservation is assumed abandoned and may be taken over
+const PENDING_TIMEOUT: Duration = Duration::minutes(10);
+
+/// Idempotency key supplied with a request, scoped to one endpoint
+pub struct IdempotencyKey {
+    pub key: String,
+    pub endpoint: &'static str,
+    pub request_hash: String,
+}
+
+impl IdempotencyKey {
+    /// Read the `Idempotency-Key` header, returning `None` when the client did not send one
+    pub fn from_request<T: Serialize>(
+        req: &HttpRequest,
+        endpoint: &'static str,
+        body: &T,
+    ) -> Result<Option<Self>, AppError> {
+        let key = match req.headers().get(IDEMPOTENCY_HEADER) {
+            Some(value) => value
+                .to_str()
+                .map_err(|_| {
+                    AppError::ValidationError("Idempotency-Key must be valid ASCII".to_string())
+                })?
+                .trim()
+                .to_string(),
+            None => return Ok(None),
+        };
+
+        if key.is_empty() || key.len() > 255 {
+            return Err(AppError::ValidationError(
+                "Idempotency-Key must be between 1 and 255 characters".to_string(),
+            ));
+        }
+
+        let body_json = serde_json::to_vec(body).map_err(|e| {
+            error!("Failed to serialize request body for hashing: {}", e);
+            AppError::SerializationError
+        })?;
+
+        Ok(Some(Self {
+            key,
+            endpoint,
+            request_hash: format!("{:x}", Sha256::digest(&body_json)),
+        }))
+    }
+}
+
+/// Stores responses for idempotent create requests so retries don't create duplicates
+pub struct IdempotencyService {
+    db_pool: Arc<Pool>,
+}
+
+impl IdempotencyService {
+    pub fn new(db_pool: Arc<Pool>) -> Self {
+        Self { db_pool }
+    }
+
+    /// Reserve a key before doing the work it guards.
+    ///
+    /// Returns the stored response when the key was already completed, `None` once the
+    /// key is reserved for this request, and `Conflict` if the key was used with a
+    /// different request body or another request holding it is still in progress.
+    pub async fn reserve(
+        &self,
+        user_id: i64,
+        key: &IdempotencyKey,
+    ) -> Result<Option<HttpResponse>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let now = Utc::now();
+        r"INSERT IGNORE INTO idempotency_keys
+            (idempotency_key, user_id, endpoint, request_hash, status_code, response_body, created_at)
+          VALUES (:key, :user_id, :endpoint, :request_hash, :pending, '', :created_at)"
+            .with(params! {
+                "key" => &key.key,
+                "user_id" => user_id,
+                "endpoint" => key.endpoint,
+                "request_hash" => &key.request_hash,
+                "pending" => PENDING_STATUS,
+                "created_at" => to_db(now),
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reserving idempotency key: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        if conn.affected_rows() == 1 {
+            return Ok(None);
+        }
+
+        let stored: Option<(String, u16, String)> =
+            r"SELECT request_hash, status_code, response_body
+              FROM idempotency_keys
+              WHERE user_id = :user_id AND endpoint = :endpoint AND idempotency_key = :key"
+                .with(params! {
+                    "user_id" => user_id,
+                    "endpoint" => key.endpoint,
+                    "key" => &key.key,
+                })
+                .first(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when looking up idempotency key: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+
+        // Released between the insert and the lookup; the retry may run again
+        let Some((request_hash, status_code, body)) = stored else {
+            return Err(in_progress(key));
+        };
+
+        if request_hash != key.request_hash {
+            return Err(AppError::Conflict(format!(
+                "Idempotency-Key '{}' was already used with a different request body",
+                key.key
+            )));
+        }
+
+        if status_code == PENDING_STATUS {
+            // A reservation left behind by a crashed request is taken over once it is stale
+            r"UPDATE idempotency_keys SET created_at = :created_at
+              WHERE user_id = :user_id AND endpoint = :endpoint AND idempotency_key = :key
+                AND status_code = :pending AND created_at < :stale_before"
+                .with(params! {
+                    "created_at" => to_db(now),
+                    "user_id" => user_id,
+                    "endpoint" => key.endpoint,
+                    "key" => &key.key,
+                    "pending" => PENDING_STATUS,
+                    "stale_before" => to_db(now - PENDING_TIMEOUT),
+                })
+                .ignore(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when reclaiming idempotency key: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+            if conn.affected_rows() == 1 {
+                info!(
+                    "Reclaimed stale idempotency key {} on {}",
+                    key.key, key.endpoint
+                );
+                return Ok(None);
+            }
+            return Err(in_progress(key));
+        }
+
+        info!(
+            "Replaying stored response for idempotency key {} on {}",
+            key.key, key.endpoint
+        );
+
+        let status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK);
+        Ok(Some(
+            HttpResponse::build(status)
+                .content_type("application/json")
+                .insert_header((REPLAYED_HEADER, "true"))
+                .body(body),
+        ))
+    }
+
+    /// Drop a reservation after the guarded work failed so the client can retry
+    ///
+    /// Failures are only logged; the reservation then expires after `PENDING_TIMEOUT`.
+    pub async fn release(&self, user_id: i64, key: &IdempotencyKey) {
+        let result = async {
+            let mut conn = self.db_pool.get_conn().await?;
+            r"DELETE FROM idempotency_keys
+              WHERE user_id = :user_id AND endpoint = :endpoint AND idempotency_key = :key
+                AND status_code = :pending"
+                .with(params! {
+                    "user_id" => user_id,
+                    "endpoint" => key.endpoint,
+                    "key" => &key.key,
+                    "pending" => PENDING_STATUS,
+                })
+                .ignore(&mut conn)
+                .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            error!(
+                "Failed to release idempotency key {} on {}: {}",
+                key.key, key.endpoint, e
+            );
+        }
+    }
+
+    /// Store the response for a reserved key so later retries can replay it
+    ///
+    /// The work has already succeeded, so failures are logged rather than returned; the
+    /// reservation then expires after `PENDING_TIMEOUT` and a retry runs again.
+    pub async fn record<T: Serialize>(
+        &self,
+        user_id: i64,
+        key: &IdempotencyKey,
+        status: StatusCode,
+        response: &T,
+        resource_id: Option<&str>,
+    ) {
+        let body = match serde_json::to_string(response) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize response for idempotency record: {}", e);
+                return;
+            }
+        };
+
+        let result = async {
+            let mut conn = self.db_pool.get_conn().await?;
+            r"UPDATE idempotency_keys
+              SET status_code = :status_code, response_body = :response_body,
+                  resource_id = :resource_id
+              WHERE user_id = :user_id AND endpoint = :endpoint AND idempotency_key = :key
+                AND status_code = :pending"
+                .with(params! {
+                    "status_code" => status.as_u16(),
+                    "response_body" => body,
+                    "resource_id" => resource_id,
+                    "user_id" => user_id,
+                    "endpoint" => key.endpoint,
+                    "key" => &key.key,
+                    "pending" => PENDING_STATUS,
+                })
+                .ignore(&mut conn)
+                .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            error!(
+                "Failed to record response for idempotency key {} on {}: {}",
+                key.key, key.endpoint, e
+            );
+        }
+    }
+}
+
+fn in_progress(key: &IdempotencyKey) -> AppError {
+    AppError::Conflict(format!(
+        "A request with Idempotency-Key '{}' is still in progress",
+        key.key
+    ))
+}
@@ -0,0 +1,201 @@
+use crate::config::Config;
+use crate::errors::AppError;
+use crate::services::http_client::HttpClient;
+use log::{error, info};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Pin status a cluster peer reports once it holds the content
+const PINNED: &str = "pinned";
+
+/// Status of peers the cluster didn't allocate the pin to; they are left out of reports
+const REMOTE: &str = "remote";
+
+/// Pin state of a CID on one cluster peer
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PeerPinStatus {
+    pub peer_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_name: Option<String>,
+    // As reported by the cluster, e.g. pinned, pinning, pin_queued or pin_error
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How many copies of a CID the cluster holds
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ReplicationStatus {
+    // Peers asked to pin each CID
+    pub replication_factor: usize,
+    // Peers that have finished pinning it
+    pub pinned: usize,
+    // `pinned` reached the replication factor
+    pub replicated: bool,
+    // Peers the pin is allocated to
+    pub peers: Vec<PeerPinStatus>,
+}
+
+/// Client of an IPFS Cluster REST API, which replicates pins across the cluster's peers.
+///
+/// Content is still added through the IPFS node; the cluster is then asked to pin it on
+/// `replication_factor` peers.
+#[derive(Debug, Clone)]
+pub struct IpfsCluster {
+    client: HttpClient,
+    api_url: String,
+    replication_factor: usize,
+}
+
+impl IpfsCluster {
+    /// `None` (single-node mode) unless `IPFS_CLUSTER_API` is set
+    pub fn from_config(config: &Config, client: &HttpClient) -> Option<Self> {
+        let Some(api_url) = &config.ipfs_cluster_api else {
+            info!("IPFS Cluster not configured, pinning on the IPFS node only");
+            return None;
+        };
+        info!(
+            "Pinning through IPFS Cluster at {} with replication factor {}",
+            api_url, config.ipfs_cluster_replication
+        );
+
+        Some(Self {
+            client: client.with_timeout(Duration::from_secs(30)),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            replication_factor: config.ipfs_cluster_replication,
+        })
+    }
+
+    /// Ask the cluster to pin `cid` on `replication_factor` peers. Returns once the cluster
+    /// has accepted the pin; peers pin in the background.
+    pub async fn pin(&self, cid: &str) -> Result<(), AppError> {
+        let url = format!(
+            "{}/pins/{}?replication-min={factor}&replication-max={factor}",
+            self.api_url,
+            cid,
+            factor = self.replication_factor
+        );
+        let response = self.client.post(&url).send().await.map_err(|e| {
+            error!("IPFS Cluster pin request for {} failed: {}", cid, e);
+            AppError::ExternalServiceError(format!("IPFS Cluster request failed: {}", e))
+        })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            error!("IPFS Cluster refused to pin {} ({}): {}", cid, status, body);
+            return Err(AppError::ExternalServiceError(format!(
+                "IPFS Cluster pin failed ({}): {}",
+                status, body
+            )));
+        }
+
+        info!(
+            "Requested IPFS Cluster pin of {} on {} peers",
+            cid, self.replication_factor
+        );
+        Ok(())
+    }
+
+    /// Per-peer pin status of `cid`
+    pub async fn status(&self, cid: &str) -> Result<ReplicationStatus, AppError> {
+        let url = format!("{}/pins/{}", self.api_url, cid);
+        let response = self.client.get(&url).send().await.map_err(|e| {
+            error!("IPFS Cluster status request for {} failed: {}", cid, e);
+            AppError::ExternalServiceError(format!("IPFS Cluster request failed: {}", e))
+        })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            error!(
+                "IPFS Cluster status of {} failed ({}): {}",
+                cid, status, body
+            );
+            return Err(AppError::ExternalServiceError(format!(
+                "IPFS Cluster status failed ({}): {}",
+                status, body
+            )));
+        }
+
+        let info: Value = response.json().await.map_err(|e| {
+            error!("Failed to parse IPFS Cluster status of {}: {}", cid, e);
+            AppError::DeserializationError
+        })?;
+        Ok(replication_status(&info, self.replication_factor))
+    }
+}
+
+/// Read a cluster `GlobalPinInfo` object, skipping peers the pin isn't allocated to
+fn replication_status(info: &Value, replication_factor: usize) -> ReplicationStatus {
+    let mut peers: Vec<PeerPinStatus> = info["peer_map"]
+        .as_object()
+        .map(|peer_map| {
+            peer_map
+                .iter()
+                .filter_map(|(peer_id, peer)| {
+                    let status = peer["status"].as_str()?;
+                    if status == REMOTE {
+                        return None;
+                    }
+                    Some(PeerPinStatus {
+                        peer_id: peer_id.clone(),
+                        peer_name: peer["peername"]
+                            .as_str()
+                            .filter(|name| !name.is_empty())
+                            .map(str::to_string),
+                        status: status.to_string(),
+                        error: peer["error"]
+                            .as_str()
+                            .filter(|error| !error.is_empty())
+                            .map(str::to_string),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+
+    let pinned = peers.iter().filter(|peer| peer.status == PINNED).count();
+    ReplicationStatus {
+        replication_factor,
+        pinned,
+        replicated: pinned >= replication_factor,
+        peers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_replication_counts_pinned_allocated_peers() {
+        let info = json!({
+            "cid": "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi",
+            "peer_map": {
+                "12D3KooWB": { "peername": "node-b", "status": "pin_error", "error": "context canceled" },
+                "12D3KooWA": { "peername": "node-a", "status": "pinned", "error": "" },
+                "12D3KooWC": { "peername": "node-c", "status": "remote", "error": "" }
+            }
+        });
+
+        let status = replication_status(&info, 2);
+        assert_eq!(status.pinned, 1);
+        assert!(!status.replicated);
+        let peers: Vec<(&str, &str)> = status
+            .peers
+            .iter()
+            .map(|peer| (peer.peer_id.as_str(), peer.status.as_str()))
+            .collect();
+        assert_eq!(
+            peers,
+            vec![("12D3KooWA", "pinned"), ("12D3KooWB", "pin_error")]
+        );
+        assert_eq!(status.peers[0].error, None);
+        assert_eq!(status.peers[1].error.as_deref(), Some("context canceled"));
+
+        assert!(replication_status(&info, 1).replicated);
+    }
+}
@@ -1,31 +1,42 @@
+use crate::database::db_time::{from_db_value, to_db};
 use crate::{
     config::Config,
     database::{
-        cleanup_expired_tasks, cleanup_failed_upload, init_schema, insert_file_metadata,
-        insert_initial_task, login_user, register_user, update_task_status,
+        cleanup_expired_tasks, cleanup_failed_upload, fail_stale_tasks, init_schema,
+        insert_file_metadata, insert_initial_task, login_user, register_user, reset_failed_task,
+        update_task_status, upsert_orcid_user,
     },
     errors::{AppError, ServiceError},
     middleware::rate_limiter::{cleanup_rate_limiters, RateLimiterEntry},
     models::{
-        auth::{Claims, TokenHeader},
+        auth::{AuthUser, Claims},
         file_metadata::*,
         requests::*,
     },
-    utils::upload_to_ipfs,
+    services::auth_token::{DilithiumKeyring, SigningContext, TokenSigner},
+    services::http_client::HttpClient,
+    services::ipfs_cluster::{IpfsCluster, ReplicationStatus},
+    services::notification_service::{NotificationService, TaskNotice},
+    services::orcid_service::OrcidProfile,
+    services::task_events::TaskEvents,
+    utils::{compute_cid, detect_mime, upload_to_ipfs, IpfsAddOptions, MIME_SNIFF_BYTES},
 };
-use chrono::{Duration, NaiveDateTime, TimeZone, Utc};
-use dashmap::DashMap;
-use futures::Stream;
+use chrono::{Duration, Utc};
+use dashmap::{DashMap, DashSet};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{FutureExt, Stream};
 use futures_util::StreamExt;
 use ipfs_api::{IpfsApi, IpfsClient, TryFromUri};
-use log::{error, info};
-use mysql_async::{prelude::*, Opts, Pool, Row, Value};
-use pqcrypto_dilithium::dilithium5::{self, PublicKey, SecretKey};
-use pqcrypto_traits::sign::{
-    DetachedSignature as DetachedSignatureTrait, PublicKey as OtherPublicKey,
-    SecretKey as OtherSecretKey,
-};
-use sha2::{Digest, Sha256};
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool, Row};
+use pqcrypto_traits::sign::{PublicKey as OtherPublicKey, SecretKey as OtherSecretKey};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 use tokio::sync::{oneshot, Semaphore};
 use uuid::Uuid;
@@ -33,14 +44,27 @@ use uuid::Uuid;
 use base64::engine::general_purpose::STANDARD as Base64Engine;
 use base64::Engine;
 
+/// Number of CIDs remembered by `add_content_dedup` before the cache is reset
+const KNOWN_CID_CACHE_LIMIT: usize = 100_000;
+
+/// Uploads finishing sooner than this don't send a completion email
+const MIN_NOTIFIED_UPLOAD_SECS: i64 = 60;
+
+/// CIDs stat'ed at once by `reconcile`
+const RECONCILE_CONCURRENCY: usize = 8;
+
+/// Unreachable and orphaned CIDs listed in a reconciliation report; the counts cover all
+const RECONCILE_SAMPLE_LIMIT: usize = 50;
+
+/// Upper bound on fetching one CID from a gateway during a re-import
+const REIMPORT_FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
 /// Service handling IPFS operations and user management
 pub struct IPFSService {
     pub client: IpfsClient,
     pub db_pool: Pool,
-    // Dilithium private key
-    signing_key: SecretKey,
-    // Dilithium public key
-    pub public_key: PublicKey,
+    // Signs and verifies auth tokens with the configured algorithms
+    token_signer: TokenSigner,
     // In-memory task tracking
     pub tasks: Arc<DashMap<String, TaskInfo>>,
     // Cap concurrent uploads
@@ -50,11 +74,131 @@ pub struct IPFSService {
     // Rate limiters for IP-based / user-specific request throttling
     // Managed via the `governor` crate to prevent excessive API usage
     pub rate_limiters: Arc<DashMap<String, RateLimiterEntry>>,
+    // CIDs known to already be stored, used to skip redundant adds
+    known_cids: Arc<DashSet<String>>,
+    // Gateways tried when content is missing from the current node
+    gateways: Vec<String>,
+    // Gzip JSON documents written through `add_content`
+    compress_documents: bool,
+    // Limits on `add_content` and `get_content`
+    add_timeout: std::time::Duration,
+    get_timeout: std::time::Duration,
+    // MFS directory DID documents are mirrored under, if enabled
+    mfs_root: Option<String>,
+    // Passed to every add, so CIDs don't depend on the node's defaults
+    add_options: IpfsAddOptions,
+    // Emails the uploader when a long upload finishes
+    notifications: Arc<NotificationService>,
+    // Wakes `/tasks/{id}/events` streams when an upload finishes
+    task_events: Arc<TaskEvents>,
+    // Replicates added content across an IPFS Cluster; `None` in single-node mode
+    cluster: Option<IpfsCluster>,
+    // Fetches content from gateways during re-imports
+    http_client: HttpClient,
+}
+
+/// Per-CID progress emitted while `reimport_all` runs
+#[derive(Debug, Clone)]
+pub struct ReimportProgress {
+    pub processed: usize,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    // Most recently processed CID
+    pub cid: String,
+}
+
+/// CID that could not be re-imported
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ReimportFailure {
+    pub cid: String,
+    pub error: String,
+}
+
+/// Outcome of re-importing all referenced content onto the current node
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct ReimportReport {
+    pub total: usize,
+    pub succeeded: usize,
+    // Already pinned on the current node, counted in `succeeded`
+    pub already_present: usize,
+    pub failed: usize,
+    pub failures: Vec<ReimportFailure>,
+}
+
+/// CID referenced by the database whose content the node couldn't retrieve
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UnreachableCid {
+    pub cid: String,
+    // Referencing column, e.g. "did_documents.cid"
+    pub source: String,
+    pub error: String,
+}
+
+/// Database/IPFS inconsistencies found by `reconcile`
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct ReconcileReport {
+    // Distinct CIDs referenced by `did_documents` and `research_papers`
+    pub checked: usize,
+    pub reachable: usize,
+    pub unreachable: usize,
+    // Up to 50 of the unreachable CIDs
+    pub unreachable_samples: Vec<UnreachableCid>,
+    // Only filled in when orphans are requested
+    pub pinned: Option<usize>,
+    // Pinned CIDs no row references
+    pub orphaned: Option<usize>,
+    // Up to 50 of the orphaned CIDs
+    pub orphaned_samples: Vec<String>,
+}
+
+/// Availability of a CID, from `pin_status`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PinStatus {
+    pub cid: String,
+    // The node could retrieve the content's root block
+    pub available: bool,
+    // Size of the root block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    // Only in cluster mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replication: Option<ReplicationStatus>,
+}
+
+/// Which stored content `enforce_retention` unpins; nothing when unset
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    // Versions of each DID kept pinned, counting the current one
+    pub keep_versions: Option<NonZeroUsize>,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.keep_versions.is_some()
+    }
+}
+
+/// Outcome of one `enforce_retention` run
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct RetentionReport {
+    pub unpinned: usize,
+    // Cumulative size of the unpinned content; the node frees it on its next GC
+    pub bytes_reclaimed: u64,
+    // Candidates left pinned because a live row still references them
+    pub kept_referenced: usize,
+    pub failed: usize,
 }
 
 impl IPFSService {
     /// Initializes a new IPFS service instance
-    pub async fn new(config: &Config) -> Result<Self, ServiceError> {
+    pub async fn new(
+        config: &Config,
+        pool: Pool,
+        http_client: &HttpClient,
+        notifications: Arc<NotificationService>,
+        task_events: Arc<TaskEvents>,
+    ) -> Result<Self, ServiceError> {
         let client = IpfsClient::from_str(&config.ipfs_node)
             .map_err(|e| ServiceError::Internal(format!("Failed to connect to IPFS: {}", e)))?;
         let version = client
@@ -66,16 +210,18 @@ impl IPFSService {
             config.ipfs_node, version.version
         );
 
-        let opts = Opts::from_url(&config.database_url)
-            .map_err(|e| ServiceError::Internal(format!("Invalid database URL: {}", e)))?;
-        let pool = Pool::new(opts);
-
         // Initialize database schema
 
         init_schema(&pool)
             .await
             .map_err(|e| ServiceError::Internal(format!("Failed to initialize schema: {}", e)))?;
 
+        // Uploads don't survive a restart; fail the ones left behind by a previous run
+        let stale = fail_stale_tasks(&pool, config.stale_task_timeout).await?;
+        if stale > 0 {
+            warn!("Marked {} interrupted upload tasks as failed", stale);
+        }
+
         let public_key = config
             .get_public_key()
             .map_err(|e| ServiceError::Internal(format!("Failed to get public key: {}", e)))?;
@@ -96,15 +242,41 @@ impl IPFSService {
             );
         }
 
+        let ed25519_key = config
+            .get_ed25519_signing_key()
+            .map_err(|e| ServiceError::Internal(format!("Failed to get Ed25519 key: {}", e)))?;
+        let token_signer = TokenSigner::new(
+            config.auth_token_algorithm,
+            config.auth_token_allowed_algorithms.clone(),
+            SigningContext::auth(&config.token_signing_namespace),
+            Some(DilithiumKeyring::single((public_key, signing_key))),
+            ed25519_key,
+        )?;
+        info!(
+            "Signing auth tokens with {} under context {}",
+            token_signer.algorithm().as_str(),
+            token_signer.context().as_str()
+        );
+
         let service = Self {
             client,
             db_pool: pool,
             url: config.ipfs_node.clone(),
-            signing_key,
-            public_key,
+            token_signer,
             tasks: Arc::new(DashMap::new()),
             operation_semaphore: Arc::new(Semaphore::new(config.max_concurrent_uploads)),
             rate_limiters: Arc::new(DashMap::new()),
+            known_cids: Arc::new(DashSet::new()),
+            gateways: config.ipfs_gateways.clone(),
+            compress_documents: config.compress_ipfs_documents,
+            add_timeout: config.ipfs_add_timeout,
+            get_timeout: config.ipfs_get_timeout,
+            mfs_root: config.ipfs_mfs_root.clone(),
+            add_options: config.ipfs_add_options.clone(),
+            notifications,
+            task_events,
+            cluster: IpfsCluster::from_config(config, http_client),
+            http_client: http_client.with_timeout(REIMPORT_FETCH_TIMEOUT),
         };
 
         // Spawn a background task to clean up expired tasks every 5 minutes
@@ -130,39 +302,9 @@ impl IPFSService {
         Ok(service)
     }
 
-    /// Generates a PQC authentication token for a given user ID
+    /// Generates an authentication token for a given user ID
     fn generate_token(&self, user_id: i32, duration: Duration) -> Result<String, ServiceError> {
-        let header = TokenHeader {
-            alg: "Dilithium5".to_string(),
-            typ: "PQC".to_string(),
-            nonce: Uuid::new_v4().to_string(),
-        };
-
-        let claims = Claims {
-            sub: user_id.to_string(),
-            exp: (Utc::now() + duration).timestamp() as usize,
-            signature: Vec::new(),
-            iat: Utc::now().timestamp() as usize,
-            nonce: Uuid::new_v4().to_string(),
-        };
-
-        let header_json = serde_json::to_string(&header)
-            .map_err(|e| ServiceError::Internal(format!("Failed to serialize header: {}", e)))?;
-        let payload_json = serde_json::to_string(&claims)
-            .map_err(|e| ServiceError::Internal(format!("Failed to serialize claims: {}", e)))?;
-        let header_encoded = Base64Engine.encode(header_json);
-        let payload_encoded = Base64Engine.encode(payload_json);
-
-        let message = format!("{}.{}", header_encoded, payload_encoded);
-        let signature = dilithium5::detached_sign(message.as_bytes(), &self.signing_key);
-
-        let signature_hash = Sha256::digest(signature.as_bytes());
-        let signature_encoded = Base64Engine.encode(&signature_hash);
-
-        Ok(format!(
-            "{}.{}.{}",
-            header_encoded, payload_encoded, signature_encoded
-        ))
+        self.token_signer.sign(user_id, duration)
     }
 
     /// Registers a new user and returns a PQC Auth token
@@ -181,63 +323,29 @@ impl IPFSService {
         Ok(token)
     }
 
-    /// Verifies a PQC authentication token
-    pub fn verify_token(&self, token: &str) -> Result<Claims, ServiceError> {
-        let parts: Vec<&str> = token.split('.').collect();
-        if parts.len() != 3 {
-            return Err(ServiceError::Auth("Invalid token format".to_string()));
-        }
-
-        let header_encoded = parts[0];
-        let payload_encoded = parts[1];
-        let signature_encoded = parts[2];
-
-        let header_json = Base64Engine
-            .decode(header_encoded)
-            .map_err(|e| ServiceError::Auth(format!("Failed to decode header: {}", e)))?;
-        let header: TokenHeader = serde_json::from_slice(&header_json)
-            .map_err(|e| ServiceError::Auth(format!("Failed to parse header: {}", e)))?;
-        if header.alg != "Dilithium5" || header.typ != "PQC" {
-            return Err(ServiceError::Auth(
-                "Unsupported algorithm or type".to_string(),
-            ));
-        }
-
-        let payload_json = Base64Engine
-            .decode(payload_encoded)
-            .map_err(|e| ServiceError::Auth(format!("Failed to decode payload: {}", e)))?;
-        let mut claims: Claims = serde_json::from_slice(&payload_json)
-            .map_err(|e| ServiceError::Auth(format!("Failed to parse claims: {}", e)))?;
-
-        let provided_signature_hash = Base64Engine
-            .decode(signature_encoded)
-            .map_err(|e| ServiceError::Auth(format!("Failed to decode signature: {}", e)))?;
-        if provided_signature_hash.len() != 32 {
-            return Err(ServiceError::Auth("Invalid signature length".to_string()));
-        }
-
-        let message = format!("{}.{}", header_encoded, payload_encoded);
-        let signature = dilithium5::detached_sign(message.as_bytes(), &self.signing_key);
-
-        if dilithium5::verify_detached_signature(&signature, message.as_bytes(), &self.public_key)
-            .is_err()
-        {
-            return Err(ServiceError::Auth(
-                "Signature verification failed".to_string(),
-            ));
-        }
-
-        let computed_signature_hash = Sha256::digest(signature.as_bytes());
-        if provided_signature_hash != computed_signature_hash.as_slice() {
-            return Err(ServiceError::Auth("Invalid signature hash".to_string()));
-        }
+    /// Signs in a researcher authenticated by ORCID, creating their account on first login,
+    /// and returns a PQC Auth token
+    pub async fn signin_orcid(&self, profile: &OrcidProfile) -> Result<String, ServiceError> {
+        let user_id = upsert_orcid_user(
+            &self.db_pool,
+            &profile.orcid,
+            profile.email.as_deref(),
+            profile.name.as_deref(),
+        )
+        .await?;
+        let token = self.generate_token(user_id, Duration::hours(12))?;
+        info!("User signed in with ORCID: {}", user_id);
+        Ok(token)
+    }
 
-        if claims.exp < Utc::now().timestamp() as usize {
-            return Err(ServiceError::Auth("Token expired".to_string()));
-        }
+    /// Sign new auth tokens with the keyring's current key, and accept only its versions
+    pub fn install_signing_keys(&self, keyring: DilithiumKeyring) {
+        self.token_signer.install_dilithium_keys(keyring);
+    }
 
-        claims.signature = Vec::new();
-        Ok(claims)
+    /// Verifies an authentication token against the allowed algorithms
+    pub fn verify_token(&self, token: &str) -> Result<Claims, ServiceError> {
+        self.token_signer.verify(token)
     }
 
     /// Performs file upload synchronously and stores its metadata
@@ -256,7 +364,8 @@ impl IPFSService {
                 ServiceError::Internal(format!("Failed to acquire semaphore: {}", e))
             })?;
 
-        let (cid, total_size) = upload_to_ipfs(&self.client, file_stream).await?;
+        let (cid, total_size, content_type) =
+            upload_to_ipfs(&self.client, file_stream, &self.add_options).await?;
 
         if total_size == 0 {
             cleanup_failed_upload(&self.client, &self.db_pool, &cid).await?;
@@ -264,6 +373,7 @@ impl IPFSService {
                 "Empty file uploaded".to_string(),
             ));
         }
+        replicate(self.cluster.as_ref(), &cid).await;
 
         let metadata = FileMetadata {
             cid: cid.clone(),
@@ -271,9 +381,19 @@ impl IPFSService {
             size: total_size,
             timestamp: Utc::now(),
             user_id,
+            content_type: content_type.map(str::to_string),
         };
 
-        insert_file_metadata(&self.db_pool, &cid, &file_name, total_size, user_id, None).await?;
+        insert_file_metadata(
+            &self.db_pool,
+            &cid,
+            &file_name,
+            total_size,
+            user_id,
+            None,
+            content_type,
+        )
+        .await?;
 
         info!(
             "File uploaded successfully: cid={}, size={}, user_id={}",
@@ -297,7 +417,63 @@ impl IPFSService {
         let task_id = Uuid::new_v4().to_string();
         let started_at = Utc::now();
 
-        // Initial status
+        // Persist the task before tracking it in memory
+        insert_initial_task(
+            &self.db_pool,
+            &task_id,
+            user_id,
+            &file_name,
+            // Initial status ("pending")
+            "pending",
+            started_at,
+        )
+        .await?;
+
+        Ok(self.start_upload_task(task_id, file_stream, file_name, user_id, started_at))
+    }
+
+    /// Re-run a failed asynchronous upload under its original task ID.
+    ///
+    /// The caller supplies the file again; the file name recorded for the task is reused
+    /// unless a new one is given.
+    pub async fn retry_upload<S>(
+        &self,
+        task_id: &str,
+        file_stream: S,
+        file_name: Option<String>,
+        user_id: i32,
+    ) -> Result<UploadStatus, ServiceError>
+    where
+        S: Stream<Item = Result<Vec<u8>, ServiceError>> + Send + Sync + Unpin + 'static,
+    {
+        let started_at = Utc::now();
+        let stored_name = reset_failed_task(&self.db_pool, task_id, user_id, started_at).await?;
+        let file_name = file_name
+            .or(stored_name)
+            .unwrap_or_else(|| "unnamed_file".to_string());
+
+        info!("Retrying upload task {} for user {}", task_id, user_id);
+        Ok(self.start_upload_task(
+            task_id.to_string(),
+            file_stream,
+            file_name,
+            user_id,
+            started_at,
+        ))
+    }
+
+    /// Cache a pending task whose row is already stored and process it in the background
+    fn start_upload_task<S>(
+        &self,
+        task_id: String,
+        file_stream: S,
+        file_name: String,
+        user_id: i32,
+        started_at: chrono::DateTime<Utc>,
+    ) -> UploadStatus
+    where
+        S: Stream<Item = Result<Vec<u8>, ServiceError>> + Send + Sync + Unpin + 'static,
+    {
         let status = UploadStatus {
             task_id: task_id.clone(),
             status: "pending".to_string(),
@@ -310,7 +486,7 @@ impl IPFSService {
         // Create oneshot channel for result communication
         let (tx, _rx) = oneshot::channel();
 
-        // Store task info
+        // Store task info, replacing the final entry of a retried task
         self.tasks.insert(
             task_id.clone(),
             TaskInfo {
@@ -318,98 +494,89 @@ impl IPFSService {
                 tx: Some(tx),
             },
         );
-        // Store initial task in database
-        insert_initial_task(
-            &self.db_pool,
-            &task_id,
-            user_id,
-            // Initial status ("pending")
-            &status.status,
-            status.started_at,
-        )
-        .await?;
 
         // Clone necessary data for async task
         let client = self.client.clone();
+        let add_options = self.add_options.clone();
+        let cluster = self.cluster.clone();
         let db_pool = self.db_pool.clone();
         let tasks = self.tasks.clone();
         let semaphore = self.operation_semaphore.clone();
-        let task_id_clone = task_id.clone();
-        let file_name_clone = file_name.clone();
+        let notifications = self.notifications.clone();
+        let task_events = self.task_events.clone();
 
         tokio::task::spawn_local(async move {
             // Acquire semaphore permit within the async task
-            match semaphore.acquire().await {
+            let result = match semaphore.acquire().await {
                 Ok(_permit) => {
-                    let result = Self::process_upload(
+                    // A panic must still leave the task failed rather than pending
+                    AssertUnwindSafe(Self::process_upload(
                         client,
+                        add_options,
+                        cluster,
                         db_pool.clone(),
                         file_stream,
-                        file_name_clone,
+                        file_name,
                         user_id,
-                        task_id_clone.clone(),
+                        task_id.clone(),
                         tasks.clone(),
-                    )
-                    .await;
-
-                    match result {
-                        Ok(metadata) => {
-                            update_task_status(
-                                tasks,
-                                &db_pool,
-                                &task_id_clone,
-                                "completed",
-                                Some(&metadata.cid),
-                                None,
-                                Some(100.0),
-                            )
-                            .await
-                            .unwrap_or_else(|e| {
-                                error!("Failed to update task status: {}", e);
-                            });
-                        }
-                        Err(e) => {
-                            update_task_status(
-                                tasks,
-                                &db_pool,
-                                &task_id_clone,
-                                "failed",
-                                None,
-                                Some(&e.to_string()),
-                                None,
-                            )
-                            .await
-                            .unwrap_or_else(|e| {
-                                error!("Failed to update task status: {}", e);
-                            });
-                        }
-                    }
-                }
-                Err(e) => {
-                    // Handle semaphore acquisition failure
-                    update_task_status(
-                        tasks,
-                        &db_pool,
-                        &task_id_clone,
-                        "failed",
-                        None,
-                        Some(&format!("Failed to acquire semaphore: {}", e)),
-                        None,
-                    )
+                    ))
+                    .catch_unwind()
                     .await
-                    .unwrap_or_else(|e| {
-                        error!("Failed to update task status: {}", e);
-                    });
+                    .unwrap_or_else(|_| {
+                        Err(ServiceError::Internal("Upload task panicked".to_string()))
+                    })
+                    .map_err(|e| e.to_string())
                 }
+                Err(e) => Err(format!("Failed to acquire semaphore: {}", e)),
+            };
+
+            let (status, cid, error) = match &result {
+                Ok(metadata) => ("completed", Some(metadata.cid.as_str()), None),
+                Err(e) => ("failed", None, Some(e.as_str())),
+            };
+            update_task_status(
+                tasks,
+                &db_pool,
+                &task_id,
+                status,
+                cid,
+                error,
+                cid.map(|_| 100.0),
+            )
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to update task status: {}", e);
+            });
+            task_events.publish(&task_id);
+
+            // Quick uploads are still being watched by the client; only long ones notify
+            if Utc::now() - started_at >= Duration::seconds(MIN_NOTIFIED_UPLOAD_SECS) {
+                notifications
+                    .task_finished(
+                        i64::from(user_id),
+                        TaskNotice {
+                            task_id: &task_id,
+                            kind: "Upload",
+                            status,
+                            did: None,
+                            cid,
+                            error,
+                        },
+                    )
+                    .await;
             }
         });
 
-        Ok(status)
+        status
     }
 
     /// Processes an asynchronous file upload
+    #[allow(clippy::too_many_arguments)]
     async fn process_upload<S>(
         client: IpfsClient,
+        add_options: IpfsAddOptions,
+        cluster: Option<IpfsCluster>,
         db_pool: Pool,
         file_stream: S,
         file_name: String,
@@ -420,7 +587,9 @@ impl IPFSService {
     where
         S: Stream<Item = Result<Vec<u8>, ServiceError>> + Send + Sync + Unpin + 'static,
     {
-        let (cid, total_size) = upload_to_ipfs(&client, file_stream).await?;
+        let (cid, total_size, content_type) =
+            upload_to_ipfs(&client, file_stream, &add_options).await?;
+        replicate(cluster.as_ref(), &cid).await;
 
         let metadata = FileMetadata {
             cid: cid.clone(),
@@ -428,6 +597,7 @@ impl IPFSService {
             size: total_size,
             timestamp: Utc::now(),
             user_id,
+            content_type: content_type.map(str::to_string),
         };
 
         // Insert metadata into the database with task_id
@@ -438,6 +608,7 @@ impl IPFSService {
             total_size,
             user_id,
             Some(&task_id),
+            content_type,
         )
         .await?;
 
@@ -588,36 +759,17 @@ impl IPFSService {
             })?;
         let result: Option<Row> = conn
             .exec_first(
-                "SELECT cid, name, size, timestamp, user_id FROM file_metadata WHERE cid = :cid",
+                "SELECT cid, name, size, timestamp, user_id, content_type FROM file_metadata WHERE cid = :cid",
                 params! { "cid" => cid },
             )
             .await
             .map_err(|e| ServiceError::Internal(format!("Failed to get file metadata: {}", e)))?;
 
         Ok(result.map(|row| {
-            let timestamp_value: Value = row.get(3).unwrap();
-            let timestamp = match timestamp_value {
-                Value::Date(year, month, day, hour, minute, second, micro) => NaiveDateTime::new(
-                    chrono::NaiveDate::from_ymd_opt(year.into(), month.into(), day.into()).unwrap(),
-                    chrono::NaiveTime::from_hms_micro_opt(
-                        hour.into(),
-                        minute.into(),
-                        second.into(),
-                        micro,
-                    )
-                    .unwrap(),
-                ),
-                _ => {
-                    // Fallback to parsing from string if needed
-                    let timestamp_str: String = row.get::<String, _>(3).unwrap();
-                    NaiveDateTime::parse_from_str(&timestamp_str, "%Y-%m-%d %H:%M:%S")
-                        .unwrap_or_else(|e| {
-                            log::warn!("Failed to parse timestamp: {}, defaulting to now", e);
-                            Utc::now().naive_utc()
-                        })
-                }
-            };
-            let timestamp_utc = Utc.from_utc_datetime(&timestamp);
+            let timestamp_utc = row.as_ref(3).and_then(from_db_value).unwrap_or_else(|| {
+                log::warn!("Failed to parse timestamp of {}, defaulting to now", cid);
+                Utc::now()
+            });
 
             FileMetadata {
                 cid: row.get(0).unwrap(),
@@ -625,50 +777,739 @@ impl IPFSService {
                 size: row.get(2).unwrap(),
                 timestamp: timestamp_utc,
                 user_id: row.get(4).unwrap(),
+                content_type: row.get(5).flatten(),
             }
         }))
     }
 
+    /// Check that `requester` may read a file's content.
+    ///
+    /// The uploader always may. Files attached to, or the paper of, a DID under embargo are
+    /// closed to everyone but the DID's owner until the embargo ends. Otherwise files
+    /// without ACL entries are open to every signed-in user; files with entries require the
+    /// requester to be listed by user ID or own one of the listed DIDs.
+    pub async fn check_access(&self, cid: &str, requester: &AuthUser) -> Result<(), AppError> {
+        let metadata = self
+            .get_file_metadata(cid)
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?
+            .ok_or_else(|| AppError::NotFound(format!("File {} not found", cid)))?;
+        if i64::from(metadata.user_id) == requester.id {
+            return Ok(());
+        }
+
+        let mut conn = self.acl_conn().await?;
+        let embargo_until: Option<Option<String>> =
+            r"SELECT DATE_FORMAT(MAX(embargo_until), '%Y-%m-%d %H:%i:%s UTC')
+                FROM did_documents
+                WHERE embargo_until > :now AND user_id != :user_id
+                    AND (did IN (SELECT did FROM did_attachments WHERE cid = :cid)
+                        OR did IN (SELECT did FROM research_papers WHERE cid = :cid))"
+                .with(params! {
+                    "now" => to_db(Utc::now()),
+                    "user_id" => requester.id,
+                    "cid" => cid,
+                })
+                .first(&mut conn)
+                .await
+                .map_err(acl_error)?;
+        if let Some(until) = embargo_until.flatten() {
+            return Err(AppError::AuthorizationError(format!(
+                "File is under embargo until {}",
+                until
+            )));
+        }
+
+        let (entries, granted): (i64, i64) = r"SELECT COUNT(*),
+                    CAST(COALESCE(SUM(user_id = :user_id
+                        OR did IN (SELECT did FROM did_documents WHERE user_id = :user_id)), 0)
+                        AS SIGNED)
+                FROM file_acl WHERE cid = :cid"
+            .with(params! { "cid" => cid, "user_id" => requester.id })
+            .first(&mut conn)
+            .await
+            .map_err(acl_error)?
+            .unwrap_or((0, 0));
+
+        if entries == 0 || granted > 0 {
+            Ok(())
+        } else {
+            Err(AppError::AuthorizationError(
+                "Not authorized to access this file".to_string(),
+            ))
+        }
+    }
+
+    /// Current ACL of a file; only its uploader may view it
+    pub async fn get_file_acl(&self, cid: &str, owner_id: i64) -> Result<FileAcl, AppError> {
+        self.require_file_owner(cid, owner_id).await?;
+
+        let mut conn = self.acl_conn().await?;
+        let rows: Vec<(Option<String>, Option<i64>)> =
+            "SELECT did, user_id FROM file_acl WHERE cid = :cid ORDER BY id"
+                .with(params! { "cid" => cid })
+                .fetch(&mut conn)
+                .await
+                .map_err(acl_error)?;
+
+        let mut acl = FileAcl::default();
+        for (did, user_id) in rows {
+            acl.dids.extend(did);
+            acl.user_ids.extend(user_id);
+        }
+        Ok(acl)
+    }
+
+    /// Replace a file's ACL; an empty ACL makes the file public again
+    pub async fn set_file_acl(
+        &self,
+        cid: &str,
+        owner_id: i64,
+        acl: &FileAcl,
+    ) -> Result<(), AppError> {
+        self.require_file_owner(cid, owner_id).await?;
+
+        let mut conn = self.acl_conn().await?;
+        let mut tx = conn
+            .start_transaction(mysql_async::TxOpts::default())
+            .await
+            .map_err(acl_error)?;
+
+        "DELETE FROM file_acl WHERE cid = :cid"
+            .with(params! { "cid" => cid })
+            .run(&mut tx)
+            .await
+            .map_err(acl_error)?;
+
+        let now = to_db(Utc::now());
+        let entries = acl
+            .dids
+            .iter()
+            .map(|did| (Some(did.as_str()), None))
+            .chain(acl.user_ids.iter().map(|&user_id| (None, Some(user_id))));
+        "INSERT INTO file_acl (cid, did, user_id, created_at)
+            VALUES (:cid, :did, :user_id, :created_at)"
+            .with(entries.map(|(did, user_id)| {
+                params! {
+                    "cid" => cid,
+                    "did" => did,
+                    "user_id" => user_id,
+                    "created_at" => &now,
+                }
+            }))
+            .batch(&mut tx)
+            .await
+            .map_err(acl_error)?;
+
+        tx.commit().await.map_err(acl_error)?;
+
+        if acl.is_empty() {
+            info!("ACL for file {} cleared, file is public", cid);
+        } else {
+            info!(
+                "ACL for file {} set to {} DIDs and {} users",
+                cid,
+                acl.dids.len(),
+                acl.user_ids.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Raw file content, bounded by `IPFS_GET_TIMEOUT_SECS`. Callers check access first.
+    pub async fn get_file_content(&self, cid: &str) -> Result<Vec<u8>, AppError> {
+        with_ipfs_timeout(self.get_timeout, collect_stream_bytes(self.client.cat(cid))).await
+    }
+
+    /// Stream a file's content, or the `(offset, length)` range of it, as it arrives from
+    /// IPFS instead of buffering it. Callers check access first.
+    ///
+    /// The stream ends with an "IPFS timeout" error if no chunk arrives for
+    /// `IPFS_GET_TIMEOUT_SECS`.
+    pub fn stream_file_content(
+        &self,
+        cid: &str,
+        range: Option<(u64, u64)>,
+    ) -> impl Stream<Item = Result<actix_web::web::Bytes, AppError>> + Unpin + 'static {
+        let stream = match range {
+            Some((offset, length)) => self.client.cat_range(cid, offset as usize, length as usize),
+            None => self.client.cat(cid),
+        };
+        stream_with_idle_timeout(stream, cid.to_string(), self.get_timeout)
+    }
+
+    async fn require_file_owner(&self, cid: &str, owner_id: i64) -> Result<(), AppError> {
+        let metadata = self
+            .get_file_metadata(cid)
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?
+            .ok_or_else(|| AppError::NotFound(format!("File {} not found", cid)))?;
+
+        if i64::from(metadata.user_id) != owner_id {
+            return Err(AppError::AuthorizationError(
+                "Only the file's uploader can manage its access list".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn acl_conn(&self) -> Result<mysql_async::Conn, AppError> {
+        self.db_pool.get_conn().await.map_err(acl_error)
+    }
+
+    /// MIME type of stored content: the type recorded at upload, or sniffed from the first
+    /// bytes on IPFS for files uploaded before detection existed
+    pub async fn detect_content_type(&self, cid: &str) -> Result<Option<String>, ServiceError> {
+        if let Some(content_type) = self
+            .get_file_metadata(cid)
+            .await?
+            .and_then(|metadata| metadata.content_type)
+        {
+            return Ok(Some(content_type));
+        }
+
+        let mut stream = self.client.cat(cid);
+        let mut head = Vec::with_capacity(MIME_SNIFF_BYTES);
+        while head.len() < MIME_SNIFF_BYTES {
+            match stream.next().await {
+                Some(chunk) => head.extend(
+                    chunk.map_err(|e| ServiceError::Internal(format!("Fetch failed: {}", e)))?,
+                ),
+                None => break,
+            }
+        }
+        head.truncate(MIME_SNIFF_BYTES);
+
+        Ok(detect_mime(&head).map(str::to_string))
+    }
+
     /// Cleanup rate limiters for the service instance
     pub async fn cleanup_rate_limiters(&self) {
         cleanup_rate_limiters(self.rate_limiters.clone()).await;
     }
 
-    /// Add a string content to IPFS and return its CID
+    /// Add a string content to IPFS and return its CID.
+    ///
+    /// With `IPFS_COMPRESS_DOCUMENTS` enabled, larger documents are stored gzip-compressed;
+    /// `get_content` decompresses them transparently.
     pub async fn add_content(&self, content: &str) -> Result<String, AppError> {
-        info!("Adding string content to IPFS: {} bytes", content.len());
+        let stored = encode_document(content, self.compress_documents)?;
+        info!("Adding content to IPFS: {} bytes", stored.len());
 
-        // Copy the content to a Vec<u8> to avoid lifetime issues
-        let content_bytes = content.as_bytes().to_vec();
+        let hash = add_document(&self.client, stored, &self.add_options, self.add_timeout).await?;
+        info!("Content stored on IPFS with hash: {}", hash);
+        replicate(self.cluster.as_ref(), &hash).await;
 
-        // Create a cursor to implement Read trait for the Vec<u8>
-        let cursor = std::io::Cursor::new(content_bytes);
+        Ok(hash)
+    }
 
-        let response = self.client.add(cursor).await.map_err(|e| {
-            error!("IPFS add error: {}", e);
-            AppError::IPFSError(e)
+    /// Copy a stored DID document to `{root}/dids/{did}.json` in MFS so operators can browse
+    /// DIDs by name, replacing any previous version.
+    ///
+    /// Does nothing unless `IPFS_MFS_ROOT` is set. The MFS entry is only a convenience view:
+    /// failures are logged and never fail the DID operation, and callers keep storing the
+    /// content CID.
+    pub async fn mirror_did_to_mfs(&self, did: &str, cid: &str) {
+        let Some(root) = &self.mfs_root else {
+            return;
+        };
+        let dir = format!("{}/dids", root);
+        let path = format!("{}/{}.json", dir, did);
+
+        let result = with_ipfs_timeout(self.add_timeout, async {
+            self.client
+                .files_mkdir(&dir, true)
+                .await
+                .map_err(AppError::IPFSError)?;
+            // `files/cp` refuses to overwrite, so drop the previous version; a missing
+            // entry is expected for new DIDs
+            let _ = self.client.files_rm(&path, false).await;
+            self.client
+                .files_cp(&format!("/ipfs/{}", cid), &path)
+                .await
+                .map_err(AppError::IPFSError)
+        })
+        .await;
+
+        match result {
+            Ok(()) => info!("Mirrored DID {} to MFS path {}", did, path),
+            Err(e) => warn!("Failed to mirror DID {} to MFS path {}: {:?}", did, path, e),
+        }
+    }
+
+    /// Add string content to IPFS unless identical content is already stored.
+    ///
+    /// The CID is computed locally first and checked against CIDs this service has seen and
+    /// those referenced in the database. Returns the CID and whether the add was skipped.
+    pub async fn add_content_dedup(&self, content: &str) -> Result<(String, bool), AppError> {
+        // Hash exactly the bytes `add_content` would store, compressed or not
+        let local_cid = compute_cid(
+            &encode_document(content, self.compress_documents)?,
+            &self.add_options,
+        );
+
+        if let Some(cid) = &local_cid {
+            if self.known_cids.contains(cid) || self.is_cid_referenced(cid).await? {
+                info!("Content already stored on IPFS with hash: {}", cid);
+                self.remember_cid(cid);
+                return Ok((cid.clone(), true));
+            }
+        }
+
+        let cid = self.add_content(content).await?;
+        if let Some(local_cid) = local_cid {
+            if local_cid != cid {
+                warn!(
+                    "Locally computed CID {} differs from IPFS CID {}; node may not support the configured add options",
+                    local_cid, cid
+                );
+            }
+        }
+        self.remember_cid(&cid);
+
+        Ok((cid, false))
+    }
+
+    /// Whether any stored record already points at `cid` and its content is still pinned.
+    /// A CID the retention policy unpinned may still be named by a record that raced the
+    /// unpinning, but its content may be gone, so it's added again.
+    async fn is_cid_referenced(&self, cid: &str) -> Result<bool, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
         })?;
 
-        let hash = response.hash;
-        info!("Content stored on IPFS with hash: {}", hash);
+        let found: Option<i32> = r"SELECT 1 FROM (
+                SELECT cid FROM did_documents WHERE cid = :cid
+                UNION ALL SELECT cid FROM file_metadata WHERE cid = :cid
+                UNION ALL SELECT cid FROM research_papers WHERE cid = :cid
+              ) referenced
+              WHERE NOT EXISTS (
+                SELECT 1 FROM did_versions WHERE cid = :cid AND unpinned_at IS NOT NULL
+              )
+              LIMIT 1"
+            .with(params! { "cid" => cid })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when checking for existing CID: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
 
-        Ok(hash)
+        Ok(found.is_some())
     }
 
-    /// Retrieve content from IPFS by its CID
-    pub async fn get_content(&self, cid: &str) -> Result<String, AppError> {
-        info!("Getting content from IPFS for CID: {}", cid);
+    fn remember_cid(&self, cid: &str) {
+        if self.known_cids.len() >= KNOWN_CID_CACHE_LIMIT {
+            self.known_cids.clear();
+        }
+        self.known_cids.insert(cid.to_string());
+    }
+
+    /// Re-add and pin every CID referenced by `did_documents` and `research_papers` on the
+    /// current node, for use after migrating to a new IPFS node.
+    ///
+    /// Content already pinned is left alone. Anything else is fetched through the configured
+    /// gateways, re-added and checked to hash to the same CID; as a last resort the node is
+    /// asked to pin it from the network. Progress for each CID is sent on `progress`.
+    pub async fn reimport_all(
+        &self,
+        progress: tokio::sync::mpsc::UnboundedSender<ReimportProgress>,
+    ) -> Result<ReimportReport, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let cids: Vec<String> = r"SELECT cid FROM did_documents
+              UNION SELECT cid FROM research_papers
+              UNION SELECT knowledge_graph_cid FROM research_papers WHERE knowledge_graph_cid IS NOT NULL"
+            .with(())
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing CIDs for reimport: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        drop(conn);
+
+        info!("Re-importing {} CIDs onto {}", cids.len(), self.url);
+
+        let mut report = ReimportReport {
+            total: cids.len(),
+            ..Default::default()
+        };
+
+        for (index, cid) in cids.into_iter().enumerate() {
+            let result = self.reimport_cid(&cid).await;
+            match &result {
+                Ok(source) => {
+                    report.succeeded += 1;
+                    if source == "already pinned" {
+                        report.already_present += 1;
+                    }
+                    self.remember_cid(&cid);
+                }
+                Err(e) => {
+                    warn!("Failed to re-import {}: {}", cid, e);
+                    report.failed += 1;
+                    report.failures.push(ReimportFailure {
+                        cid: cid.clone(),
+                        error: e.clone(),
+                    });
+                }
+            }
+
+            // The receiver going away only means nobody is watching
+            let _ = progress.send(ReimportProgress {
+                processed: index + 1,
+                total: report.total,
+                succeeded: report.succeeded,
+                failed: report.failed,
+                cid,
+            });
+        }
+
+        info!(
+            "Re-import finished: {} succeeded ({} already present), {} failed",
+            report.succeeded, report.already_present, report.failed
+        );
+
+        Ok(report)
+    }
+
+    /// Public gateway URL of `cid`, using the first configured gateway
+    pub fn gateway_url(&self, cid: &str) -> String {
+        let gateway = self
+            .gateways
+            .first()
+            .map(String::as_str)
+            .unwrap_or("https://ipfs.io");
+        format!("{}/ipfs/{}", gateway, cid)
+    }
+
+    /// Size in bytes of the block behind `cid`, fetching it from the network if the node
+    /// doesn't have it. Fails with "IPFS timeout" after `IPFS_GET_TIMEOUT_SECS`.
+    pub async fn stat(&self, cid: &str) -> Result<u64, AppError> {
+        with_ipfs_timeout(self.get_timeout, async {
+            let response = self.client.block_stat(cid).await.map_err(|e| {
+                warn!("IPFS stat failed for {}: {}", cid, e);
+                AppError::IPFSError(e)
+            })?;
+            Ok(response.size)
+        })
+        .await
+    }
+
+    /// Whether the node can retrieve `cid`, and in cluster mode how many peers pin it. A
+    /// failed cluster request is reported as an error; unavailable content is not.
+    pub async fn pin_status(&self, cid: &str) -> Result<PinStatus, AppError> {
+        let size = self.stat(cid).await.ok();
+        let replication = self.replication_status(cid).await?;
+        Ok(PinStatus {
+            cid: cid.to_string(),
+            available: size.is_some(),
+            size,
+            replication,
+        })
+    }
+
+    /// Per-peer pin status of `cid`; `None` in single-node mode
+    pub async fn replication_status(
+        &self,
+        cid: &str,
+    ) -> Result<Option<ReplicationStatus>, AppError> {
+        match &self.cluster {
+            Some(cluster) => Ok(Some(cluster.status(cid).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check that every CID referenced by `did_documents` and `research_papers` can still be
+    /// retrieved, and with `include_orphans` also list recursively pinned CIDs that no row
+    /// (including uploaded files in `file_metadata`) references.
+    ///
+    /// Only reports; nothing is re-pinned or unpinned. Run `reimport_all` to repair
+    /// unreachable content.
+    pub async fn reconcile(&self, include_orphans: bool) -> Result<ReconcileReport, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let rows: Vec<(String, String)> = r"SELECT cid, 'did_documents.cid' FROM did_documents
+              UNION ALL SELECT cid, 'research_papers.cid' FROM research_papers
+              UNION ALL SELECT knowledge_graph_cid, 'research_papers.knowledge_graph_cid'
+                FROM research_papers WHERE knowledge_graph_cid IS NOT NULL"
+            .with(())
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing CIDs for reconciliation: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let file_cids: Vec<String> = if include_orphans {
+            "SELECT cid FROM file_metadata"
+                .with(())
+                .fetch(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when listing file CIDs: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?
+        } else {
+            Vec::new()
+        };
+        drop(conn);
+
+        // A CID referenced from several places is checked once, under its first source
+        let mut referenced = std::collections::HashSet::new();
+        let references: Vec<(String, String)> = rows
+            .into_iter()
+            .filter(|(cid, _)| referenced.insert(cid.clone()))
+            .collect();
+
+        info!(
+            "Reconciling {} referenced CIDs on {}",
+            references.len(),
+            self.url
+        );
+
+        let mut report = ReconcileReport {
+            checked: references.len(),
+            ..Default::default()
+        };
+
+        let mut results = futures::stream::iter(references)
+            .map(|(cid, source)| async move {
+                let result = self.stat(&cid).await;
+                (cid, source, result)
+            })
+            .buffer_unordered(RECONCILE_CONCURRENCY);
+        while let Some((cid, source, result)) = results.next().await {
+            match result {
+                Ok(_) => report.reachable += 1,
+                Err(e) => {
+                    report.unreachable += 1;
+                    if report.unreachable_samples.len() < RECONCILE_SAMPLE_LIMIT {
+                        report.unreachable_samples.push(UnreachableCid {
+                            cid,
+                            source,
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if include_orphans {
+            let pins = self
+                .client
+                .pin_ls(None, Some("recursive"))
+                .await
+                .map_err(|e| {
+                    error!("Failed to list IPFS pins: {}", e);
+                    AppError::IPFSError(e)
+                })?;
+            referenced.extend(file_cids);
+
+            report.pinned = Some(pins.keys.len());
+            let mut orphaned: Vec<String> = pins
+                .keys
+                .into_keys()
+                .filter(|cid| !referenced.contains(cid))
+                .collect();
+            orphaned.sort();
+
+            report.orphaned = Some(orphaned.len());
+            orphaned.truncate(RECONCILE_SAMPLE_LIMIT);
+            report.orphaned_samples = orphaned;
+        }
+
+        info!(
+            "Reconciliation finished: {} of {} referenced CIDs unreachable, {} orphaned pins",
+            report.unreachable,
+            report.checked,
+            report.orphaned.unwrap_or(0)
+        );
+
+        Ok(report)
+    }
+
+    /// Make a single CID available on the current node, returning where it came from
+    async fn reimport_cid(&self, cid: &str) -> Result<String, String> {
+        let short = std::time::Duration::from_secs(10);
+
+        if let Ok(Ok(_)) = tokio::time::timeout(short, self.client.pin_ls(Some(cid), None)).await {
+            return Ok("already pinned".to_string());
+        }
+
+        let mut last_error = "no gateways configured".to_string();
+        for gateway in &self.gateways {
+            let url = format!("{}/ipfs/{}", gateway, cid);
+            let bytes = match self.http_client.get(&url).send().await {
+                Ok(response) if response.status().is_success() => match response.bytes().await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        last_error = format!("{}: {}", gateway, e);
+                        continue;
+                    }
+                },
+                Ok(response) => {
+                    last_error = format!("{}: HTTP {}", gateway, response.status());
+                    continue;
+                }
+                Err(e) => {
+                    last_error = format!("{}: {}", gateway, e);
+                    continue;
+                }
+            };
+
+            let added = self
+                .client
+                .add_with_options(
+                    std::io::Cursor::new(bytes.to_vec()),
+                    self.add_options.request(),
+                )
+                .await
+                .map_err(|e| format!("IPFS add failed: {}", e))?;
+            if added.hash != cid {
+                return Err(format!(
+                    "content from {} re-added as {} instead",
+                    gateway, added.hash
+                ));
+            }
 
-        let response_stream = self.client.cat(cid);
+            self.client
+                .pin_add(cid, true)
+                .await
+                .map_err(|e| format!("IPFS pin failed: {}", e))?;
+            return Ok(gateway.clone());
+        }
 
-        // Use stream_to_vec to collect all bytes from the stream
-        let bytes = self.collect_stream_bytes(response_stream).await?;
+        // Gateways failed; the node may still find the content through the DHT
+        match tokio::time::timeout(
+            std::time::Duration::from_secs(120),
+            self.client.pin_add(cid, true),
+        )
+        .await
+        {
+            Ok(Ok(_)) => Ok("ipfs network".to_string()),
+            Ok(Err(e)) => Err(format!("{}; network pin failed: {}", last_error, e)),
+            Err(_) => Err(format!("{}; network pin timed out", last_error)),
+        }
+    }
 
-        let content = String::from_utf8(bytes).map_err(|e| {
-            error!("Failed to convert IPFS bytes to string: {}", e);
-            AppError::DeserializationError
+    /// Unpin content `policy` no longer retains: versions of a DID older than its latest
+    /// `keep_versions`.
+    ///
+    /// A CID still referenced by a DID, attachment, paper or uploaded file stays pinned, and
+    /// is considered again on the next run.
+    pub async fn enforce_retention(
+        &self,
+        policy: &RetentionPolicy,
+    ) -> Result<RetentionReport, AppError> {
+        let db_error = |e: mysql_async::Error| {
+            error!("Database error when enforcing retention: {}", e);
+            AppError::DatabaseError(e.to_string())
+        };
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
         })?;
 
+        let Some(keep) = policy.keep_versions else {
+            return Ok(RetentionReport::default());
+        };
+        let versions: Vec<(String, String, bool)> = r"SELECT did, cid, unpinned_at IS NOT NULL
+            FROM did_versions ORDER BY did, id DESC"
+            .with(())
+            .fetch(&mut conn)
+            .await
+            .map_err(db_error)?;
+        let mut version_cids = superseded_versions(versions, keep.get());
+
+        let mut seen = std::collections::HashSet::new();
+        version_cids.retain(|cid| seen.insert(cid.clone()));
+        if version_cids.is_empty() {
+            return Ok(RetentionReport::default());
+        }
+
+        let live: std::collections::HashSet<String> = r"SELECT cid FROM did_documents
+              UNION SELECT cid FROM did_attachments
+              UNION SELECT cid FROM research_papers
+              UNION SELECT knowledge_graph_cid FROM research_papers
+                WHERE knowledge_graph_cid IS NOT NULL
+              UNION SELECT cid FROM file_metadata"
+            .with(())
+            .fetch(&mut conn)
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .collect();
+
+        let mut report = RetentionReport::default();
+        let now = to_db(Utc::now());
+
+        for cid in version_cids {
+            if live.contains(&cid) {
+                report.kept_referenced += 1;
+                continue;
+            }
+            if self.unpin(&cid, &mut report).await {
+                "UPDATE did_versions SET unpinned_at = :now WHERE cid = :cid"
+                    .with(params! { "now" => &now, "cid" => &cid })
+                    .ignore(&mut conn)
+                    .await
+                    .map_err(db_error)?;
+            }
+        }
+
+        info!(
+            "Retention unpinned {} CIDs ({} bytes), kept {} still referenced, {} failed",
+            report.unpinned, report.bytes_reclaimed, report.kept_referenced, report.failed
+        );
+
+        Ok(report)
+    }
+
+    /// Unpin `cid` for `enforce_retention`, returning whether it is no longer pinned. An
+    /// unpinned CID is forgotten by the dedup cache, so adding its content again pins it.
+    async fn unpin(&self, cid: &str, report: &mut RetentionReport) -> bool {
+        self.known_cids.remove(cid);
+        let size = match self.client.object_stat(cid).await {
+            Ok(stat) => stat.cumulative_size,
+            Err(e) => {
+                warn!("IPFS stat failed for {} before unpinning: {}", cid, e);
+                0
+            }
+        };
+        match self.client.pin_rm(cid, true).await {
+            Ok(_) => {
+                report.unpinned += 1;
+                report.bytes_reclaimed += size;
+                true
+            }
+            // Unpinned by hand or by an earlier run that couldn't record it
+            Err(e) if e.to_string().contains("not pinned") => true,
+            Err(e) => {
+                warn!("Failed to unpin {}: {}", cid, e);
+                report.failed += 1;
+                false
+            }
+        }
+    }
+
+    /// Retrieve content from IPFS by its CID
+    ///
+    /// Fails with an "IPFS timeout" error if the whole document isn't read within
+    /// `IPFS_GET_TIMEOUT_SECS`.
+    pub async fn get_content(&self, cid: &str) -> Result<String, AppError> {
+        info!("Getting content from IPFS for CID: {}", cid);
+
+        let content = get_document(&self.client, cid, self.get_timeout).await?;
+
         info!(
             "Successfully retrieved content from IPFS, size: {} bytes",
             content.len()
@@ -676,26 +1517,377 @@ impl IPFSService {
 
         Ok(content)
     }
+}
 
-    // Helper method to collect bytes from a stream
-    async fn collect_stream_bytes(
-        &self,
-        mut stream: impl futures_util::Stream<Item = Result<actix_web::web::Bytes, ipfs_api::Error>>
-            + Unpin,
-    ) -> Result<Vec<u8>, AppError> {
-        use futures_util::StreamExt;
+/// Ask the cluster, if any, to replicate newly added `cid`. The node already holds a copy,
+/// so a failed request is only logged; `pin_status` shows content left unreplicated.
+async fn replicate(cluster: Option<&IpfsCluster>, cid: &str) {
+    if let Some(cluster) = cluster {
+        if let Err(e) = cluster.pin(cid).await {
+            warn!("Content {} is only stored on the IPFS node: {}", cid, e);
+        }
+    }
+}
 
-        let mut bytes = Vec::new();
+/// CIDs of the versions not yet unpinned beyond the latest `keep` of each DID, given
+/// `(did, cid, unpinned)` rows grouped by DID, newest first
+fn superseded_versions(versions: Vec<(String, String, bool)>, keep: usize) -> Vec<String> {
+    let mut superseded = Vec::new();
+    let mut current: Option<String> = None;
+    let mut seen = 0;
+    for (did, cid, unpinned) in versions {
+        if current.as_deref() != Some(did.as_str()) {
+            current = Some(did);
+            seen = 0;
+        }
+        seen += 1;
+        if seen > keep && !unpinned {
+            superseded.push(cid);
+        }
+    }
+    superseded
+}
+
+fn acl_error(e: mysql_async::Error) -> AppError {
+    error!("File ACL database error: {}", e);
+    AppError::DatabaseError(e.to_string())
+}
 
-        while let Some(chunk_result) = stream.next().await {
-            let chunk = chunk_result.map_err(|e| {
-                error!("Error reading from IPFS stream: {}", e);
+/// Prefix marking gzip-compressed documents. A leading NUL never starts the UTF-8 JSON
+/// stored by older versions, so compressed and plaintext content can coexist.
+pub const COMPRESSED_DOCUMENT_MAGIC: &[u8] = b"\0bdsgz1";
+
+/// Documents smaller than this are stored as plaintext even when compression is enabled
+const MIN_COMPRESSED_DOCUMENT_BYTES: usize = 1024;
+
+/// Bytes to store on IPFS for a JSON document, gzip-compressed behind
+/// `COMPRESSED_DOCUMENT_MAGIC` when `compress` is set and it actually saves space
+fn encode_document(content: &str, compress: bool) -> Result<Vec<u8>, AppError> {
+    if !compress || content.len() < MIN_COMPRESSED_DOCUMENT_BYTES {
+        return Ok(content.as_bytes().to_vec());
+    }
+
+    let mut encoder = GzEncoder::new(COMPRESSED_DOCUMENT_MAGIC.to_vec(), Compression::default());
+    encoder.write_all(content.as_bytes()).map_err(|e| {
+        error!("Failed to compress document: {}", e);
+        AppError::SerializationError
+    })?;
+    let compressed = encoder.finish().map_err(|e| {
+        error!("Failed to compress document: {}", e);
+        AppError::SerializationError
+    })?;
+
+    if compressed.len() >= content.len() {
+        return Ok(content.as_bytes().to_vec());
+    }
+    Ok(compressed)
+}
+
+/// Inverse of `encode_document`; legacy plaintext content is returned unchanged
+fn decode_document(bytes: Vec<u8>) -> Result<String, AppError> {
+    let bytes = match bytes.strip_prefix(COMPRESSED_DOCUMENT_MAGIC) {
+        Some(compressed) => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(compressed)
+                .read_to_end(&mut decompressed)
+                .map_err(|e| {
+                    error!("Failed to decompress IPFS document: {}", e);
+                    AppError::DeserializationError
+                })?;
+            decompressed
+        }
+        None => bytes,
+    };
+
+    String::from_utf8(bytes).map_err(|e| {
+        error!("Failed to convert IPFS bytes to string: {}", e);
+        AppError::DeserializationError
+    })
+}
+
+/// Run an IPFS operation with an upper bound on its duration.
+///
+/// Everything happens inside the awaited future rather than a spawned task, so when the
+/// caller is dropped (e.g. actix cancelling a handler after the client disconnects) the
+/// in-flight request and its connection are dropped with it.
+async fn with_ipfs_timeout<T>(
+    limit: std::time::Duration,
+    operation: impl std::future::Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    tokio::time::timeout(limit, operation).await.map_err(|_| {
+        error!("IPFS operation timed out after {:?}", limit);
+        AppError::ExternalServiceError("IPFS timeout".to_string())
+    })?
+}
+
+/// Chunks of `stream`, ending with an "IPFS timeout" error once the node sends nothing for
+/// `idle_limit`, so a stalled node can't hold a download open forever
+fn stream_with_idle_timeout<S>(
+    stream: S,
+    cid: String,
+    idle_limit: std::time::Duration,
+) -> impl Stream<Item = Result<actix_web::web::Bytes, AppError>> + Unpin + 'static
+where
+    S: Stream<Item = Result<actix_web::web::Bytes, ipfs_api::Error>> + Unpin + 'static,
+{
+    Box::pin(futures::stream::unfold(Some(stream), move |stream| {
+        let cid = cid.clone();
+        async move {
+            let mut stream = stream?;
+            match tokio::time::timeout(idle_limit, stream.next()).await {
+                Ok(Some(Ok(chunk))) => Some((Ok(chunk), Some(stream))),
+                Ok(Some(Err(e))) => {
+                    error!("Error streaming {} from IPFS: {}", cid, e);
+                    Some((Err(AppError::IPFSError(e)), None))
+                }
+                Ok(None) => None,
+                Err(_) => {
+                    error!(
+                        "Streaming {} from IPFS stalled for {:?}; giving up",
+                        cid, idle_limit
+                    );
+                    Some((
+                        Err(AppError::ExternalServiceError("IPFS timeout".to_string())),
+                        None,
+                    ))
+                }
+            }
+        }
+    }))
+}
+
+/// Add raw document bytes to IPFS, returning the CID
+async fn add_document(
+    client: &IpfsClient,
+    bytes: Vec<u8>,
+    options: &IpfsAddOptions,
+    limit: std::time::Duration,
+) -> Result<String, AppError> {
+    with_ipfs_timeout(limit, async {
+        let response = client
+            .add_with_options(std::io::Cursor::new(bytes), options.request())
+            .await
+            .map_err(|e| {
+                error!("IPFS add error: {}", e);
                 AppError::IPFSError(e)
             })?;
+        Ok(response.hash)
+    })
+    .await
+}
+
+/// Fetch and decode a stored document
+async fn get_document(
+    client: &IpfsClient,
+    cid: &str,
+    limit: std::time::Duration,
+) -> Result<String, AppError> {
+    with_ipfs_timeout(limit, read_document(client.cat(cid))).await
+}
+
+/// Collect a `cat` stream and decode it as a stored document
+async fn read_document(
+    stream: impl futures_util::Stream<Item = Result<actix_web::web::Bytes, ipfs_api::Error>> + Unpin,
+) -> Result<String, AppError> {
+    decode_document(collect_stream_bytes(stream).await?)
+}
 
-            bytes.extend_from_slice(&chunk);
+// Helper method to collect bytes from a stream
+async fn collect_stream_bytes(
+    mut stream: impl futures_util::Stream<Item = Result<actix_web::web::Bytes, ipfs_api::Error>> + Unpin,
+) -> Result<Vec<u8>, AppError> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk_result) = stream.next().await {
+        let chunk = chunk_result.map_err(|e| {
+            error!("Error reading from IPFS stream: {}", e);
+            AppError::IPFSError(e)
+        })?;
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Bytes;
+    use futures_util::stream;
+    use std::time::Duration as StdDuration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_superseded_versions_keep_latest_per_did() {
+        let row = |did: &str, cid: &str, unpinned| (did.to_string(), cid.to_string(), unpinned);
+        let versions = vec![
+            row("did:bio:a", "a3", false),
+            row("did:bio:a", "a2", false),
+            row("did:bio:a", "a1", true),
+            row("did:bio:a", "a0", false),
+            row("did:bio:b", "b1", false),
+            row("did:bio:b", "b0", false),
+            row("did:bio:c", "c0", false),
+        ];
+
+        assert_eq!(superseded_versions(versions.clone(), 2), vec!["a0"]);
+        assert_eq!(superseded_versions(versions, 1), vec!["a2", "a0", "b0"]);
+    }
+
+    fn sample_document() -> String {
+        let fields = (0..100)
+            .map(|i| format!("\"field_{}\": \"value {}\"", i, i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{{\"custom_fields\": {{{}}}}}", fields)
+    }
+
+    /// What `get_content` reads for content stored as `bytes`, split across two chunks
+    async fn get_stored(bytes: Vec<u8>) -> Result<String, AppError> {
+        let (head, tail) = bytes.split_at(bytes.len() / 2);
+        let chunks = vec![
+            Ok(Bytes::copy_from_slice(head)),
+            Ok(Bytes::copy_from_slice(tail)),
+        ];
+        read_document(stream::iter(chunks)).await
+    }
+
+    #[tokio::test]
+    async fn test_compressed_document_round_trip() {
+        let document = sample_document();
+        let stored = encode_document(&document, true).unwrap();
+
+        assert!(stored.starts_with(COMPRESSED_DOCUMENT_MAGIC));
+        assert!(stored.len() < document.len());
+        assert_eq!(get_stored(stored).await.unwrap(), document);
+    }
+
+    #[tokio::test]
+    async fn test_uncompressed_document_round_trip() {
+        let document = sample_document();
+        let stored = encode_document(&document, false).unwrap();
+
+        assert_eq!(stored, document.as_bytes());
+        assert_eq!(get_stored(stored).await.unwrap(), document);
+    }
+
+    #[tokio::test]
+    async fn test_small_document_stays_plaintext() {
+        let document = r#"{"id": "did:bio:123"}"#;
+        let stored = encode_document(document, true).unwrap();
+
+        assert_eq!(stored, document.as_bytes());
+        assert_eq!(get_stored(stored).await.unwrap(), document);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_compressed_document_fails() {
+        let mut stored = COMPRESSED_DOCUMENT_MAGIC.to_vec();
+        stored.extend_from_slice(b"not gzip");
+
+        assert!(matches!(
+            get_stored(stored).await,
+            Err(AppError::DeserializationError)
+        ));
+    }
+
+    /// Start a fake IPFS node that answers one request with `response` and then stalls.
+    /// The receiver resolves once the client closes the connection.
+    async fn stalled_ipfs_node(response: &'static [u8]) -> (IpfsClient, oneshot::Receiver<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed_tx, closed_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await;
+            if !response.is_empty() {
+                socket.write_all(response).await.unwrap();
+            }
+            // Never finish the response; wait for the client to hang up
+            while let Ok(n) = socket.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+            }
+            let _ = closed_tx.send(());
+        });
+
+        let client = IpfsClient::from_str(&format!("http://{}", addr)).unwrap();
+        (client, closed_rx)
+    }
+
+    const PARTIAL_CAT_RESPONSE: &[u8] =
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n{\"id\"\r\n";
+
+    #[tokio::test]
+    async fn test_get_times_out_when_node_stalls_mid_stream() {
+        let (client, _closed) = stalled_ipfs_node(PARTIAL_CAT_RESPONSE).await;
+
+        let result = get_document(&client, "QmStalled", StdDuration::from_millis(200)).await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::ExternalServiceError(message)) if message == "IPFS timeout"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_with_timeout_when_node_stalls_mid_stream() {
+        let (client, _closed) = stalled_ipfs_node(PARTIAL_CAT_RESPONSE).await;
+
+        let mut stream = stream_with_idle_timeout(
+            client.cat("QmStalled"),
+            "QmStalled".to_string(),
+            StdDuration::from_millis(200),
+        );
+
+        assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), b"{\"id\"");
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(AppError::ExternalServiceError(message))) if message == "IPFS timeout"
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_times_out_when_node_never_responds() {
+        let (client, _closed) = stalled_ipfs_node(b"").await;
+
+        let result = add_document(
+            &client,
+            b"{}".to_vec(),
+            &IpfsAddOptions::default(),
+            StdDuration::from_millis(200),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::ExternalServiceError(message)) if message == "IPFS timeout"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_get_drops_ipfs_connection() {
+        let (client, closed) = stalled_ipfs_node(PARTIAL_CAT_RESPONSE).await;
+
+        // Stand-in for actix dropping the handler future when the client disconnects
+        tokio::select! {
+            _ = get_document(&client, "QmStalled", StdDuration::from_secs(60)) => {
+                panic!("stalled read should not complete")
+            }
+            _ = tokio::time::sleep(StdDuration::from_millis(200)) => {}
         }
 
-        Ok(bytes)
+        assert!(
+            tokio::time::timeout(StdDuration::from_secs(5), closed)
+                .await
+                .is_ok(),
+            "IPFS connection was left open after the request was dropped"
+        );
     }
 }
@@ -0,0 +1,571 @@
+use crate::config::Config;
+use crate::database::db_time::{from_db, to_db};
+use crate::errors::AppError;
+use crate::services::audit_service::AuditService;
+use crate::services::auth_token::{DilithiumKeyring, INITIAL_KEY_VERSION};
+use crate::services::field_encryption::{FieldEncryption, KemKeyring};
+use crate::services::ipfs_service::IPFSService;
+use base64::engine::general_purpose::STANDARD as Base64Engine;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool, Row};
+use pqcrypto_dilithium::dilithium5::{self, PublicKey, SecretKey};
+use pqcrypto_kyber::kyber1024;
+use pqcrypto_traits::kem::{PublicKey as _, SecretKey as _};
+use pqcrypto_traits::sign::{PublicKey as _, SecretKey as _};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+/// Signs new tokens; exactly one version is current
+const STATUS_CURRENT: &str = "current";
+/// Superseded by a rotation but still accepted for verification
+const STATUS_RETAINED: &str = "retained";
+/// No longer accepted for signatures; its signing secret key has been erased, while its
+/// Kyber1024 secret key is kept to open the fields sealed to it
+const STATUS_RETIRED: &str = "retired";
+
+/// Prefix of secret keys wrapped with the key-encryption key. Base64 never contains a
+/// colon, so secrets stored by earlier versions, in plain base64, are told apart.
+const WRAPPED_PREFIX: &str = "kek1:";
+
+/// Length of a ChaCha20-Poly1305 nonce
+const NONCE_LEN: usize = 12;
+
+/// A version of the service's post-quantum key pairs, without the keys themselves
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct KeyVersion {
+    pub version: u32,
+    // "current", "retained" or "retired"
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    // When a newer version replaced it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotated_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<DateTime<Utc>>,
+    // Base64 Dilithium5 public key, for verifying what this version signed
+    pub signing_public_key: String,
+    // Base64 Kyber1024 public key; absent for the initial version, configured without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kem_public_key: Option<String>,
+}
+
+/// Registry of the service's Kyber1024/Dilithium5 key pairs in `key_versions`.
+///
+/// The current version signs auth tokens and its Kyber1024 key seals encrypted DID fields;
+/// retained versions still verify the tokens they signed until they are retired, and every
+/// version opens the fields sealed to it. The key pairs configured in `DILITHIUM_*_KEY` and
+/// `KYBER_*_KEY` become `INITIAL_KEY_VERSION` on first start. Every instance reloads the registry periodically,
+/// so a rotation made through one of them reaches the others.
+///
+/// Secret keys are stored wrapped with `KEY_ENCRYPTION_KEY`, so reading the database alone
+/// doesn't reveal them.
+pub struct KeyRegistry {
+    db_pool: Arc<Pool>,
+    ipfs_service: Arc<IPFSService>,
+    audit_service: Arc<AuditService>,
+    field_encryption: Arc<FieldEncryption>,
+    kek: KeyEncryptionKey,
+}
+
+impl KeyRegistry {
+    /// Register the configured key pairs if the registry is empty, then sign and seal with
+    /// the registry's current version
+    pub async fn new(
+        db_pool: Arc<Pool>,
+        ipfs_service: Arc<IPFSService>,
+        audit_service: Arc<AuditService>,
+        field_encryption: Arc<FieldEncryption>,
+        config: &Config,
+    ) -> Result<Self, AppError> {
+        let registry = Self {
+            db_pool,
+            ipfs_service,
+            audit_service,
+            field_encryption,
+            kek: KeyEncryptionKey::new(
+                config
+                    .get_key_encryption_key()
+                    .map_err(AppError::ServiceError)?,
+            ),
+        };
+
+        let public_key = config.get_public_key().map_err(AppError::ServiceError)?;
+        let secret_key = config.get_secret_key().map_err(AppError::ServiceError)?;
+        let kem_keys = config.get_kyber_keys().map_err(AppError::ServiceError)?;
+        let kem_public_key = kem_keys
+            .as_ref()
+            .map(|(public_key, _)| Base64Engine.encode(public_key.as_bytes()));
+        let kem_secret_key = kem_keys
+            .as_ref()
+            .map(|(_, secret_key)| registry.kek.wrap(KEM_SECRET_COLUMN, secret_key.as_bytes()));
+        let mut conn = registry.conn().await?;
+        r"INSERT IGNORE INTO key_versions
+            (version, status, kem_public_key, kem_secret_key, sign_public_key, sign_secret_key,
+             created_at)
+          VALUES (:version, :status, :kem_public_key, :kem_secret_key, :sign_public_key,
+             :sign_secret_key, :created_at)"
+            .with(params! {
+                "version" => INITIAL_KEY_VERSION,
+                "status" => STATUS_CURRENT,
+                "kem_public_key" => &kem_public_key,
+                "kem_secret_key" => &kem_secret_key,
+                "sign_public_key" => Base64Engine.encode(public_key.as_bytes()),
+                "sign_secret_key" => registry.kek.wrap(SIGN_SECRET_COLUMN, secret_key.as_bytes()),
+                "created_at" => to_db(Utc::now()),
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when registering the initial key: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        if conn.affected_rows() > 0 {
+            info!(
+                "Registered the configured signing key as version {}",
+                INITIAL_KEY_VERSION
+            );
+        }
+
+        // A registry created before a Kyber1024 key pair was configured takes it on
+        if kem_keys.is_some() {
+            r"UPDATE key_versions SET kem_public_key = :kem_public_key,
+                kem_secret_key = :kem_secret_key
+              WHERE version = :version AND kem_public_key IS NULL"
+                .with(params! {
+                    "kem_public_key" => &kem_public_key,
+                    "kem_secret_key" => &kem_secret_key,
+                    "version" => INITIAL_KEY_VERSION,
+                })
+                .ignore(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when registering the initial KEM key: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+        }
+
+        let current = registry.reload().await?;
+        info!("Signing auth tokens with key version {}", current);
+        if registry.field_encryption.has_fields()
+            && registry.field_encryption.current_kem_version().is_none()
+        {
+            return Err(AppError::ServiceError(format!(
+                "DID_ENCRYPTED_FIELDS needs a Kyber1024 key, but key version {} has none; \
+                 set KYBER_PUBLIC_KEY and KYBER_SECRET_KEY or rotate the keys",
+                current
+            )));
+        }
+        Ok(registry)
+    }
+
+    /// Reload the registry every `interval`, picking up rotations made by other instances
+    pub fn start_refresh(self: Arc<Self>, interval: StdDuration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately; keys were just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.reload().await {
+                    warn!("Failed to reload signing keys: {}", e);
+                }
+            }
+        });
+    }
+
+    /// All versions, newest first
+    pub async fn list(&self) -> Result<Vec<KeyVersion>, AppError> {
+        let mut conn = self.conn().await?;
+        let rows: Vec<Row> = r"SELECT version, status, created_at, rotated_at, retired_at,
+                 sign_public_key, kem_public_key
+               FROM key_versions ORDER BY version DESC"
+            .with(())
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing key versions: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        rows.into_iter().map(key_version_from_row).collect()
+    }
+
+    /// Generate a new Kyber1024/Dilithium5 key pair and make it the current version. The
+    /// previous version is retained for verification, and DID fields are sealed to the new
+    /// Kyber1024 key from now on.
+    pub async fn rotate(&self, user_id: i64) -> Result<KeyVersion, AppError> {
+        let (kem_public, kem_secret) = kyber1024::keypair();
+        let (sign_public, sign_secret) = dilithium5::keypair();
+        let now = to_db(Utc::now());
+
+        let mut conn = self.conn().await?;
+        let mut tx = conn
+            .start_transaction(mysql_async::TxOpts::default())
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        // Locks the current row, so concurrent rotations queue up behind each other
+        let previous: Option<u32> =
+            "SELECT version FROM key_versions WHERE status = :current FOR UPDATE"
+                .with(params! { "current" => STATUS_CURRENT })
+                .first(&mut tx)
+                .await
+                .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        "UPDATE key_versions SET status = :retained, rotated_at = :now WHERE status = :current"
+            .with(params! {
+                "retained" => STATUS_RETAINED,
+                "current" => STATUS_CURRENT,
+                "now" => &now,
+            })
+            .ignore(&mut tx)
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        r"INSERT INTO key_versions
+            (status, kem_public_key, kem_secret_key, sign_public_key, sign_secret_key,
+             created_at, created_by)
+          VALUES (:status, :kem_public_key, :kem_secret_key, :sign_public_key, :sign_secret_key,
+             :created_at, :created_by)"
+            .with(params! {
+                "status" => STATUS_CURRENT,
+                "kem_public_key" => Base64Engine.encode(kem_public.as_bytes()),
+                "kem_secret_key" => self.kek.wrap(KEM_SECRET_COLUMN, kem_secret.as_bytes()),
+                "sign_public_key" => Base64Engine.encode(sign_public.as_bytes()),
+                "sign_secret_key" => self.kek.wrap(SIGN_SECRET_COLUMN, sign_secret.as_bytes()),
+                "created_at" => &now,
+                "created_by" => user_id,
+            })
+            .ignore(&mut tx)
+            .await
+            .map_err(|e| {
+                error!("Database error when storing rotated keys: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        let version = tx
+            .last_insert_id()
+            .and_then(|id| u32::try_from(id).ok())
+            .ok_or_else(|| AppError::DatabaseError("Missing key version ID".to_string()))?;
+        tx.commit()
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        self.reload().await?;
+        info!(
+            "User {} rotated the signing keys from version {:?} to {}",
+            user_id, previous, version
+        );
+        self.audit_service
+            .record_audit(
+                user_id,
+                "keys.rotate",
+                "key_version",
+                &version.to_string(),
+                serde_json::json!({ "previous_version": previous }),
+            )
+            .await;
+
+        self.find(version).await
+    }
+
+    /// Stop accepting what `version` signed and erase its signing secret key. Its Kyber1024
+    /// secret key stays, since fields sealed to it must remain readable. The current version
+    /// can't be retired; rotate first.
+    pub async fn retire(&self, version: u32, user_id: i64) -> Result<KeyVersion, AppError> {
+        let existing = self.find(version).await?;
+        match existing.status.as_str() {
+            STATUS_CURRENT => {
+                return Err(AppError::Conflict(format!(
+                    "Key version {} is current; rotate the keys before retiring it",
+                    version
+                )))
+            }
+            STATUS_RETIRED => return Ok(existing),
+            _ => {}
+        }
+
+        let mut conn = self.conn().await?;
+        r"UPDATE key_versions
+          SET status = :retired, retired_at = :now, sign_secret_key = NULL
+          WHERE version = :version AND status = :retained"
+            .with(params! {
+                "retired" => STATUS_RETIRED,
+                "retained" => STATUS_RETAINED,
+                "now" => to_db(Utc::now()),
+                "version" => version,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Database error when retiring key version {}: {}",
+                    version, e
+                );
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        self.reload().await?;
+        info!("User {} retired key version {}", user_id, version);
+        self.audit_service
+            .record_audit(
+                user_id,
+                "keys.retire",
+                "key_version",
+                &version.to_string(),
+                serde_json::json!({}),
+            )
+            .await;
+
+        self.find(version).await
+    }
+
+    async fn find(&self, version: u32) -> Result<KeyVersion, AppError> {
+        let mut conn = self.conn().await?;
+        let row: Option<Row> = r"SELECT version, status, created_at, rotated_at, retired_at,
+                 sign_public_key, kem_public_key
+               FROM key_versions WHERE version = :version"
+            .with(params! { "version" => version })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reading key version {}: {}", version, e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        row.map(key_version_from_row)
+            .transpose()?
+            .ok_or_else(|| AppError::NotFound(format!("Key version {} not found", version)))
+    }
+
+    /// Install the current and retained Dilithium5 keys in the token signer and every
+    /// version's Kyber1024 keys in field encryption, returning the current version
+    async fn reload(&self) -> Result<u32, AppError> {
+        let mut conn = self.conn().await?;
+        let rows: Vec<KeyRow> = r"SELECT version, status, sign_public_key, sign_secret_key,
+                 kem_public_key, kem_secret_key
+               FROM key_versions"
+            .with(())
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when loading signing keys: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let mut current = None;
+        let mut keys = HashMap::new();
+        let mut kem_keys = HashMap::new();
+        for (version, status, public_key, secret_key, kem_public_key, kem_secret_key) in rows {
+            if status == STATUS_CURRENT {
+                current = Some(version);
+            }
+            if let (Some(public_key), Some(secret_key)) = (kem_public_key, kem_secret_key) {
+                let secret_key = self
+                    .unwrap_secret(&mut conn, version, KEM_SECRET_COLUMN, &secret_key)
+                    .await?;
+                kem_keys.insert(version, decode_kem_keys(version, &public_key, &secret_key)?);
+            }
+            let Some(secret_key) = secret_key.filter(|_| status != STATUS_RETIRED) else {
+                continue;
+            };
+            let secret_key = self
+                .unwrap_secret(&mut conn, version, SIGN_SECRET_COLUMN, &secret_key)
+                .await?;
+            keys.insert(
+                version,
+                decode_signing_keys(version, &public_key, &secret_key)?,
+            );
+        }
+        let current = current
+            .ok_or_else(|| AppError::ServiceError("No current signing key version".to_string()))?;
+
+        let keyring = DilithiumKeyring::new(current, keys)
+            .map_err(|e| AppError::ServiceError(e.to_string()))?;
+        self.ipfs_service.install_signing_keys(keyring);
+        self.field_encryption
+            .install_keys(KemKeyring::new(current, kem_keys));
+        Ok(current)
+    }
+
+    /// Unwrap a secret key read from `column` of `version`. A secret stored unwrapped by an
+    /// earlier version is wrapped in place.
+    async fn unwrap_secret(
+        &self,
+        conn: &mut mysql_async::Conn,
+        version: u32,
+        column: &'static str,
+        stored: &str,
+    ) -> Result<Vec<u8>, AppError> {
+        if let Some(wrapped) = stored.strip_prefix(WRAPPED_PREFIX) {
+            return self.kek.unwrap(column, wrapped).ok_or_else(|| {
+                error!(
+                    "Key version {} can't be unwrapped; is KEY_ENCRYPTION_KEY the one it was stored with?",
+                    version
+                );
+                AppError::ServiceError(format!("Key version {} is corrupt", version))
+            });
+        }
+
+        let secret = Base64Engine
+            .decode(stored)
+            .map_err(|_| AppError::ServiceError(format!("Key version {} is corrupt", version)))?;
+        format!(
+            "UPDATE key_versions SET {column} = :wrapped WHERE version = :version AND {column} = :stored"
+        )
+        .with(params! {
+            "wrapped" => self.kek.wrap(column, &secret),
+            "version" => version,
+            "stored" => stored,
+        })
+        .ignore(&mut *conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when wrapping key version {}: {}", version, e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        info!("Wrapped the {} of key version {}", column, version);
+        Ok(secret)
+    }
+
+    async fn conn(&self) -> Result<mysql_async::Conn, AppError> {
+        self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })
+    }
+}
+
+/// Columns of `key_versions` holding wrapped secret keys; a wrapped secret is bound to its
+/// column, so a secret can't be swapped into the other one
+const SIGN_SECRET_COLUMN: &str = "sign_secret_key";
+const KEM_SECRET_COLUMN: &str = "kem_secret_key";
+
+/// ChaCha20-Poly1305 key wrapping the secret keys stored in `key_versions`
+struct KeyEncryptionKey(Key);
+
+impl KeyEncryptionKey {
+    fn new(key: [u8; 32]) -> Self {
+        Self(Key::from(key))
+    }
+
+    /// `secret` sealed for `column`, as `kek1:` and the base64 nonce and ciphertext
+    fn wrap(&self, column: &str, secret: &[u8]) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&self.0)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: secret,
+                    aad: column.as_bytes(),
+                },
+            )
+            .expect("ChaCha20-Poly1305 encrypts any secret key");
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend(ciphertext);
+        format!("{}{}", WRAPPED_PREFIX, Base64Engine.encode(wrapped))
+    }
+
+    /// The secret in `wrapped`, stripped of its prefix, unless it was wrapped with another
+    /// key or for another column
+    fn unwrap(&self, column: &str, wrapped: &str) -> Option<Vec<u8>> {
+        let wrapped = Base64Engine.decode(wrapped).ok()?;
+        if wrapped.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = wrapped.split_at(NONCE_LEN);
+        ChaCha20Poly1305::new(&self.0)
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: column.as_bytes(),
+                },
+            )
+            .ok()
+    }
+}
+
+/// Version, status, signing public and secret keys, and Kyber1024 public and secret keys
+type KeyRow = (
+    u32,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn decode_kem_keys(
+    version: u32,
+    public_key: &str,
+    secret_key: &[u8],
+) -> Result<(kyber1024::PublicKey, kyber1024::SecretKey), AppError> {
+    let invalid = || AppError::ServiceError(format!("Key version {} is corrupt", version));
+    let public_key = Base64Engine
+        .decode(public_key)
+        .ok()
+        .and_then(|bytes| kyber1024::PublicKey::from_bytes(&bytes).ok())
+        .ok_or_else(invalid)?;
+    let secret_key = kyber1024::SecretKey::from_bytes(secret_key).map_err(|_| invalid())?;
+    Ok((public_key, secret_key))
+}
+
+fn decode_signing_keys(
+    version: u32,
+    public_key: &str,
+    secret_key: &[u8],
+) -> Result<(PublicKey, SecretKey), AppError> {
+    let invalid = || AppError::ServiceError(format!("Key version {} is corrupt", version));
+    let public_key = Base64Engine
+        .decode(public_key)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+        .ok_or_else(invalid)?;
+    let secret_key = SecretKey::from_bytes(secret_key).map_err(|_| invalid())?;
+    Ok((public_key, secret_key))
+}
+
+fn key_version_from_row(row: Row) -> Result<KeyVersion, AppError> {
+    let timestamp = |index: usize| -> Result<Option<DateTime<Utc>>, AppError> {
+        row.get::<Option<String>, _>(index)
+            .flatten()
+            .map(|value| from_db(&value).ok_or(AppError::DeserializationError))
+            .transpose()
+    };
+
+    Ok(KeyVersion {
+        version: row.get(0).unwrap_or_default(),
+        status: row.get(1).unwrap_or_default(),
+        created_at: timestamp(2)?.ok_or(AppError::DeserializationError)?,
+        rotated_at: timestamp(3)?,
+        retired_at: timestamp(4)?,
+        signing_public_key: row.get(5).unwrap_or_default(),
+        kem_public_key: row.get(6).flatten(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_keys_are_wrapped_for_their_column() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let wrapped = kek.wrap(SIGN_SECRET_COLUMN, b"secret key");
+        assert!(!wrapped.contains(&Base64Engine.encode(b"secret key")));
+        let wrapped = wrapped.strip_prefix(WRAPPED_PREFIX).unwrap();
+
+        assert_eq!(
+            kek.unwrap(SIGN_SECRET_COLUMN, wrapped).as_deref(),
+            Some(&b"secret key"[..])
+        );
+        assert!(kek.unwrap(KEM_SECRET_COLUMN, wrapped).is_none());
+        assert!(KeyEncryptionKey::new([8; 32])
+            .unwrap(SIGN_SECRET_COLUMN, wrapped)
+            .is_none());
+        assert!(kek.unwrap(SIGN_SECRET_COLUMN, "AAAA").is_none());
+    }
+}
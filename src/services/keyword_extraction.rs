@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+/// Longest keyphrase considered, in words
+const MAX_PHRASE_WORDS: usize = 3;
+
+/// Words keyphrases don't cross
+const STOPWORDS: &[&str] = &[
+    "a",
+    "about",
+    "above",
+    "across",
+    "after",
+    "again",
+    "against",
+    "all",
+    "also",
+    "although",
+    "among",
+    "an",
+    "and",
+    "any",
+    "are",
+    "as",
+    "at",
+    "be",
+    "been",
+    "before",
+    "being",
+    "between",
+    "both",
+    "but",
+    "by",
+    "can",
+    "could",
+    "did",
+    "do",
+    "does",
+    "done",
+    "due",
+    "during",
+    "each",
+    "either",
+    "et",
+    "etc",
+    "few",
+    "for",
+    "found",
+    "from",
+    "further",
+    "had",
+    "has",
+    "have",
+    "having",
+    "here",
+    "how",
+    "however",
+    "i",
+    "if",
+    "in",
+    "into",
+    "is",
+    "it",
+    "its",
+    "itself",
+    "may",
+    "more",
+    "most",
+    "much",
+    "must",
+    "neither",
+    "no",
+    "nor",
+    "not",
+    "of",
+    "on",
+    "once",
+    "only",
+    "or",
+    "other",
+    "our",
+    "out",
+    "over",
+    "per",
+    "respectively",
+    "same",
+    "several",
+    "should",
+    "show",
+    "showed",
+    "shown",
+    "shows",
+    "since",
+    "so",
+    "some",
+    "such",
+    "than",
+    "that",
+    "the",
+    "their",
+    "them",
+    "then",
+    "there",
+    "these",
+    "they",
+    "this",
+    "those",
+    "through",
+    "thus",
+    "to",
+    "under",
+    "until",
+    "up",
+    "upon",
+    "use",
+    "used",
+    "using",
+    "very",
+    "via",
+    "was",
+    "we",
+    "were",
+    "what",
+    "when",
+    "where",
+    "whether",
+    "which",
+    "while",
+    "who",
+    "whom",
+    "why",
+    "will",
+    "with",
+    "within",
+    "without",
+    "would",
+    "yet",
+];
+
+/// Up to `max` keyphrases of `text`, best first, lowercased.
+///
+/// Candidates are phrases of up to `MAX_PHRASE_WORDS` words that don't cross stopwords or
+/// punctuation. A phrase scores its number of occurrences times its number of words, so
+/// repeated specific terms such as "single-cell rna sequencing" beat the words they are
+/// made of; multi-word phrases have to occur at least twice to count. Phrases contained in
+/// a better one are left out, and ties keep the order in which they first appear.
+pub fn extract_keywords(text: &str, max: usize) -> Vec<String> {
+    // Phrase, its word count and occurrences, in order of first appearance
+    let mut candidates: Vec<(String, usize, usize)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for run in content_runs(text) {
+        for start in 0..run.len() {
+            for words in 1..=MAX_PHRASE_WORDS.min(run.len() - start) {
+                let phrase = run[start..start + words].join(" ");
+                match index.get(&phrase) {
+                    Some(&i) => candidates[i].2 += 1,
+                    None => {
+                        index.insert(phrase.clone(), candidates.len());
+                        candidates.push((phrase, words, 1));
+                    }
+                }
+            }
+        }
+    }
+
+    candidates.retain(|(_, words, occurrences)| *words == 1 || *occurrences > 1);
+    // Stable, so equal scores stay in order of appearance
+    candidates.sort_by_key(|(_, words, occurrences)| std::cmp::Reverse(words * occurrences));
+
+    let mut keywords: Vec<String> = Vec::new();
+    for (phrase, _, _) in candidates {
+        if keywords.len() == max {
+            break;
+        }
+        let padded = format!(" {} ", phrase);
+        if !keywords
+            .iter()
+            .any(|keyword| format!(" {} ", keyword).contains(&padded))
+        {
+            keywords.push(phrase);
+        }
+    }
+    keywords
+}
+
+/// Runs of content words in `text`, lowercased, split at stopwords and punctuation
+fn content_runs(text: &str) -> Vec<Vec<String>> {
+    let mut runs = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+
+    for token in text.split_inclusive(|c: char| c.is_whitespace() || is_phrase_break(c)) {
+        let word = token
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        if is_content_word(&word) {
+            current.push(word);
+        } else if !word.is_empty() && !current.is_empty() {
+            runs.push(std::mem::take(&mut current));
+        }
+        if token.ends_with(is_phrase_break) && !current.is_empty() {
+            runs.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        runs.push(current);
+    }
+    runs
+}
+
+fn is_phrase_break(c: char) -> bool {
+    matches!(
+        c,
+        '.' | ','
+            | ';'
+            | ':'
+            | '!'
+            | '?'
+            | '('
+            | ')'
+            | '['
+            | ']'
+            | '{'
+            | '}'
+            | '"'
+            | '\u{2013}'
+            | '\u{2014}'
+    )
+}
+
+/// Words that can be part of a keyphrase: not a stopword, a number or a single letter
+fn is_content_word(word: &str) -> bool {
+    word.chars().count() > 1
+        && word.chars().any(char::is_alphabetic)
+        && STOPWORDS.binary_search(&word).is_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stopwords_are_sorted() {
+        assert!(STOPWORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_extract_keywords_prefers_multiword_terms() {
+        let abstract_text = "We applied single-cell RNA sequencing to 12 tumour samples. \
+            Single-cell RNA sequencing revealed distinct macrophage populations, and \
+            spatial transcriptomics confirmed that these macrophage populations are \
+            located at the tumour margin.";
+
+        let keywords = extract_keywords(abstract_text, 4);
+        assert_eq!(
+            keywords,
+            vec![
+                "single-cell rna sequencing",
+                "macrophage populations",
+                "tumour",
+                "applied",
+            ]
+        );
+
+        assert!(extract_keywords("", 5).is_empty());
+        assert!(extract_keywords("The and of it.", 5).is_empty());
+    }
+}
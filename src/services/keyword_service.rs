@@ -0,0 +1,347 @@
+use crate::config::Config;
+use crate::errors::AppError;
+use log::{error, info};
+use mysql_async::{params, prelude::*, Pool};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Longest normalized keyword indexed, the width of `did_keywords.normalized`
+const MAX_NORMALIZED_CHARS: usize = 255;
+
+/// Suggestions returned when the request doesn't ask for a number
+pub const DEFAULT_SUGGESTIONS: u32 = 10;
+
+/// Most suggestions returned for one request
+pub const MAX_SUGGESTIONS: u32 = 50;
+
+/// Comparable form of a free-text keyword: lower case, with hyphens and underscores read as
+/// spaces, runs of whitespace collapsed and surrounding punctuation dropped, so
+/// "Machine-Learning" and "machine  learning" both become "machine learning"
+pub fn keyword_normalize(raw: &str) -> String {
+    let spaced: String = raw
+        .chars()
+        .map(|c| if c == '-' || c == '_' { ' ' } else { c })
+        .flat_map(char::to_lowercase)
+        .collect();
+    spaced
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c.is_ascii_punctuation() && c != '+' && c != '#')
+        .trim()
+        .to_string()
+}
+
+/// Controlled vocabulary keywords are mapped to, e.g. MeSH preferred terms
+#[derive(Debug, Default)]
+pub struct KeywordVocabulary {
+    // Normalized synonym or preferred term to the preferred term as written
+    terms: HashMap<String, String>,
+}
+
+impl KeywordVocabulary {
+    /// Parse `synonym = Preferred Term` lines. Blank lines and lines starting with `#` are
+    /// skipped, and every preferred term also maps to itself.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut terms = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (synonym, preferred) = line
+                .split_once('=')
+                .map(|(synonym, preferred)| (synonym.trim(), preferred.trim()))
+                .filter(|(synonym, preferred)| {
+                    !keyword_normalize(synonym).is_empty()
+                        && !keyword_normalize(preferred).is_empty()
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "line {}: expected `synonym = Preferred Term`, found '{}'",
+                        number + 1,
+                        line
+                    )
+                })?;
+            terms.insert(keyword_normalize(synonym), preferred.to_string());
+            terms.insert(keyword_normalize(preferred), preferred.to_string());
+        }
+        Ok(Self { terms })
+    }
+
+    /// Synonyms and preferred terms known
+    pub fn term_count(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// Normalized form of `raw`, or of its preferred term when the vocabulary knows it
+    pub fn normalize(&self, raw: &str) -> String {
+        let normalized = keyword_normalize(raw);
+        match self.terms.get(&normalized) {
+            Some(preferred) => keyword_normalize(preferred),
+            None => normalized,
+        }
+    }
+
+    /// Preferred term, as written in the vocabulary, with this normalized form
+    fn label(&self, normalized: &str) -> Option<&str> {
+        self.terms.get(normalized).map(String::as_str)
+    }
+}
+
+/// An observed keyword matching an autocomplete query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct KeywordSuggestion {
+    // The vocabulary's preferred term, or else the spelling used most
+    pub keyword: String,
+    pub normalized: String,
+    // DIDs listing the keyword in any spelling
+    pub count: u64,
+}
+
+/// Index of the keywords DIDs and papers list, in their original and normalized forms
+pub struct KeywordService {
+    db_pool: Arc<Pool>,
+    vocabulary: KeywordVocabulary,
+}
+
+impl KeywordService {
+    /// Load the controlled vocabulary from `KEYWORD_VOCABULARY_FILE`, if set
+    pub fn new(config: &Config, db_pool: Arc<Pool>) -> Result<Self, AppError> {
+        let vocabulary = match &config.keyword_vocabulary_file {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).map_err(|e| {
+                    error!("Failed to read keyword vocabulary {}: {}", path, e);
+                    AppError::FileError(format!("Failed to read keyword vocabulary: {}", e))
+                })?;
+                let vocabulary = KeywordVocabulary::parse(&text).map_err(|e| {
+                    error!("Invalid keyword vocabulary {}: {}", path, e);
+                    AppError::ValidationError(format!("Invalid keyword vocabulary: {}", e))
+                })?;
+                info!(
+                    "Loaded {} keyword vocabulary terms from {}",
+                    vocabulary.term_count(),
+                    path
+                );
+                vocabulary
+            }
+            None => KeywordVocabulary::default(),
+        };
+
+        Ok(Self {
+            db_pool,
+            vocabulary,
+        })
+    }
+
+    pub fn vocabulary(&self) -> &KeywordVocabulary {
+        &self.vocabulary
+    }
+
+    /// Replace the keywords indexed for `did`, in the caller's transaction
+    pub async fn replace(
+        &self,
+        conn: &mut impl Queryable,
+        did: &str,
+        keywords: &[String],
+    ) -> Result<(), mysql_async::Error> {
+        conn.exec_drop(
+            "DELETE FROM did_keywords WHERE did = :did",
+            params! { "did" => did },
+        )
+        .await?;
+        self.insert(conn, did, keywords).await
+    }
+
+    /// Index `keywords` for `did` alongside those it already has, e.g. a paper's
+    pub async fn add(&self, did: &str, keywords: &[String]) -> Result<(), AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        self.insert(&mut conn, did, keywords).await.map_err(|e| {
+            error!("Database error when indexing keywords of {}: {}", did, e);
+            AppError::DatabaseError(e.to_string())
+        })
+    }
+
+    async fn insert(
+        &self,
+        conn: &mut impl Queryable,
+        did: &str,
+        keywords: &[String],
+    ) -> Result<(), mysql_async::Error> {
+        // The first spelling of a keyword is kept when a DID lists it several ways
+        conn.exec_batch(
+            "INSERT IGNORE INTO did_keywords (did, keyword, normalized) VALUES (:did, :keyword, :normalized)",
+            keywords
+                .iter()
+                .map(|keyword| (keyword, self.vocabulary.normalize(keyword)))
+                .filter(|(_, normalized)| {
+                    !normalized.is_empty() && normalized.chars().count() <= MAX_NORMALIZED_CHARS
+                })
+                .map(|(keyword, normalized)| {
+                    params! {
+                        "did" => did,
+                        "keyword" => keyword.chars().take(MAX_NORMALIZED_CHARS).collect::<String>(),
+                        "normalized" => normalized,
+                    }
+                }),
+        )
+        .await
+    }
+
+    /// Keywords starting with `query`, or with a word of it, most used first.
+    /// A query the vocabulary maps, e.g. an abbreviation, also suggests its preferred term.
+    pub async fn suggest(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<KeywordSuggestion>, AppError> {
+        let normalized = keyword_normalize(query);
+        if normalized.is_empty() {
+            return Err(AppError::ValidationError(
+                "Query must contain a keyword".to_string(),
+            ));
+        }
+        let prefix = escape_like(&normalized);
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let rows: Vec<(String, String, u64)> = r"SELECT normalized, keyword, COUNT(*)
+            FROM did_keywords
+            WHERE normalized LIKE :prefix OR normalized LIKE :word_prefix
+               OR normalized = :mapped
+            GROUP BY normalized, keyword"
+            .with(params! {
+                "prefix" => format!("{}%", prefix),
+                "word_prefix" => format!("% {}%", prefix),
+                "mapped" => self.vocabulary.normalize(query),
+            })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when suggesting keywords: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        Ok(rank_suggestions(
+            rows,
+            &self.vocabulary,
+            limit.clamp(1, MAX_SUGGESTIONS) as usize,
+        ))
+    }
+}
+
+/// `value` with the wildcards of a `LIKE` pattern escaped
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Merge the spellings in `rows` of (normalized, keyword, uses) into one suggestion per
+/// normalized keyword, most used first
+fn rank_suggestions(
+    rows: Vec<(String, String, u64)>,
+    vocabulary: &KeywordVocabulary,
+    limit: usize,
+) -> Vec<KeywordSuggestion> {
+    // Normalized keyword to its total uses and most used spelling
+    let mut merged: HashMap<String, (u64, String, u64)> = HashMap::new();
+    for (normalized, keyword, uses) in rows {
+        let entry = merged
+            .entry(normalized)
+            .or_insert_with(|| (0, keyword.clone(), 0));
+        entry.0 += uses;
+        if uses > entry.2 || (uses == entry.2 && keyword < entry.1) {
+            entry.1 = keyword;
+            entry.2 = uses;
+        }
+    }
+
+    let mut suggestions: Vec<KeywordSuggestion> = merged
+        .into_iter()
+        .map(|(normalized, (count, spelling, _))| KeywordSuggestion {
+            keyword: vocabulary
+                .label(&normalized)
+                .map(str::to_string)
+                .unwrap_or(spelling),
+            normalized,
+            count,
+        })
+        .collect();
+    suggestions.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.normalized.cmp(&b.normalized))
+    });
+    suggestions.truncate(limit);
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_spellings_normalize_and_map_to_preferred_terms() {
+        assert_eq!(keyword_normalize("Machine Learning"), "machine learning");
+        assert_eq!(keyword_normalize(" machine-learning. "), "machine learning");
+        assert_eq!(
+            keyword_normalize("single_cell  RNA-seq"),
+            "single cell rna seq"
+        );
+        assert_eq!(keyword_normalize("C++"), "c++");
+        assert_eq!(keyword_normalize("--"), "");
+
+        let vocabulary = KeywordVocabulary::parse(
+            "# MeSH\nML = Machine Learning\n\nneoplasms = Neoplasms, Glandular\n",
+        )
+        .unwrap();
+        assert_eq!(vocabulary.normalize("ML"), "machine learning");
+        assert_eq!(vocabulary.normalize("machine-learning"), "machine learning");
+        assert_eq!(vocabulary.normalize("Neoplasms"), "neoplasms, glandular");
+        assert_eq!(vocabulary.normalize("Proteomics"), "proteomics");
+        assert!(KeywordVocabulary::parse("ML Machine Learning").is_err());
+        assert!(KeywordVocabulary::parse("ML = ").is_err());
+
+        let rows = vec![
+            ("machine learning".to_string(), "ML".to_string(), 2),
+            (
+                "machine learning".to_string(),
+                "machine-learning".to_string(),
+                1,
+            ),
+            ("metabolomics".to_string(), "Metabolomics".to_string(), 4),
+            ("microbiome".to_string(), "microbiome".to_string(), 1),
+            ("microbiome".to_string(), "Microbiome".to_string(), 1),
+        ];
+        assert_eq!(
+            rank_suggestions(rows, &vocabulary, 3),
+            vec![
+                KeywordSuggestion {
+                    keyword: "Metabolomics".to_string(),
+                    normalized: "metabolomics".to_string(),
+                    count: 4,
+                },
+                KeywordSuggestion {
+                    keyword: "Machine Learning".to_string(),
+                    normalized: "machine learning".to_string(),
+                    count: 3,
+                },
+                KeywordSuggestion {
+                    keyword: "Microbiome".to_string(),
+                    normalized: "microbiome".to_string(),
+                    count: 2,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::errors::AppError;
+use log::error;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+
+/// Label used for requests that did not match any registered route
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Prometheus registry with the HTTP and upstream-service metrics exported at `/metrics`
+pub struct MetricsService {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    external_service_errors_total: IntCounterVec,
+    cache_requests_total: IntCounterVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+    db_pool_acquire_seconds: Gauge,
+    db_pool_health_check_failures_total: IntCounter,
+}
+
+impl MetricsService {
+    pub fn new() -> Result<Self, AppError> {
+        let registry =
+            Registry::new_custom(Some("bio_did_seq".to_string()), None).map_err(metrics_error)?;
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .map_err(metrics_error)?;
+
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+            ]),
+            &["method", "route"],
+        )
+        .map_err(metrics_error)?;
+
+        let external_service_errors_total = IntCounterVec::new(
+            Opts::new(
+                "external_service_errors_total",
+                "Failed calls to upstream services such as BioAgents and Dataverse",
+            ),
+            &["service"],
+        )
+        .map_err(metrics_error)?;
+
+        let cache_requests_total = IntCounterVec::new(
+            Opts::new(
+                "cache_requests_total",
+                "In-memory cache lookups by cache and result (hit or miss)",
+            ),
+            &["cache", "result"],
+        )
+        .map_err(metrics_error)?;
+
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Database connections of the service's user by state (active or idle), from the last pool health check",
+            ),
+            &["state"],
+        )
+        .map_err(metrics_error)?;
+
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Upper bound of the database connection pool",
+        )
+        .map_err(metrics_error)?;
+
+        let db_pool_acquire_seconds = Gauge::new(
+            "db_pool_acquire_seconds",
+            "Time the last pool health check waited for a database connection",
+        )
+        .map_err(metrics_error)?;
+
+        let db_pool_health_check_failures_total = IntCounter::new(
+            "db_pool_health_check_failures_total",
+            "Pool health checks and readiness probes that couldn't run a query",
+        )
+        .map_err(metrics_error)?;
+
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(http_request_duration_seconds.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(external_service_errors_total.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(cache_requests_total.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(db_pool_max_connections.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(db_pool_acquire_seconds.clone()))
+            .map_err(metrics_error)?;
+        registry
+            .register(Box::new(db_pool_health_check_failures_total.clone()))
+            .map_err(metrics_error)?;
+
+        Ok(Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            external_service_errors_total,
+            cache_requests_total,
+            db_pool_connections,
+            db_pool_max_connections,
+            db_pool_acquire_seconds,
+            db_pool_health_check_failures_total,
+        })
+    }
+
+    /// Record a finished HTTP request. `route` should be the matched route pattern
+    /// (e.g. `/api/did/{did}`) so per-resource paths don't create new series.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, duration_secs: f64) {
+        self.http_requests_total
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration_seconds
+            .with_label_values(&[method, route])
+            .observe(duration_secs);
+    }
+
+    /// Count a failed call to an upstream service
+    pub fn record_external_error(&self, service: &str) {
+        self.external_service_errors_total
+            .with_label_values(&[service])
+            .inc();
+    }
+
+    /// Count a lookup in an in-memory cache
+    pub fn record_cache_lookup(&self, cache: &str, hit: bool) {
+        self.cache_requests_total
+            .with_label_values(&[cache, if hit { "hit" } else { "miss" }])
+            .inc();
+    }
+
+    /// Record the outcome of a successful database pool health check. Unknown counts leave
+    /// the previous values in place.
+    pub fn record_db_pool(
+        &self,
+        active: Option<u64>,
+        idle: Option<u64>,
+        max: usize,
+        acquire_secs: f64,
+    ) {
+        if let Some(active) = active {
+            self.db_pool_connections
+                .with_label_values(&["active"])
+                .set(active as i64);
+        }
+        if let Some(idle) = idle {
+            self.db_pool_connections
+                .with_label_values(&["idle"])
+                .set(idle as i64);
+        }
+        self.db_pool_max_connections.set(max as i64);
+        self.db_pool_acquire_seconds.set(acquire_secs);
+    }
+
+    /// Count a database pool health check or readiness probe that failed
+    pub fn record_db_health_failure(&self) {
+        self.db_pool_health_check_failures_total.inc();
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> Result<String, AppError> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(metrics_error)?;
+
+        String::from_utf8(buffer).map_err(|_| AppError::SerializationError)
+    }
+}
+
+fn metrics_error(e: prometheus::Error) -> AppError {
+    error!("Metrics error: {}", e);
+    AppError::ServiceError(format!("Metrics error: {}", e))
+}
@@ -1,6 +1,34 @@
+pub mod audit_service;
+pub mod auth_token;
 pub mod bioagents_service;
+pub mod circuit_breaker;
 pub mod dataverse_service;
+pub mod did_cache;
 pub mod did_service;
+pub mod entity_enrichment;
+pub mod export_service;
+pub mod field_encryption;
+pub mod http_client;
+pub mod idempotency_service;
+pub mod ipfs_cluster;
 pub mod ipfs_service;
+pub mod key_registry;
+pub mod keyword_extraction;
+pub mod keyword_service;
+pub mod metrics_service;
+pub mod notification_service;
+pub mod orcid_service;
+pub mod pdf_metadata;
+pub mod pii_scanner;
+pub mod provenance_service;
+pub mod publication_service;
+pub mod quota_service;
 pub mod research_paper_service;
+pub mod sparql_service;
+pub mod stats_service;
+pub mod step_up_service;
+pub mod task_events;
+pub mod task_service;
 pub mod ucan_service;
+pub mod upload_session_service;
+pub mod webhook_service;
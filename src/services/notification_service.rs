@@ -0,0 +1,184 @@
+use crate::config::Config;
+use crate::database::ORCID_PLACEHOLDER_EMAIL_DOMAIN;
+use crate::errors::AppError;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{info, warn};
+use mysql_async::{prelude::*, Pool};
+use std::sync::Arc;
+
+/// Final state of a long-running task, as reported to its owner
+pub struct TaskNotice<'a> {
+    pub task_id: &'a str,
+    // Human-readable task kind, e.g. "Upload" or "BioAgents paper processing"
+    pub kind: &'a str,
+    pub status: &'a str,
+    pub did: Option<&'a str>,
+    pub cid: Option<&'a str>,
+    pub error: Option<&'a str>,
+}
+
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// Emails users when their background tasks finish.
+///
+/// Every call is a no-op unless `SMTP_HOST` is configured. Delivery problems are logged and
+/// never surface to callers, so a failed email cannot fail the task it reports on.
+pub struct NotificationService {
+    db_pool: Arc<Pool>,
+    mailer: Option<Mailer>,
+}
+
+impl NotificationService {
+    pub fn new(config: &Config, db_pool: Arc<Pool>) -> Result<Self, AppError> {
+        let mailer = match &config.smtp_host {
+            Some(host) => Some(build_mailer(config, host)?),
+            None => {
+                info!("SMTP_HOST not set, task notifications are disabled");
+                None
+            }
+        };
+
+        Ok(Self { db_pool, mailer })
+    }
+
+    /// Whether notifications are actually sent
+    pub fn is_enabled(&self) -> bool {
+        self.mailer.is_some()
+    }
+
+    /// Email the task's owner about its outcome
+    pub async fn task_finished(&self, user_id: i64, notice: TaskNotice<'_>) {
+        let Some(mailer) = &self.mailer else {
+            return;
+        };
+
+        if let Err(e) = self.send_task_notice(mailer, user_id, &notice).await {
+            warn!(
+                "Failed to send notification for task {}: {}",
+                notice.task_id, e
+            );
+        }
+    }
+
+    async fn send_task_notice(
+        &self,
+        mailer: &Mailer,
+        user_id: i64,
+        notice: &TaskNotice<'_>,
+    ) -> Result<(), AppError> {
+        let Some(email) = self.find_email(user_id).await? else {
+            info!(
+                "User {} has no email address, skipping notification for task {}",
+                user_id, notice.task_id
+            );
+            return Ok(());
+        };
+
+        let to = email
+            .parse::<Mailbox>()
+            .map_err(|e| AppError::ValidationError(format!("Invalid recipient address: {}", e)))?;
+        let message = Message::builder()
+            .from(mailer.from.clone())
+            .to(to)
+            .subject(format!(
+                "{} {}: {}",
+                notice.kind, notice.status, notice.task_id
+            ))
+            .body(task_notice_body(notice))
+            .map_err(|e| AppError::ServiceError(format!("Failed to build email: {}", e)))?;
+
+        mailer
+            .transport
+            .send(message)
+            .await
+            .map_err(|e| AppError::ExternalServiceError(format!("SMTP error: {}", e)))?;
+
+        info!(
+            "Sent {} notification for task {} to user {}",
+            notice.status, notice.task_id, user_id
+        );
+        Ok(())
+    }
+
+    async fn find_email(&self, user_id: i64) -> Result<Option<String>, AppError> {
+        let mut conn = self
+            .db_pool
+            .get_conn()
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let email: Option<String> = "SELECT email FROM users WHERE id = :id"
+            .with(params! { "id" => user_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        // Placeholder addresses of ORCID users can't receive mail
+        Ok(email.filter(|e| {
+            !e.trim().is_empty() && !e.ends_with(&format!("@{}", ORCID_PLACEHOLDER_EMAIL_DOMAIN))
+        }))
+    }
+}
+
+fn build_mailer(config: &Config, host: &str) -> Result<Mailer, AppError> {
+    let smtp_error = |e: lettre::transport::smtp::Error| {
+        AppError::ServiceError(format!("Invalid SMTP configuration: {}", e))
+    };
+
+    let from = config
+        .smtp_from
+        .as_deref()
+        .ok_or_else(|| {
+            AppError::ServiceError("SMTP_FROM must be set when SMTP_HOST is".to_string())
+        })?
+        .parse::<Mailbox>()
+        .map_err(|e| AppError::ServiceError(format!("Invalid SMTP_FROM address: {}", e)))?;
+
+    let mut builder = match config.smtp_tls.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(smtp_error)?,
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        "starttls" => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host).map_err(smtp_error)?
+        }
+        other => {
+            return Err(AppError::ServiceError(format!(
+                "Invalid SMTP_TLS '{}', expected starttls, tls or none",
+                other
+            )))
+        }
+    };
+    if let Some(port) = config.smtp_port {
+        builder = builder.port(port);
+    }
+    if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    info!("Task notifications enabled via SMTP host {}", host);
+    Ok(Mailer {
+        transport: builder.build(),
+        from,
+    })
+}
+
+fn task_notice_body(notice: &TaskNotice<'_>) -> String {
+    let mut body = format!(
+        "Your task has finished.\n\nTask: {}\nType: {}\nStatus: {}\n",
+        notice.task_id, notice.kind, notice.status
+    );
+    if let Some(did) = notice.did {
+        body.push_str(&format!("DID: {}\n", did));
+    }
+    if let Some(cid) = notice.cid {
+        body.push_str(&format!("CID: {}\n", cid));
+    }
+    if let Some(error) = notice.error {
+        body.push_str(&format!("Error: {}\n", error));
+    }
+    body
+}
@@ -0,0 +1,205 @@
+use crate::config::Config;
+use crate::errors::AppError;
+use crate::services::http_client::HttpClient;
+use crate::services::metrics_service::MetricsService;
+use log::{error, info, warn};
+use reqwest::Url;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Cookie holding the OAuth `state` between the login redirect and the callback
+pub const ORCID_STATE_COOKIE: &str = "orcid_state";
+
+/// Identity of a researcher who signed in with ORCID
+#[derive(Debug, Clone)]
+pub struct OrcidProfile {
+    // ORCID iD, e.g. 0000-0002-1825-0097
+    pub orcid: String,
+    // Public, verified email of the record, preferring the primary one
+    pub email: Option<String>,
+    // Name on the record, unless the researcher keeps it private
+    pub name: Option<String>,
+}
+
+/// Token endpoint response; `/authenticate` tokens carry the iD of the signed-in record
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    orcid: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmailsResponse {
+    #[serde(default)]
+    email: Vec<OrcidEmail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OrcidEmail {
+    email: String,
+    #[serde(default)]
+    verified: bool,
+    #[serde(default)]
+    primary: bool,
+}
+
+/// OAuth2 authorization-code login against ORCID
+pub struct OrcidService {
+    client: HttpClient,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    // ORCID site (authorize and token endpoints) and its public API
+    base_url: String,
+    api_url: String,
+    metrics: Arc<MetricsService>,
+}
+
+impl OrcidService {
+    /// `None` unless the client ID, secret and redirect URI are all configured
+    pub fn from_config(
+        config: &Config,
+        client: &HttpClient,
+        metrics: Arc<MetricsService>,
+    ) -> Option<Self> {
+        let (Some(client_id), Some(client_secret), Some(redirect_uri)) = (
+            &config.orcid_client_id,
+            &config.orcid_client_secret,
+            &config.orcid_redirect_uri,
+        ) else {
+            info!("ORCID client not configured, ORCID login is disabled");
+            return None;
+        };
+
+        let base_url = config.orcid_base_url.trim_end_matches('/').to_string();
+
+        Some(Self {
+            client: client.with_timeout(Duration::from_secs(15)),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            redirect_uri: redirect_uri.clone(),
+            // https://orcid.org -> https://pub.orcid.org, and likewise for the sandbox
+            api_url: base_url.replacen("://", "://pub.", 1),
+            base_url,
+            metrics,
+        })
+    }
+
+    /// Whether the callback is served over HTTPS, so the state cookie can be `Secure`
+    pub fn redirect_is_https(&self) -> bool {
+        self.redirect_uri.starts_with("https://")
+    }
+
+    /// Path of the callback, which the state cookie is scoped to, so it works under both
+    /// `/api/v1` and the unversioned prefix
+    pub fn callback_path(&self) -> String {
+        Url::parse(&self.redirect_uri)
+            .map(|url| url.path().to_string())
+            .unwrap_or_else(|_| "/".to_string())
+    }
+
+    /// ORCID authorization page to send the browser to, carrying `state`
+    pub fn authorize_url(&self, state: &str) -> Result<String, AppError> {
+        Url::parse_with_params(
+            &format!("{}/oauth/authorize", self.base_url),
+            &[
+                ("client_id", self.client_id.as_str()),
+                ("response_type", "code"),
+                ("scope", "/authenticate"),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("state", state),
+            ],
+        )
+        .map(String::from)
+        .map_err(|e| AppError::ServiceError(format!("Invalid ORCID_BASE_URL: {}", e)))
+    }
+
+    /// Exchange an authorization code for the signed-in researcher's ORCID iD and email
+    pub async fn exchange_code(&self, code: &str) -> Result<OrcidProfile, AppError> {
+        let response = self
+            .client
+            .post(format!("{}/oauth/token", self.base_url))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_uri.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(|e| self.upstream_error(format!("ORCID token request failed: {}", e)))?;
+
+        let status = response.status();
+        if status.is_client_error() {
+            // Expired, reused or forged codes
+            let body = response.text().await.unwrap_or_default();
+            info!("ORCID rejected authorization code ({}): {}", status, body);
+            return Err(AppError::AuthError(
+                "ORCID authorization code is invalid or expired".to_string(),
+            ));
+        }
+        if !status.is_success() {
+            return Err(self.upstream_error(format!("ORCID token endpoint returned {}", status)));
+        }
+
+        let token: TokenResponse = response.json().await.map_err(|e| {
+            error!("Unexpected ORCID token response: {}", e);
+            AppError::DeserializationError
+        })?;
+
+        let email = match self.fetch_email(&token.orcid, &token.access_token).await {
+            Ok(email) => email,
+            Err(e) => {
+                warn!(
+                    "Failed to read emails of ORCID record {}: {}",
+                    token.orcid, e
+                );
+                None
+            }
+        };
+
+        Ok(OrcidProfile {
+            orcid: token.orcid,
+            email,
+            name: token.name.filter(|name| !name.trim().is_empty()),
+        })
+    }
+
+    /// Verified public email of a record, the primary one if it is public
+    async fn fetch_email(
+        &self,
+        orcid: &str,
+        access_token: &str,
+    ) -> Result<Option<String>, AppError> {
+        let response = self
+            .client
+            .get(format!("{}/v3.0/{}/email", self.api_url, orcid))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| self.upstream_error(format!("ORCID API request failed: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(self.upstream_error(format!("ORCID API returned {}", response.status())));
+        }
+
+        let emails: EmailsResponse = response
+            .json()
+            .await
+            .map_err(|_| AppError::DeserializationError)?;
+        let mut verified: Vec<OrcidEmail> =
+            emails.email.into_iter().filter(|e| e.verified).collect();
+        verified.sort_by_key(|e| !e.primary);
+        Ok(verified.into_iter().next().map(|e| e.email))
+    }
+
+    fn upstream_error(&self, message: String) -> AppError {
+        self.metrics.record_external_error("orcid");
+        AppError::ExternalServiceError(message)
+    }
+}
@@ -0,0 +1,498 @@
+use crate::services::bioagents_service::ExtractedMetadata;
+use lopdf::content::Content;
+use lopdf::{Document, Encoding, Error as PdfError, LoadOptions, Object};
+use std::collections::{BTreeMap, HashMap};
+
+/// Decoded size at which a stream is rejected, so a small upload can't inflate without bound
+const MAX_DECODED_STREAM_BYTES: usize = 16 * 1024 * 1024;
+
+/// Characters of first-page text kept as the abstract
+const MAX_ABSTRACT_CHARS: usize = 2000;
+
+/// Why local extraction produced nothing usable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractionUnavailable {
+    // No PDF header; the upload is something else
+    NotPdf,
+    Encrypted,
+    // Neither document metadata nor readable first-page text, e.g. a scanned paper
+    NoText,
+}
+
+impl ExtractionUnavailable {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::NotPdf => "File is not a PDF",
+            Self::Encrypted => "PDF is encrypted",
+            Self::NoText => "PDF has no extractable text; it may consist of scanned images",
+        }
+    }
+}
+
+/// Title, authors, DOI and, where the first page has them, abstract and keywords of a PDF,
+/// read locally without BioAgents.
+///
+/// Document metadata (the Info dictionary, then XMP) is preferred; the first page's text
+/// fills in what it lacks. `biological_entities` is always empty.
+pub fn extract_basic_metadata(pdf: &[u8]) -> Result<ExtractedMetadata, ExtractionUnavailable> {
+    if !pdf[..pdf.len().min(1024)]
+        .windows(5)
+        .any(|window| window == b"%PDF-")
+    {
+        return Err(ExtractionUnavailable::NotPdf);
+    }
+    let options = LoadOptions {
+        max_decompressed_size: Some(MAX_DECODED_STREAM_BYTES),
+        ..Default::default()
+    };
+    let document = match Document::load_mem_with_options(pdf, options) {
+        Ok(document) => document,
+        Err(
+            PdfError::Decryption(_)
+            | PdfError::InvalidPassword
+            | PdfError::UnsupportedSecurityHandler(_),
+        ) => return Err(ExtractionUnavailable::Encrypted),
+        Err(_) => return Err(ExtractionUnavailable::NoText),
+    };
+    // Still encrypted after loading: the empty user password didn't open it
+    if document.trailer.get(b"Encrypt").is_ok() {
+        return Err(ExtractionUnavailable::Encrypted);
+    }
+
+    let info = info(&document);
+    let xmp = xmp(&document).unwrap_or_default();
+    let text = first_page_text(&document).filter(|text| is_readable(text));
+    let lines: Vec<&str> = text
+        .as_deref()
+        .map(|text| {
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let title = info
+        .get("Title")
+        .filter(|title| is_plausible_title(title))
+        .cloned()
+        .or_else(|| first_xmp_item(&xmp, "dc:title"))
+        .or_else(|| {
+            lines
+                .iter()
+                .find(|line| is_plausible_title(line))
+                .map(|line| line.to_string())
+        });
+    let Some(title) = title else {
+        return Err(ExtractionUnavailable::NoText);
+    };
+
+    let authors = match info.get("Author") {
+        Some(author) => split_authors(author),
+        None => xmp_items(&xmp, "dc:creator"),
+    };
+
+    let keywords = match info.get("Keywords") {
+        Some(keywords) => split_list(keywords),
+        None => lines
+            .iter()
+            .find_map(|line| strip_heading(line, &["keywords", "key words"]))
+            .map(split_list)
+            .unwrap_or_default(),
+    };
+
+    let doi = first_xmp_item(&xmp, "prism:doi")
+        .and_then(|doi| find_doi(&doi))
+        .or_else(|| info.get("doi").and_then(|doi| find_doi(doi)))
+        .or_else(|| info.get("Subject").and_then(|subject| find_doi(subject)))
+        .or_else(|| text.as_deref().and_then(find_doi));
+
+    Ok(ExtractedMetadata {
+        title,
+        authors,
+        abstract_text: abstract_from(&lines).unwrap_or_default(),
+        keywords,
+        publication_date: first_xmp_item(&xmp, "prism:publicationDate")
+            .or_else(|| first_xmp_item(&xmp, "prism:coverDate")),
+        journal: first_xmp_item(&xmp, "prism:publicationName"),
+        doi,
+        biological_entities: Vec::new(),
+        keywords_auto_generated: false,
+        needs_review: false,
+    })
+}
+
+/// Text entries of the Info dictionary, e.g. `Title` and `Author`
+fn info(document: &Document) -> HashMap<&'static str, String> {
+    let Some(info) = document
+        .trailer
+        .get(b"Info")
+        .and_then(|info| document.dereference(info))
+        .and_then(|(_, info)| info.as_dict())
+        .ok()
+    else {
+        return HashMap::new();
+    };
+
+    ["Title", "Author", "Subject", "Keywords", "doi"]
+        .into_iter()
+        .filter_map(|key| {
+            let value = info.get_deref(key.as_bytes(), document).ok()?;
+            let text = lopdf::decode_text_string(value).ok()?;
+            let text = text.trim();
+            (!text.is_empty()).then(|| (key, text.to_string()))
+        })
+        .collect()
+}
+
+/// XMP metadata packet of the document catalog
+fn xmp(document: &Document) -> Option<String> {
+    let metadata = document
+        .catalog()
+        .ok()?
+        .get_deref(b"Metadata", document)
+        .ok()?
+        .as_stream()
+        .ok()?;
+    let packet = metadata
+        .decompressed_content_with_limit(MAX_DECODED_STREAM_BYTES)
+        .unwrap_or_else(|_| metadata.content.clone());
+    Some(String::from_utf8_lossy(&packet).into_owned())
+}
+
+/// Text of the first page. Lines break where the text position moves down.
+fn first_page_text(document: &Document) -> Option<String> {
+    let page_id = *document.get_pages().values().next()?;
+    let content = document
+        .get_page_content_with_limit(page_id, MAX_DECODED_STREAM_BYTES)
+        .ok()?;
+    let operations = Content::decode(&content).ok()?.operations;
+    let encodings: BTreeMap<Vec<u8>, Encoding> = document
+        .get_page_fonts(page_id)
+        .ok()?
+        .into_iter()
+        .filter_map(|(name, font)| Some((name, font.get_font_encoding(document).ok()?)))
+        .collect();
+
+    let mut text = String::new();
+    let mut encoding = None;
+    for operation in &operations {
+        let operands = operation.operands.as_slice();
+        match operation.operator.as_str() {
+            "Tf" => {
+                encoding = operands
+                    .first()
+                    .and_then(|font| font.as_name().ok())
+                    .and_then(|font| encodings.get(font));
+            }
+            "Tj" => push_string(&mut text, encoding, operands.last()),
+            "'" | "\"" => {
+                text.push('\n');
+                push_string(&mut text, encoding, operands.last());
+            }
+            "TJ" => {
+                let items = operands.last().and_then(|items| items.as_array().ok());
+                for item in items.into_iter().flatten() {
+                    match item.as_float() {
+                        // Large negative adjustments are word gaps
+                        Ok(adjustment) if adjustment < -200.0 => text.push(' '),
+                        Ok(_) => {}
+                        Err(_) => push_string(&mut text, encoding, Some(item)),
+                    }
+                }
+            }
+            "Td" | "TD" => match operands.get(1).and_then(|ty| ty.as_float().ok()) {
+                Some(ty) if ty != 0.0 => text.push('\n'),
+                _ => text.push(' '),
+            },
+            "T*" | "Tm" | "ET" => text.push('\n'),
+            _ => {}
+        }
+    }
+    Some(text)
+}
+
+/// Append a shown string, decoded with the current font's encoding; fonts without one
+/// fall back to Latin-1, which standard encodings agree with for readable characters
+fn push_string(text: &mut String, encoding: Option<&Encoding>, string: Option<&Object>) {
+    let Some(bytes) = string.and_then(|string| string.as_str().ok()) else {
+        return;
+    };
+    match encoding.and_then(|encoding| Document::decode_text(encoding, bytes).ok()) {
+        Some(decoded) => text.push_str(&decoded),
+        None => text.extend(bytes.iter().map(|&b| b as char)),
+    }
+}
+
+/// Whether extracted text is mostly letters, digits, whitespace and punctuation; text of
+/// fonts with custom encodings comes out as unrelated symbols
+fn is_readable(text: &str) -> bool {
+    let total = text.chars().filter(|c| !c.is_whitespace()).count();
+    let readable = text
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_ascii_punctuation())
+        .count();
+    total >= 20 && readable * 10 >= total * 8
+}
+
+/// Candidate title: a few words, and not a file name as left in the metadata by some tools
+fn is_plausible_title(title: &str) -> bool {
+    let title = title.trim();
+    let lower = title.to_lowercase();
+    (10..=300).contains(&title.chars().count())
+        && title.split_whitespace().count() >= 2
+        && !lower.starts_with("microsoft word")
+        && ![".pdf", ".doc", ".docx", ".tex", ".dvi"]
+            .iter()
+            .any(|ext| lower.ends_with(ext))
+}
+
+/// Authors from an Info `Author` entry: separated by semicolons when present (names may be
+/// written "Last, First"), else by commas and "and"
+fn split_authors(authors: &str) -> Vec<String> {
+    let parts: Vec<&str> = if authors.contains(';') {
+        authors.split(';').collect()
+    } else {
+        authors
+            .split(',')
+            .flat_map(|part| part.split(" and "))
+            .collect()
+    };
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|author| !author.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split([',', ';', '·', '•'])
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Rest of `line` after one of `headings` (case-insensitive) and a separator
+fn strip_heading<'a>(line: &'a str, headings: &[&str]) -> Option<&'a str> {
+    headings.iter().find_map(|heading| {
+        let prefix = line.get(..heading.len())?;
+        if !prefix.eq_ignore_ascii_case(heading) {
+            return None;
+        }
+        Some(
+            line[heading.len()..]
+                .trim_start_matches([':', '.', '-', '—', ' '])
+                .trim(),
+        )
+    })
+}
+
+/// Text after an "Abstract" heading up to the keywords or introduction
+fn abstract_from(lines: &[&str]) -> Option<String> {
+    let start = lines
+        .iter()
+        .position(|line| strip_heading(line, &["abstract", "summary"]).is_some())?;
+    let mut parts = vec![strip_heading(lines[start], &["abstract", "summary"])?];
+    for line in &lines[start + 1..] {
+        let lower = line.to_lowercase();
+        if [
+            "keywords",
+            "key words",
+            "introduction",
+            "1 introduction",
+            "1. introduction",
+        ]
+        .iter()
+        .any(|heading| lower.starts_with(heading))
+        {
+            break;
+        }
+        parts.push(line);
+    }
+
+    let text = parts
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let text: String = text.chars().take(MAX_ABSTRACT_CHARS).collect();
+    (!text.is_empty()).then_some(text)
+}
+
+/// First DOI (`10.<registrant>/<suffix>`) in `text`
+fn find_doi(text: &str) -> Option<String> {
+    let mut start = 0;
+    while let Some(at) = text[start..].find("10.").map(|i| start + i) {
+        start = at + 3;
+        if text[..at]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+        {
+            continue;
+        }
+        let rest = &text[at + 3..];
+        let registrant = rest.chars().take_while(char::is_ascii_digit).count();
+        if !(4..=9).contains(&registrant) || !rest[registrant..].starts_with('/') {
+            continue;
+        }
+        let suffix: String = rest[registrant + 1..]
+            .chars()
+            .take_while(|c| !c.is_whitespace() && !matches!(c, '"' | '<' | '>'))
+            .collect();
+        let suffix = suffix.trim_end_matches(['.', ',', ';', ')', ']']);
+        if !suffix.is_empty() {
+            return Some(format!("10.{}/{}", &rest[..registrant], suffix));
+        }
+    }
+    None
+}
+
+/// Texts of the `rdf:li` items (or the plain content) of an XMP element
+fn xmp_items(xmp: &str, element: &str) -> Vec<String> {
+    let open = format!("<{}", element);
+    let close = format!("</{}>", element);
+    let Some(start) = xmp.find(&open) else {
+        return Vec::new();
+    };
+    let Some(content_start) = xmp[start..].find('>').map(|i| start + i + 1) else {
+        return Vec::new();
+    };
+    let Some(end) = xmp[content_start..].find(&close).map(|i| content_start + i) else {
+        return Vec::new();
+    };
+    let content = &xmp[content_start..end];
+
+    let items: Vec<String> = if content.contains("<rdf:li") {
+        content
+            .split("<rdf:li")
+            .skip(1)
+            .filter_map(|item| {
+                let text = &item[item.find('>')? + 1..];
+                Some(text[..text.find("</rdf:li>")?].to_string())
+            })
+            .collect()
+    } else {
+        vec![content.to_string()]
+    };
+    items
+        .iter()
+        .map(|item| unescape_xml(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn first_xmp_item(xmp: &str, element: &str) -> Option<String> {
+    xmp_items(xmp, element).into_iter().next()
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// Minimal PDF with one page showing `content`, plus `info` and `trailer_extra` entries
+    fn build_pdf(content: &[u8], compress: bool, info: &str, trailer_extra: &str) -> Vec<u8> {
+        let (stream, filter) = if compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(content).unwrap();
+            (encoder.finish().unwrap(), " /Filter /FlateDecode")
+        } else {
+            (content.to_vec(), "")
+        };
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        pdf.extend_from_slice(b"1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n");
+        pdf.extend_from_slice(b"2 0 obj\n<< /Type /Pages /Kids [3 0 R] /Count 1 >>\nendobj\n");
+        pdf.extend_from_slice(
+            b"3 0 obj\n<< /Type /Page /Parent 2 0 R /Resources << /Font << /F1 << /Type /Font >> >> >> /Contents 4 0 R >>\nendobj\n",
+        );
+        pdf.extend_from_slice(
+            format!(
+                "4 0 obj\n<< /Length {}{} >>\nstream\n",
+                stream.len(),
+                filter
+            )
+            .as_bytes(),
+        );
+        pdf.extend_from_slice(&stream);
+        pdf.extend_from_slice(b"\nendstream\nendobj\n");
+        pdf.extend_from_slice(format!("5 0 obj\n<< {} >>\nendobj\n", info).as_bytes());
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size 6 /Root 1 0 R /Info 5 0 R {} >>\n%%EOF\n",
+                trailer_extra
+            )
+            .as_bytes(),
+        );
+        pdf
+    }
+
+    const PAGE: &[u8] = b"BT /F1 18 Tf 72 720 Td (Gene Regulation in Yeast Cells) Tj \
+        /F1 10 Tf 0 -30 Td (Abstract: We describe how genes are regulated ) Tj \
+        0 -12 Td [(in yeast under) -300 (stress.)] TJ \
+        0 -12 Td (Keywords: yeast; stress; regulation) Tj \
+        0 -12 Td (https://doi.org/10.1234/yeast.2023.001.) Tj ET";
+
+    #[test]
+    fn test_reads_info_dictionary_and_first_page() {
+        let pdf = build_pdf(
+            PAGE,
+            true,
+            "/Title (Gene Regulation in Yeast) /Author (Ada Lovelace; Grace Hopper)",
+            "",
+        );
+        let metadata = extract_basic_metadata(&pdf).unwrap();
+
+        assert_eq!(metadata.title, "Gene Regulation in Yeast");
+        assert_eq!(metadata.authors, vec!["Ada Lovelace", "Grace Hopper"]);
+        assert_eq!(metadata.doi.as_deref(), Some("10.1234/yeast.2023.001"));
+        assert_eq!(metadata.keywords, vec!["yeast", "stress", "regulation"]);
+        assert_eq!(
+            metadata.abstract_text,
+            "We describe how genes are regulated in yeast under stress."
+        );
+        assert!(metadata.biological_entities.is_empty());
+    }
+
+    #[test]
+    fn test_falls_back_to_page_text_without_metadata() {
+        let pdf = build_pdf(PAGE, false, "/Producer (test)", "");
+        let metadata = extract_basic_metadata(&pdf).unwrap();
+
+        assert_eq!(metadata.title, "Gene Regulation in Yeast Cells");
+        assert!(metadata.authors.is_empty());
+    }
+
+    #[test]
+    fn test_reports_encrypted_and_image_only_pdfs_as_unavailable() {
+        let encrypted = build_pdf(PAGE, true, "", "/Encrypt 6 0 R");
+        assert_eq!(
+            extract_basic_metadata(&encrypted).unwrap_err(),
+            ExtractionUnavailable::Encrypted
+        );
+
+        let scanned = build_pdf(b"q 612 0 0 792 0 0 cm /Im1 Do Q", true, "", "");
+        assert_eq!(
+            extract_basic_metadata(&scanned).unwrap_err(),
+            ExtractionUnavailable::NoText
+        );
+
+        assert_eq!(
+            extract_basic_metadata(b"not a pdf").unwrap_err(),
+            ExtractionUnavailable::NotPdf
+        );
+    }
+}
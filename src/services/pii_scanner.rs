@@ -0,0 +1,380 @@
+use crate::errors::AppError;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Replaces each finding when paper text is redacted
+pub const REDACTION_MARKER: &str = "[REDACTED]";
+
+/// Kinds of personal data a scanner can recognise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PiiKind {
+    Email,
+    PhoneNumber,
+    // US social security number
+    NationalId,
+    MedicalRecordNumber,
+    DateOfBirth,
+}
+
+impl PiiKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PiiKind::Email => "email",
+            PiiKind::PhoneNumber => "phone_number",
+            PiiKind::NationalId => "national_id",
+            PiiKind::MedicalRecordNumber => "medical_record_number",
+            PiiKind::DateOfBirth => "date_of_birth",
+        }
+    }
+}
+
+/// Possible personal data found in a text, as the byte range it occupies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PiiFinding {
+    pub kind: PiiKind,
+    pub range: Range<usize>,
+}
+
+/// Finds personal data in paper text before it is stored or indexed.
+///
+/// Implementations must return non-overlapping ranges on `char` boundaries, sorted by start.
+pub trait PiiScanner: Send + Sync {
+    fn scan_for_pii(&self, text: &str) -> Vec<PiiFinding>;
+}
+
+/// What happens to a paper whose text has more findings than the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiiAction {
+    // Replace the findings with `REDACTION_MARKER`
+    Redact,
+    // Store the text as is, but keep the paper out of search until an admin approves it
+    Flag,
+}
+
+impl PiiAction {
+    /// Parse `redact` or `flag`, in any case
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "redact" => Ok(PiiAction::Redact),
+            "flag" => Ok(PiiAction::Flag),
+            _ => Err(AppError::ValidationError(format!(
+                "Unknown PII action '{}', expected redact or flag",
+                value
+            ))),
+        }
+    }
+}
+
+/// When and how papers with personal data are handled
+#[derive(Debug, Clone, Copy)]
+pub struct PiiPolicy {
+    // Findings tolerated per paper before `action` applies
+    pub threshold: usize,
+    pub action: PiiAction,
+}
+
+/// Number of findings of each kind, for logging without the values themselves
+pub fn summarize(findings: &[PiiFinding]) -> String {
+    let mut counts: BTreeMap<PiiKind, usize> = BTreeMap::new();
+    for finding in findings {
+        *counts.entry(finding.kind).or_default() += 1;
+    }
+    counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `text` with every finding replaced by `REDACTION_MARKER`
+pub fn redact(text: &str, findings: &[PiiFinding]) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut last = 0;
+    for finding in findings {
+        redacted.push_str(&text[last..finding.range.start]);
+        redacted.push_str(REDACTION_MARKER);
+        last = finding.range.end;
+    }
+    redacted.push_str(&text[last..]);
+    redacted
+}
+
+/// Labels introducing a medical record number, lowercase
+const MRN_LABELS: &[&str] = &[
+    "mrn",
+    "medical record number",
+    "medical record no",
+    "patient id",
+    "patient number",
+    "patient no",
+];
+
+/// Labels introducing a date of birth, lowercase
+const DOB_LABELS: &[&str] = &["dob", "d.o.b", "date of birth", "born on"];
+
+/// Default scanner matching common patterns: email addresses, phone numbers, US social
+/// security numbers, and medical record numbers and dates of birth that follow a label
+/// such as `MRN:` or `DOB`. It is tuned to miss the numbers that fill scientific text
+/// (statistics, accessions, DOIs), so it will also miss unusually formatted identifiers.
+#[derive(Debug, Default)]
+pub struct PatternPiiScanner;
+
+impl PiiScanner for PatternPiiScanner {
+    fn scan_for_pii(&self, text: &str) -> Vec<PiiFinding> {
+        let mut findings = Vec::new();
+
+        for (start, word) in words(text) {
+            let trimmed = word.trim_matches(is_wrapping_punctuation);
+            let offset =
+                start + (word.len() - word.trim_start_matches(is_wrapping_punctuation).len());
+            let range = offset..offset + trimmed.len();
+            if is_email(trimmed) {
+                findings.push(PiiFinding {
+                    kind: PiiKind::Email,
+                    range,
+                });
+            } else if is_ssn(trimmed) {
+                findings.push(PiiFinding {
+                    kind: PiiKind::NationalId,
+                    range,
+                });
+            }
+        }
+        findings.extend(phone_numbers(text));
+        findings.extend(labelled_values(
+            text,
+            MRN_LABELS,
+            PiiKind::MedicalRecordNumber,
+            is_record_number,
+        ));
+        findings.extend(labelled_values(
+            text,
+            DOB_LABELS,
+            PiiKind::DateOfBirth,
+            is_numeric_date,
+        ));
+
+        // Keep the first of overlapping findings
+        findings.sort_by_key(|finding| (finding.range.start, std::cmp::Reverse(finding.range.end)));
+        let mut merged: Vec<PiiFinding> = Vec::with_capacity(findings.len());
+        for finding in findings {
+            if merged
+                .last()
+                .is_none_or(|last| finding.range.start >= last.range.end)
+            {
+                merged.push(finding);
+            }
+        }
+        merged
+    }
+}
+
+/// Whitespace-separated words of `text` with their byte offsets
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split_whitespace()
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+fn is_wrapping_punctuation(c: char) -> bool {
+    matches!(
+        c,
+        '(' | ')' | '[' | ']' | '<' | '>' | '{' | '}' | ',' | ';' | ':' | '.' | '"' | '\''
+    )
+}
+
+fn is_email(word: &str) -> bool {
+    let Some((local, domain)) = word.split_once('@') else {
+        return false;
+    };
+    let labels: Vec<&str> = domain.split('.').collect();
+    !local.is_empty()
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-'))
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+/// `123-45-6789`
+fn is_ssn(word: &str) -> bool {
+    let groups: Vec<&str> = word.split('-').collect();
+    groups.len() == 3
+        && groups
+            .iter()
+            .zip([3, 2, 4])
+            .all(|(group, len)| group.len() == len && group.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Runs like `+44 20 7946 0958`, `(555) 123-4567` or `555.123.4567`: 10 to 15 digits in
+/// 2 to 5 groups, the last of at least 3 digits, not attached to a word
+fn phone_numbers(text: &str) -> Vec<PiiFinding> {
+    let bytes = text.as_bytes();
+    let is_phone_byte =
+        |b: u8| b.is_ascii_digit() || matches!(b, b'+' | b'(' | b')' | b'-' | b'.' | b' ');
+    let mut findings = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let starts_run = matches!(bytes[i], b'0'..=b'9' | b'+' | b'(')
+            && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric());
+        if !starts_run {
+            i += 1;
+            continue;
+        }
+        let mut end = i;
+        while end < bytes.len() && is_phone_byte(bytes[end]) {
+            end += 1;
+        }
+        let next = end;
+        // A trailing separator or sentence end isn't part of the number
+        while end > i && !bytes[end - 1].is_ascii_digit() {
+            end -= 1;
+        }
+        let attached = next < bytes.len() && bytes[next].is_ascii_alphanumeric() && end == next;
+        let run = &text[i..end];
+        let groups: Vec<&str> = run
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|group| !group.is_empty())
+            .collect();
+        let digits: usize = groups.iter().map(|group| group.len()).sum();
+        if !attached
+            && (10..=15).contains(&digits)
+            && (2..=5).contains(&groups.len())
+            && groups.last().is_some_and(|group| group.len() >= 3)
+            && groups.iter().all(|group| group.len() <= 4)
+        {
+            findings.push(PiiFinding {
+                kind: PiiKind::PhoneNumber,
+                range: i..end,
+            });
+        }
+        i = next.max(i + 1);
+    }
+    findings
+}
+
+/// The word following any of `labels` (and an optional `:`, `#`, `no.` or `is`), when it
+/// passes `accept`
+fn labelled_values(
+    text: &str,
+    labels: &[&str],
+    kind: PiiKind,
+    accept: fn(&str) -> bool,
+) -> Vec<PiiFinding> {
+    // ASCII lowercasing keeps byte offsets aligned with `text`
+    let lower = text.to_ascii_lowercase();
+    let mut findings = Vec::new();
+    for label in labels {
+        for (at, _) in lower.match_indices(label) {
+            let before_ok = lower[..at]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !c.is_alphanumeric());
+            let after = at + label.len();
+            if !before_ok
+                || lower[after..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_alphanumeric())
+            {
+                continue;
+            }
+
+            let mut rest = after;
+            for (start, word) in words(&text[after..]).take(3) {
+                let word_lower = word.to_ascii_lowercase();
+                let filler = word.chars().all(|c| matches!(c, ':' | '#' | '-' | '='))
+                    || matches!(
+                        word_lower.as_str(),
+                        "no" | "no." | "number" | "is" | "was" | "#:"
+                    );
+                if !filler {
+                    rest = after + start;
+                    break;
+                }
+            }
+            let Some((start, word)) = words(&text[rest..]).next() else {
+                continue;
+            };
+            let leading = word.len() - word.trim_start_matches([':', '#', '(']).len();
+            let value = word[leading..].trim_end_matches([',', ';', '.', ')']);
+            if accept(value) {
+                let begin = rest + start + leading;
+                findings.push(PiiFinding {
+                    kind,
+                    range: begin..begin + value.len(),
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// At least 4 digits, made only of letters, digits and `-`
+fn is_record_number(value: &str) -> bool {
+    value.bytes().filter(u8::is_ascii_digit).count() >= 4
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+/// `12/05/1984`, `1984-05-12` or `12.05.84`
+fn is_numeric_date(value: &str) -> bool {
+    let groups: Vec<&str> = value.split(['/', '-', '.']).collect();
+    groups.len() == 3
+        && groups.iter().all(|group| {
+            (1..=4).contains(&group.len()) && group.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(text: &str) -> Vec<(PiiKind, &str)> {
+        PatternPiiScanner
+            .scan_for_pii(text)
+            .into_iter()
+            .map(|finding| (finding.kind, &text[finding.range]))
+            .collect()
+    }
+
+    #[test]
+    fn test_pattern_scanner_finds_identifiers() {
+        let text = "Patient (MRN: 00482913, DOB 12/05/1984) was reached at +1 555-123-4567 \
+            or jane.doe@example.org; SSN 123-45-6789.";
+        assert_eq!(
+            kinds(text),
+            vec![
+                (PiiKind::MedicalRecordNumber, "00482913"),
+                (PiiKind::DateOfBirth, "12/05/1984"),
+                (PiiKind::PhoneNumber, "+1 555-123-4567"),
+                (PiiKind::Email, "jane.doe@example.org"),
+                (PiiKind::NationalId, "123-45-6789"),
+            ]
+        );
+
+        let findings = PatternPiiScanner.scan_for_pii(text);
+        assert_eq!(
+            redact(text, &findings),
+            "Patient (MRN: [REDACTED], DOB [REDACTED]) was reached at [REDACTED] \
+            or [REDACTED]; SSN [REDACTED]."
+        );
+        assert_eq!(
+            summarize(&findings),
+            "1 email, 1 phone_number, 1 national_id, 1 medical_record_number, 1 date_of_birth"
+        );
+    }
+
+    #[test]
+    fn test_pattern_scanner_ignores_scientific_numbers() {
+        let text = "Expression rose 2.5-fold (p = 0.0001, n = 1234) across 0.12 0.34 0.56 \
+            0.78 0.90 samples; see doi 10.1016/j.cell.2020.01.001, GEO GSE123456, \
+            chr7:55019017-55211628 and the 2019-2020 cohort of Adobe users.";
+        assert!(kinds(text).is_empty());
+    }
+}
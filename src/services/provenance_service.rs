@@ -0,0 +1,309 @@
+use crate::errors::AppError;
+use crate::models::did::DIDDocument;
+use crate::models::file_metadata::ResearchPaperMetadata;
+use crate::services::dataverse_service::DataverseService;
+use crate::services::did_service::DIDService;
+use crate::services::ipfs_cluster::ReplicationStatus;
+use crate::services::ipfs_service::IPFSService;
+use crate::services::research_paper_service::ResearchPaperService;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Outcome of checking one link of a provenance chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkStatus {
+    // The linked content or dataset was found
+    Verified,
+    // It is gone: missing from IPFS, unknown to Dataverse, or deaccessioned
+    Broken,
+    // The check itself failed, e.g. Dataverse couldn't be reached
+    Unknown,
+}
+
+/// Health of one link, with the reason when it isn't verified
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct LinkCheck {
+    pub status: LinkStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    // Cluster copies of linked IPFS content, in cluster mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replication: Option<ReplicationStatus>,
+}
+
+impl LinkCheck {
+    fn verified() -> Self {
+        Self {
+            status: LinkStatus::Verified,
+            detail: None,
+            replication: None,
+        }
+    }
+
+    fn failed(status: LinkStatus, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: Some(detail.into()),
+            replication: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ProvenanceDocument {
+    pub cid: String,
+    pub document: DIDDocument,
+    // The document is still retrievable from IPFS at `cid`
+    pub content: LinkCheck,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ProvenancePaper {
+    pub metadata: ResearchPaperMetadata,
+    // The paper file is still retrievable from IPFS
+    pub content: LinkCheck,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ProvenanceDataset {
+    pub persistent_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    // Dataverse `versionState` of the latest version: DRAFT, RELEASED or DEACCESSIONED
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_state: Option<String>,
+    // The DOI still resolves to a dataset that wasn't deaccessioned
+    pub link: LinkCheck,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ProvenanceKnowledgeGraph {
+    pub cid: String,
+    // The graph is still retrievable from IPFS
+    pub content: LinkCheck,
+}
+
+/// Paper → DID → Dataverse dataset → knowledge graph, with the health of each link. Links
+/// the DID doesn't have are absent.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Provenance {
+    pub did: String,
+    pub document: ProvenanceDocument,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paper: Option<ProvenancePaper>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<ProvenanceDataset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub knowledge_graph: Option<ProvenanceKnowledgeGraph>,
+    // Every present link is verified
+    pub verified: bool,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl Provenance {
+    fn links(&self) -> impl Iterator<Item = &LinkCheck> {
+        std::iter::once(&self.document.content)
+            .chain(self.paper.as_ref().map(|paper| &paper.content))
+            .chain(self.dataset.as_ref().map(|dataset| &dataset.link))
+            .chain(self.knowledge_graph.as_ref().map(|graph| &graph.content))
+    }
+}
+
+/// Assembles a DID's provenance from the DID, paper, Dataverse and IPFS services, checking
+/// each link as it goes
+pub struct ProvenanceService {
+    did_service: Arc<DIDService>,
+    research_paper_service: Arc<ResearchPaperService>,
+    dataverse_service: Arc<DataverseService>,
+    ipfs_service: Arc<IPFSService>,
+}
+
+impl ProvenanceService {
+    pub fn new(
+        did_service: Arc<DIDService>,
+        research_paper_service: Arc<ResearchPaperService>,
+        dataverse_service: Arc<DataverseService>,
+        ipfs_service: Arc<IPFSService>,
+    ) -> Self {
+        Self {
+            did_service,
+            research_paper_service,
+            dataverse_service,
+            ipfs_service,
+        }
+    }
+
+    /// The provenance of `did_id`, with embargoed fields stripped from the document unless
+    /// `requester` owns it. Fails only if the DID itself can't be read; broken links are
+    /// reported in the result.
+    pub async fn get_provenance(
+        &self,
+        did_id: &str,
+        requester: Option<i64>,
+    ) -> Result<Provenance, AppError> {
+        let (document, cid) = self.did_service.get_did_with_cid(did_id).await?;
+        let document = self.did_service.apply_embargo(document, requester).await?;
+        let persistent_id = self.did_service.get_dataverse_doi(did_id).await?;
+
+        let paper = match self
+            .research_paper_service
+            .get_paper_metadata_by_did(did_id)
+            .await
+        {
+            Ok(paper) => Some(paper),
+            Err(AppError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let knowledge_graph_cid = paper
+            .as_ref()
+            .and_then(|paper| paper.knowledge_graph_cid.clone());
+
+        let (document_content, paper_content, dataset, graph_content) = futures::join!(
+            self.check_content(&cid),
+            async {
+                match &paper {
+                    Some(paper) => Some(self.check_content(&paper.cid).await),
+                    None => None,
+                }
+            },
+            async {
+                match persistent_id {
+                    Some(persistent_id) => Some(self.check_dataset(persistent_id).await),
+                    None => None,
+                }
+            },
+            async {
+                match &knowledge_graph_cid {
+                    Some(graph_cid) => Some(self.check_content(graph_cid).await),
+                    None => None,
+                }
+            },
+        );
+
+        let mut provenance = Provenance {
+            did: did_id.to_string(),
+            document: ProvenanceDocument {
+                cid,
+                document,
+                content: document_content,
+            },
+            paper: paper
+                .zip(paper_content)
+                .map(|(metadata, content)| ProvenancePaper { metadata, content }),
+            dataset,
+            knowledge_graph: knowledge_graph_cid
+                .zip(graph_content)
+                .map(|(cid, content)| ProvenanceKnowledgeGraph { cid, content }),
+            verified: false,
+            checked_at: Utc::now(),
+        };
+        let verified = provenance
+            .links()
+            .all(|link| link.status == LinkStatus::Verified);
+        provenance.verified = verified;
+
+        info!(
+            "Checked provenance of {}: {}",
+            did_id,
+            if provenance.verified {
+                "all links verified"
+            } else {
+                "some links unverified"
+            }
+        );
+
+        Ok(provenance)
+    }
+
+    /// Whether `cid`'s root block can still be retrieved, and how many cluster peers pin it
+    async fn check_content(&self, cid: &str) -> LinkCheck {
+        let (stat, replication) = futures::join!(
+            self.ipfs_service.stat(cid),
+            self.ipfs_service.replication_status(cid)
+        );
+        let mut check = match stat {
+            Ok(_) => LinkCheck::verified(),
+            Err(e) => LinkCheck::failed(LinkStatus::Broken, e.to_string()),
+        };
+        check.replication = replication.unwrap_or_else(|e| {
+            warn!("Could not check replication of {}: {}", cid, e);
+            None
+        });
+        check
+    }
+
+    async fn check_dataset(&self, persistent_id: String) -> ProvenanceDataset {
+        match self
+            .dataverse_service
+            .get_dataset_metadata(&persistent_id)
+            .await
+        {
+            Ok(metadata) => dataset_provenance(persistent_id, &metadata),
+            Err(e) => {
+                warn!("Could not check Dataverse dataset {}: {}", persistent_id, e);
+                // Dataverse answered and refused; anything else says nothing about the dataset
+                let status = match e {
+                    AppError::DataverseApiError(_) | AppError::NotFound(_) => LinkStatus::Broken,
+                    _ => LinkStatus::Unknown,
+                };
+                ProvenanceDataset {
+                    persistent_id,
+                    title: None,
+                    version_state: None,
+                    link: LinkCheck::failed(status, e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// The dataset link as described by the dataset's Dataverse metadata
+fn dataset_provenance(persistent_id: String, metadata: &Value) -> ProvenanceDataset {
+    let version_state = metadata["latestVersion"]["versionState"]
+        .as_str()
+        .map(str::to_string);
+    let link = match version_state.as_deref() {
+        Some("DEACCESSIONED") => LinkCheck::failed(LinkStatus::Broken, "Dataset was deaccessioned"),
+        _ => LinkCheck::verified(),
+    };
+    ProvenanceDataset {
+        persistent_id,
+        title: DataverseService::dataset_title(metadata),
+        version_state,
+        link,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_deaccessioned_datasets_break_the_link() {
+        let metadata = |state: &str| {
+            json!({
+                "latestVersion": {
+                    "versionState": state,
+                    "metadataBlocks": { "citation": { "fields": [
+                        { "typeName": "title", "value": "Yeast proteome" }
+                    ] } }
+                }
+            })
+        };
+
+        let released = dataset_provenance("doi:10.5072/FK2/A".to_string(), &metadata("RELEASED"));
+        assert_eq!(released.link.status, LinkStatus::Verified);
+        assert_eq!(released.title.as_deref(), Some("Yeast proteome"));
+        assert_eq!(released.version_state.as_deref(), Some("RELEASED"));
+
+        let withdrawn =
+            dataset_provenance("doi:10.5072/FK2/A".to_string(), &metadata("DEACCESSIONED"));
+        assert_eq!(withdrawn.link.status, LinkStatus::Broken);
+    }
+}
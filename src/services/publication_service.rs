@@ -0,0 +1,371 @@
+use crate::errors::AppError;
+use crate::models::task::Task;
+use crate::services::dataverse_service::DataverseService;
+use crate::services::did_service::DIDService;
+use crate::services::task_service::TaskService;
+use chrono::Utc;
+use log::{error, info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Background task kind tracking a publication until Dataverse finalizes or rejects it
+pub const PUBLISH_TASK_KIND: &str = "dataverse_publish";
+
+/// How often unfinished publications are checked
+const PUBLICATION_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Publications still unfinished after this long, e.g. never reviewed, are given up on
+const PUBLICATION_TIMEOUT_DAYS: i64 = 30;
+
+/// Lock held on a dataset while a curator has yet to decide
+const REVIEW_LOCK: &str = "InReview";
+
+/// Where a publication stands, as shown in its task's `details.state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PublicationState {
+    // Dataverse is finalizing the release, registering the PID or running workflows
+    Publishing,
+    // Submitted for review and awaiting a curator
+    InReview,
+    Published,
+    // Returned to the author, deaccessioned, or left unreleased by Dataverse
+    Rejected,
+}
+
+/// Details of a `dataverse_publish` task
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Publication {
+    // As submitted
+    pub persistent_id: String,
+    pub state: PublicationState,
+    // Once published; differs from `persistent_id` if Dataverse assigned a new one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_persistent_id: Option<String>,
+    // Released version, e.g. "1.0"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    // Why the publication was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    // DIDs relinked to `published_persistent_id`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relinked_dids: Vec<String>,
+}
+
+/// Starts Dataverse publications and follows them to their outcome. Progress is kept in
+/// background tasks, which a periodic sync advances, so publications that take days in
+/// review survive restarts.
+pub struct PublicationService {
+    dataverse_service: Arc<DataverseService>,
+    did_service: Arc<DIDService>,
+    task_service: Arc<TaskService>,
+}
+
+impl PublicationService {
+    pub fn new(
+        dataverse_service: Arc<DataverseService>,
+        did_service: Arc<DIDService>,
+        task_service: Arc<TaskService>,
+    ) -> Self {
+        Self {
+            dataverse_service,
+            did_service,
+            task_service,
+        }
+    }
+
+    /// Publish the dataset, or submit it for review, and return the ID of the task
+    /// following it. Fails if Dataverse refuses the request outright.
+    pub async fn start(
+        &self,
+        persistent_id: &str,
+        submit_for_review: bool,
+        user_id: i64,
+    ) -> Result<(String, Publication), AppError> {
+        let state = if submit_for_review {
+            self.dataverse_service
+                .submit_for_review(persistent_id)
+                .await?;
+            PublicationState::InReview
+        } else {
+            self.dataverse_service
+                .publish_dataset(persistent_id)
+                .await?;
+            PublicationState::Publishing
+        };
+
+        let publication = Publication {
+            persistent_id: persistent_id.to_string(),
+            state,
+            published_persistent_id: None,
+            version: None,
+            reason: None,
+            relinked_dids: Vec::new(),
+        };
+        let task_id = self
+            .task_service
+            .create_background_task(user_id, PUBLISH_TASK_KIND)
+            .await?;
+        self.record(&task_id, &publication).await?;
+
+        Ok((task_id, publication))
+    }
+
+    /// Check unfinished publications every `PUBLICATION_SYNC_INTERVAL`
+    pub fn start_sync(self: Arc<Self>) {
+        tokio::task::spawn_local(async move {
+            let mut ticker = tokio::time::interval(PUBLICATION_SYNC_INTERVAL);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.sync().await {
+                    warn!("Failed to sync Dataverse publications: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn sync(&self) -> Result<(), AppError> {
+        let tasks = self
+            .task_service
+            .unfinished_background_tasks(PUBLISH_TASK_KIND)
+            .await?;
+        for task in tasks {
+            if let Err(e) = self.check(&task).await {
+                warn!("Failed to check publication task {}: {}", task.task_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Advance one publication from the dataset's current state in Dataverse
+    async fn check(&self, task: &Task) -> Result<(), AppError> {
+        let mut publication: Publication = task
+            .details
+            .clone()
+            .and_then(|details| serde_json::from_value(details).ok())
+            .ok_or(AppError::DeserializationError)?;
+        let persistent_id = publication.persistent_id.clone();
+
+        if Utc::now() - task.created_at > chrono::Duration::days(PUBLICATION_TIMEOUT_DAYS) {
+            publication.reason = Some(format!(
+                "Dataverse didn't finish the publication within {} days",
+                PUBLICATION_TIMEOUT_DAYS
+            ));
+            return self
+                .finish(task, publication, PublicationState::Rejected)
+                .await;
+        }
+
+        // Failed checks are retried on the next sync, until the publication times out
+        let (metadata, locks) = self.fetch_state(&persistent_id).await?;
+        let checked = publication_outcome(publication.state, &metadata, &locks);
+
+        match checked {
+            Checked::Pending(state) => {
+                if state != publication.state {
+                    publication.state = state;
+                    self.record(&task.task_id, &publication).await?;
+                }
+                Ok(())
+            }
+            Checked::Published {
+                persistent_id: published,
+                version,
+                title,
+            } => {
+                if published != persistent_id {
+                    publication.relinked_dids = self
+                        .relink(&persistent_id, &published, title.as_deref())
+                        .await?;
+                }
+                publication.published_persistent_id = Some(published);
+                publication.version = version;
+                self.finish(task, publication, PublicationState::Published)
+                    .await
+            }
+            Checked::Rejected(reason) => {
+                publication.reason = Some(reason);
+                self.finish(task, publication, PublicationState::Rejected)
+                    .await
+            }
+        }
+    }
+
+    async fn fetch_state(&self, persistent_id: &str) -> Result<(Value, Vec<String>), AppError> {
+        let metadata = self
+            .dataverse_service
+            .get_dataset_metadata(persistent_id)
+            .await?;
+        let locks = self.dataverse_service.get_locks(persistent_id).await?;
+        Ok((metadata, locks))
+    }
+
+    /// Point DIDs linked to the dataset at the persistent ID it was published under
+    async fn relink(
+        &self,
+        submitted: &str,
+        published: &str,
+        title: Option<&str>,
+    ) -> Result<Vec<String>, AppError> {
+        let mut relinked = Vec::new();
+        for (did, owner) in self.did_service.find_by_dataverse_doi(submitted).await? {
+            self.did_service
+                .link_to_dataverse(&did, published, title, owner)
+                .await?;
+            info!(
+                "Relinked {} from {} to published dataset {}",
+                did, submitted, published
+            );
+            relinked.push(did);
+        }
+        Ok(relinked)
+    }
+
+    async fn record(&self, task_id: &str, publication: &Publication) -> Result<(), AppError> {
+        let details =
+            serde_json::to_value(publication).map_err(|_| AppError::SerializationError)?;
+        self.task_service
+            .update_background_task(task_id, "processing", 0.0, Some(&details), None)
+            .await
+    }
+
+    async fn finish(
+        &self,
+        task: &Task,
+        mut publication: Publication,
+        state: PublicationState,
+    ) -> Result<(), AppError> {
+        publication.state = state;
+        let details =
+            serde_json::to_value(&publication).map_err(|_| AppError::SerializationError)?;
+        let (status, error) = match state {
+            PublicationState::Published => {
+                info!("Dataset {} was published", publication.persistent_id);
+                ("completed", None)
+            }
+            _ => {
+                error!(
+                    "Publication of {} was rejected: {}",
+                    publication.persistent_id,
+                    publication.reason.as_deref().unwrap_or_default()
+                );
+                ("failed", publication.reason.as_deref())
+            }
+        };
+        self.task_service
+            .update_background_task(&task.task_id, status, 100.0, Some(&details), error)
+            .await
+    }
+}
+
+/// What a check of a publication found
+#[derive(Debug, PartialEq)]
+enum Checked {
+    Pending(PublicationState),
+    Published {
+        persistent_id: String,
+        version: Option<String>,
+        title: Option<String>,
+    },
+    Rejected(String),
+}
+
+/// Outcome of a publication last seen in `previous`, from the dataset's metadata and locks
+fn publication_outcome(previous: PublicationState, metadata: &Value, locks: &[String]) -> Checked {
+    let latest = &metadata["latestVersion"];
+    match latest["versionState"].as_str() {
+        Some("RELEASED") if locks.is_empty() => Checked::Published {
+            persistent_id: DataverseService::persistent_id(metadata).unwrap_or_else(|| {
+                metadata["persistentUrl"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string()
+            }),
+            version: latest["versionNumber"].as_u64().map(|major| {
+                format!(
+                    "{}.{}",
+                    major,
+                    latest["versionMinorNumber"].as_u64().unwrap_or(0)
+                )
+            }),
+            title: DataverseService::dataset_title(metadata),
+        },
+        Some("DEACCESSIONED") => Checked::Rejected("Dataset was deaccessioned".to_string()),
+        _ if locks.iter().any(|lock| lock == REVIEW_LOCK) => {
+            Checked::Pending(PublicationState::InReview)
+        }
+        _ if !locks.is_empty() => Checked::Pending(PublicationState::Publishing),
+        // An unlocked draft: the review ended, or publishing stopped, without a release
+        _ if previous == PublicationState::InReview => {
+            Checked::Rejected("A curator returned the dataset to its author".to_string())
+        }
+        _ => Checked::Rejected("Dataverse finished without releasing the dataset".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_publication_outcome_follows_review_and_release() {
+        let dataset = |state: &str| {
+            json!({
+                "protocol": "doi",
+                "authority": "10.5072",
+                "identifier": "FK2/PUB",
+                "latestVersion": {
+                    "versionState": state,
+                    "versionNumber": 1,
+                    "versionMinorNumber": 0,
+                    "metadataBlocks": { "citation": { "fields": [
+                        { "typeName": "title", "value": "Yeast proteome" }
+                    ] } }
+                }
+            })
+        };
+        let locks = |types: &[&str]| types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            publication_outcome(
+                PublicationState::InReview,
+                &dataset("DRAFT"),
+                &locks(&["InReview"])
+            ),
+            Checked::Pending(PublicationState::InReview)
+        );
+        assert_eq!(
+            publication_outcome(
+                PublicationState::InReview,
+                &dataset("DRAFT"),
+                &locks(&["finalizePublication"])
+            ),
+            Checked::Pending(PublicationState::Publishing)
+        );
+        assert_eq!(
+            publication_outcome(PublicationState::Publishing, &dataset("RELEASED"), &[]),
+            Checked::Published {
+                persistent_id: "doi:10.5072/FK2/PUB".to_string(),
+                version: Some("1.0".to_string()),
+                title: Some("Yeast proteome".to_string()),
+            }
+        );
+
+        // Unlocked drafts weren't released; after a review that means a curator said no
+        assert!(matches!(
+            publication_outcome(PublicationState::InReview, &dataset("DRAFT"), &[]),
+            Checked::Rejected(reason) if reason.contains("curator")
+        ));
+        assert!(matches!(
+            publication_outcome(PublicationState::Publishing, &dataset("DRAFT"), &[]),
+            Checked::Rejected(reason) if reason.contains("without releasing")
+        ));
+    }
+}
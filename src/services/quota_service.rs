@@ -0,0 +1,303 @@
+use crate::errors::AppError;
+use crate::models::auth::AuthUser;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Utc};
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Expensive operations counted against a user's monthly quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuotaResource {
+    // Papers submitted for BioAgents processing
+    Papers,
+    // Dataverse datasets created
+    Datasets,
+}
+
+impl QuotaResource {
+    pub const ALL: [QuotaResource; 2] = [QuotaResource::Papers, QuotaResource::Datasets];
+
+    /// Parse `papers` or `datasets`, in any case
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "papers" => Ok(QuotaResource::Papers),
+            "datasets" => Ok(QuotaResource::Datasets),
+            _ => Err(AppError::ValidationError(format!(
+                "Unknown quota resource '{}', expected papers or datasets",
+                value
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaResource::Papers => "papers",
+            QuotaResource::Datasets => "datasets",
+        }
+    }
+}
+
+/// Monthly limits per role and resource. Resources without a limit for a role are
+/// unlimited for it.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaLimits {
+    limits: HashMap<(String, QuotaResource), u64>,
+}
+
+impl QuotaLimits {
+    /// Parse `role:resource=limit` entries, e.g. `user:papers=100`
+    pub fn parse(entries: &[String]) -> Result<Self, AppError> {
+        let mut limits = HashMap::new();
+        for entry in entries {
+            let invalid = || {
+                AppError::ValidationError(format!(
+                    "Invalid quota '{}', expected role:resource=limit",
+                    entry
+                ))
+            };
+            let (key, limit) = entry.split_once('=').ok_or_else(invalid)?;
+            let (role, resource) = key.split_once(':').ok_or_else(invalid)?;
+            let role = role.trim();
+            if role.is_empty() {
+                return Err(invalid());
+            }
+            let limit = limit.trim().parse::<u64>().map_err(|_| invalid())?;
+            limits.insert((role.to_string(), QuotaResource::parse(resource)?), limit);
+        }
+        Ok(Self { limits })
+    }
+
+    /// Monthly limit of `resource` for a user, the most generous of their roles' limits.
+    /// `None` when any of their roles is unlimited.
+    pub fn limit_for(&self, user: &AuthUser, resource: QuotaResource) -> Option<u64> {
+        let mut limit = None;
+        for role in &user.roles {
+            let role_limit = self.limits.get(&(role.clone(), resource))?;
+            limit = Some(limit.map_or(*role_limit, |l: u64| l.max(*role_limit)));
+        }
+        limit
+    }
+}
+
+/// A user's use of one resource in the current window
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ResourceUsage {
+    // "papers" or "datasets"
+    pub resource: String,
+    pub used: u64,
+    // Absent when unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+}
+
+/// A user's quota usage in the current monthly window
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct QuotaUsage {
+    pub window_start: DateTime<Utc>,
+    // When usage resets to zero
+    pub resets_at: DateTime<Utc>,
+    pub resources: Vec<ResourceUsage>,
+}
+
+/// Counts expensive operations per user and calendar month (UTC) in `user_quotas`, so
+/// quotas survive restarts
+pub struct QuotaService {
+    db_pool: Arc<Pool>,
+    limits: QuotaLimits,
+}
+
+impl QuotaService {
+    pub fn new(db_pool: Arc<Pool>, limits: QuotaLimits) -> Self {
+        Self { db_pool, limits }
+    }
+
+    /// Count one use of `resource` by `user`, failing with `ValidationError` if that would
+    /// exceed their monthly limit. Call right before the operation; use `refund` if it fails.
+    pub async fn check_and_increment(
+        &self,
+        user: &AuthUser,
+        resource: QuotaResource,
+    ) -> Result<(), AppError> {
+        let limit = self.limits.limit_for(user, resource);
+        let window = window_start(Utc::now()).format("%Y-%m-%d").to_string();
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        "INSERT IGNORE INTO user_quotas (user_id, resource, window_start, used)
+         VALUES (:user_id, :resource, :window_start, 0)"
+            .with(params! {
+                "user_id" => user.id,
+                "resource" => resource.as_str(),
+                "window_start" => &window,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when creating quota window: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        // The limit is checked in the same statement as the increment, so concurrent
+        // requests can't both take the last unit
+        "UPDATE user_quotas SET used = used + 1
+         WHERE user_id = :user_id AND resource = :resource AND window_start = :window_start
+           AND (:limit IS NULL OR used < :limit)"
+            .with(params! {
+                "user_id" => user.id,
+                "resource" => resource.as_str(),
+                "window_start" => &window,
+                "limit" => limit,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when counting quota usage: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        if conn.affected_rows() == 0 {
+            info!(
+                "User {} reached their monthly {} quota",
+                user.id,
+                resource.as_str()
+            );
+            return Err(AppError::ValidationError(format!(
+                "Monthly {} quota of {} exceeded; it resets at {}",
+                resource.as_str(),
+                limit.unwrap_or_default(),
+                next_window_start(Utc::now()).to_rfc3339()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Give back a use counted by `check_and_increment` whose operation then failed.
+    /// Failures are logged rather than returned, so they don't mask the original error.
+    pub async fn refund(&self, user_id: i64, resource: QuotaResource) {
+        let window = window_start(Utc::now()).format("%Y-%m-%d").to_string();
+        let result = async {
+            let mut conn = self.db_pool.get_conn().await?;
+            "UPDATE user_quotas SET used = used - 1
+             WHERE user_id = :user_id AND resource = :resource AND window_start = :window_start
+               AND used > 0"
+                .with(params! {
+                    "user_id" => user_id,
+                    "resource" => resource.as_str(),
+                    "window_start" => &window,
+                })
+                .ignore(&mut conn)
+                .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            warn!(
+                "Failed to refund {} quota of user {}: {}",
+                resource.as_str(),
+                user_id,
+                e
+            );
+        }
+    }
+
+    /// The user's usage and limits in the current window
+    pub async fn usage(&self, user: &AuthUser) -> Result<QuotaUsage, AppError> {
+        let now = Utc::now();
+        let window = window_start(now);
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let rows: Vec<(String, u64)> = "SELECT resource, used FROM user_quotas
+             WHERE user_id = :user_id AND window_start = :window_start"
+            .with(params! {
+                "user_id" => user.id,
+                "window_start" => window.format("%Y-%m-%d").to_string(),
+            })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reading quota usage: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        let used: HashMap<String, u64> = rows.into_iter().collect();
+
+        Ok(QuotaUsage {
+            window_start: window.and_time(NaiveTime::MIN).and_utc(),
+            resets_at: next_window_start(now),
+            resources: QuotaResource::ALL
+                .iter()
+                .map(|&resource| ResourceUsage {
+                    resource: resource.as_str().to_string(),
+                    used: used.get(resource.as_str()).copied().unwrap_or(0),
+                    limit: self.limits.limit_for(user, resource),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// First day of the month `now` falls in
+fn window_start(now: DateTime<Utc>) -> NaiveDate {
+    now.date_naive().with_day(1).unwrap_or(now.date_naive())
+}
+
+/// Start of the month after the one `now` falls in
+fn next_window_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = if now.month() == 12 {
+        (now.year() + 1, 1)
+    } else {
+        (now.year(), now.month() + 1)
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_with_roles(roles: &[&str]) -> AuthUser {
+        AuthUser::new(
+            1,
+            "alice".to_string(),
+            roles.iter().map(|r| r.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_limit_for_takes_most_generous_role() {
+        let limits = QuotaLimits::parse(&[
+            "user:papers=100".to_string(),
+            "curator:papers=500".to_string(),
+            "user:datasets=20".to_string(),
+        ])
+        .unwrap();
+
+        let user = user_with_roles(&["user"]);
+        assert_eq!(limits.limit_for(&user, QuotaResource::Papers), Some(100));
+        assert_eq!(limits.limit_for(&user, QuotaResource::Datasets), Some(20));
+
+        let curator = user_with_roles(&["user", "curator"]);
+        assert_eq!(limits.limit_for(&curator, QuotaResource::Papers), Some(500));
+        // No datasets limit for curators, so they are unlimited
+        assert_eq!(limits.limit_for(&curator, QuotaResource::Datasets), None);
+
+        assert_eq!(
+            limits.limit_for(&user_with_roles(&["admin"]), QuotaResource::Papers),
+            None
+        );
+        assert!(QuotaLimits::parse(&["user:uploads=5".to_string()]).is_err());
+        assert!(QuotaLimits::parse(&["papers=5".to_string()]).is_err());
+    }
+}
@@ -1,13 +1,124 @@
+use crate::database::db_time::{from_db_value, to_db};
 use crate::errors::AppError;
+use crate::models::did::{BiometadataExtension, GeneratedKeypair, Researcher};
 use crate::models::file_metadata::{BiologicalEntityReference, ResearchPaperMetadata};
-use crate::services::bioagents_service::{BioAgentsService, ExtractedMetadata};
+use crate::models::license::License;
+use crate::services::bioagents_service::{BioAgentsService, BiologicalEntity, ExtractedMetadata};
 use crate::services::did_service::DIDService;
+use crate::services::entity_enrichment::EntityEnrichmentService;
 use crate::services::ipfs_service::IPFSService;
-use chrono::{TimeZone, Utc};
-use log::{error, info};
+use crate::services::keyword_extraction::extract_keywords;
+use crate::services::keyword_service::{KeywordService, KeywordVocabulary};
+use crate::services::pii_scanner::{redact, summarize, PiiAction, PiiPolicy, PiiScanner};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
 use mysql_async::{params, prelude::*, Row};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// DID minted for a processed paper and the keypair controlling it
+pub struct ProcessedPaper {
+    pub did: String,
+    // Only returned once; the server doesn't keep the private key
+    pub keypair: GeneratedKeypair,
+}
+
+/// Result of a dry-run paper processing request
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PaperPreview {
+    // Metadata as extracted by BioAgents
+    pub extracted: ExtractedMetadata,
+    // DID metadata that a real submission would end up with
+    pub proposed_metadata: BiometadataExtension,
+}
+
+/// Most papers one reprocessing request may name
+pub const MAX_REPROCESS_PAPERS: usize = 500;
+
+/// Field of a paper's stored metadata that reprocessing can keep, e.g. after the user
+/// corrected it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaperField {
+    Title,
+    Authors,
+    // Also kept out of the DID's description
+    Abstract,
+    Doi,
+    PublicationDate,
+    Journal,
+    Keywords,
+    BiologicalEntities,
+}
+
+/// Progress of a reprocessing run, sent after each paper
+#[derive(Debug, Clone)]
+pub struct ReprocessProgress {
+    pub processed: usize,
+    pub total: usize,
+    pub updated: usize,
+    pub failed: usize,
+    // Most recently processed DID
+    pub did: String,
+}
+
+/// What reprocessing did with one paper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReprocessStatus {
+    Updated,
+    // The caller has no paper under the DID
+    NotFound,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ReprocessResult {
+    pub did: String,
+    pub status: ReprocessStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub knowledge_graph_cid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of reprocessing a set of papers, one result per DID in request order
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ReprocessReport {
+    pub total: usize,
+    pub updated: usize,
+    pub not_found: usize,
+    pub failed: usize,
+    pub results: Vec<ReprocessResult>,
+}
+
+/// Related papers returned when the request doesn't ask for a number
+pub const DEFAULT_RELATED_PAPERS: u32 = 10;
+
+/// Most related papers returned for one request
+pub const MAX_RELATED_PAPERS: u32 = 50;
+
+/// Most recently updated papers compared against when finding related ones
+const MAX_RELATED_CANDIDATES: usize = 10_000;
+
+/// A shared biological entity counts this many times as much as a shared keyword
+const SHARED_ENTITY_WEIGHT: u32 = 2;
+
+/// A paper sharing biological entities or keywords with another
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RelatedPaper {
+    pub did: String,
+    pub title: String,
+    // Shared entities weighted by `SHARED_ENTITY_WEIGHT`, plus shared keywords
+    pub score: u32,
+    // Entities of the requested paper, by identifier, that this paper also mentions
+    pub shared_entities: Vec<BiologicalEntityReference>,
+    // Keywords of the requested paper, as written there, matching one of this paper's
+    pub shared_keywords: Vec<String>,
+}
 
 /// Database row representation for research paper metadata
 #[derive(Debug, Deserialize)]
@@ -23,8 +134,9 @@ struct PaperDbRow {
     did: String,
     biological_entities: String,
     knowledge_graph_cid: Option<String>,
-    created_at: String,
-    updated_at: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    needs_review: bool,
 }
 
 impl FromRow for PaperDbRow {
@@ -41,8 +153,9 @@ impl FromRow for PaperDbRow {
             did: row.get(8).unwrap_or_default(),
             biological_entities: row.get(9).unwrap_or_default(),
             knowledge_graph_cid: row.get(10),
-            created_at: row.get(11).unwrap_or_default(),
-            updated_at: row.get(12).unwrap_or_default(),
+            created_at: row.as_ref(11).and_then(from_db_value).unwrap_or_default(),
+            updated_at: row.as_ref(12).and_then(from_db_value).unwrap_or_default(),
+            needs_review: row.get(13).unwrap_or_default(),
         }
     }
 
@@ -74,37 +187,63 @@ impl FromRow for PaperDbRow {
                 .ok_or_else(|| mysql_async::FromRowError(row.clone()))?,
             knowledge_graph_cid: row.get(10),
             created_at: row
-                .get(11)
+                .as_ref(11)
+                .and_then(from_db_value)
                 .ok_or_else(|| mysql_async::FromRowError(row.clone()))?,
             updated_at: row
-                .get(12)
+                .as_ref(12)
+                .and_then(from_db_value)
                 .ok_or_else(|| mysql_async::FromRowError(row.clone()))?,
+            needs_review: row.get(13).unwrap_or_default(),
         })
     }
 }
 
+/// Keywords generated from the title and abstract when BioAgents returns none
+const FALLBACK_KEYWORD_COUNT: usize = 8;
+
+/// `custom_fields` entry set on DIDs whose keywords were generated locally
+pub const KEYWORDS_AUTO_GENERATED_FIELD: &str = "keywords_auto_generated";
+
+/// File types BioAgents can process: PDF, JATS-style XML and plain text
+pub const SUPPORTED_PAPER_CONTENT_TYPES: &[&str] =
+    &["application/pdf", "application/xml", "text/plain"];
+
 /// Service for managing research paper metadata
 pub struct ResearchPaperService {
     db_pool: Arc<mysql_async::Pool>,
-    #[allow(dead_code)]
     ipfs_service: Arc<IPFSService>,
     did_service: Arc<DIDService>,
     bioagents_service: Arc<BioAgentsService>,
+    entity_enrichment: Arc<EntityEnrichmentService>,
+    keyword_service: Arc<KeywordService>,
+    // Run over abstracts and entity mentions before they are stored
+    pii_scanner: Arc<dyn PiiScanner>,
+    pii_policy: PiiPolicy,
 }
 
 impl ResearchPaperService {
     /// Create a new ResearchPaperService
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         db_pool: Arc<mysql_async::Pool>,
         ipfs_service: Arc<IPFSService>,
         did_service: Arc<DIDService>,
         bioagents_service: Arc<BioAgentsService>,
+        entity_enrichment: Arc<EntityEnrichmentService>,
+        keyword_service: Arc<KeywordService>,
+        pii_scanner: Arc<dyn PiiScanner>,
+        pii_policy: PiiPolicy,
     ) -> Self {
         Self {
             db_pool,
             ipfs_service,
             did_service,
             bioagents_service,
+            entity_enrichment,
+            keyword_service,
+            pii_scanner,
+            pii_policy,
         }
     }
 
@@ -118,20 +257,10 @@ impl ResearchPaperService {
         knowledge_graph_cid: Option<&str>,
     ) -> Result<ResearchPaperMetadata, AppError> {
         let now = Utc::now();
-        let created_at = now.naive_utc().format("%Y-%m-%d %H:%M:%S").to_string();
+        let created_at = to_db(now);
         let updated_at = created_at.clone();
 
-        // Convert BioAgents entities to our internal format
-        let biological_entities: Vec<BiologicalEntityReference> = metadata
-            .biological_entities
-            .into_iter()
-            .map(|entity| BiologicalEntityReference {
-                entity_type: entity.entity_type,
-                name: entity.name,
-                identifier: entity.identifier,
-                source: entity.source,
-            })
-            .collect();
+        let biological_entities = self.entity_references(&metadata.biological_entities).await;
 
         // Create the research paper metadata object
         let paper_metadata = ResearchPaperMetadata {
@@ -148,6 +277,7 @@ impl ResearchPaperService {
             knowledge_graph_cid: knowledge_graph_cid.map(|cid| cid.to_string()),
             created_at: now,
             updated_at: now,
+            needs_review: metadata.needs_review,
         };
 
         // Serialize the JSON fields
@@ -164,7 +294,7 @@ impl ResearchPaperService {
             AppError::DatabaseError(e.to_string())
         })?;
 
-        "INSERT INTO research_papers (title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, user_id) VALUES (:title, :authors, :abstract_text, :doi, :publication_date, :journal, :keywords, :cid, :did, :biological_entities, :knowledge_graph_cid, :created_at, :updated_at, :user_id)"
+        "INSERT INTO research_papers (title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, user_id, needs_review) VALUES (:title, :authors, :abstract_text, :doi, :publication_date, :journal, :keywords, :cid, :did, :biological_entities, :knowledge_graph_cid, :created_at, :updated_at, :user_id, :needs_review)"
             .with(params! {
                 "title" => &paper_metadata.title,
                 "authors" => &authors_json,
@@ -180,6 +310,7 @@ impl ResearchPaperService {
                 "created_at" => &created_at,
                 "updated_at" => &updated_at,
                 "user_id" => user_id,
+                "needs_review" => paper_metadata.needs_review,
             })
             .run(&mut conn)
             .await
@@ -188,11 +319,38 @@ impl ResearchPaperService {
                 AppError::DatabaseError(e.to_string())
             })?;
 
+        // The paper is stored either way; its keywords are indexed again with the DID's
+        if let Err(e) = self
+            .keyword_service
+            .add(did, &paper_metadata.keywords)
+            .await
+        {
+            warn!("Failed to index keywords of paper {}: {}", did, e);
+        }
+
         info!("Created research paper metadata for DID: {}", did);
 
         Ok(paper_metadata)
     }
 
+    /// Fill in missing identifiers, then convert BioAgents entities to our internal format
+    async fn entity_references(
+        &self,
+        entities: &[BiologicalEntity],
+    ) -> Vec<BiologicalEntityReference> {
+        self.entity_enrichment
+            .enrich_entities(entities)
+            .await
+            .into_iter()
+            .map(|entity| BiologicalEntityReference {
+                entity_type: entity.entity_type,
+                name: entity.name,
+                identifier: entity.identifier,
+                source: entity.source,
+            })
+            .collect()
+    }
+
     /// Get research paper metadata by DID
     pub async fn get_paper_metadata_by_did(
         &self,
@@ -204,7 +362,7 @@ impl ResearchPaperService {
         })?;
 
         // Query the database for the paper metadata
-        let row = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at FROM research_papers WHERE did = :did"
+        let row = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, needs_review FROM research_papers WHERE did = :did"
             .with(params! { "did" => did })
             .first::<PaperDbRow, _>(&mut conn)
             .await
@@ -220,41 +378,37 @@ impl ResearchPaperService {
             ))
         })?;
 
-        // Parse the JSON fields
-        let authors: Vec<String> =
-            serde_json::from_str(&row.authors).map_err(|_| AppError::DeserializationError)?;
-        let keywords: Vec<String> =
-            serde_json::from_str(&row.keywords).map_err(|_| AppError::DeserializationError)?;
-        let biological_entities: Vec<BiologicalEntityReference> =
-            serde_json::from_str(&row.biological_entities)
-                .map_err(|_| AppError::DeserializationError)?;
-
-        // Parse the timestamps
-        let created_at =
-            chrono::NaiveDateTime::parse_from_str(&row.created_at, "%Y-%m-%d %H:%M:%S")
-                .map_err(|_| AppError::DeserializationError)?;
-        let updated_at =
-            chrono::NaiveDateTime::parse_from_str(&row.updated_at, "%Y-%m-%d %H:%M:%S")
-                .map_err(|_| AppError::DeserializationError)?;
+        paper_from_row(row)
+    }
 
-        // Create the research paper metadata object
-        let paper_metadata = ResearchPaperMetadata {
-            title: row.title,
-            authors,
-            abstract_text: row.abstract_text,
-            doi: row.doi,
-            publication_date: row.publication_date,
-            journal: row.journal,
-            keywords,
-            cid: row.cid,
-            did: row.did,
-            biological_entities,
-            knowledge_graph_cid: row.knowledge_graph_cid,
-            created_at: Utc.from_utc_datetime(&created_at),
-            updated_at: Utc.from_utc_datetime(&updated_at),
-        };
+    /// Knowledge graph CID of each known paper among `paper_cids`, `None` for papers
+    /// without a graph. Unknown papers are left out.
+    pub async fn knowledge_graph_cids(
+        &self,
+        paper_cids: &[String],
+    ) -> Result<HashMap<String, Option<String>>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
 
-        Ok(paper_metadata)
+        let mut graphs = HashMap::new();
+        for cid in paper_cids {
+            let row: Option<Option<String>> =
+                "SELECT knowledge_graph_cid FROM research_papers WHERE cid = :cid"
+                    .with(params! { "cid" => cid })
+                    .first(&mut conn)
+                    .await
+                    .map_err(|e| {
+                        error!("Database error when retrieving knowledge graph CID: {}", e);
+                        AppError::DatabaseError(e.to_string())
+                    })?;
+            if let Some(knowledge_graph_cid) = row {
+                graphs.insert(cid.clone(), knowledge_graph_cid);
+            }
+        }
+
+        Ok(graphs)
     }
 
     /// Get research paper metadata by CID
@@ -268,7 +422,7 @@ impl ResearchPaperService {
         })?;
 
         // Query the database for the paper metadata
-        let row = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at FROM research_papers WHERE cid = :cid"
+        let row = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, needs_review FROM research_papers WHERE cid = :cid"
             .with(params! { "cid" => cid })
             .first::<PaperDbRow, _>(&mut conn)
             .await
@@ -284,44 +438,10 @@ impl ResearchPaperService {
             ))
         })?;
 
-        // Parse the JSON fields
-        let authors: Vec<String> =
-            serde_json::from_str(&row.authors).map_err(|_| AppError::DeserializationError)?;
-        let keywords: Vec<String> =
-            serde_json::from_str(&row.keywords).map_err(|_| AppError::DeserializationError)?;
-        let biological_entities: Vec<BiologicalEntityReference> =
-            serde_json::from_str(&row.biological_entities)
-                .map_err(|_| AppError::DeserializationError)?;
-
-        // Parse the timestamps
-        let created_at =
-            chrono::NaiveDateTime::parse_from_str(&row.created_at, "%Y-%m-%d %H:%M:%S")
-                .map_err(|_| AppError::DeserializationError)?;
-        let updated_at =
-            chrono::NaiveDateTime::parse_from_str(&row.updated_at, "%Y-%m-%d %H:%M:%S")
-                .map_err(|_| AppError::DeserializationError)?;
-
-        // Create the research paper metadata object
-        let paper_metadata = ResearchPaperMetadata {
-            title: row.title,
-            authors,
-            abstract_text: row.abstract_text,
-            doi: row.doi,
-            publication_date: row.publication_date,
-            journal: row.journal,
-            keywords,
-            cid: row.cid,
-            did: row.did,
-            biological_entities,
-            knowledge_graph_cid: row.knowledge_graph_cid,
-            created_at: Utc.from_utc_datetime(&created_at),
-            updated_at: Utc.from_utc_datetime(&updated_at),
-        };
-
-        Ok(paper_metadata)
+        paper_from_row(row)
     }
 
-    /// Search for research papers by keywords
+    /// Search for research papers by title, abstract or a keyword in any spelling
     pub async fn search_papers(&self, query: &str) -> Result<Vec<ResearchPaperMetadata>, AppError> {
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
@@ -329,8 +449,11 @@ impl ResearchPaperService {
         })?;
 
         // Query the database for papers matching the search term
-        let rows = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at FROM research_papers WHERE title LIKE :query OR abstract_text LIKE :query"
-            .with(params! { "query" => format!("%{}%", query) })
+        let rows = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, needs_review FROM research_papers WHERE needs_review = FALSE AND (title LIKE :query OR abstract_text LIKE :query OR did IN (SELECT did FROM did_keywords WHERE normalized = :keyword))"
+            .with(params! {
+                "query" => format!("%{}%", query),
+                "keyword" => self.keyword_service.vocabulary().normalize(query),
+            })
             .fetch::<PaperDbRow, _>(&mut conn)
             .await
             .map_err(|e| {
@@ -338,50 +461,94 @@ impl ResearchPaperService {
                 AppError::DatabaseError(e.to_string())
             })?;
 
-        // Convert the rows to ResearchPaperMetadata objects
-        let mut results = Vec::new();
-        for row in rows {
-            // Parse the JSON fields
-            let authors: Vec<String> =
-                serde_json::from_str(&row.authors).map_err(|_| AppError::DeserializationError)?;
-            let keywords: Vec<String> =
-                serde_json::from_str(&row.keywords).map_err(|_| AppError::DeserializationError)?;
-            let biological_entities: Vec<BiologicalEntityReference> =
-                serde_json::from_str(&row.biological_entities)
-                    .map_err(|_| AppError::DeserializationError)?;
-
-            // Parse the timestamps
-            let created_at =
-                chrono::NaiveDateTime::parse_from_str(&row.created_at, "%Y-%m-%d %H:%M:%S")
-                    .map_err(|_| AppError::DeserializationError)?;
-            let updated_at =
-                chrono::NaiveDateTime::parse_from_str(&row.updated_at, "%Y-%m-%d %H:%M:%S")
-                    .map_err(|_| AppError::DeserializationError)?;
-
-            // Create the research paper metadata object
-            let paper_metadata = ResearchPaperMetadata {
-                title: row.title,
-                authors,
-                abstract_text: row.abstract_text,
-                doi: row.doi,
-                publication_date: row.publication_date,
-                journal: row.journal,
-                keywords,
-                cid: row.cid,
-                did: row.did,
-                biological_entities,
-                knowledge_graph_cid: row.knowledge_graph_cid,
-                created_at: Utc.from_utc_datetime(&created_at),
-                updated_at: Utc.from_utc_datetime(&updated_at),
-            };
+        rows.into_iter().map(paper_from_row).collect()
+    }
 
-            results.push(paper_metadata);
-        }
+    /// Papers sharing biological entities, by identifier, or normalized keywords with the
+    /// paper behind `did`, most overlap first. Papers awaiting review are left out, as in
+    /// search; only the `MAX_RELATED_CANDIDATES` most recently updated papers are compared.
+    pub async fn find_related(&self, did: &str, limit: u32) -> Result<Vec<RelatedPaper>, AppError> {
+        let paper = self.get_paper_metadata_by_did(did).await?;
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let rows: Vec<(String, String, Option<String>, Option<String>)> = format!(
+            r"SELECT did, title, keywords, biological_entities FROM research_papers
+            WHERE needs_review = FALSE AND did <> :did
+            ORDER BY updated_at DESC
+            LIMIT {}",
+            MAX_RELATED_CANDIDATES
+        )
+        .with(params! { "did" => did })
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!(
+                "Database error when finding papers related to {}: {}",
+                did, e
+            );
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let candidates = rows
+            .into_iter()
+            .map(|(did, title, keywords, entities)| RelatedCandidate {
+                did,
+                title,
+                keywords: keywords
+                    .and_then(|keywords| serde_json::from_str(&keywords).ok())
+                    .unwrap_or_default(),
+                biological_entities: entities
+                    .and_then(|entities| serde_json::from_str(&entities).ok())
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(rank_related(
+            &paper,
+            candidates,
+            self.keyword_service.vocabulary(),
+            limit.clamp(1, MAX_RELATED_PAPERS) as usize,
+        ))
+    }
+
+    /// Check that the file behind `file_cid` is a paper format BioAgents accepts, returning
+    /// its detected MIME type
+    pub async fn validate_paper_file(&self, file_cid: &str) -> Result<String, AppError> {
+        let content_type = self
+            .ipfs_service
+            .detect_content_type(file_cid)
+            .await
+            .map_err(|e| {
+                error!("Failed to detect content type of {}: {}", file_cid, e);
+                AppError::ServiceError(format!("Failed to read paper file: {}", e))
+            })?;
 
-        Ok(results)
+        match content_type {
+            Some(content_type)
+                if SUPPORTED_PAPER_CONTENT_TYPES.contains(&content_type.as_str()) =>
+            {
+                Ok(content_type)
+            }
+            other => Err(AppError::ValidationError(format!(
+                "Unsupported paper file type {}, expected one of: {}",
+                other.as_deref().unwrap_or("unknown"),
+                SUPPORTED_PAPER_CONTENT_TYPES.join(", ")
+            ))),
+        }
     }
 
-    /// Process a research paper with BioAgents and create metadata
+    /// Process a research paper with BioAgents and create metadata.
+    ///
+    /// With `keyword_fallback` set, keywords are extracted from the title and abstract when
+    /// BioAgents returns none, and the DID metadata records that they were auto-generated.
+    /// With `refresh_cache` set, BioAgents is asked again even if this paper's extraction is
+    /// cached.
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(user_id = user_id, file_cid = file_cid))]
     pub async fn process_paper_and_create_metadata(
         &self,
         file_cid: &str,
@@ -389,42 +556,22 @@ impl ResearchPaperService {
         authors: &[String],
         doi: Option<&str>,
         user_id: i64,
-    ) -> Result<String, AppError> {
+        keyword_fallback: bool,
+        refresh_cache: bool,
+    ) -> Result<ProcessedPaper, AppError> {
+        self.validate_paper_file(file_cid).await?;
+
         // First, create a DID for the paper
-        let did_metadata = crate::models::did::BiometadataExtension {
-            title: title.to_string(),
-            description: Some(format!("Research paper: {}", title)),
-            researchers: authors
-                .iter()
-                .map(|author| crate::models::did::Researcher {
-                    name: author.clone(),
-                    orcid: None,
-                    role: "Author".to_string(),
-                    affiliation: None,
-                    email: None,
-                })
-                .collect(),
-            // Will be updated after processing
-            keywords: Vec::new(),
-            data_type: "Research Paper".to_string(),
-            license: "CC-BY-4.0".to_string(),
-            doi: doi.map(|d| d.to_string()),
-            handle: None,
-            dataverse_link: None,
-            related_identifiers: None,
-            dataset_size: None,
-            funding_info: None,
-            creation_date: Utc::now(),
-            last_modified: Utc::now(),
-            custom_fields: None,
-        };
+        let did_metadata =
+            paper_did_metadata(title, authors, doi, self.did_service.default_license());
 
-        // Create a DID for the paper
+        // Create a DID for the paper, controlled by a key only the caller will hold
+        let keypair = DIDService::generate_keypair();
         let did_request = crate::models::did::DIDCreationRequest {
-            // This should be the user's actual DID
-            controller: format!("did:key:user{}", user_id),
-            // This should be generated
-            public_key: "z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK".to_string(),
+            controller: keypair.controller.clone(),
+            public_key: keypair.public_key_multibase.clone(),
+            verification_methods: Vec::new(),
+            assertion_methods: None,
             service_endpoints: Vec::new(),
             metadata: did_metadata,
         };
@@ -434,6 +581,318 @@ impl ResearchPaperService {
 
         info!("Created DID for paper: {}", did);
 
+        let (mut metadata, knowledge_graph_cid) = self
+            .extract_metadata(file_cid, title, authors, doi, true, refresh_cache)
+            .await?;
+        if keyword_fallback {
+            fill_missing_keywords(&mut metadata);
+        }
+        let keywords_auto_generated = metadata.keywords_auto_generated;
+
+        // Create the paper metadata
+        let paper_metadata = self
+            .create_paper_metadata(
+                metadata,
+                file_cid,
+                &did,
+                user_id,
+                knowledge_graph_cid.as_deref(),
+            )
+            .await?;
+
+        // Update the DID document with the keywords from the metadata
+        if !paper_metadata.keywords.is_empty() {
+            let patch = paper_did_patch(&paper_metadata, keywords_auto_generated);
+            self.patch_paper_did(&did, patch, user_id).await?;
+        }
+
+        Ok(ProcessedPaper { did, keypair })
+    }
+
+    async fn patch_paper_did(
+        &self,
+        did: &str,
+        patch: serde_json::Value,
+        user_id: i64,
+    ) -> Result<(), AppError> {
+        let update_request = crate::models::did::DIDUpdateRequest {
+            controller: None,
+            add_verification_method: None,
+            remove_verification_method: None,
+            set_assertion_method: None,
+            add_service: None,
+            remove_service: None,
+            update_metadata: None,
+            patch_metadata: Some(patch),
+            expected_cid: None,
+        };
+
+        self.did_service
+            .update_did(did, update_request, user_id)
+            .await?;
+        Ok(())
+    }
+
+    /// Run BioAgents again on the caller's papers among `dids`, e.g. after its models
+    /// improved, replacing their stored metadata, knowledge graph and the DID metadata
+    /// taken from them. Fields in `preserve` keep their stored values.
+    ///
+    /// Papers are processed one at a time and always sent to BioAgents, bypassing the
+    /// extraction cache. A paper that fails doesn't stop the rest.
+    pub async fn reprocess(
+        &self,
+        dids: &[String],
+        user_id: i64,
+        preserve: &[PaperField],
+        progress: UnboundedSender<ReprocessProgress>,
+    ) -> ReprocessReport {
+        let mut report = ReprocessReport {
+            total: dids.len(),
+            updated: 0,
+            not_found: 0,
+            failed: 0,
+            results: Vec::with_capacity(dids.len()),
+        };
+
+        for (index, did) in dids.iter().enumerate() {
+            let (status, knowledge_graph_cid, error) =
+                match self.reprocess_paper(did, user_id, preserve).await {
+                    Ok(paper) => {
+                        report.updated += 1;
+                        (ReprocessStatus::Updated, paper.knowledge_graph_cid, None)
+                    }
+                    Err(AppError::NotFound(message)) => {
+                        report.not_found += 1;
+                        (ReprocessStatus::NotFound, None, Some(message))
+                    }
+                    Err(e) => {
+                        warn!("Failed to reprocess paper {}: {}", did, e);
+                        report.failed += 1;
+                        (ReprocessStatus::Failed, None, Some(e.to_string()))
+                    }
+                };
+            report.results.push(ReprocessResult {
+                did: did.clone(),
+                status,
+                knowledge_graph_cid,
+                error,
+            });
+
+            let _ = progress.send(ReprocessProgress {
+                processed: index + 1,
+                total: report.total,
+                updated: report.updated,
+                failed: report.failed + report.not_found,
+                did: did.clone(),
+            });
+        }
+
+        info!(
+            "Reprocessed papers for user {}: {} updated, {} not found, {} failed",
+            user_id, report.updated, report.not_found, report.failed
+        );
+        report
+    }
+
+    async fn reprocess_paper(
+        &self,
+        did: &str,
+        user_id: i64,
+        preserve: &[PaperField],
+    ) -> Result<ResearchPaperMetadata, AppError> {
+        let stored = self.owned_paper(did, user_id).await?;
+
+        let (mut extracted, knowledge_graph_cid) = self
+            .extract_metadata(
+                &stored.cid,
+                &stored.title,
+                &stored.authors,
+                stored.doi.as_deref(),
+                true,
+                true,
+            )
+            .await?;
+        fill_missing_keywords(&mut extracted);
+        let keywords_auto_generated =
+            extracted.keywords_auto_generated && !preserve.contains(&PaperField::Keywords);
+        let biological_entities = if preserve.contains(&PaperField::BiologicalEntities) {
+            stored.biological_entities.clone()
+        } else {
+            self.entity_references(&extracted.biological_entities).await
+        };
+        let paper = merge_reprocessed(
+            stored,
+            extracted,
+            biological_entities,
+            knowledge_graph_cid,
+            preserve,
+        );
+
+        self.update_paper(&paper, user_id).await?;
+        if let Err(e) = self.keyword_service.add(did, &paper.keywords).await {
+            warn!("Failed to index keywords of paper {}: {}", did, e);
+        }
+
+        let mut patch = paper_did_patch(&paper, keywords_auto_generated);
+        if let Some(patch) = patch.as_object_mut() {
+            for (field, key) in [
+                (PaperField::Title, "title"),
+                (PaperField::Abstract, "description"),
+                (PaperField::Keywords, "keywords"),
+                (PaperField::Doi, "doi"),
+            ] {
+                if preserve.contains(&field) {
+                    patch.remove(key);
+                }
+            }
+        }
+        if patch.as_object().is_some_and(|patch| !patch.is_empty()) {
+            self.patch_paper_did(did, patch, user_id).await?;
+        }
+
+        info!("Reprocessed research paper {}", did);
+        Ok(paper)
+    }
+
+    /// The paper behind `did`, provided `user_id` submitted it
+    async fn owned_paper(
+        &self,
+        did: &str,
+        user_id: i64,
+    ) -> Result<ResearchPaperMetadata, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let row = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, needs_review FROM research_papers WHERE did = :did AND user_id = :user_id"
+            .with(params! {
+                "did" => did,
+                "user_id" => user_id,
+            })
+            .first::<PaperDbRow, _>(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when retrieving research paper metadata: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let row = row.ok_or_else(|| {
+            AppError::NotFound(format!("You have no research paper for DID: {}", did))
+        })?;
+        paper_from_row(row)
+    }
+
+    /// Store reprocessed metadata over the paper's row
+    async fn update_paper(
+        &self,
+        paper: &ResearchPaperMetadata,
+        user_id: i64,
+    ) -> Result<(), AppError> {
+        let authors_json =
+            serde_json::to_string(&paper.authors).map_err(|_| AppError::SerializationError)?;
+        let keywords_json =
+            serde_json::to_string(&paper.keywords).map_err(|_| AppError::SerializationError)?;
+        let biological_entities_json = serde_json::to_string(&paper.biological_entities)
+            .map_err(|_| AppError::SerializationError)?;
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        "UPDATE research_papers SET title = :title, authors = :authors, abstract_text = :abstract_text, doi = :doi, publication_date = :publication_date, journal = :journal, keywords = :keywords, biological_entities = :biological_entities, knowledge_graph_cid = :knowledge_graph_cid, updated_at = :updated_at, needs_review = :needs_review WHERE did = :did AND user_id = :user_id"
+            .with(params! {
+                "title" => &paper.title,
+                "authors" => &authors_json,
+                "abstract_text" => &paper.abstract_text,
+                "doi" => &paper.doi,
+                "publication_date" => &paper.publication_date,
+                "journal" => &paper.journal,
+                "keywords" => &keywords_json,
+                "biological_entities" => &biological_entities_json,
+                "knowledge_graph_cid" => &paper.knowledge_graph_cid,
+                "updated_at" => to_db(paper.updated_at),
+                "needs_review" => paper.needs_review,
+                "did" => &paper.did,
+                "user_id" => user_id,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when updating research paper metadata: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })
+    }
+
+    /// Run BioAgents extraction on a paper without minting a DID or storing anything.
+    ///
+    /// Returns what `process_paper_and_create_metadata` would record, so the caller can
+    /// correct authors or keywords before submitting. Knowledge graph generation is skipped
+    /// because it would pin a new document on IPFS, and no task is recorded.
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(user_id = user_id, file_cid = file_cid))]
+    pub async fn preview_paper_metadata(
+        &self,
+        file_cid: &str,
+        title: &str,
+        authors: &[String],
+        doi: Option<&str>,
+        user_id: i64,
+        keyword_fallback: bool,
+        refresh_cache: bool,
+    ) -> Result<PaperPreview, AppError> {
+        self.validate_paper_file(file_cid).await?;
+
+        let (mut extracted, _) = self
+            .extract_metadata(file_cid, title, authors, doi, false, refresh_cache)
+            .await?;
+        if keyword_fallback {
+            fill_missing_keywords(&mut extracted);
+        }
+
+        let mut proposed_metadata =
+            paper_did_metadata(title, authors, doi, self.did_service.default_license());
+        self.did_service
+            .link_own_orcid(&mut proposed_metadata, user_id)
+            .await?;
+        // Mirrors the DID update made after extraction in the non-dry-run flow
+        if !extracted.keywords.is_empty() {
+            proposed_metadata.title = extracted.title.clone();
+            if !extracted.needs_review {
+                proposed_metadata.description = Some(extracted.abstract_text.clone());
+            }
+            proposed_metadata.keywords = extracted.keywords.clone();
+            proposed_metadata.doi = extracted.doi.clone();
+            if extracted.keywords_auto_generated {
+                proposed_metadata.custom_fields = Some(HashMap::from([(
+                    KEYWORDS_AUTO_GENERATED_FIELD.to_string(),
+                    serde_json::Value::Bool(true),
+                )]));
+            }
+        }
+
+        Ok(PaperPreview {
+            extracted,
+            proposed_metadata,
+        })
+    }
+
+    /// Submit a paper to BioAgents and wait for its extracted metadata and, when
+    /// generated, the knowledge graph CID.
+    ///
+    /// Extraction is deterministic per paper, so results are cached by file CID and request
+    /// options and reused unless `refresh_cache` is set. The cache holds BioAgents' output
+    /// as returned; personal data screening runs on every use.
+    async fn extract_metadata(
+        &self,
+        file_cid: &str,
+        title: &str,
+        authors: &[String],
+        doi: Option<&str>,
+        generate_knowledge_graph: bool,
+        refresh_cache: bool,
+    ) -> Result<(ExtractedMetadata, Option<String>), AppError> {
         // Process the paper with BioAgents
         let process_request = crate::services::bioagents_service::ProcessPaperRequest {
             file_cid: file_cid.to_string(),
@@ -441,8 +900,19 @@ impl ResearchPaperService {
             authors: authors.to_vec(),
             doi: doi.map(|d| d.to_string()),
             extract_metadata: true,
-            generate_knowledge_graph: true,
+            generate_knowledge_graph,
         };
+        let options_hash = process_request.options_hash();
+
+        if !refresh_cache {
+            if let Some((mut metadata, knowledge_graph_cid)) =
+                self.cached_extraction(file_cid, &options_hash).await
+            {
+                info!("Using cached BioAgents extraction for {}", file_cid);
+                self.screen_for_pii(&mut metadata, file_cid);
+                return Ok((metadata, knowledge_graph_cid));
+            }
+        }
 
         let process_response = self
             .bioagents_service
@@ -464,20 +934,19 @@ impl ResearchPaperService {
         }
 
         if status.status == "failed" {
-            return Err(AppError::ExternalServiceError(format!(
-                "BioAgents processing failed: {:?}",
-                status.error
-            )));
+            return Err(self
+                .bioagents_service
+                .upstream_error(format!("BioAgents processing failed: {:?}", status.error)));
         }
 
         if status.status != "completed" {
-            return Err(AppError::ExternalServiceError(
-                "BioAgents processing timed out".to_string(),
-            ));
+            return Err(self
+                .bioagents_service
+                .upstream_error("BioAgents processing timed out".to_string()));
         }
 
         // Get the extracted metadata
-        let metadata = self
+        let mut metadata = self
             .bioagents_service
             .get_extracted_metadata(&task_id)
             .await?;
@@ -492,49 +961,621 @@ impl ResearchPaperService {
             None
         };
 
-        // Create the paper metadata
-        let paper_metadata = self
-            .create_paper_metadata(
-                metadata,
-                file_cid,
-                &did,
-                user_id,
-                knowledge_graph_cid.as_deref(),
-            )
-            .await?;
+        self.store_extraction(
+            file_cid,
+            &options_hash,
+            &metadata,
+            knowledge_graph_cid.as_deref(),
+        )
+        .await;
+        self.screen_for_pii(&mut metadata, file_cid);
 
-        // Update the DID document with the keywords from the metadata
-        if !paper_metadata.keywords.is_empty() {
-            let update_request = crate::models::did::DIDUpdateRequest {
-                controller: None,
-                add_verification_method: None,
-                remove_verification_method: None,
-                add_service: None,
-                remove_service: None,
-                update_metadata: Some(crate::models::did::BiometadataExtension {
-                    title: paper_metadata.title.clone(),
-                    description: Some(paper_metadata.abstract_text.clone()),
-                    researchers: did_doc.metadata.unwrap().researchers,
-                    keywords: paper_metadata.keywords.clone(),
-                    data_type: "Research Paper".to_string(),
-                    license: "CC-BY-4.0".to_string(),
-                    doi: paper_metadata.doi.clone(),
-                    handle: None,
-                    dataverse_link: None,
-                    related_identifiers: None,
-                    dataset_size: None,
-                    funding_info: None,
-                    creation_date: Utc::now(),
-                    last_modified: Utc::now(),
-                    custom_fields: None,
-                }),
-            };
+        Ok((metadata, knowledge_graph_cid))
+    }
+
+    /// Cached extraction of a paper, if any. Failures are logged and treated as a miss.
+    async fn cached_extraction(
+        &self,
+        file_cid: &str,
+        options_hash: &str,
+    ) -> Option<(ExtractedMetadata, Option<String>)> {
+        let mut conn = self
+            .db_pool
+            .get_conn()
+            .await
+            .inspect_err(|e| warn!("Failed to get database connection: {}", e))
+            .ok()?;
+
+        let row: Option<(String, Option<String>)> = r"SELECT metadata, knowledge_graph_cid
+            FROM bioagent_extraction_cache
+            WHERE file_cid = :file_cid AND options_hash = :options_hash"
+            .with(params! {
+                "file_cid" => file_cid,
+                "options_hash" => options_hash,
+            })
+            .first(&mut conn)
+            .await
+            .inspect_err(|e| warn!("Failed to read cached BioAgents extraction: {}", e))
+            .ok()?;
+        let (metadata, knowledge_graph_cid) = row?;
+        let metadata = serde_json::from_str(&metadata)
+            .inspect_err(|e| {
+                warn!(
+                    "Ignoring unreadable cached extraction of {}: {}",
+                    file_cid, e
+                )
+            })
+            .ok()?;
+        Some((metadata, knowledge_graph_cid))
+    }
+
+    /// Cache an extraction, replacing an earlier one for the same paper and options.
+    /// Failures are logged; the extraction is still returned to the caller.
+    async fn store_extraction(
+        &self,
+        file_cid: &str,
+        options_hash: &str,
+        metadata: &ExtractedMetadata,
+        knowledge_graph_cid: Option<&str>,
+    ) {
+        let Ok(metadata) = serde_json::to_string(metadata) else {
+            return;
+        };
+        let mut conn = match self.db_pool.get_conn().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Failed to get database connection: {}", e);
+                return;
+            }
+        };
+
+        let stored = r"REPLACE INTO bioagent_extraction_cache
+            (file_cid, options_hash, metadata, knowledge_graph_cid, created_at)
+          VALUES (:file_cid, :options_hash, :metadata, :knowledge_graph_cid, :created_at)"
+            .with(params! {
+                "file_cid" => file_cid,
+                "options_hash" => options_hash,
+                "metadata" => metadata,
+                "knowledge_graph_cid" => knowledge_graph_cid,
+                "created_at" => to_db(Utc::now()),
+            })
+            .ignore(&mut conn)
+            .await;
+        if let Err(e) = stored {
+            warn!(
+                "Failed to cache BioAgents extraction of {}: {}",
+                file_cid, e
+            );
+        }
+    }
+
+    /// Scan the abstract and entity mentions for personal data and, beyond the configured
+    /// threshold, redact it or flag the paper for review. Only the kinds and counts of
+    /// findings are logged.
+    fn screen_for_pii(&self, metadata: &mut ExtractedMetadata, file_cid: &str) {
+        let abstract_findings = self.pii_scanner.scan_for_pii(&metadata.abstract_text);
+        let mut all_findings = abstract_findings.clone();
+        let mut entity_findings = Vec::with_capacity(metadata.biological_entities.len());
+        for entity in &metadata.biological_entities {
+            let name = self.pii_scanner.scan_for_pii(&entity.name);
+            let mentions: Vec<_> = entity
+                .mentions
+                .iter()
+                .map(|mention| self.pii_scanner.scan_for_pii(&mention.text))
+                .collect();
+            all_findings.extend(name.iter().cloned());
+            all_findings.extend(mentions.iter().flatten().cloned());
+            entity_findings.push((name, mentions));
+        }
+
+        if all_findings.is_empty() {
+            return;
+        }
+        warn!(
+            "Possible personal data in paper {}: {}",
+            file_cid,
+            summarize(&all_findings)
+        );
+        if all_findings.len() <= self.pii_policy.threshold {
+            return;
+        }
+
+        match self.pii_policy.action {
+            PiiAction::Redact => {
+                metadata.abstract_text = redact(&metadata.abstract_text, &abstract_findings);
+                let mut kept = Vec::with_capacity(metadata.biological_entities.len());
+                for (mut entity, (name, mentions)) in
+                    metadata.biological_entities.drain(..).zip(entity_findings)
+                {
+                    // An entity named after personal data has nothing left worth keeping
+                    if !name.is_empty() {
+                        continue;
+                    }
+                    for (mention, findings) in entity.mentions.iter_mut().zip(&mentions) {
+                        mention.text = redact(&mention.text, findings);
+                    }
+                    kept.push(entity);
+                }
+                metadata.biological_entities = kept;
+                info!("Redacted personal data from paper {}", file_cid);
+            }
+            PiiAction::Flag => {
+                metadata.needs_review = true;
+                info!("Flagged paper {} for review", file_cid);
+            }
+        }
+    }
+
+    /// Papers flagged for personal data that are waiting for an admin, oldest first
+    pub async fn list_papers_needing_review(&self) -> Result<Vec<ResearchPaperMetadata>, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
 
-            self.did_service
-                .update_did(&did, update_request, user_id)
-                .await?;
+        let rows = "SELECT title, authors, abstract_text, doi, publication_date, journal, keywords, cid, did, biological_entities, knowledge_graph_cid, created_at, updated_at, needs_review FROM research_papers WHERE needs_review = TRUE ORDER BY created_at"
+            .with(())
+            .fetch::<PaperDbRow, _>(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing papers needing review: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        rows.into_iter().map(paper_from_row).collect()
+    }
+
+    /// Clear the review flag of the paper behind `did`, making it searchable again.
+    /// `NotFound` unless the paper is waiting for review.
+    pub async fn approve_paper(&self, did: &str) -> Result<ResearchPaperMetadata, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let updated_at = to_db(Utc::now());
+        "UPDATE research_papers SET needs_review = FALSE, updated_at = :updated_at
+         WHERE did = :did AND needs_review = TRUE"
+            .with(params! {
+                "did" => did,
+                "updated_at" => &updated_at,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when approving research paper: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        if conn.affected_rows() == 0 {
+            return Err(AppError::NotFound(format!(
+                "No research paper awaiting review for DID: {}",
+                did
+            )));
         }
 
-        Ok(did)
+        info!("Research paper {} approved after review", did);
+        self.get_paper_metadata_by_did(did).await
+    }
+}
+
+/// Convert a `research_papers` row, parsing its JSON fields and timestamps
+fn paper_from_row(row: PaperDbRow) -> Result<ResearchPaperMetadata, AppError> {
+    let authors: Vec<String> =
+        serde_json::from_str(&row.authors).map_err(|_| AppError::DeserializationError)?;
+    let keywords: Vec<String> =
+        serde_json::from_str(&row.keywords).map_err(|_| AppError::DeserializationError)?;
+    let biological_entities: Vec<BiologicalEntityReference> =
+        serde_json::from_str(&row.biological_entities)
+            .map_err(|_| AppError::DeserializationError)?;
+
+    Ok(ResearchPaperMetadata {
+        title: row.title,
+        authors,
+        abstract_text: row.abstract_text,
+        doi: row.doi,
+        publication_date: row.publication_date,
+        journal: row.journal,
+        keywords,
+        cid: row.cid,
+        did: row.did,
+        biological_entities,
+        knowledge_graph_cid: row.knowledge_graph_cid,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+        needs_review: row.needs_review,
+    })
+}
+
+/// A paper compared against by `find_related`
+struct RelatedCandidate {
+    did: String,
+    title: String,
+    keywords: Vec<String>,
+    biological_entities: Vec<BiologicalEntityReference>,
+}
+
+/// Comparable form of an entity's identifier, qualified by its source since the same
+/// accession can mean different things in different databases
+fn entity_key(entity: &BiologicalEntityReference) -> Option<(String, String)> {
+    let identifier = entity.identifier.as_deref()?.trim();
+    if identifier.is_empty() {
+        return None;
+    }
+    Some((
+        entity
+            .source
+            .as_deref()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+        identifier.to_ascii_lowercase(),
+    ))
+}
+
+/// `candidates` scored by the entities and keywords they share with `paper`, keeping the
+/// best `limit` with any overlap. Ties go to more shared entities, then to the DID.
+fn rank_related(
+    paper: &ResearchPaperMetadata,
+    candidates: Vec<RelatedCandidate>,
+    vocabulary: &KeywordVocabulary,
+    limit: usize,
+) -> Vec<RelatedPaper> {
+    // Each entity and keyword of the paper once, by its comparable form
+    let mut entities: Vec<((String, String), &BiologicalEntityReference)> = Vec::new();
+    for entity in &paper.biological_entities {
+        if let Some(key) = entity_key(entity) {
+            if !entities.iter().any(|(known, _)| *known == key) {
+                entities.push((key, entity));
+            }
+        }
+    }
+    let mut keywords: Vec<(String, &String)> = Vec::new();
+    for keyword in &paper.keywords {
+        let normalized = vocabulary.normalize(keyword);
+        if !normalized.is_empty() && !keywords.iter().any(|(known, _)| *known == normalized) {
+            keywords.push((normalized, keyword));
+        }
+    }
+    if entities.is_empty() && keywords.is_empty() {
+        return Vec::new();
+    }
+
+    let mut related: Vec<RelatedPaper> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.did != paper.did)
+        .filter_map(|candidate| {
+            let candidate_entities: HashSet<(String, String)> = candidate
+                .biological_entities
+                .iter()
+                .filter_map(entity_key)
+                .collect();
+            let candidate_keywords: HashSet<String> = candidate
+                .keywords
+                .iter()
+                .map(|keyword| vocabulary.normalize(keyword))
+                .collect();
+
+            let shared_entities: Vec<BiologicalEntityReference> = entities
+                .iter()
+                .filter(|(key, _)| candidate_entities.contains(key))
+                .map(|(_, entity)| (*entity).clone())
+                .collect();
+            let shared_keywords: Vec<String> = keywords
+                .iter()
+                .filter(|(normalized, _)| candidate_keywords.contains(normalized))
+                .map(|(_, keyword)| (*keyword).clone())
+                .collect();
+            let score =
+                shared_entities.len() as u32 * SHARED_ENTITY_WEIGHT + shared_keywords.len() as u32;
+            (score > 0).then_some(RelatedPaper {
+                did: candidate.did,
+                title: candidate.title,
+                score,
+                shared_entities,
+                shared_keywords,
+            })
+        })
+        .collect();
+
+    related.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| b.shared_entities.len().cmp(&a.shared_entities.len()))
+            .then_with(|| a.did.cmp(&b.did))
+    });
+    related.truncate(limit);
+    related
+}
+
+/// DID metadata patch carrying a processed paper's title, abstract, keywords and DOI
+fn paper_did_patch(
+    paper: &ResearchPaperMetadata,
+    keywords_auto_generated: bool,
+) -> serde_json::Value {
+    let mut patch = serde_json::json!({
+        "title": paper.title,
+        "description": paper.abstract_text,
+        "keywords": paper.keywords,
+        "doi": paper.doi,
+    });
+    if keywords_auto_generated {
+        patch["custom_fields"] = serde_json::json!({ KEYWORDS_AUTO_GENERATED_FIELD: true });
+    }
+    // DID documents are public on IPFS, so abstracts awaiting review stay out of them
+    if paper.needs_review {
+        if let Some(patch) = patch.as_object_mut() {
+            patch.remove("description");
+        }
+    }
+    patch
+}
+
+/// `stored` with the fields of a new extraction, except those in `preserve`. The paper
+/// stays flagged for review if its kept abstract was, and is flagged if the extraction is.
+fn merge_reprocessed(
+    stored: ResearchPaperMetadata,
+    extracted: ExtractedMetadata,
+    biological_entities: Vec<BiologicalEntityReference>,
+    knowledge_graph_cid: Option<String>,
+    preserve: &[PaperField],
+) -> ResearchPaperMetadata {
+    let keep = |field: PaperField| preserve.contains(&field);
+    ResearchPaperMetadata {
+        title: if keep(PaperField::Title) {
+            stored.title
+        } else {
+            extracted.title
+        },
+        authors: if keep(PaperField::Authors) {
+            stored.authors
+        } else {
+            extracted.authors
+        },
+        abstract_text: if keep(PaperField::Abstract) {
+            stored.abstract_text
+        } else {
+            extracted.abstract_text
+        },
+        doi: if keep(PaperField::Doi) {
+            stored.doi
+        } else {
+            extracted.doi
+        },
+        publication_date: if keep(PaperField::PublicationDate) {
+            stored.publication_date
+        } else {
+            extracted.publication_date
+        },
+        journal: if keep(PaperField::Journal) {
+            stored.journal
+        } else {
+            extracted.journal
+        },
+        keywords: if keep(PaperField::Keywords) {
+            stored.keywords
+        } else {
+            extracted.keywords
+        },
+        cid: stored.cid,
+        did: stored.did,
+        biological_entities,
+        // A graph BioAgents didn't regenerate is kept
+        knowledge_graph_cid: knowledge_graph_cid.or(stored.knowledge_graph_cid),
+        created_at: stored.created_at,
+        updated_at: Utc::now(),
+        needs_review: extracted.needs_review || (keep(PaperField::Abstract) && stored.needs_review),
+    }
+}
+
+/// Fill in keywords extracted from the title and abstract when BioAgents found none
+fn fill_missing_keywords(metadata: &mut ExtractedMetadata) {
+    if !metadata.keywords.is_empty() {
+        return;
+    }
+    // Keywords end up in the public DID document, so a flagged abstract isn't used
+    let text = if metadata.needs_review {
+        metadata.title.clone()
+    } else {
+        format!("{}. {}", metadata.title, metadata.abstract_text)
+    };
+    metadata.keywords = extract_keywords(&text, FALLBACK_KEYWORD_COUNT);
+    metadata.keywords_auto_generated = !metadata.keywords.is_empty();
+    if metadata.keywords_auto_generated {
+        info!(
+            "BioAgents returned no keywords for '{}', generated {} locally",
+            metadata.title,
+            metadata.keywords.len()
+        );
+    }
+}
+
+/// Initial DID metadata for a paper, before BioAgents extraction fills in keywords
+fn paper_did_metadata(
+    title: &str,
+    authors: &[String],
+    doi: Option<&str>,
+    license: &License,
+) -> BiometadataExtension {
+    BiometadataExtension {
+        title: title.to_string(),
+        description: Some(format!("Research paper: {}", title)),
+        researchers: authors
+            .iter()
+            .map(|author| Researcher {
+                name: author.clone(),
+                orcid: None,
+                role: "Author".to_string(),
+                affiliation: None,
+                email: None,
+            })
+            .collect(),
+        // Will be updated after processing
+        keywords: Vec::new(),
+        data_type: "Research Paper".to_string(),
+        license: license.to_string(),
+        doi: doi.map(|d| d.to_string()),
+        handle: None,
+        dataverse_link: None,
+        related_identifiers: None,
+        dataset_size: None,
+        funding_info: None,
+        creation_date: Utc::now(),
+        last_modified: Utc::now(),
+        custom_fields: None,
+        embargo_until: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reprocessing_keeps_preserved_fields() {
+        let stored = ResearchPaperMetadata {
+            title: "Corrected title".to_string(),
+            authors: vec!["A. Author".to_string()],
+            abstract_text: "Old abstract".to_string(),
+            doi: Some("10.1000/old".to_string()),
+            publication_date: None,
+            journal: Some("Old Journal".to_string()),
+            keywords: vec!["curated".to_string()],
+            cid: "QmPaper".to_string(),
+            did: "did:bio:1".to_string(),
+            biological_entities: Vec::new(),
+            knowledge_graph_cid: Some("QmOldGraph".to_string()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            needs_review: false,
+        };
+        let extracted = ExtractedMetadata {
+            title: "Extracted title".to_string(),
+            authors: vec!["A. Author".to_string(), "B. Author".to_string()],
+            abstract_text: "New abstract".to_string(),
+            keywords: vec!["proteomics".to_string()],
+            publication_date: Some("2024-01-01".to_string()),
+            journal: Some("New Journal".to_string()),
+            doi: Some("10.1000/new".to_string()),
+            biological_entities: Vec::new(),
+            keywords_auto_generated: false,
+            needs_review: false,
+        };
+
+        let paper = merge_reprocessed(
+            stored.clone(),
+            extracted,
+            Vec::new(),
+            None,
+            &[PaperField::Title, PaperField::Keywords],
+        );
+        assert_eq!(paper.title, "Corrected title");
+        assert_eq!(paper.keywords, vec!["curated"]);
+        assert_eq!(paper.abstract_text, "New abstract");
+        assert_eq!(paper.authors.len(), 2);
+        assert_eq!(paper.doi.as_deref(), Some("10.1000/new"));
+        assert_eq!(paper.publication_date.as_deref(), Some("2024-01-01"));
+        assert_eq!(paper.cid, stored.cid);
+        assert_eq!(paper.created_at, stored.created_at);
+        // BioAgents returned no new graph, so the old one stays
+        assert_eq!(paper.knowledge_graph_cid.as_deref(), Some("QmOldGraph"));
+
+        let mut patch = paper_did_patch(&paper, false);
+        assert_eq!(patch["description"], "New abstract");
+        let flagged = ResearchPaperMetadata {
+            needs_review: true,
+            ..paper
+        };
+        patch = paper_did_patch(&flagged, true);
+        assert!(patch.get("description").is_none());
+        assert_eq!(patch["custom_fields"][KEYWORDS_AUTO_GENERATED_FIELD], true);
+    }
+
+    #[test]
+    fn test_related_papers_rank_by_shared_entities_and_keywords() {
+        let entity =
+            |name: &str, identifier: Option<&str>, source: &str| BiologicalEntityReference {
+                entity_type: "protein".to_string(),
+                name: name.to_string(),
+                identifier: identifier.map(str::to_string),
+                source: Some(source.to_string()),
+            };
+        let candidate = |did: &str, keywords: &[&str], entities| RelatedCandidate {
+            did: did.to_string(),
+            title: format!("Paper {}", did),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            biological_entities: entities,
+        };
+        let paper = ResearchPaperMetadata {
+            title: "p53 in yeast".to_string(),
+            authors: Vec::new(),
+            abstract_text: String::new(),
+            doi: None,
+            publication_date: None,
+            journal: None,
+            keywords: vec!["Machine-Learning".to_string(), "Proteomics".to_string()],
+            cid: "QmPaper".to_string(),
+            did: "did:bio:self".to_string(),
+            biological_entities: vec![
+                entity("p53", Some("P04637"), "UniProt"),
+                entity("BRCA1", Some("672"), "NCBI"),
+                entity("unresolved", None, "UniProt"),
+            ],
+            knowledge_graph_cid: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            needs_review: false,
+        };
+
+        let related = rank_related(
+            &paper,
+            vec![
+                candidate("did:bio:self", &["proteomics"], Vec::new()),
+                candidate(
+                    "did:bio:keywords",
+                    &["machine learning", "proteomics"],
+                    Vec::new(),
+                ),
+                candidate(
+                    "did:bio:entity",
+                    &[],
+                    vec![entity("TP53", Some("p04637"), "uniprot")],
+                ),
+                // Same accession in another database doesn't count
+                candidate(
+                    "did:bio:other-source",
+                    &[],
+                    vec![entity("x", Some("672"), "UniProt")],
+                ),
+                candidate(
+                    "did:bio:unrelated",
+                    &["genomics"],
+                    vec![entity("unresolved", None, "UniProt")],
+                ),
+                candidate(
+                    "did:bio:both",
+                    &["Proteomics"],
+                    vec![entity("BRCA1", Some("672"), "NCBI")],
+                ),
+            ],
+            &KeywordVocabulary::default(),
+            3,
+        );
+
+        let ranked: Vec<(&str, u32)> = related.iter().map(|r| (r.did.as_str(), r.score)).collect();
+        assert_eq!(
+            ranked,
+            vec![
+                ("did:bio:both", 3),
+                ("did:bio:entity", 2),
+                ("did:bio:keywords", 2)
+            ]
+        );
+        assert_eq!(related[0].shared_keywords, vec!["Proteomics"]);
+        assert_eq!(related[0].shared_entities[0].name, "BRCA1");
+        assert_eq!(related[1].shared_entities[0].name, "p53");
+        assert_eq!(
+            related[2].shared_keywords,
+            vec!["Machine-Learning", "Proteomics"]
+        );
     }
 }
@@ -0,0 +1,338 @@
+use crate::errors::AppError;
+use crate::services::ipfs_service::IPFSService;
+use log::{error, info};
+use lru::LruCache;
+use oxigraph::io::RdfFormat;
+use oxigraph::sparql::results::{QueryResultsFormat, QueryResultsSerializer};
+use oxigraph::sparql::{
+    CancellationToken, PreparedSparqlQuery, QueryEvaluationError, QueryResults, SparqlEvaluator,
+};
+use oxigraph::store::Store;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Media type of SPARQL JSON results
+pub const SPARQL_RESULTS_MEDIA_TYPE: &str = "application/sparql-results+json";
+
+/// Number of parsed knowledge graphs kept in memory, and for how long. Graphs on IPFS never
+/// change, so the expiry only bounds how long a large graph holds on to memory.
+const STORE_CACHE_SIZE: usize = 16;
+const STORE_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Outcome of a query that didn't produce results
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueryFailure {
+    // The query is malformed, an update, or asks for something unsupported
+    Invalid(String),
+    Cancelled,
+}
+
+/// Parse `text` as a read-only SPARQL query, refusing updates. `SERVICE` fails when
+/// evaluated, since oxigraph is built without its HTTP client.
+pub fn parse_query(
+    text: &str,
+    cancellation: CancellationToken,
+) -> Result<PreparedSparqlQuery, String> {
+    match SparqlEvaluator::new()
+        .with_cancellation_token(cancellation)
+        .parse_query(text)
+    {
+        Ok(query) => Ok(query),
+        Err(_) if SparqlEvaluator::new().parse_update(text).is_ok() => {
+            Err("Knowledge graphs are read-only; updates aren't allowed".to_string())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Run `query` against `store` in SPARQL JSON results form. Only `SELECT` and `ASK`
+/// queries are answered, and a `SELECT` returning more than `max_results` rows fails.
+pub fn evaluate(
+    query: PreparedSparqlQuery,
+    store: &Store,
+    max_results: usize,
+) -> Result<serde_json::Value, QueryFailure> {
+    let failure = |e: QueryEvaluationError| match e {
+        QueryEvaluationError::Cancelled => QueryFailure::Cancelled,
+        e => QueryFailure::Invalid(e.to_string()),
+    };
+    let serializer = QueryResultsSerializer::from_format(QueryResultsFormat::Json);
+    let written = match query.on_store(store).execute().map_err(failure)? {
+        QueryResults::Boolean(value) => serializer.serialize_boolean_to_writer(Vec::new(), value),
+        QueryResults::Solutions(solutions) => {
+            let mut writer = serializer
+                .serialize_solutions_to_writer(Vec::new(), solutions.variables().to_vec())
+                .map_err(|e| QueryFailure::Invalid(e.to_string()))?;
+            for (row, solution) in solutions.enumerate() {
+                if row == max_results {
+                    return Err(QueryFailure::Invalid(format!(
+                        "Query returns more than {} results; add a LIMIT or narrow it",
+                        max_results
+                    )));
+                }
+                writer
+                    .serialize(&solution.map_err(failure)?)
+                    .map_err(|e| QueryFailure::Invalid(e.to_string()))?;
+            }
+            writer.finish()
+        }
+        QueryResults::Graph(_) => {
+            return Err(QueryFailure::Invalid(
+                "Only SELECT and ASK queries are supported".to_string(),
+            ))
+        }
+    }
+    .map_err(|e| QueryFailure::Invalid(e.to_string()))?;
+    serde_json::from_slice(&written).map_err(|e| QueryFailure::Invalid(e.to_string()))
+}
+
+/// Load Turtle or N-Triples `text` into an in-memory store
+pub fn load_graph(text: &str) -> Result<Store, String> {
+    let store = Store::new().map_err(|e| e.to_string())?;
+    store
+        .load_from_slice(RdfFormat::Turtle, text)
+        .map_err(|e| e.to_string())?;
+    Ok(store)
+}
+
+struct CachedStore {
+    store: Store,
+    loaded_at: Instant,
+}
+
+/// Read-only SPARQL queries over knowledge graphs stored on IPFS.
+///
+/// Graphs are fetched and loaded into an in-memory oxigraph store on first use and kept for
+/// a few minutes, since a CID's content never changes. Queries run on the blocking thread
+/// pool and are cancelled at their deadline, and `SELECT` results are capped.
+pub struct SparqlService {
+    ipfs_service: Arc<IPFSService>,
+    stores: Mutex<LruCache<String, CachedStore>>,
+    timeout: Duration,
+    max_results: usize,
+}
+
+impl SparqlService {
+    pub fn new(ipfs_service: Arc<IPFSService>, timeout: Duration, max_results: usize) -> Self {
+        Self {
+            ipfs_service,
+            stores: Mutex::new(LruCache::new(
+                NonZeroUsize::new(STORE_CACHE_SIZE).expect("cache size is non-zero"),
+            )),
+            timeout,
+            max_results,
+        }
+    }
+
+    /// Run `query` against the knowledge graph stored at `cid`, in SPARQL JSON results form
+    pub async fn query(&self, cid: &str, query: &str) -> Result<serde_json::Value, AppError> {
+        let cancellation = CancellationToken::new();
+        let query = parse_query(query, cancellation.clone())
+            .map_err(|e| AppError::ValidationError(format!("Invalid SPARQL query: {}", e)))?;
+        let store = self.load_store(cid).await?;
+
+        let max_results = self.max_results;
+        let started = Instant::now();
+        let task = tokio::task::spawn_blocking(move || evaluate(query, &store, max_results));
+        let results = match tokio::time::timeout(self.timeout, task).await {
+            Ok(joined) => joined.map_err(|e| {
+                error!("SPARQL query task failed: {}", e);
+                AppError::ServiceError("SPARQL query failed".to_string())
+            })?,
+            // The evaluation stops at its next cancellation check
+            Err(_) => {
+                cancellation.cancel();
+                Err(QueryFailure::Cancelled)
+            }
+        }
+        .map_err(|failure| match failure {
+            QueryFailure::Invalid(e) => AppError::ValidationError(e),
+            QueryFailure::Cancelled => AppError::ValidationError(format!(
+                "Query took longer than {} seconds",
+                self.timeout.as_secs_f64()
+            )),
+        })?;
+
+        info!(
+            "Ran SPARQL query on knowledge graph {} in {} ms",
+            cid,
+            started.elapsed().as_millis()
+        );
+        Ok(results)
+    }
+
+    async fn load_store(&self, cid: &str) -> Result<Store, AppError> {
+        {
+            let mut stores = self.stores.lock().unwrap_or_else(PoisonError::into_inner);
+            match stores.get(cid) {
+                Some(cached) if cached.loaded_at.elapsed() < STORE_CACHE_TTL => {
+                    return Ok(cached.store.clone())
+                }
+                Some(_) => {
+                    stores.pop(cid);
+                }
+                None => {}
+            }
+        }
+
+        let content = self.ipfs_service.get_file_content(cid).await?;
+        let text = String::from_utf8(content).map_err(|_| {
+            AppError::ValidationError(format!("Knowledge graph {} isn't RDF text", cid))
+        })?;
+        let store = tokio::task::spawn_blocking(move || load_graph(&text))
+            .await
+            .map_err(|e| {
+                error!("Knowledge graph loading task failed: {}", e);
+                AppError::ServiceError("Failed to load knowledge graph".to_string())
+            })?
+            .map_err(|e| {
+                AppError::ValidationError(format!(
+                    "Knowledge graph {} isn't valid Turtle or N-Triples: {}",
+                    cid, e
+                ))
+            })?;
+        info!(
+            "Loaded knowledge graph {} ({} triples)",
+            cid,
+            store.len().unwrap_or_default()
+        );
+
+        self.stores
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(
+                cid.to_string(),
+                CachedStore {
+                    store: store.clone(),
+                    loaded_at: Instant::now(),
+                },
+            );
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const GRAPH: &str = r#"@prefix ex: <http://example.org/> .
+        ex:BRCA1 a ex:Gene ; ex:label "BRCA1" ; ex:associatedWith ex:BreastCancer .
+        ex:TP53 a ex:Gene ; ex:label "TP53" ; ex:associatedWith ex:BreastCancer, ex:LungCancer .
+        ex:BreastCancer a ex:Disease ; ex:label "breast cancer"@en ."#;
+
+    fn run(query: &str, max_results: usize) -> Result<serde_json::Value, String> {
+        let store = load_graph(GRAPH).unwrap();
+        let query = parse_query(query, CancellationToken::new())?;
+        evaluate(query, &store, max_results).map_err(|failure| match failure {
+            QueryFailure::Invalid(e) => e,
+            QueryFailure::Cancelled => "cancelled".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_select_joins_patterns() {
+        let results = run(
+            r#"PREFIX ex: <http://example.org/>
+            SELECT ?gene ?name WHERE {
+                ?gene a ex:Gene ; ex:associatedWith ?disease .
+                ?disease ex:label "breast cancer"@en .
+                ?gene ex:label ?name
+            } LIMIT 10"#,
+            100,
+        )
+        .unwrap();
+
+        assert_eq!(results["head"]["vars"], json!(["gene", "name"]));
+        let mut names: Vec<&str> = results["results"]["bindings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|binding| binding["name"]["value"].as_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["BRCA1", "TP53"]);
+        assert_eq!(
+            results["results"]["bindings"][0]["gene"]["type"],
+            json!("uri")
+        );
+    }
+
+    #[test]
+    fn test_ask_distinct_limit_and_offset() {
+        let ask =
+            "PREFIX ex: <http://example.org/> ASK { ex:TP53 ex:associatedWith ex:LungCancer }";
+        assert_eq!(run(ask, 100).unwrap()["boolean"], json!(true));
+        let ask = "ASK { <http://example.org/BRCA1> ?p <http://example.org/LungCancer> }";
+        assert_eq!(run(ask, 100).unwrap()["boolean"], json!(false));
+
+        let count = |query: &str| {
+            run(query, 100).unwrap()["results"]["bindings"]
+                .as_array()
+                .unwrap()
+                .len()
+        };
+        let diseases =
+            "PREFIX ex: <http://example.org/> SELECT ?d WHERE { ?g ex:associatedWith ?d }";
+        assert_eq!(count(diseases), 3);
+        assert_eq!(count(&diseases.replace("SELECT", "SELECT DISTINCT")), 2);
+        assert_eq!(count(&format!("{} LIMIT 2 OFFSET 2", diseases)), 1);
+    }
+
+    #[test]
+    fn test_filter_optional_and_aggregates() {
+        let results = run(
+            r#"PREFIX ex: <http://example.org/>
+            SELECT ?gene (COUNT(?disease) AS ?diseases) WHERE {
+                ?gene a ex:Gene ; ex:associatedWith ?disease .
+                OPTIONAL { ?disease ex:label ?label }
+                FILTER (!BOUND(?label) || LANG(?label) = "en")
+            } GROUP BY ?gene ORDER BY DESC(?diseases)"#,
+            100,
+        )
+        .unwrap();
+
+        let bindings = results["results"]["bindings"].as_array().unwrap();
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(
+            bindings[0]["gene"]["value"],
+            json!("http://example.org/TP53")
+        );
+        assert_eq!(bindings[0]["diseases"]["value"], json!("2"));
+    }
+
+    #[test]
+    fn test_rejects_updates_unsupported_queries_and_large_results() {
+        let update = "INSERT DATA { <http://a> <http://b> <http://c> }";
+        assert!(run(update, 100).unwrap_err().contains("read-only"));
+        let delete = "PREFIX ex: <http://example.org/> DELETE WHERE { ?s ?p ?o }";
+        assert!(run(delete, 100).unwrap_err().contains("read-only"));
+        let construct = "CONSTRUCT { ?s ?p ?o } WHERE { ?s ?p ?o }";
+        assert_eq!(
+            run(construct, 100).unwrap_err(),
+            "Only SELECT and ASK queries are supported"
+        );
+        assert!(run("SELECT ?s WHERE { ?s ?p }", 100).is_err());
+
+        let everything = "SELECT * WHERE { ?s ?p ?o }";
+        assert!(run(everything, 5)
+            .unwrap_err()
+            .contains("more than 5 results"));
+        assert!(run(&format!("{} LIMIT 5", everything), 5).is_ok());
+    }
+
+    #[test]
+    fn test_query_past_its_deadline_fails() {
+        let store = load_graph(GRAPH).unwrap();
+        let cancellation = CancellationToken::new();
+        let query = parse_query(
+            "SELECT * WHERE { ?a ?b ?c . ?d ?e ?f . ?g ?h ?i } ORDER BY ?a",
+            cancellation.clone(),
+        )
+        .unwrap();
+        cancellation.cancel();
+        let result = evaluate(query, &store, usize::MAX);
+        assert_eq!(result.unwrap_err(), QueryFailure::Cancelled);
+    }
+}
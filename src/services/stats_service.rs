@@ -0,0 +1,155 @@
+use crate::errors::AppError;
+use log::error;
+use lru::LruCache;
+use mysql_async::{prelude::*, Pool};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long a user's statistics are served from memory before being counted again
+const STATS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Users whose statistics are cached at once
+const STATS_CACHE_SIZE: usize = 1024;
+
+/// Totals across everything a user has stored
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct UserStats {
+    // DIDs and papers stored
+    pub dids: u64,
+    pub papers: u64,
+    // DIDs linked to a Dataverse dataset
+    pub datasets_published: u64,
+    // Uploaded files and the sum of their sizes
+    pub files: u64,
+    pub storage_bytes: u64,
+    // Upload, BioAgents and background tasks, by status
+    pub tasks: BTreeMap<String, u64>,
+}
+
+struct CachedStats {
+    stats: UserStats,
+    counted_at: Instant,
+}
+
+/// Aggregate statistics per user, counted with a few aggregate queries and cached briefly
+pub struct StatsService {
+    db_pool: Arc<Pool>,
+    cache: Mutex<LruCache<i64, CachedStats>>,
+}
+
+impl StatsService {
+    pub fn new(db_pool: Arc<Pool>) -> Self {
+        Self {
+            db_pool,
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(STATS_CACHE_SIZE).expect("cache size is non-zero"),
+            )),
+        }
+    }
+
+    /// The user's totals, at most `STATS_CACHE_TTL` old
+    pub async fn get_user_stats(&self, user_id: i64) -> Result<UserStats, AppError> {
+        {
+            let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(cached) = cache.get(&user_id) {
+                if cached.counted_at.elapsed() < STATS_CACHE_TTL {
+                    return Ok(cached.stats.clone());
+                }
+            }
+        }
+
+        let stats = self.count(user_id).await?;
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(
+                user_id,
+                CachedStats {
+                    stats: stats.clone(),
+                    counted_at: Instant::now(),
+                },
+            );
+        Ok(stats)
+    }
+
+    async fn count(&self, user_id: i64) -> Result<UserStats, AppError> {
+        let stats_error = |e: mysql_async::Error| {
+            error!("Database error when counting user statistics: {}", e);
+            AppError::DatabaseError(e.to_string())
+        };
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let totals: Option<(u64, u64, u64, u64, u64)> = r"SELECT
+                (SELECT COUNT(*) FROM did_documents WHERE user_id = :user_id),
+                (SELECT COUNT(dataverse_doi) FROM did_documents WHERE user_id = :user_id),
+                (SELECT COUNT(*) FROM research_papers WHERE user_id = :user_id),
+                (SELECT COUNT(*) FROM file_metadata WHERE user_id = :user_id),
+                (SELECT CAST(COALESCE(SUM(size), 0) AS UNSIGNED) FROM file_metadata
+                  WHERE user_id = :user_id)"
+            .with(params! { "user_id" => user_id })
+            .first(&mut conn)
+            .await
+            .map_err(stats_error)?;
+        let (dids, datasets_published, papers, files, storage_bytes) = totals.unwrap_or_default();
+
+        let tasks: Vec<(String, u64)> = r"SELECT status, COUNT(*) FROM upload_tasks
+                WHERE user_id = :user_id GROUP BY status
+              UNION ALL SELECT status, COUNT(*) FROM bioagent_tasks
+                WHERE user_id = :user_id GROUP BY status
+              UNION ALL SELECT status, COUNT(*) FROM background_tasks
+                WHERE user_id = :user_id GROUP BY status"
+            .with(params! { "user_id" => user_id })
+            .fetch(&mut conn)
+            .await
+            .map_err(stats_error)?;
+
+        Ok(UserStats {
+            dids,
+            papers,
+            datasets_published,
+            files,
+            storage_bytes,
+            tasks: tally_tasks(tasks),
+        })
+    }
+}
+
+/// Add up per-table task counts sharing a status
+fn tally_tasks(counts: Vec<(String, u64)>) -> BTreeMap<String, u64> {
+    let mut tasks = BTreeMap::new();
+    for (status, count) in counts {
+        *tasks.entry(status).or_insert(0) += count;
+    }
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_counts_are_merged_by_status() {
+        let tasks = tally_tasks(vec![
+            ("completed".to_string(), 3),
+            ("failed".to_string(), 1),
+            ("completed".to_string(), 2),
+            ("pending".to_string(), 4),
+        ]);
+
+        assert_eq!(
+            tasks.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("completed".to_string(), 5),
+                ("failed".to_string(), 1),
+                ("pending".to_string(), 4),
+            ]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::database::db_time::to_db;
+use crate::errors::{AppError, REAUTHENTICATION_REQUIRED};
+use crate::models::auth::AuthUser;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool};
+use rand_core::{OsRng, RngCore};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha1::Sha1;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Request header carrying a TOTP code for an operation that needs step-up
+pub const TOTP_CODE_HEADER: &str = "X-TOTP-Code";
+
+/// Issuer shown by authenticator apps for enrolled accounts
+const TOTP_ISSUER: &str = "Bio-DID-Seq";
+
+/// RFC 6238 parameters: 30 second steps, 6 digit codes, and a code from the step before
+/// or after the current one is accepted to allow for clock drift
+const TOTP_STEP_SECS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_DRIFT_STEPS: i64 = 1;
+
+/// Bytes of a generated TOTP secret, the size of an HMAC-SHA1 key
+const TOTP_SECRET_BYTES: usize = 20;
+
+/// Irreversible operations that can be configured to need step-up verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StepUpOperation {
+    // Giving a DID and its papers to another user
+    TransferOwnership,
+    // Retiring a signing key version, which invalidates the tokens it signed
+    RetireKey,
+}
+
+impl StepUpOperation {
+    pub const ALL: [StepUpOperation; 2] = [
+        StepUpOperation::TransferOwnership,
+        StepUpOperation::RetireKey,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepUpOperation::TransferOwnership => "transfer_ownership",
+            StepUpOperation::RetireKey => "retire_key",
+        }
+    }
+
+    /// Parse an operation name, in any case
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let value = value.trim();
+        StepUpOperation::ALL
+            .iter()
+            .find(|operation| operation.as_str().eq_ignore_ascii_case(value))
+            .copied()
+            .ok_or_else(|| {
+                AppError::ValidationError(format!(
+                    "Unknown step-up operation '{}', expected one of: {}",
+                    value,
+                    StepUpOperation::ALL
+                        .iter()
+                        .map(StepUpOperation::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })
+    }
+}
+
+/// A new TOTP secret, to be added to an authenticator app and confirmed with a code
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TotpEnrollment {
+    // Base32, for entering by hand
+    pub secret: String,
+    // `otpauth://` URI, usually shown as a QR code
+    pub otpauth_uri: String,
+}
+
+/// Whether the caller's TOTP secret has been confirmed and is used for step-up
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TotpStatus {
+    pub enabled: bool,
+}
+
+/// Step-up verification for irreversible operations: the caller must have signed in within
+/// `max_age`, or send a code from the TOTP secret stored for them in `user_totp`
+pub struct StepUpService {
+    db_pool: Arc<Pool>,
+    operations: HashSet<StepUpOperation>,
+    max_age: Duration,
+}
+
+impl StepUpService {
+    pub fn new(
+        db_pool: Arc<Pool>,
+        operations: HashSet<StepUpOperation>,
+        max_age: Duration,
+    ) -> Self {
+        Self {
+            db_pool,
+            operations,
+            max_age,
+        }
+    }
+
+    /// Fail with `AuthError(REAUTHENTICATION_REQUIRED)` unless `operation` doesn't need
+    /// step-up, the user's session is recent enough, or `code` is a valid TOTP code of theirs
+    pub async fn require(
+        &self,
+        user: &AuthUser,
+        operation: StepUpOperation,
+        code: Option<&str>,
+    ) -> Result<(), AppError> {
+        if !self.operations.contains(&operation) {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        let recent = user.authenticated_at.is_some_and(|at| {
+            now.signed_duration_since(at)
+                .to_std()
+                .is_ok_and(|age| age <= self.max_age)
+        });
+        if recent {
+            return Ok(());
+        }
+
+        if let Some(code) = code {
+            if self.accept_code(user.id, code, true).await? {
+                return Ok(());
+            }
+            warn!(
+                "Invalid TOTP code from user {} for {}",
+                user.id,
+                operation.as_str()
+            );
+        }
+        Err(AppError::AuthError(REAUTHENTICATION_REQUIRED.to_string()))
+    }
+
+    /// Generate a new secret for the user, replacing one that was never confirmed. Fails with
+    /// `Conflict` once a secret is confirmed.
+    pub async fn enroll(&self, user: &AuthUser) -> Result<TotpEnrollment, AppError> {
+        let mut secret = [0u8; TOTP_SECRET_BYTES];
+        OsRng.fill_bytes(&mut secret);
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        r"INSERT INTO user_totp (user_id, secret, created_at) VALUES (:user_id, :secret, :now)
+            ON DUPLICATE KEY UPDATE
+              secret = IF(confirmed_at IS NULL, VALUES(secret), secret),
+              created_at = IF(confirmed_at IS NULL, VALUES(created_at), created_at)"
+            .with(params! {
+                "user_id" => user.id,
+                "secret" => secret.to_vec(),
+                "now" => to_db(Utc::now()),
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(totp_error)?;
+
+        let stored: Option<Vec<u8>> = "SELECT secret FROM user_totp WHERE user_id = :user_id"
+            .with(params! { "user_id" => user.id })
+            .first(&mut conn)
+            .await
+            .map_err(totp_error)?;
+        if stored.as_deref() != Some(&secret[..]) {
+            return Err(AppError::Conflict("TOTP is already enabled".to_string()));
+        }
+
+        let encoded = base32_encode(&secret);
+        Ok(TotpEnrollment {
+            otpauth_uri: format!(
+                "otpauth://totp/{issuer}:{user}?secret={secret}&issuer={issuer}&digits={digits}&period={period}",
+                issuer = TOTP_ISSUER,
+                user = percent_encode(&user.username),
+                secret = encoded,
+                digits = TOTP_DIGITS,
+                period = TOTP_STEP_SECS,
+            ),
+            secret: encoded,
+        })
+    }
+
+    /// Check a code from the user's authenticator, confirming their enrollment if it wasn't
+    /// yet. Fails with `AuthError` when the code is wrong.
+    pub async fn verify(&self, user: &AuthUser, code: &str) -> Result<TotpStatus, AppError> {
+        if !self.accept_code(user.id, code, false).await? {
+            return Err(AppError::AuthError("Invalid TOTP code".to_string()));
+        }
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        let confirmed = r"UPDATE user_totp SET confirmed_at = :now
+            WHERE user_id = :user_id AND confirmed_at IS NULL"
+            .with(params! { "user_id" => user.id, "now" => to_db(Utc::now()) })
+            .run(&mut conn)
+            .await
+            .map_err(totp_error)?
+            .affected_rows();
+        if confirmed > 0 {
+            info!("User {} enabled TOTP", user.id);
+        }
+
+        Ok(TotpStatus { enabled: true })
+    }
+
+    /// Whether `code` matches the user's secret (only a confirmed one with `confirmed_only`).
+    /// A step is accepted once, so a code can't be replayed.
+    async fn accept_code(
+        &self,
+        user_id: i64,
+        code: &str,
+        confirmed_only: bool,
+    ) -> Result<bool, AppError> {
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+        let stored: Option<(Vec<u8>, bool)> =
+            "SELECT secret, confirmed_at IS NOT NULL FROM user_totp WHERE user_id = :user_id"
+                .with(params! { "user_id" => user_id })
+                .first(&mut conn)
+                .await
+                .map_err(totp_error)?;
+        let Some((secret, confirmed)) = stored else {
+            return Ok(false);
+        };
+        if confirmed_only && !confirmed {
+            return Ok(false);
+        }
+        let Some(step) = matching_step(&secret, code, Utc::now().timestamp()) else {
+            return Ok(false);
+        };
+
+        let claimed = r"UPDATE user_totp SET last_used_step = :step
+            WHERE user_id = :user_id AND (last_used_step IS NULL OR last_used_step < :step)"
+            .with(params! { "user_id" => user_id, "step" => step })
+            .run(&mut conn)
+            .await
+            .map_err(totp_error)?
+            .affected_rows();
+        Ok(claimed > 0)
+    }
+}
+
+fn totp_error(e: mysql_async::Error) -> AppError {
+    error!("TOTP database error: {}", e);
+    AppError::DatabaseError(e.to_string())
+}
+
+/// RFC 4226 code for counter `step` of `secret`
+fn totp_code(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Step whose code `code` is, among the steps around `unix_time` allowed for drift
+fn matching_step(secret: &[u8], code: &str, unix_time: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = unix_time.div_euclid(TOTP_STEP_SECS);
+    (current - TOTP_DRIFT_STEPS..=current + TOTP_DRIFT_STEPS)
+        .find(|&step| totp_code(secret, step) == code)
+}
+
+/// RFC 4648 base32 without padding, as authenticator apps expect secrets
+fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Escape everything but unreserved characters for the `otpauth` URI label
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Secret of the RFC 6238 SHA-1 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_totp_matches_rfc_6238_vectors() {
+        // The RFC lists 8 digit codes; these are their last 6 digits
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1234567890, 5924),
+            (2000000000, 279037),
+        ] {
+            assert_eq!(totp_code(RFC_SECRET, time / TOTP_STEP_SECS), code);
+        }
+    }
+
+    #[test]
+    fn test_codes_from_adjacent_steps_are_accepted() {
+        let time = 1111111109;
+        let step = time / TOTP_STEP_SECS;
+        let code = |step| format!("{:06}", totp_code(RFC_SECRET, step));
+
+        assert_eq!(matching_step(RFC_SECRET, &code(step), time), Some(step));
+        assert_eq!(
+            matching_step(RFC_SECRET, &code(step - 1), time),
+            Some(step - 1)
+        );
+        assert_eq!(matching_step(RFC_SECRET, &code(step + 2), time), None);
+        assert_eq!(matching_step(RFC_SECRET, "81804", time), None);
+        assert_eq!(matching_step(RFC_SECRET, "abcdef", time), None);
+    }
+
+    #[test]
+    fn test_base32_encoding() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(
+            base32_encode(RFC_SECRET),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use dashmap::DashMap;
+use tokio::sync::broadcast;
+
+/// Signals buffered per subscriber. Subscribers reload the task on every signal, so a
+/// lagging one loses nothing by skipping older signals.
+const TASK_EVENT_CAPACITY: usize = 1;
+
+/// In-process fan-out of task changes to the clients watching them.
+///
+/// Signals carry no payload: subscribers reload the task, so every task table only has to
+/// report that something changed. Updates made by other instances aren't signalled here.
+#[derive(Default)]
+pub struct TaskEvents {
+    channels: DashMap<String, broadcast::Sender<()>>,
+}
+
+impl TaskEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Watch a task. Channels without subscribers left are dropped here.
+    pub fn subscribe(&self, task_id: &str) -> broadcast::Receiver<()> {
+        self.channels
+            .retain(|_, sender| sender.receiver_count() > 0);
+        self.channels
+            .entry(task_id.to_string())
+            .or_insert_with(|| broadcast::channel(TASK_EVENT_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Tell the task's subscribers, if any, that its status or progress changed
+    pub fn publish(&self, task_id: &str) {
+        if let Some(sender) = self.channels.get(task_id) {
+            // Fails only when nobody is subscribed
+            let _ = sender.send(());
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use crate::database::db_time::{from_db, to_db};
+use crate::errors::AppError;
+use crate::models::task::{Task, TaskCursor, TaskType};
+use crate::services::bioagents_service::BioAgentsService;
+use crate::services::notification_service::{NotificationService, TaskNotice};
+use crate::services::task_events::TaskEvents;
+use chrono::Utc;
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool, Row};
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Upper bound on tasks returned by a single listing
+pub const MAX_LISTED_TASKS: u32 = 100;
+
+/// How often a watched BioAgents task is polled
+const BIOAGENT_WATCH_INTERVAL_SECS: u64 = 15;
+
+/// Watching stops after this many polls (about two hours)
+const BIOAGENT_WATCH_MAX_POLLS: u32 = 480;
+
+/// All task tables projected onto the same columns. BioAgents reports progress as 0.0-1.0,
+/// so it is scaled to match the upload percentage.
+const UNIFIED_TASKS_QUERY: &str = r"
+    SELECT * FROM (
+        SELECT task_id, 'upload' AS task_type, NULL AS kind, user_id, status, progress,
+               NULL AS cid, cid AS result_cid, error, NULL AS details,
+               DATE_FORMAT(started_at, '%Y-%m-%d %H:%i:%s') AS created_at,
+               DATE_FORMAT(completed_at, '%Y-%m-%d %H:%i:%s') AS completed_at
+        FROM upload_tasks
+        UNION ALL
+        SELECT task_id, 'bioagent' AS task_type, NULL AS kind, user_id, status, progress * 100.0,
+               cid, result_cid, NULL AS error, NULL AS details,
+               DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at,
+               DATE_FORMAT(completed_at, '%Y-%m-%d %H:%i:%s') AS completed_at
+        FROM bioagent_tasks
+        UNION ALL
+        SELECT task_id, 'background' AS task_type, kind, user_id, status, progress,
+               NULL AS cid, NULL AS result_cid, error, details,
+               DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at,
+               DATE_FORMAT(completed_at, '%Y-%m-%d %H:%i:%s') AS completed_at
+        FROM background_tasks
+    ) AS tasks";
+
+/// Service presenting upload and BioAgents tasks through one status API
+pub struct TaskService {
+    db_pool: Arc<Pool>,
+    bioagents_service: Arc<BioAgentsService>,
+    notifications: Arc<NotificationService>,
+    events: Arc<TaskEvents>,
+}
+
+impl TaskService {
+    pub fn new(
+        db_pool: Arc<Pool>,
+        bioagents_service: Arc<BioAgentsService>,
+        notifications: Arc<NotificationService>,
+        events: Arc<TaskEvents>,
+    ) -> Self {
+        Self {
+            db_pool,
+            bioagents_service,
+            notifications,
+            events,
+        }
+    }
+
+    /// Receive a signal whenever the task changes; reload it with `get_status`
+    pub fn subscribe(&self, task_id: &str) -> tokio::sync::broadcast::Receiver<()> {
+        self.events.subscribe(task_id)
+    }
+
+    /// Record a BioAgents task so it can be polled through the task API
+    pub async fn record_bioagent_task(
+        &self,
+        task_id: &str,
+        user_id: i64,
+        cid: &str,
+        status: &str,
+    ) -> Result<(), AppError> {
+        let mut conn = self.get_conn().await?;
+
+        r"INSERT INTO bioagent_tasks (task_id, user_id, cid, status, progress, created_at)
+          VALUES (:task_id, :user_id, :cid, :status, 0.0, :created_at)"
+            .with(params! {
+                "task_id" => task_id,
+                "user_id" => user_id,
+                "cid" => cid,
+                "status" => status,
+                "created_at" => to_db(Utc::now()),
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when recording BioAgents task: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Recorded BioAgents task {} for user {}", task_id, user_id);
+        Ok(())
+    }
+
+    /// Poll a BioAgents task in the background until it finishes, so its owner is notified
+    /// without having to poll. Does nothing when notifications are disabled.
+    pub fn watch_bioagent_task(self: &Arc<Self>, task_id: String) {
+        if !self.notifications.is_enabled() {
+            return;
+        }
+
+        let service = Arc::clone(self);
+        tokio::task::spawn_local(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(BIOAGENT_WATCH_INTERVAL_SECS));
+            // The first tick completes immediately
+            interval.tick().await;
+
+            for _ in 0..BIOAGENT_WATCH_MAX_POLLS {
+                interval.tick().await;
+                match service.find_task(&task_id).await {
+                    Ok(Some(task)) if !task.is_finished() => {
+                        if service.refresh_bioagent_task(task).await.is_finished() {
+                            return;
+                        }
+                    }
+                    Ok(_) => return,
+                    Err(e) => warn!("Failed to load watched task {}: {}", task_id, e),
+                }
+            }
+            warn!(
+                "Stopped watching BioAgents task {} before it finished",
+                task_id
+            );
+        });
+    }
+
+    /// Create a pending background task and return its id
+    pub async fn create_background_task(
+        &self,
+        user_id: i64,
+        kind: &str,
+    ) -> Result<String, AppError> {
+        let task_id = Uuid::new_v4().to_string();
+        let mut conn = self.get_conn().await?;
+
+        r"INSERT INTO background_tasks (task_id, user_id, kind, status, progress, created_at)
+          VALUES (:task_id, :user_id, :kind, 'pending', 0.0, :created_at)"
+            .with(params! {
+                "task_id" => &task_id,
+                "user_id" => user_id,
+                "kind" => kind,
+                "created_at" => to_db(Utc::now()),
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when creating background task: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Created {} task {} for user {}", kind, task_id, user_id);
+        Ok(task_id)
+    }
+
+    /// Update a background task's progress; `completed_at` is set once it reaches a final state
+    pub async fn update_background_task(
+        &self,
+        task_id: &str,
+        status: &str,
+        progress: f64,
+        details: Option<&serde_json::Value>,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        let details = details
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|_| AppError::SerializationError)?;
+        let finished = matches!(status, "completed" | "failed");
+        let completed_at = finished.then(|| to_db(Utc::now()));
+        let mut conn = self.get_conn().await?;
+
+        r"UPDATE background_tasks
+          SET status = :status, progress = :progress, details = COALESCE(:details, details),
+              error = :error, completed_at = COALESCE(:completed_at, completed_at)
+          WHERE task_id = :task_id"
+            .with(params! {
+                "task_id" => task_id,
+                "status" => status,
+                "progress" => progress,
+                "details" => details,
+                "error" => error,
+                "completed_at" => completed_at,
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when updating background task: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        self.events.publish(task_id);
+
+        if finished && self.notifications.is_enabled() {
+            if let Some(task) = self.find_task(task_id).await? {
+                let kind = task.kind.as_deref().unwrap_or("Background task");
+                self.notify_finished(&task, kind).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unfinished background tasks of one kind, oldest first. Used by jobs that are advanced
+    /// by periodic polling rather than a running future, so they survive restarts.
+    pub async fn unfinished_background_tasks(&self, kind: &str) -> Result<Vec<Task>, AppError> {
+        let mut conn = self.get_conn().await?;
+
+        let rows: Vec<Row> = format!(
+            "{} WHERE task_type = 'background' AND kind = :kind
+               AND status NOT IN ('completed', 'failed')
+             ORDER BY created_at",
+            UNIFIED_TASKS_QUERY
+        )
+        .with(params! { "kind" => kind })
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing {} tasks: {}", kind, e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        rows.into_iter().map(task_from_row).collect()
+    }
+
+    /// Those of `task_ids` that are BioAgents tasks owned by the user
+    pub async fn owned_bioagent_tasks(
+        &self,
+        task_ids: &[String],
+        user_id: i64,
+    ) -> Result<HashSet<String>, AppError> {
+        if task_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let mut conn = self.db_pool.get_conn().await?;
+
+        let query = format!(
+            "SELECT task_id FROM bioagent_tasks WHERE user_id = ? AND task_id IN ({})",
+            vec!["?"; task_ids.len()].join(", ")
+        );
+        let params: Vec<mysql_async::Value> = std::iter::once(user_id.into())
+            .chain(task_ids.iter().map(|id| id.as_str().into()))
+            .collect();
+        let owned: Vec<String> = query.with(params).fetch(&mut conn).await.map_err(|e| {
+            error!("Database error when checking task ownership: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        Ok(owned.into_iter().collect())
+    }
+
+    /// Get a single task owned by the user, refreshing unfinished BioAgents tasks
+    pub async fn get_status(&self, task_id: &str, user_id: i64) -> Result<Task, AppError> {
+        let task = self
+            .find_task(task_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Task not found: {}", task_id)))?;
+
+        if task.user_id != user_id {
+            return Err(AppError::AuthorizationError(
+                "Not authorized to view this task".to_string(),
+            ));
+        }
+
+        if task.task_type == TaskType::Bioagent && !task.is_finished() {
+            return Ok(self.refresh_bioagent_task(task).await);
+        }
+
+        Ok(task)
+    }
+
+    /// List the user's tasks newest first, optionally restricted to one type and status.
+    ///
+    /// Returns up to `limit` tasks (at most `MAX_LISTED_TASKS`) created before `cursor`, and
+    /// the cursor of the next page if more tasks remain.
+    pub async fn list_tasks(
+        &self,
+        user_id: i64,
+        task_type: Option<TaskType>,
+        status: Option<&str>,
+        cursor: Option<&TaskCursor>,
+        limit: u32,
+    ) -> Result<(Vec<Task>, Option<TaskCursor>), AppError> {
+        let limit = limit.clamp(1, MAX_LISTED_TASKS);
+        let mut conn = self.get_conn().await?;
+
+        // Keyset pagination on (created_at, task_id); one extra row tells whether another
+        // page exists
+        let rows: Vec<Row> = format!(
+            r"{} WHERE user_id = :user_id
+                AND (:task_type IS NULL OR task_type = :task_type)
+                AND (:status IS NULL OR status = :status)
+                AND (:cursor_created_at IS NULL OR created_at < :cursor_created_at
+                     OR (created_at = :cursor_created_at AND task_id < :cursor_task_id))
+              ORDER BY created_at DESC, task_id DESC LIMIT {}",
+            UNIFIED_TASKS_QUERY,
+            limit + 1
+        )
+        .with(params! {
+            "user_id" => user_id,
+            "task_type" => task_type.map(|t| t.as_str()),
+            "status" => status,
+            "cursor_created_at" => cursor
+                .map(|c| to_db(c.created_at)),
+            "cursor_task_id" => cursor.map(|c| c.task_id.as_str()),
+        })
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing tasks: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let mut tasks = rows
+            .into_iter()
+            .map(task_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        let next_cursor = if tasks.len() > limit as usize {
+            tasks.truncate(limit as usize);
+            tasks.last().map(TaskCursor::after)
+        } else {
+            None
+        };
+
+        Ok((tasks, next_cursor))
+    }
+
+    async fn find_task(&self, task_id: &str) -> Result<Option<Task>, AppError> {
+        let mut conn = self.get_conn().await?;
+
+        let row: Option<Row> = format!("{} WHERE task_id = :task_id", UNIFIED_TASKS_QUERY)
+            .with(params! { "task_id" => task_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when retrieving task: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        row.map(task_from_row).transpose()
+    }
+
+    async fn notify_finished(&self, task: &Task, kind: &str) {
+        self.notifications
+            .task_finished(
+                task.user_id,
+                TaskNotice {
+                    task_id: &task.task_id,
+                    kind,
+                    status: &task.status,
+                    did: None,
+                    cid: task.result_cid.as_deref().or(task.cid.as_deref()),
+                    error: task.error.as_deref(),
+                },
+            )
+            .await;
+    }
+
+    /// Pull the latest state from BioAgents and persist it. Falls back to the stored
+    /// state if BioAgents is unreachable.
+    async fn refresh_bioagent_task(&self, mut task: Task) -> Task {
+        let status = match self
+            .bioagents_service
+            .check_task_status(&task.task_id)
+            .await
+        {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to refresh BioAgents task {}: {}", task.task_id, e);
+                return task;
+            }
+        };
+
+        task.status = status.status;
+        task.progress = Some(f64::from(status.progress) * 100.0);
+        task.error = status.error;
+        if let Some(cid) = status
+            .result
+            .as_ref()
+            .and_then(|r| r.get("knowledge_graph_cid").or_else(|| r.get("cid")))
+            .and_then(|c| c.as_str())
+        {
+            task.result_cid = Some(cid.to_string());
+        }
+        if task.is_finished() && task.completed_at.is_none() {
+            task.completed_at = Some(Utc::now());
+        }
+
+        match self.store_bioagent_task(&task).await {
+            // Only the refresh that records completion notifies, even if several race
+            Ok(true) if task.is_finished() => {
+                self.events.publish(&task.task_id);
+                self.notify_finished(&task, "BioAgents paper processing")
+                    .await
+            }
+            Ok(true) => self.events.publish(&task.task_id),
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to persist refreshed BioAgents task {}: {}",
+                task.task_id, e
+            ),
+        }
+
+        task
+    }
+
+    /// Persist a refreshed task; returns false if it had already been marked finished
+    async fn store_bioagent_task(&self, task: &Task) -> Result<bool, AppError> {
+        let mut conn = self.get_conn().await?;
+
+        r"UPDATE bioagent_tasks
+          SET status = :status, progress = :progress, result_cid = :result_cid, completed_at = :completed_at
+          WHERE task_id = :task_id AND completed_at IS NULL"
+            .with(params! {
+                "task_id" => &task.task_id,
+                "status" => &task.status,
+                "progress" => task.progress.map(|p| p / 100.0),
+                "result_cid" => &task.result_cid,
+                "completed_at" => task
+                    .completed_at
+                    .map(to_db),
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    async fn get_conn(&self) -> Result<mysql_async::Conn, AppError> {
+        self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })
+    }
+}
+
+/// Build a `Task` from a row of `UNIFIED_TASKS_QUERY`
+fn task_from_row(row: Row) -> Result<Task, AppError> {
+    let task_type: String = row.get(1).unwrap_or_default();
+    let details: Option<String> = row.get(9).flatten();
+    let created_at: String = row.get(10).unwrap_or_default();
+    let completed_at: Option<String> = row.get(11).flatten();
+
+    Ok(Task {
+        task_id: row.get(0).unwrap_or_default(),
+        task_type: TaskType::parse(&task_type).ok_or(AppError::DeserializationError)?,
+        kind: row.get(2).flatten(),
+        user_id: row.get(3).unwrap_or_default(),
+        status: row.get(4).unwrap_or_default(),
+        progress: row.get(5).flatten(),
+        cid: row.get(6).flatten(),
+        result_cid: row.get(7).flatten(),
+        error: row.get(8).flatten(),
+        details: details.and_then(|d| serde_json::from_str(&d).ok()),
+        created_at: from_db(&created_at).ok_or(AppError::DeserializationError)?,
+        completed_at: completed_at.as_deref().and_then(from_db),
+    })
+}
@@ -1,13 +1,28 @@
+use crate::database::db_time::{from_db_value, to_db};
 use crate::errors::AppError;
-use chrono::{Duration, Utc};
-use log::{error, info};
-use mysql_async::{prelude::*, Pool};
+use crate::models::did::{decode_ed25519_multibase, multibase_key, ED25519_MULTICODEC};
+use crate::services::audit_service::AuditService;
+use crate::services::auth_token::SigningContext;
+use crate::services::did_service::DIDService;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use ed25519_zebra::{Signature, SigningKey, VerificationKey};
+use log::{error, info, warn};
+use lru::LruCache;
+use mysql_async::{prelude::*, Pool, Row};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration as StdDuration, Instant};
 use uuid;
 
 /// Resource types for Bio-DID-Seq capabilities
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum BioResource {
     // Dataset with optional ID
     Dataset(String),
@@ -21,8 +36,71 @@ pub enum BioResource {
     UserProfile(String),
 }
 
+/// Resource ID matching every resource of a type
+pub const ANY_RESOURCE: &str = "*";
+
+impl BioResource {
+    /// Parse a capability `with` URI: `dataset:<id>`, `file:<cid>`, `metadata:<id>`,
+    /// `user:<id>` or a DID (`did:bio:...`), where `*` in place of the ID or DID matches
+    /// all resources of that type (e.g. `did:*`)
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let value = value.trim();
+        let (kind, id) = value.split_once(':').unwrap_or((value, ""));
+        if id.is_empty() {
+            return Err(AppError::ValidationError(format!(
+                "Invalid capability resource '{}', expected <type>:<id> with type one of: \
+                 dataset, did, file, metadata, user",
+                value
+            )));
+        }
+
+        match kind {
+            "dataset" => Ok(BioResource::Dataset(id.to_string())),
+            "did" if id == ANY_RESOURCE => Ok(BioResource::DID(id.to_string())),
+            "did" => Ok(BioResource::DID(value.to_string())),
+            "file" => Ok(BioResource::File(id.to_string())),
+            "metadata" => Ok(BioResource::Metadata(id.to_string())),
+            "user" => Ok(BioResource::UserProfile(id.to_string())),
+            _ => Err(AppError::ValidationError(format!(
+                "Unknown capability resource type '{}', expected one of: dataset, did, file, \
+                 metadata, user",
+                kind
+            ))),
+        }
+    }
+
+    /// ID, CID or DID of the resource, or `*` for all of them
+    pub fn id(&self) -> &str {
+        match self {
+            BioResource::Dataset(id)
+            | BioResource::DID(id)
+            | BioResource::File(id)
+            | BioResource::Metadata(id)
+            | BioResource::UserProfile(id) => id,
+        }
+    }
+
+    /// Matches every resource of its type
+    pub fn is_wildcard(&self) -> bool {
+        self.id() == ANY_RESOURCE
+    }
+}
+
+impl fmt::Display for BioResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BioResource::Dataset(id) => write!(f, "dataset:{}", id),
+            BioResource::DID(id) if id == ANY_RESOURCE => write!(f, "did:{}", id),
+            BioResource::DID(did) => f.write_str(did),
+            BioResource::File(cid) => write!(f, "file:{}", cid),
+            BioResource::Metadata(id) => write!(f, "metadata:{}", id),
+            BioResource::UserProfile(id) => write!(f, "user:{}", id),
+        }
+    }
+}
+
 /// Actions that can be performed on Bio-DID-Seq resources
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BioAction {
     Create,
     Read,
@@ -34,6 +112,58 @@ pub enum BioAction {
     Publish,
 }
 
+impl BioAction {
+    pub const ALL: &'static [BioAction] = &[
+        BioAction::Create,
+        BioAction::Read,
+        BioAction::Update,
+        BioAction::Delete,
+        BioAction::Upload,
+        BioAction::Download,
+        BioAction::Process,
+        BioAction::Publish,
+    ];
+
+    /// Parse a capability `can` value, in any case
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let value = value.trim();
+        BioAction::ALL
+            .iter()
+            .find(|action| action.as_str().eq_ignore_ascii_case(value))
+            .copied()
+            .ok_or_else(|| {
+                AppError::ValidationError(format!(
+                    "Unknown capability action '{}', expected one of: {}",
+                    value,
+                    BioAction::ALL
+                        .iter()
+                        .map(BioAction::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BioAction::Create => "create",
+            BioAction::Read => "read",
+            BioAction::Update => "update",
+            BioAction::Delete => "delete",
+            BioAction::Upload => "upload",
+            BioAction::Download => "download",
+            BioAction::Process => "process",
+            BioAction::Publish => "publish",
+        }
+    }
+}
+
+impl fmt::Display for BioAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Simple capability structure for Bio-DID-Seq
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BioCapability {
@@ -41,33 +171,272 @@ pub struct BioCapability {
     pub action: BioAction,
 }
 
+impl BioCapability {
+    /// Parse a `(with, can)` pair
+    pub fn parse(with: &str, can: &str) -> Result<Self, AppError> {
+        Ok(Self {
+            resource: BioResource::parse(with)?,
+            action: BioAction::parse(can)?,
+        })
+    }
+
+    /// Whether this capability allows `action` on `resource`; a wildcard capability covers
+    /// every resource of its type, while a wildcard `resource` needs a wildcard capability
+    pub fn grants(&self, resource: &BioResource, action: BioAction) -> bool {
+        self.action == action
+            && std::mem::discriminant(&self.resource) == std::mem::discriminant(resource)
+            && (self.resource.is_wildcard() || self.resource.id() == resource.id())
+    }
+}
+
+/// Whether a bearer token is a UCAN rather than a session token: an `EdDSA` JWT, issued
+/// by this service or an external issuer
+pub fn is_ucan_token(token: &str) -> bool {
+    token.split('.').count() == 3
+        && token
+            .split('.')
+            .next()
+            .and_then(|header| decode_jwt_segment::<UcanJwtHeader>(header, "header").ok())
+            .is_some_and(|header| header.alg == "EdDSA")
+}
+
 /// Token validation result
 pub struct TokenValidationData {
     pub issuer: String,
     pub audience: String,
     pub capabilities: Vec<(String, String)>,
+    // `capabilities` that parse as Bio-DID-Seq resources and actions; others are left out
+    pub typed_capabilities: Vec<BioCapability>,
     pub expires_at: i64,
 }
 
+/// Parse the capabilities of a validated token, skipping ones this service doesn't define
+fn typed_capabilities(capabilities: &[(String, String)]) -> Vec<BioCapability> {
+    capabilities
+        .iter()
+        .filter_map(|(with, can)| BioCapability::parse(with, can).ok())
+        .collect()
+}
+
+/// UCANs listed per page unless the request asks for a number
+pub const DEFAULT_LISTED_TOKENS: u32 = 50;
+
+/// Most UCANs listed per page
+pub const MAX_LISTED_TOKENS: u32 = 200;
+
+/// A UCAN a user issued, described without the token itself
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UcanTokenSummary {
+    pub id: String,
+    pub audience: String,
+    pub capabilities: Vec<UcanAttenuation>,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked: bool,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revocation_reason: Option<String>,
+    // ID of the token this one was delegated from
+    pub delegated_from: Option<String>,
+}
+
+/// One page of a user's UCANs, newest first
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UcanTokenPage {
+    pub tokens: Vec<UcanTokenSummary>,
+    pub limit: u32,
+    pub offset: u64,
+    // Tokens matching the filters across all pages
+    pub total: u64,
+}
+
+/// UCAN spec version of the tokens this service issues
+const UCAN_VERSION: &str = "0.10.0";
+
+/// How long a resolved issuer key is trusted before the DID is resolved again
+const ISSUER_KEY_CACHE_TTL: StdDuration = StdDuration::from_secs(300);
+
+/// Issuer keys kept in memory; the least recently used are evicted beyond this
+const ISSUER_KEY_CACHE_SIZE: usize = 1024;
+
+/// JOSE header of a JWT-encoded UCAN
+#[derive(Debug, Deserialize)]
+struct UcanJwtHeader {
+    alg: String,
+}
+
+/// Claims of a JWT-encoded UCAN
+#[derive(Debug, Deserialize)]
+struct UcanJwtPayload {
+    iss: String,
+    aud: String,
+    exp: Option<i64>,
+    nbf: Option<i64>,
+    #[serde(default)]
+    att: Vec<UcanAttenuation>,
+    // Nonce; the `ucan_tokens` ID for tokens issued by this service
+    #[serde(default)]
+    nnc: Option<String>,
+}
+
+/// Capability granted by a JWT-encoded UCAN
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct UcanAttenuation {
+    pub with: String,
+    pub can: String,
+}
+
+fn decode_jwt_segment<T: serde::de::DeserializeOwned>(
+    segment: &str,
+    name: &str,
+) -> Result<T, String> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(segment)
+        .map_err(|_| format!("Invalid UCAN {} encoding", name))?;
+    serde_json::from_slice(&bytes).map_err(|_| format!("Invalid UCAN {}", name))
+}
+
+/// Capabilities a stored token grants; none if its payload can't be read
+fn token_capabilities(token: &str) -> Vec<UcanAttenuation> {
+    token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| decode_jwt_segment::<UcanJwtPayload>(payload, "payload").ok())
+        .map(|payload| payload.att)
+        .unwrap_or_default()
+}
+
+/// A token's place in a delegation chain, as loaded by `cascade_revocations`
+#[derive(Debug, Clone)]
+struct ChainLink {
+    revoked: bool,
+    delegated_from: Option<String>,
+}
+
+/// Nearest revoked ancestor of `token_id` along its `delegated_from` chain. `None` if no
+/// ancestor is revoked, or the chain loops or reaches a token missing from `links`.
+fn first_revoked_ancestor(
+    token_id: &str,
+    links: &HashMap<String, Option<ChainLink>>,
+) -> Option<String> {
+    let mut seen = HashSet::from([token_id.to_string()]);
+    let mut current = links.get(token_id)?.as_ref()?.delegated_from.clone();
+    while let Some(id) = current {
+        if !seen.insert(id.clone()) {
+            return None;
+        }
+        let link = links.get(&id)?.as_ref()?;
+        if link.revoked {
+            return Some(id);
+        }
+        current = link.delegated_from.clone();
+    }
+    None
+}
+
 /// Service for handling UCAN based authorization
 pub struct UcanService {
     db_pool: Arc<Pool>,
+    audit_service: Arc<AuditService>,
+    did_service: Arc<DIDService>,
+    // Issuer DID -> Ed25519 key and when it was resolved
+    issuer_keys: Mutex<LruCache<String, (VerificationKey, Instant)>>,
+    // Actions non-admin users may delegate; admins may delegate any action
+    delegable_actions: Vec<BioAction>,
+    // Issuer of the UCANs this service signs, and the key it signs them with
+    service_did: String,
+    signing_key: SigningKey,
+    // Prefixed to the signing input of UCANs signed with `signing_key`
+    signing_context: SigningContext,
 }
 
 impl UcanService {
-    /// Create a new UCAN service
-    pub async fn new(db_pool: Arc<Pool>) -> Result<Self, AppError> {
-        Ok(Self { db_pool })
+    /// Create a new UCAN service signing tokens with `signing_key`.
+    ///
+    /// `service_did` defaults to the key's `did:key`; a `did:key` that doesn't match the
+    /// key is rejected. For other DID methods the key must be listed in the DID's document
+    /// for external parties to verify the tokens.
+    pub async fn new(
+        db_pool: Arc<Pool>,
+        audit_service: Arc<AuditService>,
+        did_service: Arc<DIDService>,
+        delegable_actions: Vec<BioAction>,
+        service_did: Option<String>,
+        signing_key: SigningKey,
+        signing_context: SigningContext,
+    ) -> Result<Self, AppError> {
+        let key_did = format!(
+            "did:key:{}",
+            multibase_key(
+                &ED25519_MULTICODEC,
+                VerificationKey::from(&signing_key).as_ref()
+            )
+        );
+        let service_did = match service_did {
+            Some(did) if did.starts_with("did:key:") && did != key_did => {
+                return Err(AppError::ValidationError(format!(
+                    "SERVICE_DID {} does not match the signing key, whose did:key is {}",
+                    did, key_did
+                )))
+            }
+            Some(did) => did,
+            None => key_did,
+        };
+        info!("Issuing UCANs as {}", service_did);
+
+        Ok(Self {
+            db_pool,
+            audit_service,
+            did_service,
+            issuer_keys: Mutex::new(LruCache::new(
+                NonZeroUsize::new(ISSUER_KEY_CACHE_SIZE).expect("cache size is non-zero"),
+            )),
+            delegable_actions,
+            service_did,
+            signing_key,
+            signing_context,
+        })
+    }
+
+    /// Check that a user may delegate a capability.
+    ///
+    /// Non-admins are limited to the configured delegable actions and to named resources;
+    /// wildcard resources such as `did:*` can only be granted by admins.
+    fn check_delegation(&self, capability: &BioCapability, is_admin: bool) -> Result<(), AppError> {
+        if is_admin {
+            return Ok(());
+        }
+        if !self.delegable_actions.contains(&capability.action) {
+            return Err(AppError::AuthorizationError(format!(
+                "Only admins can delegate the '{}' capability",
+                capability.action
+            )));
+        }
+        if capability.resource.is_wildcard() {
+            return Err(AppError::AuthorizationError(format!(
+                "Only admins can delegate capabilities on every resource ('{}')",
+                capability.resource
+            )));
+        }
+        Ok(())
     }
 
-    /// Issue a UCAN token for a user
+    /// Issue a UCAN token for a user.
+    ///
+    /// Capabilities must name a known resource and action, and are limited by the
+    /// delegation policy (see `check_delegation`).
     pub async fn issue_token(
         &self,
         user_id: i64,
+        is_admin: bool,
         audience_did: &str,
         capabilities: &[(String, String)],
         expiration_opt: Option<i64>,
     ) -> Result<(String, i64), AppError> {
+        for (with, can) in capabilities {
+            let capability = BioCapability::parse(with, can)?;
+            self.check_delegation(&capability, is_admin)?;
+        }
+
         let now = Utc::now();
 
         // Default expiration is 24 hours if not specified
@@ -78,20 +447,31 @@ impl UcanService {
 
         let expiry_timestamp = expiry.timestamp();
 
-        // In a real implementation, you would use the actual DID of the service as issuer
-        let service_did = "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK";
-
-        // Format a simplified JWT-like token for demonstration
         let token_id = uuid::Uuid::new_v4().to_string();
-        let capabilities_json = serde_json::to_string(&capabilities).unwrap_or_default();
-        let token = format!(
-            "ucan:demo:{}:{}:{}:{}:{}",
-            token_id,
-            service_did,
-            audience_did,
-            now.timestamp(),
-            capabilities_json
+        let header = serde_json::json!({ "alg": "EdDSA", "typ": "JWT" });
+        let payload = serde_json::json!({
+            "ucv": UCAN_VERSION,
+            "iss": self.service_did,
+            "aud": audience_did,
+            "nbf": now.timestamp(),
+            "exp": expiry_timestamp,
+            "nnc": token_id,
+            "att": capabilities
+                .iter()
+                .map(|(with, can)| serde_json::json!({ "with": with, "can": can }))
+                .collect::<Vec<_>>(),
+            "prf": [],
+        });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(payload.to_string())
         );
+        let signature: [u8; 64] = self
+            .signing_key
+            .sign(&self.signing_context.signing_input(signing_input.as_bytes()))
+            .into();
+        let token = format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature));
 
         // Store the token in the database
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
@@ -99,8 +479,8 @@ impl UcanService {
             AppError::DatabaseError(e.to_string())
         })?;
 
-        let issued_at = now.naive_utc().format("%Y-%m-%d %H:%M:%S").to_string();
-        let expires_at = expiry.naive_utc().format("%Y-%m-%d %H:%M:%S").to_string();
+        let issued_at = to_db(now);
+        let expires_at = to_db(expiry);
 
         "INSERT INTO ucan_tokens (id, user_id, token, audience_did, issued_at, expires_at) VALUES (:id, :user_id, :token, :audience_did, :issued_at, :expires_at)"
             .with(params! {
@@ -123,103 +503,199 @@ impl UcanService {
             user_id, audience_did
         );
 
+        self.audit_service
+            .record_audit(
+                user_id,
+                "ucan.issue",
+                "ucan_token",
+                &token_id,
+                serde_json::json!({
+                    "audience": audience_did,
+                    "capabilities": capabilities,
+                    "expires_at": expiry_timestamp,
+                }),
+            )
+            .await;
+
         Ok((token, expiry_timestamp))
     }
 
-    /// Validate a UCAN token
+    /// Validate a UCAN token.
+    ///
+    /// The signature is checked against the issuer's key. Tokens issued by this service
+    /// must also still be on record and not revoked.
     pub async fn validate_token(
         &self,
         token: &str,
     ) -> Result<Result<TokenValidationData, String>, AppError> {
-        // Parse token with simple format: ucan:demo:id:issuer:audience:timestamp:capabilities
-        let parts: Vec<&str> = token.split(':').collect();
-        if parts.len() < 7 || parts[0] != "ucan" || parts[1] != "demo" {
-            return Ok(Err("Invalid UCAN token format".to_string()));
-        }
-
-        // Extract token components
-        let token_id = parts[2];
-        let issuer = parts[3];
-        let audience = parts[4];
-
-        // Parse timestamp safely
-        let issued_timestamp = match parts[5].parse::<i64>() {
-            Ok(ts) => ts,
-            Err(_) => return Ok(Err("Invalid timestamp in token".to_string())),
+        let data = match self.validate_jwt_token(token).await {
+            Ok(data) => data,
+            Err(reason) => return Ok(Err(reason)),
         };
-
-        // Log the token information
-        info!("Validating token issued at timestamp {}", issued_timestamp);
-
-        let capabilities_json = parts[6];
-
-        // Check if token is revoked
-        let is_revoked = self.is_token_revoked(token).await?;
-        if is_revoked {
-            return Ok(Err("Token has been revoked".to_string()));
+        if data.issuer != self.service_did {
+            return Ok(Ok(data));
         }
 
-        // Check if token is expired
-        let now = Utc::now().timestamp();
-
-        // Get expiration from database
+        let Some(token_id) = self.own_token_id(token) else {
+            return Ok(Err("Token has no ID".to_string()));
+        };
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
             AppError::DatabaseError(e.to_string())
         })?;
-
-        // Use string format for the expires_at field instead of NaiveDateTime
-        let expires_at: Option<String> =
-            "SELECT DATE_FORMAT(expires_at, '%Y-%m-%d %H:%i:%s') FROM ucan_tokens WHERE id = :id"
-                .with(params! {
-                    "id" => token_id,
-                })
+        let revoked: Option<(bool, Option<String>)> =
+            "SELECT revoked, revocation_reason FROM ucan_tokens WHERE id = :id"
+                .with(params! { "id" => &token_id })
                 .first(&mut conn)
                 .await
                 .map_err(|e| {
-                    error!("Database error when checking token expiration: {}", e);
+                    error!("Database error when checking token revocation: {}", e);
                     AppError::DatabaseError(e.to_string())
                 })?;
 
-        // Parse the expires_at string to a timestamp
-        let expires_timestamp = match expires_at {
-            Some(dt_str) => {
-                match chrono::NaiveDateTime::parse_from_str(&dt_str, "%Y-%m-%d %H:%M:%S") {
-                    Ok(dt) => dt.and_utc().timestamp(),
-                    Err(_) => return Ok(Err("Invalid expiration date format".to_string())),
-                }
-            }
-            None => return Ok(Err("Token not found in database".to_string())),
+        match revoked {
+            None => Ok(Err("Token not found in database".to_string())),
+            Some((true, Some(reason))) => Ok(Err(format!("Token has been revoked: {}", reason))),
+            Some((true, None)) => Ok(Err("Token has been revoked".to_string())),
+            Some((false, _)) => Ok(Ok(data)),
+        }
+    }
+
+    /// Validate a JWT-encoded UCAN.
+    ///
+    /// Only `EdDSA` tokens are accepted; the signature is checked against the issuer DID's
+    /// Ed25519 key. Errors are returned as the reason shown to the caller.
+    async fn validate_jwt_token(&self, token: &str) -> Result<TokenValidationData, String> {
+        let mut segments = token.split('.');
+        let (Some(header_b64), Some(payload_b64), Some(signature_b64)) =
+            (segments.next(), segments.next(), segments.next())
+        else {
+            return Err("Invalid UCAN token format".to_string());
         };
 
-        if now > expires_timestamp {
-            return Ok(Err("Token has expired".to_string()));
+        let header: UcanJwtHeader = decode_jwt_segment(header_b64, "header")?;
+        if header.alg != "EdDSA" {
+            return Err(format!(
+                "Unsupported UCAN signature algorithm '{}', expected EdDSA",
+                header.alg
+            ));
         }
+        let payload: UcanJwtPayload = decode_jwt_segment(payload_b64, "payload")?;
+
+        let signature: [u8; 64] = URL_SAFE_NO_PAD
+            .decode(signature_b64)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| "Invalid UCAN signature encoding".to_string())?;
 
-        // Parse capabilities
-        let capabilities: Vec<(String, String)> = match serde_json::from_str(capabilities_json) {
-            Ok(caps) => caps,
-            Err(_) => return Ok(Err("Invalid capabilities format in token".to_string())),
+        let key = self.resolve_issuer_key(&payload.iss).await?;
+        let signing_input = &token.as_bytes()[..header_b64.len() + 1 + payload_b64.len()];
+        // Whatever the issuer DID, a signature by the service's own key only counts under the
+        // UCAN context, so nothing else it signs can pass as a UCAN
+        let signing_input = if key.as_ref() == VerificationKey::from(&self.signing_key).as_ref() {
+            self.signing_context.signing_input(signing_input)
+        } else {
+            signing_input.to_vec()
         };
+        key.verify(&Signature::from(signature), &signing_input)
+            .map_err(|_| format!("Signature verification failed for issuer {}", payload.iss))?;
 
-        // Token is valid
-        Ok(Ok(TokenValidationData {
-            issuer: issuer.to_string(),
-            audience: audience.to_string(),
+        let now = Utc::now().timestamp();
+        if payload.nbf.is_some_and(|nbf| now < nbf) {
+            return Err("Token is not yet valid".to_string());
+        }
+        if payload.exp.is_some_and(|exp| now > exp) {
+            return Err("Token has expired".to_string());
+        }
+
+        let capabilities: Vec<(String, String)> = payload
+            .att
+            .into_iter()
+            .map(|capability| (capability.with, capability.can))
+            .collect();
+
+        Ok(TokenValidationData {
+            issuer: payload.iss,
+            audience: payload.aud,
+            typed_capabilities: typed_capabilities(&capabilities),
             capabilities,
-            expires_at: expires_timestamp,
-        }))
+            expires_at: payload.exp.unwrap_or(i64::MAX),
+        })
     }
 
-    /// Revoke a UCAN token
-    pub async fn revoke_token(&self, user_id: i64, token: &str) -> Result<(), AppError> {
-        // Parse token to extract ID
-        let parts: Vec<&str> = token.split(':').collect();
-        if parts.len() < 3 || parts[0] != "ucan" || parts[1] != "demo" {
-            return Err(AppError::AuthError("Invalid UCAN token format".to_string()));
+    /// Ed25519 public key of an issuer DID.
+    ///
+    /// `did:key` identifiers carry the key inline; other DIDs are resolved and their first
+    /// `Ed25519VerificationKey2020` method is used. Resolved keys are cached for
+    /// `ISSUER_KEY_CACHE_TTL`.
+    pub async fn resolve_issuer_key(&self, issuer_did: &str) -> Result<VerificationKey, String> {
+        if issuer_did == self.service_did {
+            return Ok(VerificationKey::from(&self.signing_key));
+        }
+        if let Some(key) = self.cached_issuer_key(issuer_did) {
+            return Ok(key);
         }
 
-        let token_id = parts[2];
+        let key = if let Some(multibase) = issuer_did.strip_prefix("did:key:") {
+            decode_ed25519_multibase(multibase)
+                .ok_or_else(|| format!("Issuer {} is not an Ed25519 did:key", issuer_did))?
+        } else {
+            let document = self
+                .did_service
+                .resolve_did(issuer_did)
+                .await
+                .map_err(|e| {
+                    warn!("Failed to resolve UCAN issuer {}: {}", issuer_did, e);
+                    format!("Could not resolve issuer DID {}", issuer_did)
+                })?;
+
+            document
+                .verification_method
+                .iter()
+                .filter(|method| method.vm_type == "Ed25519VerificationKey2020")
+                .find_map(|method| {
+                    method
+                        .public_key_multibase
+                        .as_deref()
+                        .and_then(decode_ed25519_multibase)
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "Issuer DID {} has no Ed25519VerificationKey2020 key",
+                        issuer_did
+                    )
+                })?
+        };
+
+        self.issuer_keys
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(issuer_did.to_string(), (key, Instant::now()));
+        Ok(key)
+    }
+
+    /// Cached key of an issuer, dropping it once it is older than `ISSUER_KEY_CACHE_TTL`
+    fn cached_issuer_key(&self, issuer_did: &str) -> Option<VerificationKey> {
+        let mut cache = self
+            .issuer_keys
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (key, resolved_at) = *cache.get(issuer_did)?;
+        if resolved_at.elapsed() < ISSUER_KEY_CACHE_TTL {
+            return Some(key);
+        }
+        cache.pop(issuer_did);
+        None
+    }
+
+    /// Revoke a UCAN token
+    pub async fn revoke_token(&self, user_id: i64, token: &str) -> Result<(), AppError> {
+        let token_id = self.own_token_id(token).ok_or_else(|| {
+            AppError::ValidationError(
+                "Only UCANs issued by this service can be revoked".to_string(),
+            )
+        })?;
+        let token_id = token_id.as_str();
 
         // Check if the user owns the token
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
@@ -246,10 +722,7 @@ impl UcanService {
         }
 
         // Mark the token as revoked in the database
-        let now = Utc::now()
-            .naive_utc()
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+        let now = to_db(Utc::now());
 
         "UPDATE ucan_tokens SET revoked = TRUE, revoked_at = :revoked_at WHERE id = :id"
             .with(params! {
@@ -265,34 +738,327 @@ impl UcanService {
 
         info!("Revoked token {} for user {}", token_id, user_id);
 
+        self.audit_service
+            .record_audit(
+                user_id,
+                "ucan.revoke",
+                "ucan_token",
+                token_id,
+                serde_json::json!({}),
+            )
+            .await;
+
         Ok(())
     }
 
-    /// Check if a token is revoked
-    async fn is_token_revoked(&self, token: &str) -> Result<bool, AppError> {
-        // Extract token ID from our simple format
-        let token_id = token.split(':').nth(2).ok_or_else(|| {
-            error!("Invalid token format");
-            AppError::AuthError("Invalid token format".to_string())
+    /// The UCANs `user_id` issued, newest first. Revoked and expired tokens are left out
+    /// unless asked for.
+    pub async fn list_tokens(
+        &self,
+        user_id: i64,
+        include_revoked: bool,
+        include_expired: bool,
+        limit: u32,
+        offset: u64,
+    ) -> Result<UcanTokenPage, AppError> {
+        let limit = limit.clamp(1, MAX_LISTED_TOKENS);
+        let filter = r"WHERE user_id = :user_id
+              AND (:include_revoked OR revoked = FALSE)
+              AND (:include_expired OR expires_at > UTC_TIMESTAMP())";
+        let params = params! {
+            "user_id" => user_id,
+            "include_revoked" => include_revoked,
+            "include_expired" => include_expired,
+        };
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
         })?;
 
-        // Check the database to see if it's revoked
+        let total: Option<u64> = format!("SELECT COUNT(*) FROM ucan_tokens {}", filter)
+            .with(params.clone())
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when counting UCAN tokens: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        // The token is only read for its capabilities and never returned
+        let rows: Vec<Row> = format!(
+            r"SELECT id, token, audience_did, issued_at, expires_at, revoked, revoked_at,
+                     revocation_reason, delegated_from
+              FROM ucan_tokens {} ORDER BY issued_at DESC, id DESC LIMIT {} OFFSET {}",
+            filter, limit, offset
+        )
+        .with(params)
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing UCAN tokens: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        let tokens = rows
+            .into_iter()
+            .map(|row| {
+                let token: String = row.get(1).ok_or(AppError::DeserializationError)?;
+                Ok(UcanTokenSummary {
+                    id: row.get(0).ok_or(AppError::DeserializationError)?,
+                    audience: row.get(2).ok_or(AppError::DeserializationError)?,
+                    capabilities: token_capabilities(&token),
+                    issued_at: row
+                        .as_ref(3)
+                        .and_then(from_db_value)
+                        .ok_or(AppError::DeserializationError)?,
+                    expires_at: row
+                        .as_ref(4)
+                        .and_then(from_db_value)
+                        .ok_or(AppError::DeserializationError)?,
+                    revoked: row.get::<Option<bool>, _>(5).flatten().unwrap_or(false),
+                    revoked_at: row.as_ref(6).and_then(from_db_value),
+                    revocation_reason: row.get(7).flatten(),
+                    delegated_from: row.get(8).flatten(),
+                })
+            })
+            .collect::<Result<_, AppError>>()?;
+
+        Ok(UcanTokenPage {
+            tokens,
+            limit,
+            offset,
+            total: total.unwrap_or(0),
+        })
+    }
+
+    /// Revoke every active token delegated, directly or through other tokens, from a
+    /// revoked token. Returns the number of tokens revoked.
+    ///
+    /// `delegated_from` chains are walked from each unexpired, unrevoked delegated token;
+    /// chains that loop or lead to a token no longer on record are left alone.
+    pub async fn cascade_revocations(&self) -> Result<usize, AppError> {
         let mut conn = self.db_pool.get_conn().await.map_err(|e| {
             error!("Failed to get database connection: {}", e);
             AppError::DatabaseError(e.to_string())
         })?;
 
-        let revoked: Option<i32> = "SELECT revoked FROM ucan_tokens WHERE id = :id"
+        let candidates: Vec<(String, i64, String)> =
+            "SELECT id, user_id, delegated_from FROM ucan_tokens
+             WHERE revoked = FALSE AND expires_at > UTC_TIMESTAMP() AND delegated_from IS NOT NULL"
+                .with(())
+                .fetch(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when listing delegated tokens: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+
+        let mut links: HashMap<String, Option<ChainLink>> = candidates
+            .iter()
+            .map(|(id, _, parent)| {
+                let link = ChainLink {
+                    revoked: false,
+                    delegated_from: Some(parent.clone()),
+                };
+                (id.clone(), Some(link))
+            })
+            .collect();
+
+        // Load the ancestors of the candidates, one generation per pass
+        let mut missing: Vec<String> = candidates
+            .iter()
+            .map(|(_, _, parent)| parent.clone())
+            .filter(|parent| !links.contains_key(parent))
+            .collect();
+        while !missing.is_empty() {
+            let mut next = Vec::new();
+            for id in missing {
+                if links.contains_key(&id) {
+                    continue;
+                }
+                let row: Option<(bool, Option<String>)> =
+                    "SELECT revoked, delegated_from FROM ucan_tokens WHERE id = :id"
+                        .with(params! { "id" => &id })
+                        .first(&mut conn)
+                        .await
+                        .map_err(|e| {
+                            error!("Database error when loading delegation chain: {}", e);
+                            AppError::DatabaseError(e.to_string())
+                        })?;
+                let link = row.map(|(revoked, delegated_from)| ChainLink {
+                    revoked,
+                    delegated_from,
+                });
+                if let Some(parent) = link.as_ref().and_then(|l| l.delegated_from.clone()) {
+                    next.push(parent);
+                }
+                links.insert(id, link);
+            }
+            missing = next;
+        }
+
+        let now = to_db(Utc::now());
+        let mut cascaded = 0;
+        for (token_id, user_id, _) in &candidates {
+            let Some(ancestor) = first_revoked_ancestor(token_id, &links) else {
+                continue;
+            };
+            let reason = format!("Ancestor token {} was revoked", ancestor);
+
+            "UPDATE ucan_tokens SET revoked = TRUE, revoked_at = :revoked_at,
+                 revocation_reason = :reason
+             WHERE id = :id AND revoked = FALSE"
+                .with(params! {
+                    "revoked_at" => &now,
+                    "reason" => &reason,
+                    "id" => token_id,
+                })
+                .ignore(&mut conn)
+                .await
+                .map_err(|e| {
+                    error!("Database error when revoking token: {}", e);
+                    AppError::DatabaseError(e.to_string())
+                })?;
+            if conn.affected_rows() == 0 {
+                continue;
+            }
+            cascaded += 1;
+
+            self.audit_service
+                .record_audit(
+                    *user_id,
+                    "ucan.revoke",
+                    "ucan_token",
+                    token_id,
+                    serde_json::json!({ "cascade": true, "reason": reason }),
+                )
+                .await;
+        }
+
+        Ok(cascaded)
+    }
+
+    /// Run `cascade_revocations` every `interval`
+    pub fn start_revocation_sweep(self: Arc<Self>, interval: StdDuration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.cascade_revocations().await {
+                    Ok(0) => {}
+                    Ok(cascaded) => info!(
+                        "Revoked {} delegated UCANs with a revoked ancestor",
+                        cascaded
+                    ),
+                    Err(e) => error!("UCAN revocation sweep failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// User a token issued by this service acts for; `None` for external UCANs, which
+    /// aren't tied to a local user
+    pub async fn token_user_id(&self, token: &str) -> Result<Option<i64>, AppError> {
+        let Some(token_id) = self.own_token_id(token) else {
+            return Ok(None);
+        };
+
+        let mut conn = self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        "SELECT user_id FROM ucan_tokens WHERE id = :id"
             .with(params! {
                 "id" => token_id,
             })
             .first(&mut conn)
             .await
             .map_err(|e| {
-                error!("Database error when checking token revocation: {}", e);
+                error!("Database error when looking up token owner: {}", e);
                 AppError::DatabaseError(e.to_string())
-            })?;
+            })
+    }
+
+    /// `ucan_tokens` ID of a token naming this service as issuer. The signature isn't
+    /// checked; callers validate the token first.
+    fn own_token_id(&self, token: &str) -> Option<String> {
+        let payload = token.split('.').nth(1)?;
+        let payload: UcanJwtPayload = decode_jwt_segment(payload, "payload").ok()?;
+        if payload.iss != self.service_did {
+            return None;
+        }
+        payload.nnc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(revoked: bool, delegated_from: Option<&str>) -> Option<ChainLink> {
+        Some(ChainLink {
+            revoked,
+            delegated_from: delegated_from.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn test_first_revoked_ancestor_walks_chain() {
+        let links: HashMap<String, Option<ChainLink>> = [
+            ("root", link(true, None)),
+            ("middle", link(false, Some("root"))),
+            ("leaf", link(false, Some("middle"))),
+            ("orphan", link(false, Some("gone"))),
+            ("gone", None),
+            ("loop-a", link(false, Some("loop-b"))),
+            ("loop-b", link(false, Some("loop-a"))),
+            ("live", link(false, None)),
+            ("child-of-live", link(false, Some("live"))),
+        ]
+        .into_iter()
+        .map(|(id, link)| (id.to_string(), link))
+        .collect();
 
-        Ok(revoked.unwrap_or(0) == 1)
+        assert_eq!(
+            first_revoked_ancestor("leaf", &links).as_deref(),
+            Some("root")
+        );
+        assert_eq!(
+            first_revoked_ancestor("middle", &links).as_deref(),
+            Some("root")
+        );
+        assert_eq!(first_revoked_ancestor("orphan", &links), None);
+        assert_eq!(first_revoked_ancestor("loop-a", &links), None);
+        assert_eq!(first_revoked_ancestor("child-of-live", &links), None);
+    }
+
+    #[test]
+    fn test_listed_capabilities_come_from_the_stored_payload() {
+        let payload = serde_json::json!({
+            "iss": "did:key:z6Mkservice",
+            "aud": "did:key:z6Mkaudience",
+            "att": [
+                { "with": "dataset:doi:10.5072/FK2/ABC", "can": "upload" },
+                { "with": "did:*", "can": "read" },
+            ],
+        });
+        let token = format!("e30.{}.c2ln", URL_SAFE_NO_PAD.encode(payload.to_string()));
+
+        assert_eq!(
+            token_capabilities(&token),
+            vec![
+                UcanAttenuation {
+                    with: "dataset:doi:10.5072/FK2/ABC".to_string(),
+                    can: "upload".to_string(),
+                },
+                UcanAttenuation {
+                    with: "did:*".to_string(),
+                    can: "read".to_string(),
+                },
+            ]
+        );
+        assert!(token_capabilities("not-a-token").is_empty());
     }
 }
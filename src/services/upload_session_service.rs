@@ -0,0 +1,681 @@
+use crate::database::db_time::{from_db_value, to_db};
+use crate::errors::AppError;
+use crate::models::file_metadata::FileMetadata;
+use crate::services::ipfs_service::IPFSService;
+use crate::utils::to_hex;
+use chrono::{DateTime, Duration, Utc};
+use futures::{Stream, StreamExt};
+use log::{error, info, warn};
+use mysql_async::{params, prelude::*, Pool, Row};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Sessions not written to for this long expire, and their chunks are deleted
+const SESSION_TTL_HOURS: i64 = 24;
+
+/// How often expired sessions are swept
+const SESSION_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Where an upload session stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadSessionStatus {
+    // Accepting chunks
+    Open,
+    // `complete` is adding the assembled file to IPFS
+    Assembling,
+    Completed,
+}
+
+impl UploadSessionStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UploadSessionStatus::Open => "open",
+            UploadSessionStatus::Assembling => "assembling",
+            UploadSessionStatus::Completed => "completed",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(UploadSessionStatus::Open),
+            "assembling" => Some(UploadSessionStatus::Assembling),
+            "completed" => Some(UploadSessionStatus::Completed),
+            _ => None,
+        }
+    }
+}
+
+/// A resumable upload: the file's size and SHA-256 are declared up front, then its chunks
+/// are sent in any order, as often as needed, until `complete` assembles them
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UploadSession {
+    pub id: String,
+    pub file_name: String,
+    // Bytes
+    pub size: u64,
+    // Every chunk is this size except the last, which holds the rest
+    pub chunk_size: u64,
+    pub chunk_count: u64,
+    // Hex SHA-256 the assembled file must have
+    pub sha256: String,
+    pub status: UploadSessionStatus,
+    // Once completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // Extended by each chunk received
+    pub expires_at: DateTime<Utc>,
+}
+
+impl UploadSession {
+    /// Bytes chunk `index` must hold
+    fn chunk_len(&self, index: u64) -> u64 {
+        if index + 1 == self.chunk_count {
+            self.size - index * self.chunk_size
+        } else {
+            self.chunk_size
+        }
+    }
+}
+
+/// A chunk the server holds
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ReceivedChunk {
+    pub index: u64,
+    pub size: u64,
+    // Hex SHA-256 of the chunk as received, to find corrupted chunks when `complete` reports
+    // a hash mismatch
+    pub sha256: String,
+}
+
+/// A session with the chunks received so far and those still to send
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UploadSessionProgress {
+    #[serde(flatten)]
+    pub session: UploadSession,
+    pub bytes_received: u64,
+    pub received_chunks: Vec<ReceivedChunk>,
+    pub missing_chunks: Vec<u64>,
+}
+
+/// Resumable uploads for files too large to send in one request. Chunks are staged on disk
+/// under `UPLOAD_SESSION_DIR` and tracked in `upload_session_chunks` until the session is
+/// completed or expires.
+pub struct UploadSessionService {
+    db_pool: Arc<Pool>,
+    ipfs_service: Arc<IPFSService>,
+    dir: PathBuf,
+    chunk_size: u64,
+    max_size: u64,
+}
+
+impl UploadSessionService {
+    pub fn new(
+        db_pool: Arc<Pool>,
+        ipfs_service: Arc<IPFSService>,
+        dir: PathBuf,
+        chunk_size: u64,
+        max_size: u64,
+    ) -> Self {
+        Self {
+            db_pool,
+            ipfs_service,
+            dir,
+            chunk_size,
+            max_size,
+        }
+    }
+
+    async fn conn(&self) -> Result<mysql_async::Conn, AppError> {
+        self.db_pool.get_conn().await.map_err(|e| {
+            error!("Failed to get database connection: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })
+    }
+
+    /// Open a session for a file of `size` bytes whose SHA-256 is `sha256`
+    pub async fn create(
+        &self,
+        user_id: i32,
+        file_name: &str,
+        size: u64,
+        sha256: &str,
+    ) -> Result<UploadSession, AppError> {
+        if size == 0 {
+            return Err(AppError::ValidationError(
+                "Upload size must be at least 1 byte".to_string(),
+            ));
+        }
+        if size > self.max_size {
+            return Err(AppError::ValidationError(format!(
+                "Upload exceeds the limit of {} bytes",
+                self.max_size
+            )));
+        }
+        let sha256 = parse_sha256(sha256)?;
+
+        let now = Utc::now();
+        let session = UploadSession {
+            id: Uuid::new_v4().to_string(),
+            file_name: file_name.to_string(),
+            size,
+            chunk_size: self.chunk_size,
+            chunk_count: size.div_ceil(self.chunk_size),
+            sha256,
+            status: UploadSessionStatus::Open,
+            cid: None,
+            created_at: now,
+            expires_at: now + Duration::hours(SESSION_TTL_HOURS),
+        };
+
+        let mut conn = self.conn().await?;
+        r"INSERT INTO upload_sessions
+            (id, user_id, file_name, size, chunk_size, sha256, status, created_at, expires_at)
+            VALUES (:id, :user_id, :file_name, :size, :chunk_size, :sha256, :status, :created_at, :expires_at)"
+            .with(params! {
+                "id" => &session.id,
+                "user_id" => user_id,
+                "file_name" => &session.file_name,
+                "size" => session.size,
+                "chunk_size" => session.chunk_size,
+                "sha256" => &session.sha256,
+                "status" => session.status.as_str(),
+                "created_at" => to_db(session.created_at),
+                "expires_at" => to_db(session.expires_at),
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when creating upload session: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!(
+            "Opened upload session {} for {} ({} bytes in {} chunks) for user {}",
+            session.id, session.file_name, session.size, session.chunk_count, user_id
+        );
+        Ok(session)
+    }
+
+    /// The session with its received and missing chunks
+    pub async fn progress(
+        &self,
+        session_id: &str,
+        user_id: i32,
+    ) -> Result<UploadSessionProgress, AppError> {
+        let session = self.get(session_id, user_id).await?;
+        let received_chunks = self.received_chunks(session_id).await?;
+        Ok(UploadSessionProgress {
+            bytes_received: received_chunks.iter().map(|chunk| chunk.size).sum(),
+            missing_chunks: missing_chunks(session.chunk_count, &received_chunks),
+            received_chunks,
+            session,
+        })
+    }
+
+    /// Store chunk `index`, replacing any earlier copy, so resending a chunk is harmless.
+    /// `offset`, when given, must be where the chunk starts, and `sha256` what it hashes to.
+    pub async fn put_chunk<S, E>(
+        &self,
+        session_id: &str,
+        user_id: i32,
+        index: u64,
+        offset: Option<u64>,
+        sha256: Option<&str>,
+        mut body: S,
+    ) -> Result<ReceivedChunk, AppError>
+    where
+        S: Stream<Item = Result<actix_web::web::Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let session = self.get(session_id, user_id).await?;
+        if session.status != UploadSessionStatus::Open {
+            return Err(AppError::Conflict(format!(
+                "Upload session {} is {}",
+                session_id,
+                session.status.as_str()
+            )));
+        }
+        if index >= session.chunk_count {
+            return Err(AppError::ValidationError(format!(
+                "Chunk {} is out of range, the upload has {} chunks",
+                index, session.chunk_count
+            )));
+        }
+        if let Some(offset) = offset.filter(|&offset| offset != index * session.chunk_size) {
+            return Err(AppError::ValidationError(format!(
+                "Chunk {} starts at offset {}, not {}",
+                index,
+                index * session.chunk_size,
+                offset
+            )));
+        }
+        let expected_sha256 = sha256.map(parse_sha256).transpose()?;
+        let expected_len = session.chunk_len(index);
+
+        // Written under a unique name and moved into place, so concurrent or interrupted
+        // sends of the same chunk never leave a partial chunk behind
+        let session_dir = self.dir.join(&session.id);
+        tokio::fs::create_dir_all(&session_dir)
+            .await
+            .map_err(stage_error)?;
+        let partial = session_dir.join(format!("{}.{}.part", index, Uuid::new_v4()));
+        let result = self
+            .write_chunk(&partial, &mut body, index, expected_len)
+            .await;
+        let digest = match result {
+            Ok(digest) => digest,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        };
+        if let Some(expected) = expected_sha256.filter(|expected| *expected != digest) {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(AppError::ValidationError(format!(
+                "Chunk {} has SHA-256 {}, expected {}",
+                index, digest, expected
+            )));
+        }
+        tokio::fs::rename(&partial, session_dir.join(index.to_string()))
+            .await
+            .map_err(stage_error)?;
+
+        let now = Utc::now();
+        let mut conn = self.conn().await?;
+        r"INSERT INTO upload_session_chunks (session_id, chunk_index, size, sha256, received_at)
+            VALUES (:session_id, :chunk_index, :size, :sha256, :received_at)
+            ON DUPLICATE KEY UPDATE size = VALUES(size), sha256 = VALUES(sha256),
+                received_at = VALUES(received_at)"
+            .with(params! {
+                "session_id" => session_id,
+                "chunk_index" => index,
+                "size" => expected_len,
+                "sha256" => &digest,
+                "received_at" => to_db(now),
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when recording upload chunk: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        "UPDATE upload_sessions SET expires_at = :expires_at WHERE id = :id"
+            .with(params! {
+                "expires_at" => to_db(now + Duration::hours(SESSION_TTL_HOURS)),
+                "id" => session_id,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when extending upload session: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        Ok(ReceivedChunk {
+            index,
+            size: expected_len,
+            sha256: digest,
+        })
+    }
+
+    /// Copy the request body to `path`, failing unless it is exactly `expected_len` bytes,
+    /// and return its hex SHA-256
+    async fn write_chunk<S, E>(
+        &self,
+        path: &std::path::Path,
+        body: &mut S,
+        index: u64,
+        expected_len: u64,
+    ) -> Result<String, AppError>
+    where
+        S: Stream<Item = Result<actix_web::web::Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let mut file = tokio::fs::File::create(path).await.map_err(stage_error)?;
+        let mut hasher = Sha256::new();
+        let mut written: u64 = 0;
+        while let Some(bytes) = body.next().await {
+            let bytes = bytes.map_err(|e| {
+                warn!("Chunk {} upload interrupted: {}", index, e);
+                AppError::FileError(format!("Upload interrupted: {}", e))
+            })?;
+            written += bytes.len() as u64;
+            if written > expected_len {
+                return Err(chunk_size_mismatch(index, expected_len, written));
+            }
+            hasher.update(&bytes);
+            file.write_all(&bytes).await.map_err(stage_error)?;
+        }
+        if written != expected_len {
+            return Err(chunk_size_mismatch(index, expected_len, written));
+        }
+        file.flush().await.map_err(stage_error)?;
+        Ok(to_hex(&hasher.finalize()))
+    }
+
+    /// Assemble the chunks, check the file's SHA-256 and add it to IPFS. Completing a
+    /// completed session returns its file again.
+    pub async fn complete(&self, session_id: &str, user_id: i32) -> Result<FileMetadata, AppError> {
+        let session = self.get(session_id, user_id).await?;
+        if let Some(cid) = session.cid.as_deref() {
+            return self.stored_file(cid).await;
+        }
+
+        // Only one request assembles a session
+        let mut conn = self.conn().await?;
+        let claimed = r"UPDATE upload_sessions SET status = :assembling
+            WHERE id = :id AND status = :open"
+            .with(params! {
+                "assembling" => UploadSessionStatus::Assembling.as_str(),
+                "open" => UploadSessionStatus::Open.as_str(),
+                "id" => session_id,
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when completing upload session: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?
+            .affected_rows();
+        if claimed == 0 {
+            return Err(AppError::Conflict(format!(
+                "Upload session {} is already being completed",
+                session_id
+            )));
+        }
+
+        let result = self.assemble(&session, user_id).await;
+        let (status, cid) = match &result {
+            Ok(metadata) => (UploadSessionStatus::Completed, Some(metadata.cid.as_str())),
+            Err(_) => (UploadSessionStatus::Open, None),
+        };
+        "UPDATE upload_sessions SET status = :status, cid = :cid WHERE id = :id"
+            .with(params! {
+                "status" => status.as_str(),
+                "cid" => cid,
+                "id" => session_id,
+            })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when completing upload session: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let metadata = result?;
+        "DELETE FROM upload_session_chunks WHERE session_id = :id"
+            .with(params! { "id" => session_id })
+            .ignore(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when clearing upload chunks: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        self.remove_staged(session_id).await;
+
+        info!(
+            "Completed upload session {} as {} for user {}",
+            session_id, metadata.cid, user_id
+        );
+        Ok(metadata)
+    }
+
+    async fn assemble(
+        &self,
+        session: &UploadSession,
+        user_id: i32,
+    ) -> Result<FileMetadata, AppError> {
+        let received = self.received_chunks(&session.id).await?;
+        let missing = missing_chunks(session.chunk_count, &received);
+        if !missing.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "Upload session {} is missing chunks {}",
+                session.id,
+                missing
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+
+        // Hashed before anything reaches IPFS, so a corrupted file is never added
+        let chunk_paths: Vec<PathBuf> = (0..session.chunk_count)
+            .map(|index| self.dir.join(&session.id).join(index.to_string()))
+            .collect();
+        let mut hasher = Sha256::new();
+        for path in &chunk_paths {
+            let bytes = tokio::fs::read(path).await.map_err(stage_error)?;
+            hasher.update(&bytes);
+        }
+        let digest = to_hex(&hasher.finalize());
+        if digest != session.sha256 {
+            warn!(
+                "Upload session {} assembled to SHA-256 {}, declared {}",
+                session.id, digest, session.sha256
+            );
+            return Err(AppError::ValidationError(format!(
+                "Assembled file has SHA-256 {}, expected {}; compare the received chunks' \
+                 hashes and resend those that differ",
+                digest, session.sha256
+            )));
+        }
+
+        let file_stream = Box::pin(futures::stream::iter(chunk_paths).then(|path| async move {
+            tokio::fs::read(path)
+                .await
+                .map_err(|e| crate::errors::ServiceError::Internal(e.to_string()))
+        }));
+        self.ipfs_service
+            .upload(file_stream, session.file_name.clone(), user_id)
+            .await
+            .map_err(|e| {
+                error!("Failed to add upload session {} to IPFS: {}", session.id, e);
+                AppError::ServiceError(e.to_string())
+            })
+    }
+
+    async fn stored_file(&self, cid: &str) -> Result<FileMetadata, AppError> {
+        self.ipfs_service
+            .get_file_metadata(cid)
+            .await
+            .map_err(|e| AppError::ServiceError(e.to_string()))?
+            .ok_or_else(|| AppError::NotFound(format!("File not found: {}", cid)))
+    }
+
+    /// The session, if `user_id` opened it and it hasn't expired
+    async fn get(&self, session_id: &str, user_id: i32) -> Result<UploadSession, AppError> {
+        let mut conn = self.conn().await?;
+        let row: Option<Row> = r"SELECT id, file_name, size, chunk_size, sha256, status, cid,
+                created_at, expires_at
+            FROM upload_sessions
+            WHERE id = :id AND user_id = :user_id
+              AND (expires_at > :now OR status = :completed)"
+            .with(params! {
+                "id" => session_id,
+                "user_id" => user_id,
+                "now" => to_db(Utc::now()),
+                "completed" => UploadSessionStatus::Completed.as_str(),
+            })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reading upload session: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        row.and_then(session_from_row)
+            .ok_or_else(|| AppError::NotFound(format!("Upload session not found: {}", session_id)))
+    }
+
+    async fn received_chunks(&self, session_id: &str) -> Result<Vec<ReceivedChunk>, AppError> {
+        let mut conn = self.conn().await?;
+        let rows: Vec<(u64, u64, String)> = r"SELECT chunk_index, size, sha256
+            FROM upload_session_chunks WHERE session_id = :id ORDER BY chunk_index"
+            .with(params! { "id" => session_id })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when reading upload chunks: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+        Ok(rows
+            .into_iter()
+            .map(|(index, size, sha256)| ReceivedChunk {
+                index,
+                size,
+                sha256,
+            })
+            .collect())
+    }
+
+    async fn remove_staged(&self, session_id: &str) {
+        let dir = self.dir.join(session_id);
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove staged chunks in {}: {}", dir.display(), e);
+            }
+        }
+    }
+
+    /// Delete expired sessions and their chunks every `SESSION_SWEEP_INTERVAL`
+    pub fn start_sweep(self: Arc<Self>) {
+        tokio::task::spawn_local(async move {
+            let mut ticker = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                match self.sweep_expired().await {
+                    Ok(0) => {}
+                    Ok(removed) => info!("Removed {} expired upload sessions", removed),
+                    Err(e) => warn!("Failed to sweep expired upload sessions: {}", e),
+                }
+            }
+        });
+    }
+
+    async fn sweep_expired(&self) -> Result<usize, AppError> {
+        let mut conn = self.conn().await?;
+        let now = to_db(Utc::now());
+        let expired: Vec<String> = r"SELECT id FROM upload_sessions
+            WHERE expires_at <= :now AND status <> :completed"
+            .with(params! {
+                "now" => &now,
+                "completed" => UploadSessionStatus::Completed.as_str(),
+            })
+            .fetch(&mut conn)
+            .await?;
+        for session_id in &expired {
+            self.remove_staged(session_id).await;
+            // Chunk rows go with the session
+            "DELETE FROM upload_sessions WHERE id = :id"
+                .with(params! { "id" => session_id })
+                .ignore(&mut conn)
+                .await?;
+        }
+        Ok(expired.len())
+    }
+}
+
+/// Lowercase hex SHA-256, or a validation error
+fn parse_sha256(value: &str) -> Result<String, AppError> {
+    let value = value.trim().to_ascii_lowercase();
+    if value.len() != 64 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::ValidationError(format!(
+            "Invalid SHA-256, expected 64 hex digits: {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+/// Indexes below `chunk_count` not among `received`, which is sorted by index
+fn missing_chunks(chunk_count: u64, received: &[ReceivedChunk]) -> Vec<u64> {
+    let mut received = received.iter().map(|chunk| chunk.index).peekable();
+    (0..chunk_count)
+        .filter(|index| {
+            while received.next_if(|received| received < index).is_some() {}
+            received.next_if_eq(index).is_none()
+        })
+        .collect()
+}
+
+fn chunk_size_mismatch(index: u64, expected: u64, received: u64) -> AppError {
+    AppError::ValidationError(format!(
+        "Chunk {} must be {} bytes, received {}",
+        index,
+        expected,
+        if received > expected {
+            format!("more than {}", expected)
+        } else {
+            received.to_string()
+        }
+    ))
+}
+
+fn stage_error(e: std::io::Error) -> AppError {
+    error!("Failed to stage upload chunk: {}", e);
+    AppError::FileError(format!("Failed to stage upload chunk: {}", e))
+}
+
+fn session_from_row(row: Row) -> Option<UploadSession> {
+    let size: u64 = row.get(2)?;
+    let chunk_size: u64 = row.get(3)?;
+    Some(UploadSession {
+        id: row.get(0)?,
+        file_name: row.get(1)?,
+        size,
+        chunk_size,
+        chunk_count: size.div_ceil(chunk_size),
+        sha256: row.get(4)?,
+        status: UploadSessionStatus::parse(&row.get::<String, _>(5)?)?,
+        cid: row.get(6)?,
+        created_at: row.as_ref(7).and_then(from_db_value)?,
+        expires_at: row.as_ref(8).and_then(from_db_value)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_report_missing_chunks_and_last_chunk_size() {
+        let session = UploadSession {
+            id: "s".to_string(),
+            file_name: "reads.fastq.gz".to_string(),
+            size: 10,
+            chunk_size: 4,
+            chunk_count: 10u64.div_ceil(4),
+            sha256: "0".repeat(64),
+            status: UploadSessionStatus::Open,
+            cid: None,
+            created_at: Utc::now(),
+            expires_at: Utc::now(),
+        };
+        assert_eq!(session.chunk_count, 3);
+        assert_eq!(session.chunk_len(0), 4);
+        assert_eq!(session.chunk_len(2), 2);
+
+        let chunk = |index| ReceivedChunk {
+            index,
+            size: 4,
+            sha256: String::new(),
+        };
+        assert_eq!(missing_chunks(3, &[]), vec![0, 1, 2]);
+        assert_eq!(missing_chunks(3, &[chunk(1)]), vec![0, 2]);
+        assert_eq!(
+            missing_chunks(3, &[chunk(0), chunk(1), chunk(2)]),
+            Vec::<u64>::new()
+        );
+
+        assert_eq!(parse_sha256(&"AB".repeat(32)).unwrap(), "ab".repeat(32));
+        assert!(parse_sha256("abc").is_err());
+        assert!(parse_sha256(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,540 @@
+use crate::database::db_time::{from_db, to_db};
+use crate::errors::AppError;
+use crate::models::webhook::{
+    Webhook, WebhookCreated, WebhookDelivery, WebhookEvent, WebhookRequest, MAX_WEBHOOKS_PER_USER,
+};
+use crate::services::http_client::{check_public_url, HttpClient};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use mysql_async::{prelude::*, Pool, Row};
+use rand_core::{OsRng, RngCore};
+use serde_json::json;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Attempts per delivery before it is marked failed
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+/// Wait before the first retry; doubled after each failed attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Upper bound on a single delivery request
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of recent deliveries returned per webhook
+const DELIVERY_HISTORY_LIMIT: u32 = 50;
+
+/// Header carrying `sha256=<hex HMAC of the body>`
+const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Subscriber registered for a delivery
+struct Subscriber {
+    webhook_id: String,
+    url: String,
+    secret: String,
+}
+
+/// Manages webhook subscriptions and delivers signed DID lifecycle events to them.
+///
+/// Users receive events for the DIDs they own; webhooks registered by admins receive
+/// every event. Deliveries run in the background and never fail or delay the operation
+/// that triggered them.
+pub struct WebhookService {
+    db_pool: Arc<Pool>,
+    client: HttpClient,
+}
+
+impl WebhookService {
+    /// Deliveries go through a client of their own that doesn't follow redirects or
+    /// connect to private addresses
+    pub fn new(db_pool: Arc<Pool>, client: &HttpClient) -> Result<Self, reqwest::Error> {
+        Ok(Self {
+            db_pool,
+            client: client.public_only()?.with_timeout(DELIVERY_TIMEOUT),
+        })
+    }
+
+    /// Register a webhook; the returned secret is not shown again
+    pub async fn create_webhook(
+        &self,
+        mut request: WebhookRequest,
+        user_id: i64,
+    ) -> Result<WebhookCreated, AppError> {
+        request.validate()?;
+        check_public_url(&request.url)
+            .await
+            .map_err(|e| AppError::ValidationError(format!("Invalid webhook URL: {}", e)))?;
+
+        let mut conn = self.db_pool.get_conn().await?;
+        let count: Option<u64> = "SELECT COUNT(*) FROM webhooks WHERE user_id = :user_id"
+            .with(params! { "user_id" => user_id })
+            .first(&mut conn)
+            .await?;
+        if count.unwrap_or(0) >= MAX_WEBHOOKS_PER_USER as u64 {
+            return Err(AppError::ValidationError(format!(
+                "At most {} webhooks can be registered",
+                MAX_WEBHOOKS_PER_USER
+            )));
+        }
+
+        let webhook_id = Uuid::new_v4().to_string();
+        let secret = generate_secret();
+        let now = to_db(Utc::now());
+        let events = serde_json::to_string(&request.events).map_err(|e| {
+            error!("Failed to serialize webhook events: {}", e);
+            AppError::SerializationError
+        })?;
+
+        r"INSERT INTO webhooks (webhook_id, user_id, url, secret, events, active, created_at, updated_at)
+          VALUES (:webhook_id, :user_id, :url, :secret, :events, :active, :created_at, :updated_at)"
+            .with(params! {
+                "webhook_id" => &webhook_id,
+                "user_id" => user_id,
+                "url" => &request.url,
+                "secret" => &secret,
+                "events" => events,
+                "active" => request.active,
+                "created_at" => &now,
+                "updated_at" => &now,
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when storing webhook: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Registered webhook {} for user {}", webhook_id, user_id);
+
+        Ok(WebhookCreated {
+            webhook: self.get_webhook(&webhook_id, user_id).await?,
+            secret,
+        })
+    }
+
+    /// List the user's webhooks, newest first
+    pub async fn list_webhooks(&self, user_id: i64) -> Result<Vec<Webhook>, AppError> {
+        let mut conn = self.db_pool.get_conn().await?;
+
+        let rows: Vec<Row> = format!(
+            "{} WHERE user_id = :user_id ORDER BY created_at DESC",
+            WEBHOOK_COLUMNS
+        )
+        .with(params! { "user_id" => user_id })
+        .fetch(&mut conn)
+        .await
+        .map_err(|e| {
+            error!("Database error when listing webhooks: {}", e);
+            AppError::DatabaseError(e.to_string())
+        })?;
+
+        rows.into_iter().map(webhook_from_row).collect()
+    }
+
+    /// Get a webhook owned by the user
+    pub async fn get_webhook(&self, webhook_id: &str, user_id: i64) -> Result<Webhook, AppError> {
+        let mut conn = self.db_pool.get_conn().await?;
+
+        let row: Option<Row> = format!("{} WHERE webhook_id = :webhook_id", WEBHOOK_COLUMNS)
+            .with(params! { "webhook_id" => webhook_id })
+            .first(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when retrieving webhook: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        let webhook = row
+            .map(webhook_from_row)
+            .transpose()?
+            .ok_or_else(|| AppError::NotFound(format!("Webhook not found: {}", webhook_id)))?;
+
+        if webhook.user_id != user_id {
+            return Err(AppError::AuthorizationError(
+                "Not authorized to access this webhook".to_string(),
+            ));
+        }
+
+        Ok(webhook)
+    }
+
+    /// Replace a webhook's URL, events and active flag. The secret is kept.
+    pub async fn update_webhook(
+        &self,
+        webhook_id: &str,
+        mut request: WebhookRequest,
+        user_id: i64,
+    ) -> Result<Webhook, AppError> {
+        request.validate()?;
+        check_public_url(&request.url)
+            .await
+            .map_err(|e| AppError::ValidationError(format!("Invalid webhook URL: {}", e)))?;
+        self.get_webhook(webhook_id, user_id).await?;
+
+        let events = serde_json::to_string(&request.events).map_err(|e| {
+            error!("Failed to serialize webhook events: {}", e);
+            AppError::SerializationError
+        })?;
+
+        let mut conn = self.db_pool.get_conn().await?;
+        r"UPDATE webhooks
+          SET url = :url, events = :events, active = :active, updated_at = :updated_at
+          WHERE webhook_id = :webhook_id"
+            .with(params! {
+                "webhook_id" => webhook_id,
+                "url" => &request.url,
+                "events" => events,
+                "active" => request.active,
+                "updated_at" => to_db(Utc::now()),
+            })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when updating webhook: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Updated webhook {}", webhook_id);
+
+        self.get_webhook(webhook_id, user_id).await
+    }
+
+    /// Delete a webhook and its delivery history
+    pub async fn delete_webhook(&self, webhook_id: &str, user_id: i64) -> Result<(), AppError> {
+        self.get_webhook(webhook_id, user_id).await?;
+
+        let mut conn = self.db_pool.get_conn().await?;
+        "DELETE FROM webhooks WHERE webhook_id = :webhook_id"
+            .with(params! { "webhook_id" => webhook_id })
+            .run(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when deleting webhook: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        info!("Deleted webhook {}", webhook_id);
+        Ok(())
+    }
+
+    /// Most recent deliveries to a webhook owned by the user, newest first
+    pub async fn list_deliveries(
+        &self,
+        webhook_id: &str,
+        user_id: i64,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        self.get_webhook(webhook_id, user_id).await?;
+
+        let mut conn = self.db_pool.get_conn().await?;
+        let rows: Vec<Row> = r"
+            SELECT id, webhook_id, event, did, status, attempts, response_status, last_error,
+                   DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s'),
+                   DATE_FORMAT(delivered_at, '%Y-%m-%d %H:%i:%s')
+            FROM webhook_deliveries
+            WHERE webhook_id = :webhook_id
+            ORDER BY id DESC
+            LIMIT :limit"
+            .with(params! { "webhook_id" => webhook_id, "limit" => DELIVERY_HISTORY_LIMIT })
+            .fetch(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing webhook deliveries: {}", e);
+                AppError::DatabaseError(e.to_string())
+            })?;
+
+        rows.into_iter().map(delivery_from_row).collect()
+    }
+
+    /// Deliver a DID lifecycle event to every matching webhook in the background
+    pub fn dispatch(&self, event: WebhookEvent, did: &str, cid: &str, owner_id: i64) {
+        let db_pool = self.db_pool.clone();
+        let client = self.client.clone();
+        let did = did.to_string();
+        let payload = json!({
+            "event": event.as_str(),
+            "did": did,
+            "cid": cid,
+            "timestamp": Utc::now().to_rfc3339(),
+        })
+        .to_string();
+
+        tokio::spawn(async move {
+            let subscribers = match find_subscribers(&db_pool, event, owner_id).await {
+                Ok(subscribers) => subscribers,
+                Err(e) => {
+                    warn!(
+                        "Failed to look up webhooks for {} on {}: {}",
+                        event.as_str(),
+                        did,
+                        e
+                    );
+                    return;
+                }
+            };
+
+            // Each subscriber retries independently so a slow one can't hold up the rest
+            for subscriber in subscribers {
+                tokio::spawn(deliver(
+                    db_pool.clone(),
+                    client.clone(),
+                    subscriber,
+                    event,
+                    did.clone(),
+                    payload.clone(),
+                ));
+            }
+        });
+    }
+}
+
+/// Active webhooks subscribed to `event` that may see DIDs owned by `owner_id`
+async fn find_subscribers(
+    db_pool: &Pool,
+    event: WebhookEvent,
+    owner_id: i64,
+) -> Result<Vec<Subscriber>, AppError> {
+    let mut conn = db_pool.get_conn().await?;
+    let rows: Vec<(String, String, String, String)> = r"
+        SELECT w.webhook_id, w.url, w.secret, w.events
+        FROM webhooks w JOIN users u ON u.id = w.user_id
+        WHERE w.active = TRUE AND (w.user_id = :owner_id OR u.role = 'admin')"
+        .with(params! { "owner_id" => owner_id })
+        .fetch(&mut conn)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter(|(_, _, _, events)| {
+            serde_json::from_str::<Vec<String>>(events)
+                .map(|events| events.iter().any(|e| e == event.as_str()))
+                .unwrap_or(false)
+        })
+        .map(|(webhook_id, url, secret, _)| Subscriber {
+            webhook_id,
+            url,
+            secret,
+        })
+        .collect())
+}
+
+/// POST the payload to one subscriber, retrying with exponential backoff and recording
+/// each attempt in `webhook_deliveries`
+async fn deliver(
+    db_pool: Arc<Pool>,
+    client: HttpClient,
+    subscriber: Subscriber,
+    event: WebhookEvent,
+    did: String,
+    payload: String,
+) {
+    let delivery_id = match record_delivery(&db_pool, &subscriber, event, &did, &payload).await {
+        Ok(id) => id,
+        Err(e) => {
+            warn!(
+                "Failed to record delivery to webhook {}: {}",
+                subscriber.webhook_id, e
+            );
+            return;
+        }
+    };
+    let signature = sign_payload(&subscriber.secret, &payload);
+
+    let mut delay = INITIAL_RETRY_DELAY;
+    for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+        // The host may have been re-pointed since the webhook was registered
+        if let Err(e) = check_public_url(&subscriber.url).await {
+            warn!(
+                "Not delivering {} for {} to webhook {}: {}",
+                event.as_str(),
+                did,
+                subscriber.webhook_id,
+                e
+            );
+            update_delivery(&db_pool, delivery_id, "failed", attempt, None, Some(&e)).await;
+            return;
+        }
+
+        let result = client
+            .post(&subscriber.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header("X-Webhook-Event", event.as_str())
+            .header("X-Webhook-Delivery", delivery_id.to_string())
+            .body(payload.clone())
+            .send()
+            .await;
+
+        let (response_status, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                update_delivery(
+                    &db_pool,
+                    delivery_id,
+                    "delivered",
+                    attempt,
+                    Some(response.status().as_u16()),
+                    None,
+                )
+                .await;
+                info!(
+                    "Delivered {} for {} to webhook {}",
+                    event.as_str(),
+                    did,
+                    subscriber.webhook_id
+                );
+                return;
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                format!("Subscriber responded with {}", response.status()),
+            ),
+            Err(e) => (None, format!("Request failed: {}", e)),
+        };
+
+        let status = if attempt == MAX_DELIVERY_ATTEMPTS {
+            "failed"
+        } else {
+            "pending"
+        };
+        update_delivery(
+            &db_pool,
+            delivery_id,
+            status,
+            attempt,
+            response_status,
+            Some(&error),
+        )
+        .await;
+
+        if attempt < MAX_DELIVERY_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        } else {
+            warn!(
+                "Giving up on delivering {} for {} to webhook {} after {} attempts: {}",
+                event.as_str(),
+                did,
+                subscriber.webhook_id,
+                attempt,
+                error
+            );
+        }
+    }
+}
+
+async fn record_delivery(
+    db_pool: &Pool,
+    subscriber: &Subscriber,
+    event: WebhookEvent,
+    did: &str,
+    payload: &str,
+) -> Result<u64, AppError> {
+    let mut conn = db_pool.get_conn().await?;
+    r"INSERT INTO webhook_deliveries (webhook_id, event, did, payload, status, attempts, created_at)
+      VALUES (:webhook_id, :event, :did, :payload, 'pending', 0, :created_at)"
+        .with(params! {
+            "webhook_id" => &subscriber.webhook_id,
+            "event" => event.as_str(),
+            "did" => did,
+            "payload" => payload,
+            "created_at" => to_db(Utc::now()),
+        })
+        .run(&mut conn)
+        .await?;
+
+    conn.last_insert_id()
+        .ok_or_else(|| AppError::DatabaseError("Missing delivery ID".to_string()))
+}
+
+async fn update_delivery(
+    db_pool: &Pool,
+    delivery_id: u64,
+    status: &str,
+    attempts: u32,
+    response_status: Option<u16>,
+    error: Option<&str>,
+) {
+    let result = async {
+        let mut conn = db_pool.get_conn().await?;
+        r"UPDATE webhook_deliveries
+          SET status = :status, attempts = :attempts, response_status = :response_status,
+              last_error = :last_error,
+              delivered_at = IF(:status = 'delivered', UTC_TIMESTAMP(), delivered_at)
+          WHERE id = :id"
+            .with(params! {
+                "id" => delivery_id,
+                "status" => status,
+                "attempts" => attempts,
+                "response_status" => response_status,
+                "last_error" => error,
+            })
+            .run(&mut conn)
+            .await?;
+        Ok::<_, AppError>(())
+    };
+
+    if let Err(e) = result.await {
+        warn!("Failed to update webhook delivery {}: {}", delivery_id, e);
+    }
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of the body, keyed with the webhook's secret string
+fn sign_payload(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    format!(
+        "sha256={}",
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+/// Random 256-bit secret, hex-encoded
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+const WEBHOOK_COLUMNS: &str = r"
+    SELECT webhook_id, user_id, url, events, active,
+           DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s'),
+           DATE_FORMAT(updated_at, '%Y-%m-%d %H:%i:%s')
+    FROM webhooks";
+
+fn webhook_from_row(row: Row) -> Result<Webhook, AppError> {
+    let events: String = row.get(3).unwrap_or_default();
+    let created_at: String = row.get(5).unwrap_or_default();
+    let updated_at: String = row.get(6).unwrap_or_default();
+
+    Ok(Webhook {
+        webhook_id: row.get(0).unwrap_or_default(),
+        user_id: row.get(1).unwrap_or_default(),
+        url: row.get(2).unwrap_or_default(),
+        events: serde_json::from_str(&events).map_err(|_| AppError::DeserializationError)?,
+        active: row.get(4).unwrap_or_default(),
+        created_at: from_db(&created_at).ok_or(AppError::DeserializationError)?,
+        updated_at: from_db(&updated_at).ok_or(AppError::DeserializationError)?,
+    })
+}
+
+fn delivery_from_row(row: Row) -> Result<WebhookDelivery, AppError> {
+    let created_at: String = row.get(8).unwrap_or_default();
+    let delivered_at: Option<String> = row.get(9).flatten();
+
+    Ok(WebhookDelivery {
+        id: row.get(0).unwrap_or_default(),
+        webhook_id: row.get(1).unwrap_or_default(),
+        event: row.get(2).unwrap_or_default(),
+        did: row.get(3).unwrap_or_default(),
+        status: row.get(4).unwrap_or_default(),
+        attempts: row.get(5).unwrap_or_default(),
+        response_status: row.get(6).flatten(),
+        last_error: row.get(7).flatten(),
+        created_at: from_db(&created_at).ok_or(AppError::DeserializationError)?,
+        delivered_at: delivered_at.as_deref().and_then(from_db),
+    })
+}
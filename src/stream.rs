@@ -69,9 +69,7 @@ where
                 // Return the data
                 Poll::Ready(Some(Ok(bytes)))
             }
-            Poll::Ready(Some(Err(e))) => {
-                Poll::Ready(Some(Err(std::io::Error::new(std::io::ErrorKind::Other, e))))
-            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(std::io::Error::other(e)))),
             // Stream has ended
             Poll::Ready(None) => Poll::Ready(None),
             // No data ready yet
@@ -137,9 +135,7 @@ where
                 }
                 Poll::Ready(Ok(()))
             }
-            Poll::Ready(Some(Err(e))) => {
-                Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, e)))
-            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Err(std::io::Error::other(e))),
             Poll::Ready(None) => Poll::Ready(Ok(())),
             Poll::Pending => Poll::Pending,
         }
@@ -212,9 +208,7 @@ where
                 }
                 Poll::Ready(Ok(to_copy))
             }
-            Poll::Ready(Some(Err(e))) => {
-                Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, e)))
-            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Err(std::io::Error::other(e))),
             // EOF: Signal end of stream with 0 bytes read.
             Poll::Ready(None) => Poll::Ready(Ok(0)),
             // Pending: No data yet, rely on the waker for retry.
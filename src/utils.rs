@@ -1,26 +1,88 @@
+use crate::database::db_time::to_db;
+use crate::errors::{AppError, ServiceError};
+use crate::models::file_metadata::TaskInfo;
 use crate::stream::SizedByteStream;
-use crate::{errors::ServiceError, models::file_metadata::TaskInfo};
 use bcrypt::{hash, verify, DEFAULT_COST};
 use chrono::{Duration, Utc};
 use dashmap::DashMap;
 use futures::Stream;
-use ipfs_api::{IpfsApi, IpfsClient};
+use futures_util::StreamExt;
+use ipfs_api::{request::Add, IpfsApi, IpfsClient};
 use mysql_async::{prelude::*, Pool};
+use sha2::{Digest, Sha256};
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-/// Uploads a file to IPFS and returns the CID and file size.
+/// Number of leading bytes inspected by `detect_mime`
+pub const MIME_SNIFF_BYTES: usize = 512;
+
+/// Detect a file's MIME type from its leading bytes.
+///
+/// Only formats seen in research uploads are recognised; anything else returns `None` and
+/// should be treated as `application/octet-stream`. Text without a known signature is
+/// reported as `text/plain` when it is valid UTF-8.
+pub fn detect_mime(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"%PDF-", "application/pdf"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"<?xml", "application/xml"),
+    ];
+
+    if head.is_empty() {
+        return None;
+    }
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(mime);
+    }
+
+    // A multi-byte character may be cut off at the end of the sniffed prefix
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    let trimmed = text.trim_start();
+    if trimmed.starts_with("<?xml") {
+        Some("application/xml")
+    } else if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        Some("application/json")
+    } else if !text.contains('\0') {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+/// Uploads a file to IPFS and returns the CID, file size and detected MIME type.
 pub async fn upload_to_ipfs<S>(
     client: &IpfsClient,
     file_stream: S,
-) -> Result<(String, u64), ServiceError>
+    options: &IpfsAddOptions,
+) -> Result<(String, u64, Option<&'static str>), ServiceError>
 where
     S: Stream<Item = Result<Vec<u8>, ServiceError>> + Send + Sync + Unpin + 'static,
 {
+    // Keep the first bytes as they stream past so the type can be sniffed afterwards
+    let head = Arc::new(Mutex::new(Vec::with_capacity(MIME_SNIFF_BYTES)));
+    let head_writer = head.clone();
+    let file_stream = file_stream.map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            if let Ok(mut head) = head_writer.lock() {
+                let wanted = MIME_SNIFF_BYTES.saturating_sub(head.len());
+                head.extend_from_slice(&bytes[..wanted.min(bytes.len())]);
+            }
+        }
+        chunk
+    });
     let (sized_stream, size_tracker) = SizedByteStream::new(file_stream);
 
     let response = client
-        .add_async(sized_stream)
+        .add_async_with_options(sized_stream, options.request())
         .await
         .map_err(|e| ServiceError::Internal(format!("Failed to upload to IPFS: {}", e)))?;
 
@@ -40,7 +102,9 @@ where
         ));
     }
 
-    Ok((response.hash, total_size))
+    let content_type = head.lock().ok().and_then(|head| detect_mime(&head));
+
+    Ok((response.hash, total_size, content_type))
 }
 
 /// Cleans up old upload tasks from memory and database that are older than 2 hours
@@ -75,7 +139,7 @@ pub async fn cleanup_old_tasks(
         .await
         .map_err(|e| ServiceError::Internal(format!("Failed to get database connection: {}", e)))?;
 
-    let cutoff_str = cutoff_time.format("%Y-%m-%d %H:%M:%S").to_string();
+    let cutoff_str = to_db(cutoff_time);
     conn.exec_drop(
         r"DELETE FROM upload_tasks 
           WHERE started_at < :cutoff 
@@ -100,6 +164,183 @@ pub async fn cleanup_old_tasks(
     Ok(())
 }
 
+/// Largest content `compute_cid` can hash; bigger files are chunked by IPFS
+pub const IPFS_CHUNK_SIZE: usize = 262_144;
+
+/// Chunker passed to `ipfs add`, fixed so chunking doesn't depend on the node's defaults
+const IPFS_CHUNKER: &str = "size-262144";
+
+/// Multihash function `compute_cid` supports, and the default of `IPFS_HASH`
+pub const DEFAULT_IPFS_HASH: &str = "sha2-256";
+
+/// Settings passed to every `ipfs add`. They determine the CIDs content gets, so changing
+/// them gives the same content a different CID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpfsAddOptions {
+    // 0 or 1
+    pub cid_version: u32,
+    // Multihash function, e.g. "sha2-256" or "blake2b-256"
+    pub hash: String,
+    // Store leaves as raw blocks rather than UnixFS nodes
+    pub raw_leaves: bool,
+}
+
+impl Default for IpfsAddOptions {
+    /// Kubo's defaults: CIDv0, sha2-256 and UnixFS leaves
+    fn default() -> Self {
+        Self {
+            cid_version: 0,
+            hash: DEFAULT_IPFS_HASH.to_string(),
+            raw_leaves: false,
+        }
+    }
+}
+
+impl IpfsAddOptions {
+    /// Build options from `IPFS_CID_VERSION`, `IPFS_HASH` and `IPFS_RAW_LEAVES`. Raw leaves
+    /// default to on for CIDv1, as in Kubo; CIDv0 only supports sha2-256 without them.
+    pub fn parse(
+        cid_version: Option<&str>,
+        hash: Option<&str>,
+        raw_leaves: Option<&str>,
+    ) -> Result<Self, AppError> {
+        let cid_version = match cid_version.map(str::trim) {
+            None | Some("0") => 0,
+            Some("1") => 1,
+            Some(other) => {
+                return Err(AppError::ValidationError(format!(
+                    "Invalid CID version '{}', expected 0 or 1",
+                    other
+                )))
+            }
+        };
+        let hash = hash
+            .map(|h| h.trim().to_ascii_lowercase())
+            .unwrap_or_else(|| DEFAULT_IPFS_HASH.to_string());
+        let raw_leaves = match raw_leaves.map(|v| v.trim().to_ascii_lowercase()) {
+            None => cid_version == 1,
+            Some(v) if matches!(v.as_str(), "1" | "true" | "yes") => true,
+            Some(v) if matches!(v.as_str(), "0" | "false" | "no") => false,
+            Some(v) => {
+                return Err(AppError::ValidationError(format!(
+                    "Invalid raw leaves flag '{}', expected true or false",
+                    v
+                )))
+            }
+        };
+
+        if cid_version == 0 && (hash != DEFAULT_IPFS_HASH || raw_leaves) {
+            return Err(AppError::ValidationError(
+                "CIDv0 requires sha2-256 without raw leaves; use CID version 1".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            cid_version,
+            hash,
+            raw_leaves,
+        })
+    }
+
+    /// `ipfs add` parameters applying these options
+    pub fn request(&self) -> Add<'_> {
+        Add {
+            cid_version: Some(self.cid_version),
+            hash: Some(&self.hash),
+            raw_leaves: Some(self.raw_leaves),
+            chunker: Some(IPFS_CHUNKER),
+            ..Default::default()
+        }
+    }
+}
+
+/// Computes the CID `ipfs add` with `options` would assign to `content` (256 KiB chunks).
+///
+/// Returns `None` for content that spans more than one chunk, since reproducing the
+/// balanced DAG layout locally isn't worth the complexity, and for hash functions other
+/// than sha2-256.
+pub fn compute_cid(content: &[u8], options: &IpfsAddOptions) -> Option<String> {
+    if content.len() > IPFS_CHUNK_SIZE || options.hash != DEFAULT_IPFS_HASH {
+        return None;
+    }
+
+    // A single raw leaf is the whole DAG: a CIDv1 of the raw codec over the bytes
+    if options.raw_leaves {
+        return Some(cid_v1(RAW_CODEC, &Sha256::digest(content)));
+    }
+
+    // UnixFS Data { Type: File, Data: content, filesize: len }
+    let mut unixfs = vec![0x08, 0x02];
+    if !content.is_empty() {
+        unixfs.push(0x12);
+        write_varint(&mut unixfs, content.len() as u64);
+        unixfs.extend_from_slice(content);
+    }
+    unixfs.push(0x18);
+    write_varint(&mut unixfs, content.len() as u64);
+
+    // PBNode { Data: unixfs } with no links
+    let mut node = vec![0x0a];
+    write_varint(&mut node, unixfs.len() as u64);
+    node.extend_from_slice(&unixfs);
+
+    let digest = Sha256::digest(&node);
+    if options.cid_version == 1 {
+        return Some(cid_v1(DAG_PB_CODEC, &digest));
+    }
+
+    // CIDv0 is the bare base58btc sha2-256 multihash
+    let mut multihash = vec![0x12, 0x20];
+    multihash.extend_from_slice(&digest);
+
+    Some(bs58::encode(multihash).into_string())
+}
+
+/// Multicodec codes of raw and dag-pb blocks
+const RAW_CODEC: u8 = 0x55;
+const DAG_PB_CODEC: u8 = 0x70;
+
+/// CIDv1 of a sha2-256 `digest`, in the base32 multibase IPFS prints
+fn cid_v1(codec: u8, digest: &[u8]) -> String {
+    let mut cid = vec![0x01, codec, 0x12, 0x20];
+    cid.extend_from_slice(digest);
+    format!("b{}", encode_base32_lower(&cid))
+}
+
+/// RFC 4648 base32, lowercase and without padding
+fn encode_base32_lower(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Appends `value` as a protobuf varint
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Lowercase hex encoding of a digest
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 /// Hashes a password using bcrypt.
 pub fn hash_password(password: &str) -> Result<String, ServiceError> {
     hash(password, DEFAULT_COST)
@@ -111,3 +352,37 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, ServiceError>
     verify(password, hash)
         .map_err(|e| ServiceError::Internal(format!("Password verification failed: {}", e)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_cid_matches_ipfs_add() {
+        // `ipfs add`, `ipfs add --cid-version 1 --raw-leaves=false` and
+        // `ipfs add --cid-version 1` of the same bytes
+        let content = b"hello world\n";
+        let v0 = IpfsAddOptions::default();
+        let v1_dag_pb = IpfsAddOptions::parse(Some("1"), None, Some("false")).unwrap();
+        let v1_raw = IpfsAddOptions::parse(Some("1"), None, None).unwrap();
+
+        assert_eq!(
+            compute_cid(content, &v0).as_deref(),
+            Some("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o")
+        );
+        assert_eq!(
+            compute_cid(content, &v1_dag_pb).as_deref(),
+            Some("bafybeicg2rebjoofv4kbyovkw7af3rpiitvnl6i7ckcywaq6xjcxnc2mby")
+        );
+        assert_eq!(
+            compute_cid(b"hello world", &v1_raw).as_deref(),
+            Some("bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e")
+        );
+
+        let blake = IpfsAddOptions::parse(Some("1"), Some("blake2b-256"), None).unwrap();
+        assert_eq!(compute_cid(content, &blake), None);
+        assert!(IpfsAddOptions::parse(None, Some("blake2b-256"), None).is_err());
+        assert!(IpfsAddOptions::parse(Some("0"), None, Some("true")).is_err());
+        assert!(IpfsAddOptions::parse(Some("2"), None, None).is_err());
+    }
+}